@@ -105,6 +105,61 @@ pub unsafe extern "C" fn svc_handler() {
     : : : "r0", "r1", "lr", "cc", "memory" : "volatile" );
 }
 
+/// SVC handler for chips that use `syscall::SysCallFp`.
+///
+/// This works like `svc_handler`, except that it resumes a process with the
+/// EXC_RETURN value that `switch_to_user_arm_v7m_fp` passed in `r3`, since
+/// the process stack may hold an extended frame with the floating-point
+/// context.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+#[naked]
+pub unsafe extern "C" fn svc_handler_arm_v7m_fp() {
+    llvm_asm!(
+        "
+    // First check to see which direction we are going in. If the link register
+    // is something other than 0xfffffff9, then we are coming from an app which
+    // has called a syscall.
+    cmp lr, #0xfffffff9
+    bne to_kernel_fp
+
+    // If we get here, then this is a context switch from the kernel to the
+    // application. Set thread mode to unprivileged to run the application.
+    mov r0, #1
+    msr CONTROL, r0
+    /* CONTROL writes must be followed by ISB */
+    /* http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.dai0321a/BIHFJCAC.html */
+    isb
+
+    // Return to Thread mode with Process stack, with the EXC_RETURN value of
+    // the process. Read `r3` from the frame the kernel stacked, since a
+    // tail-chained interrupt handler may have changed the register.
+    ldr lr, [sp, #12]
+    // Switch to the app.
+    bx lr
+
+  to_kernel_fp:
+    // An application called a syscall. We mark this in the global variable
+    // `SYSCALL_FIRED` which is stored in the syscall file.
+    // `UserspaceKernelBoundary` will use this variable to decide why the app
+    // stopped executing.
+    ldr r0, =SYSCALL_FIRED
+    mov r1, #1
+    str r1, [r0, #0]
+
+    // Set thread mode to privileged as we switch back to the kernel.
+    mov r0, #0
+    msr CONTROL, r0
+    /* CONTROL writes must be followed by ISB */
+    /* http://infocenter.arm.com/help/index.jsp?topic=/com.arm.doc.dai0321a/BIHFJCAC.html */
+    isb
+
+    // This is a special address to return Thread mode with Main stack
+    movw LR, #0xFFF9
+    movt LR, #0xFFFF
+    bx lr"
+    : : : "r0", "r1", "lr", "cc", "memory" : "volatile" );
+}
+
 /// All ISRs are caught by this handler. This must ensure the interrupt is
 /// disabled (per Tock's interrupt model) and then as quickly as possible resume
 /// the main thread (i.e. leave the interrupt context). The interrupt will be
@@ -248,6 +303,111 @@ pub unsafe extern "C" fn switch_to_user_arm_v7m(
     user_stack
 }
 
+/// Assembly function called from `syscall::SysCallFp` to switch to an
+/// application that may use the FPU. In addition to what
+/// `switch_to_user_arm_v7m` does, this restores and saves S16-S31 and FPSCR,
+/// and completes the lazy saving of S0-S15 and FPSCR to the process stack.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+#[no_mangle]
+pub unsafe extern "C" fn switch_to_user_arm_v7m_fp(
+    mut user_stack: *const usize,
+    process_regs: &mut [usize; 8],
+    process_fp_regs: &mut [u32; 18],
+) -> *const usize {
+    llvm_asm!(
+        "
+    // The arguments passed in are:
+    // - `r0` is the top of the user stack
+    // - `r1` is a reference to `CortexMStoredState.regs`
+    // - `r2` is a reference to `CortexMFpStoredState.fp_regs`, which holds
+    //   S16-S31, FPSCR and EXC_RETURN of the process
+
+    // The kernel is built without floating-point instructions, so tell the
+    // assembler about the FPU that this function requires.
+    .fpu fpv4-sp-d16
+
+    // Load bottom of stack into Process Stack Pointer.
+    msr psp, $0
+
+    // Load non-hardware-stacked registers from the process stored state. Ensure
+    // that $2 is stored in a callee saved register.
+    ldmia $2, {r4-r11}
+
+    // Using the FPU sets CONTROL.FPCA, so an exception would stack an
+    // extended frame on the kernel stack. `generic_isr` and `systick_handler`
+    // return with EXC_RETURN 0xfffffff9, which pops a basic frame, so mask
+    // interrupts until FPCA is cleared again.
+    cpsid i
+
+    // Load the floating-point registers the hardware does not stack.
+    vldmia $3, {s16-s31}
+    ldr r3, [$3, #64]
+    vmsr fpscr, r3
+
+    // Clear CONTROL.FPCA for the kernel, so that the `svc` below stacks a
+    // basic frame and the SVC handler sees 0xfffffff9.
+    mrs r3, CONTROL
+    bic r3, r3, #4
+    msr CONTROL, r3
+    isb
+
+    // `svc` must not be executed with interrupts masked, as it would escalate
+    // to a HardFault.
+    cpsie i
+
+    // The SVC handler resumes the process with this EXC_RETURN value.
+    ldr r3, [$3, #68]
+
+    // SWITCH
+    svc 0xff   // It doesn't matter which SVC number we use here as it has no
+               // defined meaning for the Cortex-M syscall interface. Data being
+               // returned from a syscall is transfered on the app's stack.
+
+    // When execution returns here we have switched back to the kernel from the
+    // application.
+
+    // Push non-hardware-stacked registers into the saved state for the
+    // application.
+    stmia $2, {r4-r11}
+
+    // Mask interrupts while the FPU is used, as above.
+    cpsid i
+
+    // If the process had an active floating-point context, the hardware
+    // stacked an extended frame, but deferred saving S0-S15 and FPSCR into it
+    // (FPCCR.LSPACT is set). Remember which frame the process stack holds.
+    movw r3, #0xef34
+    movt r3, #0xe000
+    ldr r3, [r3]
+    tst r3, #1
+    ite ne
+    mvnne r3, #0x12  // EXC_RETURN 0xffffffed: Process stack, extended frame
+    mvneq r3, #0x2   // EXC_RETURN 0xfffffffd: Process stack, basic frame
+    str r3, [$3, #68]
+
+    // Save the floating-point registers the hardware does not stack. Using
+    // the FPU first saves S0-S15 and FPSCR to the process stack, if that is
+    // still pending, so the next process cannot see or change them.
+    vstmia $3, {s16-s31}
+    vmrs r3, fpscr
+    str r3, [$3, #64]
+
+    // Clear CONTROL.FPCA again for the kernel.
+    mrs r3, CONTROL
+    bic r3, r3, #4
+    msr CONTROL, r3
+    isb
+    cpsie i
+
+    // Update the user stack pointer with the current value after the
+    // application has executed.
+    mrs $0, PSP   // r0 = PSP"
+    : "={r0}"(user_stack)
+    : "{r0}"(user_stack), "{r1}"(process_regs), "{r2}"(process_fp_regs)
+    : "r3", "r4","r5","r6","r8","r9","r10","r11", "cc", "memory" : "volatile" );
+    user_stack
+}
+
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 #[inline(never)]
 unsafe fn kernel_hardfault_arm_v7m(faulting_stack: *mut u32) {
@@ -709,6 +869,11 @@ pub unsafe extern "C" fn svc_handler() {
     unimplemented!()
 }
 
+#[cfg(not(any(target_arch = "arm", target_os = "none")))]
+pub unsafe extern "C" fn svc_handler_arm_v7m_fp() {
+    unimplemented!()
+}
+
 #[cfg(not(any(target_arch = "arm", target_os = "none")))]
 pub unsafe extern "C" fn generic_isr() {
     unimplemented!()
@@ -727,6 +892,15 @@ pub unsafe extern "C" fn switch_to_user_arm_v7m(
     unimplemented!()
 }
 
+#[cfg(not(any(target_arch = "arm", target_os = "none")))]
+pub unsafe extern "C" fn switch_to_user_arm_v7m_fp(
+    _user_stack: *const usize,
+    _process_regs: &mut [usize; 8],
+    _process_fp_regs: &mut [u32; 18],
+) -> *const usize {
+    unimplemented!()
+}
+
 #[cfg(not(any(target_arch = "arm", target_os = "none")))]
 pub unsafe extern "C" fn hard_fault_handler_arm_v7m() {
     unimplemented!()
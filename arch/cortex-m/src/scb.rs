@@ -67,6 +67,23 @@ register_structs! {
     }
 }
 
+register_structs! {
+    /// Floating-point system registers of an ARMv7-M processor with the
+    /// floating-point extension, also in the SCS.
+    FpRegisters {
+        /// Floating-Point Context Control Register
+        (0x00 => fpccr: ReadWrite<u32, FloatingPointContextControl::Register>),
+
+        /// Floating-Point Context Address Register
+        (0x04 => fpcar: ReadWrite<u32>),
+
+        /// Floating-Point Default Status Control Register
+        (0x08 => fpdscr: ReadWrite<u32>),
+
+        (0x0c => @END),
+    }
+}
+
 register_bitfields![u32,
     CpuId [
         /// Implementer code assigned by ARM. ARM implementations are 0x41.
@@ -262,11 +279,33 @@ register_bitfields![u32,
         CP2             OFFSET(4)  NUMBITS(2),
         CP1             OFFSET(2)  NUMBITS(2),
         CP0             OFFSET(0)  NUMBITS(2)
+    ],
+
+    FloatingPointContextControl [
+        /// Exception entry saves the floating-point context of a thread that
+        /// uses the FPU.
+        ASPEN           OFFSET(31)  NUMBITS(1),
+
+        /// Exception entry only reserves stack space for the floating-point
+        /// context, which is saved when the handler first uses the FPU.
+        LSPEN           OFFSET(30)  NUMBITS(1),
+
+        MONRDY          OFFSET(8)   NUMBITS(1),
+        BFRDY           OFFSET(6)   NUMBITS(1),
+        MMRDY           OFFSET(5)   NUMBITS(1),
+        HFRDY           OFFSET(4)   NUMBITS(1),
+        THREAD          OFFSET(3)   NUMBITS(1),
+        USER            OFFSET(1)   NUMBITS(1),
+
+        /// Saving the floating-point context is still pending.
+        LSPACT          OFFSET(0)   NUMBITS(1)
     ]
 ];
 
 const SCB: StaticRef<ScbRegisters> = unsafe { StaticRef::new(0xE000ED00 as *const ScbRegisters) };
 
+const FP: StaticRef<FpRegisters> = unsafe { StaticRef::new(0xE000EF34 as *const FpRegisters) };
+
 /// Allow the core to go into deep sleep on WFI.
 ///
 /// The specific definition of "deep sleep" is chip specific.
@@ -315,3 +354,38 @@ pub unsafe fn disable_fpca() {
 pub unsafe fn disable_fpca() {
     unimplemented!()
 }
+
+/// Enable the FPU for processes, with lazy saving of the floating-point
+/// context on exception entry, as `syscall::SysCallFp` expects.
+///
+/// The kernel itself must not use floating-point instructions.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub unsafe fn enable_fpu() {
+    FP.fpccr
+        .modify(FloatingPointContextControl::ASPEN::SET + FloatingPointContextControl::LSPEN::SET);
+    SCB.cpacr.modify(
+        CoprocessorAccessControl::CP10.val(0b11) + CoprocessorAccessControl::CP11.val(0b11),
+    );
+
+    llvm_asm!("dsb");
+    llvm_asm!("isb");
+
+    // A prior stage may have left a floating-point context active in thread
+    // mode, which would make exceptions taken by the kernel stack it.
+    llvm_asm!(
+        "
+    mrs r0, CONTROL
+    bic r0, r0, #4
+    msr CONTROL, r0
+    isb"
+    : : : "r0" : "volatile" );
+}
+
+// Mock implementation for tests on Travis-CI.
+#[cfg(not(any(target_arch = "arm", target_os = "none")))]
+pub unsafe fn enable_fpu() {
+    // Prevent unused code warning.
+    FP.fpccr.get();
+
+    unimplemented!()
+}
@@ -42,6 +42,30 @@ pub struct CortexMStoredState {
     psr: usize,
 }
 
+/// Index of FPSCR in `CortexMFpStoredState.fp_regs`.
+const FP_FPSCR: usize = 16;
+
+/// Index of the EXC_RETURN value in `CortexMFpStoredState.fp_regs`.
+const FP_EXC_RETURN: usize = 17;
+
+/// EXC_RETURN value to resume a process whose stack holds a basic frame.
+const EXC_RETURN_PROCESS: u32 = 0xFFFF_FFFD;
+
+/// Bit of EXC_RETURN that is clear if the stack holds an extended frame with
+/// the floating-point context.
+const EXC_RETURN_BASIC_FRAME: u32 = 0x10;
+
+/// This holds all of the state that the kernel must keep for a process that
+/// can use the FPU when the process is not executing.
+#[derive(Default)]
+pub struct CortexMFpStoredState {
+    base: CortexMStoredState,
+    /// S16-S31, FPSCR and the EXC_RETURN value the process resumes with, in
+    /// the layout `switch_to_user_arm_v7m_fp` expects. The hardware stacks
+    /// S0-S15 with the rest of the floating-point context.
+    fp_regs: [u32; 18],
+}
+
 /// Implementation of the `UserspaceKernelBoundary` for the Cortex-M non-floating point
 /// architecture.
 pub struct SysCall();
@@ -52,6 +76,16 @@ impl SysCall {
     }
 }
 
+/// Implementation of the `UserspaceKernelBoundary` for Cortex-M4F and
+/// Cortex-M7 cores with an FPU that processes may use.
+///
+/// Chips that use it must call `scb::enable_fpu()` during startup and use the
+/// `svc_handler_arm_v7m_fp` SVC handler. On exception entry the hardware
+/// reserves stack space for S0-S15 and FPSCR of a process that uses the FPU,
+/// and the context switch saves them there, and S16-S31 in the stored state,
+/// before the next process runs.
+pub struct SysCallFp();
+
 impl kernel::syscall::UserspaceKernelBoundary for SysCall {
     type StoredState = CortexMStoredState;
 
@@ -136,63 +170,21 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
         state: &mut CortexMStoredState,
     ) -> (*mut usize, kernel::syscall::ContextSwitchReason) {
         let new_stack_pointer = switch_to_user(stack_pointer, &mut state.regs);
-
-        // Determine why this returned and the process switched back to the
-        // kernel.
-
-        // Check to see if the fault handler was called while the process was
-        // running.
-        let app_fault = read_volatile(&APP_HARD_FAULT);
-        write_volatile(&mut APP_HARD_FAULT, 0);
-
-        // Check to see if the svc_handler was called and the process called a
-        // syscall.
-        let syscall_fired = read_volatile(&SYSCALL_FIRED);
-        write_volatile(&mut SYSCALL_FIRED, 0);
-
-        // Now decide the reason based on which flags were set.
-        let switch_reason = if app_fault == 1 {
-            // APP_HARD_FAULT takes priority. This means we hit the hardfault
-            // handler and this process faulted.
-            kernel::syscall::ContextSwitchReason::Fault
-        } else if syscall_fired == 1 {
-            // Save these fields after a syscall. If this is a synchronous
-            // syscall (i.e. we return a value to the app immediately) then this
-            // will have no effect. If we are doing something like `yield()`,
-            // however, then we need to have this state.
-            state.yield_pc = read_volatile(new_stack_pointer.offset(6));
-            state.psr = read_volatile(new_stack_pointer.offset(7));
-
-            // Get the syscall arguments and return them along with the syscall.
-            // It's possible the app did something invalid, in which case we put
-            // the app in the fault state.
-            let r0 = read_volatile(new_stack_pointer.offset(0));
-            let r1 = read_volatile(new_stack_pointer.offset(1));
-            let r2 = read_volatile(new_stack_pointer.offset(2));
-            let r3 = read_volatile(new_stack_pointer.offset(3));
-
-            // Get the actual SVC number.
-            let pcptr = read_volatile((new_stack_pointer as *const *const u16).offset(6));
-            let svc_instr = read_volatile(pcptr.offset(-1));
-            let svc_num = (svc_instr & 0xff) as u8;
-
-            // Use the helper function to convert these raw values into a Tock
-            // `Syscall` type.
-            let syscall = kernel::syscall::arguments_to_syscall(svc_num, r0, r1, r2, r3);
-
-            match syscall {
-                Some(s) => kernel::syscall::ContextSwitchReason::SyscallFired { syscall: s },
-                None => kernel::syscall::ContextSwitchReason::Fault,
-            }
-        } else {
-            // If none of the above cases are true its because the process was interrupted by an
-            // ISR for a hardware event
-            kernel::syscall::ContextSwitchReason::Interrupted
-        };
+        let switch_reason = context_switch_reason(new_stack_pointer, state);
 
         (new_stack_pointer as *mut usize, switch_reason)
     }
 
+    unsafe fn get_process_pc(
+        &self,
+        stack_pointer: *const usize,
+        _state: &CortexMStoredState,
+    ) -> usize {
+        // The PC is part of the exception frame the hardware pushed to the
+        // process stack.
+        read_volatile(stack_pointer.offset(6))
+    }
+
     unsafe fn print_context(
         &self,
         stack_pointer: *const usize,
@@ -270,3 +262,174 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
         ));
     }
 }
+
+impl SysCallFp {
+    pub const unsafe fn new() -> SysCallFp {
+        SysCallFp()
+    }
+}
+
+impl kernel::syscall::UserspaceKernelBoundary for SysCallFp {
+    type StoredState = CortexMFpStoredState;
+
+    unsafe fn initialize_process(
+        &self,
+        stack_pointer: *const usize,
+        stack_size: usize,
+        state: &mut Self::StoredState,
+    ) -> Result<*const usize, ()> {
+        // The process starts without a floating-point context, so the
+        // hardware stacks a basic frame until it uses the FPU.
+        state.fp_regs.iter_mut().for_each(|x| *x = 0);
+        state.fp_regs[FP_EXC_RETURN] = EXC_RETURN_PROCESS;
+
+        SysCall().initialize_process(stack_pointer, stack_size, &mut state.base)
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        stack_pointer: *const usize,
+        state: &mut Self::StoredState,
+        return_value: isize,
+    ) {
+        SysCall().set_syscall_return_value(stack_pointer, &mut state.base, return_value)
+    }
+
+    /// The first eight words of the extended frame are the basic frame, so
+    /// this works the same as for processes without a floating-point
+    /// context. The process resumes with the floating-point registers of the
+    /// frame.
+    unsafe fn set_process_function(
+        &self,
+        stack_pointer: *const usize,
+        remaining_stack_memory: usize,
+        state: &mut CortexMFpStoredState,
+        callback: kernel::procs::FunctionCall,
+    ) -> Result<*mut usize, *mut usize> {
+        SysCall().set_process_function(
+            stack_pointer,
+            remaining_stack_memory,
+            &mut state.base,
+            callback,
+        )
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        stack_pointer: *const usize,
+        state: &mut CortexMFpStoredState,
+    ) -> (*mut usize, kernel::syscall::ContextSwitchReason) {
+        let new_stack_pointer = crate::switch_to_user_arm_v7m_fp(
+            stack_pointer,
+            &mut state.base.regs,
+            &mut state.fp_regs,
+        );
+        let switch_reason = context_switch_reason(new_stack_pointer, &mut state.base);
+
+        (new_stack_pointer as *mut usize, switch_reason)
+    }
+
+    unsafe fn get_process_pc(
+        &self,
+        stack_pointer: *const usize,
+        state: &CortexMFpStoredState,
+    ) -> usize {
+        SysCall().get_process_pc(stack_pointer, &state.base)
+    }
+
+    unsafe fn print_context(
+        &self,
+        stack_pointer: *const usize,
+        state: &CortexMFpStoredState,
+        writer: &mut dyn Write,
+    ) {
+        SysCall().print_context(stack_pointer, &state.base, writer);
+
+        let _ = writer.write_fmt(format_args!(
+            " FPSCR: {:#010X}\r\n",
+            state.fp_regs[FP_FPSCR]
+        ));
+        if state.fp_regs[FP_EXC_RETURN] & EXC_RETURN_BASIC_FRAME == 0 {
+            // S0-S15 are in the extended frame, after the basic frame.
+            for i in 0..16 {
+                let _ = writer.write_fmt(format_args!(
+                    "  S{:<2}: {:#010X}    S{:<2}: {:#010X}\r\n",
+                    i,
+                    read_volatile(stack_pointer.offset(8 + i as isize)),
+                    i + 16,
+                    state.fp_regs[i],
+                ));
+            }
+        } else {
+            let _ = writer.write_fmt(format_args!(
+                " S0-S15: no active floating-point context\r\n"
+            ));
+            for i in 0..8 {
+                let _ = writer.write_fmt(format_args!(
+                    "  S{:<2}: {:#010X}    S{:<2}: {:#010X}\r\n",
+                    i + 16,
+                    state.fp_regs[i],
+                    i + 24,
+                    state.fp_regs[i + 8],
+                ));
+            }
+        }
+    }
+}
+
+/// Determine why the process switched back to the kernel, and save the state
+/// of a process that called a syscall.
+unsafe fn context_switch_reason(
+    new_stack_pointer: *const usize,
+    state: &mut CortexMStoredState,
+) -> kernel::syscall::ContextSwitchReason {
+    // Check to see if the fault handler was called while the process was
+    // running.
+    let app_fault = read_volatile(&APP_HARD_FAULT);
+    write_volatile(&mut APP_HARD_FAULT, 0);
+
+    // Check to see if the svc_handler was called and the process called a
+    // syscall.
+    let syscall_fired = read_volatile(&SYSCALL_FIRED);
+    write_volatile(&mut SYSCALL_FIRED, 0);
+
+    // Now decide the reason based on which flags were set.
+    if app_fault == 1 {
+        // APP_HARD_FAULT takes priority. This means we hit the hardfault
+        // handler and this process faulted.
+        kernel::syscall::ContextSwitchReason::Fault
+    } else if syscall_fired == 1 {
+        // Save these fields after a syscall. If this is a synchronous
+        // syscall (i.e. we return a value to the app immediately) then this
+        // will have no effect. If we are doing something like `yield()`,
+        // however, then we need to have this state.
+        state.yield_pc = read_volatile(new_stack_pointer.offset(6));
+        state.psr = read_volatile(new_stack_pointer.offset(7));
+
+        // Get the syscall arguments and return them along with the syscall.
+        // It's possible the app did something invalid, in which case we put
+        // the app in the fault state.
+        let r0 = read_volatile(new_stack_pointer.offset(0));
+        let r1 = read_volatile(new_stack_pointer.offset(1));
+        let r2 = read_volatile(new_stack_pointer.offset(2));
+        let r3 = read_volatile(new_stack_pointer.offset(3));
+
+        // Get the actual SVC number.
+        let pcptr = read_volatile((new_stack_pointer as *const *const u16).offset(6));
+        let svc_instr = read_volatile(pcptr.offset(-1));
+        let svc_num = (svc_instr & 0xff) as u8;
+
+        // Use the helper function to convert these raw values into a Tock
+        // `Syscall` type.
+        let syscall = kernel::syscall::arguments_to_syscall(svc_num, r0, r1, r2, r3);
+
+        match syscall {
+            Some(s) => kernel::syscall::ContextSwitchReason::SyscallFired { syscall: s },
+            None => kernel::syscall::ContextSwitchReason::InvalidSyscall,
+        }
+    } else {
+        // If none of the above cases are true its because the process was interrupted by an
+        // ISR for a hardware event
+        kernel::syscall::ContextSwitchReason::Interrupted
+    }
+}
@@ -134,6 +134,13 @@ pub struct MPU {
     /// is currently configured for so that the MPU can skip updating when the
     /// kernel returns to the same app.
     hardware_is_configured_for: OptionalCell<AppId>,
+    /// Whether the process regions are enabled. The kernel disables them
+    /// while it runs, and `configure_mpu()` enables them again.
+    app_regions_enabled: Cell<bool>,
+    /// Number of regions, starting from region 0, that protect the kernel
+    /// once `enable_kernel_mpu()` has been called. Processes use the regions
+    /// above them.
+    kernel_regions: Cell<usize>,
 }
 
 impl MPU {
@@ -141,6 +148,52 @@ impl MPU {
         MPU {
             registers: MPU_BASE_ADDRESS,
             hardware_is_configured_for: OptionalCell::empty(),
+            app_regions_enabled: Cell::new(false),
+            kernel_regions: Cell::new(0),
+        }
+    }
+
+    /// Number of regions available to a process.
+    fn app_regions(&self) -> usize {
+        8 - self.kernel_regions.get()
+    }
+
+    fn write_region(&self, region_num: usize, region: &CortexMRegion) {
+        self.registers.rbar.write(
+            region.base_address()
+                + RegionBaseAddress::VALID::UseRBAR
+                + RegionBaseAddress::REGION.val(region_num as u32),
+        );
+        self.registers.rasr.write(region.attributes());
+    }
+
+    /// Disable the regions used by processes, leaving the kernel regions in
+    /// place. The process regions keep their configuration, so that
+    /// `configure_mpu()` only has to enable them again when switching back to
+    /// the same process.
+    fn disable_app_regions(&self) {
+        if !self.app_regions_enabled.replace(false) {
+            return;
+        }
+        for region_num in self.kernel_regions.get()..8 {
+            self.registers
+                .rnr
+                .write(RegionNumber::REGION.val(region_num as u32));
+            self.registers.rasr.modify(RegionAttributes::ENABLE::CLEAR);
+        }
+    }
+
+    /// Enable the process regions of `config` that `disable_app_regions()`
+    /// disabled.
+    fn enable_app_regions(&self, config: &CortexMConfig) {
+        let kernel_regions = self.kernel_regions.get();
+        for (i, region) in config.regions.iter().take(8 - kernel_regions).enumerate() {
+            if region.location().is_some() {
+                self.registers
+                    .rnr
+                    .write(RegionNumber::REGION.val((kernel_regions + i) as u32));
+                self.registers.rasr.modify(RegionAttributes::ENABLE::SET);
+            }
         }
     }
 }
@@ -149,13 +202,17 @@ impl MPU {
 ///
 /// The cortex-m MPU has eight regions, all of which must be configured (though
 /// unused regions may be configured as disabled). This struct caches the result
-/// of region configuration calculation
+/// of region configuration calculation. If the kernel MPU is enabled, entry `n`
+/// is written to region `n` plus the number of kernel regions, and only the
+/// entries that fit in the remaining regions are used.
 pub struct CortexMConfig {
     /// The computed region configuration for this process.
     regions: [CortexMRegion; 8],
     /// Has the configuration changed since the last time the this process
     /// configuration was written to hardware?
     is_dirty: Cell<bool>,
+    /// Which region protects the guard below the process stack, if any.
+    stack_guard_region: Option<usize>,
 }
 
 const APP_MEMORY_REGION_NUM: usize = 0;
@@ -163,17 +220,9 @@ const APP_MEMORY_REGION_NUM: usize = 0;
 impl Default for CortexMConfig {
     fn default() -> CortexMConfig {
         CortexMConfig {
-            regions: [
-                CortexMRegion::empty(0),
-                CortexMRegion::empty(1),
-                CortexMRegion::empty(2),
-                CortexMRegion::empty(3),
-                CortexMRegion::empty(4),
-                CortexMRegion::empty(5),
-                CortexMRegion::empty(6),
-                CortexMRegion::empty(7),
-            ],
+            regions: [CortexMRegion::empty(); 8],
             is_dirty: Cell::new(true),
+            stack_guard_region: None,
         }
     }
 }
@@ -181,60 +230,65 @@ impl Default for CortexMConfig {
 impl fmt::Display for CortexMConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "\r\n Cortex-M MPU")?;
-        for (i, region) in self.regions.iter().enumerate() {
-            if let Some(location) = region.location() {
-                let access_bits = region.attributes().read(RegionAttributes::AP);
-                let access_str = match access_bits {
-                    0b000 => "NoAccess",
-                    0b001 => "PrivilegedOnly",
-                    0b010 => "UnprivilegedReadOnly",
-                    0b011 => "ReadWrite",
-                    0b100 => "Reserved",
-                    0b101 => "PrivilegedOnlyReadOnly",
-                    0b110 => "ReadOnly",
-                    0b111 => "ReadOnlyAlias",
-                    _ => "ERR",
-                };
-                let start = location.0 as usize;
+        write_regions(f, &self.regions)?;
+        write!(f, "\r\n")
+    }
+}
+
+fn write_regions(f: &mut fmt::Formatter<'_>, regions: &[CortexMRegion]) -> fmt::Result {
+    for (i, region) in regions.iter().enumerate() {
+        if let Some(location) = region.location() {
+            let access_bits = region.attributes().read(RegionAttributes::AP);
+            let access_str = match access_bits {
+                0b000 => "NoAccess",
+                0b001 => "PrivilegedOnly",
+                0b010 => "UnprivilegedReadOnly",
+                0b011 => "ReadWrite",
+                0b100 => "Reserved",
+                0b101 => "PrivilegedOnlyReadOnly",
+                0b110 => "ReadOnly",
+                0b111 => "ReadOnlyAlias",
+                _ => "ERR",
+            };
+            let start = location.0 as usize;
+            write!(
+                f,
+                "\
+                 \r\n  Region {}: [{:#010X}:{:#010X}], length: {} bytes; {} ({:#x})",
+                i,
+                start,
+                start + location.1,
+                location.1,
+                access_str,
+                access_bits,
+            )?;
+            let subregion_bits = region.attributes().read(RegionAttributes::SRD);
+            let subregion_size = location.1 / 8;
+            for j in 0..8 {
                 write!(
                     f,
                     "\
-                     \r\n  Region {}: [{:#010X}:{:#010X}], length: {} bytes; {} ({:#x})",
-                    i,
-                    start,
-                    start + location.1,
-                    location.1,
-                    access_str,
-                    access_bits,
+                     \r\n    Sub-region {}: [{:#010X}:{:#010X}], {}",
+                    j,
+                    start + j * subregion_size,
+                    start + (j + 1) * subregion_size,
+                    if (subregion_bits >> j) & 1 == 0 {
+                        "Enabled"
+                    } else {
+                        "Disabled"
+                    },
                 )?;
-                let subregion_bits = region.attributes().read(RegionAttributes::SRD);
-                let subregion_size = location.1 / 8;
-                for j in 0..8 {
-                    write!(
-                        f,
-                        "\
-                         \r\n    Sub-region {}: [{:#010X}:{:#010X}], {}",
-                        j,
-                        start + j * subregion_size,
-                        start + (j + 1) * subregion_size,
-                        if (subregion_bits >> j) & 1 == 0 {
-                            "Enabled"
-                        } else {
-                            "Disabled"
-                        },
-                    )?;
-                }
-            } else {
-                write!(f, "\r\n  Region {}: Unused", i)?;
             }
+        } else {
+            write!(f, "\r\n  Region {}: Unused", i)?;
         }
-        write!(f, "\r\n")
     }
+    Ok(())
 }
 
 impl CortexMConfig {
-    fn unused_region_number(&self) -> Option<usize> {
-        for (number, region) in self.regions.iter().enumerate() {
+    fn unused_region_number(&self, num_regions: usize) -> Option<usize> {
+        for (number, region) in self.regions.iter().enumerate().take(num_regions) {
             if number == APP_MEMORY_REGION_NUM {
                 continue;
             }
@@ -260,7 +314,6 @@ impl CortexMRegion {
         logical_size: usize,
         region_start: *const u8,
         region_size: usize,
-        region_num: usize,
         subregions: Option<(usize, usize)>,
         permissions: mpu::Permissions,
     ) -> CortexMRegion {
@@ -288,10 +341,80 @@ impl CortexMRegion {
             ),
         };
 
+        CortexMRegion::with_access(
+            logical_start,
+            logical_size,
+            region_start,
+            region_size,
+            subregions,
+            access,
+            execute,
+        )
+    }
+
+    /// Region that only privileged code can access, used to protect the
+    /// kernel's own memory.
+    fn kernel(
+        region_start: *const u8,
+        region_size: usize,
+        subregions: Option<(usize, usize)>,
+        permissions: mpu::Permissions,
+    ) -> CortexMRegion {
+        // Privileged code cannot have write access without read access, so
+        // execute-only is read-execute.
+        let (access, execute) = match permissions {
+            mpu::Permissions::ReadWriteExecute => (
+                RegionAttributes::AP::PrivilegedOnly,
+                RegionAttributes::XN::Enable,
+            ),
+            mpu::Permissions::ReadWriteOnly => (
+                RegionAttributes::AP::PrivilegedOnly,
+                RegionAttributes::XN::Disable,
+            ),
+            mpu::Permissions::ReadExecuteOnly | mpu::Permissions::ExecuteOnly => (
+                RegionAttributes::AP::PrivilegedOnlyReadOnly,
+                RegionAttributes::XN::Enable,
+            ),
+            mpu::Permissions::ReadOnly => (
+                RegionAttributes::AP::PrivilegedOnlyReadOnly,
+                RegionAttributes::XN::Disable,
+            ),
+        };
+
+        // The region covers the enabled subregions.
+        let (start, size) = match subregions {
+            Some((min_subregion, max_subregion)) => {
+                let subregion_size = region_size / 8;
+                (
+                    region_start as usize + min_subregion * subregion_size,
+                    (max_subregion - min_subregion + 1) * subregion_size,
+                )
+            }
+            None => (region_start as usize, region_size),
+        };
+
+        CortexMRegion::with_access(
+            start as *const u8,
+            size,
+            region_start,
+            region_size,
+            subregions,
+            access,
+            execute,
+        )
+    }
+
+    fn with_access(
+        logical_start: *const u8,
+        logical_size: usize,
+        region_start: *const u8,
+        region_size: usize,
+        subregions: Option<(usize, usize)>,
+        access: FieldValue<u32, RegionAttributes::Register>,
+        execute: FieldValue<u32, RegionAttributes::Register>,
+    ) -> CortexMRegion {
         // Base address register
-        let base_address = RegionBaseAddress::ADDR.val((region_start as u32) >> 5)
-            + RegionBaseAddress::VALID::UseRBAR
-            + RegionBaseAddress::REGION.val(region_num as u32);
+        let base_address = RegionBaseAddress::ADDR.val((region_start as u32) >> 5);
 
         let size_value = math::log_base_two(region_size as u32) - 1;
 
@@ -320,11 +443,29 @@ impl CortexMRegion {
         }
     }
 
-    fn empty(region_num: usize) -> CortexMRegion {
+    /// Region that the process can neither read, write nor execute. `start`
+    /// must be aligned to `size`, which must be a power of two of at least 32
+    /// bytes.
+    fn no_access(start: *const u8, size: usize) -> CortexMRegion {
+        let base_address = RegionBaseAddress::ADDR.val((start as u32) >> 5);
+
+        let size_value = math::log_base_two(size as u32) - 1;
+        let attributes = RegionAttributes::ENABLE::SET
+            + RegionAttributes::SIZE.val(size_value)
+            + RegionAttributes::AP::NoAccess
+            + RegionAttributes::XN::Disable;
+
+        CortexMRegion {
+            location: Some((start, size)),
+            base_address: base_address,
+            attributes: attributes,
+        }
+    }
+
+    fn empty() -> CortexMRegion {
         CortexMRegion {
             location: None,
-            base_address: RegionBaseAddress::VALID::UseRBAR
-                + RegionBaseAddress::REGION.val(region_num as u32),
+            base_address: RegionBaseAddress::ADDR.val(0),
             attributes: RegionAttributes::ENABLE::CLEAR,
         }
     }
@@ -366,7 +507,12 @@ impl kernel::mpu::MPU for MPU {
     type MpuConfig = CortexMConfig;
 
     fn clear_mpu(&self) {
-        self.registers.ctrl.write(Control::ENABLE::CLEAR);
+        if self.kernel_regions.get() == 0 {
+            self.registers.ctrl.write(Control::ENABLE::CLEAR);
+        } else {
+            // The kernel regions stay in place once enabled.
+            self.disable_app_regions();
+        }
     }
 
     fn enable_app_mpu(&self) {
@@ -378,9 +524,16 @@ impl kernel::mpu::MPU for MPU {
     }
 
     fn disable_app_mpu(&self) {
-        // The MPU is not enabled for privileged mode, so we don't have to do
-        // anything
-        self.registers.ctrl.write(Control::ENABLE::CLEAR);
+        if self.kernel_regions.get() == 0 {
+            // The MPU is not enabled for privileged mode, so we don't have to
+            // do anything
+            self.registers.ctrl.write(Control::ENABLE::CLEAR);
+        } else {
+            // The MPU stays enabled to protect the kernel, so remove the
+            // process regions, which would otherwise also apply to the kernel
+            // (a stack guard would stop it from accessing process memory).
+            self.disable_app_regions();
+        }
     }
 
     fn number_total_regions(&self) -> usize {
@@ -402,7 +555,7 @@ impl kernel::mpu::MPU for MPU {
             }
         }
 
-        let region_num = config.unused_region_number()?;
+        let region_num = config.unused_region_number(self.app_regions())?;
 
         // Logical region
         let mut start = unallocated_memory_start as usize;
@@ -503,7 +656,6 @@ impl kernel::mpu::MPU for MPU {
             size,
             region_start as *const u8,
             region_size,
-            region_num,
             subregions,
             permissions,
         );
@@ -610,7 +762,6 @@ impl kernel::mpu::MPU for MPU {
             region_size,
             region_start as *const u8,
             region_size,
-            APP_MEMORY_REGION_NUM,
             Some((0, num_subregions_used - 1)),
             permissions,
         );
@@ -670,7 +821,6 @@ impl kernel::mpu::MPU for MPU {
             region_size,
             region_start as *const u8,
             region_size,
-            APP_MEMORY_REGION_NUM,
             Some((0, num_subregions_used - 1)),
             permissions,
         );
@@ -681,17 +831,177 @@ impl kernel::mpu::MPU for MPU {
         Ok(())
     }
 
+    fn allocate_stack_guard_region(
+        &self,
+        memory_start: *const u8,
+        stack_bottom: *const u8,
+        min_guard_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<mpu::Region> {
+        // The guard must be a power of two of at least 32 bytes, aligned to
+        // its size, so it ends at the highest such boundary below the stack.
+        let size = math::closest_power_of_two(cmp::max(min_guard_size, 32) as u32) as usize;
+        let end = stack_bottom as usize - (stack_bottom as usize % size);
+        let start = end.checked_sub(size)?;
+        if start < memory_start as usize {
+            return None;
+        }
+
+        // When regions overlap, the one with the highest number applies.
+        // Every region but the app memory region has a higher number, and
+        // the other regions do not overlap process memory.
+        let region_num = match config.stack_guard_region {
+            Some(region_num) => region_num,
+            None => config.unused_region_number(self.app_regions())?,
+        };
+
+        config.regions[region_num] = CortexMRegion::no_access(start as *const u8, size);
+        config.stack_guard_region = Some(region_num);
+        config.is_dirty.set(true);
+
+        Some(mpu::Region::new(start as *const u8, size))
+    }
+
+    fn remove_stack_guard_region(&self, config: &mut Self::MpuConfig) {
+        if let Some(region_num) = config.stack_guard_region.take() {
+            config.regions[region_num] = CortexMRegion::empty();
+            config.is_dirty.set(true);
+        }
+    }
+
     fn configure_mpu(&self, config: &Self::MpuConfig, app_id: &AppId) {
         // If the hardware is already configured for this app and the app's MPU
         // configuration has not changed, then skip the hardware update.
         if !self.hardware_is_configured_for.contains(app_id) || config.is_dirty.get() {
-            // Set MPU regions
-            for region in config.regions.iter() {
-                self.registers.rbar.write(region.base_address());
-                self.registers.rasr.write(region.attributes());
+            // Set MPU regions, above the kernel regions
+            let kernel_regions = self.kernel_regions.get();
+            for (i, region) in config.regions.iter().take(8 - kernel_regions).enumerate() {
+                self.write_region(kernel_regions + i, region);
             }
             self.hardware_is_configured_for.set(*app_id);
             config.is_dirty.set(false);
+        } else if !self.app_regions_enabled.get() {
+            self.enable_app_regions(config);
+        }
+        self.app_regions_enabled.set(true);
+    }
+}
+
+/// Struct storing the configuration of the kernel regions of a Cortex-M MPU.
+///
+/// The kernel regions use the lowest region numbers, so that process regions,
+/// which use higher numbers, take precedence where they overlap. Like all
+/// regions, kernel regions apply to the kernel's own accesses: `PRIVDEFENA`
+/// only gives privileged code the default memory map for memory that no
+/// region covers. They deny unprivileged access, which processes do not have
+/// outside their own regions anyway. A kernel region can be larger than the
+/// memory it protects, and its permissions then also apply to the memory
+/// around it, so callers must check the region they get back.
+pub struct CortexMKernelConfig {
+    regions: [CortexMRegion; MAX_KERNEL_REGIONS],
+    num_regions: usize,
+}
+
+/// At most half of the regions can protect the kernel, to leave enough for
+/// processes.
+const MAX_KERNEL_REGIONS: usize = 4;
+
+impl Default for CortexMKernelConfig {
+    fn default() -> CortexMKernelConfig {
+        CortexMKernelConfig {
+            regions: [CortexMRegion::empty(); MAX_KERNEL_REGIONS],
+            num_regions: 0,
         }
     }
 }
+
+impl fmt::Display for CortexMKernelConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\r\n Cortex-M MPU (kernel)")?;
+        write_regions(f, &self.regions[..self.num_regions])?;
+        write!(f, "\r\n")
+    }
+}
+
+impl kernel::mpu::KernelMPU for MPU {
+    type KernelMpuConfig = CortexMKernelConfig;
+
+    fn allocate_kernel_region(
+        &self,
+        memory_start: *const u8,
+        memory_size: usize,
+        permissions: mpu::Permissions,
+        config: &mut Self::KernelMpuConfig,
+    ) -> Option<mpu::Region> {
+        // Kernel regions cannot change once enabled.
+        if self.kernel_regions.get() != 0 || config.num_regions == MAX_KERNEL_REGIONS {
+            return None;
+        }
+
+        let start = memory_start as usize;
+        let end = start.checked_add(memory_size)?;
+        if memory_size == 0 {
+            return None;
+        }
+
+        // Find the smallest region that covers the memory. Regions of 256
+        // bytes or more can disable the subregions outside of the memory.
+        // Regions allocated later take precedence where they overlap, so
+        // callers must allocate the regions whose permissions matter most
+        // last. The region can extend past the memory, so the enabled part
+        // of it is returned for callers to check.
+        let mut region_size: usize = 32;
+        let (region_start, subregions) = loop {
+            let region_start = start - (start % region_size);
+            if end - region_start <= region_size {
+                if region_size < 256 {
+                    break (region_start, None);
+                }
+                let subregion_size = region_size / 8;
+                let min_subregion = (start - region_start) / subregion_size;
+                let max_subregion = (end - 1 - region_start) / subregion_size;
+                if min_subregion == 0 && max_subregion == 7 {
+                    break (region_start, None);
+                }
+                break (region_start, Some((min_subregion, max_subregion)));
+            }
+            if region_size == 1 << 31 {
+                return None;
+            }
+            region_size *= 2;
+        };
+
+        let region = CortexMRegion::kernel(
+            region_start as *const u8,
+            region_size,
+            subregions,
+            permissions,
+        );
+        let (start, size) = region.location()?;
+
+        config.regions[config.num_regions] = region;
+        config.num_regions += 1;
+
+        Some(mpu::Region::new(start, size))
+    }
+
+    fn enable_kernel_mpu(&self, config: &mut Self::KernelMpuConfig) {
+        for (region_num, region) in config.regions[..config.num_regions].iter().enumerate() {
+            self.write_region(region_num, region);
+        }
+        self.kernel_regions.set(config.num_regions);
+        // The process regions now start at a different region number.
+        for region_num in config.num_regions..8 {
+            self.write_region(region_num, &CortexMRegion::empty());
+        }
+        self.hardware_is_configured_for.clear();
+        self.app_regions_enabled.set(false);
+
+        // Enable the MPU, disable it during HardFault/NMI handlers, and allow
+        // privileged code access to all unprotected memory. From now on the
+        // MPU stays enabled in privileged mode.
+        self.registers
+            .ctrl
+            .write(Control::ENABLE::SET + Control::HFNMIENA::CLEAR + Control::PRIVDEFENA::SET);
+    }
+}
@@ -17,6 +17,7 @@ pub use cortexm::nvic;
 pub use cortexm::print_cortexm_state as print_cortexm4_state;
 pub use cortexm::scb;
 pub use cortexm::svc_handler;
+pub use cortexm::svc_handler_arm_v7m_fp as svc_handler_fp;
 pub use cortexm::syscall;
 pub use cortexm::systick;
 pub use cortexm::systick_handler;
@@ -9,6 +9,7 @@ pub mod mie;
 pub mod minstret;
 pub mod mip;
 pub mod mscratch;
+pub mod mseccfg;
 pub mod mstatus;
 pub mod mtval;
 pub mod mtvec;
@@ -31,6 +32,7 @@ pub struct CSR {
     pub mcause: ReadWriteRiscvCsr<u32, mcause::mcause::Register>,
     pub mtval: ReadWriteRiscvCsr<u32, mtval::mtval::Register>,
     pub mip: ReadWriteRiscvCsr<u32, mip::mip::Register>,
+    pub mseccfg: ReadWriteRiscvCsr<u32, mseccfg::mseccfg::Register>,
     pub mtvec: ReadWriteRiscvCsr<u32, mtvec::mtvec::Register>,
     pub stvec: ReadWriteRiscvCsr<u32, stvec::stvec::Register>,
     pub utvec: ReadWriteRiscvCsr<u32, utvec::utvec::Register>,
@@ -53,6 +55,7 @@ pub const CSR: &CSR = &CSR {
     mcause: ReadWriteRiscvCsr::new(riscv_csr::csr::MCAUSE),
     mtval: ReadWriteRiscvCsr::new(riscv_csr::csr::MTVAL),
     mip: ReadWriteRiscvCsr::new(riscv_csr::csr::MIP),
+    mseccfg: ReadWriteRiscvCsr::new(riscv_csr::csr::MSECCFG),
     pmpcfg: [
         ReadWriteRiscvCsr::new(riscv_csr::csr::PMPCFG0),
         ReadWriteRiscvCsr::new(riscv_csr::csr::PMPCFG1),
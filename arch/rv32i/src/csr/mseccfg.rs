@@ -0,0 +1,17 @@
+use kernel::common::registers::register_bitfields;
+
+// Machine Security Configuration register, from the PMP Enhancements for
+// memory access and execution prevention on Machine mode (Smepmp)
+// extension.
+register_bitfields![u32,
+    pub mseccfg [
+        // Machine Mode Lockdown: locked PMP entries become M-mode only rules
+        // and unlocked entries become U-mode only rules.
+        mml OFFSET(0) NUMBITS(1) [],
+        // Machine Mode Whitelist Policy: deny M-mode accesses that do not
+        // match any PMP entry.
+        mmwp OFFSET(1) NUMBITS(1) [],
+        // Rule Locking Bypass: allow locked PMP entries to be modified.
+        rlb OFFSET(2) NUMBITS(1) []
+    ]
+];
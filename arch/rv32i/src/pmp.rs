@@ -17,13 +17,26 @@
 //! address must be aligned to the size, which results in wasted memory. To
 //! avoid this wasted memory we use TOR and each memory region uses two physical
 //! PMP regions.
+//!
+//! ## Kernel memory protection
+//!
+//! The PMP also implements `KernelMPU`, which requires the PMP Enhancements
+//! for memory access and execution prevention on Machine mode (Smepmp). Kernel
+//! regions use locked entries at the top of the PMP, and once they are enabled
+//! Machine Mode Lockdown (`mseccfg.MML`) makes locked entries apply only to
+//! machine mode and unlocked entries only to user mode. Machine mode cannot
+//! execute memory that no entry covers, and cannot access process memory
+//! through the unlocked entries, so the process entries are turned off
+//! whenever the kernel runs. Boards for chips without Smepmp must not call
+//! `enable_kernel_mpu()`.
 
 /// Instantiate a PMP configuration.
 ///
 /// `$x` is the number of PMP entries the hardware supports.
 ///
 /// Since we use TOR, we will use two PMP entries for each region. So the actual
-/// number of regions we can protect is `$x/2`.
+/// number of regions we can protect is `$x/2`, shared between the kernel and
+/// processes.
 #[macro_export]
 macro_rules! PMPConfigMacro {
     ( $x:expr ) => {
@@ -34,6 +47,7 @@ use core::fmt;
 use kernel::common::cells::OptionalCell;
 
 use rv32i::csr;
+use rv32i::csr::mseccfg::mseccfg;
 use kernel::common::cells::MapCell;
 use kernel::common::registers;
 use kernel::common::registers::register_bitfields;
@@ -62,13 +76,38 @@ pub struct PMP {
     /// the `is_dirty` flag) to determine if MPU can skip writing the
     /// configuration to hardware.
     last_configured_for: MapCell<AppId>,
+    /// Number of regions, at the top of the PMP, that protect the kernel once
+    /// `enable_kernel_mpu()` has been called.
+    kernel_regions: Cell<usize>,
 }
 
 impl PMP {
     pub const unsafe fn new() -> PMP {
         PMP {
             last_configured_for: MapCell::empty(),
+            kernel_regions: Cell::new(0),
+        }
+    }
+
+    /// Number of regions available to a process.
+    fn app_regions(&self) -> usize {
+        $x / 2 - self.kernel_regions.get()
+    }
+
+    /// Set the configuration of a single PMP entry.
+    fn set_entry_cfg(entry: usize, cfg: u8) {
+        let shift = (entry % 4) * 8;
+        let value = csr::CSR.pmpcfg[entry / 4].get() & !(0xFF << shift);
+        csr::CSR.pmpcfg[entry / 4].set(value | (cfg as u32) << shift);
+    }
+
+    /// Turn off the entries used by processes, leaving the kernel entries in
+    /// place.
+    fn disable_app_entries(&self) {
+        for entry in 0..(self.app_regions() * 2) {
+            PMP::set_entry_cfg(entry, 0);
         }
+        self.last_configured_for.take();
     }
 }
 
@@ -128,10 +167,23 @@ impl PMPRegion {
         }
     }
 
+    /// Region that grants no access, so that it hides the part of a larger
+    /// region that it overlaps as long as it is in a lower PMP entry.
+    fn guard(start: *const u8, size: usize) -> PMPRegion {
+        PMPRegion {
+            location: (start, size),
+            cfg: pmpcfg::r::CLEAR + pmpcfg::w::CLEAR + pmpcfg::x::CLEAR + pmpcfg::a::TOR,
+        }
+    }
+
     fn location(&self) -> (*const u8, usize) {
         self.location
     }
 
+    fn is_guard(&self) -> bool {
+        !(pmpcfg::r::SET + pmpcfg::w::SET + pmpcfg::x::SET).matches_any(self.cfg.value)
+    }
+
     fn overlaps(&self, other_start: *const u8, other_size: usize) -> bool {
         let other_start = other_start as usize;
         let other_end = other_start + other_size;
@@ -187,8 +239,8 @@ impl fmt::Display for PMPConfig {
 }
 
 impl PMPConfig {
-    fn unused_region_number(&self) -> Option<usize> {
-        for (number, region) in self.regions.iter().enumerate() {
+    fn unused_region_number(&self, num_regions: usize) -> Option<usize> {
+        for (number, region) in self.regions.iter().enumerate().take(num_regions) {
             if self.app_memory_region.contains(&number) {
                 continue;
             }
@@ -212,17 +264,18 @@ impl PMPConfig {
             None
         };
 
-        // Sort the regions
+        // Sort the regions. Guard regions go first, since the lowest matching
+        // entry decides the access and guards overlap the app region.
         self.regions.sort_unstable_by(|a, b| {
-            let (a_start, _a_size) = match a {
-                Some(region) => (region.location().0 as usize, region.location().1),
-                None => (0xFFFF_FFFF, 0xFFFF_FFFF),
+            let (a_guard, a_start) = match a {
+                Some(region) => (region.is_guard(), region.location().0 as usize),
+                None => (false, 0xFFFF_FFFF),
             };
-            let (b_start, _b_size) = match b {
-                Some(region) => (region.location().0 as usize, region.location().1),
-                None => (0xFFFF_FFFF, 0xFFFF_FFFF),
+            let (b_guard, b_start) = match b {
+                Some(region) => (region.is_guard(), region.location().0 as usize),
+                None => (false, 0xFFFF_FFFF),
             };
-            a_start.cmp(&b_start)
+            b_guard.cmp(&a_guard).then(a_start.cmp(&b_start))
         });
 
         // Update the app region after the sort
@@ -230,7 +283,7 @@ impl PMPConfig {
             for (i, region) in self.regions.iter().enumerate() {
                 match region {
                     Some(reg) => {
-                        if reg.location.0 == app_addres.unwrap() {
+                        if !reg.is_guard() && reg.location.0 == app_addres.unwrap() {
                             self.app_memory_region.set(i);
                         }
                     }
@@ -245,6 +298,13 @@ impl kernel::mpu::MPU for PMP {
     type MpuConfig = PMPConfig;
 
     fn clear_mpu(&self) {
+        if self.kernel_regions.get() != 0 {
+            // The kernel entries are locked, and granting access to the entire
+            // space would only apply to user mode.
+            self.disable_app_entries();
+            return;
+        }
+
         // We want to disable all of the hardware entries, so we use `$x` here,
         // and not `$x / 2`.
         for x in 0..$x {
@@ -304,8 +364,12 @@ impl kernel::mpu::MPU for PMP {
     fn enable_app_mpu(&self) {}
 
     fn disable_app_mpu(&self) {
-        // PMP is not enabled for machine mode, so we don't have to do
-        // anything
+        // Unless the kernel entries are enabled, PMP is not enabled for
+        // machine mode, so we don't have to do anything. Otherwise, the
+        // process entries would deny machine mode access to process memory.
+        if self.kernel_regions.get() != 0 {
+            self.disable_app_entries();
+        }
     }
 
     fn number_total_regions(&self) -> usize {
@@ -331,7 +395,7 @@ impl kernel::mpu::MPU for PMP {
             }
         }
 
-        let region_num = config.unused_region_number()?;
+        let region_num = config.unused_region_number(self.app_regions())?;
 
         // Logical region
         let mut start = unallocated_memory_start as usize;
@@ -387,7 +451,7 @@ impl kernel::mpu::MPU for PMP {
         let region_num = if config.app_memory_region.is_some() {
             config.app_memory_region.unwrap_or(0)
         } else {
-            config.unused_region_number()?
+            config.unused_region_number(self.app_regions())?
         };
 
         // App memory size is what we actual set the region to. So this region
@@ -468,6 +532,60 @@ impl kernel::mpu::MPU for PMP {
         Ok(())
     }
 
+    fn allocate_stack_guard_region(
+        &self,
+        memory_start: *const u8,
+        stack_bottom: *const u8,
+        min_guard_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<mpu::Region> {
+        // The guard ends at the stack bottom, rounded down to 4 bytes.
+        let end = stack_bottom as usize & !0x3;
+
+        // Guard size always has to align to 4 bytes, and be at least 8 bytes
+        let mut size = min_guard_size;
+        if size % 4 != 0 {
+            size += 4 - (size % 4);
+        }
+        if size < 8 {
+            size = 8;
+        }
+
+        let start = end.checked_sub(size)?;
+        if start < memory_start as usize {
+            return None;
+        }
+
+        let region_num = match config
+            .regions
+            .iter()
+            .position(|region| region.map_or(false, |region| region.is_guard()))
+        {
+            Some(region_num) => region_num,
+            None => config.unused_region_number(self.app_regions())?,
+        };
+
+        config.regions[region_num] = Some(PMPRegion::guard(start as *const u8, size));
+        config.is_dirty.set(true);
+
+        config.sort_regions();
+
+        Some(mpu::Region::new(start as *const u8, size))
+    }
+
+    fn remove_stack_guard_region(&self, config: &mut Self::MpuConfig) {
+        if let Some(region_num) = config
+            .regions
+            .iter()
+            .position(|region| region.map_or(false, |region| region.is_guard()))
+        {
+            config.regions[region_num] = None;
+            config.is_dirty.set(true);
+
+            config.sort_regions();
+        }
+    }
+
     fn configure_mpu(&self, config: &Self::MpuConfig, app_id: &AppId) {
         // Is the PMP already configured for this app?
         let last_configured_for_this_app = self
@@ -477,7 +595,7 @@ impl kernel::mpu::MPU for PMP {
         // Skip PMP configuration if it is already configured for this app and the MPU
         // configuration of this app has not changed.
         if !last_configured_for_this_app || config.is_dirty.get() {
-            for (x, region) in config.regions.iter().enumerate() {
+            for (x, region) in config.regions.iter().enumerate().take(self.app_regions()) {
                 match region {
                     Some(r) => {
                         let cfg_val = r.cfg.value as u32;
@@ -487,11 +605,15 @@ impl kernel::mpu::MPU for PMP {
                         match x % 2 {
                             0 => {
                                 // Disable access up to the start address
+                                // and clear what the end entry allowed before.
                                 csr::CSR.pmpcfg[x / 2].modify(
                                     csr::pmpconfig::pmpcfg::r0::CLEAR
                                         + csr::pmpconfig::pmpcfg::w0::CLEAR
                                         + csr::pmpconfig::pmpcfg::x0::CLEAR
-                                        + csr::pmpconfig::pmpcfg::a0::TOR,
+                                        + csr::pmpconfig::pmpcfg::a0::TOR
+                                        + csr::pmpconfig::pmpcfg::r1::CLEAR
+                                        + csr::pmpconfig::pmpcfg::w1::CLEAR
+                                        + csr::pmpconfig::pmpcfg::x1::CLEAR,
                                 );
                                 csr::CSR.pmpaddr[x * 2].set((start as u32) >> 2);
 
@@ -503,11 +625,15 @@ impl kernel::mpu::MPU for PMP {
                             }
                             1 => {
                                 // Disable access up to the start address
+                                // and clear what the end entry allowed before.
                                 csr::CSR.pmpcfg[x / 2].modify(
                                     csr::pmpconfig::pmpcfg::r2::CLEAR
                                         + csr::pmpconfig::pmpcfg::w2::CLEAR
                                         + csr::pmpconfig::pmpcfg::x2::CLEAR
-                                        + csr::pmpconfig::pmpcfg::a2::TOR,
+                                        + csr::pmpconfig::pmpcfg::a2::TOR
+                                        + csr::pmpconfig::pmpcfg::r3::CLEAR
+                                        + csr::pmpconfig::pmpcfg::w3::CLEAR
+                                        + csr::pmpconfig::pmpcfg::x3::CLEAR,
                                 );
                                 csr::CSR.pmpaddr[x * 2].set((start as u32) >> 2);
 
@@ -527,6 +653,113 @@ impl kernel::mpu::MPU for PMP {
             self.last_configured_for.put(*app_id);
         }
     }
+}
+
+/// At most this many regions can protect the kernel.
+const MAX_KERNEL_REGIONS: usize = 4;
+
+/// Struct storing the configuration of the kernel regions of the PMP.
+pub struct PMPKernelConfig {
+    regions: [Option<PMPRegion>; MAX_KERNEL_REGIONS],
+}
+
+impl Default for PMPKernelConfig {
+    fn default() -> Self {
+        PMPKernelConfig {
+            regions: [None; MAX_KERNEL_REGIONS],
+        }
+    }
+}
+
+impl fmt::Display for PMPKernelConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, " PMP kernel regions:")?;
+        for (n, region) in self.regions.iter().enumerate() {
+            match region {
+                None => writeln!(f, "  <unset>")?,
+                Some(region) => writeln!(f, "  [{}]: {}", n, region)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl kernel::mpu::KernelMPU for PMP {
+    type KernelMpuConfig = PMPKernelConfig;
+
+    fn allocate_kernel_region(
+        &self,
+        memory_start: *const u8,
+        memory_size: usize,
+        permissions: mpu::Permissions,
+        config: &mut Self::KernelMpuConfig,
+    ) -> Option<mpu::Region> {
+        // Kernel entries are locked once enabled, and processes need at least
+        // one region.
+        if self.kernel_regions.get() != 0 {
+            return None;
+        }
+        let max_regions = cmp::min(MAX_KERNEL_REGIONS, $x / 2 - 1);
+        let region_num = config
+            .regions
+            .iter()
+            .take(max_regions)
+            .position(|region| region.is_none())?;
+
+        // With Machine Mode Lockdown, locked entries that allow writing and
+        // executing are shared read-only regions, so the kernel cannot have
+        // both.
+        let pmpcfg = match permissions {
+            mpu::Permissions::ReadWriteExecute => return None,
+            mpu::Permissions::ReadWriteOnly => pmpcfg::r::SET + pmpcfg::w::SET,
+            mpu::Permissions::ReadExecuteOnly => pmpcfg::r::SET + pmpcfg::x::SET,
+            mpu::Permissions::ReadOnly => pmpcfg::r::SET,
+            mpu::Permissions::ExecuteOnly => pmpcfg::x::SET,
+        };
+
+        // TOR addresses have a granularity of 4 bytes, so round the region
+        // out to cover all of the memory.
+        let start = (memory_start as usize) & !0x3;
+        let end = (memory_start as usize).checked_add(memory_size)?;
+        let end = end.checked_add(3)? & !0x3;
+        if memory_size == 0 {
+            return None;
+        }
+
+        config.regions[region_num] = Some(PMPRegion {
+            location: (start as *const u8, end - start),
+            cfg: pmpcfg + pmpcfg::a::TOR + pmpcfg::l::SET,
+        });
+
+        Some(mpu::Region::new(start as *const u8, end - start))
+    }
+
+    fn enable_kernel_mpu(&self, config: &mut Self::KernelMpuConfig) {
+        let num_regions = config.regions.iter().filter(|region| region.is_some()).count();
+
+        // Turn off the process entries, which would otherwise apply to
+        // machine mode as well until Machine Mode Lockdown is set.
+        self.kernel_regions.set(num_regions);
+        self.disable_app_entries();
+
+        // Each kernel region uses the two entries of a region at the top of
+        // the PMP. The first entry only holds the start address, so it never
+        // matches, and both are locked so they cannot be changed until reset.
+        for (i, region) in config.regions.iter().flatten().enumerate() {
+            let entry = ($x / 2 - num_regions + i) * 2;
+            let start = region.location.0 as usize;
+            let end = start + region.location.1;
+
+            csr::CSR.pmpaddr[entry].set((start as u32) >> 2);
+            csr::CSR.pmpaddr[entry + 1].set((end as u32) >> 2);
+            PMP::set_entry_cfg(entry, u8::from(pmpcfg::a::OFF + pmpcfg::l::SET));
+            PMP::set_entry_cfg(entry + 1, u8::from(region.cfg));
+        }
+
+        // From now on locked entries only apply to machine mode, and machine
+        // mode can only execute the kernel regions that allow it.
+        csr::CSR.mseccfg.modify(mseccfg::mml::SET);
     }
+}
 };
 }
@@ -356,7 +356,7 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
                         );
                         match syscall {
                             Some(s) => ContextSwitchReason::SyscallFired { syscall: s },
-                            None => ContextSwitchReason::Fault,
+                            None => ContextSwitchReason::InvalidSyscall,
                         }
                     }
                     _ => {
@@ -370,6 +370,14 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
         (new_stack_pointer as *mut usize, ret)
     }
 
+    unsafe fn get_process_pc(
+        &self,
+        _stack_pointer: *const usize,
+        state: &RiscvimacStoredState,
+    ) -> usize {
+        state.pc
+    }
+
     unsafe fn print_context(
         &self,
         stack_pointer: *const usize,
@@ -0,0 +1,98 @@
+//! Component for the application watchdog, with which processes prove that
+//! they are alive.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let app_watchdog = components::app_watchdog::AppWatchdogComponent::new(
+//!     board_kernel,
+//!     mux_alarm,
+//!     &["controller"],
+//! )
+//! .finalize(components::app_watchdog_component_helper!(sam4l::ast::Ast));
+//! ```
+//!
+//! The last argument names the critical processes. When one of them misses
+//! a heartbeat, the kernel stops tickling the hardware watchdog.
+
+use core::mem::MaybeUninit;
+
+use capsules::app_watchdog::AppWatchdog;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::{self, Alarm};
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! app_watchdog_component_helper {
+    ($A:ty) => {{
+        use capsules::app_watchdog::AppWatchdog;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use components::app_watchdog::Capability;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<
+            AppWatchdog<'static, VirtualMuxAlarm<'static, $A>, Capability>,
+        > = MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+unsafe impl capabilities::WatchdogCapability for Capability {}
+
+pub struct AppWatchdogComponent<A: 'static + time::Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    critical_processes: &'static [&'static str],
+}
+
+impl<A: 'static + time::Alarm<'static>> AppWatchdogComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        mux: &'static MuxAlarm<'static, A>,
+        critical_processes: &'static [&'static str],
+    ) -> AppWatchdogComponent<A> {
+        AppWatchdogComponent {
+            board_kernel: board_kernel,
+            alarm_mux: mux,
+            critical_processes: critical_processes,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for AppWatchdogComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<AppWatchdog<'static, VirtualMuxAlarm<'static, A>, Capability>>,
+    );
+    type Output = &'static AppWatchdog<'static, VirtualMuxAlarm<'static, A>, Capability>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let watchdog_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let app_watchdog = static_init_half!(
+            static_buffer.1,
+            AppWatchdog<'static, VirtualMuxAlarm<'static, A>, Capability>,
+            AppWatchdog::new(
+                self.board_kernel,
+                watchdog_alarm,
+                self.board_kernel.create_grant(&grant_cap),
+                self.critical_processes,
+                Capability,
+            )
+        );
+
+        watchdog_alarm.set_alarm_client(app_watchdog);
+        app_watchdog
+    }
+}
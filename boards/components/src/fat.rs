@@ -0,0 +1,55 @@
+//! Component for files on a FAT volume, such as on an SD card.
+//!
+//! The volume is mounted when a process first uses the driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let fat = components::fat::FatComponent::new(board_kernel, sdcard).finalize(());
+//! ```
+
+use capsules::fat::{FatDriver, FatFilesystem};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::block_storage::BlockStorage;
+use kernel::static_init;
+
+pub struct FatComponent {
+    board_kernel: &'static kernel::Kernel,
+    device: &'static dyn BlockStorage<'static>,
+}
+
+impl FatComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        device: &'static dyn BlockStorage<'static>,
+    ) -> FatComponent {
+        FatComponent {
+            board_kernel: board_kernel,
+            device: device,
+        }
+    }
+}
+
+impl Component for FatComponent {
+    type StaticInput = ();
+    type Output = &'static FatDriver<'static>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let filesystem = static_init!(
+            FatFilesystem<'static>,
+            FatFilesystem::new(self.device, &mut capsules::fat::BUFFER)
+        );
+        self.device.set_client(filesystem);
+
+        let fat = static_init!(
+            FatDriver<'static>,
+            FatDriver::new(filesystem, self.board_kernel.create_grant(&grant_cap))
+        );
+        filesystem.set_client(fat);
+        fat
+    }
+}
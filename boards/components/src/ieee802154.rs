@@ -1,16 +1,22 @@
 //! Component for IEEE 802.15.4 radio syscall interface.
 //!
-//! This provides one Component, `Ieee802154Component`, which implements a
-//! userspace syscall interface to a full 802.15.4 stack with a
-//! always-on MAC implementation, as well as multiplexed access to that MAC implementation.
+//! This provides two Components. `AesCcmMuxComponent` shares one AES-CCM
+//! engine, built on the AES hardware, between the users that secure frames.
+//! `Ieee802154Component` implements a userspace syscall interface to a full
+//! 802.15.4 stack with a always-on MAC implementation, as well as multiplexed
+//! access to that MAC implementation.
 //!
 //! Usage
 //! -----
 //! ```rust
+//! let mux_aes_ccm = components::ieee802154::AesCcmMuxComponent::new(&nrf52::aes::AESECB)
+//!     .finalize(components::aes_ccm_mux_component_helper!(
+//!         nrf52::aes::AesECB<'static>
+//!     ));
 //! let (radio, mux_mac) = components::ieee802154::Ieee802154Component::new(
 //!     board_kernel,
 //!     &nrf52::ieee802154_radio::RADIO,
-//!     &nrf52::aes::AESECB,
+//!     mux_aes_ccm,
 //!     PAN_ID,
 //!     SRC_MAC,
 //! )
@@ -23,6 +29,7 @@
 use capsules;
 use capsules::ieee802154::device::MacDevice;
 use capsules::ieee802154::mac::{AwakeMac, Mac};
+use capsules::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
@@ -30,22 +37,76 @@ use kernel::hil::radio;
 use kernel::hil::symmetric_encryption::{self, AES128Ctr, AES128, AES128CBC, AES128CCM};
 use kernel::{create_capability, static_init, static_init_half};
 
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! aes_ccm_mux_component_helper {
+    ($A:ty) => {{
+        use capsules::virtual_aes_ccm::MuxAES128CCM;
+        use core::mem::MaybeUninit;
+
+        static mut BUF1: MaybeUninit<capsules::aes_ccm::AES128CCM<'static, $A>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<
+            MuxAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, $A>>,
+        > = MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct AesCcmMuxComponent<A: 'static + AES128<'static> + AES128Ctr + AES128CBC> {
+    aes: &'static A,
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC> AesCcmMuxComponent<A> {
+    pub fn new(aes: &'static A) -> Self {
+        Self { aes }
+    }
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC> Component for AesCcmMuxComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<capsules::aes_ccm::AES128CCM<'static, A>>,
+        &'static mut MaybeUninit<MuxAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, A>>>,
+    );
+    type Output = &'static MuxAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let aes_ccm = static_init_half!(
+            static_buffer.0,
+            capsules::aes_ccm::AES128CCM<'static, A>,
+            capsules::aes_ccm::AES128CCM::new(self.aes, &mut CRYPT_BUF)
+        );
+        self.aes.set_client(aes_ccm);
+        self.aes.enable();
+
+        let mux_aes_ccm = static_init_half!(
+            static_buffer.1,
+            MuxAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, A>>,
+            MuxAES128CCM::new(aes_ccm)
+        );
+        aes_ccm.set_client(mux_aes_ccm);
+
+        mux_aes_ccm
+    }
+}
+
 // Setup static space for the objects.
 #[macro_export]
 macro_rules! ieee802154_component_helper {
     ($R:ty, $A:ty) => {{
         use capsules::ieee802154::mac::AwakeMac;
+        use capsules::virtual_aes_ccm::VirtualAES128CCM;
         use core::mem::MaybeUninit;
-        use kernel::hil::symmetric_encryption::{AES128Ctr, AES128, AES128CBC, AES128CCM};
 
-        static mut BUF1: MaybeUninit<capsules::aes_ccm::AES128CCM<'static, $A>> =
-            MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<
+            VirtualAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, $A>>,
+        > = MaybeUninit::uninit();
         static mut BUF2: MaybeUninit<AwakeMac<'static, $R>> = MaybeUninit::uninit();
         static mut BUF3: MaybeUninit<
             capsules::ieee802154::framer::Framer<
                 'static,
                 AwakeMac<'static, $R>,
-                capsules::aes_ccm::AES128CCM<'static, $A>,
+                VirtualAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, $A>>,
             >,
         > = MaybeUninit::uninit();
         (&mut BUF1, &mut BUF2, &mut BUF3)
@@ -58,7 +119,7 @@ pub struct Ieee802154Component<
 > {
     board_kernel: &'static kernel::Kernel,
     radio: &'static R,
-    aes: &'static A,
+    mux_aes_ccm: &'static MuxAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, A>>,
     pan_id: capsules::net::ieee802154::PanID,
     short_addr: u16,
 }
@@ -71,14 +132,14 @@ impl<
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         radio: &'static R,
-        aes: &'static A,
+        mux_aes_ccm: &'static MuxAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, A>>,
         pan_id: capsules::net::ieee802154::PanID,
         short_addr: u16,
     ) -> Self {
         Self {
             board_kernel,
             radio,
-            aes,
+            mux_aes_ccm,
             pan_id,
             short_addr,
         }
@@ -91,8 +152,10 @@ static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
 static mut RADIO_RX_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
 
 // This buffer is used as an intermediate buffer for AES CCM encryption
-// An upper bound on the required size is 3 * BLOCK_SIZE + radio::MAX_BUF_SIZE
-const CRYPT_SIZE: usize = 3 * symmetric_encryption::AES128_BLOCK_SIZE + radio::MAX_BUF_SIZE;
+// An upper bound on the required size is 3 * BLOCK_SIZE plus the length of the
+// longest secured data, which MLE messages are: they can be longer than frames
+const CRYPT_SIZE: usize =
+    3 * symmetric_encryption::AES128_BLOCK_SIZE + capsules::net::thread::mle::CRYPT_BUF_LEN;
 static mut CRYPT_BUF: [u8; CRYPT_SIZE] = [0x00; CRYPT_SIZE];
 
 impl<
@@ -101,13 +164,15 @@ impl<
     > Component for Ieee802154Component<R, A>
 {
     type StaticInput = (
-        &'static mut MaybeUninit<capsules::aes_ccm::AES128CCM<'static, A>>,
+        &'static mut MaybeUninit<
+            VirtualAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, A>>,
+        >,
         &'static mut MaybeUninit<capsules::ieee802154::mac::AwakeMac<'static, R>>,
         &'static mut MaybeUninit<
             capsules::ieee802154::framer::Framer<
                 'static,
                 AwakeMac<'static, R>,
-                capsules::aes_ccm::AES128CCM<'static, A>,
+                VirtualAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, A>>,
             >,
         >,
     );
@@ -121,14 +186,12 @@ impl<
 
         let aes_ccm = static_init_half!(
             static_buffer.0,
-            capsules::aes_ccm::AES128CCM<'static, A>,
-            capsules::aes_ccm::AES128CCM::new(self.aes, &mut CRYPT_BUF)
+            VirtualAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, A>>,
+            VirtualAES128CCM::new(self.mux_aes_ccm)
         );
 
-        self.aes.set_client(aes_ccm);
-        self.aes.enable();
-
-        // Keeps the radio on permanently; pass-through layer
+        // Keeps the radio on permanently, unless its receiver is turned off
+        // when idle; pass-through layer
         let awake_mac = static_init_half!(
             static_buffer.1,
             AwakeMac<'static, R>,
@@ -136,13 +199,14 @@ impl<
         );
         self.radio.set_transmit_client(awake_mac);
         self.radio.set_receive_client(awake_mac, &mut RADIO_RX_BUF);
+        self.radio.set_power_client(awake_mac);
 
         let mac_device = static_init_half!(
             static_buffer.2,
             capsules::ieee802154::framer::Framer<
                 'static,
                 AwakeMac<'static, R>,
-                capsules::aes_ccm::AES128CCM<'static, A>,
+                VirtualAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, A>>,
             >,
             capsules::ieee802154::framer::Framer::new(awake_mac, aes_ccm)
         );
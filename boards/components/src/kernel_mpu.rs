@@ -0,0 +1,142 @@
+//! Component that protects the kernel's own memory with the MPU.
+//!
+//! This provides one Component, KernelMpuComponent, which marks the kernel's
+//! code read-execute, its read-only data read-only and its RAM (stack, data
+//! and BSS) read-write but not executable, and then enables the kernel MPU.
+//! The regions come from the symbols defined in `boards/kernel_layout.ld`.
+//!
+//! On MPUs that round regions out to their granularity, such as the Cortex-M
+//! MPU, the board must align `_srodata` by defining `KERNEL_RODATA_ALIGN` in
+//! its linker script, or the component panics because the code region would
+//! cover read-only data.
+//!
+//! The component must be finalized before processes are loaded, since the
+//! kernel regions reduce the number of regions available to processes. On
+//! RISC-V it requires a PMP that supports Smepmp.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! components::kernel_mpu::KernelMpuComponent::new(chip.mpu())
+//!     .finalize(components::kernel_mpu_component_helper!(cortexm4::mpu::MPU));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use kernel::component::Component;
+use kernel::mpu::{KernelMPU, Permissions};
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! kernel_mpu_component_helper {
+    ($M:ty) => {{
+        use core::mem::MaybeUninit;
+        use kernel::mpu::KernelMPU;
+        static mut BUF: MaybeUninit<<$M as KernelMPU>::KernelMpuConfig> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct KernelMpuComponent<M: 'static + KernelMPU> {
+    mpu: &'static M,
+}
+
+impl<M: 'static + KernelMPU> KernelMpuComponent<M> {
+    pub fn new(mpu: &'static M) -> KernelMpuComponent<M> {
+        KernelMpuComponent { mpu: mpu }
+    }
+}
+
+impl<M: 'static + KernelMPU> Component for KernelMpuComponent<M> {
+    type StaticInput = &'static mut MaybeUninit<M::KernelMpuConfig>;
+    type Output = &'static M::KernelMpuConfig;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        /// These symbols are defined in the linker script.
+        extern "C" {
+            /// Beginning of the kernel's code in flash.
+            static _stext: u8;
+            /// End of the kernel's code and beginning of its read-only data.
+            static _srodata: u8;
+            /// End of the kernel's read-only data.
+            static _erodata: u8;
+            /// Beginning of the kernel's RAM, where its stack is.
+            static _sstack: u8;
+            /// End of the kernel's RAM, after the BSS.
+            static _ezero: u8;
+            /// Beginning of the kernel's non-volatile storage, after the
+            /// read-only data.
+            static _sstorage: u8;
+            /// Beginning of the applications in flash.
+            static _sapps: u8;
+        }
+
+        let config = static_init_half!(
+            static_buffer,
+            M::KernelMpuConfig,
+            M::KernelMpuConfig::default()
+        );
+
+        let text_start = &_stext as *const u8;
+        let rodata_start = &_srodata as *const u8;
+        let rodata_end = &_erodata as *const u8;
+        let ram_start = &_sstack as *const u8;
+        let ram_end = &_ezero as *const u8;
+
+        // The MPU can round a region out past the end of the memory. No
+        // region may reach the storage, which the kernel writes, or the
+        // applications, since kernel regions also apply to processes. The
+        // code must not reach the read-only data either, which would make it
+        // executable; boards whose MPU rounds regions out align `_srodata`
+        // with `KERNEL_RODATA_ALIGN` in their linker script. Since regions
+        // allocated later take precedence, the code is allocated after the
+        // read-only data, which can then extend back into the code.
+        let storage_start = &_sstorage as *const u8;
+        let apps_start = &_sapps as *const u8;
+        let regions: [(*const u8, *const u8, Permissions, &[*const u8]); 3] = [
+            (
+                rodata_start,
+                rodata_end,
+                Permissions::ReadOnly,
+                &[storage_start, apps_start],
+            ),
+            (
+                text_start,
+                rodata_start,
+                Permissions::ReadExecuteOnly,
+                &[rodata_start, storage_start, apps_start],
+            ),
+            (
+                ram_start,
+                ram_end,
+                Permissions::ReadWriteOnly,
+                &[storage_start, apps_start],
+            ),
+        ];
+        for &(start, end, permissions, boundaries) in regions.iter() {
+            let region = match self.mpu.allocate_kernel_region(
+                start,
+                end as usize - start as usize,
+                permissions,
+                config,
+            ) {
+                Some(region) => region,
+                None => panic!("Cannot protect kernel memory [{:p}:{:p}]", start, end),
+            };
+            let region_start = region.start_address();
+            let region_end = region_start.wrapping_add(region.size());
+            for &boundary in boundaries.iter() {
+                if region_start < boundary && boundary < region_end {
+                    panic!(
+                        "Kernel MPU region [{:p}:{:p}] crosses {:p}",
+                        region_start, region_end, boundary
+                    );
+                }
+            }
+        }
+
+        self.mpu.enable_kernel_mpu(config);
+        config
+    }
+}
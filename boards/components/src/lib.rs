@@ -4,6 +4,7 @@
 pub mod adc;
 pub mod alarm;
 pub mod analog_comparator;
+pub mod app_watchdog;
 pub mod bus;
 pub mod button;
 pub mod cdc;
@@ -12,6 +13,7 @@ pub mod crc;
 pub mod ctap;
 pub mod debug_queue;
 pub mod debug_writer;
+pub mod fat;
 pub mod ft6x06;
 pub mod gpio;
 pub mod hd44780;
@@ -19,27 +21,33 @@ pub mod hmac;
 pub mod i2c;
 pub mod ieee802154;
 pub mod isl29035;
+pub mod kernel_mpu;
 pub mod l3gd20;
 pub mod led;
 pub mod lldb;
 pub mod lsm303dlhc;
 pub mod mlx90614;
 pub mod mx25r6435f;
+pub mod neighbor_discovery;
 pub mod ninedof;
 pub mod nonvolatile_storage;
 pub mod nrf51822;
 pub mod panic_button;
 pub mod process_console;
 pub mod rng;
+pub mod rpl;
 pub mod sched;
 pub mod screen;
 pub mod segger_rtt;
 pub mod si7021;
 pub mod spi;
+pub mod spi_nor;
 pub mod st77xx;
+pub mod tcp;
 pub mod temperature;
 pub mod temperature_stm;
 pub mod test;
+pub mod thread;
 pub mod touch;
 pub mod udp_driver;
 pub mod udp_mux;
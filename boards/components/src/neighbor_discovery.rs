@@ -0,0 +1,272 @@
+//! Component to initialize ICMPv6 and 6LoWPAN Neighbor Discovery.
+//!
+//! This provides one Component, NeighborDiscoveryComponent. Like the TCP
+//! stack, ICMPv6 uses its own MAC user, 6LoWPAN state and IP sender and
+//! receiver on the shared `MuxMac`. The component returns the
+//! `NeighborDiscovery` capsule, which answers echo requests and registers the
+//! node with a router once started, the `NeighborCache` it maintains, and a
+//! `RoutingTable`, which stays empty unless a routing protocol such as RPL
+//! (see `RplComponent`) fills it. The cache and the routing table should be
+//! passed to `UDPMuxComponent` and `TCPComponent`, so that their packets are
+//! sent to the right neighbor.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (neighbor_discovery, neighbor_cache, routing_table) = NeighborDiscoveryComponent::new(
+//!        mux_mac,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        DST_MAC_ADDR,
+//!        src_mac_from_serial_num,
+//!        local_ip_ifaces,
+//!        mux_alarm,
+//!    )
+//!    .finalize(components::neighbor_discovery_component_helper!(sam4l::ast::Ast));
+//!    ...
+//!    neighbor_discovery.start();
+//! ```
+
+use capsules;
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
+use capsules::net::icmpv6::icmpv6_nd::NeighborDiscovery;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_recv::IP6Receiver;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::neighbor_cache::{NeighborCache, NeighborEntry};
+use capsules::net::ipv6::routing_table::{Route, RoutingTable};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::{static_init, static_init_half};
+
+/// Number of neighbors, including routers, the neighbor cache can hold.
+pub const NEIGHBOR_CACHE_SIZE: usize = 8;
+
+/// Number of routes the routing table can hold.
+pub const ROUTING_TABLE_SIZE: usize = 8;
+
+/// Largest ICMPv6 payload sent by the stack, which limits the size of the
+/// echo requests that are answered.
+pub const MAX_MESSAGE_LEN: usize = 200;
+
+static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
+static mut ICMP_DGRAM: [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+static mut ND_BUF: [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+static mut NEIGHBOR_CACHE_ENTRIES: [Option<NeighborEntry>; NEIGHBOR_CACHE_SIZE] =
+    [None; NEIGHBOR_CACHE_SIZE];
+static mut ROUTES: [Option<Route>; ROUTING_TABLE_SIZE] = [None; ROUTING_TABLE_SIZE];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! neighbor_discovery_component_helper {
+    ($A:ty) => {{
+        use capsules;
+        use capsules::net::icmpv6::icmpv6_nd::NeighborDiscovery;
+        use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>> =
+            MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+                sixlowpan_compression::Context,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<sixlowpan_state::RxState<'static>> = MaybeUninit::uninit();
+        static mut BUF5: MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>,
+        > = MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<NeighborDiscovery<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6,
+        )
+    };};
+}
+
+pub struct NeighborDiscoveryComponent<A: Alarm<'static> + 'static> {
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    interface_list: &'static [IPAddr],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> NeighborDiscoveryComponent<A> {
+    pub fn new(
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        interface_list: &'static [IPAddr],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            mux_mac,
+            ctx_pfix_len,
+            ctx_pfix,
+            dst_mac_addr,
+            src_mac_addr,
+            interface_list,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for NeighborDiscoveryComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>>,
+        &'static mut MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+        >,
+        &'static mut MaybeUninit<sixlowpan_state::RxState<'static>>,
+        &'static mut MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        &'static mut MaybeUninit<NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = (
+        &'static NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>>,
+        &'static NeighborCache<'static>,
+        &'static RoutingTable<'static>,
+    );
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let ipsender_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let nd_virtual_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        let icmp_mac = static_init_half!(
+            static_buffer.2,
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(icmp_mac);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let sixlowpan = static_init_half!(
+            static_buffer.3,
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
+            )
+        );
+
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+        let default_rx_state = static_init_half!(
+            static_buffer.4,
+            sixlowpan_state::RxState<'static>,
+            sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUF)
+        );
+        sixlowpan_state.add_rx_state(default_rx_state);
+        icmp_mac.set_receive_client(sixlowpan);
+
+        let ip_pyld: IPPayload = IPPayload {
+            header: TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type129)),
+            payload: &mut ICMP_DGRAM,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let neighbor_cache = static_init!(
+            NeighborCache<'static>,
+            NeighborCache::new(&mut NEIGHBOR_CACHE_ENTRIES)
+        );
+        let routing_table = static_init!(RoutingTable<'static>, RoutingTable::new(&mut ROUTES));
+
+        let ip_send = static_init_half!(
+            static_buffer.5,
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+            capsules::net::ipv6::ipv6_send::IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RADIO_BUF,
+                sixlowpan_tx,
+                icmp_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_alarm_client(ip_send);
+        ip_send.set_neighbor_cache(neighbor_cache);
+        ip_send.set_routing_table(routing_table);
+        icmp_mac.set_transmit_client(ip_send);
+
+        let ip_receive = static_init!(
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct::new()
+        );
+        sixlowpan_state.set_rx_client(ip_receive);
+
+        let neighbor_discovery = static_init_half!(
+            static_buffer.6,
+            NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>>,
+            NeighborDiscovery::new(
+                ip_send,
+                neighbor_cache,
+                nd_virtual_alarm,
+                self.src_mac_addr,
+                self.interface_list,
+                &mut ND_BUF,
+                net_cap,
+            )
+        );
+        ip_send.set_client(neighbor_discovery);
+        ip_receive.set_client(neighbor_discovery);
+        nd_virtual_alarm.set_alarm_client(neighbor_discovery);
+
+        (neighbor_discovery, neighbor_cache, routing_table)
+    }
+}
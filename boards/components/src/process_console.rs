@@ -7,8 +7,13 @@
 //! Usage
 //! -----
 //! ```rust
-//! let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux).finalize(());
+//! let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux, Some(reset)).finalize(());
 //! ```
+//!
+//! The last argument is the function the `reboot` command calls. On
+//! bare-metal targets the `kernel` command prints the addresses of the
+//! kernel's sections, which are read from the symbols of the board's linker
+//! script.
 
 // Author: Philip Levis <pal@cs.stanford.edu>
 // Last modified: 6/20/2018
@@ -23,20 +28,64 @@ use kernel::static_init;
 pub struct ProcessConsoleComponent {
     board_kernel: &'static kernel::Kernel,
     uart_mux: &'static MuxUart<'static>,
+    reset_function: Option<fn() -> !>,
 }
 
 impl ProcessConsoleComponent {
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         uart_mux: &'static MuxUart,
+        reset_function: Option<fn() -> !>,
     ) -> ProcessConsoleComponent {
         ProcessConsoleComponent {
             board_kernel: board_kernel,
             uart_mux: uart_mux,
+            reset_function: reset_function,
         }
     }
 }
 
+/// Addresses of the kernel's sections, from the symbols of the board's linker
+/// script.
+#[cfg(target_os = "none")]
+unsafe fn kernel_addresses() -> Option<process_console::KernelAddresses> {
+    extern "C" {
+        /// Beginning of the kernel's stack.
+        static _sstack: u8;
+        /// End of the kernel's stack.
+        static _estack: u8;
+        /// Beginning of the kernel's code.
+        static _stext: u8;
+        /// End of the kernel's code and read-only data.
+        static _etext: u8;
+        /// Beginning of the kernel's initialized data in RAM.
+        static _srelocate: u8;
+        /// End of the kernel's initialized data in RAM.
+        static _erelocate: u8;
+        /// Beginning of the kernel's BSS.
+        static _szero: u8;
+        /// End of the kernel's BSS.
+        static _ezero: u8;
+    }
+
+    Some(process_console::KernelAddresses {
+        stack_start: &_sstack,
+        stack_end: &_estack,
+        text_start: &_stext,
+        text_end: &_etext,
+        relocate_start: &_srelocate,
+        relocate_end: &_erelocate,
+        bss_start: &_szero,
+        bss_end: &_ezero,
+    })
+}
+
+/// Boards built for a hosted target have no linker script.
+#[cfg(not(target_os = "none"))]
+unsafe fn kernel_addresses() -> Option<process_console::KernelAddresses> {
+    None
+}
+
 pub struct Capability;
 unsafe impl capabilities::ProcessManagementCapability for Capability {}
 
@@ -56,7 +105,10 @@ impl Component for ProcessConsoleComponent {
                 &mut process_console::WRITE_BUF,
                 &mut process_console::READ_BUF,
                 &mut process_console::COMMAND_BUF,
+                &mut process_console::HISTORY_BUF,
                 self.board_kernel,
+                kernel_addresses(),
+                self.reset_function,
                 Capability,
             )
         );
@@ -0,0 +1,301 @@
+//! Component to initialize RPL routing.
+//!
+//! This provides one Component, RplComponent. Like the ICMPv6 stack of
+//! `NeighborDiscoveryComponent`, RPL uses its own MAC user, 6LoWPAN state and
+//! IP sender and receiver on the shared `MuxMac`. The component returns the
+//! `RplNode` capsule, which fills the `RoutingTable` created by
+//! `NeighborDiscoveryComponent` once started, using the link estimates of its
+//! `NeighborCache` to choose parents, and which forwards packets for other
+//! nodes through this IP sender.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let rpl_node = RplComponent::new(
+//!        mux_mac,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        DST_MAC_ADDR,
+//!        src_mac_from_serial_num,
+//!        local_ip_ifaces,
+//!        neighbor_cache,
+//!        routing_table,
+//!        mux_alarm,
+//!    )
+//!    .finalize(components::rpl_component_helper!(sam4l::ast::Ast));
+//!    ...
+//!    rpl_node.start();
+//! ```
+
+use capsules;
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_recv::IP6Receiver;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::neighbor_cache::NeighborCache;
+use capsules::net::ipv6::routing_table::RoutingTable;
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::rpl::rpl_node::RplNode;
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::net::trickle::Trickle;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::{static_init, static_init_half};
+
+/// Largest RPL message sent by the node, which limits the number of targets
+/// a DAO can carry.
+pub const MAX_MESSAGE_LEN: usize = 200;
+/// Largest payload of a forwarded packet, that of a packet filling the
+/// 1280-byte receive buffer.
+pub const MAX_FORWARDED_LEN: usize = 1280 - 40;
+
+static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
+static mut IP6_DGRAM: [u8; MAX_FORWARDED_LEN] = [0; MAX_FORWARDED_LEN];
+static mut RPL_BUF: [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+static mut FORWARD_BUF: [u8; MAX_FORWARDED_LEN] = [0; MAX_FORWARDED_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! rpl_component_helper {
+    ($A:ty) => {{
+        use capsules;
+        use capsules::net::rpl::rpl_node::RplNode;
+        use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+        use capsules::net::trickle::Trickle;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>> =
+            MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+                sixlowpan_compression::Context,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF5: MaybeUninit<sixlowpan_state::RxState<'static>> = MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>,
+        > = MaybeUninit::uninit();
+        static mut BUF7: MaybeUninit<Trickle<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF8: MaybeUninit<RplNode<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6, &mut BUF7,
+            &mut BUF8,
+        )
+    };};
+}
+
+/// Seeds the Trickle timer with the low bits of the MAC address, which
+/// differ between neighbors.
+fn trickle_seed(mac_addr: MacAddress) -> u32 {
+    match mac_addr {
+        MacAddress::Short(short_addr) => short_addr as u32,
+        MacAddress::Long(long_addr) => {
+            (long_addr[4] as u32) << 24
+                | (long_addr[5] as u32) << 16
+                | (long_addr[6] as u32) << 8
+                | long_addr[7] as u32
+        }
+    }
+}
+
+pub struct RplComponent<A: Alarm<'static> + 'static> {
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    interface_list: &'static [IPAddr],
+    neighbor_cache: &'static NeighborCache<'static>,
+    routing_table: &'static RoutingTable<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> RplComponent<A> {
+    pub fn new(
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        interface_list: &'static [IPAddr],
+        neighbor_cache: &'static NeighborCache<'static>,
+        routing_table: &'static RoutingTable<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            mux_mac,
+            ctx_pfix_len,
+            ctx_pfix,
+            dst_mac_addr,
+            src_mac_addr,
+            interface_list,
+            neighbor_cache,
+            routing_table,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for RplComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>>,
+        &'static mut MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+        >,
+        &'static mut MaybeUninit<sixlowpan_state::RxState<'static>>,
+        &'static mut MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        &'static mut MaybeUninit<Trickle<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<RplNode<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static RplNode<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let ipsender_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let rpl_virtual_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let trickle_virtual_alarm = static_init_half!(
+            static_buffer.2,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        let rpl_mac = static_init_half!(
+            static_buffer.3,
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(rpl_mac);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let sixlowpan = static_init_half!(
+            static_buffer.4,
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
+            )
+        );
+
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+        let default_rx_state = static_init_half!(
+            static_buffer.5,
+            sixlowpan_state::RxState<'static>,
+            sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUF)
+        );
+        sixlowpan_state.add_rx_state(default_rx_state);
+        rpl_mac.set_receive_client(sixlowpan);
+
+        let ip_pyld: IPPayload = IPPayload {
+            header: TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type155)),
+            payload: &mut IP6_DGRAM,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_send = static_init_half!(
+            static_buffer.6,
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+            capsules::net::ipv6::ipv6_send::IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RADIO_BUF,
+                sixlowpan_tx,
+                rpl_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_alarm_client(ip_send);
+        ip_send.set_neighbor_cache(self.neighbor_cache);
+        ip_send.set_routing_table(self.routing_table);
+        rpl_mac.set_transmit_client(ip_send);
+
+        let ip_receive = static_init!(
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct::new()
+        );
+        sixlowpan_state.set_rx_client(ip_receive);
+
+        let trickle = static_init_half!(
+            static_buffer.7,
+            Trickle<'static, VirtualMuxAlarm<'static, A>>,
+            Trickle::new(trickle_virtual_alarm, trickle_seed(self.src_mac_addr))
+        );
+        trickle_virtual_alarm.set_alarm_client(trickle);
+
+        let rpl_node = static_init_half!(
+            static_buffer.8,
+            RplNode<'static, VirtualMuxAlarm<'static, A>>,
+            RplNode::new(
+                ip_send,
+                self.neighbor_cache,
+                self.routing_table,
+                trickle,
+                rpl_virtual_alarm,
+                self.src_mac_addr,
+                self.interface_list,
+                &mut RPL_BUF,
+                &mut FORWARD_BUF,
+                net_cap,
+            )
+        );
+        trickle.set_client(rpl_node);
+        ip_send.set_client(rpl_node);
+        ip_receive.set_client(rpl_node);
+        rpl_virtual_alarm.set_alarm_client(rpl_node);
+
+        rpl_node
+    }
+}
@@ -0,0 +1,108 @@
+//! Component for the real-time (earliest deadline first or rate monotonic)
+//! scheduler.
+//!
+//! This provides one Component, EDFComponent. The board can give real-time
+//! requirements for processes by name; `finalize()` panics if they are
+//! invalid, for example if a budget is smaller than
+//! `RealtimeParameters::MIN_BUDGET_US`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static REALTIME_PARAMETERS: [(&str, kernel::RealtimeParameters); 1] = [(
+//!     "control_loop",
+//!     kernel::RealtimeParameters {
+//!         period_us: 10_000,
+//!         deadline_us: 8_000,
+//!         budget_us: 2_000,
+//!     },
+//! )];
+//! let scheduler = components::sched::edf::EDFComponent::new(
+//!     mux_alarm,
+//!     &PROCESSES,
+//!     kernel::SchedulingPolicy::EarliestDeadlineFirst,
+//!     &REALTIME_PARAMETERS,
+//! )
+//! .finalize(components::edf_component_helper!(sam4l::ast::Ast, NUM_PROCS));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::component::Component;
+use kernel::hil::time::{self, Alarm};
+use kernel::procs::ProcessType;
+use kernel::static_init_half;
+use kernel::{EDFProcessNode, EDFSched, RealtimeParameters, SchedulingPolicy};
+
+#[macro_export]
+macro_rules! edf_component_helper {
+    ($A:ty, $N:expr) => {{
+        use core::mem::MaybeUninit;
+        use kernel::static_init;
+        use kernel::{EDFProcessNode, EDFSched};
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<EDFSched<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF3: [MaybeUninit<EDFProcessNode<'static>>; $N] = [MaybeUninit::uninit(); $N];
+        (&mut BUF1, &mut BUF2, &mut BUF3)
+    };};
+}
+
+pub struct EDFComponent<A: 'static + time::Alarm<'static>> {
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    processes: &'static [Option<&'static dyn ProcessType>],
+    policy: SchedulingPolicy,
+    parameters: &'static [(&'static str, RealtimeParameters)],
+}
+
+impl<A: 'static + time::Alarm<'static>> EDFComponent<A> {
+    pub fn new(
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        processes: &'static [Option<&'static dyn ProcessType>],
+        policy: SchedulingPolicy,
+        parameters: &'static [(&'static str, RealtimeParameters)],
+    ) -> EDFComponent<A> {
+        EDFComponent {
+            alarm_mux,
+            processes,
+            policy,
+            parameters,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for EDFComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<EDFSched<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut [MaybeUninit<EDFProcessNode<'static>>],
+    );
+    type Output = &'static EDFSched<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let (alarm_buf, sched_buf, proc_nodes) = static_buffer;
+        let scheduler_alarm = static_init_half!(
+            alarm_buf,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let scheduler = static_init_half!(
+            sched_buf,
+            EDFSched<'static, VirtualMuxAlarm<'static, A>>,
+            EDFSched::new(scheduler_alarm, self.policy, self.parameters)
+        );
+        scheduler_alarm.set_alarm_client(scheduler);
+        // Keep the order of the processes array, which breaks ties between
+        // processes.
+        for (i, node) in proc_nodes.iter_mut().enumerate() {
+            let init_node = static_init_half!(
+                node,
+                EDFProcessNode<'static>,
+                EDFProcessNode::new(&self.processes[i])
+            );
+            scheduler.processes.push_tail(init_node);
+        }
+        scheduler
+    }
+}
@@ -0,0 +1,284 @@
+//! Component to initialize the TCP/6LoWPAN stack and the userland TCP driver.
+//!
+//! This provides one Component, TCPComponent. TCP uses its own MAC user,
+//! 6LoWPAN state and IP sender and receiver, so it can be used alongside the
+//! UDP stack created by `UDPMuxComponent` on the same `MuxMac`. The component
+//! returns the `MuxTcp`, which kernel capsules can use to open their own
+//! sockets, and the userland driver, which shares a pool of `NUM_SOCKETS`
+//! sockets between all processes.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (tcp_mux, tcp_driver) = TCPComponent::new(
+//!        board_kernel,
+//!        mux_mac,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        DST_MAC_ADDR,
+//!        src_mac_from_serial_num,
+//!        local_ip_ifaces,
+//!        neighbor_cache,
+//!        routing_table,
+//!        mux_alarm,
+//!    )
+//!    .finalize(components::tcp_component_helper!(sam4l::ast::Ast));
+//! ```
+
+use capsules;
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_recv::IP6Receiver;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::neighbor_cache::NeighborCache;
+use capsules::net::ipv6::routing_table::RoutingTable;
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::net::tcp::tcp::TCPHeader;
+use capsules::net::tcp::tcp_mux::{MuxTcp, TCPStack};
+use capsules::net::tcp::tcp_socket::TCPSocket;
+use capsules::net::tcp::TCPDriver;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::{static_init, static_init_half};
+
+/// Number of sockets available to processes.
+pub const NUM_SOCKETS: usize = 2;
+
+/// Largest segment payload sent by the stack. This is also the MSS announced
+/// to peers.
+pub const MAX_SEGMENT_LEN: usize = 200;
+
+const SOCKET_BUF_LEN: usize = 512;
+
+static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
+static mut TCP_SEGMENT: [u8; MAX_SEGMENT_LEN] = [0; MAX_SEGMENT_LEN];
+static mut MUX_BUF: [u8; MAX_SEGMENT_LEN] = [0; MAX_SEGMENT_LEN];
+static mut SOCKET_TX_BUFS: [[u8; SOCKET_BUF_LEN]; NUM_SOCKETS] = [[0; SOCKET_BUF_LEN]; NUM_SOCKETS];
+static mut SOCKET_RX_BUFS: [[u8; SOCKET_BUF_LEN]; NUM_SOCKETS] = [[0; SOCKET_BUF_LEN]; NUM_SOCKETS];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! tcp_component_helper {
+    ($A:ty) => {{
+        use capsules;
+        use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+        use capsules::net::tcp::tcp_mux::MuxTcp;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>> =
+            MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+                sixlowpan_compression::Context,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<sixlowpan_state::RxState<'static>> = MaybeUninit::uninit();
+        static mut BUF5: MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>,
+        > = MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<MuxTcp<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6,
+        )
+    };};
+}
+
+pub struct TCPComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    interface_list: &'static [IPAddr],
+    neighbor_cache: &'static NeighborCache<'static>,
+    routing_table: &'static RoutingTable<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> TCPComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        interface_list: &'static [IPAddr],
+        neighbor_cache: &'static NeighborCache<'static>,
+        routing_table: &'static RoutingTable<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            mux_mac,
+            ctx_pfix_len,
+            ctx_pfix,
+            dst_mac_addr,
+            src_mac_addr,
+            interface_list,
+            neighbor_cache,
+            routing_table,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for TCPComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>>,
+        &'static mut MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+        >,
+        &'static mut MaybeUninit<sixlowpan_state::RxState<'static>>,
+        &'static mut MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        &'static mut MaybeUninit<MuxTcp<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = (
+        &'static MuxTcp<'static, VirtualMuxAlarm<'static, A>>,
+        &'static TCPDriver<'static>,
+    );
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let ipsender_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let tcp_virtual_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        let tcp_mac = static_init_half!(
+            static_buffer.2,
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(tcp_mac);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let sixlowpan = static_init_half!(
+            static_buffer.3,
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
+            )
+        );
+
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+        let default_rx_state = static_init_half!(
+            static_buffer.4,
+            sixlowpan_state::RxState<'static>,
+            sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUF)
+        );
+        sixlowpan_state.add_rx_state(default_rx_state);
+        tcp_mac.set_receive_client(sixlowpan);
+
+        let ip_pyld: IPPayload = IPPayload {
+            header: TransportHeader::TCP(TCPHeader::new()),
+            payload: &mut TCP_SEGMENT,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_send = static_init_half!(
+            static_buffer.5,
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+            capsules::net::ipv6::ipv6_send::IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RADIO_BUF,
+                sixlowpan_tx,
+                tcp_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_alarm_client(ip_send);
+        ip_send.set_addr(self.interface_list[0]);
+        ip_send.set_neighbor_cache(self.neighbor_cache);
+        ip_send.set_routing_table(self.routing_table);
+        tcp_mac.set_transmit_client(ip_send);
+
+        let ip_receive = static_init!(
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct::new()
+        );
+        sixlowpan_state.set_rx_client(ip_receive);
+
+        let tcp_mux = static_init_half!(
+            static_buffer.6,
+            MuxTcp<'static, VirtualMuxAlarm<'static, A>>,
+            MuxTcp::new(ip_send, tcp_virtual_alarm, &mut MUX_BUF, net_cap)
+        );
+        ip_send.set_client(tcp_mux);
+        ip_receive.set_client(tcp_mux);
+        tcp_virtual_alarm.set_alarm_client(tcp_mux);
+
+        let sockets = static_init!(
+            [TCPSocket<'static>; NUM_SOCKETS],
+            [
+                TCPSocket::new(0, &mut SOCKET_TX_BUFS[0], &mut SOCKET_RX_BUFS[0]),
+                TCPSocket::new(1, &mut SOCKET_TX_BUFS[1], &mut SOCKET_RX_BUFS[1]),
+            ]
+        );
+
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let tcp_driver = static_init!(
+            TCPDriver<'static>,
+            TCPDriver::new(tcp_mux, sockets, self.board_kernel.create_grant(&grant_cap))
+        );
+        for socket in sockets.iter() {
+            socket.set_client(tcp_driver);
+            tcp_mux.add_socket(socket);
+        }
+
+        (tcp_mux, tcp_driver)
+    }
+}
@@ -0,0 +1,342 @@
+//! Component to attach to a Thread network as a sleepy end device.
+//!
+//! This provides one Component, ThreadComponent. Like the ICMPv6 stack of
+//! `NeighborDiscoveryComponent`, Thread MLE uses its own MAC user, 6LoWPAN
+//! state and IP sender and receiver on the shared `MuxMac`, and secures its
+//! messages with its own user of the AES-CCM engine shared through
+//! `AesCcmMuxComponent`. The component returns the `MleAttach` capsule,
+//! which, once started, attaches to a parent, configures the MAC layer with
+//! the assigned address, adds the parent to the `NeighborCache` and enables
+//! link-layer security on the IP senders in `data_senders`.
+//!
+//! `MleAttach` becomes the key and device procedure of the framer, so the
+//! keys configured through the userspace 802.15.4 driver no longer apply.
+//! It derives the keys from the network master key, starting with the Key
+//! Sequence `key_sequence`. The data requests of a sleepy end device are
+//! sent by a `DataPoll` with another MAC user.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let mle_attach = ThreadComponent::new(
+//!        mux_mac,
+//!        mux_aes_ccm,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        DST_MAC_ADDR,
+//!        src_mac_from_serial_num,
+//!        neighbor_cache,
+//!        data_senders,
+//!        mux_alarm,
+//!        random,
+//!        MASTER_KEY,
+//!        0,
+//!    )
+//!    .finalize(components::thread_component_helper!(
+//!        sam4l::ast::Ast,
+//!        sam4l::aes::Aes<'static>
+//!    ));
+//!    ...
+//!    mle_attach.start();
+//! ```
+
+use capsules;
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_recv::IP6Receiver;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::neighbor_cache::NeighborCache;
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::net::thread::data_poll::DataPoll;
+use capsules::net::thread::mle;
+use capsules::net::thread::mle_attach::{MleAttach, TX_BUF_LEN};
+use capsules::net::udp::udp::UDPHeader;
+use capsules::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::rng::Random;
+use kernel::hil::symmetric_encryption::{AES128Ctr, AES128, AES128CBC, AES128CCM};
+use kernel::hil::time::Alarm;
+use kernel::{static_init, static_init_half};
+
+static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
+static mut UDP_DGRAM: [u8; TX_BUF_LEN] = [0; TX_BUF_LEN];
+static mut MLE_BUF: [u8; TX_BUF_LEN] = [0; TX_BUF_LEN];
+static mut CRYPT_BUF: [u8; mle::CRYPT_BUF_LEN] = [0; mle::CRYPT_BUF_LEN];
+static mut POLL_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! thread_component_helper {
+    ($A:ty, $C:ty) => {{
+        use capsules;
+        use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+        use capsules::net::thread::data_poll::DataPoll;
+        use capsules::net::thread::mle_attach::MleAttach;
+        use capsules::virtual_aes_ccm::VirtualAES128CCM;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>> =
+            MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+                sixlowpan_compression::Context,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<sixlowpan_state::RxState<'static>> = MaybeUninit::uninit();
+        static mut BUF5: MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>,
+        > = MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<
+            VirtualAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, $C>>,
+        > = MaybeUninit::uninit();
+        static mut BUF7: MaybeUninit<MleAttach<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF8: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF9: MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>> =
+            MaybeUninit::uninit();
+        static mut BUF10: MaybeUninit<DataPoll<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6, &mut BUF7,
+            &mut BUF8, &mut BUF9, &mut BUF10,
+        )
+    };};
+}
+
+pub struct ThreadComponent<
+    A: Alarm<'static> + 'static,
+    C: 'static + AES128<'static> + AES128Ctr + AES128CBC,
+> {
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    mux_aes_ccm: &'static MuxAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, C>>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    neighbor_cache: &'static NeighborCache<'static>,
+    data_senders: &'static [&'static dyn IP6Sender<'static>],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    random: &'static dyn Random<'static>,
+    master_key: [u8; 16],
+    key_sequence: u32,
+}
+
+impl<A: Alarm<'static> + 'static, C: 'static + AES128<'static> + AES128Ctr + AES128CBC>
+    ThreadComponent<A, C>
+{
+    pub fn new(
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        mux_aes_ccm: &'static MuxAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, C>>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        neighbor_cache: &'static NeighborCache<'static>,
+        data_senders: &'static [&'static dyn IP6Sender<'static>],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        random: &'static dyn Random<'static>,
+        master_key: [u8; 16],
+        key_sequence: u32,
+    ) -> Self {
+        Self {
+            mux_mac,
+            mux_aes_ccm,
+            ctx_pfix_len,
+            ctx_pfix,
+            dst_mac_addr,
+            src_mac_addr,
+            neighbor_cache,
+            data_senders,
+            alarm_mux,
+            random,
+            master_key,
+            key_sequence,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static, C: 'static + AES128<'static> + AES128Ctr + AES128CBC> Component
+    for ThreadComponent<A, C>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>>,
+        &'static mut MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+        >,
+        &'static mut MaybeUninit<sixlowpan_state::RxState<'static>>,
+        &'static mut MaybeUninit<
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        &'static mut MaybeUninit<
+            VirtualAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, C>>,
+        >,
+        &'static mut MaybeUninit<MleAttach<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<capsules::ieee802154::virtual_mac::MacUser<'static>>,
+        &'static mut MaybeUninit<DataPoll<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static MleAttach<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let ipsender_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let mle_virtual_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        let mle_mac = static_init_half!(
+            static_buffer.2,
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(mle_mac);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let sixlowpan = static_init_half!(
+            static_buffer.3,
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+            sixlowpan_state::Sixlowpan::new(
+                sixlowpan_compression::Context {
+                    prefix: self.ctx_pfix,
+                    prefix_len: self.ctx_pfix_len,
+                    id: 0,
+                    compress: false,
+                },
+                ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
+            )
+        );
+
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+        let default_rx_state = static_init_half!(
+            static_buffer.4,
+            sixlowpan_state::RxState<'static>,
+            sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUF)
+        );
+        sixlowpan_state.add_rx_state(default_rx_state);
+        mle_mac.set_receive_client(sixlowpan);
+
+        let ip_pyld: IPPayload = IPPayload {
+            header: TransportHeader::UDP(UDPHeader::new()),
+            payload: &mut UDP_DGRAM,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        // MLE messages are secured by MLE itself, so this sender never
+        // enables link-layer security.
+        let ip_send = static_init_half!(
+            static_buffer.5,
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+            capsules::net::ipv6::ipv6_send::IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut RADIO_BUF,
+                sixlowpan_tx,
+                mle_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_alarm_client(ip_send);
+        ip_send.set_neighbor_cache(self.neighbor_cache);
+        mle_mac.set_transmit_client(ip_send);
+
+        let ip_receive = static_init!(
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct::new()
+        );
+        sixlowpan_state.set_rx_client(ip_receive);
+
+        let aes_ccm = static_init_half!(
+            static_buffer.6,
+            VirtualAES128CCM<'static, capsules::aes_ccm::AES128CCM<'static, C>>,
+            VirtualAES128CCM::new(self.mux_aes_ccm)
+        );
+
+        let poll_virtual_alarm = static_init_half!(
+            static_buffer.8,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let poll_mac = static_init_half!(
+            static_buffer.9,
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(poll_mac);
+        let data_poll = static_init_half!(
+            static_buffer.10,
+            DataPoll<'static, VirtualMuxAlarm<'static, A>>,
+            DataPoll::new(poll_mac, poll_virtual_alarm, &mut POLL_BUF)
+        );
+        poll_mac.set_transmit_client(data_poll);
+        poll_virtual_alarm.set_alarm_client(data_poll);
+
+        let mle_attach = static_init_half!(
+            static_buffer.7,
+            MleAttach<'static, VirtualMuxAlarm<'static, A>>,
+            MleAttach::new(
+                ip_send,
+                self.data_senders,
+                mle_mac,
+                self.neighbor_cache,
+                aes_ccm,
+                mle_virtual_alarm,
+                self.random,
+                data_poll,
+                self.master_key,
+                self.key_sequence,
+                &mut CRYPT_BUF,
+                &mut MLE_BUF,
+                net_cap,
+            )
+        );
+        aes_ccm.set_client(mle_attach);
+        ip_send.set_client(mle_attach);
+        ip_receive.set_client(mle_attach);
+        mle_virtual_alarm.set_alarm_client(mle_attach);
+        mle_mac.set_key_procedure(mle_attach);
+        mle_mac.set_device_procedure(mle_attach);
+        data_poll.set_client(mle_attach);
+
+        mle_attach
+    }
+}
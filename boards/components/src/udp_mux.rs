@@ -2,7 +2,9 @@
 //!
 //! This provides one Component, UDPMuxComponent. This component
 //! exposes a MuxUdpSender that other components can implement
-//! UDPSenders on top of to use the UDP/6Lowpan stack.
+//! UDPSenders on top of to use the UDP/6Lowpan stack. Packets are
+//! sent to the next hop found in the provided RoutingTable and
+//! NeighborCache, which are created by the NeighborDiscoveryComponent.
 //!
 //! Usage
 //! -----
@@ -14,6 +16,8 @@
 //!        DST_MAC_ADDR,
 //!        src_mac_from_serial_num,
 //!        local_ip_ifaces,
+//!        neighbor_cache,
+//!        routing_table,
 //!        mux_alarm,
 //!        MAX_PAYLOAD_LEN,
 //!    )
@@ -31,6 +35,8 @@ use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
 use capsules::net::ipv6::ipv6_recv::IP6Receiver;
 use capsules::net::ipv6::ipv6_send::IP6SendStruct;
 use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::neighbor_cache::NeighborCache;
+use capsules::net::ipv6::routing_table::RoutingTable;
 use capsules::net::network_capabilities::{IpVisibilityCapability, UdpVisibilityCapability};
 use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
 use capsules::net::udp::udp::UDPHeader;
@@ -120,6 +126,8 @@ pub struct UDPMuxComponent<A: Alarm<'static> + 'static> {
     dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
     interface_list: &'static [IPAddr],
+    neighbor_cache: &'static NeighborCache<'static>,
+    routing_table: &'static RoutingTable<'static>,
     alarm_mux: &'static MuxAlarm<'static, A>,
 }
 
@@ -131,6 +139,8 @@ impl<A: Alarm<'static> + 'static> UDPMuxComponent<A> {
         dst_mac_addr: MacAddress,
         src_mac_addr: MacAddress,
         interface_list: &'static [IPAddr],
+        neighbor_cache: &'static NeighborCache<'static>,
+        routing_table: &'static RoutingTable<'static>,
         alarm_mux: &'static MuxAlarm<'static, A>,
     ) -> Self {
         Self {
@@ -140,6 +150,8 @@ impl<A: Alarm<'static> + 'static> UDPMuxComponent<A> {
             dst_mac_addr,
             src_mac_addr,
             interface_list,
+            neighbor_cache,
+            routing_table,
             alarm_mux,
         }
     }
@@ -231,12 +243,9 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
         };
         let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
 
-        // In current design, all udp senders share same IP sender, and the IP sender
-        // holds the destination mac address. This means all UDP senders must send to
-        // the same mac address...this works fine under the assumption
-        // of all packets being routed via a single gateway router, but doesn't work
-        // if multiple senders want to send to different addresses on a local network.
-        // This will be fixed once we have an ipv6_nd cache mapping IP addresses to dst macs
+        // All udp senders share the same IP sender, which looks up the destination
+        // mac address of each packet in the routing table and neighbor cache. The
+        // dst_mac_addr is only used for destinations the cache has no next hop for.
         let ip_send = static_init_half!(
             static_buffer.4,
             capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
@@ -258,6 +267,8 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
         // Notably, the src addr is the same regardless of if messages are sent from
         // userland or capsules.
         ip_send.set_addr(self.interface_list[0]);
+        ip_send.set_neighbor_cache(self.neighbor_cache);
+        ip_send.set_routing_table(self.routing_table);
         udp_mac.set_transmit_client(ip_send);
 
         let ip_receive = static_init!(
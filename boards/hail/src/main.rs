@@ -19,6 +19,7 @@ use kernel::component::Component;
 use kernel::hil;
 use kernel::hil::i2c::I2CMaster;
 use kernel::hil::Controller;
+use kernel::Chip;
 use kernel::Platform;
 #[allow(unused_imports)]
 use kernel::{create_capability, debug, debug_gpio, static_init};
@@ -71,6 +72,7 @@ struct Hail {
     button: &'static capsules::button::Button<'static, sam4l::gpio::GPIOPin<'static>>,
     rng: &'static capsules::rng::RngDriver<'static>,
     ipc: kernel::ipc::IPC,
+    ipc_messaging: &'static kernel::ipc::IPCMessaging,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     dac: &'static capsules::dac::Dac<'static>,
 }
@@ -103,6 +105,7 @@ impl Platform for Hail {
             capsules::dac::DRIVER_NUM => f(Some(self.dac)),
 
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
+            kernel::ipc::MESSAGE_DRIVER_NUM => f(Some(self.ipc_messaging)),
             _ => f(None),
         }
     }
@@ -169,6 +172,16 @@ unsafe fn set_pin_primary_functions(peripherals: &Sam4lDefaultPeripherals) {
     peripherals.pb[15].configure(None); //... D1
 }
 
+/// Reset the board, for the `reboot` command of the process console.
+fn reset() -> ! {
+    unsafe {
+        cortexm4::scb::reset();
+    }
+    loop {
+        cortexm4::support::nop();
+    }
+}
+
 /// Reset Handler.
 ///
 /// This symbol is loaded into vector table by the SAM4L chip crate.
@@ -200,7 +213,7 @@ pub unsafe fn reset_handler() {
     );
     CHIP = Some(chip);
 
-    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&mut PROCESSES));
 
     // Create capabilities that the board needs to call certain protected kernel
     // functions.
@@ -241,9 +254,12 @@ pub unsafe fn reset_handler() {
 
     // Setup the console and the process inspection console.
     let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
-    let process_console =
-        components::process_console::ProcessConsoleComponent::new(board_kernel, uart_mux)
-            .finalize(());
+    let process_console = components::process_console::ProcessConsoleComponent::new(
+        board_kernel,
+        uart_mux,
+        Some(reset),
+    )
+    .finalize(());
     components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
 
     // Initialize USART3 for UART for the nRF serialization link.
@@ -430,6 +446,12 @@ pub unsafe fn reset_handler() {
     );
     let fault_response = kernel::procs::FaultResponse::Restart(restart_policy);
 
+    let ipc_messaging = static_init!(
+        kernel::ipc::IPCMessaging,
+        kernel::ipc::IPCMessaging::new(board_kernel, &memory_allocation_capability)
+    );
+    board_kernel.set_ipc_messaging(ipc_messaging, &main_loop_capability);
+
     let hail = Hail {
         console,
         gpio,
@@ -445,6 +467,7 @@ pub unsafe fn reset_handler() {
         button,
         rng,
         ipc: kernel::ipc::IPC::new(board_kernel, &memory_allocation_capability),
+        ipc_messaging,
         crc,
         dac,
     };
@@ -459,6 +482,11 @@ pub unsafe fn reset_handler() {
 
     debug!("Initialization complete. Entering main loop.");
 
+    // Protect the kernel's code, read-only data and RAM. This must happen
+    // before processes are loaded, since they use the remaining MPU regions.
+    components::kernel_mpu::KernelMpuComponent::new(chip.mpu())
+        .finalize(components::kernel_mpu_component_helper!(cortexm4::mpu::MPU));
+
     /// These symbols are defined in the linker script.
     extern "C" {
         /// Beginning of the ROM region containing app images.
@@ -482,7 +510,6 @@ pub unsafe fn reset_handler() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         fault_response,
         &process_management_capability,
     )
@@ -0,0 +1,136 @@
+//! Helpers for host applications using the drivers of this board, and
+//! example applications.
+
+use std::cell::Cell;
+use std::slice;
+
+use host_emulation::tbf::HostApp;
+use host_emulation::userspace;
+
+const CONSOLE: usize = capsules::console::DRIVER_NUM;
+const ALARM: usize = capsules::alarm::DRIVER_NUM;
+const CONSOLE_BUFFER_LEN: usize = 64;
+
+thread_local! {
+    /// Addresses of the buffers shared with the console for writing and
+    /// reading.
+    static CONSOLE_BUFFERS: Cell<Option<(usize, usize)>> = Cell::new(None);
+    static WRITE_DONE: Cell<bool> = Cell::new(false);
+    /// Number of bytes read, once reading finished.
+    static READ_DONE: Cell<Option<usize>> = Cell::new(None);
+    static ALARM_FIRED: Cell<bool> = Cell::new(false);
+}
+
+fn write_done(_: usize, _: usize, _: usize, _: usize) {
+    WRITE_DONE.with(|done| done.set(true));
+}
+
+fn read_done(_: usize, len: usize, _: usize, _: usize) {
+    READ_DONE.with(|done| done.set(Some(len)));
+}
+
+fn alarm_fired(_: usize, _: usize, _: usize, _: usize) {
+    ALARM_FIRED.with(|fired| fired.set(true));
+}
+
+/// The buffers for writing to and reading from the console.
+fn console_buffers() -> Option<(&'static mut [u8], &'static mut [u8])> {
+    let (write, read) = CONSOLE_BUFFERS.with(|buffers| buffers.get()).or_else(|| {
+        let write = userspace::allocate(CONSOLE_BUFFER_LEN)?.as_mut_ptr() as usize;
+        let read = userspace::allocate(CONSOLE_BUFFER_LEN)?.as_mut_ptr() as usize;
+        CONSOLE_BUFFERS.with(|buffers| buffers.set(Some((write, read))));
+        Some((write, read))
+    })?;
+    unsafe {
+        Some((
+            slice::from_raw_parts_mut(write as *mut u8, CONSOLE_BUFFER_LEN),
+            slice::from_raw_parts_mut(read as *mut u8, CONSOLE_BUFFER_LEN),
+        ))
+    }
+}
+
+/// Write `text` to the console, and wait until it has been written.
+pub fn print(text: &str) {
+    let buffer = match console_buffers() {
+        Some((buffer, _)) => buffer,
+        None => return,
+    };
+    userspace::subscribe(CONSOLE, 1, Some(write_done), 0);
+    for chunk in text.as_bytes().chunks(CONSOLE_BUFFER_LEN) {
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        userspace::allow(CONSOLE, 1, buffer.as_mut_ptr(), chunk.len());
+        WRITE_DONE.with(|done| done.set(false));
+        if userspace::command(CONSOLE, 1, chunk.len(), 0) < 0 {
+            return;
+        }
+        while !WRITE_DONE.with(|done| done.get()) {
+            userspace::yield_for_callback();
+        }
+    }
+}
+
+/// Read up to `len` bytes (at most 64) from the console, and wait until
+/// they have been received.
+pub fn read(len: usize) -> Vec<u8> {
+    let buffer = match console_buffers() {
+        Some((_, buffer)) => buffer,
+        None => return Vec::new(),
+    };
+    let len = len.min(CONSOLE_BUFFER_LEN);
+    userspace::allow(CONSOLE, 2, buffer.as_mut_ptr(), len);
+    userspace::subscribe(CONSOLE, 2, Some(read_done), 0);
+    READ_DONE.with(|done| done.set(None));
+    if userspace::command(CONSOLE, 2, len, 0) < 0 {
+        return Vec::new();
+    }
+    loop {
+        if let Some(received) = READ_DONE.with(|done| done.get()) {
+            return buffer[..received.min(len)].to_vec();
+        }
+        userspace::yield_for_callback();
+    }
+}
+
+/// Wait for `ms` milliseconds.
+pub fn sleep_ms(ms: u32) {
+    let frequency = userspace::command(ALARM, 1, 0, 0);
+    if frequency <= 0 {
+        return;
+    }
+    let ticks = ms as u64 * frequency as u64 / 1000;
+    userspace::subscribe(ALARM, 0, Some(alarm_fired), 0);
+    ALARM_FIRED.with(|fired| fired.set(false));
+    if userspace::command(ALARM, 5, ticks as usize, 0) < 0 {
+        return;
+    }
+    while !ALARM_FIRED.with(|fired| fired.get()) {
+        userspace::yield_for_callback();
+    }
+}
+
+fn hello_main(_: usize, _: usize, _: usize, _: usize) {
+    print("Hello from a host process!\r\n");
+}
+
+fn counter_main(_: usize, _: usize, _: usize, _: usize) {
+    for count in 1.. {
+        sleep_ms(1000);
+        print(&format!("counter: {}\r\n", count));
+    }
+}
+
+/// Prints a greeting.
+pub const HELLO: HostApp = HostApp {
+    name: "hello",
+    main: hello_main,
+    minimum_ram_size: 4096,
+    ipc_clients: &[],
+};
+
+/// Prints how many seconds it has been running every second.
+pub const COUNTER: HostApp = HostApp {
+    name: "counter",
+    main: counter_main,
+    minimum_ram_size: 4096,
+    ipc_clients: &[],
+};
@@ -0,0 +1,210 @@
+//! Board file for running Tock as a Linux process.
+//!
+//! The board runs on the emulated chip from the `host_emulation` crate and
+//! provides the console, alarm, IPC messaging and application watchdog
+//! drivers to its processes, which are host applications written against
+//! `host_emulation::userspace`. The `apps` module has helpers for using these
+//! drivers from applications. The UART also runs the process console, which
+//! takes commands typed on the input.
+//!
+//! `setup()` creates the board; `Emulation::run()` then runs the kernel
+//! forever, while `Emulation::run_until()` runs it until a condition holds,
+//! which is how tests drive the kernel.
+
+#![feature(const_in_array_repeat_expressions)]
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use capsules::virtual_alarm::VirtualMuxAlarm;
+use host_emulation::chip::HostChip;
+use host_emulation::tbf::{self, HostApp};
+use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
+use kernel::component::Component;
+use kernel::Platform;
+use kernel::{create_capability, debug, static_init};
+
+pub mod apps;
+
+pub const NUM_PROCS: usize = 4;
+
+/// Memory available to each process.
+pub const PROCESS_MEMORY_SIZE: usize = 64 * 1024;
+
+/// Processes for which a missed heartbeat to the application watchdog stops
+/// the kernel from tickling the watchdog of the chip.
+pub const CRITICAL_PROCESSES: &[&str] = &["critical"];
+
+static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROCS] =
+    [None; NUM_PROCS];
+
+/// A structure representing this platform that holds references to all
+/// capsules for this platform.
+pub struct HostBoard {
+    console: &'static capsules::console::Console<'static>,
+    alarm: &'static capsules::alarm::AlarmDriver<
+        'static,
+        VirtualMuxAlarm<'static, host_emulation::alarm::HostAlarm<'static>>,
+    >,
+    ipc_messaging: &'static kernel::ipc::IPCMessaging,
+    app_watchdog: &'static capsules::app_watchdog::AppWatchdog<
+        'static,
+        VirtualMuxAlarm<'static, host_emulation::alarm::HostAlarm<'static>>,
+        components::app_watchdog::Capability,
+    >,
+}
+
+/// Mapping of integer syscalls to objects that implement syscalls.
+impl Platform for HostBoard {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn kernel::Driver>) -> R,
+    {
+        match driver_num {
+            capsules::console::DRIVER_NUM => f(Some(self.console)),
+            capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            kernel::ipc::MESSAGE_DRIVER_NUM => f(Some(self.ipc_messaging)),
+            capsules::app_watchdog::DRIVER_NUM => f(Some(self.app_watchdog)),
+            _ => f(None),
+        }
+    }
+}
+
+/// The kernel, chip and board of an emulated system.
+pub struct Emulation {
+    pub kernel: &'static kernel::Kernel,
+    pub chip: &'static HostChip,
+    pub board: &'static HostBoard,
+    pub scheduler: &'static kernel::RoundRobinSched<'static>,
+}
+
+impl Emulation {
+    /// Run the kernel loop forever.
+    pub fn run(&self) -> ! {
+        let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+        self.kernel
+            .kernel_loop(self.board, self.chip, None, self.scheduler, &main_loop_cap)
+    }
+
+    /// Run the kernel until `done` returns true, or until `timeout` elapsed.
+    /// Returns whether `done` returned true.
+    ///
+    /// The kernel does not sleep, so `done` is checked after every iteration
+    /// of the kernel loop.
+    pub fn run_until<F: FnMut() -> bool>(&self, timeout: Duration, mut done: F) -> bool {
+        let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            self.kernel.kernel_loop_operation(
+                self.board,
+                self.chip,
+                None,
+                self.scheduler,
+                true,
+                &main_loop_cap,
+            );
+            if done() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Create the board and load `apps` as its processes. The console writes to
+/// `output`.
+///
+/// Like the kernel, this uses global state, so it must only be called once
+/// in a Linux process.
+pub unsafe fn setup(apps: &[HostApp], output: Box<dyn Write>) -> Emulation {
+    let process_mgmt_cap = create_capability!(capabilities::ProcessManagementCapability);
+    let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+    let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&mut PROCESSES));
+
+    let dynamic_deferred_call_clients =
+        static_init!([DynamicDeferredCallClientState; 2], Default::default());
+    let dynamic_deferred_caller = static_init!(
+        DynamicDeferredCall,
+        DynamicDeferredCall::new(dynamic_deferred_call_clients)
+    );
+    DynamicDeferredCall::set_global_instance(dynamic_deferred_caller);
+
+    let chip = static_init!(HostChip, HostChip::new());
+    chip.uart.set_output(output);
+    board_kernel.set_clock(&chip.alarm, &main_loop_cap);
+
+    // Create a shared UART channel for the console and for kernel debug.
+    let uart_mux =
+        components::console::UartMuxComponent::new(&chip.uart, 115200, dynamic_deferred_caller)
+            .finalize(());
+
+    let mux_alarm = components::alarm::AlarmMuxComponent::new(&chip.alarm).finalize(
+        components::alarm_mux_component_helper!(host_emulation::alarm::HostAlarm),
+    );
+    let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm).finalize(
+        components::alarm_component_helper!(host_emulation::alarm::HostAlarm),
+    );
+    let app_watchdog = components::app_watchdog::AppWatchdogComponent::new(
+        board_kernel,
+        mux_alarm,
+        CRITICAL_PROCESSES,
+    )
+    .finalize(components::app_watchdog_component_helper!(
+        host_emulation::alarm::HostAlarm
+    ));
+
+    // Setup the console and the process inspection console.
+    let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
+    let process_console =
+        components::process_console::ProcessConsoleComponent::new(board_kernel, uart_mux, None)
+            .finalize(());
+    // Create the debugger object that handles calls to `debug!()`.
+    components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
+
+    let ipc_messaging = static_init!(
+        kernel::ipc::IPCMessaging,
+        kernel::ipc::IPCMessaging::new(board_kernel, &memory_allocation_cap)
+    );
+    board_kernel.set_ipc_messaging(ipc_messaging, &main_loop_cap);
+
+    let board = static_init!(
+        HostBoard,
+        HostBoard {
+            console: console,
+            alarm: alarm,
+            ipc_messaging: ipc_messaging,
+            app_watchdog: app_watchdog,
+        }
+    );
+
+    kernel::procs::load_processes(
+        board_kernel,
+        chip,
+        tbf::create_app_flash(apps),
+        tbf::create_app_memory(NUM_PROCS * PROCESS_MEMORY_SIZE),
+        kernel::procs::FaultResponse::Restart(static_init!(
+            kernel::procs::ThresholdRestartThenPanic,
+            kernel::procs::ThresholdRestartThenPanic::new(4)
+        )),
+        &process_mgmt_cap,
+    )
+    .unwrap_or_else(|err| {
+        debug!("Error loading processes!");
+        debug!("{:?}", err);
+    });
+
+    process_console.start();
+
+    let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
+        .finalize(components::rr_component_helper!(NUM_PROCS));
+
+    Emulation {
+        kernel: board_kernel,
+        chip: chip,
+        board: board,
+        scheduler: scheduler,
+    }
+}
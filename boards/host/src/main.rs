@@ -0,0 +1,34 @@
+//! Run Tock as a Linux process with the example applications.
+//!
+//! The console uses stdin and stdout. To use another terminal instead, pass
+//! the path of a pty (for example one created with
+//! `socat -d -d pty,raw,echo=0 pty,raw,echo=0`) as the only argument.
+
+use std::env;
+use std::fs::OpenOptions;
+use std::io;
+
+use host::apps;
+
+fn main() {
+    let apps = [apps::HELLO, apps::COUNTER];
+    let emulation = match env::args().nth(1) {
+        Some(path) => {
+            let tty = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap_or_else(|err| panic!("cannot open {}: {}", path, err));
+            let input = tty.try_clone().expect("cannot open pty for reading");
+            let emulation = unsafe { host::setup(&apps, Box::new(tty)) };
+            emulation.chip.uart.attach_reader(input);
+            emulation
+        }
+        None => {
+            let emulation = unsafe { host::setup(&apps, Box::new(io::stdout())) };
+            emulation.chip.uart.attach_reader(io::stdin());
+            emulation
+        }
+    };
+    emulation.run();
+}
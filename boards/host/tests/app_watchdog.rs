@@ -0,0 +1,103 @@
+//! Processes that miss heartbeats to the application watchdog are faulted
+//! and restarted, and a critical one starves the watchdog of the chip.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use host::apps;
+use host_emulation::tbf::HostApp;
+use host_emulation::uart::CapturedOutput;
+use host_emulation::userspace;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::create_capability;
+use kernel::introspection::KernelInfo;
+use kernel::procs::FaultReason;
+
+const WATCHDOG: usize = capsules::app_watchdog::DRIVER_NUM;
+
+static CRITICAL_RUNS: AtomicUsize = AtomicUsize::new(0);
+static WORKER_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Sends heartbeats for a while and then hangs. After its restart, it waits
+/// before it starts its watchdog again.
+fn critical_main(_: usize, _: usize, _: usize, _: usize) {
+    if CRITICAL_RUNS.fetch_add(1, Ordering::SeqCst) == 0 {
+        let invalid = userspace::command(WATCHDOG, 1, 0, 0);
+        let stopped = userspace::command(WATCHDOG, 2, 0, 0);
+        apps::print(&format!("errors {} {}\r\n", invalid, stopped));
+        userspace::command(WATCHDOG, 1, 50, 0);
+        for _ in 0..5 {
+            apps::sleep_ms(20);
+            userspace::command(WATCHDOG, 2, 0, 0);
+        }
+        apps::print("critical hangs\r\n");
+        loop {
+            apps::sleep_ms(1000);
+        }
+    } else {
+        apps::print("critical restarted\r\n");
+        apps::sleep_ms(100);
+        userspace::command(WATCHDOG, 1, 50, 0);
+        userspace::command(WATCHDOG, 3, 0, 0);
+        apps::print("critical healthy\r\n");
+    }
+}
+
+/// Starts its watchdog and never sends a heartbeat.
+fn worker_main(_: usize, _: usize, _: usize, _: usize) {
+    if WORKER_RUNS.fetch_add(1, Ordering::SeqCst) == 0 {
+        userspace::command(WATCHDOG, 1, 30, 0);
+        loop {
+            apps::sleep_ms(1000);
+        }
+    } else {
+        apps::print("worker restarted\r\n");
+    }
+}
+
+#[test]
+fn app_watchdog() {
+    let output = CapturedOutput::new();
+    let app = |name, main| HostApp {
+        name: name,
+        main: main,
+        minimum_ram_size: 4096,
+        ipc_clients: &[],
+    };
+    let emulation = unsafe {
+        host::setup(
+            &[app("critical", critical_main), app("worker", worker_main)],
+            Box::new(output.clone()),
+        )
+    };
+    let capability = create_capability!(ProcessManagementCapability);
+    let info = KernelInfo::new(emulation.kernel);
+    let wait_for = |text: &str| {
+        assert!(
+            emulation.run_until(Duration::from_secs(5), || output.contents().contains(text)),
+            "no {:?} in {:?}",
+            text,
+            output.contents()
+        );
+    };
+
+    wait_for("worker restarted");
+    assert!(output.contents().contains("errors -6 -4"));
+
+    // The critical process starves the watchdog of the chip from when it
+    // misses its heartbeat until it starts its watchdog again.
+    wait_for("critical restarted");
+    assert!(info.watchdog_starved(&capability));
+    wait_for("critical healthy");
+    assert!(!info.watchdog_starved(&capability));
+
+    emulation
+        .kernel
+        .process_each_capability(&capability, |process| {
+            assert_eq!(process.get_restart_count(), 1);
+            assert_eq!(
+                process.get_last_fault().map(|fault| fault.reason),
+                Some(FaultReason::WatchdogTimeout)
+            );
+        });
+}
@@ -0,0 +1,33 @@
+//! Processes writing to and reading from the console.
+
+use std::time::Duration;
+
+use host::apps;
+use host_emulation::tbf::HostApp;
+use host_emulation::uart::CapturedOutput;
+
+fn echo_main(_: usize, _: usize, _: usize, _: usize) {
+    let line = apps::read(5);
+    apps::print(&format!("echo: {}\r\n", String::from_utf8_lossy(&line)));
+}
+
+#[test]
+fn console() {
+    let output = CapturedOutput::new();
+    let echo = HostApp {
+        name: "echo",
+        main: echo_main,
+        minimum_ram_size: 4096,
+        ipc_clients: &[],
+    };
+    let emulation = unsafe { host::setup(&[apps::HELLO, echo], Box::new(output.clone())) };
+
+    assert!(emulation.run_until(Duration::from_secs(5), || output
+        .contents()
+        .contains("Hello from a host process!\r\n")));
+
+    emulation.chip.uart.input().push(b"tock!");
+    assert!(emulation.run_until(Duration::from_secs(5), || output
+        .contents()
+        .contains("echo: tock!\r\n")));
+}
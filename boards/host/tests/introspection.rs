@@ -0,0 +1,82 @@
+//! Statistics the kernel keeps about processes and itself, read with
+//! `KernelInfo`.
+
+use std::time::Duration;
+
+use host::apps;
+use host_emulation::tbf::HostApp;
+use host_emulation::uart::CapturedOutput;
+use host_emulation::userspace;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::create_capability;
+use kernel::introspection::KernelInfo;
+
+/// Prints, sets an alarm, and moves its memory break after telling the
+/// kernel that the heap starts at the initial break.
+fn worker_main(_: usize, _: usize, _: usize, _: usize) {
+    let heap_start = userspace::memop(1, 0);
+    userspace::memop(11, heap_start as usize);
+    assert!(userspace::memop(1, 2048) >= 0);
+    for _ in 0..3 {
+        apps::print("working\r\n");
+    }
+    apps::sleep_ms(20);
+    apps::print("worker done\r\n");
+}
+
+#[test]
+fn introspection() {
+    let output = CapturedOutput::new();
+    let emulation = unsafe {
+        host::setup(
+            &[HostApp {
+                name: "worker",
+                main: worker_main,
+                minimum_ram_size: 8192,
+                ipc_clients: &[],
+            }],
+            Box::new(output.clone()),
+        )
+    };
+    assert!(emulation.run_until(Duration::from_secs(5), || output
+        .contents()
+        .contains("worker done")));
+
+    let capability = create_capability!(ProcessManagementCapability);
+    let info = KernelInfo::new(emulation.kernel);
+    let worker = std::cell::Cell::new(None);
+    emulation
+        .kernel
+        .process_each_capability(&capability, |process| {
+            if process.get_process_name() == "worker" {
+                worker.set(Some(process.appid()));
+            }
+        });
+    let worker = worker.get().unwrap();
+
+    // Each print allows a buffer, subscribes and starts writing, and the
+    // alarm needs a subscribe and a command.
+    let histogram = info.app_syscall_histogram(worker, &capability);
+    let counts: Vec<_> = histogram.iter().collect();
+    assert_eq!(counts.len(), 2);
+    assert!(counts.contains(&(capsules::console::DRIVER_NUM, 12)));
+    assert!(counts
+        .iter()
+        .any(|&(driver, count)| driver == capsules::alarm::DRIVER_NUM && count >= 2));
+    assert_eq!(histogram.other_drivers, 0);
+
+    let (stack, heap) = info.app_memory_high_water(worker, &capability);
+    assert_eq!(stack, None);
+    assert!(heap.unwrap() >= 2048);
+
+    let cpu_time = info.app_cpu_time_us(worker, &capability);
+    let kernel_time = info.kernel_time_us(&capability).unwrap();
+    let uptime = info.uptime_us(&capability).unwrap();
+    assert!(cpu_time > 0);
+    // The alarm took at least 20 ms.
+    assert!(uptime >= 20_000);
+    assert!(uptime >= cpu_time);
+    // The emulation never sleeps when run by tests.
+    assert_eq!(info.sleep_time_us(&capability), Some(0));
+    assert!(kernel_time <= uptime - cpu_time);
+}
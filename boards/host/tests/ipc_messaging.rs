@@ -0,0 +1,160 @@
+//! Clients sending messages to a service through the IPC messaging driver,
+//! and receiving its replies, while a process the service does not list as a
+//! client cannot reach it.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use host::apps;
+use host_emulation::tbf::HostApp;
+use host_emulation::uart::CapturedOutput;
+use host_emulation::userspace;
+
+const IPC: usize = kernel::ipc::MESSAGE_DRIVER_NUM;
+const EBUSY: isize = -2;
+const EINVAL: isize = -6;
+
+/// Identifier of the service, once a client found it.
+static SERVICE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Client identifier, message identifier and length of the last message
+    /// received by the service.
+    static RECEIVED: Cell<Option<(usize, usize, usize)>> = Cell::new(None);
+    /// Event, message identifier and length or error of the last client
+    /// event.
+    static EVENT: Cell<Option<(usize, usize, usize)>> = Cell::new(None);
+}
+
+fn received(client: usize, message_id: usize, len: usize, _: usize) {
+    RECEIVED.with(|received| received.set(Some((client, message_id, len))));
+}
+
+fn event(event: usize, message_id: usize, value: usize, _: usize) {
+    EVENT.with(|last| last.set(Some((event, message_id, value))));
+}
+
+/// Replies to each message with the message in upper case.
+fn service_main(_: usize, _: usize, _: usize, _: usize) {
+    let receive = userspace::allocate(16).unwrap();
+    let reply = userspace::allocate(16).unwrap();
+    userspace::allow(IPC, 0, receive.as_mut_ptr(), receive.len());
+    userspace::allow(IPC, 1, reply.as_mut_ptr(), reply.len());
+    userspace::subscribe(IPC, 0, Some(received), 0);
+    assert_eq!(userspace::command(IPC, 1, 2, 0), 0);
+
+    loop {
+        let (_, message_id, len) = loop {
+            if let Some(message) = RECEIVED.with(|received| received.take()) {
+                break message;
+            }
+            userspace::yield_for_callback();
+        };
+        for (dst, src) in reply.iter_mut().zip(receive[..len].iter()) {
+            *dst = src.to_ascii_uppercase();
+        }
+        userspace::command(IPC, 2, 0, 0);
+        assert_eq!(userspace::command(IPC, 3, message_id, len), 0);
+    }
+}
+
+/// Send `message` to the service and return its reply.
+fn call(service: usize, message: &[u8]) -> Vec<u8> {
+    let send = userspace::allocate(message.len()).unwrap();
+    let reply = userspace::allocate(16).unwrap();
+    send.copy_from_slice(message);
+    userspace::allow(IPC, 2, send.as_mut_ptr(), send.len());
+    userspace::allow(IPC, 3, reply.as_mut_ptr(), reply.len());
+    userspace::subscribe(IPC, 1, Some(event), 0);
+
+    let message_id = userspace::command(IPC, 5, service, message.len());
+    assert!(message_id > 0);
+    // Only one request can be outstanding.
+    assert_eq!(userspace::command(IPC, 5, service, message.len()), EBUSY);
+    loop {
+        if let Some((1, id, len)) = EVENT.with(|last| last.get()) {
+            assert_eq!(id, message_id as usize);
+            return reply[..len].to_vec();
+        }
+        userspace::yield_for_callback();
+    }
+}
+
+/// Find the service, waiting until it has registered.
+fn discover() -> usize {
+    let name = userspace::allocate(4).unwrap();
+    name.copy_from_slice(b"echo");
+    userspace::allow(IPC, 2, name.as_mut_ptr(), name.len());
+    loop {
+        let service = userspace::command(IPC, 4, name.len(), 0);
+        if service > 0 {
+            SERVICE.store(service as usize, Ordering::SeqCst);
+            return service as usize;
+        }
+        apps::sleep_ms(1);
+    }
+}
+
+fn client_main(_: usize, _: usize, _: usize, _: usize) {
+    let service = discover();
+    let reply = call(service, b"hello");
+    apps::print(&format!("reply: {}\r\n", String::from_utf8_lossy(&reply)));
+}
+
+fn other_client_main(_: usize, _: usize, _: usize, _: usize) {
+    let service = discover();
+    let reply = call(service, b"tock");
+    apps::print(&format!(
+        "other reply: {}\r\n",
+        String::from_utf8_lossy(&reply)
+    ));
+}
+
+/// Tries to reach the service, which does not list it as a client.
+fn stranger_main(_: usize, _: usize, _: usize, _: usize) {
+    let service = loop {
+        match SERVICE.load(Ordering::SeqCst) {
+            0 => apps::sleep_ms(1),
+            service => break service,
+        }
+    };
+    let send = userspace::allocate(4).unwrap();
+    send.copy_from_slice(b"echo");
+    userspace::allow(IPC, 2, send.as_mut_ptr(), send.len());
+    assert_eq!(userspace::command(IPC, 4, send.len(), 0), EINVAL);
+    assert_eq!(userspace::command(IPC, 5, service, send.len()), EINVAL);
+    apps::print("stranger denied\r\n");
+}
+
+#[test]
+fn ipc_messaging() {
+    let output = CapturedOutput::new();
+    let app = |name, main| HostApp {
+        name: name,
+        main: main,
+        minimum_ram_size: 4096,
+        ipc_clients: &[],
+    };
+    let emulation = unsafe {
+        host::setup(
+            &[
+                app("client", client_main),
+                HostApp {
+                    ipc_clients: &["client", "other"],
+                    ..app("echo", service_main)
+                },
+                app("other", other_client_main),
+                app("stranger", stranger_main),
+            ],
+            Box::new(output.clone()),
+        )
+    };
+
+    assert!(emulation.run_until(Duration::from_secs(5), || {
+        let contents = output.contents();
+        contents.contains("reply: HELLO\r\n")
+            && contents.contains("other reply: TOCK\r\n")
+            && contents.contains("stranger denied\r\n")
+    }));
+}
@@ -0,0 +1,110 @@
+//! Commands typed on the process console, with line editing, history and
+//! completion.
+
+use std::time::Duration;
+
+use host::{apps, Emulation};
+use host_emulation::tbf::HostApp;
+use host_emulation::uart::CapturedOutput;
+
+fn worker_main(_: usize, _: usize, _: usize, _: usize) {
+    apps::print("worker started\r\n");
+    loop {
+        apps::sleep_ms(1000);
+    }
+}
+
+/// Type `input`, and run the kernel until `expected` was printed as often as
+/// `count`.
+fn type_until(emulation: &Emulation, output: &CapturedOutput, input: &[u8], expected: &str) {
+    let count = output.contents().matches(expected).count() + 1;
+    emulation.chip.uart.input().push(input);
+    assert!(
+        emulation.run_until(Duration::from_secs(5), || output
+            .contents()
+            .matches(expected)
+            .count()
+            >= count),
+        "{:?} not printed after typing {:?}:\n{}",
+        expected,
+        String::from_utf8_lossy(input),
+        output.contents()
+    );
+}
+
+#[test]
+fn process_console() {
+    let output = CapturedOutput::new();
+    let worker = HostApp {
+        name: "worker",
+        main: worker_main,
+        minimum_ram_size: 4096,
+        ipc_clients: &[],
+    };
+    let emulation = unsafe { host::setup(&[apps::HELLO, worker], Box::new(output.clone())) };
+    assert!(emulation.run_until(Duration::from_secs(5), || output
+        .contents()
+        .contains("worker started")));
+
+    // Tab completes commands and process names, and typed text is echoed.
+    type_until(&emulation, &output, b"li\t", "list ");
+    type_until(&emulation, &output, b"\r", "\tworker");
+    type_until(&emulation, &output, b"process w\t", "process worker");
+    type_until(&emulation, &output, b"\r", "To debug");
+    let contents = output.contents();
+    assert!(contents.contains("App: worker"));
+    assert!(contents.contains("Host process thread"));
+    assert!(contents.contains("App Flash"));
+
+    // Editing in the middle of the line, and going back in history.
+    type_until(
+        &emulation,
+        &output,
+        b"sttus\x1b[D\x1b[D\x1b[Da\r",
+        "Total processes: 2",
+    );
+    type_until(&emulation, &output, b"\x1b[A\x1b[A\r", "App: worker");
+    type_until(
+        &emulation,
+        &output,
+        b"\x1b[A\x1b[A\x1b[A\x1b[B\r",
+        "Total processes: 2",
+    );
+    type_until(
+        &emulation,
+        &output,
+        b"lust\x08\x08\x08ist\x1b[3~\r",
+        "\tworker",
+    );
+
+    type_until(&emulation, &output, b"kernel\r", "Grants of all processes");
+    assert!(output.contents().contains("Kernel memory: unknown"));
+
+    type_until(
+        &emulation,
+        &output,
+        b"restart worker\r",
+        "Process worker restarted",
+    );
+    assert!(emulation.run_until(Duration::from_secs(5), || output
+        .contents()
+        .matches("worker started")
+        .count()
+        == 2));
+    type_until(
+        &emulation,
+        &output,
+        b"terminate worker\r",
+        "Process worker terminated",
+    );
+    type_until(&emulation, &output, b"list\r", "StoppedFaulted");
+
+    type_until(
+        &emulation,
+        &output,
+        b"stop nobody\r",
+        "No process named nobody",
+    );
+    type_until(&emulation, &output, b"reboot\r", "Reboot is not supported");
+    type_until(&emulation, &output, b"bogus\r", "Valid commands are:");
+}
@@ -0,0 +1,156 @@
+//! Inspecting and controlling processes with the remote management protocol.
+
+use std::time::Duration;
+
+use capsules::remote_management::{
+    cobs_decode, cobs_encode, crc16, Command, RemoteManagement, MAX_FRAME, MAX_PAYLOAD, RESPONSE,
+};
+use host::apps;
+use host_emulation::tbf::HostApp;
+use host_emulation::uart::CapturedOutput;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::create_capability;
+use kernel::hil::uart::{Receive, Transmit};
+use tock_hil_mock::uart::MockUart;
+use tock_hil_mock::{leak, leak_buffer};
+
+fn worker_main(_: usize, _: usize, _: usize, _: usize) {
+    apps::print("worker started\r\n");
+    loop {
+        apps::sleep_ms(1000);
+    }
+}
+
+/// Send a request and return the result and data of its response.
+fn request(uart: &MockUart, command: Command, arguments: &[u8]) -> (i8, Vec<u8>) {
+    let mut payload = vec![7, command as u8];
+    payload.extend_from_slice(arguments);
+    let crc = crc16(&payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    let mut frame = vec![0; MAX_FRAME];
+    let length = cobs_encode(&payload, &mut frame).unwrap();
+    frame.truncate(length);
+    frame.push(0);
+    uart.receive_bytes(&frame);
+
+    let mut response = uart.take_transmitted();
+    assert!(uart.complete_transmit());
+    assert_eq!(response.pop(), Some(0));
+    let length = cobs_decode(&mut response).unwrap();
+    let (payload, crc) = response[..length].split_at(length - 2);
+    assert_eq!(crc, &crc16(payload).to_le_bytes());
+    assert_eq!(&payload[..2], &[7, command as u8 | RESPONSE]);
+    (payload[2] as i8, payload[3..].to_vec())
+}
+
+fn word(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// The state and name of the process at `index`.
+fn list(uart: &MockUart, index: u8) -> Option<(u8, String)> {
+    match request(uart, Command::List, &[index]) {
+        (0, data) => Some((data[4], String::from_utf8(data[30..].to_vec()).unwrap())),
+        _ => None,
+    }
+}
+
+#[test]
+fn remote_management() {
+    let output = CapturedOutput::new();
+    let worker = HostApp {
+        name: "worker",
+        main: worker_main,
+        minimum_ram_size: 4096,
+        ipc_clients: &[],
+    };
+    let emulation = unsafe { host::setup(&[apps::HELLO, worker], Box::new(output.clone())) };
+    assert!(emulation.run_until(Duration::from_secs(5), || output
+        .contents()
+        .contains("worker started")));
+    // Let the worker start sleeping.
+    emulation.run_until(Duration::from_millis(20), || false);
+
+    let uart = leak(MockUart::new());
+    let remote = leak(RemoteManagement::new(
+        uart,
+        leak_buffer(&[0; MAX_FRAME]),
+        leak_buffer(&[0; 1]),
+        leak_buffer(&[0; MAX_FRAME]),
+        emulation.kernel,
+        None,
+        None,
+        create_capability!(ProcessManagementCapability),
+    ));
+    uart.set_transmit_client(remote);
+    uart.set_receive_client(remote);
+    remote.start();
+
+    // Processes with their TBF metadata.
+    let (result, data) = request(uart, Command::List, &[1]);
+    assert_eq!(result, 0);
+    assert_eq!(&data[30..], b"worker");
+    let (flash_length, header_length) = (word(&data, 13), word(&data, 17));
+    assert!(flash_length > header_length && header_length > 0);
+    assert!(word(&data, 25) >= 4096);
+    assert_eq!(list(uart, 0).map(|(_, name)| name), Some("hello".into()));
+    assert_eq!(list(uart, 2), None);
+
+    // Counters of the kernel and of processes.
+    let (result, data) = request(uart, Command::Kernel, &[]);
+    assert_eq!(result, 0);
+    assert!(word(&data, 0) > 0);
+    assert_eq!(word(&data, 24), 2);
+    let (result, data) = request(uart, Command::Counters, b"worker");
+    assert_eq!(result, 0);
+    assert!(word(&data, 0) > 0);
+    assert_eq!(word(&data, 8), 0);
+    let (result, data) = request(uart, Command::Syscalls, b"worker");
+    assert_eq!(result, 0);
+    assert_eq!(word(&data, 0), 0);
+    let drivers: Vec<u32> = data[4..].chunks(8).map(|entry| word(entry, 0)).collect();
+    assert!(drivers.contains(&(capsules::console::DRIVER_NUM as u32)));
+    assert!(drivers.contains(&(capsules::alarm::DRIVER_NUM as u32)));
+
+    // The state of a process, in chunks.
+    let mut state = Vec::new();
+    loop {
+        let mut arguments = (state.len() as u32).to_le_bytes().to_vec();
+        arguments.extend_from_slice(b"worker");
+        let (result, data) = request(uart, Command::State, &arguments);
+        assert_eq!(result, 0);
+        state.extend_from_slice(&data);
+        if data.len() < MAX_PAYLOAD - 3 {
+            break;
+        }
+    }
+    let state = String::from_utf8(state).unwrap();
+    assert!(state.len() > MAX_PAYLOAD);
+    assert!(state.contains("App: worker"));
+    assert!(state.contains("To debug"));
+
+    // Controlling processes.
+    assert_eq!(request(uart, Command::Stop, b"worker").0, 0);
+    assert_eq!(list(uart, 1).unwrap().0, 3);
+    assert_eq!(request(uart, Command::Start, b"worker").0, 0);
+    assert_eq!(list(uart, 1).unwrap().0, 1);
+    assert_eq!(request(uart, Command::Restart, b"worker").0, 0);
+    assert!(emulation.run_until(Duration::from_secs(5), || output
+        .contents()
+        .matches("worker started")
+        .count()
+        == 2));
+    assert_eq!(request(uart, Command::Terminate, b"worker").0, 0);
+    assert_eq!(list(uart, 1).unwrap().0, 4);
+
+    // The board supports neither installing applications nor rebooting.
+    assert_eq!(request(uart, Command::Install, &[0, 1, 0, 0]).0, -10);
+    assert_eq!(request(uart, Command::Erase, b"worker").0, -10);
+    assert_eq!(request(uart, Command::Reboot, &[]).0, -10);
+    assert_eq!(request(uart, Command::Start, b"nobody").0, -6);
+}
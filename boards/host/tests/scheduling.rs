@@ -0,0 +1,63 @@
+//! Processes sharing the CPU under the round robin scheduler, and waiting for
+//! alarms.
+
+use std::time::{Duration, Instant};
+
+use host::apps;
+use host_emulation::tbf::HostApp;
+use host_emulation::uart::CapturedOutput;
+
+fn fast_main(_: usize, _: usize, _: usize, _: usize) {
+    for i in 0..5 {
+        apps::sleep_ms(10);
+        apps::print(&format!("fast {}\r\n", i));
+    }
+}
+
+fn slow_main(_: usize, _: usize, _: usize, _: usize) {
+    apps::sleep_ms(200);
+    apps::print("slow done\r\n");
+}
+
+/// Busy process that makes system calls, but never waits for a callback.
+fn busy_main(_: usize, _: usize, _: usize, _: usize) {
+    loop {
+        host_emulation::userspace::command(capsules::alarm::DRIVER_NUM, 2, 0, 0);
+    }
+}
+
+#[test]
+fn scheduling() {
+    let output = CapturedOutput::new();
+    let app = |name, main| HostApp {
+        name: name,
+        main: main,
+        minimum_ram_size: 4096,
+        ipc_clients: &[],
+    };
+    let emulation = unsafe {
+        host::setup(
+            &[
+                app("busy", busy_main),
+                app("fast", fast_main),
+                app("slow", slow_main),
+            ],
+            Box::new(output.clone()),
+        )
+    };
+
+    let start = Instant::now();
+    assert!(emulation.run_until(Duration::from_secs(5), || output
+        .contents()
+        .contains("slow done")));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    // The busy process must not have kept the others from running.
+    let contents = output.contents();
+    let fast: Vec<_> = (0..5)
+        .map(|i| contents.find(&format!("fast {}\r\n", i)))
+        .collect();
+    assert!(fast.iter().all(|position| position.is_some()));
+    assert!(fast.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(fast[4] < contents.find("slow done"));
+}
@@ -27,6 +27,8 @@ use kernel::hil::radio;
 use kernel::hil::radio::{RadioConfig, RadioData};
 //use kernel::hil::time::Alarm;
 use kernel::hil::Controller;
+use kernel::syscall_filter::SyscallFilter;
+use kernel::Chip;
 #[allow(unused_imports)]
 use kernel::{create_capability, debug, debug_gpio, static_init};
 use sam4l::chip::Sam4lDefaultPeripherals;
@@ -125,9 +127,11 @@ struct Imix {
         VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>,
     >,
     ipc: kernel::ipc::IPC,
+    ipc_messaging: &'static kernel::ipc::IPCMessaging,
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     radio_driver: &'static capsules::ieee802154::RadioDriver<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    tcp_driver: &'static capsules::net::tcp::TCPDriver<'static>,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     usb_driver: &'static capsules::usb::usb_user::UsbSyscallDriver<
         'static,
@@ -172,13 +176,25 @@ impl kernel::Platform for Imix {
             capsules::usb::usb_user::DRIVER_NUM => f(Some(self.usb_driver)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.radio_driver)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::tcp::DRIVER_NUM => f(Some(self.tcp_driver)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
+            kernel::ipc::MESSAGE_DRIVER_NUM => f(Some(self.ipc_messaging)),
             _ => f(None),
         }
     }
+
+    fn filter_syscall(
+        &self,
+        process: &dyn kernel::procs::ProcessType,
+        syscall: &kernel::syscall::Syscall,
+    ) -> Result<(), kernel::ReturnCode> {
+        // Apps that declare permissions in their TBF header are restricted
+        // to them. Apps without permissions may use every driver.
+        kernel::syscall_filter::TbfHeaderFilterDefaultAllow.filter_syscall(process, syscall)
+    }
 }
 
 unsafe fn set_pin_primary_functions(peripherals: &Sam4lDefaultPeripherals) {
@@ -251,6 +267,16 @@ unsafe fn set_pin_primary_functions(peripherals: &Sam4lDefaultPeripherals) {
     peripherals.pc[31].configure(None); //... D2          -- GPIO Pin
 }
 
+/// Reset the board, for the `reboot` command of the process console.
+fn reset() -> ! {
+    unsafe {
+        cortexm4::scb::reset();
+    }
+    loop {
+        cortexm4::support::nop();
+    }
+}
+
 /// Reset Handler.
 ///
 /// This symbol is loaded into vector table by the SAM4L chip crate.
@@ -301,7 +327,7 @@ pub unsafe fn reset_handler() {
         },
     );
 
-    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&mut PROCESSES));
 
     let dynamic_deferred_call_clients =
         static_init!([DynamicDeferredCallClientState; 2], Default::default());
@@ -317,7 +343,7 @@ pub unsafe fn reset_handler() {
     let uart_mux =
         UartMuxComponent::new(&peripherals.usart3, 115200, dynamic_deferred_caller).finalize(());
 
-    let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux).finalize(());
+    let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux, Some(reset)).finalize(());
     let console = ConsoleComponent::new(board_kernel, uart_mux).finalize(());
     DebugWriterComponent::new(uart_mux).finalize(());
 
@@ -446,10 +472,13 @@ pub unsafe fn reset_handler() {
 
     // Can this initialize be pushed earlier, or into component? -pal
     rf233.initialize(&mut RF233_BUF, &mut RF233_REG_WRITE, &mut RF233_REG_READ);
+    let mux_aes_ccm = components::ieee802154::AesCcmMuxComponent::new(&peripherals.aes).finalize(
+        components::aes_ccm_mux_component_helper!(sam4l::aes::Aes<'static>),
+    );
     let (radio_driver, mux_mac) = components::ieee802154::Ieee802154Component::new(
         board_kernel,
         rf233,
-        &peripherals.aes,
+        mux_aes_ccm,
         PAN_ID,
         serial_num_bottom_16,
     )
@@ -495,6 +524,37 @@ pub unsafe fn reset_handler() {
         ]
     );
 
+    // Answers pings and registers with a 6LoWPAN router, keeping the neighbor
+    // cache used by the UDP and TCP stacks up to date.
+    let (neighbor_discovery, neighbor_cache, routing_table) =
+        components::neighbor_discovery::NeighborDiscoveryComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            DST_MAC_ADDR,
+            src_mac_from_serial_num,
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::neighbor_discovery_component_helper!(
+            sam4l::ast::Ast
+        ));
+
+    // Joins an RPL mesh as a router, routing packets through the preferred
+    // parent and forwarding the packets of its sub-DODAG.
+    let rpl_node = components::rpl::RplComponent::new(
+        mux_mac,
+        DEFAULT_CTX_PREFIX_LEN,
+        DEFAULT_CTX_PREFIX,
+        DST_MAC_ADDR,
+        src_mac_from_serial_num,
+        local_ip_ifaces,
+        neighbor_cache,
+        routing_table,
+        mux_alarm,
+    )
+    .finalize(components::rpl_component_helper!(sam4l::ast::Ast));
+
     let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
         mux_mac,
         DEFAULT_CTX_PREFIX_LEN,
@@ -503,6 +563,8 @@ pub unsafe fn reset_handler() {
         src_mac_from_serial_num, //comment out for dual rx test only
         //MacAddress::Short(49138), //comment in for dual rx test only
         local_ip_ifaces,
+        neighbor_cache,
+        routing_table,
         mux_alarm,
     )
     .finalize(components::udp_mux_component_helper!(sam4l::ast::Ast));
@@ -517,6 +579,26 @@ pub unsafe fn reset_handler() {
     )
     .finalize(components::udp_driver_component_helper!(sam4l::ast::Ast));
 
+    let (_tcp_mux, tcp_driver) = components::tcp::TCPComponent::new(
+        board_kernel,
+        mux_mac,
+        DEFAULT_CTX_PREFIX_LEN,
+        DEFAULT_CTX_PREFIX,
+        DST_MAC_ADDR,
+        src_mac_from_serial_num,
+        local_ip_ifaces,
+        neighbor_cache,
+        routing_table,
+        mux_alarm,
+    )
+    .finalize(components::tcp_component_helper!(sam4l::ast::Ast));
+
+    let ipc_messaging = static_init!(
+        kernel::ipc::IPCMessaging,
+        kernel::ipc::IPCMessaging::new(board_kernel, &grant_cap)
+    );
+    board_kernel.set_ipc_messaging(ipc_messaging, &main_cap);
+
     let imix = Imix {
         pconsole,
         console,
@@ -533,9 +615,11 @@ pub unsafe fn reset_handler() {
         crc,
         spi: spi_syscalls,
         ipc: kernel::ipc::IPC::new(board_kernel, &grant_cap),
+        ipc_messaging,
         ninedof,
         radio_driver,
         udp_driver,
+        tcp_driver,
         usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage,
@@ -548,6 +632,8 @@ pub unsafe fn reset_handler() {
     // initialization to work.
     rf233.reset();
     rf233.start();
+    neighbor_discovery.start();
+    rpl_node.start();
 
     imix.pconsole.start();
 
@@ -599,6 +685,11 @@ pub unsafe fn reset_handler() {
 
     debug!("Initialization complete. Entering main loop");
 
+    // Protect the kernel's code, read-only data and RAM. This must happen
+    // before processes are loaded, since they use the remaining MPU regions.
+    components::kernel_mpu::KernelMpuComponent::new(chip.mpu())
+        .finalize(components::kernel_mpu_component_helper!(cortexm4::mpu::MPU));
+
     /// These symbols are defined in the linker script.
     extern "C" {
         /// Beginning of the ROM region containing app images.
@@ -622,7 +713,6 @@ pub unsafe fn reset_handler() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         FAULT_RESPONSE,
         &process_mgmt_cap,
     )
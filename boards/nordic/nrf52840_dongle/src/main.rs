@@ -115,13 +115,23 @@ impl kernel::Platform for Platform {
     }
 }
 
+/// Reset the board, for the `reboot` command of the process console.
+fn reset() -> ! {
+    unsafe {
+        cortexm4::scb::reset();
+    }
+    loop {
+        cortexm4::support::nop();
+    }
+}
+
 /// Entry point in the vector table called on hard reset.
 #[no_mangle]
 pub unsafe fn reset_handler() {
     // Loads relocations and clears BSS
     nrf52840::init();
 
-    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&mut PROCESSES));
 
     // GPIOs
     let gpio = components::gpio::GpioComponent::new(
@@ -241,9 +251,12 @@ pub unsafe fn reset_handler() {
         components::console::UartMuxComponent::new(channel, 115200, dynamic_deferred_caller)
             .finalize(());
 
-    let pconsole =
-        components::process_console::ProcessConsoleComponent::new(board_kernel, uart_mux)
-            .finalize(());
+    let pconsole = components::process_console::ProcessConsoleComponent::new(
+        board_kernel,
+        uart_mux,
+        Some(reset),
+    )
+    .finalize(());
 
     // Setup the console.
     let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
@@ -254,10 +267,14 @@ pub unsafe fn reset_handler() {
         nrf52_components::BLEComponent::new(board_kernel, &nrf52840::ble_radio::RADIO, mux_alarm)
             .finalize(());
 
+    let mux_aes_ccm = components::ieee802154::AesCcmMuxComponent::new(&nrf52840::aes::AESECB)
+        .finalize(components::aes_ccm_mux_component_helper!(
+            nrf52840::aes::AesECB<'static>
+        ));
     let (ieee802154_radio, _mux_mac) = components::ieee802154::Ieee802154Component::new(
         board_kernel,
         &nrf52840::ieee802154_radio::RADIO,
-        &nrf52840::aes::AESECB,
+        mux_aes_ccm,
         PAN_ID,
         SRC_MAC,
     )
@@ -331,7 +348,6 @@ pub unsafe fn reset_handler() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         FAULT_RESPONSE,
         &process_management_capability,
     )
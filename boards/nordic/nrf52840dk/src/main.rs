@@ -75,6 +75,7 @@ use kernel::component::Component;
 use kernel::hil::time::Counter;
 #[allow(unused_imports)]
 use kernel::hil::usb::Client;
+use kernel::Chip;
 #[allow(unused_imports)]
 use kernel::{capabilities, create_capability, debug, debug_gpio, debug_verbose, static_init};
 use nrf52840::gpio::Pin;
@@ -193,6 +194,16 @@ impl kernel::Platform for Platform {
     }
 }
 
+/// Reset the board, for the `reboot` command of the process console.
+fn reset() -> ! {
+    unsafe {
+        cortexm4::scb::reset();
+    }
+    loop {
+        cortexm4::support::nop();
+    }
+}
+
 /// Entry point in the vector table called on hard reset.
 #[no_mangle]
 pub unsafe fn reset_handler() {
@@ -214,7 +225,7 @@ pub unsafe fn reset_handler() {
         UartChannel::Pins(UartPins::new(UART_RTS, UART_TXD, UART_CTS, UART_RXD))
     };
 
-    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&mut PROCESSES));
 
     let gpio = components::gpio::GpioComponent::new(
         board_kernel,
@@ -335,9 +346,12 @@ pub unsafe fn reset_handler() {
         components::console::UartMuxComponent::new(channel, 115200, dynamic_deferred_caller)
             .finalize(());
 
-    let pconsole =
-        components::process_console::ProcessConsoleComponent::new(board_kernel, uart_mux)
-            .finalize(());
+    let pconsole = components::process_console::ProcessConsoleComponent::new(
+        board_kernel,
+        uart_mux,
+        Some(reset),
+    )
+    .finalize(());
 
     // Setup the console.
     let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
@@ -351,10 +365,14 @@ pub unsafe fn reset_handler() {
     let serial_num = nrf52840::ficr::FICR_INSTANCE.address();
     let serial_num_bottom_16 = serial_num[0] as u16 + ((serial_num[1] as u16) << 8);
     let src_mac_from_serial_num: MacAddress = MacAddress::Short(serial_num_bottom_16);
+    let mux_aes_ccm = components::ieee802154::AesCcmMuxComponent::new(&nrf52840::aes::AESECB)
+        .finalize(components::aes_ccm_mux_component_helper!(
+            nrf52840::aes::AesECB<'static>
+        ));
     let (ieee802154_radio, mux_mac) = components::ieee802154::Ieee802154Component::new(
         board_kernel,
         &nrf52840::ieee802154_radio::RADIO,
-        &nrf52840::aes::AESECB,
+        mux_aes_ccm,
         PAN_ID,
         serial_num_bottom_16,
     )
@@ -380,6 +398,37 @@ pub unsafe fn reset_handler() {
         ]
     );
 
+    // Answers pings and registers with a 6LoWPAN router, keeping the neighbor
+    // cache used by the UDP and TCP stacks up to date.
+    let (neighbor_discovery, neighbor_cache, routing_table) =
+        components::neighbor_discovery::NeighborDiscoveryComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            DST_MAC_ADDR,
+            src_mac_from_serial_num,
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::neighbor_discovery_component_helper!(
+            nrf52840::rtc::Rtc
+        ));
+
+    // Joins an RPL mesh as a router, routing packets through the preferred
+    // parent and forwarding the packets of its sub-DODAG.
+    let rpl_node = components::rpl::RplComponent::new(
+        mux_mac,
+        DEFAULT_CTX_PREFIX_LEN,
+        DEFAULT_CTX_PREFIX,
+        DST_MAC_ADDR,
+        src_mac_from_serial_num,
+        local_ip_ifaces,
+        neighbor_cache,
+        routing_table,
+        mux_alarm,
+    )
+    .finalize(components::rpl_component_helper!(nrf52840::rtc::Rtc));
+
     let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
         mux_mac,
         DEFAULT_CTX_PREFIX_LEN,
@@ -387,6 +436,8 @@ pub unsafe fn reset_handler() {
         DST_MAC_ADDR,
         src_mac_from_serial_num,
         local_ip_ifaces,
+        neighbor_cache,
+        routing_table,
         mux_alarm,
     )
     .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
@@ -400,6 +451,8 @@ pub unsafe fn reset_handler() {
         local_ip_ifaces,
     )
     .finalize(components::udp_driver_component_helper!(nrf52840::rtc::Rtc));
+    neighbor_discovery.start();
+    rpl_node.start();
 
     let temp = components::temperature::TemperatureComponent::new(
         board_kernel,
@@ -469,6 +522,20 @@ pub unsafe fn reset_handler() {
     //         components::multi_alarm_test_component_buf!(nrf52840::rtc::Rtc),
     //     );
 
+    // Uncomment to take interrupts during context switches, see the README.
+    // let storm_alarm = static_init!(
+    //     VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+    //     VirtualMuxAlarm::new(mux_alarm)
+    // );
+    // let storm = static_init!(
+    //     capsules::test::interrupt_storm::TestInterruptStorm<
+    //         'static,
+    //         VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+    //     >,
+    //     capsules::test::interrupt_storm::TestInterruptStorm::new(storm_alarm)
+    // );
+    // kernel::hil::time::Alarm::set_alarm_client(storm_alarm, storm);
+
     //--------------------------------------------------------------------------
     // USB CTAP EXAMPLE
     //--------------------------------------------------------------------------
@@ -523,6 +590,12 @@ pub unsafe fn reset_handler() {
     debug!("{}", &nrf52840::ficr::FICR_INSTANCE);
 
     // alarm_test_component.run();
+    // storm.run();
+
+    // Protect the kernel's code, read-only data and RAM. This must happen
+    // before processes are loaded, since they use the remaining MPU regions.
+    components::kernel_mpu::KernelMpuComponent::new(chip.mpu())
+        .finalize(components::kernel_mpu_component_helper!(cortexm4::mpu::MPU));
 
     /// These symbols are defined in the linker script.
     extern "C" {
@@ -547,7 +620,6 @@ pub unsafe fn reset_handler() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         FAULT_RESPONSE,
         &process_management_capability,
     )
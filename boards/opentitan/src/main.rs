@@ -108,7 +108,7 @@ pub unsafe fn reset_handler() {
 
     let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
 
-    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&mut PROCESSES));
 
     let dynamic_deferred_call_clients =
         static_init!([DynamicDeferredCallClientState; 1], Default::default());
@@ -293,6 +293,12 @@ pub unsafe fn reset_handler() {
         lowrisc::flash_ctrl::FlashCtrl
     ));
 
+    // Protect the kernel's code, read-only data and RAM. This must happen
+    // before processes are loaded, since they use the remaining MPU regions.
+    components::kernel_mpu::KernelMpuComponent::new(chip.mpu()).finalize(
+        components::kernel_mpu_component_helper!(earlgrey::chip::PMP),
+    );
+
     /// These symbols are defined in the linker script.
     extern "C" {
         /// Beginning of the ROM region containing app images.
@@ -327,7 +333,6 @@ pub unsafe fn reset_handler() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         FAULT_RESPONSE,
         &process_mgmt_cap,
     )
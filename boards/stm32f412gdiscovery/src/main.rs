@@ -361,7 +361,7 @@ pub unsafe fn reset_handler() {
 
     setup_peripherals();
 
-    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&mut PROCESSES));
 
     let dynamic_deferred_call_clients =
         static_init!([DynamicDeferredCallClientState; 2], Default::default());
@@ -414,7 +414,10 @@ pub unsafe fn reset_handler() {
     //         &mut capsules::process_console::WRITE_BUF,
     //         &mut capsules::process_console::READ_BUF,
     //         &mut capsules::process_console::COMMAND_BUF,
+    //         &mut capsules::process_console::HISTORY_BUF,
     //         board_kernel,
+    //         None,
+    //         None,
     //         ProcessConsoleCapability,
     //     )
     // );
@@ -701,7 +704,6 @@ pub unsafe fn reset_handler() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         FAULT_RESPONSE,
         &process_management_capability,
     )
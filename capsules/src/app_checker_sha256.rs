@@ -0,0 +1,172 @@
+//! Application credentials checker using a SHA-256 digest engine.
+//!
+//! Checks SHA-256 credentials footers of applications by hashing their
+//! integrity region with a `hil::digest::Digest` and comparing the result to
+//! the hash stored in the footer. Since the digest engine reads data from RAM
+//! buffers, the integrity region is copied from flash into `data_buffer` one
+//! chunk at a time.
+//!
+//! Credentials in other formats are not checked (`ENOSUPPORT`), so that the
+//! process loader moves on to the next credentials of the application.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let checker = static_init!(
+//!     capsules::app_checker_sha256::AppCheckerSha256<
+//!         'static,
+//!         VirtualMuxHmac<'static, lowrisc::hmac::Hmac<'static>, [u8; 32]>,
+//!     >,
+//!     capsules::app_checker_sha256::AppCheckerSha256::new(
+//!         virtual_hmac_user,
+//!         static_init!([u8; 64], [0; 64]),
+//!         static_init!([u8; 32], [0; 32]),
+//!         true,
+//!     )
+//! );
+//! digest::Digest::set_client(virtual_hmac_user, checker);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::digest;
+use kernel::process_checker::{
+    AsyncAppCredentialsChecker, AsyncAppCredentialsCheckerClient, CheckResult,
+    TbfFooterV2Credentials, TbfFooterV2CredentialsType,
+};
+use kernel::ReturnCode;
+
+pub struct AppCheckerSha256<'a, D: digest::Digest<'a, [u8; 32]>> {
+    hasher: &'a D,
+    client: OptionalCell<&'a dyn AsyncAppCredentialsCheckerClient<'a>>,
+    data_buffer: TakeCell<'static, [u8]>,
+    hash: TakeCell<'static, [u8; 32]>,
+    /// Part of the integrity region that has not been hashed yet.
+    remaining: Cell<&'static [u8]>,
+    /// Credentials being checked.
+    credentials: OptionalCell<TbfFooterV2Credentials>,
+    require_credentials: bool,
+}
+
+impl<'a, D: digest::Digest<'a, [u8; 32]>> AppCheckerSha256<'a, D> {
+    /// `require_credentials` determines whether applications without a
+    /// matching SHA-256 hash may be loaded.
+    pub fn new(
+        hasher: &'a D,
+        data_buffer: &'static mut [u8],
+        hash: &'static mut [u8; 32],
+        require_credentials: bool,
+    ) -> AppCheckerSha256<'a, D> {
+        AppCheckerSha256 {
+            hasher: hasher,
+            client: OptionalCell::empty(),
+            data_buffer: TakeCell::new(data_buffer),
+            hash: TakeCell::new(hash),
+            remaining: Cell::new(&[]),
+            credentials: OptionalCell::empty(),
+            require_credentials: require_credentials,
+        }
+    }
+
+    /// Copy the next chunk of the integrity region into `buffer` and pass it
+    /// to the digest engine. On error, `buffer` is returned to `data_buffer`.
+    fn add_next_chunk(&self, buffer: &'static mut [u8]) -> Result<(), ReturnCode> {
+        let remaining = self.remaining.get();
+        let len = cmp::min(buffer.len(), remaining.len());
+        buffer[..len].copy_from_slice(&remaining[..len]);
+        self.remaining.set(&remaining[len..]);
+        let mut data = LeasableBuffer::new(buffer);
+        data.slice(0..len);
+        self.hasher
+            .add_data(data)
+            .map(|_| ())
+            .map_err(|(err, buffer)| {
+                self.data_buffer.replace(buffer);
+                err
+            })
+    }
+
+    fn finish(&self, result: Result<CheckResult, ReturnCode>) {
+        self.hasher.clear_data();
+        self.credentials.take().map(|credentials| {
+            self.client
+                .map(|client| client.check_done(result, credentials));
+        });
+    }
+}
+
+impl<'a, D: digest::Digest<'a, [u8; 32]>> AsyncAppCredentialsChecker<'a>
+    for AppCheckerSha256<'a, D>
+{
+    fn set_client(&self, client: &'a dyn AsyncAppCredentialsCheckerClient<'a>) {
+        self.client.set(client);
+    }
+
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials,
+        integrity_region: &'static [u8],
+    ) -> Result<(), ReturnCode> {
+        if credentials.format() != TbfFooterV2CredentialsType::SHA256 {
+            return Err(ReturnCode::ENOSUPPORT);
+        }
+        if self.credentials.is_some() {
+            return Err(ReturnCode::EBUSY);
+        }
+        let buffer = self.data_buffer.take().ok_or(ReturnCode::ENOMEM)?;
+
+        self.credentials.set(credentials);
+        self.remaining.set(integrity_region);
+        self.add_next_chunk(buffer).map_err(|err| {
+            self.credentials.clear();
+            err
+        })
+    }
+
+    fn require_credentials(&self) -> bool {
+        self.require_credentials
+    }
+}
+
+impl<'a, D: digest::Digest<'a, [u8; 32]>> digest::Client<'a, [u8; 32]> for AppCheckerSha256<'a, D> {
+    fn add_data_done(&'a self, result: Result<(), ReturnCode>, data: &'static mut [u8]) {
+        let result = match result {
+            Ok(()) if self.remaining.get().len() > 0 => self.add_next_chunk(data),
+            Ok(()) => {
+                // The whole integrity region has been added.
+                self.data_buffer.replace(data);
+                self.hash.take().map_or(Err(ReturnCode::ENOMEM), |hash| {
+                    self.hasher.run(hash).map_err(|(err, hash)| {
+                        self.hash.replace(hash);
+                        err
+                    })
+                })
+            }
+            Err(err) => {
+                self.data_buffer.replace(data);
+                Err(err)
+            }
+        };
+        if let Err(err) = result {
+            self.finish(Err(err));
+        }
+    }
+
+    fn hash_done(&'a self, result: Result<(), ReturnCode>, digest: &'static mut [u8; 32]) {
+        let check = result.map(|()| {
+            let matches = self
+                .credentials
+                .map_or(false, |credentials| credentials.data() == &digest[..]);
+            if matches {
+                CheckResult::Accept
+            } else {
+                CheckResult::Reject
+            }
+        });
+        self.hash.replace(digest);
+        self.finish(check);
+    }
+}
@@ -0,0 +1,375 @@
+//! Userspace interface for installing and removing applications at runtime.
+//!
+//! A process (typically a trusted loader application that receives new
+//! applications over a radio, USB or the console) uses this driver to write
+//! the TBF of a new application to unused application flash and to start it
+//! as a new process, without reflashing the board. It can also stop an
+//! application and free its flash and memory.
+//!
+//! Installing an application takes three steps:
+//!
+//! 1. `setup` finds unused application flash for a TBF of the given size.
+//! 2. `write` copies the TBF from an allowed buffer into that flash, one chunk
+//!    at a time.
+//! 3. `load` writes the padding entries that keep the list of applications in
+//!    flash valid and creates the process.
+//!
+//! Processes are created and removed by a `DynamicProcessManagement`
+//! implementation from the kernel, and flash is written through a
+//! `NonvolatileStorage` (such as `NonvolatileToPages` on top of the chip's
+//! `hil::flash::Flash` driver) covering application flash.
+//!
+//! Since any process using this driver can replace every other application,
+//! boards should only make it available to trusted processes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! pub static mut APP_LOADER_BUFFER: [u8; 512] = [0; 512];
+//! let app_loader = static_init!(
+//!     capsules::app_loader::AppLoader<'static>,
+//!     capsules::app_loader::AppLoader::new(
+//!         process_manager,
+//!         nv_to_page,
+//!         board_kernel.create_grant(&grant_cap),
+//!         &mut APP_LOADER_BUFFER
+//!     )
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_page, app_loader);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::procs::{AppFlashRegion, DynamicProcessManagement};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::AppLoader as usize;
+
+/// Operations reported in the first callback argument. These match the
+/// command numbers that started them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operation {
+    Write = 2,
+    Load = 3,
+    Unload = 4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Flash has been chosen for a new application, which the process is
+    /// writing.
+    Setup,
+    /// Writing a chunk of the new application.
+    Write,
+    /// Writing a padding entry next to the new application.
+    Padding,
+    /// Marking the flash of an unloaded application as padding.
+    Unload,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct AppLoader<'a> {
+    manager: &'a dyn DynamicProcessManagement,
+    storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+    apps: Grant<App>,
+    /// The process that owns the current operation.
+    current_app: OptionalCell<AppId>,
+    state: Cell<State>,
+    /// Flash of the application being installed. Padding entries are removed
+    /// once they have been written.
+    region: Cell<Option<AppFlashRegion>>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a> AppLoader<'a> {
+    pub fn new(
+        manager: &'a dyn DynamicProcessManagement,
+        storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+        grant: Grant<App>,
+        buffer: &'static mut [u8],
+    ) -> AppLoader<'a> {
+        AppLoader {
+            manager: manager,
+            storage: storage,
+            apps: grant,
+            current_app: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            region: Cell::new(None),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    /// Notify the current process that `operation` finished.
+    fn notify(&self, operation: Operation, result: ReturnCode, value: usize) {
+        self.current_app.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, _| {
+                app.callback.map(|mut cb| {
+                    cb.schedule(operation as usize, usize::from(result), value);
+                });
+            });
+        });
+    }
+
+    /// Copy `length` bytes of the allowed buffer of `appid` to `offset` in
+    /// the flash of the new application.
+    fn write(&self, appid: AppId, offset: usize, length: usize) -> ReturnCode {
+        let (app_start, app_length) = match self.region.get() {
+            Some(region) => region.app,
+            None => return ReturnCode::EINVAL,
+        };
+        if offset
+            .checked_add(length)
+            .map_or(true, |end| end > app_length)
+        {
+            return ReturnCode::EINVAL;
+        }
+
+        self.apps
+            .enter(appid, |app, _| {
+                app.buffer
+                    .as_ref()
+                    .map_or(ReturnCode::ERESERVE, |app_buffer| {
+                        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+                            let length = cmp::min(length, cmp::min(buffer.len(), app_buffer.len()));
+                            buffer[..length].copy_from_slice(&app_buffer.as_ref()[..length]);
+                            let result = self.storage.write(buffer, app_start + offset, length);
+                            if result == ReturnCode::SUCCESS {
+                                self.state.set(State::Write);
+                            }
+                            result
+                        })
+                    })
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Write a padding header at `address` for `length` bytes of unused flash.
+    fn write_padding(&self, address: usize, length: usize) -> ReturnCode {
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            let header = kernel::procs::padding_header(length);
+            buffer[..header.len()].copy_from_slice(&header);
+            self.storage.write(buffer, address, header.len())
+        })
+    }
+
+    /// Write the next padding entry around the new application, or create the
+    /// process once all have been written.
+    fn continue_load(&self) -> ReturnCode {
+        let mut region = match self.region.get() {
+            Some(region) => region,
+            None => return ReturnCode::EINVAL,
+        };
+        let padding = region
+            .padding_before
+            .take()
+            .or_else(|| region.padding_after.take());
+
+        match padding {
+            Some((address, length)) => {
+                let result = self.write_padding(address, length);
+                if result == ReturnCode::SUCCESS {
+                    self.region.set(Some(region));
+                    self.state.set(State::Padding);
+                }
+                result
+            }
+            None => {
+                self.state.set(State::Idle);
+                self.region.set(None);
+                let (result, identifier) = match self.manager.load_process(region.app) {
+                    Ok(Some(appid)) => (ReturnCode::SUCCESS, appid.id()),
+                    Ok(None) => (ReturnCode::EINVAL, 0),
+                    Err(_) => (ReturnCode::FAIL, 0),
+                };
+                self.notify(Operation::Load, result, identifier);
+                ReturnCode::SUCCESS
+            }
+        }
+    }
+
+    /// Stop the process with the given identifier and mark its flash unused.
+    fn unload(&self, appid: AppId, identifier: usize) -> ReturnCode {
+        // The calling process is still executing the system call, so it
+        // cannot remove itself.
+        if identifier == appid.id() {
+            return ReturnCode::EINVAL;
+        }
+        match self.manager.unload_process(identifier) {
+            Ok(app_flash) => {
+                let result = self.write_padding(app_flash.as_ptr() as usize, app_flash.len());
+                if result == ReturnCode::SUCCESS {
+                    self.current_app.set(appid);
+                    self.state.set(State::Unload);
+                }
+                result
+            }
+            Err(err) => err,
+        }
+    }
+
+    /// Forget the process that owns the current operation if it no longer
+    /// exists, e.g. because it faulted or was unloaded while installing an
+    /// application, so other processes can use the driver. An application it
+    /// was installing is abandoned. Flash operations in progress complete
+    /// first.
+    fn check_current_app(&self) {
+        let valid = self
+            .current_app
+            .map_or(true, |appid| self.apps.enter(*appid, |_, _| ()).is_ok());
+        if valid {
+            return;
+        }
+        self.current_app.clear();
+        if self.state.get() == State::Setup {
+            self.state.set(State::Idle);
+            self.region.set(None);
+        }
+    }
+
+    /// Return to `Idle` and notify the process if an operation failed after
+    /// its command returned.
+    fn fail(&self, operation: Operation, result: ReturnCode) {
+        self.state.set(State::Idle);
+        self.region.set(None);
+        self.notify(operation, result, 0);
+    }
+}
+
+impl hil::nonvolatile_storage::NonvolatileStorageClient<'static> for AppLoader<'_> {
+    fn read_done(&self, _buffer: &'static mut [u8], _length: usize) {}
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+
+        match self.state.get() {
+            State::Write => {
+                self.state.set(State::Setup);
+                self.notify(Operation::Write, ReturnCode::SUCCESS, length);
+            }
+            State::Padding => {
+                let result = self.continue_load();
+                if result != ReturnCode::SUCCESS {
+                    self.fail(Operation::Load, result);
+                }
+            }
+            State::Unload => {
+                self.state.set(State::Idle);
+                self.notify(Operation::Unload, ReturnCode::SUCCESS, 0);
+            }
+            State::Idle | State::Setup => {}
+        }
+    }
+}
+
+impl Driver for AppLoader<'_> {
+    /// Setup buffer to write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Set the buffer holding the next chunk of the application.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Set the callback for completed operations. The arguments are
+    ///   the command number of the operation, its `ReturnCode`, and the
+    ///   number of bytes written (`write`) or the identifier of the new
+    ///   process (`load`).
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Application loading control.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Find unused flash for an application whose TBF is `arg1` bytes
+    ///   long.
+    /// - `2`: Write `arg2` bytes from the allowed buffer at offset `arg1` in
+    ///   the TBF of the new application.
+    /// - `3`: Create a process for the new application.
+    /// - `4`: Stop the process with identifier `arg1` and free its flash and
+    ///   memory.
+    /// - `5`: Abort installing the new application.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SUCCESS;
+        }
+
+        // Only one process can install or remove applications at a time.
+        self.check_current_app();
+        let owner = self.current_app.map_or(true, |current| *current == appid);
+        if self.state.get() != State::Idle && !owner {
+            return ReturnCode::EBUSY;
+        }
+
+        match (command_num, self.state.get()) {
+            (1, State::Idle) => match self.manager.find_flash_region(arg1) {
+                Ok(region) => {
+                    self.current_app.set(appid);
+                    self.region.set(Some(region));
+                    self.state.set(State::Setup);
+                    ReturnCode::SUCCESS
+                }
+                Err(err) => err,
+            },
+            (2, State::Setup) => self.write(appid, arg1, arg2),
+            (3, State::Setup) => self.continue_load(),
+            (4, State::Idle) => self.unload(appid, arg1),
+            (5, State::Setup) => {
+                self.state.set(State::Idle);
+                self.region.set(None);
+                ReturnCode::SUCCESS
+            }
+            (1..=5, State::Idle) | (1..=5, State::Setup) => ReturnCode::EINVAL,
+            (1..=5, _) => ReturnCode::EBUSY,
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Watchdog with which processes prove that they are alive.
+//!
+//! A process starts its watchdog with a timeout, and then sends heartbeats.
+//! If it does not send a heartbeat for longer than the timeout, the kernel
+//! faults it with `FaultReason::WatchdogTimeout`, so the board's
+//! `FaultResponse` and restart policy decide whether it is restarted,
+//! stopped, or panics the kernel.
+//!
+//! The board can also name critical processes. When a critical process
+//! misses a heartbeat, the kernel stops tickling the hardware watchdog,
+//! which then resets the board unless the process is restarted and sends a
+//! heartbeat again before the hardware watchdog expires. Processes are only
+//! unhealthy when they miss a heartbeat: a critical process that stops its
+//! watchdog or exits is not watched anymore.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::{capabilities, static_init};
+//!
+//! struct WatchdogCap;
+//! unsafe impl capabilities::ProcessManagementCapability for WatchdogCap {}
+//! unsafe impl capabilities::WatchdogCapability for WatchdogCap {}
+//! let watchdog_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let app_watchdog = static_init!(
+//!     capsules::app_watchdog::AppWatchdog<
+//!         'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!         WatchdogCap,
+//!     >,
+//!     capsules::app_watchdog::AppWatchdog::new(
+//!         board_kernel,
+//!         watchdog_alarm,
+//!         board_kernel.create_grant(&memory_allocation_cap),
+//!         &["controller"],
+//!         WatchdogCap,
+//!     )
+//! );
+//! watchdog_alarm.set_alarm_client(app_watchdog);
+//! ```
+
+use core::cell::Cell;
+use kernel::capabilities::{ProcessManagementCapability, WatchdogCapability};
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
+use kernel::procs::FaultReason;
+use kernel::{AppId, Driver, Grant, Kernel, ReturnCode};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::AppWatchdog as usize;
+
+/// Number of critical processes a board can name. Further names are ignored.
+pub const MAX_CRITICAL_PROCESSES: usize = 32;
+
+pub struct App<T: Ticks> {
+    /// Time of the last heartbeat and the timeout, while the watchdog of the
+    /// process runs.
+    watchdog: Option<(T, T)>,
+}
+
+impl<T: Ticks> Default for App<T> {
+    fn default() -> App<T> {
+        App { watchdog: None }
+    }
+}
+
+pub struct AppWatchdog<'a, A: Alarm<'a>, C: ProcessManagementCapability + WatchdogCapability> {
+    kernel: &'static Kernel,
+    alarm: &'a A,
+    apps: Grant<App<A::Ticks>>,
+    critical_processes: &'a [&'a str],
+    /// Bit `i` is set while `critical_processes[i]` is unhealthy.
+    unhealthy: Cell<u32>,
+    capability: C,
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability + WatchdogCapability> AppWatchdog<'a, A, C> {
+    pub fn new(
+        kernel: &'static Kernel,
+        alarm: &'a A,
+        grant: Grant<App<A::Ticks>>,
+        critical_processes: &'a [&'a str],
+        capability: C,
+    ) -> AppWatchdog<'a, A, C> {
+        AppWatchdog {
+            kernel: kernel,
+            alarm: alarm,
+            apps: grant,
+            critical_processes: critical_processes,
+            unhealthy: Cell::new(0),
+            capability: capability,
+        }
+    }
+
+    /// Timeout in ticks, if `ms` is not 0 and the alarm can measure it.
+    fn ticks_from_ms(ms: usize) -> Option<A::Ticks> {
+        let ticks = ms as u64 * A::Frequency::frequency() as u64 / 1000;
+        if ms == 0 || ticks > (u32::MAX / 2) as u64 {
+            None
+        } else {
+            Some(A::Ticks::from(ticks as u32))
+        }
+    }
+
+    /// Arm the alarm for the next timeout of a process.
+    fn arm(&self) {
+        let now = self.alarm.now();
+        let next = self
+            .apps
+            .iter()
+            .filter_map(|app| {
+                app.enter(|app, _| {
+                    app.watchdog.map(|(heartbeat, timeout)| {
+                        let elapsed = now.wrapping_sub(heartbeat);
+                        if elapsed >= timeout {
+                            A::Ticks::from(0)
+                        } else {
+                            timeout.wrapping_sub(elapsed)
+                        }
+                    })
+                })
+            })
+            .min();
+        match next {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Index of the process in `critical_processes`, if it is critical.
+    fn critical_index(&self, appid: AppId) -> Option<usize> {
+        let index = Cell::new(None);
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.appid() == appid {
+                    index.set(
+                        self.critical_processes
+                            .iter()
+                            .take(MAX_CRITICAL_PROCESSES)
+                            .position(|name| *name == process.get_process_name()),
+                    );
+                }
+            });
+        index.get()
+    }
+
+    /// The process sent a heartbeat, so tickle the hardware watchdog again
+    /// if it was the last unhealthy critical process.
+    fn healthy(&self, appid: AppId) {
+        if let Some(index) = self.critical_index(appid) {
+            let unhealthy = self.unhealthy.get() & !(1 << index);
+            self.unhealthy.set(unhealthy);
+            if unhealthy == 0 {
+                self.kernel.starve_watchdog(false, &self.capability);
+            }
+        }
+    }
+
+    /// Fault a process that missed its heartbeat.
+    fn timeout(&self, appid: AppId) {
+        if let Some(index) = self.critical_index(appid) {
+            self.unhealthy.set(self.unhealthy.get() | 1 << index);
+            self.kernel.starve_watchdog(true, &self.capability);
+        }
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.appid() == appid {
+                    process.set_fault_state(FaultReason::WatchdogTimeout);
+                }
+            });
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability + WatchdogCapability> time::AlarmClient
+    for AppWatchdog<'a, A, C>
+{
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        for app in self.apps.iter() {
+            let expired = app.enter(|app, _| match app.watchdog {
+                Some((heartbeat, timeout)) if now.wrapping_sub(heartbeat) >= timeout => {
+                    app.watchdog = None;
+                    Some(app.appid())
+                }
+                _ => None,
+            });
+            if let Some(appid) = expired {
+                self.timeout(appid);
+            }
+        }
+        self.arm();
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability + WatchdogCapability> Driver
+    for AppWatchdog<'a, A, C>
+{
+    /// Control the watchdog of the calling process.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Start the watchdog, or change its timeout, and send a
+    ///   heartbeat. `data` is the timeout in milliseconds.
+    /// - `2`: Send a heartbeat.
+    /// - `3`: Stop the watchdog.
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        let now = self.alarm.now();
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                let timeout = match Self::ticks_from_ms(data) {
+                    Some(timeout) => timeout,
+                    None => return ReturnCode::EINVAL,
+                };
+                let result = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.watchdog = Some((now, timeout));
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if result == ReturnCode::SUCCESS {
+                    self.healthy(appid);
+                    self.arm();
+                }
+                result
+            }
+
+            2 => {
+                // The deadline only moves later, so the alarm does not need
+                // to be set again.
+                let result = self
+                    .apps
+                    .enter(appid, |app, _| match app.watchdog {
+                        Some((_, timeout)) => {
+                            app.watchdog = Some((now, timeout));
+                            ReturnCode::SUCCESS
+                        }
+                        None => ReturnCode::EOFF,
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if result == ReturnCode::SUCCESS {
+                    self.healthy(appid);
+                }
+                result
+            }
+
+            3 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.watchdog = None;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
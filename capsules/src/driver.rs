@@ -19,6 +19,9 @@ pub enum NUM {
 
     // Kernel
     Ipc                   = 0x10000,
+    AppLoader             = 0x10001,
+    IpcMessaging          = 0x10002,
+    AppWatchdog           = 0x10003,
 
     // HW Buses
     Spi                   = 0x20001,
@@ -31,6 +34,7 @@ pub enum NUM {
     BleAdvertising        = 0x30000,
     Ieee802154            = 0x30001,
     Udp                   = 0x30002,
+    Tcp                   = 0x30003,
 
     // Cryptography
     Rng                   = 0x40001,
@@ -42,6 +46,8 @@ pub enum NUM {
     AppFlash              = 0x50000,
     NvmStorage            = 0x50001,
     SdCard                = 0x50002,
+    KVStore               = 0x50003,
+    Fat                   = 0x50004,
 
     // Sensors
     Temperature           = 0x60000,
@@ -0,0 +1,499 @@
+//! Syscall driver for files on a FAT filesystem.
+//!
+//! Each process has its own directory, `/APPS/NAME`, where NAME is the
+//! process's package name in upper case, cut to 8 characters, with
+//! characters short names cannot have replaced by `_`. The directories are
+//! created when first used. A process can only open and list the files in
+//! its directory, by short (8.3) names. Processes whose names have the same
+//! first 8 characters share a directory.
+//!
+//! A process can have one request outstanding, and up to `MAX_OPEN_FILES`
+//! files open. Requests from different processes are served one at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let fat_driver = static_init!(
+//!     capsules::fat::FatDriver<'static>,
+//!     capsules::fat::FatDriver::new(
+//!         fat,
+//!         board_kernel.create_grant(&memory_allocation_cap)
+//!     )
+//! );
+//! fat.set_client(fat_driver);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::OptionalCell;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use super::filesystem::{self, Completion, Directory, FatClient, FatFilesystem, File};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Fat as usize;
+
+/// Number of files a process can have open at once.
+pub const MAX_OPEN_FILES: usize = 4;
+
+/// Directory that holds the directories of the processes.
+const APPS_DIRECTORY: [u8; 11] = *b"APPS       ";
+
+/// Open flag that creates the file if it does not exist.
+const OPEN_CREATE: usize = 1 << 0;
+/// Open flag that removes the contents of the file if it exists.
+const OPEN_TRUNCATE: usize = 1 << 1;
+
+/// Requests that complete with a callback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Request {
+    Open {
+        name: [u8; 11],
+        create: bool,
+        truncate: bool,
+    },
+    Read {
+        handle: usize,
+        len: usize,
+    },
+    Write {
+        handle: usize,
+        len: usize,
+    },
+    List {
+        index: usize,
+    },
+}
+
+impl Request {
+    /// Number of the command that made the request.
+    fn command_num(&self) -> usize {
+        match self {
+            Request::Open { .. } => 1,
+            Request::Read { .. } => 2,
+            Request::Write { .. } => 3,
+            Request::List { .. } => 6,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    read_buffer: Option<AppSlice<Shared, u8>>,
+    write_buffer: Option<AppSlice<Shared, u8>>,
+    directory: Option<Directory>,
+    files: [Option<File>; MAX_OPEN_FILES],
+    request: Option<Request>,
+}
+
+pub struct FatDriver<'a> {
+    filesystem: &'a FatFilesystem<'a>,
+    apps: Grant<App>,
+    apps_directory: Cell<Option<Directory>>,
+    /// Process whose request the filesystem is working on.
+    current: OptionalCell<AppId>,
+}
+
+impl<'a> FatDriver<'a> {
+    pub fn new(filesystem: &'a FatFilesystem<'a>, grant: Grant<App>) -> FatDriver<'a> {
+        FatDriver {
+            filesystem: filesystem,
+            apps: grant,
+            apps_directory: Cell::new(None),
+            current: OptionalCell::empty(),
+        }
+    }
+
+    /// Start requests until one is waiting for the filesystem, or none is
+    /// left.
+    fn run_next(&self) {
+        while self.current.is_none() {
+            let mut next = None;
+            for app in self.apps.iter() {
+                app.enter(|app, _| {
+                    if next.is_none() {
+                        next = app
+                            .request
+                            .map(|request| (app.appid(), request, app.directory, app.files));
+                    }
+                });
+            }
+            let (appid, request, directory, files) = match next {
+                Some(next) => next,
+                None => return,
+            };
+            self.current.set(appid);
+            let result = self.start(appid, request, directory, &files);
+            if result != ReturnCode::SUCCESS {
+                self.current.clear();
+                self.complete(appid, Err(result));
+            }
+        }
+    }
+
+    /// Start the next step of a request: mounting the filesystem, opening
+    /// the process's directory, or the request itself.
+    fn start(
+        &self,
+        appid: AppId,
+        request: Request,
+        directory: Option<Directory>,
+        files: &[Option<File>; MAX_OPEN_FILES],
+    ) -> ReturnCode {
+        let filesystem = self.filesystem;
+        let root = match filesystem.root() {
+            Some(root) => root,
+            None => return filesystem.mount(),
+        };
+        let apps_directory = match self.apps_directory.get() {
+            Some(apps_directory) => apps_directory,
+            None => return filesystem.open_directory(root, APPS_DIRECTORY, true),
+        };
+        let directory = match directory {
+            Some(directory) => directory,
+            None => {
+                let name = directory_name(appid.get_process_name());
+                return filesystem.open_directory(apps_directory, name, true);
+            }
+        };
+        match request {
+            Request::Open {
+                name,
+                create,
+                truncate,
+            } => filesystem.open(directory, name, create, truncate),
+            Request::Read { handle, len } => {
+                files[handle].map_or(ReturnCode::EINVAL, |file| filesystem.read(file, len))
+            }
+            Request::Write { handle, len } => {
+                files[handle].map_or(ReturnCode::EINVAL, |file| filesystem.write(file, len))
+            }
+            Request::List { index } => filesystem.read_directory(directory, index),
+        }
+    }
+
+    /// Finish the request of a process, or record the directory opened for
+    /// it.
+    fn complete(&self, appid: AppId, result: Result<Completion, ReturnCode>) {
+        if let Ok(Completion::Directory(directory)) = result {
+            if self.apps_directory.get().is_none() {
+                self.apps_directory.set(Some(directory));
+                return;
+            }
+        }
+        let _ = self.apps.enter(appid, |app, _| {
+            let request = match app.request {
+                Some(request) => request,
+                None => return,
+            };
+            let (value, extra) = match result {
+                Ok(Completion::Mounted) => return,
+                Ok(Completion::Directory(directory)) => {
+                    app.directory = Some(directory);
+                    return;
+                }
+                Ok(Completion::File(file)) => match app.files.iter().position(Option::is_none) {
+                    Some(handle) => {
+                        app.files[handle] = Some(file);
+                        (handle, file.size())
+                    }
+                    None => (usize::from(ReturnCode::ENOMEM), 0),
+                },
+                Ok(Completion::Read(file, len)) | Ok(Completion::Written(file, len)) => {
+                    match request {
+                        Request::Read { handle, .. } | Request::Write { handle, .. } => {
+                            app.files[handle] = Some(file);
+                        }
+                        _ => {}
+                    }
+                    (len, 0)
+                }
+                Ok(Completion::Entry(Some(entry))) => {
+                    let mut name = [0; 12];
+                    let len = entry.format_name(&mut name);
+                    let copied = app.read_buffer.as_mut().map_or(0, |buffer| {
+                        let len = cmp::min(len, buffer.len());
+                        buffer.as_mut()[..len].copy_from_slice(&name[..len]);
+                        len
+                    });
+                    (copied, entry.size as usize)
+                }
+                Ok(Completion::Entry(None)) => (0, 0),
+                Err(error) => (usize::from(error), 0),
+            };
+            app.request = None;
+            app.callback.map(|mut callback| {
+                callback.schedule(request.command_num(), value, extra);
+            });
+        });
+    }
+
+    /// Record a request from a process, and start it if the filesystem is
+    /// free.
+    fn request(&self, appid: AppId, request: Request) -> ReturnCode {
+        let result = self
+            .apps
+            .enter(appid, |app, _| {
+                if app.request.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                let checked = match request {
+                    Request::Open { .. } => {
+                        if app.files.iter().all(Option::is_some) {
+                            ReturnCode::ENOMEM
+                        } else {
+                            ReturnCode::SUCCESS
+                        }
+                    }
+                    Request::Read { handle, len } => {
+                        check_transfer(&app.files, handle, len, &app.read_buffer)
+                    }
+                    Request::Write { handle, len } => {
+                        check_transfer(&app.files, handle, len, &app.write_buffer)
+                    }
+                    Request::List { .. } => ReturnCode::SUCCESS,
+                };
+                if checked == ReturnCode::SUCCESS {
+                    app.request = Some(request);
+                }
+                checked
+            })
+            .unwrap_or_else(|err| err.into());
+        if result == ReturnCode::SUCCESS {
+            self.run_next();
+        }
+        result
+    }
+
+    /// Change an open file without starting a request.
+    fn with_file<F: FnOnce(&mut File) -> ReturnCode>(
+        &self,
+        appid: AppId,
+        handle: usize,
+        f: F,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                if app.request.is_some() {
+                    // The request may change the file.
+                    return ReturnCode::EBUSY;
+                }
+                match app.files.get_mut(handle) {
+                    Some(Some(file)) => f(file),
+                    _ => ReturnCode::EINVAL,
+                }
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+fn check_transfer(
+    files: &[Option<File>; MAX_OPEN_FILES],
+    handle: usize,
+    len: usize,
+    buffer: &Option<AppSlice<Shared, u8>>,
+) -> ReturnCode {
+    if handle >= MAX_OPEN_FILES || files[handle].is_none() {
+        ReturnCode::EINVAL
+    } else if len > buffer.as_ref().map_or(0, |buffer| buffer.len()) {
+        ReturnCode::ESIZE
+    } else {
+        ReturnCode::SUCCESS
+    }
+}
+
+/// Short name of the directory of a process.
+fn directory_name(process_name: &str) -> [u8; 11] {
+    let mut name = [b' '; 11];
+    for (i, byte) in process_name.bytes().take(8).enumerate() {
+        name[i] = filesystem::short_name(&[byte]).map_or(b'_', |short| short[0]);
+    }
+    if process_name.is_empty() {
+        name[..3].copy_from_slice(b"APP");
+    }
+    name
+}
+
+impl<'a> FatClient for FatDriver<'a> {
+    fn read_data(&self, offset: usize, data: &[u8]) {
+        self.current.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, _| {
+                app.read_buffer.as_mut().map(|buffer| {
+                    let buffer = buffer.as_mut();
+                    if offset < buffer.len() {
+                        let len = cmp::min(data.len(), buffer.len() - offset);
+                        buffer[offset..offset + len].copy_from_slice(&data[..len]);
+                    }
+                });
+            });
+        });
+    }
+
+    fn write_data(&self, offset: usize, data: &mut [u8]) {
+        self.current.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, _| {
+                app.write_buffer.as_ref().map(|buffer| {
+                    let buffer = buffer.as_ref();
+                    if offset < buffer.len() {
+                        let len = cmp::min(data.len(), buffer.len() - offset);
+                        data[..len].copy_from_slice(&buffer[offset..offset + len]);
+                    }
+                });
+            });
+        });
+    }
+
+    fn operation_done(&self, result: Result<Completion, ReturnCode>) {
+        if let Some(appid) = self.current.take() {
+            self.complete(appid, result);
+        }
+        self.run_next();
+    }
+}
+
+impl<'a> Driver for FatDriver<'a> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Buffer that data and directory entries are read into.
+    /// - `1`: Buffer that data and file names are written from.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.read_buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.write_buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup the callback, called when a request completes.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Request completed.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Files.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Open the file named by the first `data1` bytes of the write
+    ///        buffer. `data2` is a bitmask of flags: 1 creates the file if it
+    ///        does not exist, 2 removes its contents if it does.
+    /// - `2`: Read up to `data2` bytes from file `data1`.
+    /// - `3`: Write `data2` bytes to file `data1`.
+    /// - `4`: Move file `data1` to position `data2`.
+    /// - `5`: Close file `data1`.
+    /// - `6`: List entry `data1` of the directory.
+    /// - `7`: Get the size of file `data1`.
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                let name = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.write_buffer
+                            .as_ref()
+                            .filter(|buffer| data1 <= buffer.len())
+                            .map_or(Err(ReturnCode::ESIZE), |buffer| {
+                                filesystem::short_name(&buffer.as_ref()[..data1])
+                            })
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                match name {
+                    Ok(name) => self.request(
+                        appid,
+                        Request::Open {
+                            name: name,
+                            create: data2 & OPEN_CREATE != 0,
+                            truncate: data2 & OPEN_TRUNCATE != 0,
+                        },
+                    ),
+                    Err(error) => error,
+                }
+            }
+
+            2 => self.request(
+                appid,
+                Request::Read {
+                    handle: data1,
+                    len: data2,
+                },
+            ),
+
+            3 => self.request(
+                appid,
+                Request::Write {
+                    handle: data1,
+                    len: data2,
+                },
+            ),
+
+            4 => self.with_file(appid, data1, |file| file.seek(data2)),
+
+            5 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if app.request.is_some() {
+                        ReturnCode::EBUSY
+                    } else {
+                        match app.files.get_mut(data1) {
+                            Some(file) if file.is_some() => {
+                                *file = None;
+                                ReturnCode::SUCCESS
+                            }
+                            _ => ReturnCode::EINVAL,
+                        }
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            6 => self.request(appid, Request::List { index: data1 }),
+
+            7 => self.with_file(appid, data1, |file| ReturnCode::SuccessWithValue {
+                value: file.size(),
+            }),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
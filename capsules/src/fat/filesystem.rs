@@ -0,0 +1,1498 @@
+//! FAT12, FAT16 and FAT32 filesystems on block storage.
+//!
+//! `FatFilesystem` mounts the FAT volume at the start of a block device, or
+//! in the first partition of a device with a master boot record, and gives
+//! its client access to directories and files by their short (8.3) names.
+//! Long file names are ignored, so files created on a PC need short names
+//! to be found. Files written here have short names, and are dated
+//! 1980-01-01 as there is no clock.
+//!
+//! The filesystem does one operation at a time, through a buffer that caches
+//! one sector. Data is copied between that buffer and the client with
+//! `FatClient::read_data()` and `FatClient::write_data()` while an operation
+//! runs, and the operation ends with `FatClient::operation_done()`. This can
+//! happen before the call that started the operation returns, if no sector
+//! has to be read. Changes are written to the device before an operation
+//! that made them ends.
+//!
+//! The filesystem does not track open files. A `File` holds the state of an
+//! open file, including its size and position, and operations on the file
+//! return it updated. Opening the same file twice and writing through both
+//! `File`s leaves the size of only one of them in its directory entry.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let fat = static_init!(
+//!     capsules::fat::FatFilesystem<'static>,
+//!     capsules::fat::FatFilesystem::new(sdcard, &mut capsules::fat::BUFFER)
+//! );
+//! hil::block_storage::BlockStorage::set_client(sdcard, fat);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient};
+use kernel::ReturnCode;
+
+/// Size of sectors, which must be the block size of the device.
+pub const SECTOR_SIZE: usize = 512;
+
+pub static mut BUFFER: [u8; SECTOR_SIZE] = [0; SECTOR_SIZE];
+
+const ENTRY_SIZE: usize = 32;
+const ENTRIES_PER_SECTOR: u32 = (SECTOR_SIZE / ENTRY_SIZE) as u32;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+const ENTRY_FREE: u8 = 0xe5;
+const ENTRY_END: u8 = 0x00;
+
+/// 1980-01-01, the earliest date FAT can store.
+const DATE: u16 = 1 << 5 | 1;
+
+/// Partition types of FAT volumes in a master boot record.
+const FAT_PARTITION_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0b, 0x0c, 0x0e];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// Layout of a mounted volume. Sector numbers are blocks of the device.
+#[derive(Clone, Copy, Debug)]
+struct Volume {
+    fat_type: FatType,
+    sectors_per_cluster: u32,
+    fat_start: u32,
+    fat_sectors: u32,
+    fats: u32,
+    /// Fixed root directory of FAT12 and FAT16.
+    root_start: u32,
+    root_sectors: u32,
+    /// Root directory cluster of FAT32.
+    root_cluster: u32,
+    data_start: u32,
+    /// Clusters are numbered from 2 to `clusters + 1`.
+    clusters: u32,
+    /// FSInfo sector of FAT32, or 0.
+    fsinfo: u32,
+}
+
+impl Volume {
+    /// Parse the boot sector of a volume that starts at sector `start`.
+    fn parse(boot: &[u8], start: u32) -> Option<Volume> {
+        let u16_at = |offset: usize| u16::from_le_bytes([boot[offset], boot[offset + 1]]) as u32;
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([
+                boot[offset],
+                boot[offset + 1],
+                boot[offset + 2],
+                boot[offset + 3],
+            ])
+        };
+        let bytes_per_sector = u16_at(11);
+        let sectors_per_cluster = boot[13] as u32;
+        let reserved = u16_at(14);
+        let fats = boot[16] as u32;
+        let root_entries = u16_at(17);
+        let total_sectors = if u16_at(19) != 0 {
+            u16_at(19)
+        } else {
+            u32_at(32)
+        };
+        let fat_sectors = if u16_at(22) != 0 {
+            u16_at(22)
+        } else {
+            u32_at(36)
+        };
+        if (boot[0] != 0xeb && boot[0] != 0xe9)
+            || bytes_per_sector != SECTOR_SIZE as u32
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved == 0
+            || fats == 0
+            || fat_sectors == 0
+        {
+            return None;
+        }
+
+        let root_sectors =
+            (root_entries * ENTRY_SIZE as u32 + SECTOR_SIZE as u32 - 1) / SECTOR_SIZE as u32;
+        let data_start = reserved + fats * fat_sectors + root_sectors;
+        if total_sectors <= data_start {
+            return None;
+        }
+        let clusters = (total_sectors - data_start) / sectors_per_cluster;
+        let fat_type = if clusters < 4085 {
+            FatType::Fat12
+        } else if clusters < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+        let (root_cluster, fsinfo) = if fat_type == FatType::Fat32 {
+            (u32_at(44), u16_at(48))
+        } else {
+            (0, 0)
+        };
+        if (fat_type == FatType::Fat32) != (root_sectors == 0) {
+            return None;
+        }
+        Some(Volume {
+            fat_type: fat_type,
+            sectors_per_cluster: sectors_per_cluster,
+            fat_start: start + reserved,
+            fat_sectors: fat_sectors,
+            fats: fats,
+            root_start: start + reserved + fats * fat_sectors,
+            root_sectors: root_sectors,
+            root_cluster: root_cluster,
+            data_start: start + data_start,
+            clusters: clusters,
+            fsinfo: if fsinfo == 0 || fsinfo == 0xffff {
+                0
+            } else {
+                start + fsinfo
+            },
+        })
+    }
+
+    fn cluster_bytes(&self) -> u32 {
+        self.sectors_per_cluster * SECTOR_SIZE as u32
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    fn is_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.clusters + 2
+    }
+
+    /// Whether a FAT entry marks the end of a cluster chain.
+    fn is_end(&self, entry: u32) -> bool {
+        match self.fat_type {
+            FatType::Fat12 => entry >= 0xff8,
+            FatType::Fat16 => entry >= 0xfff8,
+            FatType::Fat32 => entry >= 0x0fff_fff8,
+        }
+    }
+
+    fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xfff,
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+
+    /// Offset of the FAT entry of `cluster` in the FAT, and its length.
+    fn fat_entry(&self, cluster: u32) -> (u32, u32) {
+        match self.fat_type {
+            FatType::Fat12 => (cluster + cluster / 2, 2),
+            FatType::Fat16 => (cluster * 2, 2),
+            FatType::Fat32 => (cluster * 4, 4),
+        }
+    }
+
+    /// Sector and offset of byte `offset` of FAT copy `fat`.
+    fn fat_byte(&self, fat: u32, offset: u32) -> (u32, usize) {
+        let sector = self.fat_start + fat * self.fat_sectors + offset / SECTOR_SIZE as u32;
+        (sector, offset as usize % SECTOR_SIZE)
+    }
+
+    /// New value of byte `byte` of the FAT entry of `cluster`, when the
+    /// entry is set to `value`.
+    fn fat_entry_byte(&self, cluster: u32, value: u32, byte: u32, old: u8) -> u8 {
+        match (self.fat_type, byte) {
+            (FatType::Fat12, 0) if cluster & 1 == 1 => (old & 0x0f) | (value << 4) as u8,
+            (FatType::Fat12, 1) if cluster & 1 == 1 => (value >> 4) as u8,
+            (FatType::Fat12, 0) => value as u8,
+            (FatType::Fat12, _) => (old & 0xf0) | (value >> 8) as u8 & 0x0f,
+            (FatType::Fat32, 3) => (old & 0xf0) | (value >> 24) as u8 & 0x0f,
+            (_, byte) => (value >> (8 * byte)) as u8,
+        }
+    }
+}
+
+/// A directory. Cluster 0 is the fixed root directory of FAT12 and FAT16.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Directory {
+    cluster: u32,
+}
+
+/// Position in a directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Cursor {
+    cluster: u32,
+    /// Sector in the cluster, or in the fixed root directory.
+    sector: u32,
+    entry: u32,
+}
+
+impl Cursor {
+    fn start(directory: Directory) -> Cursor {
+        Cursor {
+            cluster: directory.cluster,
+            sector: 0,
+            entry: 0,
+        }
+    }
+}
+
+/// Location of a directory entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct EntryLocation {
+    sector: u32,
+    entry: u32,
+}
+
+/// An open file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct File {
+    entry: EntryLocation,
+    first_cluster: u32,
+    size: u32,
+    position: u32,
+    /// Cluster number `cluster_index` of the file, or 0 if not known yet.
+    cluster: u32,
+    cluster_index: u32,
+}
+
+impl File {
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    pub fn position(&self) -> usize {
+        self.position as usize
+    }
+
+    /// Move to `position`, which cannot be past the end of the file.
+    pub fn seek(&mut self, position: usize) -> ReturnCode {
+        if position > self.size as usize {
+            ReturnCode::EINVAL
+        } else {
+            self.position = position as u32;
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+/// A file or directory listed by `FatFilesystem::read_directory()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    /// Name in the 8.3 form used in directory entries.
+    pub name: [u8; 11],
+    pub attributes: u8,
+    pub size: u32,
+}
+
+impl DirectoryEntry {
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// Write the name as "NAME.EXT" to `buffer`, and return its length.
+    pub fn format_name(&self, buffer: &mut [u8; 12]) -> usize {
+        let mut len = 0;
+        for &byte in self.name[..8].iter().filter(|byte| **byte != b' ') {
+            buffer[len] = byte;
+            len += 1;
+        }
+        if self.name[8] != b' ' {
+            buffer[len] = b'.';
+            len += 1;
+            for &byte in self.name[8..].iter().filter(|byte| **byte != b' ') {
+                buffer[len] = byte;
+                len += 1;
+            }
+        }
+        len
+    }
+}
+
+/// Convert a name such as "log.txt" to the 8.3 form used in directory
+/// entries. Letters are converted to upper case. Returns EINVAL if the name
+/// does not fit, or has characters short names cannot have.
+pub fn short_name(name: &[u8]) -> Result<[u8; 11], ReturnCode> {
+    let (base, extension) = match name.iter().rposition(|byte| *byte == b'.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, &name[name.len()..]),
+    };
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return Err(ReturnCode::EINVAL);
+    }
+    let mut short = [b' '; 11];
+    for (i, &byte) in base.iter().enumerate() {
+        short[i] = short_name_char(byte)?;
+    }
+    for (i, &byte) in extension.iter().enumerate() {
+        short[8 + i] = short_name_char(byte)?;
+    }
+    Ok(short)
+}
+
+fn short_name_char(byte: u8) -> Result<u8, ReturnCode> {
+    match byte {
+        b'A'..=b'Z' | b'0'..=b'9' => Ok(byte),
+        b'a'..=b'z' => Ok(byte.to_ascii_uppercase()),
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'(' | b')' | b'-' | b'@' | b'^' | b'_'
+        | b'`' | b'{' | b'}' | b'~' => Ok(byte),
+        _ => Err(ReturnCode::EINVAL),
+    }
+}
+
+/// Result of a successful operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Completion {
+    Mounted,
+    Directory(Directory),
+    File(File),
+    /// The file, and the number of bytes read.
+    Read(File, usize),
+    /// The file, and the number of bytes written.
+    Written(File, usize),
+    /// The entry, or None if the directory has fewer entries.
+    Entry(Option<DirectoryEntry>),
+}
+
+pub trait FatClient {
+    /// Copy `data`, read from a file, to offset `offset` of the data the
+    /// operation reads.
+    fn read_data(&self, offset: usize, data: &[u8]);
+
+    /// Fill `data` with the bytes at offset `offset` of the data the
+    /// operation writes.
+    fn write_data(&self, offset: usize, data: &mut [u8]);
+
+    fn operation_done(&self, result: Result<Completion, ReturnCode>);
+}
+
+/// Why an operation cannot continue now.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pending {
+    /// A sector is being read or written, and the operation continues when
+    /// that is done.
+    Wait,
+    Error(ReturnCode),
+}
+
+type Poll<T> = Result<T, Pending>;
+
+/// Entries a directory scan looks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Target {
+    Name([u8; 11]),
+    /// The entry with this index among the listed entries.
+    Index(u32),
+}
+
+/// Outcome of scanning one sector of a directory.
+enum ScanResult {
+    Found(EntryLocation, [u8; ENTRY_SIZE]),
+    End,
+    Continue,
+}
+
+/// Progress through a directory, looking for an entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Scan {
+    target: Target,
+    cursor: Cursor,
+    /// Whether the entries of the sector at `cursor` have been looked at.
+    sector_done: bool,
+    /// Listed entries seen so far.
+    count: u32,
+    /// First free entry seen so far.
+    free: Option<EntryLocation>,
+}
+
+impl Scan {
+    fn new(directory: Directory, target: Target) -> Scan {
+        Scan {
+            target: target,
+            cursor: Cursor::start(directory),
+            sector_done: false,
+            count: 0,
+            free: None,
+        }
+    }
+}
+
+/// Progress of allocating a cluster and linking it to a chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AllocationStage {
+    Search,
+    Mark,
+    Link,
+    /// Zeroing the sectors of the cluster, from this one on.
+    Zero(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Allocation {
+    stage: AllocationStage,
+    candidate: u32,
+    searched: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MountStage {
+    ReadFirstSector,
+    ReadBootSector(u32),
+    InvalidateFsinfo,
+    Flush,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OpenStage {
+    Scan(Scan),
+    /// Freeing the clusters of a file opened for truncation. `next` is the
+    /// cluster after `cluster` once it has been read.
+    Truncate {
+        cluster: u32,
+        next: Option<u32>,
+    },
+    /// Allocating the first cluster of a new directory. Holds the free
+    /// entry the scan found, or the last cluster of the parent if there is
+    /// none.
+    AllocateDirectory(Result<EntryLocation, u32>),
+    /// Writing the "." and ".." entries of a new directory.
+    InitializeDirectory(Result<EntryLocation, u32>, u32),
+    /// Adding a cluster to the directory, which has no free entries left.
+    /// Holds the last cluster of the directory, and the first cluster of the
+    /// new file or directory.
+    Extend(u32, u32),
+    WriteEntry(EntryLocation, u32),
+    Flush,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WriteStage {
+    Data,
+    UpdateEntry,
+    Flush,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operation {
+    Idle,
+    Mount(MountStage),
+    Open {
+        directory: Directory,
+        name: [u8; 11],
+        is_directory: bool,
+        create: bool,
+        truncate: bool,
+        file: Option<File>,
+        stage: OpenStage,
+    },
+    Read {
+        file: File,
+        len: usize,
+        done: usize,
+        next: Option<u32>,
+    },
+    Write {
+        file: File,
+        len: usize,
+        done: usize,
+        next: Option<u32>,
+        stage: WriteStage,
+    },
+    ReadDirectory(Scan),
+}
+
+pub struct FatFilesystem<'a> {
+    device: &'a dyn BlockStorage<'a>,
+    client: OptionalCell<&'a dyn FatClient>,
+    volume: Cell<Option<Volume>>,
+    operation: Cell<Operation>,
+
+    buffer: TakeCell<'static, [u8]>,
+    /// Sector in the buffer.
+    cached: Cell<Option<u32>>,
+    /// Whether the buffer has changes that are not on the device yet.
+    dirty: Cell<bool>,
+    /// Sector being read into the buffer.
+    loading: Cell<Option<u32>>,
+    /// Sector to read into the buffer once it has been written back.
+    after_write: Cell<Option<u32>>,
+
+    /// Bytes of a FAT entry written so far, over all FAT copies.
+    fat_progress: Cell<u32>,
+    /// First byte of a FAT12 entry that spans two sectors, with its offset.
+    fat12_first_byte: Cell<Option<(u32, u8)>>,
+    allocation: Cell<Option<Allocation>>,
+    /// Where to start looking for a free cluster.
+    next_free: Cell<u32>,
+}
+
+impl<'a> FatFilesystem<'a> {
+    pub fn new(device: &'a dyn BlockStorage<'a>, buffer: &'static mut [u8]) -> FatFilesystem<'a> {
+        FatFilesystem {
+            device: device,
+            client: OptionalCell::empty(),
+            volume: Cell::new(None),
+            operation: Cell::new(Operation::Idle),
+            buffer: TakeCell::new(buffer),
+            cached: Cell::new(None),
+            dirty: Cell::new(false),
+            loading: Cell::new(None),
+            after_write: Cell::new(None),
+            fat_progress: Cell::new(0),
+            fat12_first_byte: Cell::new(None),
+            allocation: Cell::new(None),
+            next_free: Cell::new(2),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn FatClient) {
+        self.client.set(client);
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        self.volume.get().is_some()
+    }
+
+    /// The root directory of the mounted volume.
+    pub fn root(&self) -> Option<Directory> {
+        self.volume.get().map(|volume| Directory {
+            cluster: volume.root_cluster,
+        })
+    }
+
+    /// Mount the volume, or mount it again if the device has been changed.
+    /// Fails with ENOSUPPORT if the device has no FAT volume, or with the
+    /// error of the device if it cannot be read.
+    pub fn mount(&self) -> ReturnCode {
+        if self.operation.get() != Operation::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if self.device.block_size() != SECTOR_SIZE {
+            return ReturnCode::ENOSUPPORT;
+        }
+        self.volume.set(None);
+        self.cached.set(None);
+        self.dirty.set(false);
+        self.start(Operation::Mount(MountStage::ReadFirstSector))
+    }
+
+    /// Open the directory `name` in `parent`, creating it if it does not
+    /// exist and `create` is set. Fails with EINVAL if it does not exist or
+    /// is a file, and with ENOMEM if it cannot be created.
+    pub fn open_directory(&self, parent: Directory, name: [u8; 11], create: bool) -> ReturnCode {
+        self.start_open(parent, name, true, create, false)
+    }
+
+    /// Open the file `name` in `directory` at its start. If it does not
+    /// exist, `create` creates it, and `truncate` removes its contents if it
+    /// does. Fails with EINVAL if it does not exist or is a directory, and
+    /// with ENOMEM if it cannot be created.
+    pub fn open(
+        &self,
+        directory: Directory,
+        name: [u8; 11],
+        create: bool,
+        truncate: bool,
+    ) -> ReturnCode {
+        self.start_open(directory, name, false, create, truncate)
+    }
+
+    /// Read up to `len` bytes from the position of the file, and pass them
+    /// to `FatClient::read_data()`.
+    pub fn read(&self, file: File, len: usize) -> ReturnCode {
+        if !self.is_mounted() {
+            return ReturnCode::EOFF;
+        }
+        self.start(Operation::Read {
+            file: file,
+            len: len,
+            done: 0,
+            next: None,
+        })
+    }
+
+    /// Write `len` bytes, from `FatClient::write_data()`, at the position of
+    /// the file. If the volume fills up, or the file reaches 4 GiB, writes
+    /// what fits, and fails with ENOMEM or ESIZE only if nothing fits.
+    pub fn write(&self, file: File, len: usize) -> ReturnCode {
+        if !self.is_mounted() {
+            return ReturnCode::EOFF;
+        }
+        self.start(Operation::Write {
+            file: file,
+            len: len,
+            done: 0,
+            next: None,
+            stage: WriteStage::Data,
+        })
+    }
+
+    /// Find entry number `index` of the directory, not counting deleted
+    /// entries, volume labels, long names, "." and "..".
+    pub fn read_directory(&self, directory: Directory, index: usize) -> ReturnCode {
+        if !self.is_mounted() {
+            return ReturnCode::EOFF;
+        }
+        self.start(Operation::ReadDirectory(Scan::new(
+            directory,
+            Target::Index(index as u32),
+        )))
+    }
+
+    fn start_open(
+        &self,
+        directory: Directory,
+        name: [u8; 11],
+        is_directory: bool,
+        create: bool,
+        truncate: bool,
+    ) -> ReturnCode {
+        if !self.is_mounted() {
+            return ReturnCode::EOFF;
+        }
+        self.start(Operation::Open {
+            directory: directory,
+            name: name,
+            is_directory: is_directory,
+            create: create,
+            truncate: truncate,
+            file: None,
+            stage: OpenStage::Scan(Scan::new(directory, Target::Name(name))),
+        })
+    }
+
+    fn start(&self, operation: Operation) -> ReturnCode {
+        if self.operation.get() != Operation::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.operation.set(operation);
+        self.run();
+        ReturnCode::SUCCESS
+    }
+
+    /// Continue the operation until it waits for the device or ends.
+    fn run(&self) {
+        let mut operation = self.operation.get();
+        let result = match operation {
+            Operation::Idle => return,
+            Operation::Mount(ref mut stage) => self.mount_step(stage),
+            Operation::Open {
+                directory,
+                name,
+                is_directory,
+                create,
+                truncate,
+                ref mut file,
+                ref mut stage,
+            } => self.open_step(directory, name, is_directory, create, truncate, file, stage),
+            Operation::Read {
+                ref mut file,
+                len,
+                ref mut done,
+                ref mut next,
+            } => self.read_step(file, len, done, next),
+            Operation::Write {
+                ref mut file,
+                len,
+                ref mut done,
+                ref mut next,
+                ref mut stage,
+            } => self.write_step(file, len, done, next, stage),
+            Operation::ReadDirectory(ref mut scan) => self.scan(scan).map(|found| {
+                Completion::Entry(found.map(|(_, entry)| DirectoryEntry {
+                    name: name_of(&entry),
+                    attributes: entry[11],
+                    size: u32_at(&entry, 28),
+                }))
+            }),
+        };
+        self.operation.set(operation);
+        match result {
+            Err(Pending::Wait) => {}
+            Ok(completion) => self.finish(Ok(completion)),
+            Err(Pending::Error(error)) => self.finish(Err(error)),
+        }
+    }
+
+    fn finish(&self, result: Result<Completion, ReturnCode>) {
+        self.operation.set(Operation::Idle);
+        self.fat_progress.set(0);
+        self.fat12_first_byte.set(None);
+        self.allocation.set(None);
+        self.client.map(|client| client.operation_done(result));
+    }
+
+    fn volume(&self) -> Poll<Volume> {
+        self.volume.get().ok_or(Pending::Error(ReturnCode::EOFF))
+    }
+
+    fn mount_step(&self, stage: &mut MountStage) -> Poll<Completion> {
+        loop {
+            match *stage {
+                MountStage::ReadFirstSector => {
+                    let (volume, partition) = self.with_sector(0, |sector| {
+                        (Volume::parse(sector, 0), partition_start(sector))
+                    })?;
+                    match (volume, partition) {
+                        (Some(volume), _) => {
+                            self.volume.set(Some(volume));
+                            *stage = MountStage::InvalidateFsinfo;
+                        }
+                        (None, Some(start)) => *stage = MountStage::ReadBootSector(start),
+                        (None, None) => return Err(Pending::Error(ReturnCode::ENOSUPPORT)),
+                    }
+                }
+                MountStage::ReadBootSector(start) => {
+                    let volume = self.with_sector(start, |sector| Volume::parse(sector, start))?;
+                    self.volume
+                        .set(Some(volume.ok_or(Pending::Error(ReturnCode::ENOSUPPORT))?));
+                    *stage = MountStage::InvalidateFsinfo;
+                }
+                MountStage::InvalidateFsinfo => {
+                    // The free cluster count and next free cluster hints
+                    // become wrong as soon as a cluster is allocated, so mark
+                    // them unknown.
+                    let fsinfo = self.volume()?.fsinfo;
+                    if fsinfo != 0 {
+                        self.modify_sector(fsinfo, |sector| {
+                            if sector[..4] == *b"RRaA" && sector[484..488] == *b"rrAa" {
+                                for byte in sector[488..496].iter_mut() {
+                                    *byte = 0xff;
+                                }
+                            }
+                        })?;
+                    }
+                    self.next_free.set(2);
+                    *stage = MountStage::Flush;
+                }
+                MountStage::Flush => {
+                    self.flush()?;
+                    return Ok(Completion::Mounted);
+                }
+            }
+        }
+    }
+
+    fn open_step(
+        &self,
+        directory: Directory,
+        name: [u8; 11],
+        is_directory: bool,
+        create: bool,
+        truncate: bool,
+        file: &mut Option<File>,
+        stage: &mut OpenStage,
+    ) -> Poll<Completion> {
+        let volume = self.volume()?;
+        loop {
+            match *stage {
+                OpenStage::Scan(ref mut scan) => match self.scan(scan)? {
+                    Some((location, entry)) => {
+                        let attributes = entry[11];
+                        if (attributes & ATTR_DIRECTORY != 0) != is_directory {
+                            return Err(Pending::Error(ReturnCode::EINVAL));
+                        }
+                        let first_cluster =
+                            (u16_at(&entry, 20) as u32) << 16 | u16_at(&entry, 26) as u32;
+                        if is_directory {
+                            return Ok(Completion::Directory(Directory {
+                                cluster: first_cluster,
+                            }));
+                        }
+                        *file = Some(File {
+                            entry: location,
+                            first_cluster: first_cluster,
+                            size: u32_at(&entry, 28),
+                            position: 0,
+                            cluster: 0,
+                            cluster_index: 0,
+                        });
+                        if !truncate {
+                            break;
+                        }
+                        *stage = OpenStage::Truncate {
+                            cluster: first_cluster,
+                            next: None,
+                        };
+                    }
+                    None if !create => return Err(Pending::Error(ReturnCode::EINVAL)),
+                    None => {
+                        let free = scan.free.ok_or(scan.cursor.cluster);
+                        *stage = match free {
+                            _ if is_directory => OpenStage::AllocateDirectory(free),
+                            Ok(location) => OpenStage::WriteEntry(location, 0),
+                            Err(last_cluster) => OpenStage::Extend(last_cluster, 0),
+                        }
+                    }
+                },
+                OpenStage::Truncate {
+                    ref mut cluster,
+                    ref mut next,
+                } => {
+                    if volume.is_cluster(*cluster) {
+                        let following = match *next {
+                            Some(following) => following,
+                            None => {
+                                let following = self.read_fat(*cluster)?;
+                                *next = Some(following);
+                                following
+                            }
+                        };
+                        self.write_fat(*cluster, 0)?;
+                        *cluster = following;
+                        *next = None;
+                    } else {
+                        let location = file.map(|file| file.entry).unwrap();
+                        self.modify_sector(location.sector, |sector| {
+                            let entry = entry_mut(sector, location.entry);
+                            entry[20..22].copy_from_slice(&[0; 2]);
+                            entry[26..32].copy_from_slice(&[0; 6]);
+                        })?;
+                        file.as_mut().map(|file| {
+                            file.first_cluster = 0;
+                            file.size = 0;
+                        });
+                        *stage = OpenStage::Flush;
+                    }
+                }
+                OpenStage::AllocateDirectory(free) => {
+                    let cluster = self.allocate(0, true)?;
+                    *stage = OpenStage::InitializeDirectory(free, cluster);
+                }
+                OpenStage::InitializeDirectory(free, cluster) => {
+                    let parent = if directory.cluster == volume.root_cluster {
+                        0
+                    } else {
+                        directory.cluster
+                    };
+                    self.modify_sector(volume.cluster_sector(cluster), |sector| {
+                        write_entry(
+                            entry_mut(sector, 0),
+                            b".          ",
+                            ATTR_DIRECTORY,
+                            cluster,
+                        );
+                        write_entry(entry_mut(sector, 1), b"..         ", ATTR_DIRECTORY, parent);
+                    })?;
+                    *stage = match free {
+                        Ok(location) => OpenStage::WriteEntry(location, cluster),
+                        Err(last_cluster) => OpenStage::Extend(last_cluster, cluster),
+                    };
+                }
+                OpenStage::Extend(last_cluster, cluster) => {
+                    if last_cluster == 0 {
+                        // The fixed root directory cannot grow.
+                        return Err(Pending::Error(ReturnCode::ENOMEM));
+                    }
+                    let added = self.allocate(last_cluster, true)?;
+                    *stage = OpenStage::WriteEntry(
+                        EntryLocation {
+                            sector: volume.cluster_sector(added),
+                            entry: 0,
+                        },
+                        cluster,
+                    );
+                }
+                OpenStage::WriteEntry(location, cluster) => {
+                    let attributes = if is_directory {
+                        ATTR_DIRECTORY
+                    } else {
+                        ATTR_ARCHIVE
+                    };
+                    self.modify_sector(location.sector, |sector| {
+                        write_entry(
+                            entry_mut(sector, location.entry),
+                            &name,
+                            attributes,
+                            cluster,
+                        );
+                    })?;
+                    *file = Some(File {
+                        entry: location,
+                        first_cluster: cluster,
+                        size: 0,
+                        position: 0,
+                        cluster: 0,
+                        cluster_index: 0,
+                    });
+                    *stage = OpenStage::Flush;
+                }
+                OpenStage::Flush => {
+                    self.flush()?;
+                    break;
+                }
+            }
+        }
+        let file = file.unwrap();
+        if is_directory {
+            Ok(Completion::Directory(Directory {
+                cluster: file.first_cluster,
+            }))
+        } else {
+            Ok(Completion::File(file))
+        }
+    }
+
+    fn read_step(
+        &self,
+        file: &mut File,
+        len: usize,
+        done: &mut usize,
+        next: &mut Option<u32>,
+    ) -> Poll<Completion> {
+        let volume = self.volume()?;
+        while *done < len && file.position < file.size {
+            self.seek_cluster(&volume, file, next, false)?;
+            let offset = file.position % volume.cluster_bytes();
+            let sector = volume.cluster_sector(file.cluster) + offset / SECTOR_SIZE as u32;
+            let start = offset as usize % SECTOR_SIZE;
+            let count = cmp::min(
+                SECTOR_SIZE - start,
+                cmp::min(len - *done, (file.size - file.position) as usize),
+            );
+            let offset = *done;
+            self.with_sector(sector, |sector| {
+                self.client.map(|client| {
+                    client.read_data(offset, &sector[start..start + count]);
+                });
+            })?;
+            *done += count;
+            file.position += count as u32;
+        }
+        Ok(Completion::Read(*file, *done))
+    }
+
+    fn write_step(
+        &self,
+        file: &mut File,
+        len: usize,
+        done: &mut usize,
+        next: &mut Option<u32>,
+        stage: &mut WriteStage,
+    ) -> Poll<Completion> {
+        let volume = self.volume()?;
+        loop {
+            match *stage {
+                WriteStage::Data => {
+                    if *done == len {
+                        *stage = WriteStage::UpdateEntry;
+                        continue;
+                    }
+                    if file.position == u32::MAX {
+                        // Files cannot grow past 4 GiB.
+                        if *done == 0 {
+                            return Err(Pending::Error(ReturnCode::ESIZE));
+                        }
+                        *stage = WriteStage::UpdateEntry;
+                        continue;
+                    }
+                    if let Err(error) = self.seek_cluster(&volume, file, next, true) {
+                        if error == Pending::Error(ReturnCode::ENOMEM) && *done > 0 {
+                            // Keep what was written, and end with what fits.
+                            *stage = WriteStage::UpdateEntry;
+                            continue;
+                        }
+                        return Err(error);
+                    }
+                    let offset = file.position % volume.cluster_bytes();
+                    let sector = volume.cluster_sector(file.cluster) + offset / SECTOR_SIZE as u32;
+                    let start = offset as usize % SECTOR_SIZE;
+                    let count = cmp::min(
+                        SECTOR_SIZE - start,
+                        cmp::min(len - *done, (u32::MAX - file.position) as usize),
+                    );
+                    let data_offset = *done;
+                    let copy = |sector: &mut [u8]| {
+                        self.client.map(|client| {
+                            client.write_data(data_offset, &mut sector[start..start + count]);
+                        });
+                    };
+                    if start == 0 && (count == SECTOR_SIZE || file.position >= file.size) {
+                        // Nothing in the sector has to be kept.
+                        self.new_sector(sector, copy)?;
+                    } else {
+                        self.modify_sector(sector, copy)?;
+                    }
+                    *done += count;
+                    file.position += count as u32;
+                    file.size = cmp::max(file.size, file.position);
+                }
+                WriteStage::UpdateEntry => {
+                    let location = file.entry;
+                    let first_cluster = file.first_cluster;
+                    let size = file.size;
+                    self.modify_sector(location.sector, |sector| {
+                        let entry = entry_mut(sector, location.entry);
+                        entry[11] |= ATTR_ARCHIVE;
+                        entry[18..20].copy_from_slice(&DATE.to_le_bytes());
+                        entry[20..22]
+                            .copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+                        entry[24..26].copy_from_slice(&DATE.to_le_bytes());
+                        entry[26..28].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+                        entry[28..32].copy_from_slice(&size.to_le_bytes());
+                    })?;
+                    *stage = WriteStage::Flush;
+                }
+                WriteStage::Flush => {
+                    self.flush()?;
+                    return Ok(Completion::Written(*file, *done));
+                }
+            }
+        }
+    }
+
+    /// Find the cluster that holds the position of the file, and allocate it
+    /// if `extend` is set and the file ends before it.
+    fn seek_cluster(
+        &self,
+        volume: &Volume,
+        file: &mut File,
+        next: &mut Option<u32>,
+        extend: bool,
+    ) -> Poll<()> {
+        let index = file.position / volume.cluster_bytes();
+        if file.first_cluster == 0 {
+            if !extend {
+                return Err(Pending::Error(ReturnCode::FAIL));
+            }
+            let cluster = self.allocate(0, false)?;
+            file.first_cluster = cluster;
+            file.cluster = cluster;
+            file.cluster_index = 0;
+        }
+        if file.cluster == 0 || file.cluster_index > index {
+            file.cluster = file.first_cluster;
+            file.cluster_index = 0;
+        }
+        while file.cluster_index < index {
+            let following = match *next {
+                Some(following) => following,
+                None => {
+                    let following = self.read_fat(file.cluster)?;
+                    *next = Some(following);
+                    following
+                }
+            };
+            let following = if volume.is_end(following) && extend {
+                self.allocate(file.cluster, false)?
+            } else if volume.is_cluster(following) {
+                following
+            } else {
+                return Err(Pending::Error(ReturnCode::FAIL));
+            };
+            file.cluster = following;
+            file.cluster_index += 1;
+            *next = None;
+        }
+        if volume.is_cluster(file.cluster) {
+            Ok(())
+        } else {
+            Err(Pending::Error(ReturnCode::FAIL))
+        }
+    }
+
+    /// Look through a directory for the entry the scan targets, and return
+    /// it with its location, or None if the directory ends first.
+    fn scan(&self, scan: &mut Scan) -> Poll<Option<(EntryLocation, [u8; ENTRY_SIZE])>> {
+        let volume = self.volume()?;
+        loop {
+            if !scan.sector_done {
+                let sector = self.cursor_sector(&volume, scan.cursor);
+                let target = scan.target;
+                let first = scan.cursor.entry;
+                let mut count = scan.count;
+                let mut free = scan.free;
+                let result = self.with_sector(sector, |buffer| {
+                    for index in first..ENTRIES_PER_SECTOR {
+                        let location = EntryLocation {
+                            sector: sector,
+                            entry: index,
+                        };
+                        let start = index as usize * ENTRY_SIZE;
+                        let entry = &buffer[start..start + ENTRY_SIZE];
+                        if entry[0] == ENTRY_END {
+                            free = free.or(Some(location));
+                            return ScanResult::End;
+                        } else if entry[0] == ENTRY_FREE {
+                            free = free.or(Some(location));
+                            continue;
+                        } else if entry[11] & ATTR_LONG_NAME == ATTR_LONG_NAME
+                            || entry[11] & ATTR_VOLUME_ID != 0
+                        {
+                            continue;
+                        }
+                        let found = match target {
+                            Target::Name(name) => entry[..11] == name,
+                            Target::Index(wanted) => {
+                                if entry[0] == b'.' {
+                                    continue;
+                                }
+                                count += 1;
+                                count == wanted + 1
+                            }
+                        };
+                        if found {
+                            let mut copy = [0; ENTRY_SIZE];
+                            copy.copy_from_slice(entry);
+                            return ScanResult::Found(location, copy);
+                        }
+                    }
+                    ScanResult::Continue
+                })?;
+                scan.count = count;
+                scan.free = free;
+                scan.sector_done = true;
+                match result {
+                    ScanResult::Found(location, entry) => return Ok(Some((location, entry))),
+                    ScanResult::End => return Ok(None),
+                    ScanResult::Continue => {}
+                }
+            }
+            match self.next_sector(&volume, scan.cursor)? {
+                Some(cursor) => {
+                    scan.cursor = cursor;
+                    scan.sector_done = false;
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn cursor_sector(&self, volume: &Volume, cursor: Cursor) -> u32 {
+        if cursor.cluster == 0 {
+            volume.root_start + cursor.sector
+        } else {
+            volume.cluster_sector(cursor.cluster) + cursor.sector
+        }
+    }
+
+    /// The start of the directory sector after the one at `cursor`, or None
+    /// at the end of the directory.
+    fn next_sector(&self, volume: &Volume, cursor: Cursor) -> Poll<Option<Cursor>> {
+        let sectors = if cursor.cluster == 0 {
+            volume.root_sectors
+        } else {
+            volume.sectors_per_cluster
+        };
+        if cursor.sector + 1 < sectors {
+            return Ok(Some(Cursor {
+                cluster: cursor.cluster,
+                sector: cursor.sector + 1,
+                entry: 0,
+            }));
+        }
+        if cursor.cluster == 0 {
+            return Ok(None);
+        }
+        let next = self.read_fat(cursor.cluster)?;
+        if volume.is_cluster(next) {
+            Ok(Some(Cursor {
+                cluster: next,
+                sector: 0,
+                entry: 0,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Allocate a free cluster, mark it as the end of a chain, and link it
+    /// after `previous` unless that is 0. `zero` clears the cluster.
+    ///
+    /// The caller has to call this again with the same arguments until it
+    /// returns the cluster, without using other clusters in between.
+    fn allocate(&self, previous: u32, zero: bool) -> Poll<u32> {
+        let volume = self.volume()?;
+        let mut allocation = self.allocation.get().unwrap_or(Allocation {
+            stage: AllocationStage::Search,
+            candidate: self.next_free.get(),
+            searched: 0,
+        });
+        let result = self.allocate_step(&volume, &mut allocation, previous, zero);
+        self.allocation.set(match result {
+            Err(Pending::Wait) => Some(allocation),
+            _ => None,
+        });
+        result
+    }
+
+    fn allocate_step(
+        &self,
+        volume: &Volume,
+        allocation: &mut Allocation,
+        previous: u32,
+        zero: bool,
+    ) -> Poll<u32> {
+        loop {
+            match allocation.stage {
+                AllocationStage::Search => {
+                    if allocation.searched >= volume.clusters {
+                        return Err(Pending::Error(ReturnCode::ENOMEM));
+                    }
+                    if !volume.is_cluster(allocation.candidate) {
+                        allocation.candidate = 2;
+                    }
+                    if self.read_fat(allocation.candidate)? == 0 {
+                        allocation.stage = AllocationStage::Mark;
+                    } else {
+                        allocation.candidate += 1;
+                        allocation.searched += 1;
+                    }
+                }
+                AllocationStage::Mark => {
+                    self.write_fat(allocation.candidate, volume.end_of_chain())?;
+                    allocation.stage = AllocationStage::Link;
+                }
+                AllocationStage::Link => {
+                    if previous != 0 {
+                        self.write_fat(previous, allocation.candidate)?;
+                    }
+                    allocation.stage = AllocationStage::Zero(0);
+                }
+                AllocationStage::Zero(sector) => {
+                    if !zero || sector == volume.sectors_per_cluster {
+                        self.next_free.set(allocation.candidate + 1);
+                        return Ok(allocation.candidate);
+                    }
+                    let first = volume.cluster_sector(allocation.candidate);
+                    self.new_sector(first + sector, |_| {})?;
+                    allocation.stage = AllocationStage::Zero(sector + 1);
+                }
+            }
+        }
+    }
+
+    /// The FAT entry of `cluster`, from the first FAT.
+    fn read_fat(&self, cluster: u32) -> Poll<u32> {
+        let volume = self.volume()?;
+        let (offset, _) = volume.fat_entry(cluster);
+        let (sector, start) = volume.fat_byte(0, offset);
+        match volume.fat_type {
+            FatType::Fat12 => {
+                // The two bytes of the entry can be in different sectors.
+                let first = match self.fat12_first_byte.get() {
+                    Some((first_offset, byte)) if first_offset == offset => byte,
+                    _ => {
+                        let byte = self.with_sector(sector, |buffer| buffer[start])?;
+                        self.fat12_first_byte.set(Some((offset, byte)));
+                        byte
+                    }
+                };
+                let (sector, start) = volume.fat_byte(0, offset + 1);
+                let second = self.with_sector(sector, |buffer| buffer[start])?;
+                self.fat12_first_byte.set(None);
+                let value = u16::from_le_bytes([first, second]) as u32;
+                if cluster & 1 == 1 {
+                    Ok(value >> 4)
+                } else {
+                    Ok(value & 0xfff)
+                }
+            }
+            FatType::Fat16 => self.with_sector(sector, |buffer| u16_at(buffer, start) as u32),
+            FatType::Fat32 => {
+                self.with_sector(sector, |buffer| u32_at(buffer, start) & 0x0fff_ffff)
+            }
+        }
+    }
+
+    /// Set the FAT entry of `cluster` in every FAT.
+    ///
+    /// The caller has to call this again with the same arguments until it
+    /// succeeds, without changing other FAT entries in between.
+    fn write_fat(&self, cluster: u32, value: u32) -> Poll<()> {
+        let volume = self.volume()?;
+        let (offset, len) = volume.fat_entry(cluster);
+        loop {
+            let progress = self.fat_progress.get();
+            if progress == volume.fats * len {
+                self.fat_progress.set(0);
+                return Ok(());
+            }
+            let byte = progress % len;
+            let (sector, start) = volume.fat_byte(progress / len, offset + byte);
+            self.modify_sector(sector, |buffer| {
+                buffer[start] = volume.fat_entry_byte(cluster, value, byte, buffer[start]);
+            })?;
+            self.fat_progress.set(progress + 1);
+        }
+    }
+
+    /// Call `f` with the contents of `sector`, reading it first if needed.
+    fn with_sector<R, F: FnOnce(&mut [u8]) -> R>(&self, sector: u32, f: F) -> Poll<R> {
+        if self.cached.get() == Some(sector) {
+            self.buffer
+                .map(|buffer| f(buffer))
+                .ok_or(Pending::Error(ReturnCode::FAIL))
+        } else if self.dirty.get() {
+            self.after_write.set(Some(sector));
+            Err(self.write_back())
+        } else {
+            Err(self.read_sector(sector))
+        }
+    }
+
+    /// Change `sector` with `f`, reading it first if needed.
+    fn modify_sector<R, F: FnOnce(&mut [u8]) -> R>(&self, sector: u32, f: F) -> Poll<R> {
+        let result = self.with_sector(sector, f)?;
+        self.dirty.set(true);
+        Ok(result)
+    }
+
+    /// Overwrite `sector` with zeros changed by `f`, without reading it.
+    fn new_sector<F: FnOnce(&mut [u8])>(&self, sector: u32, f: F) -> Poll<()> {
+        if self.cached.get() != Some(sector) && self.dirty.get() {
+            self.after_write.set(None);
+            return Err(self.write_back());
+        }
+        self.buffer
+            .map(|buffer| {
+                for byte in buffer.iter_mut() {
+                    *byte = 0;
+                }
+                f(buffer);
+            })
+            .ok_or(Pending::Error(ReturnCode::FAIL))?;
+        self.cached.set(Some(sector));
+        self.dirty.set(true);
+        Ok(())
+    }
+
+    /// Write the cached sector to the device if it has changed.
+    fn flush(&self) -> Poll<()> {
+        if self.dirty.get() {
+            self.after_write.set(None);
+            Err(self.write_back())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read_sector(&self, sector: u32) -> Pending {
+        self.cached.set(None);
+        self.buffer
+            .take()
+            .map_or(Pending::Error(ReturnCode::FAIL), |buffer| {
+                match self.device.read_blocks(buffer, sector as usize, 1) {
+                    Ok(()) => {
+                        self.loading.set(Some(sector));
+                        Pending::Wait
+                    }
+                    Err((error, buffer)) => {
+                        self.buffer.replace(buffer);
+                        Pending::Error(error)
+                    }
+                }
+            })
+    }
+
+    fn write_back(&self) -> Pending {
+        let sector = match self.cached.get() {
+            Some(sector) => sector,
+            None => return Pending::Error(ReturnCode::FAIL),
+        };
+        self.buffer
+            .take()
+            .map_or(Pending::Error(ReturnCode::FAIL), |buffer| {
+                match self.device.write_blocks(buffer, sector as usize, 1) {
+                    Ok(()) => Pending::Wait,
+                    Err((error, buffer)) => {
+                        self.buffer.replace(buffer);
+                        Pending::Error(error)
+                    }
+                }
+            })
+    }
+}
+
+impl<'a> BlockStorageClient for FatFilesystem<'a> {
+    fn read_done(&self, buffer: &'static mut [u8], result: ReturnCode) {
+        self.buffer.replace(buffer);
+        let sector = self.loading.take();
+        if result == ReturnCode::SUCCESS {
+            self.cached.set(sector);
+            self.run();
+        } else {
+            self.finish(Err(result));
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], result: ReturnCode) {
+        self.buffer.replace(buffer);
+        if result != ReturnCode::SUCCESS {
+            self.finish(Err(result));
+            return;
+        }
+        self.dirty.set(false);
+        match self.after_write.take() {
+            Some(sector) => match self.read_sector(sector) {
+                Pending::Wait => {}
+                Pending::Error(error) => self.finish(Err(error)),
+            },
+            None => self.run(),
+        }
+    }
+
+    fn erase_done(&self, _result: ReturnCode) {
+        // The filesystem does not erase blocks.
+    }
+}
+
+/// The first sector of the first FAT partition, if `sector` is a master
+/// boot record.
+fn partition_start(sector: &[u8]) -> Option<u32> {
+    if sector[510..512] != [0x55, 0xaa] {
+        return None;
+    }
+    (0..4)
+        .map(|partition| 446 + 16 * partition)
+        .find(|&entry| FAT_PARTITION_TYPES.contains(&sector[entry + 4]))
+        .map(|entry| u32_at(sector, entry + 8))
+}
+
+fn u16_at(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+fn u32_at(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buffer[offset],
+        buffer[offset + 1],
+        buffer[offset + 2],
+        buffer[offset + 3],
+    ])
+}
+
+fn name_of(entry: &[u8]) -> [u8; 11] {
+    let mut name = [0; 11];
+    name.copy_from_slice(&entry[..11]);
+    name
+}
+
+fn entry_mut(sector: &mut [u8], index: u32) -> &mut [u8] {
+    let start = index as usize * ENTRY_SIZE;
+    &mut sector[start..start + ENTRY_SIZE]
+}
+
+/// Fill a directory entry for a new file or directory.
+fn write_entry(entry: &mut [u8], name: &[u8; 11], attributes: u8, cluster: u32) {
+    for byte in entry.iter_mut() {
+        *byte = 0;
+    }
+    entry[..11].copy_from_slice(name);
+    entry[11] = attributes;
+    entry[16..18].copy_from_slice(&DATE.to_le_bytes());
+    entry[18..20].copy_from_slice(&DATE.to_le_bytes());
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[24..26].copy_from_slice(&DATE.to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
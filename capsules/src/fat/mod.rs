@@ -0,0 +1,9 @@
+//! FAT filesystems on block storage, and a syscall driver that gives each
+//! process its own directory.
+
+pub mod driver;
+pub mod filesystem;
+
+pub use self::driver::FatDriver;
+pub use self::driver::DRIVER_NUM;
+pub use self::filesystem::{FatFilesystem, BUFFER};
@@ -11,7 +11,7 @@
 //! example, a radio chip might be able to completely inline the frame security
 //! procedure in hardware, as opposed to requiring a software implementation.
 
-use crate::ieee802154::framer::Frame;
+use crate::ieee802154::framer::{DeviceProcedure, Frame, KeyProcedure};
 use crate::net::ieee802154::{Header, KeyId, MacAddress, PanID, SecurityLevel};
 use kernel::ReturnCode;
 
@@ -20,6 +20,11 @@ pub trait MacDevice<'a> {
     fn set_transmit_client(&self, client: &'a dyn TxClient);
     /// Sets the receive client of this MAC device
     fn set_receive_client(&self, client: &'a dyn RxClient);
+    /// Sets the procedure that looks up the keys of secured frames
+    fn set_key_procedure(&self, key_procedure: &'a dyn KeyProcedure);
+    /// Sets the procedure that looks up the extended addresses of the senders
+    /// of secured frames
+    fn set_device_procedure(&self, device_procedure: &'a dyn DeviceProcedure);
 
     /// The short 16-bit address of the MAC device
     fn get_address(&self) -> u16;
@@ -27,6 +32,8 @@ pub trait MacDevice<'a> {
     fn get_address_long(&self) -> [u8; 8];
     /// The 16-bit PAN ID of the MAC device
     fn get_pan(&self) -> u16;
+    /// The frame counter the next secured frame will be sent with
+    fn get_frame_counter(&self) -> u32;
 
     /// Set the short 16-bit address of the MAC device
     fn set_address(&self, addr: u16);
@@ -44,6 +51,12 @@ pub trait MacDevice<'a> {
     /// Returns if the MAC device is currently on.
     fn is_on(&self) -> bool;
 
+    /// Sets whether the receiver stays on while no frame is being
+    /// transmitted, which it does by default. A device that turns it off,
+    /// such as a sleepy end device, only receives frames while it turns it
+    /// on again.
+    fn set_rx_on_when_idle(&self, on: bool);
+
     /// Prepares a mutable buffer slice as an 802.15.4 frame by writing the appropriate
     /// header bytes into the buffer. This needs to be done before adding the
     /// payload because the length of the header is not fixed.
@@ -69,6 +82,21 @@ pub trait MacDevice<'a> {
         security_needed: Option<(SecurityLevel, KeyId)>,
     ) -> Result<Frame, &'static mut [u8]>;
 
+    /// Prepares a MAC command frame with the command identifier
+    /// `command_id` in the same way as `prepare_data_frame`. The content of
+    /// the command, if any, is appended to the returned frame as its
+    /// payload.
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+        security_needed: Option<(SecurityLevel, KeyId)>,
+        command_id: u8,
+    ) -> Result<Frame, &'static mut [u8]>;
+
     /// Transmits a frame that has been prepared by the above process. If the
     /// transmission process fails, the buffer inside the frame is returned so
     /// that it can be re-used.
@@ -170,8 +170,9 @@ impl FrameInfo {
                 unimplemented!()
             }
             FrameType::MACCommand => {
-                // Beginning of MAC command content field
-                unimplemented!()
+                // Beginning of MAC command content field, after the command
+                // identifier
+                self.mac_payload_offset + 1
             }
             _ => {
                 // MAC payload field, which includes payload IEs
@@ -297,6 +298,10 @@ pub struct Framer<'a, M: Mac, A: AES128CCM<'a>> {
     mac: &'a M,
     aes_ccm: &'a A,
     data_sequence: Cell<u8>,
+    /// Frame counter of the next secured frame. It is never reused with the
+    /// same key, so secured frames can no longer be sent once it reaches
+    /// 0xffffffff.
+    frame_counter: Cell<u32>,
 
     /// KeyDescriptor lookup procedure
     key_procedure: OptionalCell<&'a dyn KeyProcedure>,
@@ -322,6 +327,7 @@ impl<'a, M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
             mac: mac,
             aes_ccm: aes_ccm,
             data_sequence: Cell::new(0),
+            frame_counter: Cell::new(0),
             key_procedure: OptionalCell::empty(),
             device_procedure: OptionalCell::empty(),
             tx_state: MapCell::new(TxState::Idle),
@@ -331,16 +337,6 @@ impl<'a, M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
         }
     }
 
-    /// Sets the IEEE 802.15.4 key lookup procedure to be used.
-    pub fn set_key_procedure(&self, key_procedure: &'a dyn KeyProcedure) {
-        self.key_procedure.set(key_procedure);
-    }
-
-    /// Sets the IEEE 802.15.4 key lookup procedure to be used.
-    pub fn set_device_procedure(&self, device_procedure: &'a dyn DeviceProcedure) {
-        self.device_procedure.set(device_procedure);
-    }
-
     /// Look up the key using the IEEE 802.15.4 KeyDescriptor lookup prodecure
     /// implemented elsewhere.
     fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
@@ -357,6 +353,95 @@ impl<'a, M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
         })
     }
 
+    /// Prepares a frame of type `frame_type`, as described by
+    /// `MacDevice::prepare_data_frame`.
+    fn prepare_frame(
+        &self,
+        frame_type: FrameType,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+        security_needed: Option<(SecurityLevel, KeyId)>,
+    ) -> Result<Frame, &'static mut [u8]> {
+        // IEEE 802.15.4-2015: 9.2.1, outgoing frame security
+        // Steps a-e of the security procedure are implemented here.
+
+        // TODO: For Thread, in the case of `KeyIdMode::Source4Index`, the source
+        // address should instead be some constant defined in their
+        // specification.
+        let src_addr_long = self.get_address_long();
+        let frame_counter = self.frame_counter.get();
+        if security_needed.is_some() && frame_counter == 0xffffffff {
+            // Step d: counter error
+            return Err(buf);
+        }
+        let security_desc = security_needed.and_then(|(level, key_id)| {
+            self.lookup_key(level, key_id).map(|key| {
+                let nonce = get_ccm_nonce(&src_addr_long, frame_counter, level);
+                (
+                    Security {
+                        level: level,
+                        asn_in_nonce: false,
+                        frame_counter: Some(frame_counter),
+                        key_id: key_id,
+                    },
+                    key,
+                    nonce,
+                )
+            })
+        });
+        if security_needed.is_some() && security_desc.is_none() {
+            // If security was requested, fail when desired key was not found.
+            return Err(buf);
+        }
+
+        // Construct MAC header
+        let security = security_desc.map(|(sec, _, _)| sec);
+        let mic_len = security.map_or(0, |sec| sec.level.mic_len());
+        let header = Header {
+            frame_type: frame_type,
+            /* TODO: determine this by looking at queue, and also set it in
+             * hardware so that ACKs set this flag to the right value. */
+            frame_pending: false,
+            // Unicast frames request acknowledgement
+            ack_requested: true,
+            version: FrameVersion::V2006,
+            seq: Some(self.data_sequence.get()),
+            dst_pan: Some(dst_pan),
+            dst_addr: Some(dst_addr),
+            src_pan: Some(src_pan),
+            src_addr: Some(src_addr),
+            security: security,
+            header_ies: Default::default(),
+            header_ies_len: 0,
+            payload_ies: Default::default(),
+            payload_ies_len: 0,
+        };
+
+        match header.encode(&mut buf[radio::PSDU_OFFSET..], true).done() {
+            Some((data_offset, mac_payload_offset)) => {
+                if security.is_some() {
+                    self.frame_counter.set(frame_counter + 1);
+                }
+                Ok(Frame {
+                    buf: buf,
+                    info: FrameInfo {
+                        frame_type: frame_type,
+                        mac_payload_offset: mac_payload_offset,
+                        data_offset: data_offset,
+                        data_len: 0,
+                        mic_len: mic_len,
+                        security_params: security_desc
+                            .map(|(sec, key, nonce)| (sec.level, key, nonce)),
+                    },
+                })
+            }
+            None => Err(buf),
+        }
+    }
+
     /// IEEE 802.15.4-2015, 9.2.1, outgoing frame security procedure
     /// Performs the first checks in the security procedure. The rest of the
     /// steps are performed as part of the transmission pipeline.
@@ -656,6 +741,16 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         self.rx_client.set(client);
     }
 
+    /// Sets the IEEE 802.15.4 key lookup procedure to be used.
+    fn set_key_procedure(&self, key_procedure: &'a dyn KeyProcedure) {
+        self.key_procedure.set(key_procedure);
+    }
+
+    /// Sets the IEEE 802.15.4 device lookup procedure to be used.
+    fn set_device_procedure(&self, device_procedure: &'a dyn DeviceProcedure) {
+        self.device_procedure.set(device_procedure);
+    }
+
     fn get_address(&self) -> u16 {
         self.mac.get_address()
     }
@@ -668,6 +763,10 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         self.mac.get_pan()
     }
 
+    fn get_frame_counter(&self) -> u32 {
+        self.frame_counter.get()
+    }
+
     fn set_address(&self, addr: u16) {
         self.mac.set_address(addr)
     }
@@ -688,6 +787,10 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         self.mac.is_on()
     }
 
+    fn set_rx_on_when_idle(&self, on: bool) {
+        self.mac.set_rx_on_when_idle(on)
+    }
+
     fn prepare_data_frame(
         &self,
         buf: &'static mut [u8],
@@ -697,72 +800,40 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         src_addr: MacAddress,
         security_needed: Option<(SecurityLevel, KeyId)>,
     ) -> Result<Frame, &'static mut [u8]> {
-        // IEEE 802.15.4-2015: 9.2.1, outgoing frame security
-        // Steps a-e of the security procedure are implemented here.
-
-        // TODO: For Thread, in the case of `KeyIdMode::Source4Index`, the source
-        // address should instead be some constant defined in their
-        // specification.
-        let src_addr_long = self.get_address_long();
-        let security_desc = security_needed.and_then(|(level, key_id)| {
-            self.lookup_key(level, key_id).map(|key| {
-                // TODO: lookup frame counter for device
-                let frame_counter = 0;
-                let nonce = get_ccm_nonce(&src_addr_long, frame_counter, level);
-                (
-                    Security {
-                        level: level,
-                        asn_in_nonce: false,
-                        frame_counter: Some(frame_counter),
-                        key_id: key_id,
-                    },
-                    key,
-                    nonce,
-                )
-            })
-        });
-        if security_needed.is_some() && security_desc.is_none() {
-            // If security was requested, fail when desired key was not found.
-            return Err(buf);
-        }
-
-        // Construct MAC header
-        let security = security_desc.map(|(sec, _, _)| sec);
-        let mic_len = security.map_or(0, |sec| sec.level.mic_len());
-        let header = Header {
-            frame_type: FrameType::Data,
-            /* TODO: determine this by looking at queue, and also set it in
-             * hardware so that ACKs set this flag to the right value. */
-            frame_pending: false,
-            // Unicast data frames request acknowledgement
-            ack_requested: true,
-            version: FrameVersion::V2006,
-            seq: Some(self.data_sequence.get()),
-            dst_pan: Some(dst_pan),
-            dst_addr: Some(dst_addr),
-            src_pan: Some(src_pan),
-            src_addr: Some(src_addr),
-            security: security,
-            header_ies: Default::default(),
-            header_ies_len: 0,
-            payload_ies: Default::default(),
-            payload_ies_len: 0,
-        };
-
-        match header.encode(&mut buf[radio::PSDU_OFFSET..], true).done() {
-            Some((data_offset, mac_payload_offset)) => Ok(Frame {
-                buf: buf,
-                info: FrameInfo {
-                    frame_type: FrameType::Data,
-                    mac_payload_offset: mac_payload_offset,
-                    data_offset: data_offset,
-                    data_len: 0,
-                    mic_len: mic_len,
-                    security_params: security_desc.map(|(sec, key, nonce)| (sec.level, key, nonce)),
-                },
-            }),
-            None => Err(buf),
+        self.prepare_frame(
+            FrameType::Data,
+            buf,
+            dst_pan,
+            dst_addr,
+            src_pan,
+            src_addr,
+            security_needed,
+        )
+    }
+
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+        security_needed: Option<(SecurityLevel, KeyId)>,
+        command_id: u8,
+    ) -> Result<Frame, &'static mut [u8]> {
+        let mut frame = self.prepare_frame(
+            FrameType::MACCommand,
+            buf,
+            dst_pan,
+            dst_addr,
+            src_pan,
+            src_addr,
+            security_needed,
+        )?;
+        if frame.append_payload(&[command_id]) != ReturnCode::SUCCESS {
+            return Err(frame.into_buf());
         }
+        Ok(frame)
     }
 
     fn transmit(&self, frame: Frame) -> (ReturnCode, Option<&'static mut [u8]>) {
@@ -7,10 +7,13 @@
 //!
 //! AwakeMac provides a default implementation of such a layer, maintaining
 //! the underlying kernel::hil::radio::Radio powered at all times and passing
-//! through each frame for transmission.
+//! through each frame for transmission. If its receiver is turned off when
+//! idle, it instead powers the radio down whenever no frame is being
+//! transmitted, and up again to transmit the next one.
 
 use crate::net::ieee802154::{Header, MacAddress};
-use kernel::common::cells::OptionalCell;
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::debug;
 use kernel::hil::radio;
 use kernel::ReturnCode;
@@ -53,6 +56,10 @@ pub trait Mac {
     /// Indicates whether or not the MAC protocol is active and can send frames
     fn is_on(&self) -> bool;
 
+    /// Sets whether the receiver stays on while no frame is being
+    /// transmitted. See `MacDevice::set_rx_on_when_idle`.
+    fn set_rx_on_when_idle(&self, on: bool);
+
     /// Transmits complete MAC frames, which must be prepared by an ieee802154::device::MacDevice
     /// before being passed to the Mac layer. Returns the frame buffer in case of an error.
     fn transmit(
@@ -65,10 +72,17 @@ pub trait Mac {
 ///
 /// Default implementation of a Mac layer. Acts as a pass-through between a MacDevice
 /// implementation and the underlying radio::Radio device. Does not change the power
-/// state of the radio during operation.
+/// state of the radio during operation, unless the receiver is turned off when idle.
+/// The radio must then notify the AwakeMac, as its `PowerClient`, when it has
+/// started.
 ///
 pub struct AwakeMac<'a, R: radio::Radio> {
     radio: &'a R,
+    rx_on_when_idle: Cell<bool>,
+    transmitting: Cell<bool>,
+    /// A frame waiting for the radio to start, and its length
+    tx_pending: TakeCell<'static, [u8]>,
+    tx_pending_len: Cell<usize>,
 
     tx_client: OptionalCell<&'static dyn radio::TxClient>,
     rx_client: OptionalCell<&'static dyn radio::RxClient>,
@@ -78,10 +92,38 @@ impl<'a, R: radio::Radio> AwakeMac<'a, R> {
     pub fn new(radio: &'a R) -> AwakeMac<'a, R> {
         AwakeMac {
             radio: radio,
+            rx_on_when_idle: Cell::new(true),
+            transmitting: Cell::new(false),
+            tx_pending: TakeCell::empty(),
+            tx_pending_len: Cell::new(0),
             tx_client: OptionalCell::empty(),
             rx_client: OptionalCell::empty(),
         }
     }
+
+    fn transmit_now(
+        &self,
+        full_mac_frame: &'static mut [u8],
+        frame_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let (result, buf) = self.radio.transmit(full_mac_frame, frame_len);
+        if result == ReturnCode::SUCCESS {
+            self.transmitting.set(true);
+        }
+        (result, buf)
+    }
+
+    /// Powers the radio down if the receiver is off when idle and no frame
+    /// is being transmitted.
+    fn sleep_if_idle(&self) {
+        if !self.rx_on_when_idle.get()
+            && !self.transmitting.get()
+            && self.tx_pending.is_none()
+            && self.radio.is_on()
+        {
+            self.radio.stop();
+        }
+    }
 }
 
 impl<R: radio::Radio> Mac for AwakeMac<'_, R> {
@@ -94,6 +136,17 @@ impl<R: radio::Radio> Mac for AwakeMac<'_, R> {
         self.radio.is_on()
     }
 
+    fn set_rx_on_when_idle(&self, on: bool) {
+        self.rx_on_when_idle.set(on);
+        if on {
+            if !self.radio.is_on() {
+                self.radio.start();
+            }
+        } else {
+            self.sleep_if_idle();
+        }
+    }
+
     fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
         self.radio.set_config_client(client)
     }
@@ -143,18 +196,50 @@ impl<R: radio::Radio> Mac for AwakeMac<'_, R> {
         full_mac_frame: &'static mut [u8],
         frame_len: usize,
     ) -> (ReturnCode, Option<&'static mut [u8]>) {
-        self.radio.transmit(full_mac_frame, frame_len)
+        if self.radio.is_on() {
+            return self.transmit_now(full_mac_frame, frame_len);
+        }
+        if self.tx_pending.is_some() {
+            return (ReturnCode::EBUSY, Some(full_mac_frame));
+        }
+        // The frame is transmitted once the radio has started
+        self.tx_pending.replace(full_mac_frame);
+        self.tx_pending_len.set(frame_len);
+        let result = self.radio.start();
+        if result != ReturnCode::SUCCESS {
+            return (result, self.tx_pending.take());
+        }
+        (ReturnCode::SUCCESS, None)
     }
 }
 
 impl<R: radio::Radio> radio::TxClient for AwakeMac<'_, R> {
     fn send_done(&self, buf: &'static mut [u8], acked: bool, result: ReturnCode) {
+        self.transmitting.set(false);
+        self.sleep_if_idle();
         self.tx_client.map(move |c| {
             c.send_done(buf, acked, result);
         });
     }
 }
 
+impl<R: radio::Radio> radio::PowerClient for AwakeMac<'_, R> {
+    fn changed(&self, on: bool) {
+        if !on {
+            return;
+        }
+        self.tx_pending.take().map(|buf| {
+            let (result, buf) = self.transmit_now(buf, self.tx_pending_len.get());
+            if result != ReturnCode::SUCCESS {
+                buf.map(|buf| {
+                    self.tx_client.map(move |c| c.send_done(buf, false, result));
+                });
+            }
+        });
+        self.sleep_if_idle();
+    }
+}
+
 impl<R: radio::Radio> radio::RxClient for AwakeMac<'_, R> {
     fn receive(
         &self,
@@ -236,6 +236,14 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
         self.rx_client.set(Some(client));
     }
 
+    fn set_key_procedure(&self, key_procedure: &'a dyn framer::KeyProcedure) {
+        self.mux.mac.set_key_procedure(key_procedure)
+    }
+
+    fn set_device_procedure(&self, device_procedure: &'a dyn framer::DeviceProcedure) {
+        self.mux.mac.set_device_procedure(device_procedure)
+    }
+
     fn get_address(&self) -> u16 {
         self.mux.mac.get_address()
     }
@@ -248,6 +256,10 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
         self.mux.mac.get_pan()
     }
 
+    fn get_frame_counter(&self) -> u32 {
+        self.mux.mac.get_frame_counter()
+    }
+
     fn set_address(&self, addr: u16) {
         self.mux.mac.set_address(addr)
     }
@@ -268,6 +280,10 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
         self.mux.mac.is_on()
     }
 
+    fn set_rx_on_when_idle(&self, on: bool) {
+        self.mux.mac.set_rx_on_when_idle(on)
+    }
+
     fn prepare_data_frame(
         &self,
         buf: &'static mut [u8],
@@ -282,6 +298,27 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
             .prepare_data_frame(buf, dst_pan, dst_addr, src_pan, src_addr, security_needed)
     }
 
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+        security_needed: Option<(SecurityLevel, KeyId)>,
+        command_id: u8,
+    ) -> Result<framer::Frame, &'static mut [u8]> {
+        self.mux.mac.prepare_command_frame(
+            buf,
+            dst_pan,
+            dst_addr,
+            src_pan,
+            src_addr,
+            security_needed,
+            command_id,
+        )
+    }
+
     fn transmit(&self, frame: framer::Frame) -> (ReturnCode, Option<&'static mut [u8]>) {
         // If the muxer is idle, immediately transmit the frame, otherwise
         // attempt to queue the transmission request. However, each MAC user can
@@ -356,6 +356,10 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> Mac for XMac<'a, R, A> {
         self.radio.is_on()
     }
 
+    // X-MAC already sleeps when idle and wakes up periodically to listen for
+    // the preambles that precede frames sent to this node.
+    fn set_rx_on_when_idle(&self, _on: bool) {}
+
     fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
         self.radio.set_config_client(client)
     }
@@ -0,0 +1,995 @@
+//! Implements a persistent key-value store in flash.
+//!
+//! The store keeps values in a statically allocated storage volume made up
+//! of flash pages. Each page in use starts with a header, followed by
+//! records that each hold a key and its value:
+//!
+//! ```text
+//! page:   | magic | seq | replaces | used | checksum | record | record | ...
+//! record: | seq | key length | value length | key | value | padding |
+//! ```
+//!
+//! Pages are never modified in place. To add, replace or remove a value, the
+//! records of a page are copied, with the change applied, to a free page,
+//! which is written with a single page write. Only then is the original page
+//! erased. The header of the new page names the page it replaces (by
+//! sequence number) and carries a checksum of the header and records, so
+//! that if power fails:
+//!
+//! * before the new page is completely written, its checksum is invalid and
+//!   the original page is still used.
+//! * after the new page is written but before the original page is erased,
+//!   the original page is ignored because another page replaces it, and it
+//!   is erased before the next write.
+//!
+//! Records carry a sequence number as well. If the same key is found in more
+//! than one record, the record with the highest sequence number holds the
+//! value. Older records of a key are removed after a new value is written.
+//!
+//! Removing a key takes more than one page write if its records are spread
+//! over several pages, so a key is removed by first writing a tombstone: a
+//! record without a value (its value length is 0xFFFF). The older records of
+//! the key are then removed page by page, and the tombstone last. If power
+//! fails in between, the tombstone hides the records that are left.
+//!
+//! Free pages are used in turn, so erases are spread evenly over the volume.
+//! When the store runs out of space, sparsely used pages are merged to
+//! reclaim space (garbage collection). One free page is always kept in
+//! reserve so that values can be replaced and removed in a full store.
+//!
+//! Values are read directly from the memory-mapped storage volume, so the
+//! volume must be aligned to flash pages.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//!     storage_volume!(KV_VOLUME, 8);
+//!     static mut PAGEBUFFER: sam4l::flashcalw::Sam4lPage = sam4l::flashcalw::Sam4lPage::new();
+//!
+//!     let kv_store = static_init!(
+//!         capsules::kv_store::FlashKVStore<'static, sam4l::flashcalw::FLASHCALW>,
+//!         capsules::kv_store::FlashKVStore::new(
+//!             &KV_VOLUME,
+//!             &mut sam4l::flashcalw::FLASH_CONTROLLER,
+//!             &mut PAGEBUFFER,
+//!             dynamic_deferred_caller
+//!         )
+//!     );
+//!     kernel::hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, kv_store);
+//!     kv_store.initialize_callback_handle(
+//!         dynamic_deferred_caller
+//!             .register(kv_store)
+//!             .expect("no deferred call slot available for kv store"),
+//!     );
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::convert::TryInto;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::flash::{self, Flash};
+use kernel::hil::kv_store::{KVStore, KVStoreClient};
+use kernel::ReturnCode;
+
+/// Marks pages that belong to the store ("KVS1").
+const PAGE_MAGIC: u32 = 0x4b56_5331;
+/// Page header: magic, sequence number, sequence number of the replaced
+/// page, length of the records and checksum.
+const PAGE_HEADER_SIZE: usize = 20;
+/// Record header: sequence number, key length and value length.
+const RECORD_HEADER_SIZE: usize = 8;
+/// Sequence number used for "none".
+const NO_SEQ: u32 = 0xFFFF_FFFF;
+/// Value length that marks a tombstone.
+const TOMBSTONE: u16 = 0xFFFF;
+
+/// Maximum length of a key in bytes.
+pub const MAX_KEY_LEN: usize = 64;
+/// Maximum number of pages used by a store. Pages of larger volumes are
+/// ignored.
+pub const MAX_PAGES: usize = 32;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Get,
+    Set,
+    Delete,
+    GarbageCollect,
+}
+
+/// What the store knows about one page of its volume.
+#[derive(Clone, Copy, Default)]
+struct PageInfo {
+    /// The page holds records.
+    live: bool,
+    /// The page is no longer used but has a valid header, and must be erased
+    /// before other pages are written.
+    dirty: bool,
+    /// The page is erased and can be written without erasing it first.
+    erased: bool,
+    seq: u32,
+    /// Length of the records in the page.
+    used: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Record<'b> {
+    seq: u32,
+    key: &'b [u8],
+    /// The value, or `None` for a tombstone.
+    value: Option<&'b [u8]>,
+}
+
+impl Record<'_> {
+    fn size(&self) -> usize {
+        record_size(self.key.len(), self.value.map_or(0, |value| value.len()))
+    }
+}
+
+/// Iterates over the records in the record area of a page.
+struct Records<'b> {
+    data: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Iterator for Records<'b> {
+    type Item = Record<'b>;
+
+    fn next(&mut self) -> Option<Record<'b>> {
+        let header = self.data.get(self.pos..self.pos + RECORD_HEADER_SIZE)?;
+        let key_len = read_u16(header, 4) as usize;
+        let value_len = read_u16(header, 6);
+        let key_start = self.pos + RECORD_HEADER_SIZE;
+        let value_start = key_start + key_len;
+        let value = if value_len == TOMBSTONE {
+            None
+        } else {
+            Some(
+                self.data
+                    .get(value_start..value_start + value_len as usize)?,
+            )
+        };
+        let record = Record {
+            seq: read_u32(header, 0),
+            key: self.data.get(key_start..value_start)?,
+            value: value,
+        };
+        self.pos += record.size();
+        Some(record)
+    }
+}
+
+/// A change to the store, made by copying the records of up to two pages
+/// into a free page. The source pages are erased afterwards.
+#[derive(Clone, Copy)]
+struct Plan {
+    sources: [Option<usize>; 2],
+    /// Drop records of the current key, except the one written by the
+    /// current `set()` or `delete()`.
+    drop_key: bool,
+    /// Add a record with the value of the current `set()`, or the tombstone
+    /// of the current `delete()`.
+    append: bool,
+    /// Only keep records that are still needed (see `is_needed()`).
+    current_only: bool,
+}
+
+fn record_size(key_len: usize, value_len: usize) -> usize {
+    (RECORD_HEADER_SIZE + key_len + value_len + 3) & !3
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// CRC-32 (IEEE 802.3) of `data`, continuing from the CRC `crc` of preceding
+/// data (0 for none).
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Write a record at `offset` in the record area `records` and return its
+/// size. A `value` of `None` writes a tombstone.
+fn write_record(
+    records: &mut [u8],
+    offset: usize,
+    seq: u32,
+    key: &[u8],
+    value: Option<&[u8]>,
+) -> usize {
+    let value_len = value.map_or(TOMBSTONE, |value| value.len() as u16);
+    let value = value.unwrap_or(&[]);
+    write_u32(records, offset, seq);
+    records[offset + 4..offset + 6].copy_from_slice(&(key.len() as u16).to_le_bytes());
+    records[offset + 6..offset + 8].copy_from_slice(&value_len.to_le_bytes());
+    let key_start = offset + RECORD_HEADER_SIZE;
+    records[key_start..key_start + key.len()].copy_from_slice(key);
+    records[key_start + key.len()..key_start + key.len() + value.len()].copy_from_slice(value);
+    record_size(key.len(), value.len())
+}
+
+pub struct FlashKVStore<'a, F: Flash + 'static> {
+    /// Underlying storage volume.
+    volume: &'static [u8],
+    /// Flash interface.
+    driver: &'a F,
+    /// Buffer for a flash page.
+    pagebuffer: TakeCell<'static, F::Page>,
+    /// Size of a flash page.
+    page_size: usize,
+    /// Number of pages used by the store.
+    num_pages: usize,
+    pages: [Cell<PageInfo>; MAX_PAGES],
+    client: OptionalCell<&'a dyn KVStoreClient>,
+
+    /// Current operation being executed.
+    state: Cell<State>,
+    /// Sequence number of the next page written.
+    next_page_seq: Cell<u32>,
+    /// Sequence number of the next record written.
+    next_record_seq: Cell<u32>,
+    /// Page to start looking for a free page at, so that writes are spread
+    /// over the volume.
+    next_free_page: Cell<usize>,
+    /// Page being written, once it has been erased.
+    pending_write: OptionalCell<usize>,
+    /// Pages that are replaced by the page being written.
+    pending_sources: Cell<[Option<usize>; 2]>,
+
+    /// Deferred caller for deferring client callbacks.
+    deferred_caller: &'a DynamicDeferredCall,
+    /// Handle for deferred caller.
+    handle: OptionalCell<DeferredCallHandle>,
+
+    // Note: for saving state across stack ripping.
+    /// Key of the current operation.
+    key: Cell<[u8; MAX_KEY_LEN]>,
+    key_len: Cell<usize>,
+    /// Sequence number of the record written by the current `set()` or
+    /// `delete()`.
+    record_seq: Cell<u32>,
+    /// Client-provided buffer to read into or write from.
+    buffer: TakeCell<'static, [u8]>,
+    /// Length of the value in buffer.
+    length: Cell<usize>,
+    /// Whether the key of the current `delete()` was found.
+    found: Cell<bool>,
+    /// Error returned by previously executed operation (or SUCCESS).
+    error: Cell<ReturnCode>,
+}
+
+impl<'a, F: Flash + 'static> FlashKVStore<'a, F> {
+    pub fn new(
+        volume: &'static [u8],
+        driver: &'a F,
+        pagebuffer: &'static mut F::Page,
+        deferred_caller: &'a DynamicDeferredCall,
+    ) -> FlashKVStore<'a, F> {
+        let page_size = pagebuffer.as_mut().len();
+
+        let kv_store: FlashKVStore<'a, F> = FlashKVStore {
+            volume,
+            driver,
+            pagebuffer: TakeCell::new(pagebuffer),
+            page_size,
+            num_pages: cmp::min(volume.len() / page_size, MAX_PAGES),
+            pages: Default::default(),
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            next_page_seq: Cell::new(0),
+            next_record_seq: Cell::new(0),
+            next_free_page: Cell::new(0),
+            pending_write: OptionalCell::empty(),
+            pending_sources: Cell::new([None, None]),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+            key: Cell::new([0; MAX_KEY_LEN]),
+            key_len: Cell::new(0),
+            record_seq: Cell::new(NO_SEQ),
+            buffer: TakeCell::empty(),
+            length: Cell::new(0),
+            found: Cell::new(false),
+            error: Cell::new(ReturnCode::ENODEVICE),
+        };
+
+        kv_store.mount();
+        kv_store
+    }
+
+    /// Initializes a callback handle for deferred callbacks.
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// Reconstructs the state of the store from the storage volume.
+    fn mount(&self) {
+        let mut valid = [None; MAX_PAGES];
+        for (index, page_valid) in valid.iter_mut().enumerate().take(self.num_pages) {
+            let page = self.page(index);
+            *page_valid = self.read_header(page);
+            if page_valid.is_none() {
+                self.pages[index].set(PageInfo {
+                    erased: page.iter().all(|byte| *byte == 0xff),
+                    ..PageInfo::default()
+                });
+            }
+        }
+
+        let mut max_page_seq = None;
+        for index in 0..self.num_pages {
+            if let Some((seq, _, used)) = valid[index] {
+                // A page is stale if power failed after a page replacing it
+                // was written, but before it was erased.
+                let stale = valid
+                    .iter()
+                    .any(|other| other.map_or(false, |(_, replaces, _)| replaces == seq));
+                self.pages[index].set(PageInfo {
+                    live: !stale,
+                    dirty: stale,
+                    erased: false,
+                    seq: seq,
+                    used: used,
+                });
+                max_page_seq = cmp::max(max_page_seq, Some(seq));
+            }
+        }
+
+        let mut max_record_seq = None;
+        for index in 0..self.num_pages {
+            if self.pages[index].get().live {
+                for record in self.records(index) {
+                    max_record_seq = cmp::max(max_record_seq, Some(record.seq));
+                }
+            }
+        }
+
+        self.next_page_seq
+            .set(max_page_seq.map_or(0, |seq| seq + 1));
+        self.next_record_seq
+            .set(max_record_seq.map_or(0, |seq| seq + 1));
+    }
+
+    /// Returns the contents of a page of the volume.
+    fn page(&self, index: usize) -> &'static [u8] {
+        &self.volume[index * self.page_size..(index + 1) * self.page_size]
+    }
+
+    /// Returns the flash page number of a page of the volume.
+    fn page_number(&self, index: usize) -> usize {
+        self.volume.as_ptr() as usize / self.page_size + index
+    }
+
+    /// Returns the sequence number, replaced sequence number and length of
+    /// the records of a page, if it has a valid header.
+    fn read_header(&self, page: &[u8]) -> Option<(u32, u32, usize)> {
+        if read_u32(page, 0) != PAGE_MAGIC {
+            return None;
+        }
+        let used = read_u32(page, 12) as usize;
+        if used > self.page_size - PAGE_HEADER_SIZE {
+            return None;
+        }
+        let checksum = crc32(
+            crc32(0, &page[..16]),
+            &page[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + used],
+        );
+        if checksum != read_u32(page, 16) {
+            return None;
+        }
+        Some((read_u32(page, 4), read_u32(page, 8), used))
+    }
+
+    /// Returns the records of a live page.
+    fn records(&self, index: usize) -> Records<'static> {
+        let used = self.pages[index].get().used;
+        Records {
+            data: &self.page(index)[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + used],
+            pos: 0,
+        }
+    }
+
+    fn live_pages(&self) -> impl Iterator<Item = usize> + '_ {
+        let pages = &self.pages[..self.num_pages];
+        (0..pages.len()).filter(move |index| pages[*index].get().live)
+    }
+
+    fn free_pages(&self) -> usize {
+        self.num_pages - self.live_pages().count()
+    }
+
+    /// Returns the newest record of `key`, which may be a tombstone, and its
+    /// page.
+    fn find(&self, key: &[u8]) -> Option<(usize, Record<'static>)> {
+        let mut found: Option<(usize, Record<'static>)> = None;
+        for index in self.live_pages() {
+            for record in self.records(index) {
+                if record.key == key && found.map_or(true, |(_, other)| record.seq > other.seq) {
+                    found = Some((index, record));
+                }
+            }
+        }
+        found
+    }
+
+    /// Returns the current value of `key`.
+    fn lookup(&self, key: &[u8]) -> Option<&'static [u8]> {
+        self.find(key).and_then(|(_, record)| record.value)
+    }
+
+    fn is_current(&self, record: &Record) -> bool {
+        self.find(record.key)
+            .map_or(false, |(_, current)| current.seq == record.seq)
+    }
+
+    /// Whether a record has to be kept: it holds the current value of its
+    /// key, or it is a tombstone hiding older records of its key.
+    fn is_needed(&self, record: &Record) -> bool {
+        self.is_current(record)
+            && (record.value.is_some()
+                || self.live_pages().any(|index| {
+                    self.records(index)
+                        .any(|other| other.key == record.key && other.seq != record.seq)
+                }))
+    }
+
+    /// Space taken by the records in a page that are still needed.
+    fn current_size(&self, index: usize) -> usize {
+        self.records(index)
+            .filter(|record| self.is_needed(record))
+            .map(|record| record.size())
+            .sum()
+    }
+
+    fn store_key(&self, key: &[u8]) {
+        let mut buf = [0; MAX_KEY_LEN];
+        buf[..key.len()].copy_from_slice(key);
+        self.key.set(buf);
+        self.key_len.set(key.len());
+    }
+
+    fn valid_key(&self, key: &[u8]) -> bool {
+        !key.is_empty() && key.len() <= MAX_KEY_LEN
+    }
+
+    /// Plans the next step of a `set()`.
+    fn plan_set(&self) -> Result<Option<Plan>, ReturnCode> {
+        self.plan_write(record_size(self.key_len.get(), self.length.get()))
+    }
+
+    /// Plans the next step of a `delete()`.
+    fn plan_delete(&self) -> Result<Option<Plan>, ReturnCode> {
+        let key_buf = self.key.get();
+        let key = &key_buf[..self.key_len.get()];
+        let seq = self.record_seq.get();
+        let deleted = self.find(key).map_or(true, |(_, record)| {
+            record.value.is_none() && record.seq != seq
+        });
+        if deleted {
+            Ok(None)
+        } else {
+            self.plan_write(record_size(key.len(), 0))
+        }
+    }
+
+    /// Plans the next step of writing the record of the current `set()` or
+    /// `delete()`, which takes `size` bytes, and of removing the older
+    /// records of its key afterwards.
+    fn plan_write(&self, size: usize) -> Result<Option<Plan>, ReturnCode> {
+        let key_buf = self.key.get();
+        let key = &key_buf[..self.key_len.get()];
+        let seq = self.record_seq.get();
+        let capacity = self.page_size - PAGE_HEADER_SIZE;
+
+        let existing = self.find(key);
+        if let Some((index, record)) = existing {
+            if record.seq == seq {
+                // The record has been written, remove older records.
+                let outdated = self.live_pages().find(|index| {
+                    self.records(*index)
+                        .any(|record| record.key == key && record.seq != seq)
+                });
+                if let Some(outdated) = outdated {
+                    return Ok(Some(Plan {
+                        sources: [Some(outdated), None],
+                        drop_key: true,
+                        append: false,
+                        current_only: false,
+                    }));
+                }
+                if record.value.is_some() {
+                    return Ok(None);
+                }
+                // Nothing is left for the tombstone to hide, so it can be
+                // removed as well.
+                return Ok(Some(Plan {
+                    sources: [Some(index), None],
+                    drop_key: false,
+                    append: false,
+                    current_only: true,
+                }));
+            }
+        }
+
+        let update = |index| Plan {
+            sources: [Some(index), None],
+            drop_key: true,
+            append: true,
+            current_only: false,
+        };
+        if self.free_pages() >= 1 {
+            // Prefer replacing the old value in the same page.
+            if let Some((index, record)) = existing {
+                if self.pages[index].get().used - record.size() + size <= capacity {
+                    return Ok(Some(update(index)));
+                }
+            }
+            if let Some(index) = self
+                .live_pages()
+                .find(|index| self.pages[*index].get().used + size <= capacity)
+            {
+                return Ok(Some(update(index)));
+            }
+        }
+        // Start a new page, as long as another free page remains.
+        if self.free_pages() >= 2 {
+            return Ok(Some(Plan {
+                sources: [None, None],
+                drop_key: false,
+                append: true,
+                current_only: false,
+            }));
+        }
+        // Reclaim space and try again.
+        self.plan_garbage_collection()
+            .map_or(Err(ReturnCode::ENOMEM), |plan| Ok(Some(plan)))
+    }
+
+    /// Plans the next step of garbage collection: removing a page without
+    /// needed records, or merging two pages whose needed records fit in one.
+    fn plan_garbage_collection(&self) -> Option<Plan> {
+        let capacity = self.page_size - PAGE_HEADER_SIZE;
+        let mut sizes = [0; MAX_PAGES];
+        for index in self.live_pages() {
+            sizes[index] = self.current_size(index);
+        }
+
+        let plan = |sources| Plan {
+            sources: sources,
+            drop_key: false,
+            append: false,
+            current_only: true,
+        };
+        if let Some(index) = self.live_pages().find(|index| sizes[*index] == 0) {
+            return Some(plan([Some(index), None]));
+        }
+        if self.free_pages() == 0 {
+            return None;
+        }
+        for first in self.live_pages() {
+            for second in self.live_pages().filter(|second| *second > first) {
+                if sizes[first] + sizes[second] <= capacity {
+                    return Some(plan([Some(first), Some(second)]));
+                }
+            }
+        }
+        None
+    }
+
+    /// Performs the next step of the current operation, or finishes it.
+    fn advance(&self) {
+        // Erase pages that are no longer used first, so that the pages they
+        // replaced cannot be mistaken for live pages after a reboot.
+        if let Some(index) = (0..self.num_pages).find(|index| self.pages[*index].get().dirty) {
+            let result = self.driver.erase_page(self.page_number(index));
+            if result != ReturnCode::SUCCESS {
+                self.finish(result);
+            }
+            return;
+        }
+
+        let plan = match self.state.get() {
+            State::Set => self.plan_set(),
+            State::Delete => self.plan_delete(),
+            State::GarbageCollect => Ok(self.plan_garbage_collection()),
+            State::Idle | State::Get => return,
+        };
+        match plan {
+            Ok(Some(plan)) => {
+                let result = self.execute(plan);
+                if result != ReturnCode::SUCCESS {
+                    self.finish(result);
+                }
+            }
+            Ok(None) => {
+                if self.state.get() == State::Delete && !self.found.get() {
+                    self.finish(ReturnCode::FAIL);
+                } else {
+                    self.finish(ReturnCode::SUCCESS);
+                }
+            }
+            Err(error) => self.finish(error),
+        }
+    }
+
+    /// Builds the page described by `plan` in the pagebuffer and starts
+    /// writing it to a free page.
+    fn execute(&self, plan: Plan) -> ReturnCode {
+        let key_buf = self.key.get();
+        let key = &key_buf[..self.key_len.get()];
+        let seq = self.record_seq.get();
+
+        let pagebuffer = match self.pagebuffer.take() {
+            Some(pagebuffer) => pagebuffer,
+            None => return ReturnCode::ERESERVE,
+        };
+        let page = pagebuffer.as_mut();
+        for byte in page.iter_mut() {
+            *byte = 0xff;
+        }
+
+        let mut used = 0;
+        for source in plan.sources.iter().flatten() {
+            for record in self.records(*source) {
+                let keep = if plan.current_only {
+                    // Records of a key may have the same sequence number if
+                    // power failed while merging pages.
+                    self.is_needed(&record)
+                        && !Records {
+                            data: &page[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + used],
+                            pos: 0,
+                        }
+                        .any(|other| other.key == record.key)
+                } else {
+                    !(plan.drop_key && record.key == key && record.seq != seq)
+                };
+                if keep {
+                    used += write_record(
+                        &mut page[PAGE_HEADER_SIZE..],
+                        used,
+                        record.seq,
+                        record.key,
+                        record.value,
+                    );
+                }
+            }
+        }
+        if plan.append {
+            let length = self.length.get();
+            used += if self.state.get() == State::Delete {
+                write_record(&mut page[PAGE_HEADER_SIZE..], used, seq, key, None)
+            } else {
+                self.buffer.map_or(0, |buffer| {
+                    write_record(
+                        &mut page[PAGE_HEADER_SIZE..],
+                        used,
+                        seq,
+                        key,
+                        Some(&buffer[..length]),
+                    )
+                })
+            };
+        }
+
+        if used == 0 {
+            // Nothing is left of the source pages, so they can be erased
+            // without writing a new page.
+            self.pagebuffer.replace(pagebuffer);
+            self.retire(plan.sources);
+            self.advance();
+            return ReturnCode::SUCCESS;
+        }
+
+        let index = match self.find_free_page() {
+            Some(index) => index,
+            None => {
+                self.pagebuffer.replace(pagebuffer);
+                return ReturnCode::ENOMEM;
+            }
+        };
+        let replaces = plan.sources[0].map_or(NO_SEQ, |source| self.pages[source].get().seq);
+        let page_seq = self.next_page_seq.get();
+        self.next_page_seq.set(page_seq + 1);
+        write_u32(page, 0, PAGE_MAGIC);
+        write_u32(page, 4, page_seq);
+        write_u32(page, 8, replaces);
+        write_u32(page, 12, used as u32);
+        let checksum = crc32(
+            crc32(0, &page[..16]),
+            &page[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + used],
+        );
+        write_u32(page, 16, checksum);
+
+        self.pages[index].set(PageInfo {
+            live: false,
+            dirty: false,
+            erased: self.pages[index].get().erased,
+            seq: page_seq,
+            used: used,
+        });
+        self.pending_write.set(index);
+        self.pending_sources.set(plan.sources);
+        self.next_free_page.set((index + 1) % self.num_pages);
+
+        if self.pages[index].get().erased {
+            self.write_page(index, pagebuffer)
+        } else {
+            self.pagebuffer.replace(pagebuffer);
+            let result = self.driver.erase_page(self.page_number(index));
+            if result != ReturnCode::SUCCESS {
+                self.pending_write.clear();
+            }
+            result
+        }
+    }
+
+    fn write_page(&self, index: usize, pagebuffer: &'static mut F::Page) -> ReturnCode {
+        match self.driver.write_page(self.page_number(index), pagebuffer) {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err((return_code, pagebuffer)) => {
+                self.pagebuffer.replace(pagebuffer);
+                self.pending_write.clear();
+                return_code
+            }
+        }
+    }
+
+    /// Returns the next free page, in turn.
+    fn find_free_page(&self) -> Option<usize> {
+        let start = self.next_free_page.get();
+        (0..self.num_pages)
+            .map(|offset| (start + offset) % self.num_pages)
+            .find(|index| !self.pages[*index].get().live)
+    }
+
+    /// Marks pages as no longer used, to be erased.
+    fn retire(&self, sources: [Option<usize>; 2]) {
+        for source in sources.iter().flatten() {
+            let info = self.pages[*source].get();
+            self.pages[*source].set(PageInfo {
+                live: false,
+                dirty: true,
+                ..info
+            });
+        }
+    }
+
+    /// Completes the current operation with `error`. The client is called
+    /// from a deferred call, since operations may complete during the call
+    /// that started them.
+    fn finish(&self, error: ReturnCode) {
+        self.error.set(error);
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    /// Resets the state to idle and makes a client callback.
+    fn client_callback(&self) {
+        let state = self.state.get();
+        self.state.set(State::Idle);
+        let error = self.error.get();
+        self.client.map(|client| match state {
+            State::Get => {
+                self.buffer
+                    .take()
+                    .map(|buffer| client.get_done(error, buffer, self.length.get()));
+            }
+            State::Set => {
+                self.buffer
+                    .take()
+                    .map(|buffer| client.set_done(error, buffer));
+            }
+            State::Delete => client.delete_done(error),
+            State::GarbageCollect => client.garbage_collect_done(error),
+            State::Idle => {}
+        });
+    }
+}
+
+impl<'a, F: Flash + 'static> KVStore<'a> for FlashKVStore<'a, F> {
+    fn set_client(&self, client: &'a dyn KVStoreClient) {
+        self.client.set(client);
+    }
+
+    fn get(
+        &self,
+        key: &[u8],
+        value: &'static mut [u8],
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if self.state.get() != State::Idle {
+            return Err((ReturnCode::EBUSY, value));
+        }
+        if !self.valid_key(key) {
+            return Err((ReturnCode::EINVAL, value));
+        }
+
+        match self.lookup(key) {
+            Some(current) => {
+                let length = cmp::min(value.len(), current.len());
+                value[..length].copy_from_slice(&current[..length]);
+                self.length.set(current.len());
+                if length < current.len() {
+                    self.error.set(ReturnCode::ESIZE);
+                } else {
+                    self.error.set(ReturnCode::SUCCESS);
+                }
+            }
+            None => {
+                self.length.set(0);
+                self.error.set(ReturnCode::FAIL);
+            }
+        }
+        self.buffer.replace(value);
+        self.state.set(State::Get);
+        self.finish(self.error.get());
+        Ok(())
+    }
+
+    fn set(
+        &self,
+        key: &[u8],
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if self.state.get() != State::Idle {
+            return Err((ReturnCode::EBUSY, value));
+        }
+        if !self.valid_key(key) || length > value.len() {
+            return Err((ReturnCode::EINVAL, value));
+        }
+        if length > self.max_value_len(key.len()) {
+            return Err((ReturnCode::ESIZE, value));
+        }
+
+        self.store_key(key);
+        self.buffer.replace(value);
+        self.length.set(length);
+        self.record_seq.set(self.next_record_seq.get());
+        self.next_record_seq.set(self.next_record_seq.get() + 1);
+        self.state.set(State::Set);
+        self.advance();
+        Ok(())
+    }
+
+    fn delete(&self, key: &[u8]) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if !self.valid_key(key) {
+            return ReturnCode::EINVAL;
+        }
+
+        self.store_key(key);
+        self.found.set(self.lookup(key).is_some());
+        self.record_seq.set(self.next_record_seq.get());
+        self.next_record_seq.set(self.next_record_seq.get() + 1);
+        self.state.set(State::Delete);
+        self.advance();
+        ReturnCode::SUCCESS
+    }
+
+    fn garbage_collect(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+
+        self.state.set(State::GarbageCollect);
+        self.advance();
+        ReturnCode::SUCCESS
+    }
+
+    fn max_key_len(&self) -> usize {
+        MAX_KEY_LEN
+    }
+
+    fn max_value_len(&self, key_len: usize) -> usize {
+        let capacity = self.page_size - PAGE_HEADER_SIZE - RECORD_HEADER_SIZE;
+        // The largest length marks tombstones.
+        cmp::min(capacity.saturating_sub(key_len), TOMBSTONE as usize - 1)
+    }
+}
+
+impl<'a, F: Flash + 'static> flash::Client<F> for FlashKVStore<'a, F> {
+    fn read_complete(&self, _read_buffer: &'static mut F::Page, _error: flash::Error) {
+        // Reads are made directly from the storage volume, not through the
+        // flash interface, so there is nothing to do. The buffer is not ours.
+    }
+
+    fn write_complete(&self, pagebuffer: &'static mut F::Page, error: flash::Error) {
+        self.pagebuffer.replace(pagebuffer);
+        let index = match self.pending_write.take() {
+            Some(index) => index,
+            None => return,
+        };
+        let info = self.pages[index].get();
+
+        match error {
+            flash::Error::CommandComplete => {
+                self.pages[index].set(PageInfo {
+                    live: true,
+                    erased: false,
+                    ..info
+                });
+                self.retire(self.pending_sources.get());
+                self.advance();
+            }
+            flash::Error::FlashError => {
+                // The page may have been partially written.
+                self.pages[index].set(PageInfo {
+                    dirty: true,
+                    erased: false,
+                    ..info
+                });
+                self.finish(ReturnCode::FAIL);
+            }
+        }
+    }
+
+    fn erase_complete(&self, error: flash::Error) {
+        match error {
+            flash::Error::CommandComplete => match self.pending_write.take() {
+                Some(index) => {
+                    let info = self.pages[index].get();
+                    self.pages[index].set(PageInfo {
+                        erased: true,
+                        ..info
+                    });
+                    let result =
+                        self.pagebuffer
+                            .take()
+                            .map_or(ReturnCode::ERESERVE, |pagebuffer| {
+                                self.pending_write.set(index);
+                                self.write_page(index, pagebuffer)
+                            });
+                    if result != ReturnCode::SUCCESS {
+                        self.finish(result);
+                    }
+                }
+                None => {
+                    // A page that is no longer used has been erased.
+                    if let Some(index) =
+                        (0..self.num_pages).find(|index| self.pages[*index].get().dirty)
+                    {
+                        self.pages[index].set(PageInfo {
+                            erased: true,
+                            ..PageInfo::default()
+                        });
+                    }
+                    self.advance();
+                }
+            },
+            flash::Error::FlashError => {
+                self.pending_write.clear();
+                self.finish(ReturnCode::FAIL);
+            }
+        }
+    }
+}
+
+impl<'a, F: Flash + 'static> DynamicDeferredCallClient for FlashKVStore<'a, F> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        self.client_callback();
+    }
+}
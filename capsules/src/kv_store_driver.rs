@@ -0,0 +1,299 @@
+//! Userspace interface to a persistent key-value store.
+//!
+//! Each process has its own namespace of keys: the key given by a process is
+//! prefixed with the package name from its TBF header before it is passed to
+//! the store, so a process can only read and change its own values. Since
+//! the package name does not change, values remain available when the
+//! process restarts, the board reboots, or the application is updated.
+//!
+//! Processes can start one operation at a time. Operations from different
+//! processes are queued and run in turn.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! pub static mut KV_STORE_BUFFER: [u8; 256] = [0; 256];
+//! let kv_store_driver = static_init!(
+//!     capsules::kv_store_driver::KVStoreDriver<'static>,
+//!     capsules::kv_store_driver::KVStoreDriver::new(
+//!         kv_store,
+//!         board_kernel.create_grant(&grant_cap),
+//!         &mut KV_STORE_BUFFER
+//!     )
+//! );
+//! kernel::hil::kv_store::KVStore::set_client(kv_store, kv_store_driver);
+//! ```
+
+use crate::kv_store::MAX_KEY_LEN;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::kv_store::{KVStore, KVStoreClient};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::KVStore as usize;
+
+/// Operations reported in the first callback argument. These match the
+/// command numbers that started them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operation {
+    Get = 1,
+    Set = 2,
+    Delete = 3,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    key: Option<AppSlice<Shared, u8>>,
+    value: Option<AppSlice<Shared, u8>>,
+    /// Operation waiting for the store, with the length of the value to set.
+    pending: Option<(Operation, usize)>,
+}
+
+pub struct KVStoreDriver<'a> {
+    kv_store: &'a dyn KVStore<'a>,
+    apps: Grant<App>,
+    /// The process whose operation the store is executing.
+    current_app: OptionalCell<AppId>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+/// Prefix `key` with the package name of `appid` and a zero byte, and write
+/// the result to `buf`. Returns the length of the namespaced key.
+fn namespaced_key(appid: AppId, key: &[u8], buf: &mut [u8]) -> Result<usize, ReturnCode> {
+    let name = appid.get_process_name().as_bytes();
+    let length = name.len() + 1 + key.len();
+    if key.is_empty() || length > buf.len() {
+        return Err(ReturnCode::EINVAL);
+    }
+    buf[..name.len()].copy_from_slice(name);
+    buf[name.len()] = 0;
+    buf[name.len() + 1..length].copy_from_slice(key);
+    Ok(length)
+}
+
+impl<'a> KVStoreDriver<'a> {
+    pub fn new(
+        kv_store: &'a dyn KVStore<'a>,
+        grant: Grant<App>,
+        buffer: &'static mut [u8],
+    ) -> KVStoreDriver<'a> {
+        KVStoreDriver {
+            kv_store: kv_store,
+            apps: grant,
+            current_app: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    /// Start `operation` for `appid` if the store is idle, or queue it
+    /// otherwise.
+    fn enqueue(&self, appid: AppId, operation: Operation, length: usize) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                if app.pending.is_some() || self.current_app.contains(&appid) {
+                    return ReturnCode::EBUSY;
+                }
+                if self.current_app.is_none() {
+                    self.start(appid, app, operation, length)
+                } else {
+                    app.pending = Some((operation, length));
+                    ReturnCode::SUCCESS
+                }
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Pass `operation` of `appid` to the store.
+    fn start(
+        &self,
+        appid: AppId,
+        app: &mut App,
+        operation: Operation,
+        length: usize,
+    ) -> ReturnCode {
+        let mut key = [0; MAX_KEY_LEN];
+        let key_len = match app
+            .key
+            .as_ref()
+            .map_or(Err(ReturnCode::ERESERVE), |app_key| {
+                namespaced_key(appid, app_key.as_ref(), &mut key)
+            }) {
+            Ok(key_len) => key_len,
+            Err(err) => return err,
+        };
+        let key = &key[..key_len];
+
+        let result = match operation {
+            Operation::Get => self.buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
+                self.kv_store.get(key, buffer).map_or_else(
+                    |(err, buffer)| {
+                        self.buffer.replace(buffer);
+                        err
+                    },
+                    |()| ReturnCode::SUCCESS,
+                )
+            }),
+            Operation::Set => match app.value.as_ref() {
+                Some(value) if length <= value.len() => {
+                    self.buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
+                        if length > buffer.len() {
+                            self.buffer.replace(buffer);
+                            return ReturnCode::ESIZE;
+                        }
+                        buffer[..length].copy_from_slice(&value.as_ref()[..length]);
+                        self.kv_store.set(key, buffer, length).map_or_else(
+                            |(err, buffer)| {
+                                self.buffer.replace(buffer);
+                                err
+                            },
+                            |()| ReturnCode::SUCCESS,
+                        )
+                    })
+                }
+                Some(_) => ReturnCode::EINVAL,
+                None => ReturnCode::ERESERVE,
+            },
+            Operation::Delete => self.kv_store.delete(key),
+        };
+        if result == ReturnCode::SUCCESS {
+            self.current_app.set(appid);
+        }
+        result
+    }
+
+    /// Notify the current process that its operation finished, and start the
+    /// next queued operation.
+    fn complete(&self, operation: Operation, result: ReturnCode, length: usize) {
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback.map(|mut cb| {
+                    cb.schedule(operation as usize, usize::from(result), length);
+                });
+            });
+        });
+
+        for cntr in self.apps.iter() {
+            let started = cntr.enter(|app, _| {
+                app.pending.take().map_or(false, |(operation, length)| {
+                    let result = self.start(app.appid(), app, operation, length);
+                    if result != ReturnCode::SUCCESS {
+                        app.callback.map(|mut cb| {
+                            cb.schedule(operation as usize, usize::from(result), 0);
+                        });
+                    }
+                    result == ReturnCode::SUCCESS
+                })
+            });
+            if started {
+                break;
+            }
+        }
+    }
+}
+
+impl KVStoreClient for KVStoreDriver<'_> {
+    fn get_done(&self, result: ReturnCode, value: &'static mut [u8], length: usize) {
+        self.current_app.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, _| {
+                app.value.as_mut().map(|app_value| {
+                    let copy_len = cmp::min(length, cmp::min(value.len(), app_value.len()));
+                    app_value.as_mut()[..copy_len].copy_from_slice(&value[..copy_len]);
+                });
+            });
+        });
+        self.buffer.replace(value);
+        self.complete(Operation::Get, result, length);
+    }
+
+    fn set_done(&self, result: ReturnCode, value: &'static mut [u8]) {
+        self.buffer.replace(value);
+        self.complete(Operation::Set, result, 0);
+    }
+
+    fn delete_done(&self, result: ReturnCode) {
+        self.complete(Operation::Delete, result, 0);
+    }
+
+    fn garbage_collect_done(&self, _result: ReturnCode) {}
+}
+
+impl Driver for KVStoreDriver<'_> {
+    /// Setup buffers for keys and values.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Set the key of the next operation.
+    /// - `1`: Set the buffer for values. `get` reads the value into it and
+    ///   `set` stores the value from it.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if allow_num == 0 {
+                        app.key = slice;
+                    } else {
+                        app.value = slice;
+                    }
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Set the callback for completed operations. The arguments are
+    ///   the command number of the operation, its `ReturnCode`, and the
+    ///   length of the stored value (`get`).
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Key-value store operations.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Read the value of the key into the value buffer.
+    /// - `2`: Store the first `arg1` bytes of the value buffer as the value
+    ///   of the key.
+    /// - `3`: Remove the value of the key.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.enqueue(appid, Operation::Get, 0),
+            2 => self.enqueue(appid, Operation::Set, arg1),
+            3 => self.enqueue(appid, Operation::Delete, 0),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
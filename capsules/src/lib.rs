@@ -14,7 +14,10 @@ pub mod ambient_light;
 pub mod analog_comparator;
 pub mod analog_sensor;
 pub mod apds9960;
+pub mod app_checker_sha256;
 pub mod app_flash_driver;
+pub mod app_loader;
+pub mod app_watchdog;
 pub mod ble_advertising_driver;
 pub mod bus;
 pub mod button;
@@ -25,6 +28,7 @@ pub mod ctap;
 pub mod dac;
 pub mod debug_process_restart;
 pub mod driver;
+pub mod fat;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700cq;
@@ -37,6 +41,8 @@ pub mod i2c_master;
 pub mod i2c_master_slave_driver;
 pub mod ieee802154;
 pub mod isl29035;
+pub mod kv_store;
+pub mod kv_store_driver;
 pub mod l3gd20;
 pub mod led;
 pub mod log;
@@ -55,7 +61,10 @@ pub mod nrf51822_serialization;
 pub mod panic_button;
 pub mod pca9544a;
 pub mod process_console;
+pub mod process_fault_log;
 pub mod proximity;
+pub mod remote_management;
+pub mod restart_backoff;
 pub mod rf233;
 pub mod rf233_const;
 pub mod rng;
@@ -64,6 +73,7 @@ pub mod sdcard;
 pub mod segger_rtt;
 pub mod si7021;
 pub mod spi_controller;
+pub mod spi_nor;
 pub mod spi_peripheral;
 pub mod st77xx;
 pub mod temperature;
@@ -72,7 +82,9 @@ pub mod touch;
 pub mod tsl2561;
 pub mod usb;
 pub mod virtual_adc;
+pub mod virtual_aes_ccm;
 pub mod virtual_alarm;
+pub mod virtual_block_storage;
 pub mod virtual_digest;
 pub mod virtual_flash;
 pub mod virtual_hmac;
@@ -0,0 +1,773 @@
+//! This file contains `NeighborDiscovery`, the ICMPv6 endpoint of a node on
+//! a 6LoWPAN network. It answers echo requests, so that the node can be
+//! pinged, and implements the host side of 6LoWPAN Neighbor Discovery
+//! (RFC 6775), keeping a `NeighborCache` up to date for the IPv6 layer.
+//!
+//! Neighbor Discovery proceeds as follows:
+//!
+//! - Once started, the node multicasts Router Solicitations until a Router
+//!   Advertisement arrives. Solicitations are retransmitted every
+//!   `RTR_SOLICITATION_INTERVAL` seconds, backing off exponentially to
+//!   `MAX_RTR_SOLICITATION_INTERVAL` after `MAX_RTR_SOLICITATIONS` attempts.
+//! - A Router Advertisement adds the router to the neighbor cache as a
+//!   default router for its advertised lifetime. If it carries a Prefix
+//!   Information option with the autonomous flag, the node forms a global
+//!   address from the prefix and its MAC address.
+//! - The global address is registered with the router by a unicast Neighbor
+//!   Solicitation carrying an Address Registration option. The router
+//!   confirms with a Neighbor Advertisement, and the registration is renewed
+//!   before its lifetime runs out. If the router does not answer, the router
+//!   is dropped and solicitation starts over.
+//! - Neighbor Solicitations for one of the addresses of the node are
+//!   answered with a Neighbor Advertisement, and Neighbor Advertisements
+//!   update the neighbor cache.
+//!
+//! As in RFC 6775, the node never performs address resolution: link-local
+//! addresses map directly to MAC addresses, and everything else is sent to
+//! the default router (see
+//! [NeighborCache](../../ipv6/neighbor_cache/struct.NeighborCache.html)).
+//!
+//! All timers are counted in seconds, driven by an alarm that fires every
+//! `ND_TIMER_MS` once the node has been started, which also ages the
+//! entries of the neighbor cache.
+//!
+//! The IP layer can only send one packet at a time, so outgoing messages are
+//! queued and sent in order of priority (echo replies first) whenever the IP
+//! sender is idle. Only one message of each kind is queued; an echo request
+//! that arrives while a reply is still pending is dropped.
+
+use crate::net::icmpv6::icmpv6::{na_flags, ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::neighbor_cache::NeighborCache;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
+use core::cell::Cell;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::debug;
+use kernel::hil::time;
+use kernel::ReturnCode;
+
+/// The link-local all-nodes multicast address, ff02::1.
+pub const ALL_NODES_ADDR: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+/// The link-local all-routers multicast address, ff02::2.
+pub const ALL_ROUTERS_ADDR: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+/// Period of the timer that drives Neighbor Discovery.
+pub const ND_TIMER_MS: u32 = 1000;
+
+/// Seconds between the first Router Solicitations (RFC 6775, Section 9).
+pub const RTR_SOLICITATION_INTERVAL: u32 = 10;
+/// Number of Router Solicitations sent before backing off.
+pub const MAX_RTR_SOLICITATIONS: u8 = 3;
+/// Largest interval between Router Solicitations, in seconds.
+pub const MAX_RTR_SOLICITATION_INTERVAL: u32 = 60;
+/// Number of unanswered address registrations after which the router is
+/// considered unreachable.
+pub const MAX_UNICAST_SOLICIT: u8 = 3;
+/// Seconds between retransmissions of an unanswered address registration.
+pub const RETRANS_TIMER: u32 = 1;
+/// Registration lifetime requested from routers, in units of 60 seconds.
+pub const REGISTRATION_LIFETIME: u16 = 15;
+/// Lifetime of neighbor cache entries learned from solicitations and
+/// advertisements, in seconds.
+pub const NEIGHBOR_LIFETIME: u32 = 300;
+
+/// Types of Neighbor Discovery options (RFC 4861 and RFC 6775).
+pub mod nd_opt {
+    pub const SOURCE_LL_ADDR: u8 = 1;
+    pub const TARGET_LL_ADDR: u8 = 2;
+    pub const PREFIX_INFO: u8 = 3;
+    pub const ADDR_REGISTRATION: u8 = 33;
+}
+
+/// Status values of the Address Registration option.
+pub mod aro_status {
+    pub const SUCCESS: u8 = 0;
+    pub const DUPLICATE: u8 = 1;
+    pub const CACHE_FULL: u8 = 2;
+}
+
+/// Flags of the Prefix Information option.
+pub mod pio_flags {
+    pub const ON_LINK: u8 = 0x80;
+    pub const AUTONOMOUS: u8 = 0x40;
+}
+
+/// A Neighbor Discovery option, as carried after the fixed part of Router
+/// Solicitations and Advertisements and Neighbor Solicitations and
+/// Advertisements.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NDOption {
+    SourceLLAddr(MacAddress),
+    TargetLLAddr(MacAddress),
+    PrefixInfo {
+        prefix_len: u8,
+        flags: u8,
+        valid_lifetime: u32,
+        preferred_lifetime: u32,
+        prefix: IPAddr,
+    },
+    AddrRegistration {
+        status: u8,
+        /// Registration lifetime in units of 60 seconds
+        lifetime: u16,
+        eui64: [u8; 8],
+    },
+    /// An option this implementation does not process
+    Unknown(u8),
+}
+
+impl NDOption {
+    /// Serializes an `NDOption` into a buffer, padding it to a multiple of
+    /// eight bytes. Link-layer addresses are encoded as in RFC 4944,
+    /// Section 8. Unknown options cannot be encoded.
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        let mut off = offset;
+        match *self {
+            NDOption::SourceLLAddr(mac_addr) | NDOption::TargetLLAddr(mac_addr) => {
+                let opt_type = match *self {
+                    NDOption::SourceLLAddr(_) => nd_opt::SOURCE_LL_ADDR,
+                    _ => nd_opt::TARGET_LL_ADDR,
+                };
+                off = enc_consume!(buf, off; encode_u8, opt_type);
+                match mac_addr {
+                    MacAddress::Short(short_addr) => {
+                        off = enc_consume!(buf, off; encode_u8, 1);
+                        off = enc_consume!(buf, off; encode_u16, short_addr);
+                        off = enc_consume!(buf, off; encode_bytes, &[0; 4]);
+                    }
+                    MacAddress::Long(long_addr) => {
+                        off = enc_consume!(buf, off; encode_u8, 2);
+                        off = enc_consume!(buf, off; encode_bytes, &long_addr);
+                        off = enc_consume!(buf, off; encode_bytes, &[0; 6]);
+                    }
+                }
+            }
+            NDOption::PrefixInfo {
+                prefix_len,
+                flags,
+                valid_lifetime,
+                preferred_lifetime,
+                prefix,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, nd_opt::PREFIX_INFO);
+                off = enc_consume!(buf, off; encode_u8, 4);
+                off = enc_consume!(buf, off; encode_u8, prefix_len);
+                off = enc_consume!(buf, off; encode_u8, flags);
+                off = enc_consume!(buf, off; encode_u32, valid_lifetime);
+                off = enc_consume!(buf, off; encode_u32, preferred_lifetime);
+                off = enc_consume!(buf, off; encode_u32, 0);
+                off = enc_consume!(buf, off; encode_bytes, &prefix.0);
+            }
+            NDOption::AddrRegistration {
+                status,
+                lifetime,
+                eui64,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, nd_opt::ADDR_REGISTRATION);
+                off = enc_consume!(buf, off; encode_u8, 2);
+                off = enc_consume!(buf, off; encode_u8, status);
+                off = enc_consume!(buf, off; encode_bytes, &[0; 3]);
+                off = enc_consume!(buf, off; encode_u16, lifetime);
+                off = enc_consume!(buf, off; encode_bytes, &eui64);
+            }
+            NDOption::Unknown(_) => stream_err!(),
+        }
+        stream_done!(off, off);
+    }
+
+    /// Deserializes an `NDOption` from the start of a buffer. Returns the
+    /// option and the offset of the next option.
+    pub fn decode(buf: &[u8]) -> SResult<NDOption> {
+        let (off, opt_type) = dec_try!(buf; decode_u8);
+        let (off, len) = dec_try!(buf, off; decode_u8);
+        let opt_len = len as usize * 8;
+        stream_cond!(opt_len > 0);
+        stream_len_cond!(buf, opt_len);
+
+        let option = match opt_type {
+            nd_opt::SOURCE_LL_ADDR | nd_opt::TARGET_LL_ADDR => {
+                let mac_addr = match len {
+                    1 => {
+                        let (_, short_addr) = dec_try!(buf, off; decode_u16);
+                        MacAddress::Short(short_addr)
+                    }
+                    2 => {
+                        let mut long_addr = [0; 8];
+                        dec_try!(buf, off; decode_bytes, &mut long_addr);
+                        MacAddress::Long(long_addr)
+                    }
+                    _ => stream_err!(),
+                };
+                if opt_type == nd_opt::SOURCE_LL_ADDR {
+                    NDOption::SourceLLAddr(mac_addr)
+                } else {
+                    NDOption::TargetLLAddr(mac_addr)
+                }
+            }
+            nd_opt::PREFIX_INFO => {
+                stream_cond!(len == 4);
+                let (off, prefix_len) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, valid_lifetime) = dec_try!(buf, off; decode_u32);
+                let (off, preferred_lifetime) = dec_try!(buf, off; decode_u32);
+                let off = off + 4;
+                let mut prefix = IPAddr::new();
+                dec_try!(buf, off; decode_bytes, &mut prefix.0);
+                NDOption::PrefixInfo {
+                    prefix_len: prefix_len,
+                    flags: flags,
+                    valid_lifetime: valid_lifetime,
+                    preferred_lifetime: preferred_lifetime,
+                    prefix: prefix,
+                }
+            }
+            nd_opt::ADDR_REGISTRATION => {
+                stream_cond!(len == 2);
+                let (off, status) = dec_try!(buf, off; decode_u8);
+                let off = off + 3;
+                let (off, lifetime) = dec_try!(buf, off; decode_u16);
+                let mut eui64 = [0; 8];
+                dec_try!(buf, off; decode_bytes, &mut eui64);
+                NDOption::AddrRegistration {
+                    status: status,
+                    lifetime: lifetime,
+                    eui64: eui64,
+                }
+            }
+            _ => NDOption::Unknown(opt_type),
+        };
+        stream_done!(opt_len, option);
+    }
+}
+
+/// Decodes the options in `buf`, stopping at the first malformed option.
+fn for_each_option<F: FnMut(NDOption)>(buf: &[u8], mut f: F) {
+    let mut off = 0;
+    while off < buf.len() {
+        match NDOption::decode(&buf[off..]).done() {
+            Some((len, option)) => {
+                f(option);
+                off += len;
+            }
+            None => break,
+        }
+    }
+}
+
+fn decode_target(payload: &[u8]) -> Option<IPAddr> {
+    if payload.len() < 16 {
+        return None;
+    }
+    let mut target = IPAddr::new();
+    target.0.copy_from_slice(&payload[..16]);
+    Some(target)
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Registration {
+    /// There is no global address to register
+    Unregistered,
+    /// The address has been sent for registration `tries` times without an
+    /// answer
+    Pending {
+        tries: u8,
+    },
+    Registered,
+}
+
+/// An echo reply waiting to be sent. Its payload is kept in the tx buffer.
+#[derive(Copy, Clone)]
+struct EchoReply {
+    src: IPAddr,
+    dst: IPAddr,
+    id: u16,
+    seqno: u16,
+    len: usize,
+}
+
+pub struct NeighborDiscovery<'a, A: time::Alarm<'a>> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    neighbor_cache: &'a NeighborCache<'a>,
+    alarm: &'a A,
+    src_mac_addr: MacAddress,
+    link_local_addr: IPAddr,
+    local_addrs: &'static [IPAddr],
+    global_addr: OptionalCell<IPAddr>,
+    /// The router the global address is registered with
+    router: OptionalCell<IPAddr>,
+    registration: Cell<Registration>,
+    /// Seconds until the registration is sent again
+    registration_timer: Cell<u32>,
+    solicitations: Cell<u8>,
+    /// Seconds until the next Router Solicitation
+    solicitation_timer: Cell<u32>,
+    started: Cell<bool>,
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    sending: Cell<bool>,
+    pending_echo: OptionalCell<EchoReply>,
+    /// Destination and target of a pending Neighbor Advertisement
+    pending_na: OptionalCell<(IPAddr, IPAddr)>,
+    pending_ns: Cell<bool>,
+    pending_rs: Cell<bool>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> NeighborDiscovery<'a, A> {
+    /// `local_addrs` are the addresses, besides the link-local address
+    /// derived from `src_mac_addr` and the global address formed from
+    /// Router Advertisements, that the node answers echo requests and
+    /// Neighbor Solicitations for. `tx_buffer` holds the payload of one
+    /// outgoing message, so it limits the size of the echo requests that
+    /// are answered; it must be at least 48 bytes long.
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        neighbor_cache: &'a NeighborCache<'a>,
+        alarm: &'a A,
+        src_mac_addr: MacAddress,
+        local_addrs: &'static [IPAddr],
+        tx_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> NeighborDiscovery<'a, A> {
+        NeighborDiscovery {
+            ip_sender: ip_sender,
+            neighbor_cache: neighbor_cache,
+            alarm: alarm,
+            src_mac_addr: src_mac_addr,
+            link_local_addr: IPAddr::generate_from_mac(src_mac_addr),
+            local_addrs: local_addrs,
+            global_addr: OptionalCell::empty(),
+            router: OptionalCell::empty(),
+            registration: Cell::new(Registration::Unregistered),
+            registration_timer: Cell::new(0),
+            solicitations: Cell::new(0),
+            solicitation_timer: Cell::new(0),
+            started: Cell::new(false),
+            tx_buffer: MapCell::new(LeasableBuffer::new(tx_buffer)),
+            sending: Cell::new(false),
+            pending_echo: OptionalCell::empty(),
+            pending_na: OptionalCell::empty(),
+            pending_ns: Cell::new(false),
+            pending_rs: Cell::new(false),
+            net_cap: net_cap,
+        }
+    }
+
+    /// Starts Neighbor Discovery by soliciting a router. Echo requests and
+    /// Neighbor Solicitations are answered whether or not the node has been
+    /// started.
+    pub fn start(&self) {
+        if self.started.get() {
+            return;
+        }
+        self.started.set(true);
+        self.solicit_router();
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(ND_TIMER_MS));
+    }
+
+    pub fn get_link_local_addr(&self) -> IPAddr {
+        self.link_local_addr
+    }
+
+    /// Returns the global address formed from the prefix advertised by the
+    /// router, if it has been registered.
+    pub fn get_global_addr(&self) -> Option<IPAddr> {
+        match self.registration.get() {
+            Registration::Registered => self.global_addr.map(|addr| *addr),
+            _ => None,
+        }
+    }
+
+    fn is_local_addr(&self, addr: IPAddr) -> bool {
+        addr == self.link_local_addr
+            || self.global_addr.contains(&addr)
+            || self.local_addrs.contains(&addr)
+    }
+
+    fn solicit_router(&self) {
+        let count = self.solicitations.get();
+        let interval = if count < MAX_RTR_SOLICITATIONS {
+            RTR_SOLICITATION_INTERVAL
+        } else {
+            let shift = (count - MAX_RTR_SOLICITATIONS + 1).min(8) as u32;
+            (RTR_SOLICITATION_INTERVAL << shift).min(MAX_RTR_SOLICITATION_INTERVAL)
+        };
+        self.solicitations.set(count.saturating_add(1));
+        self.solicitation_timer.set(interval);
+        self.pending_rs.set(true);
+        self.do_output();
+    }
+
+    fn register(&self) {
+        if let Registration::Pending { tries } = self.registration.get() {
+            if tries >= MAX_UNICAST_SOLICIT {
+                self.router
+                    .take()
+                    .map(|router| self.neighbor_cache.remove(router));
+                self.registration.set(Registration::Unregistered);
+                self.global_addr.clear();
+                self.solicitations.set(0);
+                self.solicit_router();
+                return;
+            }
+            self.registration
+                .set(Registration::Pending { tries: tries + 1 });
+        }
+        self.registration_timer.set(RETRANS_TIMER);
+        self.pending_ns.set(true);
+        self.do_output();
+    }
+
+    /// The EUI-64 identifying this node in address registrations, which is
+    /// the interface identifier with the universal/local bit inverted.
+    fn eui64(&self) -> [u8; 8] {
+        let mut eui64 = [0; 8];
+        eui64.copy_from_slice(&self.link_local_addr.0[8..16]);
+        eui64[0] ^= 0b00000010;
+        eui64
+    }
+
+    fn receive_echo_request(&self, ip_header: &IP6Header, id: u16, seqno: u16, data: &[u8]) {
+        let dst = ip_header.get_dst_addr();
+        if !self.is_local_addr(dst) && dst != ALL_NODES_ADDR {
+            return;
+        }
+        if self.pending_echo.is_some() {
+            return;
+        }
+        let src = if dst.is_multicast() {
+            self.link_local_addr
+        } else {
+            dst
+        };
+        self.tx_buffer.map(|buf| {
+            if data.len() <= buf.len() {
+                buf[..data.len()].copy_from_slice(data);
+                self.pending_echo.set(EchoReply {
+                    src: src,
+                    dst: ip_header.get_src_addr(),
+                    id: id,
+                    seqno: seqno,
+                    len: data.len(),
+                });
+            }
+        });
+    }
+
+    fn receive_router_advertisement(
+        &self,
+        ip_header: &IP6Header,
+        router_lifetime: u16,
+        data: &[u8],
+    ) {
+        let src = ip_header.get_src_addr();
+        if !src.is_unicast_link_local() || data.len() < 8 {
+            return;
+        }
+        let mut mac_addr = src.mac_from_iid();
+        let mut prefix = None;
+        for_each_option(&data[8..], |option| match option {
+            NDOption::SourceLLAddr(addr) => mac_addr = addr,
+            NDOption::PrefixInfo {
+                prefix_len: 64,
+                flags,
+                valid_lifetime,
+                prefix: pfx,
+                ..
+            } if flags & pio_flags::AUTONOMOUS != 0 && valid_lifetime > 0 => prefix = Some(pfx),
+            _ => {}
+        });
+
+        if router_lifetime == 0 {
+            self.neighbor_cache
+                .update(src, mac_addr, false, Some(NEIGHBOR_LIFETIME));
+            return;
+        }
+        self.neighbor_cache
+            .update(src, mac_addr, true, Some(router_lifetime as u32));
+        // Solicit again as soon as the router expires
+        self.solicitations.set(0);
+        self.solicitation_timer.set(0);
+
+        if let Some(prefix) = prefix {
+            let mut global_addr = self.link_local_addr;
+            global_addr.set_prefix(&prefix.0, 64);
+            let known = self.global_addr.contains(&global_addr) && self.router.contains(&src);
+            if !known || self.registration.get() == Registration::Unregistered {
+                self.global_addr.set(global_addr);
+                self.router.set(src);
+                self.registration.set(Registration::Pending { tries: 0 });
+                self.register();
+            }
+        }
+    }
+
+    fn receive_neighbor_solicitation(&self, ip_header: &IP6Header, data: &[u8]) {
+        let target = match decode_target(data) {
+            Some(target) => target,
+            None => return,
+        };
+        if target.is_multicast() || !self.is_local_addr(target) {
+            return;
+        }
+        let src = ip_header.get_src_addr();
+        let dst = if src.is_unspecified() {
+            ALL_NODES_ADDR
+        } else {
+            let mut mac_addr = None;
+            for_each_option(&data[16..], |option| {
+                if let NDOption::SourceLLAddr(addr) = option {
+                    mac_addr = Some(addr);
+                }
+            });
+            if let Some(mac_addr) = mac_addr {
+                let is_router = self
+                    .neighbor_cache
+                    .lookup(src)
+                    .map_or(false, |entry| entry.is_router);
+                let lifetime = if is_router {
+                    self.neighbor_cache
+                        .lookup(src)
+                        .and_then(|entry| entry.lifetime)
+                } else {
+                    Some(NEIGHBOR_LIFETIME)
+                };
+                self.neighbor_cache
+                    .update(src, mac_addr, is_router, lifetime);
+            }
+            src
+        };
+        self.pending_na.set((dst, target));
+    }
+
+    fn receive_neighbor_advertisement(&self, ip_header: &IP6Header, flags: u8, data: &[u8]) {
+        let target = match decode_target(data) {
+            Some(target) => target,
+            None => return,
+        };
+        let mut mac_addr = None;
+        let mut registration = None;
+        for_each_option(&data[16..], |option| match option {
+            NDOption::TargetLLAddr(addr) => mac_addr = Some(addr),
+            NDOption::AddrRegistration {
+                status, lifetime, ..
+            } => registration = Some((status, lifetime)),
+            _ => {}
+        });
+
+        if let Some((status, lifetime)) = registration {
+            if !self.global_addr.contains(&target)
+                || !self.router.contains(&ip_header.get_src_addr())
+            {
+                return;
+            }
+            match status {
+                aro_status::SUCCESS if lifetime > 0 => {
+                    self.registration.set(Registration::Registered);
+                    // Renew the registration a minute before it expires
+                    self.registration_timer
+                        .set((lifetime as u32 * 60).saturating_sub(60).max(RETRANS_TIMER));
+                }
+                _ => {
+                    // The address is a duplicate or the router cannot hold
+                    // it; keep using the link-local address only
+                    self.registration.set(Registration::Unregistered);
+                    self.global_addr.clear();
+                    self.router.clear();
+                }
+            }
+            return;
+        }
+
+        if target.is_multicast() {
+            return;
+        }
+        let entry = self.neighbor_cache.lookup(target);
+        let mac_addr = match mac_addr.or(entry.map(|entry| entry.mac_addr)) {
+            Some(mac_addr) => mac_addr,
+            None => return,
+        };
+        let is_router = flags & na_flags::ROUTER != 0;
+        let lifetime = match entry {
+            Some(entry) if entry.is_router && is_router => entry.lifetime,
+            _ => Some(NEIGHBOR_LIFETIME),
+        };
+        self.neighbor_cache
+            .update(target, mac_addr, is_router, lifetime);
+    }
+
+    /// Builds the next pending message into `buf`, returning its source and
+    /// destination, its header and the length of its payload.
+    fn next_message(&self, buf: &mut [u8]) -> Option<(IPAddr, IPAddr, ICMP6Header, usize)> {
+        if let Some(echo) = self.pending_echo.take() {
+            let mut header = ICMP6Header::new(ICMP6Type::Type129);
+            header.set_options(ICMP6HeaderOptions::Type129 {
+                id: echo.id,
+                seqno: echo.seqno,
+            });
+            return Some((echo.src, echo.dst, header, echo.len));
+        }
+
+        if let Some((dst, target)) = self.pending_na.take() {
+            let mut header = ICMP6Header::new(ICMP6Type::Type136);
+            let flags = if dst.is_multicast() {
+                na_flags::OVERRIDE
+            } else {
+                na_flags::SOLICITED | na_flags::OVERRIDE
+            };
+            header.set_options(ICMP6HeaderOptions::Type136 { flags: flags });
+            buf[..16].copy_from_slice(&target.0);
+            let len = NDOption::TargetLLAddr(self.src_mac_addr)
+                .encode(buf, 16)
+                .done()?
+                .0;
+            return Some((target, dst, header, len));
+        }
+
+        if self.pending_ns.get() {
+            self.pending_ns.set(false);
+            if let (Some(global_addr), Some(router)) = (
+                self.global_addr.map(|addr| *addr),
+                self.router.map(|addr| *addr),
+            ) {
+                let header = ICMP6Header::new(ICMP6Type::Type135);
+                buf[..16].copy_from_slice(&global_addr.0);
+                let (off, _) = NDOption::SourceLLAddr(self.src_mac_addr)
+                    .encode(buf, 16)
+                    .done()?;
+                let (len, _) = NDOption::AddrRegistration {
+                    status: aro_status::SUCCESS,
+                    lifetime: REGISTRATION_LIFETIME,
+                    eui64: self.eui64(),
+                }
+                .encode(buf, off)
+                .done()?;
+                return Some((global_addr, router, header, len));
+            }
+        }
+
+        if self.pending_rs.get() {
+            self.pending_rs.set(false);
+            let header = ICMP6Header::new(ICMP6Type::Type133);
+            let len = NDOption::SourceLLAddr(self.src_mac_addr)
+                .encode(buf, 0)
+                .done()?
+                .0;
+            return Some((self.link_local_addr, ALL_ROUTERS_ADDR, header, len));
+        }
+
+        None
+    }
+
+    /// Sends the next pending message if the IP layer is idle.
+    fn do_output(&self) {
+        if self.sending.get() {
+            return;
+        }
+        self.tx_buffer.take().map(|mut buf| {
+            if let Some((src, dst, header, len)) = self.next_message(&mut buf[..]) {
+                buf.slice(0..len);
+                self.sending.set(true);
+                self.ip_sender.set_addr(src);
+                let ret =
+                    self.ip_sender
+                        .send_to(dst, TransportHeader::ICMP(header), &buf, self.net_cap);
+                if ret != ReturnCode::SUCCESS {
+                    debug!("[ND] IP send_to failed: {:?}", ret);
+                    self.sending.set(false);
+                }
+            }
+            buf.reset();
+            self.tx_buffer.replace(buf);
+        });
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6RecvClient for NeighborDiscovery<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::ICMP {
+            return;
+        }
+        let (offset, header) = match ICMP6Header::decode(payload).done() {
+            Some(result) => result,
+            None => return,
+        };
+        let data = &payload[offset..];
+
+        match header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => {
+                self.receive_echo_request(&ip_header, id, seqno, data);
+            }
+            // Neighbor Discovery messages must not have been forwarded
+            // (RFC 4861, Section 6.1)
+            _ if ip_header.get_hop_limit() != 255 || header.get_code() != 0 => {}
+            ICMP6HeaderOptions::Type134 {
+                router_lifetime, ..
+            } => {
+                if self.started.get() {
+                    self.receive_router_advertisement(&ip_header, router_lifetime, data);
+                }
+            }
+            ICMP6HeaderOptions::Type135 { .. } => {
+                self.receive_neighbor_solicitation(&ip_header, data);
+            }
+            ICMP6HeaderOptions::Type136 { flags } => {
+                self.receive_neighbor_advertisement(&ip_header, flags, data);
+            }
+            _ => {}
+        }
+        self.do_output();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6SendClient for NeighborDiscovery<'a, A> {
+    fn send_done(&self, result: ReturnCode) {
+        if result != ReturnCode::SUCCESS {
+            // Lost solicitations and registrations are retransmitted
+            debug!("[ND] Send failed: {:?}", result);
+        }
+        self.sending.set(false);
+        self.do_output();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for NeighborDiscovery<'a, A> {
+    fn alarm(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(ND_TIMER_MS));
+        self.neighbor_cache.tick(1);
+
+        if self.neighbor_cache.default_router().is_none() {
+            if self.router.is_some() {
+                // The router expired, so the registration is lost with it
+                self.router.clear();
+                self.global_addr.clear();
+                self.registration.set(Registration::Unregistered);
+            }
+            let timer = self.solicitation_timer.get().saturating_sub(1);
+            self.solicitation_timer.set(timer);
+            if timer == 0 {
+                self.solicit_router();
+            }
+        }
+
+        if self.registration.get() != Registration::Unregistered {
+            let timer = self.registration_timer.get().saturating_sub(1);
+            self.registration_timer.set(timer);
+            if timer == 0 {
+                if self.registration.get() == Registration::Registered {
+                    self.registration.set(Registration::Pending { tries: 0 });
+                }
+                self.register();
+            }
+        }
+    }
+}
@@ -1,2 +1,3 @@
 pub mod icmpv6;
+pub mod icmpv6_nd;
 pub mod icmpv6_send;
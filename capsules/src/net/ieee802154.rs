@@ -101,6 +101,13 @@ impl FrameVersion {
     }
 }
 
+/// Identifiers of MAC commands, the first byte of the payload of MAC command
+/// frames.
+pub mod mac_command {
+    /// Asks a coordinator for the frames it holds for the sender
+    pub const DATA_REQUEST: u8 = 0x04;
+}
+
 #[repr(u8)]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum AddressMode {
@@ -307,7 +314,7 @@ impl Security {
         let asn_in_nonce = (scf & security_control::ASN_IN_NONCE) != 0;
 
         // Frame counter field
-        let frame_counter_present = (scf & security_control::FRAME_COUNTER_SUPPRESSION) != 0;
+        let frame_counter_present = (scf & security_control::FRAME_COUNTER_SUPPRESSION) == 0;
         let (off, frame_counter) = if frame_counter_present {
             let (off, frame_counter_be) = dec_try!(buf, off; decode_u32);
             (off, Some(u32::from_be(frame_counter_be)))
@@ -2,9 +2,10 @@
 //! of the IP stack. Note that this file also contains the definition for the
 //! [IPAddr](struct.IPAddr.html) struct and associated helper functions.
 
-use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
+use crate::net::icmpv6::icmpv6::ICMP6Header;
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::ipv6::IP6Header;
+use crate::net::tcp::tcp::TCPHeader;
 use crate::net::udp::udp::UDPHeader;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -74,6 +75,21 @@ impl IPAddr {
         ip_addr
     }
 
+    /// Recovers the 15.4 MAC address from which the interface identifier of
+    /// this address was generated, the inverse of `generate_from_mac`. On a
+    /// 6LoWPAN link this is the link-layer address of the node that owns the
+    /// address.
+    pub fn mac_from_iid(&self) -> MacAddress {
+        if self.0[8..14] == [0x00, 0x00, 0x00, 0xff, 0xfe, 0x00] {
+            MacAddress::Short((self.0[14] as u16) << 8 | self.0[15] as u16)
+        } else {
+            let mut long_addr = [0; 8];
+            long_addr.copy_from_slice(&self.0[8..16]);
+            long_addr[0] ^= 0b00000010;
+            MacAddress::Long(long_addr)
+        }
+    }
+
     pub fn is_unspecified(&self) -> bool {
         self.0.iter().all(|&b| b == 0)
     }
@@ -108,6 +124,22 @@ impl IPAddr {
         }
     }
 
+    /// Returns whether the first `prefix_len` bits of this address are
+    /// equal to those of `prefix`.
+    pub fn matches_prefix(&self, prefix: &IPAddr, prefix_len: u8) -> bool {
+        let prefix_len = prefix_len.min(128);
+        let full_bytes = (prefix_len / 8) as usize;
+        let remaining = prefix_len & 0x7;
+        if self.0[..full_bytes] != prefix.0[..full_bytes] {
+            return false;
+        }
+        if remaining == 0 {
+            return true;
+        }
+        let mask = (0xff as u8) << (8 - remaining);
+        (self.0[full_bytes] ^ prefix.0[full_bytes]) & mask == 0
+    }
+
     pub fn is_multicast(&self) -> bool {
         self.0[0] == 0xff
     }
@@ -180,48 +212,83 @@ pub fn compute_udp_checksum(
     sum as u16 //Return result as u16 in host byte order */
 }
 
+/// Computes the ICMPv6 checksum of a message consisting of `icmp_header`
+/// and `payload`, as carried in a packet with the provided IPv6 header. The
+/// length of the message is taken from `icmp_header`. As for TCP, the
+/// checksum field of `icmp_header` is included in the sum, so it should be
+/// zero when computing the checksum of an outgoing message, and a received
+/// message with a correct checksum yields 0.
 pub fn compute_icmp_checksum(
     ipv6_header: &IP6Header,
     icmp_header: &ICMP6Header,
     payload: &[u8],
 ) -> u16 {
+    let mut header = [0 as u8; 8];
+    if icmp_header.encode(&mut header, 0).done().is_none() {
+        return 0;
+    }
+
     let mut sum: u32 = 0;
 
     // add ipv6 pseudo-header
     sum += compute_ipv6_ph_sum(ipv6_header);
 
-    // add type and code
-    let msb = (icmp_header.get_type_as_int() as u32) << 8;
-    let lsb = icmp_header.get_code() as u32;
-    sum += msb + lsb;
-
-    // add options
-    match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
-            sum += unused >> 16; // upper 16 bits
-            sum += unused & 0xffff; // lower 16 bits
-        }
-        ICMP6HeaderOptions::Type128 { id, seqno } | ICMP6HeaderOptions::Type129 { id, seqno } => {
-            sum += id as u32;
-            sum += seqno as u32;
-        }
-    }
-
-    // add icmp payload
+    // add icmp header and payload
     let payload_len = icmp_header.get_len() - icmp_header.get_hdr_size() as u16;
+    sum += compute_sum(&header, header.len() as u16);
     sum += compute_sum(payload, payload_len);
 
     // carry overflow
     while sum > 0xffff {
-        let sum_upper = sum >> 16;
-        let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = (sum >> 16) + (sum & 0xffff);
     }
 
-    sum = !sum;
-    sum = sum & 0xffff;
+    !sum as u16
+}
+
+/// Computes the TCP checksum of a segment consisting of `tcp_header` and
+/// `payload`, as carried in a packet with the provided IPv6 header. The
+/// checksum field of `tcp_header` is included in the sum, so it should be
+/// zero when computing the checksum of an outgoing segment.
+pub fn compute_tcp_checksum(ip6_header: &IP6Header, tcp_header: &TCPHeader, payload: &[u8]) -> u16 {
+    let mut header = [0 as u8; 60];
+    let hdr_len = match tcp_header.encode(&mut header, 0).done() {
+        Some((offset, _)) => offset,
+        None => return 0,
+    };
+    compute_tcp_segment_checksum(ip6_header, &header[..hdr_len], payload)
+}
+
+/// Computes the TCP checksum over an already serialized segment, split into
+/// the header (including options) and payload. The header length must be a
+/// multiple of two, which is always the case for valid TCP headers. A
+/// received segment with a correct checksum yields 0.
+pub fn compute_tcp_segment_checksum(ip6_header: &IP6Header, header: &[u8], payload: &[u8]) -> u16 {
+    let tcp_len = (header.len() + payload.len()) as u32;
+    let mut sum: u32 = 0;
+
+    // IPv6 pseudo-header: addresses, upper-layer length and next header
+    let mut i = 0;
+    while i < 16 {
+        sum += (ip6_header.src_addr.0[i] as u32) << 8 | ip6_header.src_addr.0[i + 1] as u32;
+        sum += (ip6_header.dst_addr.0[i] as u32) << 8 | ip6_header.dst_addr.0[i + 1] as u32;
+        i += 2;
+    }
+    sum += tcp_len >> 16;
+    sum += tcp_len & 0xffff;
+    sum += ip6_nh::TCP as u32;
+
+    for bytes in header.chunks(2).chain(payload.chunks(2)) {
+        let msb = (bytes[0] as u32) << 8;
+        let lsb = if bytes.len() > 1 { bytes[1] as u32 } else { 0 };
+        sum += msb + lsb;
+    }
+
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
+    }
 
-    sum as u16
+    !sum as u16
 }
 
 pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
@@ -241,19 +308,25 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
 }
 
+/// Sums the first `len` bytes of `buf` as 16-bit big-endian words, padding
+/// an odd trailing byte with zero.
 pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
     let mut sum: u32 = 0;
 
     let mut i: usize = 0;
     while i < (len as usize) {
         let msb = (buf[i] as u32) << 8;
-        let lsb = buf[i + 1] as u32;
+        let lsb = if i + 1 < len as usize {
+            buf[i + 1] as u32
+        } else {
+            0
+        };
         sum += msb + lsb;
         i += 2;
     }
@@ -67,11 +67,14 @@
 // (as required by 6LoWPAN) difficult.
 
 use crate::net::icmpv6::icmpv6::ICMP6Header;
-use crate::net::ipv6::ip_utils::{compute_icmp_checksum, compute_udp_checksum, ip6_nh, IPAddr};
+use crate::net::ipv6::ip_utils::{
+    compute_icmp_checksum, compute_tcp_checksum, compute_tcp_segment_checksum,
+    compute_udp_checksum, ip6_nh, IPAddr,
+};
 use crate::net::stream::SResult;
 use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
 use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
-use crate::net::tcp::TCPHeader;
+use crate::net::tcp::tcp::TCPHeader;
 use crate::net::udp::udp::UDPHeader;
 use kernel::common::leasable_buffer::LeasableBuffer;
 use kernel::ReturnCode;
@@ -281,13 +284,25 @@ impl IP6Header {
                 ReturnCode::SUCCESS
             }
             ip6_nh::ICMP => {
-                // Untested (10/5/18)
-                let mut icmp_header: [u8; ICMP_HDR_LEN] = [0; ICMP_HDR_LEN];
-                icmp_header.copy_from_slice(&buf[..ICMP_HDR_LEN]);
-                let checksum = match ICMP6Header::decode(&icmp_header).done() {
+                if buf.len() < ICMP_HDR_LEN {
+                    return ReturnCode::FAIL;
+                }
+                let checksum = match ICMP6Header::decode(&buf[..ICMP_HDR_LEN]).done() {
                     Some((_offset, mut hdr)) => {
                         hdr.set_len(buf.len() as u16);
-                        u16::from_be(compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..]))
+                        compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..])
+                    }
+                    None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
+                };
+                if checksum != 0 {
+                    return ReturnCode::FAIL; //Incorrect cksum
+                }
+                ReturnCode::SUCCESS
+            }
+            ip6_nh::TCP => {
+                let checksum = match TCPHeader::decode(buf).done() {
+                    Some((offset, _hdr)) => {
+                        compute_tcp_segment_checksum(&self, &buf[..offset], &buf[offset..])
                     }
                     None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
                 };
@@ -304,7 +319,6 @@ impl IP6Header {
 /// This defines the currently supported `TransportHeader` types. The contents
 /// of each header is encapsulated by the enum type. Note that this definition
 /// of `TransportHeader`s means that recursive headers are not supported.
-/// As of now, there is no support for sending raw IP packets without a transport header.
 /// Currently we accept the overhead of copying these structs in/out of an OptionalCell
 /// in `udp_send.rs`.
 #[derive(Copy, Clone)]
@@ -312,6 +326,12 @@ pub enum TransportHeader {
     UDP(UDPHeader),
     TCP(TCPHeader),
     ICMP(ICMP6Header),
+    /// Headers that are already serialized at the start of the payload,
+    /// such as those of a packet forwarded for another node, or extension
+    /// headers inserted in front of a transport header. Holds the value of
+    /// the next header field for the first of them and the length of the
+    /// payload.
+    Raw(u8, u16),
 }
 
 /// The `IPPayload` struct contains a `TransportHeader` and a mutable buffer
@@ -366,10 +386,20 @@ impl<'a> IPPayload<'a> {
             TransportHeader::ICMP(mut icmp_header) => {
                 let length = (payload.len() + icmp_header.get_hdr_size()) as u16;
                 icmp_header.set_len(length);
-                self.header = transport_header;
+                self.header = TransportHeader::ICMP(icmp_header);
                 (ip6_nh::ICMP, length)
             }
-            _ => (ip6_nh::NO_NEXT, payload.len() as u16),
+            TransportHeader::TCP(mut tcp_header) => {
+                let length = (payload.len() + tcp_header.get_hdr_size()) as u16;
+                tcp_header.set_len(length);
+                self.header = TransportHeader::TCP(tcp_header);
+                (ip6_nh::TCP, length)
+            }
+            TransportHeader::Raw(next_header, _) => {
+                let length = payload.len() as u16;
+                self.header = TransportHeader::Raw(next_header, length);
+                (next_header, length)
+            }
         }
     }
 
@@ -388,9 +418,8 @@ impl<'a> IPPayload<'a> {
         let (offset, _) = match self.header {
             TransportHeader::UDP(udp_header) => udp_header.encode(buf, offset).done().unwrap(),
             TransportHeader::ICMP(icmp_header) => icmp_header.encode(buf, offset).done().unwrap(),
-            _ => {
-                unimplemented!();
-            }
+            TransportHeader::TCP(tcp_header) => tcp_header.encode(buf, offset).done().unwrap(),
+            TransportHeader::Raw(..) => (offset, offset),
         };
         let payload_length = self.get_payload_length();
         let offset = enc_consume!(buf, offset; encode_bytes, &self.payload[..payload_length]);
@@ -405,9 +434,10 @@ impl<'a> IPPayload<'a> {
             TransportHeader::ICMP(icmp_header) => {
                 icmp_header.get_len() as usize - icmp_header.get_hdr_size()
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(tcp_header) => {
+                tcp_header.get_len() as usize - tcp_header.get_hdr_size()
             }
+            TransportHeader::Raw(_, len) => len as usize,
         }
     }
 }
@@ -454,7 +484,8 @@ impl<'a> IP6Packet<'a> {
         let transport_hdr_size = match self.payload.header {
             TransportHeader::UDP(udp_hdr) => udp_hdr.get_hdr_size(),
             TransportHeader::ICMP(icmp_header) => icmp_header.get_hdr_size(),
-            _ => unimplemented!(),
+            TransportHeader::TCP(tcp_header) => tcp_header.get_hdr_size(),
+            TransportHeader::Raw(..) => 0,
         };
         40 + transport_hdr_size
     }
@@ -477,12 +508,22 @@ impl<'a> IP6Packet<'a> {
                 udp_header.set_cksum(cksum);
             }
             TransportHeader::ICMP(ref mut icmp_header) => {
+                icmp_header.set_cksum(0);
                 let cksum = compute_icmp_checksum(&self.header, &icmp_header, self.payload.payload);
                 icmp_header.set_cksum(cksum);
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(ref mut tcp_header) => {
+                let payload_len = tcp_header.get_len() as usize - tcp_header.get_hdr_size();
+                tcp_header.set_cksum(0);
+                let cksum = compute_tcp_checksum(
+                    &self.header,
+                    &tcp_header,
+                    &self.payload.payload[..payload_len],
+                );
+                tcp_header.set_cksum(cksum);
             }
+            // The checksum is part of the serialized headers
+            TransportHeader::Raw(..) => {}
         }
     }
 
@@ -510,6 +551,56 @@ impl<'a> IP6Packet<'a> {
         self.header.set_payload_len(payload_len);
     }
 
+    /// This function inserts an extension header of `len` bytes between the
+    /// IPv6 header and the rest of the packet, which is serialized into the
+    /// payload behind it. `encode` writes the extension header, and is
+    /// passed the next header value the extension header must carry. The
+    /// transport checksum must already be set, since the payload becomes
+    /// `TransportHeader::Raw`.
+    ///
+    /// # Arguments
+    ///
+    /// `next_header` - The `ip6_nh` type of the extension header
+    /// `len` - The length of the extension header
+    /// `encode` - Writes the extension header to the buffer it is passed
+    ///
+    /// # Return Value
+    ///
+    /// `bool` - Whether the header was inserted; `false` if the packet does
+    /// not fit in the payload buffer
+    pub fn insert_header<F: FnOnce(&mut [u8], u8)>(
+        &mut self,
+        next_header: u8,
+        len: usize,
+        encode: F,
+    ) -> bool {
+        let hdr_size = self.get_total_hdr_size() - 40;
+        let payload_len = self.payload.get_payload_length();
+        let total_len = len + hdr_size + payload_len;
+        if total_len > self.payload.payload.len() || total_len > u16::MAX as usize {
+            return false;
+        }
+        let buf = &mut *self.payload.payload;
+        buf.copy_within(0..payload_len, len + hdr_size);
+        match self.payload.header {
+            TransportHeader::UDP(udp_header) => {
+                udp_header.encode(buf, len);
+            }
+            TransportHeader::ICMP(icmp_header) => {
+                icmp_header.encode(buf, len);
+            }
+            TransportHeader::TCP(tcp_header) => {
+                tcp_header.encode(buf, len);
+            }
+            TransportHeader::Raw(..) => {}
+        }
+        encode(&mut buf[..len], self.header.next_header);
+        self.payload.header = TransportHeader::Raw(next_header, total_len as u16);
+        self.header.set_next_header(next_header);
+        self.header.set_payload_len(total_len as u16);
+        true
+    }
+
     // TODO: Do we need a decode equivalent? I don't think so, but we might
 
     pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
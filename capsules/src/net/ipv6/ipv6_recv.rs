@@ -1,4 +1,6 @@
+use crate::net::ipv6::ip_utils::ip6_nh;
 use crate::net::ipv6::ipv6::IP6Header;
+use crate::net::ipv6::routing_header::RoutingHeader;
 use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
 use kernel::common::cells::OptionalCell;
 use kernel::debug;
@@ -35,7 +37,9 @@ pub trait IP6RecvClient {
 /// as we do not multiplex received packets based on the address.
 /// The receiver receives IP packets destined for any local address.
 /// The receiver should drop any packets with destination addresses
-/// that are not among the local addresses of this device.
+/// that are not among the local addresses of this device, unless it
+/// forwards them. Packets that still have hops of their source route to
+/// visit are passed up with their routing header.
 pub trait IP6Receiver<'a> {
     fn set_client(&self, client: &'a dyn IP6RecvClient);
 }
@@ -65,7 +69,21 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
             return;
         }
         match IP6Header::decode(buf).done() {
-            Some((offset, ip6_header)) => {
+            Some((mut offset, mut ip6_header)) => {
+                // A routing header with no segments left has brought the
+                // packet to its final destination, and is skipped
+                if ip6_header.get_next_header() == ip6_nh::ROUTING {
+                    if let Some((_, routing_header)) =
+                        RoutingHeader::decode(&buf[offset..len]).done()
+                    {
+                        let header_len = routing_header.len();
+                        if routing_header.segments_left == 0 && offset + header_len <= len {
+                            offset += header_len;
+                            ip6_header.set_next_header(routing_header.next_header);
+                            ip6_header.set_payload_len((len - offset) as u16);
+                        }
+                    }
+                }
                 let checksum_result = ip6_header.check_transport_checksum(&buf[offset..len]);
                 if checksum_result == ReturnCode::FAIL {
                     debug!("cksum fail!: {:?}", checksum_result);
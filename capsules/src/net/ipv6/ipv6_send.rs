@@ -17,9 +17,14 @@
 // interface.
 
 use crate::ieee802154::device::{MacDevice, TxClient};
-use crate::net::ieee802154::MacAddress;
-use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ieee802154::{KeyId, MacAddress, SecurityLevel};
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
 use crate::net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use crate::net::ipv6::neighbor_cache::{NeighborCache, BROADCAST_MAC_ADDR};
+use crate::net::ipv6::routing_header::{
+    encode_source_route, source_route_len, MAX_SOURCE_ROUTE_HOPS,
+};
+use crate::net::ipv6::routing_table::RoutingTable;
 use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
 use crate::net::sixlowpan::sixlowpan_state::TxState;
 use core::cell::Cell;
@@ -38,9 +43,8 @@ pub trait IP6SendClient {
 }
 
 /// This trait provides a basic IPv6 sending interface. It exposes basic
-/// configuration information for the IPv6 layer (setting the source address,
-/// setting the gateway MAC address), as well as a way to send an IPv6
-/// packet.
+/// configuration information for the IPv6 layer (setting the source
+/// address), as well as a way to send an IPv6 packet.
 pub trait IP6Sender<'a> {
     /// This method sets the `IP6SendClient` for the `IP6Sender` instance, which
     /// receives the `send_done` callback when transmission has finished.
@@ -58,13 +62,6 @@ pub trait IP6Sender<'a> {
     /// from this instance of `IP6Sender`
     fn set_addr(&self, src_addr: IPAddr);
 
-    /// This method sets the gateway/next hop MAC address for this `IP6Sender`
-    /// instance.
-    ///
-    /// # Arguments
-    /// `gateway` - MAC address to send the constructed packet to
-    fn set_gateway(&self, gateway: MacAddress);
-
     /// This method sets the `IP6Header` for the `IP6Sender` instance
     ///
     /// # Arguments
@@ -72,6 +69,14 @@ pub trait IP6Sender<'a> {
     /// `IP6Sender` instance will use
     fn set_header(&mut self, ip6_header: IP6Header);
 
+    /// This method sets the link-layer security of the frames that carry
+    /// subsequent packets sent via this `IP6Sender` instance.
+    ///
+    /// # Arguments
+    /// `security` - The security level and key of the frames, or `None` to
+    /// send unsecured frames
+    fn set_security(&self, security: Option<(SecurityLevel, KeyId)>);
+
     /// This method sends the provided transport header and payload to the
     /// given destination IP address
     ///
@@ -86,10 +91,36 @@ pub trait IP6Sender<'a> {
         payload: &LeasableBuffer<'static, u8>,
         net_cap: &'static NetworkCapability,
     ) -> ReturnCode;
+
+    /// This method forwards a packet that was received for another node
+    /// towards its destination, keeping its header other than the hop
+    /// limit, which the caller decrements, and the destination address,
+    /// which the caller updates when the packet is source-routed.
+    ///
+    /// # Arguments
+    /// `ip6_header` - The `IP6Header` of the packet
+    /// `payload` - Everything that follows the IPv6 header in the packet
+    fn forward(
+        &self,
+        ip6_header: IP6Header,
+        payload: &LeasableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> ReturnCode;
 }
 
 /// This struct is a specific implementation of the `IP6Sender` trait. This
 /// struct sends the packet using 6LoWPAN over a generic `MacDevice` object.
+/// The link-layer destination of each packet is looked up in the
+/// `NeighborCache`, if one is set; `dst_mac_addr` is used for destinations
+/// the cache has no next hop for, and for all packets if there is no cache.
+/// If a `RoutingTable` is set and has a route to the destination, the
+/// packet is sent to the next hop of the route instead. Destinations the
+/// routing table has a source route to are sent to the first hop of the
+/// route, with a source routing header listing the rest of it; packets that
+/// already carry one are sent to their destination, the next hop of their
+/// route. Whether unicast
+/// frames are acknowledged is reported to the neighbor cache, which uses it
+/// to estimate the quality of the links to neighbors.
 pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     // We want the ip6_packet field to be a TakeCell so that it is easy to mutate
     ip6_packet: TakeCell<'static, IP6Packet<'static>>,
@@ -97,12 +128,17 @@ pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     // successful reception on receivers with slow copies out of the radio buffer
     // (imix)
     src_addr: Cell<IPAddr>,
-    gateway: Cell<MacAddress>,
+    neighbor_cache: OptionalCell<&'a NeighborCache<'a>>,
+    routing_table: OptionalCell<&'a RoutingTable<'a>>,
+    // Link-layer destination of the packet being sent
+    next_hop_mac_addr: Cell<MacAddress>,
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a dyn MacDevice<'a>,
     dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
+    // Link-layer security of outgoing frames
+    security: Cell<Option<(SecurityLevel, KeyId)>>,
     client: OptionalCell<&'a dyn IP6SendClient>,
     ip_vis: &'static IpVisibilityCapability,
 }
@@ -116,15 +152,15 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         self.src_addr.set(src_addr);
     }
 
-    fn set_gateway(&self, gateway: MacAddress) {
-        self.gateway.set(gateway);
-    }
-
     fn set_header(&mut self, ip6_header: IP6Header) {
         self.ip6_packet
             .map(|ip6_packet| ip6_packet.header = ip6_header);
     }
 
+    fn set_security(&self, security: Option<(SecurityLevel, KeyId)>) {
+        self.security.set(security);
+    }
+
     fn send_to(
         &self,
         dst: IPAddr,
@@ -135,15 +171,30 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         if !net_cap.remote_addr_valid(dst, self.ip_vis) {
             return ReturnCode::FAIL;
         }
-        self.sixlowpan.init(
-            self.src_mac_addr,
-            self.dst_mac_addr,
-            self.radio.get_pan(),
-            None,
-        );
         self.init_packet(dst, transport_header, payload);
-        let ret = self.send_next_fragment();
-        ret
+        self.send_packet()
+    }
+
+    fn forward(
+        &self,
+        ip6_header: IP6Header,
+        payload: &LeasableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> ReturnCode {
+        if !net_cap.remote_addr_valid(ip6_header.dst_addr, self.ip_vis) {
+            return ReturnCode::FAIL;
+        }
+        let fits = self.ip6_packet.map_or(false, |ip6_packet| {
+            payload.len() <= ip6_packet.payload.payload.len()
+        });
+        if !fits {
+            return ReturnCode::ESIZE;
+        }
+        self.ip6_packet.map(|ip6_packet| {
+            ip6_packet.header = ip6_header;
+            ip6_packet.set_payload(TransportHeader::Raw(ip6_header.next_header, 0), payload);
+        });
+        self.send_packet()
     }
 }
 
@@ -162,17 +213,96 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             ip6_packet: TakeCell::new(ip6_packet),
             alarm: alarm,
             src_addr: Cell::new(IPAddr::new()),
-            gateway: Cell::new(dst_mac_addr),
+            neighbor_cache: OptionalCell::empty(),
+            routing_table: OptionalCell::empty(),
+            next_hop_mac_addr: Cell::new(dst_mac_addr),
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
             dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
+            security: Cell::new(None),
             client: OptionalCell::empty(),
             ip_vis: ip_vis,
         }
     }
 
+    /// Sets the `NeighborCache` used to find the next hop of outgoing
+    /// packets.
+    pub fn set_neighbor_cache(&self, neighbor_cache: &'a NeighborCache<'a>) {
+        self.neighbor_cache.set(neighbor_cache);
+    }
+
+    /// Sets the `RoutingTable` consulted for the next hop of outgoing
+    /// packets before the neighbor cache.
+    pub fn set_routing_table(&self, routing_table: &'a RoutingTable<'a>) {
+        self.routing_table.set(routing_table);
+    }
+
+    /// Returns the link-layer address of `neighbor`, as known to the
+    /// neighbor cache or from its interface identifier.
+    fn neighbor_mac_addr(&self, neighbor: IPAddr) -> MacAddress {
+        self.neighbor_cache
+            .and_then(|neighbor_cache| neighbor_cache.lookup(neighbor))
+            .map_or_else(|| neighbor.mac_from_iid(), |entry| entry.mac_addr)
+    }
+
+    fn next_hop(&self, dst: IPAddr) -> MacAddress {
+        let next_hop = self
+            .routing_table
+            .and_then(|routing_table| routing_table.next_hop(dst))
+            .unwrap_or(dst);
+        self.neighbor_cache
+            .and_then(|neighbor_cache| neighbor_cache.next_hop(next_hop))
+            .unwrap_or(self.dst_mac_addr)
+    }
+
+    /// Picks the link-layer destination of the packet, inserting a source
+    /// routing header if the routing table has a source route to its
+    /// destination. Fails with ESIZE if the header does not fit.
+    fn route_packet(&self) -> Result<MacAddress, ReturnCode> {
+        self.ip6_packet
+            .map_or(Err(ReturnCode::ENOMEM), |ip6_packet| {
+                let dst = ip6_packet.header.dst_addr;
+                if ip6_packet.header.next_header == ip6_nh::ROUTING {
+                    return Ok(self.neighbor_mac_addr(dst));
+                }
+                let mut hops = [IPAddr::new(); MAX_SOURCE_ROUTE_HOPS];
+                let count = match self
+                    .routing_table
+                    .and_then(|routing_table| routing_table.source_route(dst, &mut hops))
+                {
+                    Some(count) => count,
+                    None => return Ok(self.next_hop(dst)),
+                };
+                let (first_hop, hops) = hops[..count].split_first().unwrap();
+                let len = source_route_len(first_hop, hops);
+                let inserted =
+                    ip6_packet.insert_header(ip6_nh::ROUTING, len, |buf, next_header| {
+                        encode_source_route(buf, next_header, first_hop, hops);
+                    });
+                if !inserted {
+                    return Err(ReturnCode::ESIZE);
+                }
+                ip6_packet.header.dst_addr = *first_hop;
+                Ok(self.neighbor_mac_addr(*first_hop))
+            })
+    }
+
+    fn send_packet(&self) -> ReturnCode {
+        match self.route_packet() {
+            Ok(next_hop_mac_addr) => self.next_hop_mac_addr.set(next_hop_mac_addr),
+            Err(ret) => return ret,
+        }
+        self.sixlowpan.init(
+            self.src_mac_addr,
+            self.next_hop_mac_addr.get(),
+            self.radio.get_pan(),
+            self.security.get(),
+        );
+        self.send_next_fragment()
+    }
+
     fn init_packet(
         &self,
         dst_addr: IPAddr,
@@ -257,6 +387,13 @@ impl<'a, A: time::Alarm<'a>> time::AlarmClient for IP6SendStruct<'a, A> {
 impl<'a, A: time::Alarm<'a>> TxClient for IP6SendStruct<'a, A> {
     fn send_done(&self, tx_buf: &'static mut [u8], acked: bool, result: ReturnCode) {
         self.tx_buf.replace(tx_buf);
+        let next_hop_mac_addr = self.next_hop_mac_addr.get();
+        if (result == ReturnCode::SUCCESS || result == ReturnCode::ENOACK)
+            && next_hop_mac_addr != BROADCAST_MAC_ADDR
+        {
+            self.neighbor_cache
+                .map(|neighbor_cache| neighbor_cache.report_transmission(next_hop_mac_addr, acked));
+        }
         if result != ReturnCode::SUCCESS {
             debug!("Send Failed: {:?}, acked: {}", result, acked);
             self.client.map(move |client| {
@@ -2,3 +2,6 @@ pub mod ip_utils;
 pub mod ipv6;
 pub mod ipv6_recv;
 pub mod ipv6_send;
+pub mod neighbor_cache;
+pub mod routing_header;
+pub mod routing_table;
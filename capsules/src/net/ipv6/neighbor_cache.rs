@@ -0,0 +1,218 @@
+//! This file contains the `NeighborCache`, a small table mapping the IPv6
+//! addresses of neighbors to their 15.4 MAC addresses. The IPv6 layer
+//! consults it to pick the link-layer destination of outgoing packets, in
+//! place of a single statically configured gateway.
+//!
+//! Next-hop selection follows the 6LoWPAN Neighbor Discovery model of
+//! RFC 6775, where hosts never perform address resolution:
+//!
+//! - Multicast destinations are sent to the 15.4 broadcast address.
+//! - Destinations with an entry in the cache are sent to the MAC address of
+//!   that entry.
+//! - Link-local destinations are sent to the MAC address their interface
+//!   identifier was generated from.
+//! - All other destinations are off-link, and are sent to the default
+//!   router.
+//!
+//! Entries are added by Neighbor Discovery (see
+//! [NeighborDiscovery](../../icmpv6/icmpv6_nd/struct.NeighborDiscovery.html))
+//! or statically by the board, and expire after a lifetime counted in
+//! seconds, which the owner of the cache advances by calling `tick`. Entries
+//! added with a lifetime of `None` never expire. When the cache is full, a
+//! new entry replaces the non-router entry that expires first.
+//!
+//! The cache also estimates the quality of the link to each neighbor as its
+//! expected transmission count (ETX, RFC 6719). The IPv6 layer reports
+//! whether each unicast frame was acknowledged, and the ETX of the neighbor
+//! is updated with an exponentially weighted moving average of these
+//! outcomes. Routing protocols use it to choose between neighbors.
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use kernel::common::cells::TakeCell;
+
+/// The 15.4 broadcast address, used for multicast destinations.
+pub const BROADCAST_MAC_ADDR: MacAddress = MacAddress::Short(0xffff);
+
+/// ETX values are fixed-point numbers with this divisor, as in RFC 6719, so
+/// a perfect link has an ETX of `ETX_DIVISOR`.
+pub const ETX_DIVISOR: u16 = 128;
+/// ETX of a neighbor no frames have been sent to yet.
+pub const INITIAL_ETX: u16 = 2 * ETX_DIVISOR;
+/// ETX sample recorded for a frame that was not acknowledged.
+pub const NOACK_ETX: u16 = 8 * ETX_DIVISOR;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NeighborEntry {
+    pub ip_addr: IPAddr,
+    pub mac_addr: MacAddress,
+    /// Whether the neighbor is a router that can be used as a default
+    /// router.
+    pub is_router: bool,
+    /// Remaining lifetime in seconds, or `None` for a static entry.
+    pub lifetime: Option<u32>,
+    /// Estimated number of transmissions needed to deliver a frame to the
+    /// neighbor, scaled by `ETX_DIVISOR`.
+    pub etx: u16,
+}
+
+pub struct NeighborCache<'a> {
+    entries: TakeCell<'a, [Option<NeighborEntry>]>,
+}
+
+impl<'a> NeighborCache<'a> {
+    /// The size of `entries` determines the number of neighbors the cache
+    /// can hold. All entries should initially be `None`.
+    pub fn new(entries: &'a mut [Option<NeighborEntry>]) -> NeighborCache<'a> {
+        NeighborCache {
+            entries: TakeCell::new(entries),
+        }
+    }
+
+    /// Adds an entry for `ip_addr`, or replaces the existing one. An
+    /// existing static entry stays static, and the link estimate of an
+    /// existing entry is kept. Returns `false` if the cache is
+    /// full of routers and static entries.
+    pub fn update(
+        &self,
+        ip_addr: IPAddr,
+        mac_addr: MacAddress,
+        is_router: bool,
+        lifetime: Option<u32>,
+    ) -> bool {
+        self.entries
+            .map(|entries| {
+                let index = entries
+                    .iter()
+                    .position(|entry| entry.map_or(false, |entry| entry.ip_addr == ip_addr))
+                    .or_else(|| entries.iter().position(|entry| entry.is_none()))
+                    .or_else(|| Self::replaceable(entries));
+                match index {
+                    Some(index) => {
+                        let (lifetime, etx) = match entries[index] {
+                            Some(entry) if entry.ip_addr == ip_addr => {
+                                (entry.lifetime.and(lifetime), entry.etx)
+                            }
+                            _ => (lifetime, INITIAL_ETX),
+                        };
+                        entries[index] = Some(NeighborEntry {
+                            ip_addr: ip_addr,
+                            mac_addr: mac_addr,
+                            is_router: is_router,
+                            lifetime: lifetime,
+                            etx: etx,
+                        });
+                        true
+                    }
+                    None => false,
+                }
+            })
+            .unwrap_or(false)
+    }
+
+    /// The index of the non-router entry with a lifetime that expires
+    /// first.
+    fn replaceable(entries: &[Option<NeighborEntry>]) -> Option<usize> {
+        entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| match entry {
+                Some(NeighborEntry {
+                    is_router: false,
+                    lifetime: Some(lifetime),
+                    ..
+                }) => Some((index, *lifetime)),
+                _ => None,
+            })
+            .min_by_key(|&(_, lifetime)| lifetime)
+            .map(|(index, _)| index)
+    }
+
+    pub fn remove(&self, ip_addr: IPAddr) {
+        self.entries.map(|entries| {
+            for entry in entries.iter_mut() {
+                if entry.map_or(false, |entry| entry.ip_addr == ip_addr) {
+                    *entry = None;
+                }
+            }
+        });
+    }
+
+    pub fn lookup(&self, ip_addr: IPAddr) -> Option<NeighborEntry> {
+        self.entries
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|entry| *entry)
+                    .find(|entry| entry.ip_addr == ip_addr)
+            })
+            .unwrap_or(None)
+    }
+
+    /// Returns the ETX of the link to `ip_addr`, if it is in the cache.
+    pub fn link_etx(&self, ip_addr: IPAddr) -> Option<u16> {
+        self.lookup(ip_addr).map(|entry| entry.etx)
+    }
+
+    /// Updates the link estimate of the neighbors with `mac_addr` after a
+    /// unicast frame was sent to it.
+    pub fn report_transmission(&self, mac_addr: MacAddress, acked: bool) {
+        let sample = if acked { ETX_DIVISOR } else { NOACK_ETX };
+        self.entries.map(|entries| {
+            for entry in entries.iter_mut().filter_map(|entry| entry.as_mut()) {
+                if entry.mac_addr == mac_addr {
+                    entry.etx = ((entry.etx as u32 * 7 + sample as u32) / 8) as u16;
+                }
+            }
+        });
+    }
+
+    /// Returns the router with the longest remaining lifetime, preferring
+    /// static entries.
+    pub fn default_router(&self) -> Option<NeighborEntry> {
+        self.entries
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|entry| *entry)
+                    .filter(|entry| entry.is_router)
+                    .max_by_key(|entry| entry.lifetime.unwrap_or(u32::MAX))
+            })
+            .unwrap_or(None)
+    }
+
+    /// Returns the MAC address to which a packet for `dst` should be sent,
+    /// or `None` if `dst` is off-link and there is no default router.
+    pub fn next_hop(&self, dst: IPAddr) -> Option<MacAddress> {
+        if dst.is_multicast() {
+            return Some(BROADCAST_MAC_ADDR);
+        }
+        if let Some(entry) = self.lookup(dst) {
+            return Some(entry.mac_addr);
+        }
+        if dst.is_unicast_link_local() {
+            return Some(dst.mac_from_iid());
+        }
+        self.default_router().map(|router| router.mac_addr)
+    }
+
+    /// Advances the lifetimes of all entries by `seconds`, removing the
+    /// entries that expire.
+    pub fn tick(&self, seconds: u32) {
+        self.entries.map(|entries| {
+            for entry in entries.iter_mut() {
+                if let Some(NeighborEntry {
+                    lifetime: Some(lifetime),
+                    ..
+                }) = entry
+                {
+                    if *lifetime <= seconds {
+                        *entry = None;
+                    } else {
+                        *lifetime -= seconds;
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,159 @@
+//! This file contains the encode/decode functionality of IPv6 routing
+//! headers, and in particular of the Source Routing Header (SRH) of
+//! RFC 6554, with which the root of a non-storing
+//! [RPL](../../rpl/rpl_node/struct.RplNode.html) DODAG sends packets down the
+//! DODAG.
+//!
+//! A source-routed packet is sent to the first hop of its route, and the
+//! SRH lists the remaining hops, ending with the final destination. Each
+//! hop swaps the next address of the list with the destination address of
+//! the packet and forwards it (`visit_next_hop`), until no segments are
+//! left. Since the hops of a DODAG usually share a prefix, the prefix the
+//! addresses of the list share with the destination address is elided.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u8, encode_bytes, encode_u8};
+
+/// Routing type of the Source Routing Header.
+pub const SOURCE_ROUTING: u8 = 3;
+/// Largest number of hops of a source route, including the first hop and
+/// the final destination.
+pub const MAX_SOURCE_ROUTE_HOPS: usize = 8;
+
+/// Longest prefix that can be elided from the addresses of an SRH.
+const MAX_ELIDED: usize = 15;
+
+/// The fixed part shared by all routing headers (RFC 8200, Section 4.4).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RoutingHeader {
+    pub next_header: u8,
+    /// Length of the header in 8-byte units, not counting the first 8 bytes
+    pub hdr_ext_len: u8,
+    pub routing_type: u8,
+    /// Number of hops left to visit before the final destination
+    pub segments_left: u8,
+}
+
+impl RoutingHeader {
+    pub fn decode(buf: &[u8]) -> SResult<RoutingHeader> {
+        let (off, next_header) = dec_try!(buf; decode_u8);
+        let (off, hdr_ext_len) = dec_try!(buf, off; decode_u8);
+        let (off, routing_type) = dec_try!(buf, off; decode_u8);
+        let (off, segments_left) = dec_try!(buf, off; decode_u8);
+        stream_done!(
+            off,
+            RoutingHeader {
+                next_header: next_header,
+                hdr_ext_len: hdr_ext_len,
+                routing_type: routing_type,
+                segments_left: segments_left,
+            }
+        );
+    }
+
+    /// The length of the whole header in bytes.
+    pub fn len(&self) -> usize {
+        8 + 8 * self.hdr_ext_len as usize
+    }
+}
+
+/// The number of leading bytes `a` and `b` share.
+fn shared_prefix_len(a: &IPAddr, b: &IPAddr) -> usize {
+    a.0.iter()
+        .zip(b.0.iter())
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+/// The number of bytes elided from each address of an SRH from `dst` along
+/// `hops`: the prefix all of them share.
+fn elided_len(dst: &IPAddr, hops: &[IPAddr]) -> usize {
+    hops.iter()
+        .map(|hop| shared_prefix_len(dst, hop))
+        .min()
+        .unwrap_or(0)
+        .min(MAX_ELIDED)
+}
+
+/// The length of the SRH sending a packet addressed to `dst` along `hops`,
+/// the hops that follow `dst`.
+pub fn source_route_len(dst: &IPAddr, hops: &[IPAddr]) -> usize {
+    let addrs_len = hops.len() * (16 - elided_len(dst, hops));
+    8 + (addrs_len + 7) / 8 * 8
+}
+
+/// Encodes the SRH sending a packet addressed to `dst` along `hops`, the
+/// hops that follow `dst`, ending with the final destination. The header
+/// is followed by a header of type `next_header`.
+pub fn encode_source_route(
+    buf: &mut [u8],
+    next_header: u8,
+    dst: &IPAddr,
+    hops: &[IPAddr],
+) -> SResult<usize> {
+    let elided = elided_len(dst, hops);
+    let len = source_route_len(dst, hops);
+    let addrs_len = hops.len() * (16 - elided);
+    let pad = len - 8 - addrs_len;
+    let mut off = 0;
+    off = enc_consume!(buf, off; encode_u8, next_header);
+    off = enc_consume!(buf, off; encode_u8, ((len - 8) / 8) as u8);
+    off = enc_consume!(buf, off; encode_u8, SOURCE_ROUTING);
+    off = enc_consume!(buf, off; encode_u8, hops.len() as u8);
+    off = enc_consume!(buf, off; encode_u8, (elided << 4 | elided) as u8);
+    off = enc_consume!(buf, off; encode_u8, (pad << 4) as u8);
+    off = enc_consume!(buf, off; encode_u8, 0);
+    off = enc_consume!(buf, off; encode_u8, 0);
+    for hop in hops.iter() {
+        off = enc_consume!(buf, off; encode_bytes, &hop.0[elided..]);
+    }
+    off = enc_consume!(buf, off; encode_bytes, &[0; 8][..pad]);
+    stream_done!(off, off);
+}
+
+/// Processes the SRH at the start of `buf` at a hop of its route
+/// (RFC 6554, Section 4.2): the next address of the route is swapped with
+/// `dst`, the destination address of the packet, which becomes the next
+/// hop the packet is forwarded to. Returns `Err` if the packet must be
+/// dropped, because the header is not a valid SRH or the next hop is a
+/// multicast address.
+pub fn visit_next_hop(buf: &mut [u8], dst: &mut IPAddr) -> Result<(), ()> {
+    let header = RoutingHeader::decode(buf).done().ok_or(())?.1;
+    if header.routing_type != SOURCE_ROUTING
+        || header.segments_left == 0
+        || buf.len() < header.len()
+        || dst.is_multicast()
+    {
+        return Err(());
+    }
+    let elided_i = (buf[4] >> 4) as usize;
+    let elided_e = (buf[4] & 0x0f) as usize;
+    let pad = (buf[5] >> 4) as usize;
+    let addrs_len = (header.len() - 8)
+        .checked_sub(pad + 16 - elided_e)
+        .ok_or(())?;
+    let count = addrs_len / (16 - elided_i) + 1;
+    let segments_left = header.segments_left as usize - 1;
+    if segments_left >= count {
+        return Err(());
+    }
+    // Index of the next address, counting from 0
+    let index = count - segments_left - 1;
+    let elided = if index == count - 1 {
+        elided_e
+    } else {
+        elided_i
+    };
+    let off = 8 + index * (16 - elided_i);
+    let addr = &mut buf[off..off + 16 - elided];
+    let mut next_hop = *dst;
+    next_hop.0[elided..].copy_from_slice(addr);
+    if next_hop.is_multicast() {
+        return Err(());
+    }
+    addr.copy_from_slice(&dst.0[elided..]);
+    *dst = next_hop;
+    buf[3] = segments_left as u8;
+    Ok(())
+}
@@ -0,0 +1,225 @@
+//! This file contains the `RoutingTable`, which maps destination prefixes to
+//! the IPv6 address of the next hop towards them. The IPv6 layer consults it
+//! before the neighbor cache: a packet whose destination matches a route is
+//! sent to the link-layer address of the next hop of the longest matching
+//! route, as resolved by the
+//! [NeighborCache](../neighbor_cache/struct.NeighborCache.html). A route
+//! with a prefix length of 0 is a default route.
+//!
+//! Multicast and link-local destinations are never routed, since they are
+//! always on-link.
+//!
+//! A route can also name the parent of the destination rather than a
+//! neighbor, as the root of a non-storing RPL DODAG learns them. Such
+//! destinations are reached by source routing: `source_route` follows the
+//! parents from the destination up to a neighbor, and the IPv6 layer sends
+//! the packet along the resulting path in a routing header (see
+//! [routing_header](../routing_header/index.html)).
+//!
+//! Routes are installed by a routing protocol, such as
+//! [RPL](../../rpl/rpl_node/struct.RplNode.html), or statically by the board.
+//! Like neighbor cache entries, routes expire after a lifetime counted in
+//! seconds, which the owner of the table advances by calling `tick`, and
+//! routes added with a lifetime of `None` never expire.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::routing_header::MAX_SOURCE_ROUTE_HOPS;
+use kernel::common::cells::TakeCell;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Route {
+    pub prefix: IPAddr,
+    pub prefix_len: u8,
+    /// Address of the neighbor packets to the prefix are sent to, or of
+    /// the parent of the prefix for a source route
+    pub next_hop: IPAddr,
+    /// Whether `next_hop` is the parent of the prefix rather than a
+    /// neighbor.
+    pub source_routed: bool,
+    /// Remaining lifetime in seconds, or `None` for a static route.
+    pub lifetime: Option<u32>,
+}
+
+impl Route {
+    fn has_prefix(&self, prefix: &IPAddr, prefix_len: u8) -> bool {
+        self.prefix_len == prefix_len && self.prefix.matches_prefix(prefix, prefix_len)
+    }
+}
+
+pub struct RoutingTable<'a> {
+    routes: TakeCell<'a, [Option<Route>]>,
+}
+
+impl<'a> RoutingTable<'a> {
+    /// The size of `routes` determines the number of routes the table can
+    /// hold. All routes should initially be `None`.
+    pub fn new(routes: &'a mut [Option<Route>]) -> RoutingTable<'a> {
+        RoutingTable {
+            routes: TakeCell::new(routes),
+        }
+    }
+
+    /// Adds a route to `prefix`, or replaces the existing route to the same
+    /// prefix. Returns `false` if the table is full.
+    pub fn add(
+        &self,
+        prefix: IPAddr,
+        prefix_len: u8,
+        next_hop: IPAddr,
+        lifetime: Option<u32>,
+    ) -> bool {
+        self.insert(prefix, prefix_len, next_hop, false, lifetime)
+    }
+
+    /// Adds a route to `prefix` through `parent`, the node the prefix is
+    /// attached to, or replaces the existing route to the same prefix.
+    /// Returns `false` if the table is full.
+    pub fn add_source_route(
+        &self,
+        prefix: IPAddr,
+        prefix_len: u8,
+        parent: IPAddr,
+        lifetime: Option<u32>,
+    ) -> bool {
+        self.insert(prefix, prefix_len, parent, true, lifetime)
+    }
+
+    fn insert(
+        &self,
+        prefix: IPAddr,
+        prefix_len: u8,
+        next_hop: IPAddr,
+        source_routed: bool,
+        lifetime: Option<u32>,
+    ) -> bool {
+        let prefix_len = prefix_len.min(128);
+        self.routes
+            .map(|routes| {
+                let index = routes
+                    .iter()
+                    .position(|route| route.map_or(false, |r| r.has_prefix(&prefix, prefix_len)))
+                    .or_else(|| routes.iter().position(|route| route.is_none()));
+                match index {
+                    Some(index) => {
+                        routes[index] = Some(Route {
+                            prefix: prefix,
+                            prefix_len: prefix_len,
+                            next_hop: next_hop,
+                            source_routed: source_routed,
+                            lifetime: lifetime,
+                        });
+                        true
+                    }
+                    None => false,
+                }
+            })
+            .unwrap_or(false)
+    }
+
+    /// Removes the route to `prefix`, if there is one.
+    pub fn remove(&self, prefix: IPAddr, prefix_len: u8) {
+        self.retain(|route| !route.has_prefix(&prefix, prefix_len));
+    }
+
+    /// Removes all routes through `next_hop`, such as when the neighbor has
+    /// become unreachable.
+    pub fn remove_next_hop(&self, next_hop: IPAddr) {
+        self.retain(|route| route.next_hop != next_hop);
+    }
+
+    /// Removes all routes for which `f` returns false.
+    pub fn retain<F: FnMut(&Route) -> bool>(&self, mut f: F) {
+        self.routes.map(|routes| {
+            for route in routes.iter_mut() {
+                if route.map_or(false, |r| !f(&r)) {
+                    *route = None;
+                }
+            }
+        });
+    }
+
+    /// Calls `f` with each route in the table.
+    pub fn for_each<F: FnMut(&Route)>(&self, mut f: F) {
+        self.routes.map(|routes| {
+            for route in routes.iter().filter_map(|route| route.as_ref()) {
+                f(route);
+            }
+        });
+    }
+
+    /// Returns the longest route matching `dst`, if `dst` can be routed.
+    pub fn lookup(&self, dst: IPAddr) -> Option<Route> {
+        if dst.is_multicast() || dst.is_unicast_link_local() {
+            return None;
+        }
+        self.routes
+            .map(|routes| {
+                routes
+                    .iter()
+                    .filter_map(|route| *route)
+                    .filter(|route| dst.matches_prefix(&route.prefix, route.prefix_len))
+                    .max_by_key(|route| route.prefix_len)
+            })
+            .unwrap_or(None)
+    }
+
+    /// Returns the address of the neighbor a packet for `dst` should be sent
+    /// to, or `None` if there is no route to `dst`. Source-routed
+    /// destinations are sent through the first hop of their path.
+    pub fn next_hop(&self, dst: IPAddr) -> Option<IPAddr> {
+        let route = self.lookup(dst)?;
+        if route.source_routed {
+            let mut hops = [IPAddr::new(); MAX_SOURCE_ROUTE_HOPS];
+            self.source_route(dst, &mut hops)?;
+            self.next_hop(hops[0])
+        } else {
+            Some(route.next_hop)
+        }
+    }
+
+    /// Fills `hops` with the path to `dst` if it is reached by source
+    /// routing, from the neighbor the packet is sent to first up to `dst`
+    /// itself, and returns the number of hops. Returns `None` if `dst` is
+    /// not source-routed, or if its path is broken, loops or does not fit
+    /// in `hops`.
+    pub fn source_route(&self, dst: IPAddr, hops: &mut [IPAddr]) -> Option<usize> {
+        let mut route = self.lookup(dst).filter(|route| route.source_routed)?;
+        let mut addr = dst;
+        let mut count = 0;
+        loop {
+            if count == hops.len() {
+                return None;
+            }
+            hops[count] = addr;
+            count += 1;
+            if !route.source_routed {
+                break;
+            }
+            addr = route.next_hop;
+            // The path ends at a neighbor, which has a route of its own
+            route = self.lookup(addr).filter(|route| route.prefix_len > 0)?;
+        }
+        hops[..count].reverse();
+        Some(count)
+    }
+
+    /// Advances the lifetimes of all routes by `seconds`, removing the
+    /// routes that expire.
+    pub fn tick(&self, seconds: u32) {
+        self.routes.map(|routes| {
+            for route in routes.iter_mut() {
+                if let Some(Route {
+                    lifetime: Some(lifetime),
+                    ..
+                }) = route
+                {
+                    if *lifetime <= seconds {
+                        *route = None;
+                    } else {
+                        *lifetime -= seconds;
+                    }
+                }
+            }
+        });
+    }
+}
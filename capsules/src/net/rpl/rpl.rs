@@ -0,0 +1,572 @@
+//! This file contains the types, constants and encode/decode functionality
+//! of RPL control messages (RFC 6550, Section 6).
+//!
+//! RPL control messages are ICMPv6 messages of type 155, whose code selects
+//! the message: DODAG Information Solicitation (DIS), DODAG Information
+//! Object (DIO), Destination Advertisement Object (DAO) or DAO
+//! Acknowledgement. Each message starts with a base object, followed by
+//! options.
+//!
+//! Since the ICMPv6 header of the stack has a fixed length of eight bytes,
+//! the first four bytes of the base object are carried in the header (see
+//! `ICMP6HeaderOptions::Type155`) and the rest of the message in the
+//! payload. `split_message` and `join_message` convert between a message
+//! body and this representation.
+
+use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
+
+/// The link-local all-RPL-nodes multicast address, ff02::1a.
+pub const ALL_RPL_NODES_ADDR: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x1a]);
+
+/// The rank of a node that is not part of a DODAG.
+pub const INFINITE_RANK: u16 = 0xffff;
+
+/// The lifetime value meaning a route never expires.
+pub const INFINITE_LIFETIME: u8 = 0xff;
+
+/// Codes of RPL control messages.
+pub mod rpl_code {
+    pub const DIS: u8 = 0x00;
+    pub const DIO: u8 = 0x01;
+    pub const DAO: u8 = 0x02;
+    pub const DAO_ACK: u8 = 0x03;
+}
+
+/// Types of RPL control message options.
+pub mod rpl_opt {
+    pub const PAD1: u8 = 0x00;
+    pub const PADN: u8 = 0x01;
+    pub const DAG_METRIC_CONTAINER: u8 = 0x02;
+    pub const ROUTE_INFO: u8 = 0x03;
+    pub const DODAG_CONFIG: u8 = 0x04;
+    pub const TARGET: u8 = 0x05;
+    pub const TRANSIT_INFO: u8 = 0x06;
+    pub const SOLICITED_INFO: u8 = 0x07;
+    pub const PREFIX_INFO: u8 = 0x08;
+}
+
+/// Status values of DAO-ACKs.
+pub mod dao_ack_status {
+    pub const ACCEPTED: u8 = 0;
+    /// Statuses from this value on reject the DAO
+    pub const REJECTED: u8 = 128;
+    /// The DAO was rejected because the routing table is full
+    pub const NO_ROUTE_SPACE: u8 = 129;
+}
+
+/// Modes of operation of a DODAG, which determine how downward routes are
+/// maintained.
+pub mod mop {
+    /// Only upward routes are maintained
+    pub const NO_DOWNWARD: u8 = 0;
+    /// The root maintains downward routes and source-routes packets
+    pub const NON_STORING: u8 = 1;
+    /// Each router maintains downward routes to its sub-DODAG
+    pub const STORING: u8 = 2;
+    pub const STORING_MULTICAST: u8 = 3;
+}
+
+/// Objective Code Points, which identify the objective function of a DODAG.
+pub mod ocp {
+    pub const OF0: u16 = 0;
+    pub const MRHOF: u16 = 1;
+}
+
+/// Flags of the Prefix Information option.
+pub mod pio_flags {
+    pub const ON_LINK: u8 = 0x80;
+    pub const AUTONOMOUS: u8 = 0x40;
+    pub const ROUTER_ADDRESS: u8 = 0x20;
+}
+
+const DIO_GROUNDED: u8 = 0x80;
+const DIO_MOP_SHIFT: u8 = 3;
+const DIO_MOP_MASK: u8 = 0x07;
+const DIO_PRF_MASK: u8 = 0x07;
+const DAO_ACK_REQUESTED: u8 = 0x80;
+const DAO_DODAG_ID_PRESENT: u8 = 0x40;
+const DAO_ACK_DODAG_ID_PRESENT: u8 = 0x80;
+const TRANSIT_EXTERNAL: u8 = 0x80;
+
+/// Builds the ICMPv6 header of an RPL control message from its code and
+/// the first four bytes of `body`, copying the rest of the body to the
+/// start of `body`. Returns the header and the length of the payload. The
+/// body must be at least four bytes long.
+pub fn split_message(code: u8, body: &mut [u8], len: usize) -> (ICMP6Header, usize) {
+    let mut header = ICMP6Header::new(ICMP6Type::Type155);
+    header.set_code(code);
+    let base =
+        (body[0] as u32) << 24 | (body[1] as u32) << 16 | (body[2] as u32) << 8 | body[3] as u32;
+    header.set_options(ICMP6HeaderOptions::Type155 { base: base });
+    body.copy_within(4..len, 0);
+    (header, len - 4)
+}
+
+/// Reassembles the body of an RPL control message from its ICMPv6 header
+/// and payload into `body`, returning the length of the body, or `None` if
+/// the header is not an RPL header. Bodies longer than `body` are
+/// truncated.
+pub fn join_message(header: &ICMP6Header, payload: &[u8], body: &mut [u8]) -> Option<usize> {
+    let base = match header.get_options() {
+        ICMP6HeaderOptions::Type155 { base } => base,
+        _ => return None,
+    };
+    let len = (4 + payload.len()).min(body.len());
+    body[..4].copy_from_slice(&[
+        (base >> 24) as u8,
+        (base >> 16) as u8,
+        (base >> 8) as u8,
+        base as u8,
+    ]);
+    body[4..len].copy_from_slice(&payload[..len - 4]);
+    Some(len)
+}
+
+/// Encodes an address that is only present in some messages.
+fn encode_optional_addr(buf: &mut [u8], addr: Option<IPAddr>) -> SResult {
+    match addr {
+        Some(addr) => encode_bytes(buf, &addr.0),
+        None => stream_done!(0),
+    }
+}
+
+/// The base object of a DODAG Information Object.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Dio {
+    pub instance_id: u8,
+    pub version: u8,
+    pub rank: u16,
+    pub grounded: bool,
+    pub mode_of_operation: u8,
+    pub preference: u8,
+    /// Destination Advertisement Trigger Sequence Number; incremented by a
+    /// node to ask its children to send DAOs again
+    pub dtsn: u8,
+    pub dodag_id: IPAddr,
+}
+
+impl Dio {
+    pub const LEN: usize = 24;
+
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        let mut off = offset;
+        let mut flags = (self.mode_of_operation & DIO_MOP_MASK) << DIO_MOP_SHIFT
+            | self.preference & DIO_PRF_MASK;
+        if self.grounded {
+            flags |= DIO_GROUNDED;
+        }
+        off = enc_consume!(buf, off; encode_u8, self.instance_id);
+        off = enc_consume!(buf, off; encode_u8, self.version);
+        off = enc_consume!(buf, off; encode_u16, self.rank);
+        off = enc_consume!(buf, off; encode_u8, flags);
+        off = enc_consume!(buf, off; encode_u8, self.dtsn);
+        off = enc_consume!(buf, off; encode_u16, 0);
+        off = enc_consume!(buf, off; encode_bytes, &self.dodag_id.0);
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<Dio> {
+        let (off, instance_id) = dec_try!(buf; decode_u8);
+        let (off, version) = dec_try!(buf, off; decode_u8);
+        let (off, rank) = dec_try!(buf, off; decode_u16);
+        let (off, flags) = dec_try!(buf, off; decode_u8);
+        let (off, dtsn) = dec_try!(buf, off; decode_u8);
+        let off = off + 2;
+        let mut dodag_id = IPAddr::new();
+        let off = dec_consume!(buf, off; decode_bytes, &mut dodag_id.0);
+        stream_done!(
+            off,
+            Dio {
+                instance_id: instance_id,
+                version: version,
+                rank: rank,
+                grounded: flags & DIO_GROUNDED != 0,
+                mode_of_operation: (flags >> DIO_MOP_SHIFT) & DIO_MOP_MASK,
+                preference: flags & DIO_PRF_MASK,
+                dtsn: dtsn,
+                dodag_id: dodag_id,
+            }
+        );
+    }
+}
+
+/// Encodes the base object of a DODAG Information Solicitation, which is
+/// padded to four bytes so that it fills the ICMPv6 header.
+pub fn encode_dis(buf: &mut [u8], offset: usize) -> SResult<usize> {
+    let mut off = offset;
+    off = enc_consume!(buf, off; encode_u16, 0);
+    off = enc_consume!(buf, off; encode_u8, rpl_opt::PADN);
+    off = enc_consume!(buf, off; encode_u8, 0);
+    stream_done!(off, off);
+}
+
+/// The base object of a Destination Advertisement Object.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Dao {
+    pub instance_id: u8,
+    /// Whether the recipient should answer with a DAO-ACK
+    pub ack_requested: bool,
+    pub sequence: u8,
+    pub dodag_id: Option<IPAddr>,
+}
+
+impl Dao {
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        let mut off = offset;
+        let mut flags = 0;
+        if self.ack_requested {
+            flags |= DAO_ACK_REQUESTED;
+        }
+        if self.dodag_id.is_some() {
+            flags |= DAO_DODAG_ID_PRESENT;
+        }
+        off = enc_consume!(buf, off; encode_u8, self.instance_id);
+        off = enc_consume!(buf, off; encode_u8, flags);
+        off = enc_consume!(buf, off; encode_u8, 0);
+        off = enc_consume!(buf, off; encode_u8, self.sequence);
+        off = enc_consume!(buf, off; encode_optional_addr, self.dodag_id);
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<Dao> {
+        let (off, instance_id) = dec_try!(buf; decode_u8);
+        let (off, flags) = dec_try!(buf, off; decode_u8);
+        let off = off + 1;
+        let (off, sequence) = dec_try!(buf, off; decode_u8);
+        let (off, dodag_id) = if flags & DAO_DODAG_ID_PRESENT != 0 {
+            let mut dodag_id = IPAddr::new();
+            let off = dec_consume!(buf, off; decode_bytes, &mut dodag_id.0);
+            (off, Some(dodag_id))
+        } else {
+            (off, None)
+        };
+        stream_done!(
+            off,
+            Dao {
+                instance_id: instance_id,
+                ack_requested: flags & DAO_ACK_REQUESTED != 0,
+                sequence: sequence,
+                dodag_id: dodag_id,
+            }
+        );
+    }
+}
+
+/// The base object of a DAO Acknowledgement.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DaoAck {
+    pub instance_id: u8,
+    pub sequence: u8,
+    /// 0 for success; values of 128 and above reject the DAO
+    pub status: u8,
+    pub dodag_id: Option<IPAddr>,
+}
+
+impl DaoAck {
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        let mut off = offset;
+        let flags = if self.dodag_id.is_some() {
+            DAO_ACK_DODAG_ID_PRESENT
+        } else {
+            0
+        };
+        off = enc_consume!(buf, off; encode_u8, self.instance_id);
+        off = enc_consume!(buf, off; encode_u8, flags);
+        off = enc_consume!(buf, off; encode_u8, self.sequence);
+        off = enc_consume!(buf, off; encode_u8, self.status);
+        off = enc_consume!(buf, off; encode_optional_addr, self.dodag_id);
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<DaoAck> {
+        let (off, instance_id) = dec_try!(buf; decode_u8);
+        let (off, flags) = dec_try!(buf, off; decode_u8);
+        let (off, sequence) = dec_try!(buf, off; decode_u8);
+        let (off, status) = dec_try!(buf, off; decode_u8);
+        let (off, dodag_id) = if flags & DAO_ACK_DODAG_ID_PRESENT != 0 {
+            let mut dodag_id = IPAddr::new();
+            let off = dec_consume!(buf, off; decode_bytes, &mut dodag_id.0);
+            (off, Some(dodag_id))
+        } else {
+            (off, None)
+        };
+        stream_done!(
+            off,
+            DaoAck {
+                instance_id: instance_id,
+                sequence: sequence,
+                status: status,
+                dodag_id: dodag_id,
+            }
+        );
+    }
+}
+
+/// The parameters of a DODAG, which the root distributes in the DODAG
+/// Configuration option of its DIOs.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DodagConfig {
+    /// The authentication and path control size fields
+    pub flags: u8,
+    pub dio_interval_doublings: u8,
+    /// The minimum DIO interval is 2^`dio_interval_min` milliseconds
+    pub dio_interval_min: u8,
+    pub dio_redundancy: u8,
+    pub max_rank_increase: u16,
+    pub min_hop_rank_increase: u16,
+    pub objective_code_point: u16,
+    /// Lifetime of routes, in units of `lifetime_unit` seconds
+    pub default_lifetime: u8,
+    pub lifetime_unit: u16,
+}
+
+impl Default for DodagConfig {
+    /// The parameters used by DODAGs this node is the root of, and for
+    /// DODAGs whose DIOs have not carried a configuration yet.
+    fn default() -> DodagConfig {
+        DodagConfig {
+            flags: 0,
+            dio_interval_doublings: 8,
+            dio_interval_min: 12,
+            dio_redundancy: 10,
+            max_rank_increase: 0,
+            min_hop_rank_increase: 256,
+            objective_code_point: ocp::MRHOF,
+            default_lifetime: 30,
+            lifetime_unit: 60,
+        }
+    }
+}
+
+impl DodagConfig {
+    /// The lifetime of routes in seconds, or `None` if they never expire.
+    pub fn route_lifetime(&self) -> Option<u32> {
+        if self.default_lifetime == INFINITE_LIFETIME {
+            None
+        } else {
+            Some(self.default_lifetime as u32 * self.lifetime_unit as u32)
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PrefixInfo {
+    pub prefix_len: u8,
+    pub flags: u8,
+    pub valid_lifetime: u32,
+    pub preferred_lifetime: u32,
+    pub prefix: IPAddr,
+}
+
+/// An option of an RPL control message.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RplOption {
+    /// Padding, of the given total length
+    Pad(u8),
+    DodagConfig(DodagConfig),
+    /// A destination advertised in a DAO
+    Target {
+        prefix_len: u8,
+        prefix: IPAddr,
+    },
+    /// Describes the path to the targets preceding it in a DAO
+    TransitInfo {
+        external: bool,
+        path_control: u8,
+        path_sequence: u8,
+        /// Lifetime of the path in lifetime units; 0 removes the path
+        path_lifetime: u8,
+        /// The parent of the advertising node, in non-storing mode
+        parent: Option<IPAddr>,
+    },
+    PrefixInfo(PrefixInfo),
+    /// An option this implementation does not process
+    Unknown(u8),
+}
+
+impl RplOption {
+    /// Serializes an `RplOption` into a buffer. Unknown options cannot be
+    /// encoded.
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        let mut off = offset;
+        match *self {
+            RplOption::Pad(1) => {
+                off = enc_consume!(buf, off; encode_u8, rpl_opt::PAD1);
+            }
+            RplOption::Pad(len) => {
+                stream_cond!(len >= 2);
+                off = enc_consume!(buf, off; encode_u8, rpl_opt::PADN);
+                off = enc_consume!(buf, off; encode_u8, len - 2);
+                for _ in 2..len {
+                    off = enc_consume!(buf, off; encode_u8, 0);
+                }
+            }
+            RplOption::DodagConfig(config) => {
+                off = enc_consume!(buf, off; encode_u8, rpl_opt::DODAG_CONFIG);
+                off = enc_consume!(buf, off; encode_u8, 14);
+                off = enc_consume!(buf, off; encode_u8, config.flags);
+                off = enc_consume!(buf, off; encode_u8, config.dio_interval_doublings);
+                off = enc_consume!(buf, off; encode_u8, config.dio_interval_min);
+                off = enc_consume!(buf, off; encode_u8, config.dio_redundancy);
+                off = enc_consume!(buf, off; encode_u16, config.max_rank_increase);
+                off = enc_consume!(buf, off; encode_u16, config.min_hop_rank_increase);
+                off = enc_consume!(buf, off; encode_u16, config.objective_code_point);
+                off = enc_consume!(buf, off; encode_u8, 0);
+                off = enc_consume!(buf, off; encode_u8, config.default_lifetime);
+                off = enc_consume!(buf, off; encode_u16, config.lifetime_unit);
+            }
+            RplOption::Target { prefix_len, prefix } => {
+                stream_cond!(prefix_len <= 128);
+                let prefix_bytes = (prefix_len as usize + 7) / 8;
+                off = enc_consume!(buf, off; encode_u8, rpl_opt::TARGET);
+                off = enc_consume!(buf, off; encode_u8, 2 + prefix_bytes as u8);
+                off = enc_consume!(buf, off; encode_u8, 0);
+                off = enc_consume!(buf, off; encode_u8, prefix_len);
+                off = enc_consume!(buf, off; encode_bytes, &prefix.0[..prefix_bytes]);
+            }
+            RplOption::TransitInfo {
+                external,
+                path_control,
+                path_sequence,
+                path_lifetime,
+                parent,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, rpl_opt::TRANSIT_INFO);
+                off = enc_consume!(buf, off; encode_u8, if parent.is_some() { 20 } else { 4 });
+                off =
+                    enc_consume!(buf, off; encode_u8, if external { TRANSIT_EXTERNAL } else { 0 });
+                off = enc_consume!(buf, off; encode_u8, path_control);
+                off = enc_consume!(buf, off; encode_u8, path_sequence);
+                off = enc_consume!(buf, off; encode_u8, path_lifetime);
+                off = enc_consume!(buf, off; encode_optional_addr, parent);
+            }
+            RplOption::PrefixInfo(info) => {
+                off = enc_consume!(buf, off; encode_u8, rpl_opt::PREFIX_INFO);
+                off = enc_consume!(buf, off; encode_u8, 30);
+                off = enc_consume!(buf, off; encode_u8, info.prefix_len);
+                off = enc_consume!(buf, off; encode_u8, info.flags);
+                off = enc_consume!(buf, off; encode_u32, info.valid_lifetime);
+                off = enc_consume!(buf, off; encode_u32, info.preferred_lifetime);
+                off = enc_consume!(buf, off; encode_u32, 0);
+                off = enc_consume!(buf, off; encode_bytes, &info.prefix.0);
+            }
+            RplOption::Unknown(_) => stream_err!(),
+        }
+        stream_done!(off, off);
+    }
+
+    /// Deserializes an `RplOption` from the start of a buffer. Returns the
+    /// option and the offset of the next option.
+    pub fn decode(buf: &[u8]) -> SResult<RplOption> {
+        let (off, opt_type) = dec_try!(buf; decode_u8);
+        if opt_type == rpl_opt::PAD1 {
+            stream_done!(off, RplOption::Pad(1));
+        }
+        let (off, len) = dec_try!(buf, off; decode_u8);
+        let opt_len = 2 + len as usize;
+        stream_len_cond!(buf, opt_len);
+
+        let option = match opt_type {
+            rpl_opt::PADN => {
+                stream_cond!(opt_len <= u8::MAX as usize);
+                RplOption::Pad(opt_len as u8)
+            }
+            rpl_opt::DODAG_CONFIG => {
+                stream_cond!(len >= 14);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, dio_interval_doublings) = dec_try!(buf, off; decode_u8);
+                let (off, dio_interval_min) = dec_try!(buf, off; decode_u8);
+                let (off, dio_redundancy) = dec_try!(buf, off; decode_u8);
+                let (off, max_rank_increase) = dec_try!(buf, off; decode_u16);
+                let (off, min_hop_rank_increase) = dec_try!(buf, off; decode_u16);
+                let (off, objective_code_point) = dec_try!(buf, off; decode_u16);
+                let off = off + 1;
+                let (off, default_lifetime) = dec_try!(buf, off; decode_u8);
+                let (_, lifetime_unit) = dec_try!(buf, off; decode_u16);
+                RplOption::DodagConfig(DodagConfig {
+                    flags: flags,
+                    dio_interval_doublings: dio_interval_doublings,
+                    dio_interval_min: dio_interval_min,
+                    dio_redundancy: dio_redundancy,
+                    max_rank_increase: max_rank_increase,
+                    min_hop_rank_increase: min_hop_rank_increase,
+                    objective_code_point: objective_code_point,
+                    default_lifetime: default_lifetime,
+                    lifetime_unit: lifetime_unit,
+                })
+            }
+            rpl_opt::TARGET => {
+                stream_cond!(len >= 2);
+                let off = off + 1;
+                let (off, prefix_len) = dec_try!(buf, off; decode_u8);
+                let prefix_bytes = (prefix_len as usize + 7) / 8;
+                stream_cond!(prefix_len <= 128 && prefix_bytes <= len as usize - 2);
+                let mut prefix = IPAddr::new();
+                dec_try!(buf, off; decode_bytes, &mut prefix.0[..prefix_bytes]);
+                RplOption::Target {
+                    prefix_len: prefix_len,
+                    prefix: prefix,
+                }
+            }
+            rpl_opt::TRANSIT_INFO => {
+                stream_cond!(len >= 4);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, path_control) = dec_try!(buf, off; decode_u8);
+                let (off, path_sequence) = dec_try!(buf, off; decode_u8);
+                let (off, path_lifetime) = dec_try!(buf, off; decode_u8);
+                let parent = if len >= 20 {
+                    let mut parent = IPAddr::new();
+                    dec_try!(buf, off; decode_bytes, &mut parent.0);
+                    Some(parent)
+                } else {
+                    None
+                };
+                RplOption::TransitInfo {
+                    external: flags & TRANSIT_EXTERNAL != 0,
+                    path_control: path_control,
+                    path_sequence: path_sequence,
+                    path_lifetime: path_lifetime,
+                    parent: parent,
+                }
+            }
+            rpl_opt::PREFIX_INFO => {
+                stream_cond!(len >= 30);
+                let (off, prefix_len) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, valid_lifetime) = dec_try!(buf, off; decode_u32);
+                let (off, preferred_lifetime) = dec_try!(buf, off; decode_u32);
+                let off = off + 4;
+                let mut prefix = IPAddr::new();
+                dec_try!(buf, off; decode_bytes, &mut prefix.0);
+                RplOption::PrefixInfo(PrefixInfo {
+                    prefix_len: prefix_len,
+                    flags: flags,
+                    valid_lifetime: valid_lifetime,
+                    preferred_lifetime: preferred_lifetime,
+                    prefix: prefix,
+                })
+            }
+            _ => RplOption::Unknown(opt_type),
+        };
+        stream_done!(opt_len, option);
+    }
+}
+
+/// Decodes the options in `buf`, stopping at the first malformed option.
+pub fn for_each_option<F: FnMut(RplOption)>(buf: &[u8], mut f: F) {
+    let mut off = 0;
+    while off < buf.len() {
+        match RplOption::decode(&buf[off..]).done() {
+            Some((len, option)) => {
+                f(option);
+                off += len;
+            }
+            None => break,
+        }
+    }
+}
@@ -0,0 +1,1100 @@
+//! This file contains `RplNode`, an implementation of the RPL routing
+//! protocol (RFC 6550) for multi-hop 6LoWPAN networks. RPL organizes the
+//! nodes of a network into a Destination-Oriented DAG (DODAG) rooted at a
+//! border router, and fills the
+//! [RoutingTable](../../ipv6/routing_table/struct.RoutingTable.html) that
+//! the IPv6 layer consults for the next hop of outgoing packets.
+//!
+//! A node joins a DODAG as follows:
+//!
+//! - Once started, the node multicasts DODAG Information Solicitations
+//!   (DIS) every `DIS_INTERVAL` seconds until it joins a DODAG.
+//! - Routers advertise the DODAG in DODAG Information Objects (DIO), which
+//!   carry their rank, the configuration of the DODAG and its prefix. Each
+//!   router that advertises a lower rank than the node is a candidate
+//!   parent.
+//! - The node selects the candidate with the cheapest path to the root as
+//!   its preferred parent, using the Minimum Rank with Hysteresis Objective
+//!   Function (MRHOF, RFC 6719) with the ETX metric: the cost of the path
+//!   through a parent is the rank of the parent plus the ETX of the link to
+//!   it, as estimated by the
+//!   [NeighborCache](../../ipv6/neighbor_cache/struct.NeighborCache.html).
+//!   The node only switches to a new parent if its path is cheaper by
+//!   `PARENT_SWITCH_THRESHOLD`. The preferred parent becomes the default
+//!   route of the routing table.
+//! - The node forms a global address from the prefix of the DODAG and
+//!   advertises it, along with the global addresses it was configured with,
+//!   in Destination Advertisement Objects (DAO). In storing mode the DAO is
+//!   sent to the parent, which adds routes to the targets, and which
+//!   advertises them in turn to its own parent; in non-storing mode it is
+//!   sent to the root and names the parent of the node. DAOs are
+//!   acknowledged and refreshed before their routes expire; a parent that
+//!   does not acknowledge `DAO_MAX_TRANSMISSIONS` DAOs is dropped.
+//! - The node becomes a router of the DODAG itself, sending DIOs on a
+//!   Trickle timer (see [Trickle](../../trickle/struct.Trickle.html)) that
+//!   is reset when its parent or rank changes, or when a DIS is received.
+//! - If the node loses all parents it detaches: it advertises an infinite
+//!   rank, so that its children look for other parents, and solicits DIOs
+//!   again. A DIO announcing a new version of the DODAG makes the node
+//!   rejoin it from scratch.
+//!
+//! A node can also be started as the root of a DODAG with `start_root`. The
+//! root of a non-storing DODAG adds a source route to the routing table for
+//! each target of the DAOs it receives, through the parent the DAO names,
+//! so that the IPv6 layer sends packets down the DODAG with a source
+//! routing header (see
+//! [routing_header](../../ipv6/routing_header/index.html)).
+//!
+//! The members of a DODAG forward the packets they receive for other nodes:
+//! upwards along the default route, downwards along the routes of the
+//! routing table, and along the source route of packets that carry one.
+//! One packet waits to be forwarded at a time, behind the RPL messages of
+//! the node; packets received in the meantime are dropped, as are packets
+//! whose hop limit runs out. Only one DODAG is joined at a time.
+//!
+//! All timers besides Trickle are counted in seconds, driven by an alarm
+//! that fires every `RPL_TIMER_MS` once the node has been started, which
+//! also ages the routes of the routing table. As in Neighbor Discovery,
+//! outgoing messages are queued and sent one at a time in order of
+//! priority.
+
+use crate::net::icmpv6::icmpv6::ICMP6Header;
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::neighbor_cache::{NeighborCache, INITIAL_ETX};
+use crate::net::ipv6::routing_header::visit_next_hop;
+use crate::net::ipv6::routing_table::RoutingTable;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::rpl::rpl::{dao_ack_status, mop, pio_flags, rpl_code};
+use crate::net::rpl::rpl::{
+    encode_dis, for_each_option, join_message, split_message, ALL_RPL_NODES_ADDR,
+    INFINITE_LIFETIME, INFINITE_RANK,
+};
+use crate::net::rpl::rpl::{Dao, DaoAck, Dio, DodagConfig, PrefixInfo, RplOption};
+use crate::net::trickle::{Trickle, TrickleClient};
+use core::cell::Cell;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::debug;
+use kernel::hil::time;
+use kernel::ReturnCode;
+
+/// Period of the timer that drives RPL.
+pub const RPL_TIMER_MS: u32 = 1000;
+/// Seconds between DODAG Information Solicitations while the node has no
+/// parent.
+pub const DIS_INTERVAL: u32 = 30;
+/// Seconds from a change of the parent or of the advertised targets to the
+/// DAO announcing it, so that changes in quick succession share one DAO.
+pub const DAO_DELAY: u32 = 1;
+/// Seconds to wait for a DAO-ACK before sending the DAO again.
+pub const DAO_ACK_TIMEOUT: u32 = 4;
+/// Number of unacknowledged DAOs after which the parent is considered
+/// unreachable.
+pub const DAO_MAX_TRANSMISSIONS: u8 = 3;
+/// Number of candidate parents the node keeps track of.
+pub const MAX_PARENTS: usize = 4;
+/// The node only switches to a new preferred parent if the path through it
+/// is cheaper by this much, 1.5 ETX (RFC 6719, Section 5).
+pub const PARENT_SWITCH_THRESHOLD: u16 = 192;
+/// RPL instance of the DODAGs this node is the root of.
+pub const DEFAULT_INSTANCE_ID: u8 = 0x1e;
+/// Initial version number of the DODAGs this node is the root of, the
+/// start of the lollipop counter (RFC 6550, Section 7.2).
+pub const INITIAL_VERSION: u8 = 240;
+
+/// Number of targets of a DAO that are processed before their transit
+/// information is reached.
+const MAX_DAO_TARGETS: usize = 8;
+/// RPL messages are copied to the stack to be decoded, and truncated to
+/// this length.
+const MAX_MESSAGE_LEN: usize = 128;
+
+/// Returns whether version `a` of a DODAG is newer than version `b`.
+/// Versions are compared as circular sequence numbers.
+fn is_newer(a: u8, b: u8) -> bool {
+    (a.wrapping_sub(b) as i8) > 0
+}
+
+#[derive(Copy, Clone)]
+struct Dodag {
+    instance_id: u8,
+    version: u8,
+    dodag_id: IPAddr,
+    mode_of_operation: u8,
+    grounded: bool,
+    preference: u8,
+    config: DodagConfig,
+    prefix: Option<PrefixInfo>,
+}
+
+/// A candidate parent, as known from its last DIO.
+#[derive(Copy, Clone)]
+struct Parent {
+    addr: IPAddr,
+    rank: u16,
+    dtsn: u8,
+}
+
+pub struct RplNode<'a, A: time::Alarm<'a>> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    neighbor_cache: &'a NeighborCache<'a>,
+    routing_table: &'a RoutingTable<'a>,
+    trickle: &'a Trickle<'a, A>,
+    alarm: &'a A,
+    link_local_addr: IPAddr,
+    local_addrs: &'static [IPAddr],
+    /// The address formed from the prefix of the DODAG
+    global_addr: OptionalCell<IPAddr>,
+    dodag: OptionalCell<Dodag>,
+    is_root: Cell<bool>,
+    rank: Cell<u16>,
+    /// Our Destination Advertisement Trigger Sequence Number
+    dtsn: Cell<u8>,
+    parents: MapCell<[Option<Parent>; MAX_PARENTS]>,
+    preferred_parent: OptionalCell<IPAddr>,
+    dao_sequence: Cell<u8>,
+    path_sequence: Cell<u8>,
+    /// Seconds until the next DAO, or 0 if none is scheduled
+    dao_timer: Cell<u32>,
+    dao_transmissions: Cell<u8>,
+    /// Sequence number of the DAO waiting to be acknowledged
+    awaiting_dao_ack: OptionalCell<u8>,
+    /// Seconds until the next DIS
+    dis_timer: Cell<u32>,
+    started: Cell<bool>,
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    forward_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    sending: Cell<bool>,
+    /// Header and length of the packet in `forward_buffer`
+    pending_forward: OptionalCell<(IP6Header, usize)>,
+    /// Destination, sequence number and status of a pending DAO-ACK
+    pending_dao_ack: OptionalCell<(IPAddr, u8, u8)>,
+    /// Destination of a DIO answering a unicast DIS
+    pending_unicast_dio: OptionalCell<IPAddr>,
+    pending_dio: Cell<bool>,
+    pending_dao: Cell<bool>,
+    pending_dis: Cell<bool>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> RplNode<'a, A> {
+    /// `local_addrs` are the global addresses, besides the one formed from
+    /// the prefix of the DODAG, that the node advertises in DAOs; others
+    /// are ignored. `tx_buffer` holds one outgoing message, and must be at
+    /// least 80 bytes long; larger buffers let DAOs carry more targets.
+    /// `forward_buffer` holds the payload of a packet being forwarded, and
+    /// limits the size of the packets the node forwards.
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        neighbor_cache: &'a NeighborCache<'a>,
+        routing_table: &'a RoutingTable<'a>,
+        trickle: &'a Trickle<'a, A>,
+        alarm: &'a A,
+        src_mac_addr: MacAddress,
+        local_addrs: &'static [IPAddr],
+        tx_buffer: &'static mut [u8],
+        forward_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> RplNode<'a, A> {
+        RplNode {
+            ip_sender: ip_sender,
+            neighbor_cache: neighbor_cache,
+            routing_table: routing_table,
+            trickle: trickle,
+            alarm: alarm,
+            link_local_addr: IPAddr::generate_from_mac(src_mac_addr),
+            local_addrs: local_addrs,
+            global_addr: OptionalCell::empty(),
+            dodag: OptionalCell::empty(),
+            is_root: Cell::new(false),
+            rank: Cell::new(INFINITE_RANK),
+            dtsn: Cell::new(0),
+            parents: MapCell::new([None; MAX_PARENTS]),
+            preferred_parent: OptionalCell::empty(),
+            dao_sequence: Cell::new(0),
+            path_sequence: Cell::new(0),
+            dao_timer: Cell::new(0),
+            dao_transmissions: Cell::new(0),
+            awaiting_dao_ack: OptionalCell::empty(),
+            dis_timer: Cell::new(0),
+            started: Cell::new(false),
+            tx_buffer: MapCell::new(LeasableBuffer::new(tx_buffer)),
+            forward_buffer: MapCell::new(LeasableBuffer::new(forward_buffer)),
+            sending: Cell::new(false),
+            pending_forward: OptionalCell::empty(),
+            pending_dao_ack: OptionalCell::empty(),
+            pending_unicast_dio: OptionalCell::empty(),
+            pending_dio: Cell::new(false),
+            pending_dao: Cell::new(false),
+            pending_dis: Cell::new(false),
+            net_cap: net_cap,
+        }
+    }
+
+    /// Starts looking for a DODAG to join.
+    pub fn start(&self) {
+        if self.started.get() {
+            return;
+        }
+        self.started.set(true);
+        self.solicit();
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(RPL_TIMER_MS));
+    }
+
+    /// Starts as the root of a new DODAG identified by `dodag_id`, a global
+    /// address of this node whose first 64 bits are advertised as the
+    /// prefix of the DODAG. `mode_of_operation` is `mop::STORING` or
+    /// `mop::NON_STORING`.
+    pub fn start_root(&self, dodag_id: IPAddr, mode_of_operation: u8) {
+        if self.started.get() {
+            return;
+        }
+        self.started.set(true);
+        self.is_root.set(true);
+        let config = DodagConfig::default();
+        let mut prefix = IPAddr::new();
+        prefix.set_prefix(&dodag_id.0, 64);
+        self.dodag.set(Dodag {
+            instance_id: DEFAULT_INSTANCE_ID,
+            version: INITIAL_VERSION,
+            dodag_id: dodag_id,
+            mode_of_operation: mode_of_operation,
+            grounded: true,
+            preference: 0,
+            config: config,
+            prefix: Some(PrefixInfo {
+                prefix_len: 64,
+                flags: pio_flags::AUTONOMOUS,
+                valid_lifetime: u32::MAX,
+                preferred_lifetime: u32::MAX,
+                prefix: prefix,
+            }),
+        });
+        self.global_addr.set(dodag_id);
+        self.rank.set(config.min_hop_rank_increase);
+        self.start_trickle(&config);
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(RPL_TIMER_MS));
+    }
+
+    pub fn get_link_local_addr(&self) -> IPAddr {
+        self.link_local_addr
+    }
+
+    /// Returns the global address formed from the prefix of the DODAG, if
+    /// the node has joined one.
+    pub fn get_global_addr(&self) -> Option<IPAddr> {
+        self.global_addr.map(|addr| *addr)
+    }
+
+    pub fn get_rank(&self) -> u16 {
+        self.rank.get()
+    }
+
+    pub fn get_preferred_parent(&self) -> Option<IPAddr> {
+        self.preferred_parent.map(|addr| *addr)
+    }
+
+    /// Returns the DODAG ID of the DODAG the node is part of.
+    pub fn get_dodag_id(&self) -> Option<IPAddr> {
+        if self.is_joined() {
+            self.dodag.map(|dodag| dodag.dodag_id)
+        } else {
+            None
+        }
+    }
+
+    fn is_joined(&self) -> bool {
+        self.is_root.get() || self.preferred_parent.is_some()
+    }
+
+    /// Returns whether `addr` is an address of this node. This includes
+    /// the addresses formed from the interface identifier of its link-local
+    /// address, which is how children name their parent in non-storing
+    /// DAOs.
+    fn is_local(&self, addr: &IPAddr) -> bool {
+        addr.0[8..] == self.link_local_addr.0[8..]
+            || self.global_addr.contains(addr)
+            || self.local_addrs.contains(addr)
+    }
+
+    fn solicit(&self) {
+        self.dis_timer.set(DIS_INTERVAL);
+        self.pending_dis.set(true);
+        self.do_output();
+    }
+
+    fn start_trickle(&self, config: &DodagConfig) {
+        let imin_ms = 1u32 << config.dio_interval_min.min(31);
+        self.trickle.configure(
+            imin_ms,
+            config.dio_interval_doublings,
+            config.dio_redundancy,
+        );
+        self.trickle.start();
+    }
+
+    /// The cost of the path to the root through `parent`.
+    fn path_cost(&self, parent: &Parent) -> u16 {
+        let etx = self
+            .neighbor_cache
+            .link_etx(parent.addr)
+            .unwrap_or(INITIAL_ETX);
+        parent.rank.saturating_add(etx)
+    }
+
+    /// The rank of the node if `parent` is its preferred parent
+    /// (RFC 6719, Section 3.3).
+    fn rank_through(&self, parent: &Parent, config: &DodagConfig) -> u16 {
+        self.path_cost(parent)
+            .max(parent.rank.saturating_add(config.min_hop_rank_increase))
+    }
+
+    /// Adds or updates a candidate parent. When the parent set is full, the
+    /// candidate with the highest rank is replaced if the new one has a
+    /// lower rank. Returns whether the DTSN of the parent changed.
+    fn update_parent(&self, parent: Parent) -> bool {
+        let preferred_parent = self.preferred_parent.map(|addr| *addr);
+        self.parents
+            .map(|parents| {
+                if let Some(existing) = parents
+                    .iter_mut()
+                    .filter_map(|p| p.as_mut())
+                    .find(|p| p.addr == parent.addr)
+                {
+                    let dtsn_changed = existing.dtsn != parent.dtsn;
+                    *existing = parent;
+                    return dtsn_changed;
+                }
+                let index = parents.iter().position(|p| p.is_none()).or_else(|| {
+                    parents
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(index, p)| p.map(|p| (index, p)))
+                        .filter(|(_, p)| Some(p.addr) != preferred_parent)
+                        .max_by_key(|(_, p)| p.rank)
+                        .filter(|(_, p)| p.rank > parent.rank)
+                        .map(|(index, _)| index)
+                });
+                if let Some(index) = index {
+                    parents[index] = Some(parent);
+                }
+                false
+            })
+            .unwrap_or(false)
+    }
+
+    fn remove_parent(&self, addr: IPAddr) {
+        self.parents.map(|parents| {
+            for parent in parents.iter_mut() {
+                if parent.map_or(false, |p| p.addr == addr) {
+                    *parent = None;
+                }
+            }
+        });
+    }
+
+    /// Selects the preferred parent and computes the rank of the node,
+    /// detaching from the DODAG if there is no acceptable parent. Returns
+    /// whether the parent or the rank changed significantly.
+    fn select_parent(&self) -> bool {
+        let dodag = match self.dodag.map(|dodag| *dodag) {
+            Some(dodag) => dodag,
+            None => return false,
+        };
+        if self.is_root.get() {
+            return false;
+        }
+        let current = self.preferred_parent.map(|addr| *addr);
+        let rank = self.rank.get();
+        let (best, current_parent) = self
+            .parents
+            .map(|parents| {
+                let mut best: Option<Parent> = None;
+                let mut current_parent = None;
+                for parent in parents.iter().filter_map(|p| *p) {
+                    if Some(parent.addr) == current {
+                        current_parent = Some(parent);
+                    } else if parent.rank >= rank {
+                        // Choosing a node that is not closer to the root
+                        // than this one could create a loop
+                        continue;
+                    }
+                    if best.map_or(true, |b| self.path_cost(&parent) < self.path_cost(&b)) {
+                        best = Some(parent);
+                    }
+                }
+                (best, current_parent)
+            })
+            .unwrap_or((None, None));
+
+        let parent = match (current_parent, best) {
+            (Some(current_parent), Some(best))
+                if self
+                    .path_cost(&best)
+                    .saturating_add(PARENT_SWITCH_THRESHOLD)
+                    >= self.path_cost(&current_parent) =>
+            {
+                current_parent
+            }
+            (_, Some(best)) => best,
+            (_, None) => {
+                if current.is_some() {
+                    self.detach();
+                    return true;
+                }
+                return false;
+            }
+        };
+
+        let new_rank = self.rank_through(&parent, &dodag.config);
+        self.rank.set(new_rank);
+        if current != Some(parent.addr) {
+            self.preferred_parent.set(parent.addr);
+            self.routing_table.add(IPAddr::new(), 0, parent.addr, None);
+            self.path_sequence
+                .set(self.path_sequence.get().wrapping_add(1));
+            if dodag.mode_of_operation == mop::STORING {
+                // Ask children to advertise their routes through us again
+                self.dtsn.set(self.dtsn.get().wrapping_add(1));
+            }
+            self.schedule_dao(DAO_DELAY);
+            if self.trickle.is_running() {
+                self.trickle.inconsistent();
+            } else {
+                self.start_trickle(&dodag.config);
+            }
+            return true;
+        }
+        let min_hop_rank_increase = dodag.config.min_hop_rank_increase.max(1);
+        if new_rank / min_hop_rank_increase != rank / min_hop_rank_increase {
+            self.trickle.inconsistent();
+            return true;
+        }
+        false
+    }
+
+    /// Forgets the parents and the default route.
+    fn leave(&self) {
+        if self.preferred_parent.take().is_some() {
+            self.routing_table.remove(IPAddr::new(), 0);
+        }
+        self.parents.map(|parents| {
+            for parent in parents.iter_mut() {
+                *parent = None;
+            }
+        });
+        self.rank.set(INFINITE_RANK);
+        self.dao_timer.set(0);
+        self.awaiting_dao_ack.clear();
+    }
+
+    /// Leaves the DODAG after losing all parents, advertising an infinite
+    /// rank so that children look for other parents (RFC 6550, Section
+    /// 8.2.2.5), and solicits DIOs again.
+    fn detach(&self) {
+        self.leave();
+        self.trickle.stop();
+        self.pending_dio.set(true);
+        self.solicit();
+    }
+
+    fn update_prefix(&self, prefix: Option<PrefixInfo>) {
+        if let Some(prefix) = prefix {
+            if prefix.prefix_len == 64
+                && prefix.flags & pio_flags::AUTONOMOUS != 0
+                && prefix.valid_lifetime > 0
+            {
+                let mut global_addr = self.link_local_addr;
+                global_addr.set_prefix(&prefix.prefix.0, 64);
+                if !self.global_addr.contains(&global_addr) {
+                    self.global_addr.set(global_addr);
+                    if self.preferred_parent.is_some() {
+                        self.schedule_dao(DAO_DELAY);
+                    }
+                }
+            }
+        }
+    }
+
+    fn receive_dis(&self, src: IPAddr, dst: IPAddr) {
+        if !self.is_joined() {
+            return;
+        }
+        if dst.is_multicast() {
+            self.trickle.inconsistent();
+        } else {
+            self.pending_unicast_dio.set(src);
+        }
+    }
+
+    fn receive_dio(&self, src: IPAddr, dio: Dio, options: &[u8]) {
+        if !src.is_unicast_link_local()
+            || (dio.mode_of_operation != mop::STORING && dio.mode_of_operation != mop::NON_STORING)
+        {
+            return;
+        }
+        let mut config = None;
+        let mut prefix = None;
+        for_each_option(options, |option| match option {
+            RplOption::DodagConfig(c) => config = Some(c),
+            RplOption::PrefixInfo(p) => prefix = Some(p),
+            _ => {}
+        });
+
+        let current = self.dodag.map(|dodag| *dodag);
+        if self.is_root.get() {
+            if current.map_or(false, |d| {
+                d.dodag_id == dio.dodag_id && d.version == dio.version
+            }) {
+                self.trickle.consistent();
+            }
+            return;
+        }
+
+        let new_dodag = Dodag {
+            instance_id: dio.instance_id,
+            version: dio.version,
+            dodag_id: dio.dodag_id,
+            mode_of_operation: dio.mode_of_operation,
+            grounded: dio.grounded,
+            preference: dio.preference,
+            config: config.unwrap_or_default(),
+            prefix: prefix,
+        };
+        let dodag = match current {
+            Some(d) if d.instance_id == dio.instance_id && d.dodag_id == dio.dodag_id => {
+                if is_newer(dio.version, d.version) {
+                    // Global repair: join the new version from scratch
+                    self.leave();
+                    new_dodag
+                } else if dio.version != d.version {
+                    // Let the sender hear about the newer version
+                    self.trickle.inconsistent();
+                    return;
+                } else {
+                    Dodag {
+                        config: config.unwrap_or(d.config),
+                        prefix: prefix.or(d.prefix),
+                        ..d
+                    }
+                }
+            }
+            // Only one DODAG is joined at a time
+            Some(_) if self.preferred_parent.is_some() => return,
+            _ => {
+                if dio.rank == INFINITE_RANK {
+                    return;
+                }
+                self.leave();
+                new_dodag
+            }
+        };
+        self.dodag.set(dodag);
+        self.update_prefix(dodag.prefix);
+
+        let dtsn_changed = if dio.rank == INFINITE_RANK {
+            self.remove_parent(src);
+            false
+        } else {
+            // Keep track of the link to the parent for its ETX
+            if self
+                .neighbor_cache
+                .lookup(src)
+                .map_or(true, |entry| !entry.is_router)
+            {
+                let lifetime = dodag.config.route_lifetime().unwrap_or(u32::MAX);
+                self.neighbor_cache
+                    .update(src, src.mac_from_iid(), false, Some(lifetime));
+            }
+            self.update_parent(Parent {
+                addr: src,
+                rank: dio.rank,
+                dtsn: dio.dtsn,
+            })
+        };
+
+        let changed = self.select_parent();
+        if dtsn_changed
+            && !changed
+            && self.preferred_parent.contains(&src)
+            && dodag.mode_of_operation == mop::STORING
+        {
+            // The parent asks for our routes again
+            self.schedule_dao(DAO_DELAY);
+        }
+        if !changed && dio.rank != INFINITE_RANK {
+            self.trickle.consistent();
+        }
+    }
+
+    fn receive_dao(&self, src: IPAddr, dao: Dao, options: &[u8]) {
+        let dodag = match self.dodag.map(|dodag| *dodag) {
+            Some(dodag) => dodag,
+            None => return,
+        };
+        // Downward routes are kept by every router in storing mode and by
+        // the root in non-storing mode, and never go through the parent,
+        // which would create a loop
+        let storing = dodag.mode_of_operation == mop::STORING;
+        if !(storing || self.is_root.get())
+            || dao.instance_id != dodag.instance_id
+            || !self.is_joined()
+            || self.preferred_parent.contains(&src)
+        {
+            return;
+        }
+        let lifetime_unit = dodag.config.lifetime_unit as u32;
+        let mut targets = [None; MAX_DAO_TARGETS];
+        let mut count = 0;
+        let mut status = dao_ack_status::ACCEPTED;
+        let mut changed = false;
+        for_each_option(options, |option| match option {
+            RplOption::Target { prefix_len, prefix } => {
+                if count < MAX_DAO_TARGETS {
+                    targets[count] = Some((prefix, prefix_len));
+                    count += 1;
+                }
+            }
+            RplOption::TransitInfo {
+                path_lifetime,
+                parent,
+                ..
+            } => {
+                for (prefix, prefix_len) in targets[..count].iter().filter_map(|t| *t) {
+                    if path_lifetime == 0 {
+                        // No-Path: the target is no longer reachable
+                        // through the sender
+                        self.routing_table.retain(|route| {
+                            (storing && route.next_hop != src)
+                                || route.prefix_len != prefix_len
+                                || !route.prefix.matches_prefix(&prefix, prefix_len)
+                        });
+                        continue;
+                    }
+                    let lifetime = if path_lifetime == INFINITE_LIFETIME {
+                        None
+                    } else {
+                        Some(path_lifetime as u32 * lifetime_unit)
+                    };
+                    let added = if storing {
+                        self.routing_table.add(prefix, prefix_len, src, lifetime)
+                    } else {
+                        match parent {
+                            // Children of the root are its neighbors
+                            Some(parent) if self.is_local(&parent) => {
+                                let mut neighbor = src;
+                                neighbor.set_unicast_link_local();
+                                self.routing_table
+                                    .add(prefix, prefix_len, neighbor, lifetime)
+                            }
+                            Some(parent) => self
+                                .routing_table
+                                .add_source_route(prefix, prefix_len, parent, lifetime),
+                            None => true,
+                        }
+                    };
+                    if !added {
+                        status = dao_ack_status::NO_ROUTE_SPACE;
+                    }
+                }
+                changed |= count > 0;
+                count = 0;
+            }
+            _ => {}
+        });
+        if dao.ack_requested {
+            self.pending_dao_ack.set((src, dao.sequence, status));
+        }
+        if changed && !self.is_root.get() {
+            // Advertise the sub-DODAG to our own parent
+            self.schedule_dao(DAO_DELAY);
+        }
+    }
+
+    fn receive_dao_ack(&self, ack: DaoAck) {
+        if !self.awaiting_dao_ack.contains(&ack.sequence) {
+            return;
+        }
+        self.awaiting_dao_ack.clear();
+        self.dao_transmissions.set(0);
+        if ack.status >= dao_ack_status::REJECTED {
+            // The parent cannot route to us, so look for another one
+            self.dao_timer.set(0);
+            self.preferred_parent.map(|addr| self.remove_parent(*addr));
+            self.select_parent();
+            return;
+        }
+        // Refresh the routes halfway through their lifetime
+        let refresh = self
+            .dodag
+            .and_then(|dodag| dodag.config.route_lifetime())
+            .map_or(0, |lifetime| (lifetime / 2).max(1));
+        self.dao_timer.set(refresh);
+    }
+
+    fn schedule_dao(&self, delay: u32) {
+        self.dao_timer.set(delay);
+        self.dao_transmissions.set(0);
+        self.awaiting_dao_ack.clear();
+    }
+
+    fn dao_timer_expired(&self) {
+        if self.awaiting_dao_ack.is_some() && self.dao_transmissions.get() >= DAO_MAX_TRANSMISSIONS
+        {
+            // The parent is unreachable
+            self.awaiting_dao_ack.clear();
+            self.dao_transmissions.set(0);
+            self.preferred_parent.map(|addr| self.remove_parent(*addr));
+            self.select_parent();
+            return;
+        }
+        if self.preferred_parent.is_none() || !self.has_targets() {
+            return;
+        }
+        let sequence = self.dao_sequence.get().wrapping_add(1);
+        self.dao_sequence.set(sequence);
+        self.awaiting_dao_ack.set(sequence);
+        self.dao_transmissions
+            .set(self.dao_transmissions.get().saturating_add(1));
+        self.dao_timer.set(DAO_ACK_TIMEOUT);
+        self.pending_dao.set(true);
+    }
+
+    fn is_advertised(addr: &IPAddr) -> bool {
+        !addr.is_unspecified() && !addr.is_multicast() && !addr.is_unicast_link_local()
+    }
+
+    fn has_targets(&self) -> bool {
+        let mut has_routes = false;
+        if self
+            .dodag
+            .map_or(false, |d| d.mode_of_operation == mop::STORING)
+        {
+            self.routing_table
+                .for_each(|route| has_routes |= route.prefix_len > 0);
+        }
+        self.global_addr.is_some() || self.local_addrs.iter().any(Self::is_advertised) || has_routes
+    }
+
+    fn encode_dio(&self, buf: &mut [u8]) -> Option<usize> {
+        let dodag = self.dodag.map(|dodag| *dodag)?;
+        let dio = Dio {
+            instance_id: dodag.instance_id,
+            version: dodag.version,
+            rank: self.rank.get(),
+            grounded: dodag.grounded,
+            mode_of_operation: dodag.mode_of_operation,
+            preference: dodag.preference,
+            dtsn: self.dtsn.get(),
+            dodag_id: dodag.dodag_id,
+        };
+        let mut off = dio.encode(buf, 0).done()?.0;
+        off = RplOption::DodagConfig(dodag.config)
+            .encode(buf, off)
+            .done()?
+            .0;
+        if let Some(prefix) = dodag.prefix {
+            off = RplOption::PrefixInfo(prefix).encode(buf, off).done()?.0;
+        }
+        Some(off)
+    }
+
+    /// Encodes a DAO advertising the addresses of the node and, in storing
+    /// mode, the routes to its sub-DODAG, returning its source, destination
+    /// and length.
+    fn encode_dao(&self, buf: &mut [u8]) -> Option<(IPAddr, IPAddr, usize)> {
+        let dodag = self.dodag.map(|dodag| *dodag)?;
+        let parent = self.preferred_parent.map(|addr| *addr)?;
+        let storing = dodag.mode_of_operation == mop::STORING;
+        let dao = Dao {
+            instance_id: dodag.instance_id,
+            ack_requested: true,
+            sequence: self.dao_sequence.get(),
+            dodag_id: None,
+        };
+        let mut off = dao.encode(buf, 0).done()?.0;
+
+        // Leave room for the transit information
+        let transit_len = if storing { 6 } else { 22 };
+        let targets_end = buf.len().checked_sub(transit_len)?;
+        let mut add_target = |prefix: IPAddr, prefix_len: u8| {
+            let option = RplOption::Target {
+                prefix_len: prefix_len,
+                prefix: prefix,
+            };
+            if let Some((next, _)) = option.encode(&mut buf[..targets_end], off).done() {
+                off = next;
+            }
+        };
+        let global_addr = self.global_addr.map(|addr| *addr);
+        if let Some(global_addr) = global_addr {
+            add_target(global_addr, 128);
+        }
+        for addr in self
+            .local_addrs
+            .iter()
+            .filter(|addr| Self::is_advertised(addr))
+        {
+            if Some(*addr) != global_addr {
+                add_target(*addr, 128);
+            }
+        }
+        if storing {
+            self.routing_table.for_each(|route| {
+                if route.prefix_len > 0 && route.next_hop != parent {
+                    add_target(route.prefix, route.prefix_len);
+                }
+            });
+        }
+
+        let (src, dst, transit_parent) = if storing {
+            (self.link_local_addr, parent, None)
+        } else {
+            // The root learns the topology from the global addresses of
+            // the parents
+            let src = global_addr.or_else(|| {
+                self.local_addrs
+                    .iter()
+                    .find(|addr| Self::is_advertised(addr))
+                    .copied()
+            })?;
+            let mut parent_global_addr = parent;
+            parent_global_addr.0[..8].copy_from_slice(&src.0[..8]);
+            (src, dodag.dodag_id, Some(parent_global_addr))
+        };
+        off = RplOption::TransitInfo {
+            external: false,
+            path_control: 0,
+            path_sequence: self.path_sequence.get(),
+            path_lifetime: dodag.config.default_lifetime,
+            parent: transit_parent,
+        }
+        .encode(buf, off)
+        .done()?
+        .0;
+        Some((src, dst, off))
+    }
+
+    /// Builds the body of the next pending message into `buf`, returning
+    /// its source and destination, its code and its length.
+    fn next_message(&self, buf: &mut [u8]) -> Option<(IPAddr, IPAddr, u8, usize)> {
+        if let Some((dst, sequence, status)) = self.pending_dao_ack.take() {
+            let instance_id = self.dodag.map_or(0, |dodag| dodag.instance_id);
+            let ack = DaoAck {
+                instance_id: instance_id,
+                sequence: sequence,
+                status: status,
+                dodag_id: None,
+            };
+            let len = ack.encode(buf, 0).done()?.0;
+            // Non-storing DAOs come from the global address of the node
+            let src = if dst.is_unicast_link_local() {
+                self.link_local_addr
+            } else {
+                self.global_addr.map_or(self.link_local_addr, |addr| *addr)
+            };
+            return Some((src, dst, rpl_code::DAO_ACK, len));
+        }
+
+        if let Some(dst) = self.pending_unicast_dio.take() {
+            let len = self.encode_dio(buf)?;
+            return Some((self.link_local_addr, dst, rpl_code::DIO, len));
+        }
+
+        if self.pending_dio.get() {
+            self.pending_dio.set(false);
+            let len = self.encode_dio(buf)?;
+            return Some((self.link_local_addr, ALL_RPL_NODES_ADDR, rpl_code::DIO, len));
+        }
+
+        if self.pending_dao.get() {
+            self.pending_dao.set(false);
+            let (src, dst, len) = self.encode_dao(buf)?;
+            return Some((src, dst, rpl_code::DAO, len));
+        }
+
+        if self.pending_dis.get() {
+            self.pending_dis.set(false);
+            let len = encode_dis(buf, 0).done()?.0;
+            return Some((self.link_local_addr, ALL_RPL_NODES_ADDR, rpl_code::DIS, len));
+        }
+
+        None
+    }
+
+    /// Queues a packet received for another node to be forwarded: to the
+    /// next hop towards its destination or, if it is addressed to this
+    /// node with hops of its source route left, to the next hop of the
+    /// route.
+    fn forward(&self, mut ip_header: IP6Header, payload: &[u8]) {
+        let hop_limit = ip_header.get_hop_limit();
+        let mut dst = ip_header.get_dst_addr();
+        if !self.is_joined()
+            || hop_limit <= 1
+            || ip_header.get_src_addr().is_unicast_link_local()
+            || dst.is_unicast_link_local()
+            || self.pending_forward.is_some()
+        {
+            return;
+        }
+        self.forward_buffer.map(|buf| {
+            let len = payload.len();
+            if len > buf.len() {
+                return;
+            }
+            buf[..len].copy_from_slice(payload);
+            if self.is_local(&dst) {
+                if visit_next_hop(&mut buf[..len], &mut dst).is_err() {
+                    return;
+                }
+                ip_header.dst_addr = dst;
+            }
+            ip_header.set_hop_limit(hop_limit - 1);
+            self.pending_forward.set((ip_header, len));
+        });
+    }
+
+    /// Sends the packet waiting to be forwarded, if any.
+    fn send_forwarded(&self) {
+        if let Some((ip_header, len)) = self.pending_forward.take() {
+            self.forward_buffer.map(|buf| {
+                buf.slice(0..len);
+                self.sending.set(true);
+                let ret = self.ip_sender.forward(ip_header, buf, self.net_cap);
+                if ret != ReturnCode::SUCCESS {
+                    debug!("[RPL] Forwarding failed: {:?}", ret);
+                    self.sending.set(false);
+                }
+                buf.reset();
+            });
+        }
+    }
+
+    /// Sends the next pending message, or else the next packet to forward,
+    /// if the IP layer is idle.
+    fn do_output(&self) {
+        if self.sending.get() {
+            return;
+        }
+        self.tx_buffer.take().map(|mut buf| {
+            if let Some((src, dst, code, len)) = self.next_message(&mut buf[..]) {
+                let (header, len) = split_message(code, &mut buf[..], len);
+                buf.slice(0..len);
+                self.sending.set(true);
+                self.ip_sender.set_addr(src);
+                let ret =
+                    self.ip_sender
+                        .send_to(dst, TransportHeader::ICMP(header), &buf, self.net_cap);
+                if ret != ReturnCode::SUCCESS {
+                    debug!("[RPL] IP send_to failed: {:?}", ret);
+                    self.sending.set(false);
+                }
+            }
+            buf.reset();
+            self.tx_buffer.replace(buf);
+        });
+        if !self.sending.get() {
+            self.send_forwarded();
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> TrickleClient for RplNode<'a, A> {
+    fn transmit(&self) {
+        self.pending_dio.set(true);
+        self.do_output();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6RecvClient for RplNode<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if !self.started.get() {
+            return;
+        }
+        let dst = ip_header.get_dst_addr();
+        let local = self.is_local(&dst);
+        let source_routed = ip_header.get_next_header() == ip6_nh::ROUTING;
+        if (local && source_routed) || (!local && !dst.is_multicast()) {
+            self.forward(ip_header, payload);
+            self.do_output();
+            return;
+        }
+        if ip_header.get_next_header() != ip6_nh::ICMP {
+            return;
+        }
+        let (offset, header) = match ICMP6Header::decode(payload).done() {
+            Some(result) => result,
+            None => return,
+        };
+        let mut body = [0; MAX_MESSAGE_LEN];
+        let len = match join_message(&header, &payload[offset..], &mut body) {
+            Some(len) => len,
+            None => return,
+        };
+        let body = &body[..len];
+        let src = ip_header.get_src_addr();
+
+        match header.get_code() {
+            rpl_code::DIS => self.receive_dis(src, ip_header.get_dst_addr()),
+            rpl_code::DIO => {
+                if let Some((off, dio)) = Dio::decode(body).done() {
+                    self.receive_dio(src, dio, &body[off..]);
+                }
+            }
+            rpl_code::DAO => {
+                if let Some((off, dao)) = Dao::decode(body).done() {
+                    self.receive_dao(src, dao, &body[off..]);
+                }
+            }
+            rpl_code::DAO_ACK => {
+                if let Some((_, ack)) = DaoAck::decode(body).done() {
+                    self.receive_dao_ack(ack);
+                }
+            }
+            _ => {}
+        }
+        self.do_output();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6SendClient for RplNode<'a, A> {
+    fn send_done(&self, result: ReturnCode) {
+        if result != ReturnCode::SUCCESS {
+            // Lost DAOs are retransmitted, and Trickle sends DIOs again
+            debug!("[RPL] Send failed: {:?}", result);
+        }
+        self.sending.set(false);
+        self.do_output();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for RplNode<'a, A> {
+    fn alarm(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(RPL_TIMER_MS));
+        self.routing_table.tick(1);
+
+        if !self.is_joined() {
+            let timer = self.dis_timer.get().saturating_sub(1);
+            self.dis_timer.set(timer);
+            if timer == 0 {
+                self.solicit();
+            }
+        }
+
+        let timer = self.dao_timer.get();
+        if timer > 0 {
+            self.dao_timer.set(timer - 1);
+            if timer == 1 {
+                self.dao_timer_expired();
+            }
+        }
+        self.do_output();
+    }
+}
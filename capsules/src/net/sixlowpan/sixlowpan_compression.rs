@@ -230,7 +230,10 @@ pub fn compress<'a>(
     // Next Header
 
     //let (mut is_nhc, mut nh_len): (bool, u8) = is_ip6_nh_compressible(ip6_packet)?;
-    let is_nhc = ip6_header.next_header == ip6_nh::UDP;
+    // Serialized headers, such as those of forwarded packets, are carried
+    // inline
+    let is_nhc = ip6_header.next_header == ip6_nh::UDP
+        && !matches!(ip6_packet.payload.header, TransportHeader::Raw(..));
     compress_nh(&ip6_header, is_nhc, &mut buf, &mut written);
 
     // Hop Limit
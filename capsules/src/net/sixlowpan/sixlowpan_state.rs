@@ -573,8 +573,10 @@ impl<'a> TxState<'a> {
             // TODO: Note that in order to serialize the headers, we need to
             // statically allocate room on the stack. However, we do not know
             // how many additional headers we have until runtime. This
-            // functionality should be fixed in the future.
-            let mut headers = [0 as u8; 60];
+            // functionality should be fixed in the future. For now, this is
+            // sized for an IPv6 header followed by a maximally sized TCP
+            // header.
+            let mut headers = [0 as u8; 100];
             ip6_packet.encode(&mut headers);
             frame.append_payload(&headers[dgram_offset..dgram_offset + headers_to_write]);
             payload_len -= headers_to_write;
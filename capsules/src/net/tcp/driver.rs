@@ -0,0 +1,389 @@
+//! TCP userspace interface.
+//!
+//! Implements a userspace interface for opening, using and closing TCP
+//! connections. Each process may use one connection at a time. Connections
+//! are backed by a fixed pool of `TCPSocket`s allocated by the board, so
+//! opening a connection fails with `ENOMEM` if all sockets are in use by
+//! other processes.
+//!
+//! A socket is assigned to a process when it first calls `connect` or
+//! `listen`, and is returned to the pool when the connection is closed or
+//! aborted, or when the process dies.
+//!
+//! Sending and receiving are non-blocking and copy data between the process
+//! buffers and the send and receive buffers of the socket. The callbacks
+//! notify the process when it can make progress.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::tcp::tcp_mux::TCPStack;
+use crate::net::tcp::tcp_socket::{TCPClient, TCPSocket, TcpState};
+use crate::net::util::host_slice_to_u16;
+use core::cmp;
+use core::mem;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Tcp as usize;
+
+/// Size of an endpoint in the config buffer: an IPv6 address followed by a
+/// port in host byte order.
+const ENDPOINT_LEN: usize = mem::size_of::<IPAddr>() + mem::size_of::<u16>();
+
+/// Values of the first argument of the event callback.
+mod event {
+    /// The connection has been established.
+    pub const CONNECTED: usize = 0;
+    /// The peer closed its half of the connection; no more data will arrive
+    /// once the receive buffer has been drained.
+    pub const REMOTE_CLOSED: usize = 1;
+    /// The connection is closed. The second argument is the result.
+    pub const CLOSED: usize = 2;
+}
+
+#[derive(Default)]
+pub struct App {
+    rx_callback: Option<Callback>,
+    tx_callback: Option<Callback>,
+    event_callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<Shared, u8>>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+    socket: Option<usize>,
+}
+
+pub struct TCPDriver<'a> {
+    stack: &'a dyn TCPStack<'a>,
+
+    /// Pool of sockets shared by all processes. The id of each socket must
+    /// be its index in the pool.
+    sockets: &'a [TCPSocket<'a>],
+
+    /// Grant of apps that use this driver.
+    apps: Grant<App>,
+}
+
+impl<'a> TCPDriver<'a> {
+    pub fn new(
+        stack: &'a dyn TCPStack<'a>,
+        sockets: &'a [TCPSocket<'a>],
+        grant: Grant<App>,
+    ) -> TCPDriver<'a> {
+        TCPDriver {
+            stack: stack,
+            sockets: sockets,
+            apps: grant,
+        }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Utility function to perform an action on the socket of an app.
+    /// Returns `ERESERVE` if the app has no socket.
+    fn do_with_socket<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&'a TCPSocket<'a>, &mut App) -> ReturnCode,
+    {
+        let sockets = self.sockets;
+        self.do_with_app(appid, |app| match app.socket {
+            Some(id) => closure(&sockets[id], app),
+            None => ReturnCode::ERESERVE,
+        })
+    }
+
+    fn is_claimed(&self, id: usize) -> bool {
+        self.apps
+            .iter()
+            .any(|app| app.enter(|app, _| app.socket == Some(id)))
+    }
+
+    /// Returns the socket of the app, assigning it a free socket from the
+    /// pool if it does not have one yet. Sockets that were left open by a
+    /// process that died are reset before being reused.
+    fn claim_socket(&self, appid: AppId) -> Result<&'a TCPSocket<'a>, ReturnCode> {
+        let current = self
+            .apps
+            .enter(appid, |app, _| app.socket)
+            .map_err(ReturnCode::from)?;
+        if let Some(id) = current {
+            return Ok(&self.sockets[id]);
+        }
+        let socket = self
+            .sockets
+            .iter()
+            .find(|socket| !self.is_claimed(socket.get_id()))
+            .ok_or(ReturnCode::ENOMEM)?;
+        if socket.get_state() != TcpState::Closed {
+            self.stack.abort(socket);
+        }
+        self.apps
+            .enter(appid, |app, _| app.socket = Some(socket.get_id()))
+            .map_err(ReturnCode::from)?;
+        Ok(socket)
+    }
+
+    /// Returns a socket to the pool if the app did not manage to open it.
+    fn release_if_closed(&self, appid: AppId, socket: &TCPSocket) {
+        if socket.get_state() == TcpState::Closed {
+            let _ = self.apps.enter(appid, |app, _| app.socket = None);
+        }
+    }
+
+    fn parse_endpoint(buf: &[u8]) -> Option<(IPAddr, u16)> {
+        if buf.len() < ENDPOINT_LEN {
+            return None;
+        }
+        let (a, p) = buf.split_at(mem::size_of::<IPAddr>());
+        let mut addr = IPAddr::new();
+        addr.0.copy_from_slice(a);
+        Some((addr, host_slice_to_u16(p)))
+    }
+
+    /// Runs `closure` on the grant of the app owning `socket`, if any.
+    fn with_owner<F>(&self, socket: &TCPSocket, closure: F)
+    where
+        F: Fn(&mut App),
+    {
+        let id = socket.get_id();
+        self.apps.each(|app| {
+            if app.socket == Some(id) {
+                closure(app);
+            }
+        });
+    }
+}
+
+impl<'a> Driver for TCPDriver<'a> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Read buffer. Received data is copied into it by the `recv`
+    ///        command.
+    /// - `1`: Write buffer. Contains data to be sent by the `send` command.
+    /// - `2`: Config buffer. Holds the remote endpoint (16 byte IPv6 address
+    ///        followed by a 2 byte port) for `connect`. When a connection is
+    ///        accepted by a listening socket, the remote endpoint is written
+    ///        into this buffer in the same format.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self.do_with_app(appid, |app| {
+                app.app_read = slice;
+                ReturnCode::SUCCESS
+            }),
+            1 => self.do_with_app(appid, |app| {
+                app.app_write = slice;
+                ReturnCode::SUCCESS
+            }),
+            2 => self.do_with_app(appid, |app| {
+                app.app_cfg = slice;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Data received. The first argument is the number of bytes
+    ///        waiting to be read with `recv`.
+    /// - `1`: Data sent. The first argument is the free space in the send
+    ///        buffer, i.e. the maximum number of bytes `send` will accept.
+    /// - `2`: Connection events. The first argument is the event: `0` when
+    ///        the connection is established, `1` when the peer has closed
+    ///        its half of the connection, and `2` when the connection is
+    ///        closed, in which case the second argument is the result
+    ///        (`SUCCESS` for an orderly close, `ECANCEL` if the connection
+    ///        was reset, and `FAIL` if it was refused or timed out).
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.do_with_app(app_id, |app| {
+                app.rx_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            1 => self.do_with_app(app_id, |app| {
+                app.tx_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            2 => self.do_with_app(app_id, |app| {
+                app.event_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// TCP control.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Connect to the remote endpoint in the config buffer. Returns
+    ///        `EBUSY` if the app already has a connection open, and `ENOMEM`
+    ///        if no socket is available. Completion is signaled through the
+    ///        event callback.
+    /// - `2`: Listen for a connection on local port `arg1`. The app accepts
+    ///        a single connection, signaled through the event callback.
+    /// - `3`: Send up to `arg1` bytes from the write buffer. Returns the
+    ///        number of bytes accepted into the send buffer, which may be
+    ///        less than requested.
+    /// - `4`: Receive up to `arg1` bytes into the read buffer. Returns the
+    ///        number of bytes copied.
+    /// - `5`: Close the connection gracefully.
+    /// - `6`: Abort the connection, resetting it if necessary. The socket is
+    ///        released immediately and no event callback is delivered.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                let endpoint = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.app_cfg
+                            .as_ref()
+                            .and_then(|cfg| Self::parse_endpoint(cfg.as_ref()))
+                    })
+                    .unwrap_or(None);
+                let (addr, port) = match endpoint {
+                    Some(endpoint) => endpoint,
+                    None => return ReturnCode::EINVAL,
+                };
+                match self.claim_socket(appid) {
+                    Ok(socket) => {
+                        let ret = self.stack.connect(socket, addr, port);
+                        self.release_if_closed(appid, socket);
+                        ret
+                    }
+                    Err(ret) => ret,
+                }
+            }
+
+            2 => match self.claim_socket(appid) {
+                Ok(socket) => {
+                    let ret = self.stack.listen(socket, arg1 as u16);
+                    self.release_if_closed(appid, socket);
+                    ret
+                }
+                Err(ret) => ret,
+            },
+
+            3 => {
+                let stack = self.stack;
+                self.do_with_socket(appid, |socket, app| {
+                    app.app_write.as_ref().map_or(ReturnCode::EINVAL, |write| {
+                        let len = cmp::min(arg1, write.len());
+                        let count = stack.send(socket, &write.as_ref()[..len]);
+                        ReturnCode::SuccessWithValue { value: count }
+                    })
+                })
+            }
+
+            4 => {
+                let stack = self.stack;
+                self.do_with_socket(appid, |socket, app| {
+                    app.app_read.as_mut().map_or(ReturnCode::EINVAL, |read| {
+                        let len = cmp::min(arg1, read.len());
+                        let count = stack.recv(socket, &mut read.as_mut()[..len]);
+                        ReturnCode::SuccessWithValue { value: count }
+                    })
+                })
+            }
+
+            5 => {
+                let socket = self.apps.enter(appid, |app, _| app.socket).unwrap_or(None);
+                match socket {
+                    Some(id) => {
+                        let socket = &self.sockets[id];
+                        let ret = self.stack.close(socket);
+                        self.release_if_closed(appid, socket);
+                        ret
+                    }
+                    None => ReturnCode::ERESERVE,
+                }
+            }
+
+            6 => {
+                let socket = self
+                    .apps
+                    .enter(appid, |app, _| app.socket.take())
+                    .unwrap_or(None);
+                match socket {
+                    Some(id) => {
+                        self.stack.abort(&self.sockets[id]);
+                        ReturnCode::SUCCESS
+                    }
+                    None => ReturnCode::ERESERVE,
+                }
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a> TCPClient<'a> for TCPDriver<'a> {
+    fn connected(&self, socket: &'a TCPSocket<'a>) {
+        let (addr, port) = socket.get_remote_endpoint();
+        self.with_owner(socket, |app| {
+            app.app_cfg.as_mut().map(|cfg| {
+                if cfg.len() >= ENDPOINT_LEN {
+                    let buf = cfg.as_mut();
+                    buf[..16].copy_from_slice(&addr.0);
+                    buf[16..ENDPOINT_LEN].copy_from_slice(&port.to_ne_bytes());
+                }
+            });
+            app.event_callback
+                .map(|mut cb| cb.schedule(event::CONNECTED, 0, 0));
+        });
+    }
+
+    fn received(&self, socket: &'a TCPSocket<'a>) {
+        let available = socket.bytes_available();
+        let remote_closed = socket.is_remote_closed();
+        self.with_owner(socket, |app| {
+            if available > 0 {
+                app.rx_callback.map(|mut cb| cb.schedule(available, 0, 0));
+            }
+            if remote_closed {
+                app.event_callback
+                    .map(|mut cb| cb.schedule(event::REMOTE_CLOSED, 0, 0));
+            }
+        });
+    }
+
+    fn sent(&self, socket: &'a TCPSocket<'a>) {
+        let space = socket.send_space();
+        self.with_owner(socket, |app| {
+            app.tx_callback.map(|mut cb| cb.schedule(space, 0, 0));
+        });
+    }
+
+    fn closed(&self, socket: &'a TCPSocket<'a>, result: ReturnCode) {
+        self.with_owner(socket, |app| {
+            app.socket = None;
+            app.event_callback
+                .map(|mut cb| cb.schedule(event::CLOSED, result.into(), 0));
+        });
+    }
+}
@@ -0,0 +1,7 @@
+pub mod driver;
+pub mod tcp;
+pub mod tcp_mux;
+pub mod tcp_socket;
+
+pub use self::driver::TCPDriver;
+pub use self::driver::DRIVER_NUM;
@@ -0,0 +1,276 @@
+//! This file contains the structs and methods associated with the TCP header.
+//! This includes getters and setters for the various header fields, as well
+//! as the standard encode/decode functionality required for serializing
+//! the struct for transmission.
+//!
+//! The only TCP option understood by this implementation is the Maximum
+//! Segment Size (MSS) option. Any other options present in a received header
+//! are skipped over when decoding, and are never generated when encoding.
+
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_u16, encode_u32, encode_u8};
+
+/// Size of a TCP header without any options
+pub const TCP_HDR_LEN: usize = 20;
+
+/// Size of the encoded MSS option
+const MSS_OPTION_LEN: usize = 4;
+
+/// Bit positions of the TCP control flags, as found in the lower byte of the
+/// `offset_and_control` field.
+pub mod tcp_flags {
+    pub const FIN: u16 = 0x01;
+    pub const SYN: u16 = 0x02;
+    pub const RST: u16 = 0x04;
+    pub const PSH: u16 = 0x08;
+    pub const ACK: u16 = 0x10;
+    pub const URG: u16 = 0x20;
+}
+
+mod tcp_options {
+    pub const END: u8 = 0;
+    pub const NOP: u8 = 1;
+    pub const MSS: u8 = 2;
+}
+
+// Note: Unlike the `UDPHeader`, all TCP header fields are stored in host byte
+// order and converted when encoding/decoding.
+
+/// The `TCPHeader` struct follows the layout for the TCP segment header.
+#[derive(Copy, Clone, Debug)]
+pub struct TCPHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq_num: u32,
+    pub ack_num: u32,
+    pub offset_and_control: u16,
+    pub window: u16,
+    pub cksum: u16,
+    pub urg_ptr: u16,
+    pub mss: Option<u16>,
+    pub len: u16, // Not a real TCP field, here for convenience
+}
+
+impl Default for TCPHeader {
+    fn default() -> TCPHeader {
+        TCPHeader {
+            src_port: 0,
+            dst_port: 0,
+            seq_num: 0,
+            ack_num: 0,
+            offset_and_control: ((TCP_HDR_LEN / 4) as u16) << 12,
+            window: 0,
+            cksum: 0,
+            urg_ptr: 0,
+            mss: None,
+            len: TCP_HDR_LEN as u16,
+        }
+    }
+}
+
+impl TCPHeader {
+    pub fn new() -> TCPHeader {
+        TCPHeader::default()
+    }
+
+    pub fn set_src_port(&mut self, port: u16) {
+        self.src_port = port;
+    }
+
+    pub fn set_dst_port(&mut self, port: u16) {
+        self.dst_port = port;
+    }
+
+    pub fn set_seq_num(&mut self, seq_num: u32) {
+        self.seq_num = seq_num;
+    }
+
+    pub fn set_ack_num(&mut self, ack_num: u32) {
+        self.ack_num = ack_num;
+    }
+
+    /// Sets the control flags (see `tcp_flags`), replacing any flags that
+    /// were previously set.
+    pub fn set_flags(&mut self, flags: u16) {
+        self.offset_and_control = (self.offset_and_control & 0xf000) | (flags & 0x003f);
+    }
+
+    pub fn set_window(&mut self, window: u16) {
+        self.window = window;
+    }
+
+    pub fn set_cksum(&mut self, cksum: u16) {
+        self.cksum = cksum;
+    }
+
+    /// Sets the MSS option. This changes the size of the encoded header.
+    pub fn set_mss(&mut self, mss: Option<u16>) {
+        self.mss = mss;
+        let hdr_words = (self.get_hdr_size() / 4) as u16;
+        self.offset_and_control = (self.offset_and_control & 0x0fff) | (hdr_words << 12);
+    }
+
+    /// Sets the total length of the segment (header and payload). This is not
+    /// transmitted, but is needed by the IP layer since TCP has no length
+    /// field of its own.
+    pub fn set_len(&mut self, len: u16) {
+        self.len = len;
+    }
+
+    pub fn get_src_port(&self) -> u16 {
+        self.src_port
+    }
+
+    pub fn get_dst_port(&self) -> u16 {
+        self.dst_port
+    }
+
+    pub fn get_seq_num(&self) -> u32 {
+        self.seq_num
+    }
+
+    pub fn get_ack_num(&self) -> u32 {
+        self.ack_num
+    }
+
+    pub fn get_flags(&self) -> u16 {
+        self.offset_and_control & 0x003f
+    }
+
+    pub fn has_flags(&self, flags: u16) -> bool {
+        self.get_flags() & flags == flags
+    }
+
+    pub fn get_window(&self) -> u16 {
+        self.window
+    }
+
+    pub fn get_cksum(&self) -> u16 {
+        self.cksum
+    }
+
+    pub fn get_mss(&self) -> Option<u16> {
+        self.mss
+    }
+
+    pub fn get_len(&self) -> u16 {
+        self.len
+    }
+
+    /// Returns the length of the header as given by the data offset field.
+    /// For a received header this includes any options that were skipped
+    /// over when decoding.
+    pub fn get_data_offset(&self) -> usize {
+        ((self.offset_and_control >> 12) as usize) * 4
+    }
+
+    /// Returns the size of the header when encoded by `encode`.
+    pub fn get_hdr_size(&self) -> usize {
+        match self.mss {
+            Some(_) => TCP_HDR_LEN + MSS_OPTION_LEN,
+            None => TCP_HDR_LEN,
+        }
+    }
+
+    /// Returns the amount of sequence space occupied by a segment with this
+    /// header carrying `payload_len` bytes of data.
+    pub fn get_seq_len(&self, payload_len: usize) -> u32 {
+        let mut len = payload_len as u32;
+        if self.has_flags(tcp_flags::SYN) {
+            len += 1;
+        }
+        if self.has_flags(tcp_flags::FIN) {
+            len += 1;
+        }
+        len
+    }
+
+    /// This function serializes the `TCPHeader` into the provided buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - A mutable buffer to serialize the `TCPHeader` into
+    /// `offset` - The current offset into the provided buffer
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the new offset into the buffer wrapped in an
+    /// SResult.
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, self.get_hdr_size() + offset);
+
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u16, self.src_port);
+        off = enc_consume!(buf, off; encode_u16, self.dst_port);
+        off = enc_consume!(buf, off; encode_u32, self.seq_num);
+        off = enc_consume!(buf, off; encode_u32, self.ack_num);
+        off = enc_consume!(buf, off; encode_u16, self.offset_and_control);
+        off = enc_consume!(buf, off; encode_u16, self.window);
+        off = enc_consume!(buf, off; encode_u16, self.cksum);
+        off = enc_consume!(buf, off; encode_u16, self.urg_ptr);
+        if let Some(mss) = self.mss {
+            off = enc_consume!(buf, off; encode_u8, tcp_options::MSS);
+            off = enc_consume!(buf, off; encode_u8, MSS_OPTION_LEN as u8);
+            off = enc_consume!(buf, off; encode_u16, mss);
+        }
+        stream_done!(off, off);
+    }
+
+    /// This function deserializes the `TCPHeader` from the provided buffer.
+    /// The returned offset is the start of the segment payload, i.e. it
+    /// accounts for any options present in the header.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - The byte array corresponding to a serialized `TCPHeader`
+    ///
+    /// # Return Value
+    ///
+    /// This function returns a `TCPHeader` struct wrapped in an SResult
+    pub fn decode(buf: &[u8]) -> SResult<TCPHeader> {
+        stream_len_cond!(buf, TCP_HDR_LEN);
+        let mut tcp_header = Self::new();
+        let off = 0;
+        let (off, src_port) = dec_try!(buf, off; decode_u16);
+        tcp_header.src_port = src_port;
+        let (off, dst_port) = dec_try!(buf, off; decode_u16);
+        tcp_header.dst_port = dst_port;
+        let (off, seq_num) = dec_try!(buf, off; decode_u32);
+        tcp_header.seq_num = seq_num;
+        let (off, ack_num) = dec_try!(buf, off; decode_u32);
+        tcp_header.ack_num = ack_num;
+        let (off, offset_and_control) = dec_try!(buf, off; decode_u16);
+        tcp_header.offset_and_control = offset_and_control;
+        let (off, window) = dec_try!(buf, off; decode_u16);
+        tcp_header.window = window;
+        let (off, cksum) = dec_try!(buf, off; decode_u16);
+        tcp_header.cksum = cksum;
+        let (mut off, urg_ptr) = dec_try!(buf, off; decode_u16);
+        tcp_header.urg_ptr = urg_ptr;
+
+        let data_offset = tcp_header.get_data_offset();
+        stream_cond!(data_offset >= TCP_HDR_LEN);
+        stream_len_cond!(buf, data_offset);
+
+        // Walk the options, only retaining the MSS
+        while off < data_offset {
+            let (next, kind) = dec_try!(buf, off; decode_u8);
+            match kind {
+                tcp_options::END => break,
+                tcp_options::NOP => off = next,
+                _ => {
+                    let (next, len) = dec_try!(buf, next; decode_u8);
+                    stream_cond!(len >= 2 && off + len as usize <= data_offset);
+                    if kind == tcp_options::MSS && len as usize == MSS_OPTION_LEN {
+                        let (_, mss) = dec_try!(buf, next; decode_u16);
+                        tcp_header.mss = Some(mss);
+                    }
+                    off += len as usize;
+                }
+            }
+        }
+        tcp_header.len = buf.len() as u16;
+        stream_done!(data_offset, tcp_header);
+    }
+}
@@ -0,0 +1,383 @@
+//! This file contains the `MuxTcp`, which multiplexes any number of
+//! `TCPSocket`s over a single `IP6Sender`, and the
+//! [TCPStack](trait.TCPStack.html) trait through which kernel capsules and
+//! the userspace driver open, use and close sockets.
+//!
+//! Transmission: The IP layer can only send one packet at a time, so sockets
+//! never send segments themselves. Instead, whenever the IP sender is idle,
+//! the mux asks the sockets (round-robin) for the next segment they want to
+//! send, copies its payload into the single kernel buffer of the mux, and
+//! passes it to the IP layer. Resets generated in reply to segments that do
+//! not belong to any connection take precedence over socket output.
+//!
+//! Reception: The mux is the client of an `IP6Receiver`, and ignores any
+//! packets that do not carry TCP. Incoming segments are matched against
+//! established connections first, then against listening sockets. A segment
+//! that matches no socket is answered with a reset.
+//!
+//! Timers: All socket timers (retransmission, persist and TIME-WAIT) are
+//! counted in ticks of `TCP_TIMER_MS`, driven by a single alarm that only
+//! runs while at least one socket has an active timer.
+//!
+//! Unlike UDP, TCP ports are not bound through the `UdpPortManager`; the mux
+//! ensures that listening ports are unique and picks ephemeral ports for
+//! outgoing connections itself.
+
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::tcp::tcp::{tcp_flags, TCPHeader};
+use crate::net::tcp::tcp_socket::{TCPSocket, TcpEvents, TcpState, TCP_TIMER_MS};
+use core::cell::Cell;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::common::List;
+use kernel::debug;
+use kernel::hil::time::{self, Ticks};
+use kernel::ReturnCode;
+
+const EPHEMERAL_PORT_MIN: u16 = 49152;
+
+/// The interface used by kernel capsules (and the userspace driver) to
+/// operate on `TCPSocket`s. All calls are non-blocking: completion of
+/// connection establishment, newly received data, acknowledged data and the
+/// end of a connection are reported through the `TCPClient` of the socket.
+pub trait TCPStack<'a> {
+    /// Registers a socket with the stack. A socket must be added before it
+    /// can be used with any other method of this trait.
+    fn add_socket(&self, socket: &'a TCPSocket<'a>);
+
+    /// Actively opens a connection to `addr`:`port` from an ephemeral local
+    /// port. Returns `EBUSY` if the socket is not closed and `ENOMEM` if no
+    /// local port is available.
+    fn connect(&self, socket: &'a TCPSocket<'a>, addr: IPAddr, port: u16) -> ReturnCode;
+
+    /// Puts the socket in the LISTEN state on local port `port`. The socket
+    /// accepts the first incoming connection and then behaves like an
+    /// actively opened socket. Returns `EBUSY` if the socket is not closed
+    /// or another socket is already listening on `port`.
+    fn listen(&self, socket: &'a TCPSocket<'a>, port: u16) -> ReturnCode;
+
+    /// Queues `data` for transmission, returning the number of bytes that
+    /// fit into the send buffer of the socket.
+    fn send(&self, socket: &'a TCPSocket<'a>, data: &[u8]) -> usize;
+
+    /// Reads received data into `buf`, returning the number of bytes read.
+    fn recv(&self, socket: &'a TCPSocket<'a>, buf: &mut [u8]) -> usize;
+
+    /// Starts an orderly close of the connection. The `closed` callback is
+    /// invoked once the close completes, unless the socket was not yet
+    /// synchronized with a peer, in which case it is closed immediately.
+    fn close(&self, socket: &'a TCPSocket<'a>) -> ReturnCode;
+
+    /// Immediately closes the socket, resetting the connection if there is
+    /// one. No `closed` callback is invoked.
+    fn abort(&self, socket: &'a TCPSocket<'a>);
+}
+
+pub struct MuxTcp<'a, A: time::Alarm<'a>> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    sockets: List<'a, TCPSocket<'a>>,
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    sending: Cell<bool>,
+    pending_rst: OptionalCell<(IPAddr, TCPHeader)>,
+    /// Id of the socket that sent last, for round-robin scheduling
+    last_sender: OptionalCell<usize>,
+    timer_running: Cell<bool>,
+    /// Number of timer ticks elapsed, used to measure round-trip times
+    clock: Cell<u32>,
+    next_port: Cell<u16>,
+    iss_offset: Cell<u32>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> MuxTcp<'a, A> {
+    /// `tx_buffer` holds the payload of one outgoing segment, so its size
+    /// determines the MSS announced to peers. It should be no larger than
+    /// the payload buffer of the `IP6Packet` used by `ip_sender`, minus the
+    /// size of a TCP header.
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        tx_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> MuxTcp<'a, A> {
+        MuxTcp {
+            ip_sender: ip_sender,
+            alarm: alarm,
+            sockets: List::new(),
+            tx_buffer: MapCell::new(LeasableBuffer::new(tx_buffer)),
+            sending: Cell::new(false),
+            pending_rst: OptionalCell::empty(),
+            last_sender: OptionalCell::empty(),
+            timer_running: Cell::new(false),
+            clock: Cell::new(0),
+            next_port: Cell::new(EPHEMERAL_PORT_MIN),
+            iss_offset: Cell::new(0),
+            net_cap: net_cap,
+        }
+    }
+
+    /// Generates an initial sequence number. RFC 6528 recommends a hash of
+    /// the connection identifiers; lacking a hash function, we combine the
+    /// current time with a per-connection offset.
+    fn next_iss(&self) -> u32 {
+        let offset = self.iss_offset.get().wrapping_add(64000);
+        self.iss_offset.set(offset);
+        self.alarm
+            .now()
+            .into_u32()
+            .wrapping_mul(251)
+            .wrapping_add(offset)
+    }
+
+    fn port_in_use(&self, port: u16) -> bool {
+        self.sockets.iter().any(|socket| socket.uses_port(port))
+    }
+
+    fn allocate_port(&self) -> Option<u16> {
+        let range = (u16::MAX - EPHEMERAL_PORT_MIN) as usize + 1;
+        for _ in 0..range {
+            let port = self.next_port.get();
+            self.next_port.set(if port == u16::MAX {
+                EPHEMERAL_PORT_MIN
+            } else {
+                port + 1
+            });
+            if !self.port_in_use(port) {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    /// Queues a reset in reply to a segment that does not belong to any
+    /// connection (RFC 793, page 65). Only one reset is queued at a time;
+    /// if the peer keeps sending, it will get a reset for a later segment.
+    fn queue_rst(&self, dst: IPAddr, received: &TCPHeader, payload_len: usize) {
+        if received.has_flags(tcp_flags::RST) || self.pending_rst.is_some() {
+            return;
+        }
+        let mut header = TCPHeader::new();
+        header.set_src_port(received.get_dst_port());
+        header.set_dst_port(received.get_src_port());
+        if received.has_flags(tcp_flags::ACK) {
+            header.set_seq_num(received.get_ack_num());
+            header.set_flags(tcp_flags::RST);
+        } else {
+            let seq_len = received.get_seq_len(payload_len);
+            header.set_ack_num(received.get_seq_num().wrapping_add(seq_len));
+            header.set_flags(tcp_flags::RST | tcp_flags::ACK);
+        }
+        self.pending_rst.set((dst, header));
+    }
+
+    /// Returns the next segment to send from the sockets, starting with the
+    /// socket after the one that sent last.
+    fn next_socket_segment(&self, buf: &mut [u8]) -> Option<(IPAddr, TCPHeader, usize)> {
+        let mss = buf.len();
+        let now = self.clock.get();
+        let last = self.last_sender.take();
+        let mut past_last = last.is_none();
+        let mut first_ready = None;
+        for socket in self.sockets.iter() {
+            if socket.has_output(mss) {
+                if past_last {
+                    first_ready = Some(socket);
+                    break;
+                }
+                if first_ready.is_none() {
+                    first_ready = Some(socket);
+                }
+            }
+            if Some(socket.get_id()) == last {
+                past_last = true;
+            }
+        }
+        first_ready.and_then(|socket| {
+            self.last_sender.set(socket.get_id());
+            socket.next_segment(buf, mss, now)
+        })
+    }
+
+    /// Sends the next pending segment if the IP layer is idle.
+    fn do_output(&self) {
+        if self.sending.get() {
+            return;
+        }
+        self.tx_buffer.take().map(|mut buf| {
+            let segment = match self.pending_rst.take() {
+                Some((dst, header)) => Some((dst, header, 0)),
+                None => self.next_socket_segment(&mut buf[..]),
+            };
+            if let Some((dst, header, len)) = segment {
+                buf.slice(0..len);
+                self.sending.set(true);
+                let ret =
+                    self.ip_sender
+                        .send_to(dst, TransportHeader::TCP(header), &buf, self.net_cap);
+                if ret != ReturnCode::SUCCESS {
+                    debug!("[TCP] IP send_to failed: {:?}", ret);
+                    self.sending.set(false);
+                }
+            }
+            buf.reset();
+            self.tx_buffer.replace(buf);
+        });
+        self.start_timer();
+    }
+
+    fn start_timer(&self) {
+        if !self.timer_running.get() && self.sockets.iter().any(|socket| socket.timer_active()) {
+            self.timer_running.set(true);
+            self.alarm
+                .set_alarm(self.alarm.now(), A::ticks_from_ms(TCP_TIMER_MS));
+        }
+    }
+
+    fn finish(&self, socket: &'a TCPSocket<'a>, events: TcpEvents) {
+        socket.check_persist();
+        socket.report(events);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> TCPStack<'a> for MuxTcp<'a, A> {
+    fn add_socket(&self, socket: &'a TCPSocket<'a>) {
+        self.sockets.push_tail(socket);
+    }
+
+    fn connect(&self, socket: &'a TCPSocket<'a>, addr: IPAddr, port: u16) -> ReturnCode {
+        if socket.get_state() != TcpState::Closed {
+            return ReturnCode::EBUSY;
+        }
+        if port == 0 || addr.is_unspecified() || addr.is_multicast() {
+            return ReturnCode::EINVAL;
+        }
+        let local_port = match self.allocate_port() {
+            Some(local_port) => local_port,
+            None => return ReturnCode::ENOMEM,
+        };
+        socket.open_active(local_port, addr, port, self.next_iss());
+        self.do_output();
+        ReturnCode::SUCCESS
+    }
+
+    fn listen(&self, socket: &'a TCPSocket<'a>, port: u16) -> ReturnCode {
+        if socket.get_state() != TcpState::Closed {
+            return ReturnCode::EBUSY;
+        }
+        if port == 0 {
+            return ReturnCode::EINVAL;
+        }
+        if self
+            .sockets
+            .iter()
+            .any(|other| other.matches_listener(port))
+        {
+            return ReturnCode::EBUSY;
+        }
+        socket.open_passive(port);
+        ReturnCode::SUCCESS
+    }
+
+    fn send(&self, socket: &'a TCPSocket<'a>, data: &[u8]) -> usize {
+        let count = socket.write(data);
+        if count > 0 {
+            self.do_output();
+        }
+        count
+    }
+
+    fn recv(&self, socket: &'a TCPSocket<'a>, buf: &mut [u8]) -> usize {
+        let count = socket.read(buf);
+        if count > 0 {
+            self.do_output();
+        }
+        count
+    }
+
+    fn close(&self, socket: &'a TCPSocket<'a>) -> ReturnCode {
+        let ret = socket.close();
+        self.do_output();
+        ret
+    }
+
+    fn abort(&self, socket: &'a TCPSocket<'a>) {
+        if let Some(rst) = socket.abort() {
+            self.pending_rst.replace(rst);
+        }
+        self.do_output();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6RecvClient for MuxTcp<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::TCP {
+            return;
+        }
+        let (offset, header) = match TCPHeader::decode(payload).done() {
+            Some(result) => result,
+            None => {
+                debug!("[TCP] Failed to decode header");
+                return;
+            }
+        };
+        let data = &payload[offset..];
+        let src_addr = ip_header.get_src_addr();
+        let src_port = header.get_src_port();
+        let dst_port = header.get_dst_port();
+
+        let socket = self
+            .sockets
+            .iter()
+            .find(|socket| socket.matches_connection(src_addr, src_port, dst_port))
+            .or_else(|| {
+                self.sockets
+                    .iter()
+                    .find(|socket| socket.matches_listener(dst_port))
+            });
+        match socket {
+            Some(socket) => {
+                let events = socket.receive_segment(
+                    src_addr,
+                    &header,
+                    data,
+                    self.clock.get(),
+                    self.next_iss(),
+                );
+                if events.send_rst {
+                    self.queue_rst(src_addr, &header, data.len());
+                }
+                self.finish(socket, events);
+            }
+            None => self.queue_rst(src_addr, &header, data.len()),
+        }
+        self.do_output();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6SendClient for MuxTcp<'a, A> {
+    fn send_done(&self, result: ReturnCode) {
+        if result != ReturnCode::SUCCESS {
+            // Lost segments are recovered by retransmission
+            debug!("[TCP] Send failed: {:?}", result);
+        }
+        self.sending.set(false);
+        self.do_output();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for MuxTcp<'a, A> {
+    fn alarm(&self) {
+        self.timer_running.set(false);
+        self.clock.set(self.clock.get().wrapping_add(1));
+        for socket in self.sockets.iter() {
+            let events = socket.tick();
+            self.finish(socket, events);
+        }
+        self.do_output();
+    }
+}
@@ -0,0 +1,1050 @@
+//! This file contains the per-connection state of the TCP implementation:
+//! the transmission control block (TCB) defined in RFC 793, together with the
+//! send and receive buffers of the connection.
+//!
+//! A `TCPSocket` does not send or receive packets itself. Incoming segments
+//! are handed to it by the `MuxTcp` (see `tcp_mux.rs`), which also asks each
+//! socket for the next segment it wants to transmit whenever the IP layer is
+//! idle, and drives the retransmission timers of all sockets from a single
+//! alarm. This keeps all state transitions in this file, while the mux deals
+//! with demultiplexing and the lower layers.
+//!
+//! Both buffers are byte ring buffers. The send buffer holds all data that
+//! has been accepted from the user but not yet acknowledged by the peer; the
+//! first byte of the buffer always corresponds to `snd_una`. The receive
+//! buffer holds in-order data that has not yet been read by the user, and its
+//! free space is advertised to the peer as the receive window.
+//!
+//! Known limitations:
+//!
+//! - Out-of-order segments are dropped rather than queued; the peer will
+//!   retransmit them after the missing data has been acknowledged.
+//! - Retransmission is go-back-N from the oldest unacknowledged byte.
+//! - A listening socket is consumed by the first connection it accepts, so a
+//!   server that wants to serve several clients concurrently must listen on
+//!   several sockets.
+//! - Urgent data is not supported.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::tcp::tcp::{tcp_flags, TCPHeader};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::{ListLink, ListNode};
+use kernel::ReturnCode;
+
+/// Granularity of all TCP timers, in milliseconds.
+pub const TCP_TIMER_MS: u32 = 100;
+
+/// Initial retransmission timeout (RFC 6298, section 2.1).
+const INITIAL_RTO_MS: u32 = 1000;
+/// Lower bound of the retransmission timeout (RFC 6298, section 2.4).
+const MIN_RTO_MS: u32 = 1000;
+/// Upper bound of the retransmission timeout.
+const MAX_RTO_MS: u32 = 60000;
+/// Number of retransmissions of a segment before the connection is dropped.
+const MAX_RETRIES: u8 = 8;
+/// Time spent in TIME-WAIT. RFC 793 asks for 2 * MSL (4 minutes); we use a
+/// much shorter period to be able to reuse the few sockets we have.
+const TIME_WAIT_MS: u32 = 2000;
+
+/// Default MSS assumed for a peer that does not send the MSS option
+/// (RFC 8200 minimum MTU minus the IPv6 and TCP headers).
+pub const DEFAULT_MSS: u16 = 1220;
+
+/// The states of a TCP connection, as defined in RFC 793.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// Callbacks from a `TCPSocket` to the user of the socket.
+pub trait TCPClient<'a> {
+    /// The three-way handshake has completed, either for a connection that
+    /// was actively opened with `connect` or for one that was accepted by a
+    /// listening socket. In the latter case, the remote endpoint can be
+    /// retrieved with `TCPSocket::get_remote_endpoint`.
+    fn connected(&self, socket: &'a TCPSocket<'a>);
+
+    /// New data is available in the receive buffer, or the peer closed its
+    /// half of the connection (see `TCPSocket::is_remote_closed`).
+    fn received(&self, socket: &'a TCPSocket<'a>);
+
+    /// Data was acknowledged by the peer, freeing space in the send buffer.
+    fn sent(&self, socket: &'a TCPSocket<'a>);
+
+    /// The connection has been closed and the socket is `Closed` again.
+    /// `result` is `SUCCESS` after an orderly close, `ECANCEL` if the
+    /// connection was reset by the peer and `FAIL` if the peer stopped
+    /// responding or refused the connection.
+    fn closed(&self, socket: &'a TCPSocket<'a>, result: ReturnCode);
+}
+
+/// Modular comparisons of sequence numbers (RFC 793, section 3.3).
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_leq(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+/// Events resulting from processing a segment or timer, which the mux
+/// reports to the socket client once the socket state is consistent.
+#[derive(Copy, Clone, Default)]
+pub struct TcpEvents {
+    pub connected: bool,
+    pub received: bool,
+    pub sent: bool,
+    pub closed: Option<ReturnCode>,
+    /// A reset that must be sent to the peer in reply to the segment.
+    pub send_rst: bool,
+}
+
+pub struct TCPSocket<'a> {
+    id: usize,
+    state: Cell<TcpState>,
+    /// Whether the socket was opened passively, in which case a reset in
+    /// SYN-RECEIVED returns it to LISTEN (RFC 793, page 70).
+    passive: Cell<bool>,
+
+    local_port: Cell<u16>,
+    remote_addr: Cell<IPAddr>,
+    remote_port: Cell<u16>,
+
+    // Send sequence variables
+    iss: Cell<u32>,
+    snd_una: Cell<u32>,
+    snd_nxt: Cell<u32>,
+    snd_wnd: Cell<u32>,
+    snd_wl1: Cell<u32>,
+    snd_wl2: Cell<u32>,
+    snd_mss: Cell<u16>,
+
+    // Receive sequence variables
+    rcv_nxt: Cell<u32>,
+    rcv_adv_wnd: Cell<u32>,
+
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_start: Cell<usize>,
+    tx_len: Cell<usize>,
+    rx_buf: TakeCell<'static, [u8]>,
+    rx_start: Cell<usize>,
+    rx_len: Cell<usize>,
+
+    /// The user asked to close the connection once all data has been sent.
+    close_requested: Cell<bool>,
+    /// Our FIN has been sent and occupies sequence number `snd_nxt - 1`.
+    fin_sent: Cell<bool>,
+    /// The peer sent a FIN; no more data will be received.
+    fin_received: Cell<bool>,
+    ack_pending: Cell<bool>,
+    /// Send a single byte even if the peer's window is zero.
+    probe_pending: Cell<bool>,
+
+    // Retransmission state, in units of `TCP_TIMER_MS`
+    timer: Cell<u32>,
+    rto_ms: Cell<u32>,
+    srtt_ms: Cell<u32>,
+    rttvar_ms: Cell<u32>,
+    rtt_seq: OptionalCell<u32>,
+    rtt_start: Cell<u32>,
+    retries: Cell<u8>,
+
+    client: OptionalCell<&'a dyn TCPClient<'a>>,
+    next: ListLink<'a, TCPSocket<'a>>,
+}
+
+impl<'a> ListNode<'a, TCPSocket<'a>> for TCPSocket<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, TCPSocket<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> TCPSocket<'a> {
+    /// Creates a new, closed socket. `id` is an arbitrary identifier that
+    /// users sharing a `TCPClient` between sockets can use to tell them
+    /// apart.
+    pub fn new(id: usize, tx_buf: &'static mut [u8], rx_buf: &'static mut [u8]) -> TCPSocket<'a> {
+        TCPSocket {
+            id: id,
+            state: Cell::new(TcpState::Closed),
+            passive: Cell::new(false),
+            local_port: Cell::new(0),
+            remote_addr: Cell::new(IPAddr::new()),
+            remote_port: Cell::new(0),
+            iss: Cell::new(0),
+            snd_una: Cell::new(0),
+            snd_nxt: Cell::new(0),
+            snd_wnd: Cell::new(0),
+            snd_wl1: Cell::new(0),
+            snd_wl2: Cell::new(0),
+            snd_mss: Cell::new(DEFAULT_MSS),
+            rcv_nxt: Cell::new(0),
+            rcv_adv_wnd: Cell::new(0),
+            tx_buf: TakeCell::new(tx_buf),
+            tx_start: Cell::new(0),
+            tx_len: Cell::new(0),
+            rx_buf: TakeCell::new(rx_buf),
+            rx_start: Cell::new(0),
+            rx_len: Cell::new(0),
+            close_requested: Cell::new(false),
+            fin_sent: Cell::new(false),
+            fin_received: Cell::new(false),
+            ack_pending: Cell::new(false),
+            probe_pending: Cell::new(false),
+            timer: Cell::new(0),
+            rto_ms: Cell::new(INITIAL_RTO_MS),
+            srtt_ms: Cell::new(0),
+            rttvar_ms: Cell::new(0),
+            rtt_seq: OptionalCell::empty(),
+            rtt_start: Cell::new(0),
+            retries: Cell::new(0),
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn TCPClient<'a>) {
+        self.client.set(client);
+    }
+
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+
+    pub fn get_state(&self) -> TcpState {
+        self.state.get()
+    }
+
+    pub fn get_local_port(&self) -> u16 {
+        self.local_port.get()
+    }
+
+    pub fn get_remote_endpoint(&self) -> (IPAddr, u16) {
+        (self.remote_addr.get(), self.remote_port.get())
+    }
+
+    /// Returns true if the peer has closed its half of the connection and all
+    /// data it sent has been read.
+    pub fn is_remote_closed(&self) -> bool {
+        self.fin_received.get() && self.rx_len.get() == 0
+    }
+
+    /// Number of received bytes waiting to be read.
+    pub fn bytes_available(&self) -> usize {
+        self.rx_len.get()
+    }
+
+    /// Free space in the send buffer.
+    pub fn send_space(&self) -> usize {
+        self.tx_buf.map_or(0, |buf| buf.len()) - self.tx_len.get()
+    }
+
+    /// Number of sent bytes that have not been acknowledged yet, plus any
+    /// data still waiting to be sent.
+    pub fn bytes_unacked(&self) -> usize {
+        self.tx_len.get()
+    }
+
+    fn reset_connection_state(&self) {
+        self.tx_start.set(0);
+        self.tx_len.set(0);
+        self.rx_start.set(0);
+        self.rx_len.set(0);
+        self.close_requested.set(false);
+        self.fin_sent.set(false);
+        self.fin_received.set(false);
+        self.ack_pending.set(false);
+        self.probe_pending.set(false);
+        self.timer.set(0);
+        self.rto_ms.set(INITIAL_RTO_MS);
+        self.srtt_ms.set(0);
+        self.rttvar_ms.set(0);
+        self.rtt_seq.clear();
+        self.retries.set(0);
+        self.snd_mss.set(DEFAULT_MSS);
+    }
+
+    // Operations invoked by the mux on behalf of the socket user
+
+    pub(crate) fn open_active(&self, local_port: u16, addr: IPAddr, port: u16, iss: u32) {
+        self.reset_connection_state();
+        self.passive.set(false);
+        self.local_port.set(local_port);
+        self.remote_addr.set(addr);
+        self.remote_port.set(port);
+        self.iss.set(iss);
+        self.snd_una.set(iss);
+        self.snd_nxt.set(iss);
+        self.snd_wnd.set(0);
+        self.state.set(TcpState::SynSent);
+    }
+
+    pub(crate) fn open_passive(&self, local_port: u16) {
+        self.reset_connection_state();
+        self.passive.set(true);
+        self.local_port.set(local_port);
+        self.remote_addr.set(IPAddr::new());
+        self.remote_port.set(0);
+        self.state.set(TcpState::Listen);
+    }
+
+    /// Copies as much of `data` as fits into the send buffer, returning the
+    /// number of bytes accepted.
+    pub(crate) fn write(&self, data: &[u8]) -> usize {
+        match self.state.get() {
+            TcpState::SynSent
+            | TcpState::SynReceived
+            | TcpState::Established
+            | TcpState::CloseWait => {}
+            _ => return 0,
+        }
+        if self.close_requested.get() {
+            return 0;
+        }
+        let start = self.tx_start.get();
+        let len = self.tx_len.get();
+        self.tx_buf.map_or(0, |buf| {
+            let count = cmp::min(data.len(), buf.len() - len);
+            for (i, b) in data[..count].iter().enumerate() {
+                buf[(start + len + i) % buf.len()] = *b;
+            }
+            self.tx_len.set(len + count);
+            count
+        })
+    }
+
+    /// Copies received data into `data`, returning the number of bytes
+    /// copied. If the read opens up the receive window significantly, a
+    /// window update is scheduled.
+    pub(crate) fn read(&self, data: &mut [u8]) -> usize {
+        let start = self.rx_start.get();
+        let len = self.rx_len.get();
+        let count = self.rx_buf.map_or(0, |buf| {
+            let count = cmp::min(data.len(), len);
+            for (i, b) in data[..count].iter_mut().enumerate() {
+                *b = buf[(start + i) % buf.len()];
+            }
+            self.rx_start.set((start + count) % buf.len());
+            count
+        });
+        self.rx_len.set(len - count);
+        if count > 0 && self.is_synchronized() && !self.fin_received.get() {
+            // Avoid silly window syndrome (RFC 1122, 4.2.3.3): only update
+            // the window once it grew by a reasonable amount.
+            let threshold = cmp::min(self.rx_capacity() / 2, self.snd_mss.get() as usize) as u32;
+            if self.rcv_window().wrapping_sub(self.rcv_adv_wnd.get()) >= threshold {
+                self.ack_pending.set(true);
+            }
+        }
+        count
+    }
+
+    /// Starts an orderly close. The FIN is sent once all buffered data has
+    /// been transmitted. Returns `EALREADY` if the close already started.
+    pub(crate) fn close(&self) -> ReturnCode {
+        match self.state.get() {
+            TcpState::Closed => ReturnCode::EALREADY,
+            TcpState::Listen | TcpState::SynSent => {
+                self.state.set(TcpState::Closed);
+                self.timer.set(0);
+                ReturnCode::SUCCESS
+            }
+            TcpState::SynReceived | TcpState::Established | TcpState::CloseWait => {
+                if self.close_requested.get() {
+                    ReturnCode::EALREADY
+                } else {
+                    self.close_requested.set(true);
+                    ReturnCode::SUCCESS
+                }
+            }
+            _ => ReturnCode::EALREADY,
+        }
+    }
+
+    /// Moves the socket to `Closed` immediately. Returns the header of a
+    /// reset that must be sent to the peer, if the connection had been
+    /// synchronized.
+    pub(crate) fn abort(&self) -> Option<(IPAddr, TCPHeader)> {
+        let rst = match self.state.get() {
+            TcpState::SynReceived
+            | TcpState::Established
+            | TcpState::FinWait1
+            | TcpState::FinWait2
+            | TcpState::CloseWait => {
+                let mut header = self.make_header(tcp_flags::RST | tcp_flags::ACK);
+                header.set_seq_num(self.snd_nxt.get());
+                Some((self.remote_addr.get(), header))
+            }
+            _ => None,
+        };
+        self.state.set(TcpState::Closed);
+        self.timer.set(0);
+        rst
+    }
+
+    // Helpers
+
+    fn is_synchronized(&self) -> bool {
+        match self.state.get() {
+            TcpState::Closed | TcpState::Listen | TcpState::SynSent => false,
+            _ => true,
+        }
+    }
+
+    fn rx_capacity(&self) -> usize {
+        self.rx_buf.map_or(0, |buf| buf.len())
+    }
+
+    fn rcv_window(&self) -> u32 {
+        cmp::min(self.rx_capacity() - self.rx_len.get(), 0xffff) as u32
+    }
+
+    /// Number of bytes of the send buffer that have been transmitted (but
+    /// possibly not acknowledged).
+    fn tx_in_flight(&self) -> usize {
+        let mut seq_len = self.snd_nxt.get().wrapping_sub(self.snd_una.get());
+        if self.fin_sent.get() && seq_len > 0 {
+            seq_len -= 1;
+        }
+        cmp::min(seq_len as usize, self.tx_len.get())
+    }
+
+    fn make_header(&self, flags: u16) -> TCPHeader {
+        let mut header = TCPHeader::new();
+        header.set_src_port(self.local_port.get());
+        header.set_dst_port(self.remote_port.get());
+        header.set_seq_num(self.snd_nxt.get());
+        header.set_ack_num(if flags & tcp_flags::ACK != 0 {
+            self.rcv_nxt.get()
+        } else {
+            0
+        });
+        header.set_flags(flags);
+        let window = self.rcv_window();
+        header.set_window(window as u16);
+        header
+    }
+
+    fn start_timer_if_stopped(&self) {
+        if self.timer.get() == 0 {
+            self.timer
+                .set(cmp::max(self.rto_ms.get() / TCP_TIMER_MS, 1));
+        }
+    }
+
+    fn restart_timer(&self) {
+        self.timer
+            .set(cmp::max(self.rto_ms.get() / TCP_TIMER_MS, 1));
+    }
+
+    fn enter_time_wait(&self) {
+        self.state.set(TcpState::TimeWait);
+        self.timer.set(TIME_WAIT_MS / TCP_TIMER_MS);
+    }
+
+    /// Updates the RTT estimate (RFC 6298, section 2) with a sample taken
+    /// at timer tick `now`.
+    fn update_rtt(&self, now: u32) {
+        let sample = now.wrapping_sub(self.rtt_start.get()) * TCP_TIMER_MS;
+        if self.srtt_ms.get() == 0 {
+            self.srtt_ms.set(cmp::max(sample, 1));
+            self.rttvar_ms.set(sample / 2);
+        } else {
+            let srtt = self.srtt_ms.get();
+            let delta = if srtt > sample {
+                srtt - sample
+            } else {
+                sample - srtt
+            };
+            self.rttvar_ms.set((3 * self.rttvar_ms.get() + delta) / 4);
+            self.srtt_ms.set((7 * srtt + sample) / 8);
+        }
+        let rto = self.srtt_ms.get() + cmp::max(TCP_TIMER_MS, 4 * self.rttvar_ms.get());
+        self.rto_ms
+            .set(cmp::min(cmp::max(rto, MIN_RTO_MS), MAX_RTO_MS));
+    }
+
+    /// Returns whether this socket is the endpoint of a segment from
+    /// `addr`:`src_port` to local port `dst_port`. Listening sockets are not
+    /// matched here, see `matches_listener`.
+    pub(crate) fn matches_connection(&self, addr: IPAddr, src_port: u16, dst_port: u16) -> bool {
+        match self.state.get() {
+            TcpState::Closed | TcpState::Listen => false,
+            _ => {
+                self.local_port.get() == dst_port
+                    && self.remote_port.get() == src_port
+                    && self.remote_addr.get() == addr
+            }
+        }
+    }
+
+    pub(crate) fn matches_listener(&self, dst_port: u16) -> bool {
+        self.state.get() == TcpState::Listen && self.local_port.get() == dst_port
+    }
+
+    /// Returns whether `port` is currently used as the local port of this
+    /// socket.
+    pub(crate) fn uses_port(&self, port: u16) -> bool {
+        self.state.get() != TcpState::Closed && self.local_port.get() == port
+    }
+
+    pub(crate) fn timer_active(&self) -> bool {
+        self.timer.get() > 0
+    }
+
+    // Input processing (RFC 793, section 3.9, "SEGMENT ARRIVES")
+
+    /// Processes an incoming segment addressed to this socket. `now` is the
+    /// current timer tick and `iss` a fresh initial sequence number in case
+    /// the segment opens a connection on a listening socket.
+    pub(crate) fn receive_segment(
+        &self,
+        src_addr: IPAddr,
+        header: &TCPHeader,
+        payload: &[u8],
+        now: u32,
+        iss: u32,
+    ) -> TcpEvents {
+        let mut events = TcpEvents::default();
+        match self.state.get() {
+            TcpState::Closed => {}
+            TcpState::Listen => self.receive_listen(src_addr, header, iss, &mut events),
+            TcpState::SynSent => self.receive_syn_sent(header, now, &mut events),
+            _ => self.receive_synchronized(header, payload, now, &mut events),
+        }
+        events
+    }
+
+    fn receive_listen(
+        &self,
+        src_addr: IPAddr,
+        header: &TCPHeader,
+        iss: u32,
+        events: &mut TcpEvents,
+    ) {
+        if header.has_flags(tcp_flags::RST) {
+            return;
+        }
+        if header.has_flags(tcp_flags::ACK) {
+            events.send_rst = true;
+            return;
+        }
+        if !header.has_flags(tcp_flags::SYN) {
+            return;
+        }
+        self.remote_addr.set(src_addr);
+        self.remote_port.set(header.get_src_port());
+        self.rcv_nxt.set(header.get_seq_num().wrapping_add(1));
+        self.iss.set(iss);
+        self.snd_una.set(iss);
+        self.snd_nxt.set(iss);
+        self.snd_wnd.set(header.get_window() as u32);
+        self.snd_wl1.set(header.get_seq_num());
+        self.snd_wl2.set(iss);
+        self.snd_mss.set(header.get_mss().unwrap_or(DEFAULT_MSS));
+        self.state.set(TcpState::SynReceived);
+    }
+
+    fn receive_syn_sent(&self, header: &TCPHeader, now: u32, events: &mut TcpEvents) {
+        let ack = header.get_ack_num();
+        if header.has_flags(tcp_flags::ACK)
+            && (seq_leq(ack, self.iss.get()) || seq_lt(self.snd_nxt.get(), ack))
+        {
+            if !header.has_flags(tcp_flags::RST) {
+                events.send_rst = true;
+            }
+            return;
+        }
+        if header.has_flags(tcp_flags::RST) {
+            if header.has_flags(tcp_flags::ACK) {
+                // Connection refused
+                self.state.set(TcpState::Closed);
+                self.timer.set(0);
+                events.closed = Some(ReturnCode::FAIL);
+            }
+            return;
+        }
+        if !header.has_flags(tcp_flags::SYN) {
+            return;
+        }
+        self.rcv_nxt.set(header.get_seq_num().wrapping_add(1));
+        self.snd_mss.set(header.get_mss().unwrap_or(DEFAULT_MSS));
+        self.snd_wnd.set(header.get_window() as u32);
+        self.snd_wl1.set(header.get_seq_num());
+        self.snd_wl2.set(ack);
+        self.ack_pending.set(true);
+        if header.has_flags(tcp_flags::ACK) {
+            self.snd_una.set(ack);
+            if self.rtt_seq.contains(&self.iss.get()) {
+                self.update_rtt(now);
+            }
+            self.rtt_seq.clear();
+            self.retries.set(0);
+            self.timer.set(0);
+            self.state.set(TcpState::Established);
+            events.connected = true;
+        } else {
+            // Simultaneous open: reply with a SYN-ACK
+            self.snd_nxt.set(self.iss.get());
+            self.state.set(TcpState::SynReceived);
+        }
+    }
+
+    fn receive_synchronized(
+        &self,
+        header: &TCPHeader,
+        payload: &[u8],
+        now: u32,
+        events: &mut TcpEvents,
+    ) {
+        let mut seq = header.get_seq_num();
+        let mut payload = payload;
+        let mut fin = header.has_flags(tcp_flags::FIN);
+        let rcv_nxt = self.rcv_nxt.get();
+
+        // Check acceptability. Retransmitted data that partially overlaps
+        // what we already received is trimmed; anything starting beyond
+        // rcv_nxt is dropped since we do not queue out-of-order segments.
+        if seq_lt(seq, rcv_nxt) {
+            let dup = rcv_nxt.wrapping_sub(seq) as usize;
+            if dup > payload.len() + fin as usize || header.has_flags(tcp_flags::SYN) {
+                if !header.has_flags(tcp_flags::RST) {
+                    self.ack_pending.set(true);
+                }
+                return;
+            }
+            // Our acknowledgment of the duplicate part was probably lost
+            self.ack_pending.set(true);
+            if dup > payload.len() {
+                // Only the FIN is a duplicate
+                payload = &[];
+                fin = false;
+            } else {
+                payload = &payload[dup..];
+            }
+            seq = rcv_nxt;
+        } else if seq != rcv_nxt {
+            if !header.has_flags(tcp_flags::RST) {
+                self.ack_pending.set(true);
+            }
+            return;
+        }
+
+        if header.has_flags(tcp_flags::RST) {
+            if self.state.get() == TcpState::SynReceived && self.passive.get() {
+                self.state.set(TcpState::Listen);
+                self.timer.set(0);
+            } else {
+                self.state.set(TcpState::Closed);
+                self.timer.set(0);
+                events.closed = Some(ReturnCode::ECANCEL);
+            }
+            return;
+        }
+
+        if header.has_flags(tcp_flags::SYN) {
+            // A SYN in the window is an error (RFC 793, page 71)
+            events.send_rst = true;
+            self.state.set(TcpState::Closed);
+            self.timer.set(0);
+            events.closed = Some(ReturnCode::ECANCEL);
+            return;
+        }
+
+        if !header.has_flags(tcp_flags::ACK) {
+            return;
+        }
+
+        if !self.process_ack(header, seq, now, events) {
+            return;
+        }
+
+        // Process the segment text
+        if !payload.is_empty() {
+            match self.state.get() {
+                TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 => {
+                    let copied = self.store_received(payload);
+                    if copied > 0 {
+                        self.rcv_nxt
+                            .set(self.rcv_nxt.get().wrapping_add(copied as u32));
+                        events.received = true;
+                    }
+                    if copied < payload.len() {
+                        // Data beyond our window, the FIN cannot be
+                        // accepted yet either
+                        fin = false;
+                    }
+                    self.ack_pending.set(true);
+                }
+                _ => {}
+            }
+        }
+
+        if fin {
+            self.rcv_nxt.set(self.rcv_nxt.get().wrapping_add(1));
+            self.ack_pending.set(true);
+            match self.state.get() {
+                TcpState::SynReceived | TcpState::Established => {
+                    self.fin_received.set(true);
+                    self.state.set(TcpState::CloseWait);
+                    events.received = true;
+                }
+                TcpState::FinWait1 => {
+                    // Our FIN has not been acknowledged yet, otherwise we
+                    // would be in FIN-WAIT-2 by now
+                    self.fin_received.set(true);
+                    self.state.set(TcpState::Closing);
+                    events.received = true;
+                }
+                TcpState::FinWait2 => {
+                    self.fin_received.set(true);
+                    self.enter_time_wait();
+                    events.received = true;
+                }
+                TcpState::TimeWait => self.enter_time_wait(),
+                _ => {}
+            }
+        }
+    }
+
+    /// Processes the acknowledgment field of a segment. Returns false if the
+    /// segment should not be processed further.
+    fn process_ack(&self, header: &TCPHeader, seq: u32, now: u32, events: &mut TcpEvents) -> bool {
+        let ack = header.get_ack_num();
+        let snd_una = self.snd_una.get();
+        let snd_nxt = self.snd_nxt.get();
+
+        if self.state.get() == TcpState::SynReceived {
+            if seq_lt(snd_una, ack) && seq_leq(ack, snd_nxt) {
+                self.snd_una.set(self.iss.get().wrapping_add(1));
+                if self.rtt_seq.contains(&self.iss.get()) {
+                    self.update_rtt(now);
+                }
+                self.rtt_seq.clear();
+                self.retries.set(0);
+                self.timer.set(0);
+                self.state.set(TcpState::Established);
+                events.connected = true;
+            } else {
+                events.send_rst = true;
+                return false;
+            }
+        }
+
+        let snd_una = self.snd_una.get();
+        if seq_lt(snd_nxt, ack) {
+            // Acknowledges something we did not send yet
+            self.ack_pending.set(true);
+            return false;
+        }
+
+        if seq_lt(snd_una, ack) {
+            let mut acked = ack.wrapping_sub(snd_una) as usize;
+            let fin_acked = self.fin_sent.get() && ack == snd_nxt;
+            if fin_acked {
+                acked -= 1;
+            }
+            let acked = cmp::min(acked, self.tx_len.get());
+            if acked > 0 {
+                let capacity = self.tx_buf.map_or(1, |buf| buf.len());
+                self.tx_start.set((self.tx_start.get() + acked) % capacity);
+                self.tx_len.set(self.tx_len.get() - acked);
+                events.sent = true;
+            }
+            self.snd_una.set(ack);
+            if let Some(rtt_seq) = self.rtt_seq.take() {
+                if seq_lt(rtt_seq, ack) {
+                    self.update_rtt(now);
+                } else {
+                    self.rtt_seq.set(rtt_seq);
+                }
+            }
+            self.retries.set(0);
+            if ack == self.snd_nxt.get() {
+                self.timer.set(0);
+            } else {
+                self.restart_timer();
+            }
+
+            if fin_acked {
+                match self.state.get() {
+                    TcpState::FinWait1 => self.state.set(TcpState::FinWait2),
+                    TcpState::Closing => self.enter_time_wait(),
+                    TcpState::LastAck => {
+                        self.state.set(TcpState::Closed);
+                        self.timer.set(0);
+                        events.closed = Some(ReturnCode::SUCCESS);
+                        return false;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Update the send window (RFC 793, page 72)
+        if seq_lt(self.snd_wl1.get(), seq)
+            || (self.snd_wl1.get() == seq && seq_leq(self.snd_wl2.get(), ack))
+        {
+            self.snd_wnd.set(header.get_window() as u32);
+            self.snd_wl1.set(seq);
+            self.snd_wl2.set(ack);
+        }
+        true
+    }
+
+    /// Appends in-order data to the receive buffer, returning the number of
+    /// bytes that fit.
+    fn store_received(&self, data: &[u8]) -> usize {
+        let start = self.rx_start.get();
+        let len = self.rx_len.get();
+        let count = self.rx_buf.map_or(0, |buf| {
+            let count = cmp::min(data.len(), buf.len() - len);
+            for (i, b) in data[..count].iter().enumerate() {
+                buf[(start + len + i) % buf.len()] = *b;
+            }
+            count
+        });
+        self.rx_len.set(len + count);
+        count
+    }
+
+    // Output processing
+
+    /// Returns whether the socket has a segment to send.
+    pub(crate) fn has_output(&self, mss: usize) -> bool {
+        match self.state.get() {
+            TcpState::Closed | TcpState::Listen => false,
+            TcpState::SynSent | TcpState::SynReceived => self.syn_pending(),
+            _ => self.ack_pending.get() || self.next_data_len(mss) > 0 || self.fin_ready(),
+        }
+    }
+
+    /// Returns whether our SYN must be (re)transmitted. A duplicate SYN
+    /// received in SYN-RECEIVED means our SYN-ACK was lost.
+    fn syn_pending(&self) -> bool {
+        self.snd_nxt.get() == self.iss.get()
+            || (self.state.get() == TcpState::SynReceived && self.ack_pending.get())
+    }
+
+    /// Number of bytes of data that can be sent now. Data may still need to
+    /// be retransmitted after our FIN was sent.
+    fn next_data_len(&self, mss: usize) -> usize {
+        match self.state.get() {
+            TcpState::Established
+            | TcpState::CloseWait
+            | TcpState::FinWait1
+            | TcpState::Closing
+            | TcpState::LastAck => {}
+            _ => return 0,
+        }
+        let unsent = self.tx_len.get() - self.tx_in_flight();
+        let in_flight = self.snd_nxt.get().wrapping_sub(self.snd_una.get());
+        let window = self.snd_wnd.get().saturating_sub(in_flight) as usize;
+        let window = if window == 0 && self.probe_pending.get() {
+            1
+        } else {
+            window
+        };
+        cmp::min(
+            cmp::min(unsent, window),
+            cmp::min(mss, self.snd_mss.get() as usize),
+        )
+    }
+
+    fn fin_ready(&self) -> bool {
+        self.close_requested.get()
+            && !self.fin_sent.get()
+            && self.tx_in_flight() == self.tx_len.get()
+            && match self.state.get() {
+                TcpState::Established
+                | TcpState::CloseWait
+                | TcpState::FinWait1
+                | TcpState::Closing
+                | TcpState::LastAck => true,
+                _ => false,
+            }
+    }
+
+    /// Builds the next segment to transmit, copying any data into `payload`.
+    /// Returns the destination address, the header and the payload length.
+    /// `mss` is the largest payload the caller can send, and `now` the
+    /// current timer tick.
+    pub(crate) fn next_segment(
+        &self,
+        payload: &mut [u8],
+        mss: usize,
+        now: u32,
+    ) -> Option<(IPAddr, TCPHeader, usize)> {
+        let mss = cmp::min(mss, payload.len());
+        let (header, len) = match self.state.get() {
+            TcpState::Closed | TcpState::Listen => return None,
+            TcpState::SynSent | TcpState::SynReceived => {
+                if self.syn_pending() {
+                    self.snd_nxt.set(self.iss.get());
+                    let flags = if self.state.get() == TcpState::SynSent {
+                        tcp_flags::SYN
+                    } else {
+                        tcp_flags::SYN | tcp_flags::ACK
+                    };
+                    let mut header = self.make_header(flags);
+                    header.set_mss(Some(cmp::min(mss, 0xffff) as u16));
+                    self.snd_nxt.set(self.iss.get().wrapping_add(1));
+                    if self.retries.get() == 0 {
+                        self.rtt_seq.set(self.iss.get());
+                        self.rtt_start.set(now);
+                    }
+                    self.start_timer_if_stopped();
+                    (header, 0)
+                } else {
+                    return None;
+                }
+            }
+            _ => {
+                let len = self.next_data_len(mss);
+                if len > 0 {
+                    let offset = self.tx_in_flight();
+                    let start = self.tx_start.get();
+                    self.tx_buf.map(|buf| {
+                        for (i, b) in payload[..len].iter_mut().enumerate() {
+                            *b = buf[(start + offset + i) % buf.len()];
+                        }
+                    });
+                    let mut flags = tcp_flags::ACK;
+                    if offset + len == self.tx_len.get() {
+                        flags |= tcp_flags::PSH;
+                    }
+                    let header = self.make_header(flags);
+                    if self.rtt_seq.is_none() && self.retries.get() == 0 {
+                        self.rtt_seq.set(self.snd_nxt.get());
+                        self.rtt_start.set(now);
+                    }
+                    self.snd_nxt
+                        .set(self.snd_nxt.get().wrapping_add(len as u32));
+                    self.probe_pending.set(false);
+                    self.start_timer_if_stopped();
+                    (header, len)
+                } else if self.fin_ready() {
+                    let header = self.make_header(tcp_flags::FIN | tcp_flags::ACK);
+                    self.snd_nxt.set(self.snd_nxt.get().wrapping_add(1));
+                    self.fin_sent.set(true);
+                    match self.state.get() {
+                        TcpState::Established => self.state.set(TcpState::FinWait1),
+                        TcpState::CloseWait => self.state.set(TcpState::LastAck),
+                        _ => {}
+                    }
+                    self.start_timer_if_stopped();
+                    (header, 0)
+                } else if self.ack_pending.get() {
+                    (self.make_header(tcp_flags::ACK), 0)
+                } else {
+                    return None;
+                }
+            }
+        };
+        self.ack_pending.set(false);
+        if header.has_flags(tcp_flags::ACK) {
+            self.rcv_adv_wnd.set(header.get_window() as u32);
+        }
+        Some((self.remote_addr.get(), header, len))
+    }
+
+    /// Called by the mux every `TCP_TIMER_MS`. Returns the events that
+    /// resulted from a timer expiring.
+    pub(crate) fn tick(&self) -> TcpEvents {
+        let mut events = TcpEvents::default();
+        let timer = self.timer.get();
+        if timer == 0 {
+            return events;
+        }
+        self.timer.set(timer - 1);
+        if timer > 1 {
+            return events;
+        }
+
+        if self.state.get() == TcpState::TimeWait {
+            self.state.set(TcpState::Closed);
+            events.closed = Some(ReturnCode::SUCCESS);
+            return events;
+        }
+
+        if self.snd_una.get() == self.snd_nxt.get() {
+            // Nothing outstanding: this was the persist timer
+            if self.tx_len.get() > 0 && self.snd_wnd.get() == 0 {
+                self.probe_pending.set(true);
+            }
+            return events;
+        }
+
+        // Retransmission timeout (RFC 6298, section 5). Window probes are
+        // not counted, as a peer may legitimately keep its window closed for
+        // a long time.
+        let probing = self.is_synchronized() && self.snd_wnd.get() == 0;
+        if !probing {
+            if self.retries.get() >= MAX_RETRIES {
+                self.state.set(TcpState::Closed);
+                events.closed = Some(ReturnCode::FAIL);
+                return events;
+            }
+            self.retries.set(self.retries.get() + 1);
+        }
+        self.rto_ms.set(cmp::min(self.rto_ms.get() * 2, MAX_RTO_MS));
+        self.rtt_seq.clear();
+        match self.state.get() {
+            TcpState::SynSent | TcpState::SynReceived => {
+                self.snd_nxt.set(self.iss.get());
+            }
+            _ => {
+                // Go back to the oldest unacknowledged byte. If our FIN is
+                // outstanding, it is resent after the data.
+                self.snd_nxt.set(self.snd_una.get());
+                self.fin_sent.set(false);
+                if probing {
+                    self.probe_pending.set(true);
+                }
+            }
+        }
+        events
+    }
+
+    /// Arms the persist timer if data is waiting for the peer's window to
+    /// open, so that a lost window update cannot deadlock the connection.
+    pub(crate) fn check_persist(&self) {
+        if self.timer.get() == 0
+            && self.snd_una.get() == self.snd_nxt.get()
+            && self.snd_wnd.get() == 0
+            && self.tx_len.get() > 0
+            && self.is_synchronized()
+        {
+            self.start_timer_if_stopped();
+        }
+    }
+
+    pub(crate) fn report(&'a self, events: TcpEvents) {
+        self.client.map(|client| {
+            if events.connected {
+                client.connected(self);
+            }
+            if events.sent {
+                client.sent(self);
+            }
+            if events.received {
+                client.received(self);
+            }
+            if let Some(result) = events.closed {
+                client.closed(self, result);
+            }
+        });
+    }
+}
@@ -0,0 +1,247 @@
+//! This file contains `DataPoll`, with which a Thread sleepy end device
+//! (SED) fetches the frames its parent holds for it.
+//!
+//! A SED turns its receiver off when idle, so its parent cannot send it
+//! frames as they arrive. The parent holds them instead, until the child
+//! asks for them with a MAC Data Request command. `DataPoll` sends a data
+//! request to the parent every poll period, and keeps the receiver on for
+//! `RX_WINDOW_MS` after each data request the parent acknowledges, during
+//! which the parent sends the frame it holds, if any. A data request that
+//! is not acknowledged is sent again after `RETRY_PERIOD_MS`.
+//!
+//! The responses to the MLE requests a SED sends to its parent are held by
+//! the parent too. After sending such a request, the device calls
+//! `poll_fast`, which shortens the poll period to `FAST_POLL_PERIOD_MS` for
+//! the next `FAST_POLLS` polls.
+//!
+//! `DataPoll` turns the receiver off when it starts polling and on again
+//! when it stops, with `MacDevice::set_rx_on_when_idle`. It sends its data
+//! requests with its own MAC user, whose transmit client it is.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let data_poll = static_init!(
+//!     DataPoll<'static, VirtualMuxAlarm<'static, A>>,
+//!     DataPoll::new(poll_mac, poll_alarm, &mut POLL_BUF)
+//! );
+//! poll_mac.set_transmit_client(data_poll);
+//! poll_alarm.set_alarm_client(data_poll);
+//! data_poll.set_client(mle_attach);
+//! ```
+
+use crate::ieee802154::device::{MacDevice, TxClient};
+use crate::net::ieee802154::{mac_command, KeyId, MacAddress, SecurityLevel};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::debug;
+use kernel::hil::time;
+use kernel::ReturnCode;
+
+/// Default period of the data requests.
+pub const DEFAULT_POLL_PERIOD_MS: u32 = 5000;
+/// Milliseconds the receiver stays on after an acknowledged data request.
+pub const RX_WINDOW_MS: u32 = 100;
+/// Milliseconds after which a data request that was not acknowledged is
+/// sent again.
+pub const RETRY_PERIOD_MS: u32 = 1000;
+/// Period of the polls after `poll_fast` is called.
+pub const FAST_POLL_PERIOD_MS: u32 = 200;
+/// Number of polls sent every `FAST_POLL_PERIOD_MS` after `poll_fast` is
+/// called.
+pub const FAST_POLLS: u8 = 5;
+
+/// Trait to be implemented by the user of `DataPoll`, usually MLE, which is
+/// told whether the parent acknowledged each data request.
+pub trait DataPollClient {
+    fn poll_done(&self, acked: bool);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum PollState {
+    Stopped,
+    /// Waiting for the next poll, with the receiver off
+    Idle,
+    /// Sending a data request
+    Sending,
+    /// Listening for the frame the parent holds, if any
+    Listening,
+}
+
+pub struct DataPoll<'a, A: time::Alarm<'a>> {
+    mac: &'a dyn MacDevice<'a>,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn DataPollClient>,
+    /// The parent polled, and the security of the data requests
+    parent: OptionalCell<(MacAddress, Option<(SecurityLevel, KeyId)>)>,
+    period_ms: Cell<u32>,
+    /// Number of polls left to send every `FAST_POLL_PERIOD_MS`
+    fast_polls: Cell<u8>,
+    state: Cell<PollState>,
+    tx_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, A: time::Alarm<'a>> DataPoll<'a, A> {
+    /// `tx_buffer` must be able to hold a radio frame, that is at least
+    /// `radio::MAX_BUF_SIZE` bytes long.
+    pub fn new(
+        mac: &'a dyn MacDevice<'a>,
+        alarm: &'a A,
+        tx_buffer: &'static mut [u8],
+    ) -> DataPoll<'a, A> {
+        DataPoll {
+            mac: mac,
+            alarm: alarm,
+            client: OptionalCell::empty(),
+            parent: OptionalCell::empty(),
+            period_ms: Cell::new(DEFAULT_POLL_PERIOD_MS),
+            fast_polls: Cell::new(0),
+            state: Cell::new(PollState::Stopped),
+            tx_buffer: TakeCell::new(tx_buffer),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn DataPollClient) {
+        self.client.set(client);
+    }
+
+    /// Sets the poll period, which takes effect after the next poll.
+    pub fn set_period(&self, period_ms: u32) {
+        self.period_ms.set(period_ms);
+    }
+
+    pub fn is_polling(&self) -> bool {
+        self.parent.is_some()
+    }
+
+    /// Turns the receiver off and starts polling `parent` with data
+    /// requests secured with `security`. The first data request is sent
+    /// after one poll period. If the device is already polling, only the
+    /// parent and security are updated.
+    pub fn start(&self, parent: MacAddress, security: Option<(SecurityLevel, KeyId)>) {
+        let polling = self.is_polling();
+        self.parent.set((parent, security));
+        if polling {
+            return;
+        }
+        self.fast_polls.set(0);
+        self.mac.set_rx_on_when_idle(false);
+        if self.state.get() != PollState::Sending {
+            self.schedule(self.period_ms.get());
+        }
+    }
+
+    /// Stops polling and turns the receiver on.
+    pub fn stop(&self) {
+        if !self.is_polling() {
+            return;
+        }
+        self.parent.clear();
+        self.alarm.disarm();
+        self.mac.set_rx_on_when_idle(true);
+        if self.state.get() != PollState::Sending {
+            self.state.set(PollState::Stopped);
+        }
+    }
+
+    /// Polls every `FAST_POLL_PERIOD_MS` for the next `FAST_POLLS` polls.
+    pub fn poll_fast(&self) {
+        if !self.is_polling() {
+            return;
+        }
+        self.fast_polls.set(FAST_POLLS);
+        if self.state.get() == PollState::Idle {
+            self.schedule(self.next_period());
+        }
+    }
+
+    fn next_period(&self) -> u32 {
+        let fast_polls = self.fast_polls.get();
+        if fast_polls > 0 {
+            self.fast_polls.set(fast_polls - 1);
+            FAST_POLL_PERIOD_MS
+        } else {
+            self.period_ms.get()
+        }
+    }
+
+    fn schedule(&self, ms: u32) {
+        self.state.set(PollState::Idle);
+        self.alarm.set_alarm(self.alarm.now(), A::ticks_from_ms(ms));
+    }
+
+    /// Sends a data request to the parent.
+    fn poll(&self) {
+        let (parent, security) = match self.parent.map(|parent| *parent) {
+            Some(parent) => parent,
+            None => return,
+        };
+        let buf = match self.tx_buffer.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        // Devices are identified by their extended address until their
+        // parent assigns them a short one
+        let src_addr = match self.mac.get_address() {
+            0xfffe | 0xffff => MacAddress::Long(self.mac.get_address_long()),
+            short_addr => MacAddress::Short(short_addr),
+        };
+        let pan = self.mac.get_pan();
+        let result = match self.mac.prepare_command_frame(
+            buf,
+            pan,
+            parent,
+            pan,
+            src_addr,
+            security,
+            mac_command::DATA_REQUEST,
+        ) {
+            Ok(frame) => {
+                // The receiver stays on once the data request is sent
+                self.mac.set_rx_on_when_idle(true);
+                self.state.set(PollState::Sending);
+                self.mac.transmit(frame)
+            }
+            Err(buf) => (ReturnCode::FAIL, Some(buf)),
+        };
+        if result.0 != ReturnCode::SUCCESS {
+            debug!("[DataPoll] Data request failed: {:?}", result.0);
+            result.1.map(|buf| self.tx_buffer.replace(buf));
+            self.mac.set_rx_on_when_idle(false);
+            self.schedule(RETRY_PERIOD_MS);
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> TxClient for DataPoll<'a, A> {
+    fn send_done(&self, buf: &'static mut [u8], acked: bool, _result: ReturnCode) {
+        self.tx_buffer.replace(buf);
+        if !self.is_polling() {
+            // Stopped while sending; the receiver is already on
+            self.state.set(PollState::Stopped);
+            return;
+        }
+        if acked {
+            self.state.set(PollState::Listening);
+            self.alarm
+                .set_alarm(self.alarm.now(), A::ticks_from_ms(RX_WINDOW_MS));
+        } else {
+            self.mac.set_rx_on_when_idle(false);
+            self.schedule(RETRY_PERIOD_MS);
+        }
+        self.client.map(|client| client.poll_done(acked));
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for DataPoll<'a, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            PollState::Idle => self.poll(),
+            PollState::Listening => {
+                self.mac.set_rx_on_when_idle(false);
+                self.schedule(self.next_period());
+            }
+            PollState::Stopped | PollState::Sending => {}
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Implements the format and security of Mesh Link Establishment (MLE)
+//! messages, as outlined in Chapter 4 of the Thread 1.1.1 Specification.
+//! The attach procedure built on them is implemented by
+//! [MleAttach](../mle_attach/struct.MleAttach.html).
+//!
+//! MLE for network attaching comprises a four-step handshake that works
+//! as follows:
+//!
+//! 1. A child device multicasts a Parent Request MLE command.
+//! 2. Each potential parent device on the network unicasts a Parent
+//!    Response MLE command.
+//! 3. The child device selects a parent based on a hierarchy of
+//!    connectivity metrics and unicasts a Child ID Request MLE
+//!    command.
+//! 4. The selected parent unicasts a Child ID Response MLE command.
+//!
+//! MLE messages are UDP datagrams sent from and to port `MLE_PORT` with a
+//! hop limit of 255. They consist of a command type and a series of TLV
+//! parameters (see the [tlv](../tlv/index.html) module), and are secured
+//! at the MLE layer rather than by the link layer:
+//!
+//! ```text
+//! +----------------+------------------------+---------+------+-----+
+//! | Security suite | Aux. security header   | Command | TLVs | MIC |
+//! | (0 = secured)  | (`AUX_HEADER_LEN`)     |         |      |     |
+//! +----------------+------------------------+---------+------+-----+
+//!                                            \_____ encrypted _____/
+//! ```
+//!
+//! The command and TLVs are encrypted and authenticated with AES-CCM* at
+//! security level 5 (ENC-MIC-32), as 802.15.4 frames are, but with the MLE
+//! key, and with the IPv6 source and destination addresses and the
+//! auxiliary security header as the authenticated data. The nonce is
+//! formed from the extended address of the sender, which is the interface
+//! identifier of its link-local address, and the frame counter of the
+//! message.
+//!
+//! Keys are identified by key ID mode 2: the auxiliary header carries the
+//! Key Sequence the key was derived from, and a key index computed from
+//! it. The same index identifies the MAC key in link-layer frames, with
+//! key ID mode 1.
+
+use crate::net::ieee802154::{MacAddress, SecurityLevel};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u32, decode_u8, encode_u32, encode_u8};
+
+/// UDP port MLE messages are sent from and to.
+pub const MLE_PORT: u16 = 19788;
+/// Hop limit of MLE messages. Received messages with another hop limit did
+/// not originate from a neighbor and are dropped.
+pub const MLE_HOP_LIMIT: u8 = 255;
+
+/// First byte of a message secured as described above.
+pub const SECURITY_SUITE_SECURED: u8 = 0;
+/// First byte of a message sent without security, which this
+/// implementation never accepts.
+pub const SECURITY_SUITE_UNSECURED: u8 = 255;
+
+/// Security control field of the auxiliary security header: security
+/// level 5 and key ID mode 2.
+pub const SECURITY_CONTROL: u8 = 0x15;
+/// Length of the auxiliary security header: security control, frame
+/// counter, key source and key index.
+pub const AUX_HEADER_LEN: usize = 10;
+/// Length of the message integrity code that ends secured messages.
+pub const MIC_LEN: usize = 4;
+/// Length of the data that is authenticated but not encrypted: the IPv6
+/// source and destination addresses and the auxiliary security header.
+pub const A_DATA_LEN: usize = 16 + 16 + AUX_HEADER_LEN;
+/// Longest command and TLVs that are sent or received. Longer messages are
+/// dropped.
+pub const MAX_MESSAGE_LEN: usize = 256;
+/// Size of a buffer that holds the input to the AES-CCM* transformation of
+/// the longest message.
+pub const CRYPT_BUF_LEN: usize = A_DATA_LEN + MAX_MESSAGE_LEN + MIC_LEN;
+
+/// Version of the Thread protocol advertised in the Version TLV.
+pub const THREAD_VERSION: u16 = 2;
+
+/// MLE command types (Section 4.4).
+pub mod command {
+    pub const LINK_REQUEST: u8 = 0;
+    pub const LINK_ACCEPT: u8 = 1;
+    pub const LINK_ACCEPT_AND_REQUEST: u8 = 2;
+    pub const LINK_REJECT: u8 = 3;
+    pub const ADVERTISEMENT: u8 = 4;
+    pub const DATA_REQUEST: u8 = 7;
+    pub const DATA_RESPONSE: u8 = 8;
+    pub const PARENT_REQUEST: u8 = 9;
+    pub const PARENT_RESPONSE: u8 = 10;
+    pub const CHILD_ID_REQUEST: u8 = 11;
+    pub const CHILD_ID_RESPONSE: u8 = 12;
+    pub const CHILD_UPDATE_REQUEST: u8 = 13;
+    pub const CHILD_UPDATE_RESPONSE: u8 = 14;
+}
+
+/// Returns the key index that identifies the keys derived from
+/// `key_sequence` in MLE messages and MAC frames.
+pub fn key_index(key_sequence: u32) -> u8 {
+    ((key_sequence & 0x7f) + 1) as u8
+}
+
+/// The security level of MLE messages, which is also the level of the
+/// link-layer frames of an attached device.
+pub const SECURITY_LEVEL: SecurityLevel = SecurityLevel::EncMic32;
+
+/// Auxiliary security header of a secured MLE message.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AuxHeader {
+    pub frame_counter: u32,
+    /// Key Sequence of the key the message is secured with, sent as the key
+    /// source.
+    pub key_sequence: u32,
+    pub key_index: u8,
+}
+
+impl AuxHeader {
+    pub fn new(frame_counter: u32, key_sequence: u32) -> AuxHeader {
+        AuxHeader {
+            frame_counter: frame_counter,
+            key_sequence: key_sequence,
+            key_index: key_index(key_sequence),
+        }
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        stream_len_cond!(buf, AUX_HEADER_LEN);
+        let off = enc_consume!(buf; encode_u8, SECURITY_CONTROL);
+        // As in 802.15.4 headers, the frame counter is little-endian
+        let off = enc_consume!(buf, off; encode_u32, self.frame_counter.swap_bytes());
+        let off = enc_consume!(buf, off; encode_u32, self.key_sequence);
+        let off = enc_consume!(buf, off; encode_u8, self.key_index);
+        stream_done!(off)
+    }
+
+    /// Decodes the header, failing if the message is not secured as
+    /// described in the module documentation.
+    pub fn decode(buf: &[u8]) -> SResult<AuxHeader> {
+        let (off, security_control) = dec_try!(buf; decode_u8);
+        stream_cond!(security_control == SECURITY_CONTROL);
+        let (off, frame_counter) = dec_try!(buf, off; decode_u32);
+        let (off, key_sequence) = dec_try!(buf, off; decode_u32);
+        let (off, key_index) = dec_try!(buf, off; decode_u8);
+        stream_done!(
+            off,
+            AuxHeader {
+                frame_counter: frame_counter.swap_bytes(),
+                key_sequence: key_sequence,
+                key_index: key_index,
+            }
+        )
+    }
+}
+
+/// Returns the extended address of the sender of a message from the
+/// link-local address `addr`.
+pub fn ext_addr_from_link_local(addr: IPAddr) -> [u8; 8] {
+    let mut ext_addr = [0; 8];
+    ext_addr.copy_from_slice(&addr.0[8..16]);
+    ext_addr[0] ^= 0b00000010;
+    ext_addr
+}
+
+/// Returns the link-local address of the device with the extended address
+/// `ext_addr`.
+pub fn link_local_from_ext_addr(ext_addr: [u8; 8]) -> IPAddr {
+    IPAddr::generate_from_mac(MacAddress::Long(ext_addr))
+}
+
+/// Returns the AES-CCM* nonce of a message sent by the device with the
+/// extended address `ext_addr`.
+pub fn nonce(ext_addr: &[u8; 8], frame_counter: u32) -> [u8; 13] {
+    let mut nonce = [0; 13];
+    nonce[..8].copy_from_slice(ext_addr);
+    nonce[8..12].copy_from_slice(&frame_counter.to_be_bytes());
+    nonce[12] = SECURITY_LEVEL as u8;
+    nonce
+}
+
+/// Returns whether `rloc16` is the address of a router, rather than of a
+/// child of one.
+pub fn is_router_rloc16(rloc16: u16) -> bool {
+    rloc16 & 0x01ff == 0
+}
+
+/// Splits `buf` into its TLVs, each including its type and length fields.
+/// A TLV that does not fit in `buf` ends the iteration.
+pub fn split_tlvs(buf: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut off = 0;
+    core::iter::from_fn(move || {
+        if off + 2 > buf.len() {
+            return None;
+        }
+        let end = off + 2 + buf[off + 1] as usize;
+        if end > buf.len() {
+            return None;
+        }
+        let tlv = &buf[off..end];
+        off = end;
+        Some(tlv)
+    })
+}
@@ -0,0 +1,1182 @@
+//! This file contains `MleAttach`, which attaches a Thread sleepy end
+//! device (SED) to a Thread network with the four-step Mesh Link
+//! Establishment (MLE) handshake described in the [mle](../mle/index.html)
+//! module (Thread 1.1.1 Specification, Section 4.7):
+//!
+//! - The device multicasts a Parent Request to the link-local all-routers
+//!   address, asking only routers to respond, and collects Parent Responses
+//!   for `PARENT_REQUEST_ROUTER_TIMEOUT_MS`. If none arrives, it sends a
+//!   second Parent Request that router-eligible end devices (REEDs) answer
+//!   too, and waits `PARENT_REQUEST_REED_TIMEOUT_MS`.
+//! - Each Parent Response must return the challenge of the request. Of the
+//!   responding devices, the one with the best link quality is selected as
+//!   the parent, then the one with the highest parent priority, then the
+//!   one with the most neighbors with a link quality of 3.
+//! - The device sends a Child ID Request to the selected parent, returning
+//!   its challenge, and waits `CHILD_ID_RESPONSE_TIMEOUT_MS` for the Child
+//!   ID Response, which assigns the 16-bit address of the device (its
+//!   RLOC16).
+//! - If no parent responds, or the Child ID Request is not answered, the
+//!   device waits for an exponentially increasing backoff, from
+//!   `INITIAL_BACKOFF_MS` to `MAX_BACKOFF_MS`, and starts over.
+//!
+//! Once attached, the device configures the MAC layer with the assigned
+//! RLOC16 and, if the Child ID Response carries an Active Operational
+//! Dataset, its PAN ID. Link-layer security is enabled on the IPv6 senders
+//! of the data path, and the parent is added to the
+//! [NeighborCache](../../ipv6/neighbor_cache/struct.NeighborCache.html) as
+//! the default router. `MleAttach` also implements the `KeyProcedure` and
+//! `DeviceProcedure` of the 802.15.4 framer, which needs the MAC key and
+//! the extended address of the parent to secure and verify frames.
+//!
+//! A sleepy end device turns its receiver off when idle, and fetches the
+//! frames its parent holds for it with the MAC data requests of
+//! [DataPoll](../data_poll/struct.DataPoll.html). It starts polling once it
+//! sends the Child ID Request, as the parent holds the Child ID Response
+//! too. A device whose mode has the `ReceiverOnWhenIdle` bit set, a
+//! minimal end device, keeps its receiver on instead.
+//!
+//! The parent forgets a child it has not heard from for the child timeout.
+//! Acknowledged data requests count, but if none has been acknowledged
+//! for half the timeout, or if the mode or timeout of the device changes,
+//! the device sends a Child Update Request. If `CHILD_UPDATE_ATTEMPTS`
+//! requests in a row are not answered within
+//! `CHILD_UPDATE_RESPONSE_TIMEOUT_MS` by a Child Update Response, or the
+//! parent no longer knows the device, the device attaches again. It also
+//! answers the Child Update Requests of its parent.
+//!
+//! MLE messages are sent with their own IPv6 sender, without link-layer
+//! security, as they are secured at the MLE layer. They are encrypted and
+//! decrypted with an `AES128CCM` engine, typically a `VirtualAES128CCM`
+//! sharing the engine of the framer.
+//!
+//! The MLE and MAC keys of each Key Sequence are derived from the network
+//! master key (see `NetworkKeys`). The device switches to a newer Key
+//! Sequence when it receives an MLE message secured with its keys, and
+//! also accepts frames secured with the MAC keys of the Key Sequences
+//! before and after its own.
+//!
+//! Limitations: incoming frames are not checked for replayed link-layer
+//! frame counters.
+
+use crate::ieee802154::device::MacDevice;
+use crate::ieee802154::framer::{DeviceProcedure, KeyProcedure};
+use crate::net::icmpv6::icmpv6_nd::ALL_ROUTERS_ADDR;
+use crate::net::ieee802154::{KeyId, MacAddress, SecurityLevel};
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::neighbor_cache::NeighborCache;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::thread::data_poll::{DataPoll, DataPollClient};
+use crate::net::thread::mle::{command, AuxHeader};
+use crate::net::thread::mle::{
+    ext_addr_from_link_local, is_router_rloc16, key_index, link_local_from_ext_addr, nonce,
+    split_tlvs,
+};
+use crate::net::thread::mle::{
+    AUX_HEADER_LEN, A_DATA_LEN, MAX_MESSAGE_LEN, MIC_LEN, MLE_HOP_LIMIT, MLE_PORT, SECURITY_LEVEL,
+    SECURITY_SUITE_SECURED, THREAD_VERSION,
+};
+use crate::net::thread::sha256::hmac_sha256;
+use crate::net::thread::tlv::{LinkMode, MulticastResponder, NetworkManagementTlv, Tlv, TlvType};
+use crate::net::udp::udp::UDPHeader;
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::debug;
+use kernel::hil::rng::Random;
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM};
+use kernel::hil::time;
+use kernel::ReturnCode;
+
+/// Milliseconds to collect Parent Responses from routers.
+pub const PARENT_REQUEST_ROUTER_TIMEOUT_MS: u32 = 750;
+/// Milliseconds to collect Parent Responses from routers and REEDs.
+pub const PARENT_REQUEST_REED_TIMEOUT_MS: u32 = 1250;
+/// Milliseconds to wait for the Child ID Response.
+pub const CHILD_ID_RESPONSE_TIMEOUT_MS: u32 = 1250;
+/// Backoff after the first failed attach attempt. It doubles with each
+/// further failure, up to `MAX_BACKOFF_MS`.
+pub const INITIAL_BACKOFF_MS: u32 = 1000;
+pub const MAX_BACKOFF_MS: u32 = 60000;
+/// Default child timeout, in seconds, after which the parent forgets a
+/// child it has not heard from.
+pub const DEFAULT_CHILD_TIMEOUT: u32 = 240;
+/// Period of the timer that counts down the child timeout once attached.
+pub const ATTACHED_TIMER_MS: u32 = 1000;
+/// Milliseconds to wait for the Child Update Response.
+pub const CHILD_UPDATE_RESPONSE_TIMEOUT_MS: u32 = 2000;
+/// Number of Child Update Requests sent before the device gives up on its
+/// parent and attaches again.
+pub const CHILD_UPDATE_ATTEMPTS: u8 = 3;
+/// Size of a buffer that holds the longest outgoing MLE message.
+pub const TX_BUF_LEN: usize = 1 + AUX_HEADER_LEN + MAX_MESSAGE_LEN + MIC_LEN;
+
+/// TLVs the parent is asked to include in the Child ID Response.
+const CHILD_ID_REQUEST_TLVS: [u8; 2] = [TlvType::Address16 as u8, TlvType::NetworkData as u8];
+
+/// Keys derived from the network master key for one Key Sequence.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NetworkKeys {
+    pub key_sequence: u32,
+    /// Key of MLE messages.
+    pub mle_key: [u8; 16],
+    /// Key of link-layer frames.
+    pub mac_key: [u8; 16],
+}
+
+impl NetworkKeys {
+    /// Derives the keys of `key_sequence` from `master_key` (Section
+    /// 7.1.4): they are the first and second half of HMAC-SHA256 keyed with
+    /// the master key over the Key Sequence, in big endian, followed by the
+    /// ASCII string "Thread".
+    pub fn derive(master_key: &[u8; 16], key_sequence: u32) -> NetworkKeys {
+        let hash = hmac_sha256(master_key, &[&key_sequence.to_be_bytes(), b"Thread"]);
+        let mut keys = NetworkKeys {
+            key_sequence: key_sequence,
+            mle_key: [0; 16],
+            mac_key: [0; 16],
+        };
+        keys.mle_key.copy_from_slice(&hash[..16]);
+        keys.mac_key.copy_from_slice(&hash[16..]);
+        keys
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AttachState {
+    Detached,
+    /// Waiting for Parent Responses from routers.
+    ParentRequestRouters,
+    /// Waiting for Parent Responses from routers and REEDs.
+    ParentRequestReeds,
+    /// Waiting for the Child ID Response of the selected parent.
+    ChildIdRequest,
+    /// Waiting to start over after a failed attempt.
+    Backoff,
+    Attached,
+    /// Attached, and waiting for the Child Update Response of the parent.
+    ChildUpdateRequest,
+}
+
+/// The content of a Leader Data TLV.
+#[derive(Copy, Clone)]
+struct LeaderData {
+    partition_id: u32,
+    weighting: u8,
+    data_version: u8,
+    stable_data_version: u8,
+    leader_router_id: u8,
+}
+
+impl LeaderData {
+    fn from_tlv(tlv: &Tlv) -> Option<LeaderData> {
+        match *tlv {
+            Tlv::LeaderData {
+                partition_id,
+                weighting,
+                data_version,
+                stable_data_version,
+                leader_router_id,
+            } => Some(LeaderData {
+                partition_id: partition_id,
+                weighting: weighting,
+                data_version: data_version,
+                stable_data_version: stable_data_version,
+                leader_router_id: leader_router_id,
+            }),
+            _ => None,
+        }
+    }
+
+    fn tlv(&self) -> Tlv<'static> {
+        Tlv::LeaderData {
+            partition_id: self.partition_id,
+            weighting: self.weighting,
+            data_version: self.data_version,
+            stable_data_version: self.stable_data_version,
+            leader_router_id: self.leader_router_id,
+        }
+    }
+}
+
+/// A device that answered the Parent Request.
+#[derive(Copy, Clone)]
+struct Parent {
+    ext_addr: [u8; 8],
+    rloc16: u16,
+    /// Key Sequence and frame counter of the last MLE message accepted from
+    /// the device
+    key_sequence: u32,
+    frame_counter: u32,
+    /// Challenge of the device, returned in the Child ID Request
+    challenge: [u8; 8],
+    link_quality: u8,
+    /// Parent priority from -1 (low) to 1 (high)
+    priority: i8,
+    /// Number of neighbors of the device with a link quality of 3
+    link_quality_3: u8,
+    leader_data: Option<LeaderData>,
+}
+
+impl Parent {
+    /// Parents are compared by these metrics, in order.
+    fn rank(&self) -> (u8, i8, u8) {
+        (self.link_quality, self.priority, self.link_quality_3)
+    }
+
+    /// Whether a message from `src` secured with `aux_header` is a new
+    /// message from the device, rather than a replayed one.
+    fn is_fresh(&self, src: IPAddr, aux_header: &AuxHeader) -> bool {
+        ext_addr_from_link_local(src) == self.ext_addr
+            && (aux_header.key_sequence, aux_header.frame_counter)
+                > (self.key_sequence, self.frame_counter)
+    }
+
+    fn accept(&mut self, aux_header: &AuxHeader) {
+        self.key_sequence = aux_header.key_sequence;
+        self.frame_counter = aux_header.frame_counter;
+    }
+}
+
+/// Returns the link quality, from 0 to 3, of a link with the margin
+/// `link_margin` in dB (Section 4.4.1.1.2).
+fn link_quality(link_margin: u8) -> u8 {
+    match link_margin {
+        m if m > 20 => 3,
+        m if m > 10 => 2,
+        m if m > 2 => 1,
+        _ => 0,
+    }
+}
+
+/// Encodes `tlvs` one after the other, returning their length.
+fn encode_tlvs(buf: &mut [u8], tlvs: &[Tlv]) -> Option<usize> {
+    let mut off = 0;
+    for tlv in tlvs.iter() {
+        let (len, _) = tlv.encode(&mut buf[off..]).done()?;
+        off += len;
+    }
+    Some(off)
+}
+
+#[derive(Copy, Clone)]
+enum CryptOp {
+    Idle,
+    /// Securing a message of `len` bytes for `dst`
+    Encrypting {
+        dst: IPAddr,
+        len: usize,
+    },
+    /// Checking a received message of `len` bytes, secured with the keys of
+    /// `key_sequence`
+    Decrypting {
+        len: usize,
+        key_sequence: u32,
+    },
+}
+
+pub struct MleAttach<'a, A: time::Alarm<'a>> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    /// The senders whose frames are secured once attached
+    data_senders: &'a [&'a dyn IP6Sender<'a>],
+    mac: &'a dyn MacDevice<'a>,
+    neighbor_cache: &'a NeighborCache<'a>,
+    aes_ccm: &'a dyn AES128CCM<'a>,
+    alarm: &'a A,
+    rng: &'a dyn Random<'a>,
+    data_poll: &'a DataPoll<'a, A>,
+    master_key: [u8; 16],
+    /// Keys of the current Key Sequence
+    keys: Cell<NetworkKeys>,
+    /// The Mode TLV of the device, a combination of `LinkMode` bits
+    mode: Cell<u8>,
+    /// Child timeout in seconds
+    timeout: Cell<u32>,
+    state: Cell<AttachState>,
+    /// Challenge of the last Parent Request or Child Update Request
+    challenge: Cell<[u8; 8]>,
+    /// Best device that answered the Parent Request so far
+    candidate: OptionalCell<Parent>,
+    parent: OptionalCell<Parent>,
+    rloc16: OptionalCell<u16>,
+    mesh_local_prefix: OptionalCell<[u8; 8]>,
+    /// Frame counter of the next MLE message
+    frame_counter: Cell<u32>,
+    backoff_ms: Cell<u32>,
+    /// Seconds until the child timeout expires, while attached
+    timer: Cell<u32>,
+    /// Number of Child Update Requests sent without a response
+    child_update_attempts: Cell<u8>,
+    pending_request: Cell<bool>,
+    /// Whether a Child Update Response to the parent is pending
+    pending_response: Cell<bool>,
+    /// Challenge of the Child Update Request of the parent, returned in the
+    /// response
+    parent_challenge: OptionalCell<[u8; 8]>,
+    crypt_op: Cell<CryptOp>,
+    /// Holds the input of the AES-CCM* transformation: the authenticated
+    /// data, followed by the command and TLVs, and the MIC
+    crypt_buffer: TakeCell<'static, [u8]>,
+    tx_buffer: MapCell<LeasableBuffer<'static, u8>>,
+    sending: Cell<bool>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> MleAttach<'a, A> {
+    /// `crypt_buffer` must be at least `mle::CRYPT_BUF_LEN` bytes long, and
+    /// `tx_buffer` at least `TX_BUF_LEN` bytes long. The device starts with
+    /// the keys of `key_sequence`.
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        data_senders: &'a [&'a dyn IP6Sender<'a>],
+        mac: &'a dyn MacDevice<'a>,
+        neighbor_cache: &'a NeighborCache<'a>,
+        aes_ccm: &'a dyn AES128CCM<'a>,
+        alarm: &'a A,
+        rng: &'a dyn Random<'a>,
+        data_poll: &'a DataPoll<'a, A>,
+        master_key: [u8; 16],
+        key_sequence: u32,
+        crypt_buffer: &'static mut [u8],
+        tx_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> MleAttach<'a, A> {
+        MleAttach {
+            ip_sender: ip_sender,
+            data_senders: data_senders,
+            mac: mac,
+            neighbor_cache: neighbor_cache,
+            aes_ccm: aes_ccm,
+            alarm: alarm,
+            rng: rng,
+            data_poll: data_poll,
+            master_key: master_key,
+            keys: Cell::new(NetworkKeys::derive(&master_key, key_sequence)),
+            mode: Cell::new(LinkMode::SecureDataRequests as u8),
+            timeout: Cell::new(DEFAULT_CHILD_TIMEOUT),
+            state: Cell::new(AttachState::Detached),
+            challenge: Cell::new([0; 8]),
+            candidate: OptionalCell::empty(),
+            parent: OptionalCell::empty(),
+            rloc16: OptionalCell::empty(),
+            mesh_local_prefix: OptionalCell::empty(),
+            frame_counter: Cell::new(0),
+            backoff_ms: Cell::new(INITIAL_BACKOFF_MS),
+            timer: Cell::new(0),
+            child_update_attempts: Cell::new(0),
+            pending_request: Cell::new(false),
+            pending_response: Cell::new(false),
+            parent_challenge: OptionalCell::empty(),
+            crypt_op: Cell::new(CryptOp::Idle),
+            crypt_buffer: TakeCell::new(crypt_buffer),
+            tx_buffer: MapCell::new(LeasableBuffer::new(tx_buffer)),
+            sending: Cell::new(false),
+            net_cap: net_cap,
+        }
+    }
+
+    /// Sets the Mode TLV sent to parents, a combination of `LinkMode` bits.
+    /// Once attached, the parent is told with a Child Update Request.
+    pub fn set_mode(&self, mode: u8) {
+        self.mode.set(mode);
+        self.update_parent();
+    }
+
+    /// Sets the child timeout in seconds. Once attached, the parent is told
+    /// with a Child Update Request.
+    pub fn set_timeout(&self, timeout: u32) {
+        self.timeout.set(timeout);
+        self.update_parent();
+    }
+
+    /// Starts attaching to a parent.
+    pub fn start(&self) {
+        if self.state.get() != AttachState::Detached {
+            return;
+        }
+        self.attach();
+    }
+
+    pub fn get_state(&self) -> AttachState {
+        self.state.get()
+    }
+
+    pub fn get_key_sequence(&self) -> u32 {
+        self.keys.get().key_sequence
+    }
+
+    fn is_attached(&self) -> bool {
+        match self.state.get() {
+            AttachState::Attached | AttachState::ChildUpdateRequest => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the link-local address of the parent, once attached.
+    pub fn get_parent(&self) -> Option<IPAddr> {
+        if !self.is_attached() {
+            return None;
+        }
+        self.parent
+            .map(|parent| link_local_from_ext_addr(parent.ext_addr))
+    }
+
+    /// Returns the RLOC16 assigned by the parent, once attached.
+    pub fn get_rloc16(&self) -> Option<u16> {
+        if !self.is_attached() {
+            return None;
+        }
+        self.rloc16.map(|rloc16| *rloc16)
+    }
+
+    /// Returns the Mesh-Local Prefix of the network, if the parent sent the
+    /// Active Operational Dataset.
+    pub fn get_mesh_local_prefix(&self) -> Option<[u8; 8]> {
+        self.mesh_local_prefix.map(|prefix| *prefix)
+    }
+
+    fn link_local_addr(&self) -> IPAddr {
+        link_local_from_ext_addr(self.mac.get_address_long())
+    }
+
+    /// The security of link-layer frames with the current keys.
+    fn security(&self) -> Option<(SecurityLevel, KeyId)> {
+        Some((
+            SECURITY_LEVEL,
+            KeyId::Index(key_index(self.keys.get().key_sequence)),
+        ))
+    }
+
+    /// Switches to the keys of the newer `key_sequence`.
+    fn switch_keys(&self, key_sequence: u32) {
+        self.keys
+            .set(NetworkKeys::derive(&self.master_key, key_sequence));
+        // Frame counters start over with each key
+        self.frame_counter.set(0);
+        if self.is_attached() {
+            let security = self.security();
+            for sender in self.data_senders.iter() {
+                sender.set_security(security);
+            }
+            self.parent.map(|parent| self.update_polling(parent));
+        }
+    }
+
+    /// Polls `parent` for the frames it holds for the device if the device
+    /// is sleepy, and keeps the receiver on otherwise.
+    fn update_polling(&self, parent: &Parent) {
+        let mode = self.mode.get();
+        if mode & LinkMode::ReceiverOnWhenIdle as u8 != 0 {
+            self.data_poll.stop();
+            return;
+        }
+        let security = if mode & LinkMode::SecureDataRequests as u8 != 0 {
+            self.security()
+        } else {
+            None
+        };
+        self.data_poll
+            .start(MacAddress::Short(parent.rloc16), security);
+    }
+
+    fn attach(&self) {
+        self.data_poll.stop();
+        self.candidate.clear();
+        self.send_parent_request(
+            AttachState::ParentRequestRouters,
+            PARENT_REQUEST_ROUTER_TIMEOUT_MS,
+        );
+    }
+
+    fn new_challenge(&self) {
+        let mut challenge = [0; 8];
+        challenge[..4].copy_from_slice(&self.rng.random().to_be_bytes());
+        challenge[4..].copy_from_slice(&self.rng.random().to_be_bytes());
+        self.challenge.set(challenge);
+    }
+
+    fn send_parent_request(&self, state: AttachState, timeout_ms: u32) {
+        self.new_challenge();
+        self.state.set(state);
+        self.request(timeout_ms);
+    }
+
+    fn send_child_id_request(&self) {
+        self.state.set(AttachState::ChildIdRequest);
+        // The parent holds the response of a sleepy device
+        self.candidate.map(|parent| self.update_polling(parent));
+        self.request(CHILD_ID_RESPONSE_TIMEOUT_MS);
+    }
+
+    /// Tells the parent the mode and timeout of the device, if attached.
+    fn update_parent(&self) {
+        if self.is_attached() {
+            self.child_update_attempts.set(0);
+            self.send_child_update_request();
+        }
+    }
+
+    fn send_child_update_request(&self) {
+        self.child_update_attempts
+            .set(self.child_update_attempts.get() + 1);
+        self.new_challenge();
+        self.state.set(AttachState::ChildUpdateRequest);
+        self.parent.map(|parent| self.update_polling(parent));
+        self.request(CHILD_UPDATE_RESPONSE_TIMEOUT_MS);
+    }
+
+    /// Sends the request of the current state, and waits `timeout_ms` for
+    /// the response.
+    fn request(&self, timeout_ms: u32) {
+        self.pending_request.set(true);
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(timeout_ms));
+        // Fetch the response quickly if the parent holds it
+        self.data_poll.poll_fast();
+        self.do_output();
+    }
+
+    /// Starts counting down the child timeout.
+    fn restart_timer(&self) {
+        self.state.set(AttachState::Attached);
+        self.child_update_attempts.set(0);
+        self.timer.set(self.timeout.get());
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(ATTACHED_TIMER_MS));
+    }
+
+    fn backoff(&self) {
+        self.data_poll.stop();
+        let backoff = self.backoff_ms.get();
+        self.backoff_ms.set(cmp::min(2 * backoff, MAX_BACKOFF_MS));
+        self.state.set(AttachState::Backoff);
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(backoff));
+    }
+
+    /// Configures the MAC and IPv6 layers once the Child ID Response of
+    /// `parent` has assigned `rloc16`.
+    fn attached(
+        &self,
+        parent: Parent,
+        rloc16: u16,
+        pan_id: Option<u16>,
+        mesh_local_prefix: Option<[u8; 8]>,
+    ) {
+        self.parent.take().map(|old_parent| {
+            self.neighbor_cache
+                .remove(link_local_from_ext_addr(old_parent.ext_addr));
+        });
+        self.parent.set(parent);
+        self.rloc16.set(rloc16);
+        mesh_local_prefix.map(|prefix| self.mesh_local_prefix.set(prefix));
+
+        self.mac.set_address(rloc16);
+        pan_id.map(|pan_id| self.mac.set_pan(pan_id));
+        self.mac.config_commit();
+        let security = self.security();
+        for sender in self.data_senders.iter() {
+            sender.set_security(security);
+        }
+        // The parent is the only router the device can reach
+        self.neighbor_cache.update(
+            link_local_from_ext_addr(parent.ext_addr),
+            MacAddress::Short(parent.rloc16),
+            true,
+            None,
+        );
+        self.update_polling(&parent);
+
+        self.backoff_ms.set(INITIAL_BACKOFF_MS);
+        self.restart_timer();
+    }
+
+    /// Encodes the command and TLVs of the request of the current state
+    /// into `buf`, returning its destination and length.
+    fn encode_request(&self, buf: &mut [u8], frame_counter: u32) -> Option<(IPAddr, usize)> {
+        let (dst, command, len) = match self.state.get() {
+            AttachState::ParentRequestRouters | AttachState::ParentRequestReeds => {
+                let scan_mask = if self.state.get() == AttachState::ParentRequestRouters {
+                    MulticastResponder::Router as u8
+                } else {
+                    MulticastResponder::Router as u8 | MulticastResponder::EndDevice as u8
+                };
+                let tlvs = [
+                    Tlv::Mode(self.mode.get()),
+                    Tlv::Challenge(self.challenge.get()),
+                    Tlv::ScanMask(scan_mask),
+                    Tlv::Version(THREAD_VERSION),
+                ];
+                (
+                    ALL_ROUTERS_ADDR,
+                    command::PARENT_REQUEST,
+                    encode_tlvs(&mut buf[1..], &tlvs)?,
+                )
+            }
+            AttachState::ChildIdRequest => {
+                let parent = self.candidate.map(|parent| *parent)?;
+                let tlvs = [
+                    Tlv::Response(parent.challenge),
+                    Tlv::LinkLayerFrameCounter(self.mac.get_frame_counter()),
+                    Tlv::MleFrameCounter(frame_counter),
+                    Tlv::Mode(self.mode.get()),
+                    Tlv::Timeout(self.timeout.get()),
+                    Tlv::Version(THREAD_VERSION),
+                    Tlv::TlvRequest(&CHILD_ID_REQUEST_TLVS),
+                ];
+                (
+                    link_local_from_ext_addr(parent.ext_addr),
+                    command::CHILD_ID_REQUEST,
+                    encode_tlvs(&mut buf[1..], &tlvs)?,
+                )
+            }
+            AttachState::ChildUpdateRequest => {
+                let parent = self.parent.map(|parent| *parent)?;
+                let tlvs = [
+                    Tlv::SourceAddress(self.rloc16.map(|rloc16| *rloc16)?),
+                    Tlv::Mode(self.mode.get()),
+                    Tlv::Challenge(self.challenge.get()),
+                    Tlv::Timeout(self.timeout.get()),
+                ];
+                let mut len = encode_tlvs(&mut buf[1..], &tlvs)?;
+                if let Some(leader_data) = parent.leader_data {
+                    len += encode_tlvs(&mut buf[1 + len..], &[leader_data.tlv()])?;
+                }
+                (
+                    link_local_from_ext_addr(parent.ext_addr),
+                    command::CHILD_UPDATE_REQUEST,
+                    len,
+                )
+            }
+            _ => return None,
+        };
+        buf[0] = command;
+        Some((dst, 1 + len))
+    }
+
+    /// Encodes the Child Update Response to the parent into `buf`,
+    /// returning its destination and length.
+    fn encode_child_update_response(
+        &self,
+        buf: &mut [u8],
+        frame_counter: u32,
+    ) -> Option<(IPAddr, usize)> {
+        let parent = self.parent.map(|parent| *parent)?;
+        let tlvs = [
+            Tlv::SourceAddress(self.rloc16.map(|rloc16| *rloc16)?),
+            Tlv::Mode(self.mode.get()),
+            Tlv::Timeout(self.timeout.get()),
+        ];
+        let mut len = encode_tlvs(&mut buf[1..], &tlvs)?;
+        if let Some(leader_data) = parent.leader_data {
+            len += encode_tlvs(&mut buf[1 + len..], &[leader_data.tlv()])?;
+        }
+        if let Some(challenge) = self.parent_challenge.take() {
+            let tlvs = [
+                Tlv::Response(challenge),
+                Tlv::LinkLayerFrameCounter(self.mac.get_frame_counter()),
+                Tlv::MleFrameCounter(frame_counter),
+            ];
+            len += encode_tlvs(&mut buf[1 + len..], &tlvs)?;
+        }
+        buf[0] = command::CHILD_UPDATE_RESPONSE;
+        Some((link_local_from_ext_addr(parent.ext_addr), 1 + len))
+    }
+
+    /// Secures the pending response or request, which is sent once the
+    /// AES-CCM engine is done.
+    fn do_output(&self) {
+        if !(self.pending_response.get() || self.pending_request.get()) || self.sending.get() {
+            return;
+        }
+        let buf = match self.crypt_buffer.take() {
+            Some(buf) => buf,
+            // A received message is being checked; the request is secured
+            // once it is done.
+            None => return,
+        };
+        let frame_counter = self.frame_counter.get();
+        let message = &mut buf[A_DATA_LEN..A_DATA_LEN + MAX_MESSAGE_LEN];
+        let message = if self.pending_response.replace(false) {
+            self.encode_child_update_response(message, frame_counter)
+        } else {
+            self.pending_request.set(false);
+            self.encode_request(message, frame_counter)
+        };
+        let (dst, len) = match message {
+            Some(message) => message,
+            None => {
+                self.crypt_buffer.replace(buf);
+                // A request may still be pending
+                self.do_output();
+                return;
+            }
+        };
+        self.frame_counter.set(frame_counter.wrapping_add(1));
+
+        let ext_addr = self.mac.get_address_long();
+        let keys = self.keys.get();
+        buf[..16].copy_from_slice(&link_local_from_ext_addr(ext_addr).0);
+        buf[16..32].copy_from_slice(&dst.0);
+        let _ = AuxHeader::new(frame_counter, keys.key_sequence).encode(&mut buf[32..A_DATA_LEN]);
+        self.crypt_op
+            .set(CryptOp::Encrypting { dst: dst, len: len });
+        self.crypt(buf, len, &keys.mle_key, &ext_addr, frame_counter, true);
+    }
+
+    /// Starts encrypting or decrypting with `key` the message of `len`
+    /// bytes in `buf`, sent by the device with the extended address
+    /// `ext_addr`.
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+        key: &[u8; 16],
+        ext_addr: &[u8; 8],
+        frame_counter: u32,
+        encrypting: bool,
+    ) {
+        self.aes_ccm.set_key(key);
+        self.aes_ccm.set_nonce(&nonce(ext_addr, frame_counter));
+        let (res, buf) = self
+            .aes_ccm
+            .crypt(buf, 0, A_DATA_LEN, len, MIC_LEN, true, encrypting);
+        if res != ReturnCode::SUCCESS {
+            debug!("[MLE] AES-CCM failed: {:?}", res);
+            self.crypt_op.set(CryptOp::Idle);
+            buf.map(|buf| self.crypt_buffer.replace(buf));
+        }
+    }
+
+    /// Sends the secured message, starting at its auxiliary header, to
+    /// `dst`.
+    fn send(&self, dst: IPAddr, secured: &[u8]) {
+        self.tx_buffer.take().map(|mut buf| {
+            if 1 + secured.len() <= buf.len() {
+                buf[0] = SECURITY_SUITE_SECURED;
+                buf[1..1 + secured.len()].copy_from_slice(secured);
+                buf.slice(0..1 + secured.len());
+                let mut udp_header = UDPHeader::new();
+                udp_header.set_src_port(MLE_PORT);
+                udp_header.set_dst_port(MLE_PORT);
+                self.sending.set(true);
+                self.ip_sender.set_addr(self.link_local_addr());
+                let ret = self.ip_sender.send_to(
+                    dst,
+                    TransportHeader::UDP(udp_header),
+                    &buf,
+                    self.net_cap,
+                );
+                if ret != ReturnCode::SUCCESS {
+                    debug!("[MLE] IP send_to failed: {:?}", ret);
+                    self.sending.set(false);
+                }
+            }
+            buf.reset();
+            self.tx_buffer.replace(buf);
+        });
+    }
+
+    /// Checks the secured MLE message `message` from `src` to `dst`, which
+    /// is processed once the AES-CCM engine is done.
+    fn receive_secured(&self, src: IPAddr, dst: IPAddr, message: &[u8]) {
+        // Only responses to our requests and requests of the parent are
+        // processed
+        match self.state.get() {
+            AttachState::Detached | AttachState::Backoff => return,
+            _ => {}
+        }
+        if message.len() < 1 + AUX_HEADER_LEN + 1 + MIC_LEN || message[0] != SECURITY_SUITE_SECURED
+        {
+            return;
+        }
+        let aux_header = match AuxHeader::decode(&message[1..]).done() {
+            Some((_, aux_header)) => aux_header,
+            None => return,
+        };
+        // Messages secured with the keys of the previous Key Sequence are
+        // still accepted, and newer ones make the device switch keys
+        let key_sequence = aux_header.key_sequence;
+        let keys = self.keys.get();
+        if aux_header != AuxHeader::new(aux_header.frame_counter, key_sequence)
+            || key_sequence < keys.key_sequence.saturating_sub(1)
+        {
+            return;
+        }
+        let key = if key_sequence == keys.key_sequence {
+            keys.mle_key
+        } else {
+            NetworkKeys::derive(&self.master_key, key_sequence).mle_key
+        };
+        let len = message.len() - (1 + AUX_HEADER_LEN + MIC_LEN);
+        if len > MAX_MESSAGE_LEN {
+            return;
+        }
+        let buf = match self.crypt_buffer.take() {
+            Some(buf) => buf,
+            // Busy; the sender retransmits lost requests and responses
+            None => return,
+        };
+        buf[..16].copy_from_slice(&src.0);
+        buf[16..32].copy_from_slice(&dst.0);
+        buf[32..A_DATA_LEN + len + MIC_LEN].copy_from_slice(&message[1..]);
+        self.crypt_op.set(CryptOp::Decrypting {
+            len: len,
+            key_sequence: key_sequence,
+        });
+        self.crypt(
+            buf,
+            len,
+            &key,
+            &ext_addr_from_link_local(src),
+            aux_header.frame_counter,
+            false,
+        );
+    }
+
+    /// Processes an authenticated MLE message.
+    fn receive_message(&self, src: IPAddr, aux_header: AuxHeader, message: &[u8]) {
+        let tlvs = &message[1..];
+        match (message[0], self.state.get()) {
+            (command::PARENT_RESPONSE, AttachState::ParentRequestRouters)
+            | (command::PARENT_RESPONSE, AttachState::ParentRequestReeds) => {
+                self.receive_parent_response(src, aux_header, tlvs)
+            }
+            (command::CHILD_ID_RESPONSE, AttachState::ChildIdRequest) => {
+                self.receive_child_id_response(src, aux_header, tlvs)
+            }
+            (command::CHILD_UPDATE_RESPONSE, AttachState::ChildUpdateRequest) => {
+                self.receive_child_update_response(src, aux_header, tlvs)
+            }
+            (command::CHILD_UPDATE_REQUEST, AttachState::Attached)
+            | (command::CHILD_UPDATE_REQUEST, AttachState::ChildUpdateRequest) => {
+                self.receive_child_update_request(src, aux_header, tlvs)
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_parent_response(&self, src: IPAddr, aux_header: AuxHeader, tlvs: &[u8]) {
+        let mut response = None;
+        let mut rloc16 = None;
+        let mut challenge = None;
+        let mut link_margin = None;
+        let mut connectivity = None;
+        let mut leader_data = None;
+        for tlv in split_tlvs(tlvs) {
+            match Tlv::decode(tlv).done() {
+                Some((_, tlv @ Tlv::LeaderData { .. })) => leader_data = LeaderData::from_tlv(&tlv),
+                Some((_, Tlv::Response(value))) => response = Some(value),
+                Some((_, Tlv::SourceAddress(value))) => rloc16 = Some(value),
+                Some((_, Tlv::Challenge(value))) => challenge = Some(value),
+                Some((_, Tlv::LinkMargin(value))) => link_margin = Some(value),
+                Some((
+                    _,
+                    Tlv::Connectivity {
+                        parent_priority,
+                        link_quality_3,
+                        ..
+                    },
+                )) => connectivity = Some((parent_priority, link_quality_3)),
+                _ => {}
+            }
+        }
+        let (rloc16, challenge, link_margin, (parent_priority, link_quality_3)) =
+            match (rloc16, challenge, link_margin, connectivity) {
+                (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+                _ => return,
+            };
+        if response != Some(self.challenge.get()) {
+            return;
+        }
+        if self.state.get() == AttachState::ParentRequestRouters && !is_router_rloc16(rloc16) {
+            return;
+        }
+
+        let parent = Parent {
+            ext_addr: ext_addr_from_link_local(src),
+            rloc16: rloc16,
+            key_sequence: aux_header.key_sequence,
+            frame_counter: aux_header.frame_counter,
+            challenge: challenge,
+            link_quality: link_quality(link_margin),
+            // The two high bits are a signed priority
+            priority: (parent_priority as i8) >> 6,
+            link_quality_3: link_quality_3,
+            leader_data: leader_data,
+        };
+        let better = self.candidate.map_or(true, |candidate| {
+            candidate.ext_addr == parent.ext_addr || parent.rank() > candidate.rank()
+        });
+        if better {
+            self.candidate.set(parent);
+        }
+    }
+
+    fn receive_child_id_response(&self, src: IPAddr, aux_header: AuxHeader, tlvs: &[u8]) {
+        let mut parent = match self.candidate.map(|parent| *parent) {
+            Some(parent) => parent,
+            None => return,
+        };
+        if !parent.is_fresh(src, &aux_header) {
+            return;
+        }
+
+        let mut rloc16 = None;
+        let mut pan_id = None;
+        let mut mesh_local_prefix = None;
+        for tlv in split_tlvs(tlvs) {
+            match Tlv::decode(tlv).done() {
+                Some((_, Tlv::Address16(value))) => rloc16 = Some(value),
+                Some((_, Tlv::SourceAddress(value))) => parent.rloc16 = value,
+                Some((_, tlv @ Tlv::LeaderData { .. })) => {
+                    parent.leader_data = LeaderData::from_tlv(&tlv)
+                }
+                Some((_, Tlv::ActiveOperationalDataset(dataset))) => {
+                    for tlv in split_tlvs(dataset) {
+                        match NetworkManagementTlv::decode(tlv).done() {
+                            Some((_, NetworkManagementTlv::PanId(value))) => pan_id = Some(value),
+                            Some((_, NetworkManagementTlv::NetworkMeshLocalPrefix(value))) => {
+                                mesh_local_prefix = Some(value)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        let rloc16 = match rloc16 {
+            Some(rloc16) => rloc16,
+            None => return,
+        };
+        // The RLOC16 of a child has the router ID of its parent
+        if is_router_rloc16(rloc16) || rloc16 & !0x01ff != parent.rloc16 & !0x01ff {
+            return;
+        }
+        parent.accept(&aux_header);
+        self.candidate.clear();
+        self.attached(parent, rloc16, pan_id, mesh_local_prefix);
+    }
+
+    fn receive_child_update_response(&self, src: IPAddr, aux_header: AuxHeader, tlvs: &[u8]) {
+        let mut parent = match self.parent.map(|parent| *parent) {
+            Some(parent) => parent,
+            None => return,
+        };
+        if !parent.is_fresh(src, &aux_header) {
+            return;
+        }
+
+        let mut status = None;
+        let mut response = None;
+        let mut timeout = None;
+        for tlv in split_tlvs(tlvs) {
+            match Tlv::decode(tlv).done() {
+                Some((_, Tlv::Status(value))) => status = Some(value),
+                Some((_, Tlv::Response(value))) => response = Some(value),
+                Some((_, Tlv::Timeout(value))) => timeout = Some(value),
+                Some((_, tlv @ Tlv::LeaderData { .. })) => {
+                    parent.leader_data = LeaderData::from_tlv(&tlv)
+                }
+                _ => {}
+            }
+        }
+        parent.accept(&aux_header);
+        self.parent.set(parent);
+        if status.is_some() {
+            // The parent no longer knows the device
+            self.attach();
+            return;
+        }
+        if response != Some(self.challenge.get()) {
+            return;
+        }
+        timeout.map(|timeout| self.timeout.set(timeout));
+        self.restart_timer();
+    }
+
+    fn receive_child_update_request(&self, src: IPAddr, aux_header: AuxHeader, tlvs: &[u8]) {
+        let mut parent = match self.parent.map(|parent| *parent) {
+            Some(parent) => parent,
+            None => return,
+        };
+        if !parent.is_fresh(src, &aux_header) {
+            return;
+        }
+
+        let mut challenge = None;
+        for tlv in split_tlvs(tlvs) {
+            match Tlv::decode(tlv).done() {
+                Some((_, Tlv::Challenge(value))) => challenge = Some(value),
+                Some((_, tlv @ Tlv::LeaderData { .. })) => {
+                    parent.leader_data = LeaderData::from_tlv(&tlv)
+                }
+                _ => {}
+            }
+        }
+        parent.accept(&aux_header);
+        self.parent.set(parent);
+        self.parent_challenge.insert(challenge);
+        self.pending_response.set(true);
+        self.do_output();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6RecvClient for MleAttach<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::UDP || ip_header.get_hop_limit() != MLE_HOP_LIMIT
+        {
+            return;
+        }
+        let (offset, udp_header) = match UDPHeader::decode(payload).done() {
+            Some(result) => result,
+            None => return,
+        };
+        let len = udp_header.get_len() as usize;
+        if udp_header.get_src_port() != MLE_PORT
+            || udp_header.get_dst_port() != MLE_PORT
+            || len < offset
+            || len > payload.len()
+        {
+            return;
+        }
+        let src = ip_header.get_src_addr();
+        if !src.is_unicast_link_local() {
+            return;
+        }
+        self.receive_secured(src, ip_header.get_dst_addr(), &payload[offset..len]);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> CCMClient for MleAttach<'a, A> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        match self.crypt_op.replace(CryptOp::Idle) {
+            CryptOp::Encrypting { dst, len } => {
+                if res == ReturnCode::SUCCESS {
+                    self.send(dst, &buf[32..A_DATA_LEN + len + MIC_LEN]);
+                } else {
+                    debug!("[MLE] Encryption failed: {:?}", res);
+                }
+            }
+            CryptOp::Decrypting { len, key_sequence } => {
+                if res == ReturnCode::SUCCESS && tag_is_valid {
+                    if key_sequence > self.keys.get().key_sequence {
+                        self.switch_keys(key_sequence);
+                    }
+                    let mut src = IPAddr::new();
+                    src.0.copy_from_slice(&buf[..16]);
+                    if let Some((_, aux_header)) = AuxHeader::decode(&buf[32..A_DATA_LEN]).done() {
+                        self.receive_message(src, aux_header, &buf[A_DATA_LEN..A_DATA_LEN + len]);
+                    }
+                }
+            }
+            CryptOp::Idle => {}
+        }
+        self.crypt_buffer.replace(buf);
+        self.do_output();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6SendClient for MleAttach<'a, A> {
+    fn send_done(&self, result: ReturnCode) {
+        if result != ReturnCode::SUCCESS {
+            // Requests that are not answered are sent again after a backoff
+            debug!("[MLE] Send failed: {:?}", result);
+        }
+        self.sending.set(false);
+        self.do_output();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for MleAttach<'a, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            AttachState::ParentRequestRouters => {
+                if self.candidate.is_some() {
+                    self.send_child_id_request();
+                } else {
+                    self.send_parent_request(
+                        AttachState::ParentRequestReeds,
+                        PARENT_REQUEST_REED_TIMEOUT_MS,
+                    );
+                }
+            }
+            AttachState::ParentRequestReeds => {
+                if self.candidate.is_some() {
+                    self.send_child_id_request();
+                } else {
+                    self.backoff();
+                }
+            }
+            AttachState::ChildIdRequest => self.backoff(),
+            AttachState::Backoff => self.attach(),
+            AttachState::Attached => {
+                let timer = self.timer.get().saturating_sub(1);
+                self.timer.set(timer);
+                if timer <= self.timeout.get() / 2 {
+                    // Remind the parent of the device before it forgets it
+                    self.child_update_attempts.set(0);
+                    self.send_child_update_request();
+                } else {
+                    self.alarm
+                        .set_alarm(self.alarm.now(), A::ticks_from_ms(ATTACHED_TIMER_MS));
+                }
+            }
+            AttachState::ChildUpdateRequest => {
+                if self.child_update_attempts.get() < CHILD_UPDATE_ATTEMPTS {
+                    self.send_child_update_request();
+                } else {
+                    // The parent may have forgotten us by now
+                    self.attach();
+                }
+            }
+            AttachState::Detached => {}
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> DataPollClient for MleAttach<'a, A> {
+    fn poll_done(&self, acked: bool) {
+        // The parent does not forget a child whose data requests it
+        // acknowledges
+        if acked && self.state.get() == AttachState::Attached {
+            self.timer.set(self.timeout.get());
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> KeyProcedure for MleAttach<'a, A> {
+    /// Returns the MAC key of the current Key Sequence, or of the one
+    /// before or after it, whose frames neighbors may still or already
+    /// send.
+    fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
+        if level != SECURITY_LEVEL {
+            return None;
+        }
+        let keys = self.keys.get();
+        let current = keys.key_sequence;
+        if key_id == KeyId::Index(key_index(current)) {
+            return Some(keys.mac_key);
+        }
+        [current.checked_add(1), current.checked_sub(1)]
+            .iter()
+            .filter_map(|key_sequence| *key_sequence)
+            .find(|key_sequence| key_id == KeyId::Index(key_index(*key_sequence)))
+            .map(|key_sequence| NetworkKeys::derive(&self.master_key, key_sequence).mac_key)
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> DeviceProcedure for MleAttach<'a, A> {
+    fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]> {
+        self.parent.and_then(|parent| match addr {
+            MacAddress::Short(short_addr) if short_addr == parent.rloc16 => Some(parent.ext_addr),
+            MacAddress::Long(long_addr) if long_addr == parent.ext_addr => Some(long_addr),
+            _ => None,
+        })
+    }
+}
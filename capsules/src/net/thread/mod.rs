@@ -1 +1,5 @@
+pub mod data_poll;
+pub mod mle;
+pub mod mle_attach;
+pub mod sha256;
 pub mod tlv;
@@ -0,0 +1,151 @@
+//! A software implementation of SHA-256 (FIPS 180-4) and HMAC-SHA256
+//! (RFC 2104), with which Thread derives the MLE and MAC keys of each Key
+//! Sequence from the network master key (see
+//! [NetworkKeys](../mle_attach/struct.NetworkKeys.html)).
+//!
+//! Few chips implement the `Digest` HIL, and the inputs Thread hashes are a
+//! handful of bytes, so the hash is computed synchronously in software.
+
+use core::cmp;
+
+/// Length of a SHA-256 digest.
+pub const DIGEST_LEN: usize = 32;
+/// Length of the blocks SHA-256 processes, and of the padded HMAC key.
+const BLOCK_LEN: usize = 64;
+
+/// Initial hash value (Section 5.3.3).
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Round constants (Section 4.2.2).
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Processes one 64-byte block (Section 6.2.2).
+fn compress(state: &mut [u32; 8], block: &[u8]) {
+    let mut w = [0u32; 64];
+    for (word, bytes) in w.iter_mut().zip(block.chunks(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    for t in 16..64 {
+        let s0 = w[t - 15].rotate_right(7) ^ w[t - 15].rotate_right(18) ^ (w[t - 15] >> 3);
+        let s1 = w[t - 2].rotate_right(17) ^ w[t - 2].rotate_right(19) ^ (w[t - 2] >> 10);
+        w[t] = w[t - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[t - 7])
+            .wrapping_add(s1);
+    }
+
+    let mut v = *state;
+    for (k, w) in K.iter().zip(w.iter()) {
+        let [a, b, c, d, e, f, g, h] = v;
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(*k)
+            .wrapping_add(*w);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maj);
+        v = [t1.wrapping_add(t2), a, b, c, d.wrapping_add(t1), e, f, g];
+    }
+    for (s, v) in state.iter_mut().zip(v.iter()) {
+        *s = s.wrapping_add(*v);
+    }
+}
+
+/// Computes the SHA-256 digest of the data passed to `update`.
+pub struct Sha256 {
+    state: [u32; 8],
+    /// The data of the block being filled
+    block: [u8; BLOCK_LEN],
+    block_len: usize,
+    /// Number of bytes hashed so far
+    len: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Sha256 {
+            state: H0,
+            block: [0; BLOCK_LEN],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.len = self.len.wrapping_add(data.len() as u64);
+        let mut data = data;
+        while !data.is_empty() {
+            let n = cmp::min(BLOCK_LEN - self.block_len, data.len());
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+            if self.block_len == BLOCK_LEN {
+                compress(&mut self.state, &self.block);
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// Pads the data (Section 5.1.1) and returns its digest.
+    pub fn finish(mut self) -> [u8; DIGEST_LEN] {
+        let bit_len = self.len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block_len != BLOCK_LEN - 8 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut digest = [0; DIGEST_LEN];
+        for (bytes, s) in digest.chunks_mut(4).zip(self.state.iter()) {
+            bytes.copy_from_slice(&s.to_be_bytes());
+        }
+        digest
+    }
+}
+
+/// Returns the HMAC-SHA256 of the concatenation of `data` with `key`.
+pub fn hmac_sha256(key: &[u8], data: &[&[u8]]) -> [u8; DIGEST_LEN] {
+    // Keys longer than a block are hashed, and all keys are padded with
+    // zeros to a block
+    let mut block_key = [0; BLOCK_LEN];
+    if key.len() > BLOCK_LEN {
+        let mut hash = Sha256::new();
+        hash.update(key);
+        block_key[..DIGEST_LEN].copy_from_slice(&hash.finish());
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut pad = [0; BLOCK_LEN];
+    for (p, k) in pad.iter_mut().zip(block_key.iter()) {
+        *p = k ^ 0x36;
+    }
+    let mut inner = Sha256::new();
+    inner.update(&pad);
+    for part in data.iter() {
+        inner.update(part);
+    }
+    let inner = inner.finish();
+
+    for (p, k) in pad.iter_mut().zip(block_key.iter()) {
+        *p = k ^ 0x5c;
+    }
+    let mut outer = Sha256::new();
+    outer.update(&pad);
+    outer.update(&inner);
+    outer.finish()
+}
@@ -9,16 +9,8 @@
 //! required to support MLE for attaching a Sleepy End Device (SED) to a
 //! Thread network.
 //!
-//! MLE for network attaching comprises a four-step handshake that works
-//! as follows:
-//!
-//! 1. A child device multicasts a Parent Request MLE command.
-//! 2. Each potential parent device on the network unicasts a Parent
-//!    Response MLE command.
-//! 3. The child device selects a parent based on a hierarchy of
-//!    connectivity metrics and unicasts a Child ID Request MLE
-//!    command.
-//! 4. The selected parent unicasts a Child ID Response MLE command.
+//! The four-step handshake that uses these TLVs to attach to a network is
+//! described in the [mle](../mle/index.html) module.
 //!
 //! A TLV is comprised of three parts:
 //!
@@ -47,19 +39,14 @@
 //!
 //! Author: Mateo Garcia <mateog@stanford.edu>
 
-// TODO: Move the MLE explanation above to the MLE module, when it is created.
-
 // NOTES FOR DEBUGGING:
-// - .to_be() may not have been called on values wider than one byte
-// - encode_bytes_be may have been used instead of encode_bytes
-// - decode_bytes_be may have been used instead of decode_bytes
 // - See 4.5.25 Active Operational Dataset TLV and 4.5.26 Pending Operational Dataset TLV
 //    - Are Active and Pending Timestamp TLVs, respectively, required to be sent as well
 //      if either of the dataset tlvs are sent?
 
 use crate::net::stream::SResult;
-use crate::net::stream::{decode_bytes_be, decode_u16, decode_u32, decode_u8};
-use crate::net::stream::{encode_bytes, encode_bytes_be, encode_u16, encode_u32, encode_u8};
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
 use core::mem;
 
 const TL_WIDTH: usize = 2; // Type and length fields of TLV are each one byte.
@@ -127,7 +114,7 @@ impl Tlv<'_> {
             Tlv::SourceAddress(ref mac_address) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, mac_address.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *mac_address);
                 stream_done!(offset)
             }
             Tlv::Mode(ref mode) => {
@@ -139,37 +126,37 @@ impl Tlv<'_> {
             Tlv::Timeout(ref max_transmit_interval) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, max_transmit_interval.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *max_transmit_interval);
                 stream_done!(offset)
             }
             Tlv::Challenge(ref byte_str) => {
                 let value_width = byte_str.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, byte_str);
+                offset = enc_consume!(buf, offset; encode_bytes, byte_str);
                 stream_done!(offset)
             }
             Tlv::Response(ref byte_str) => {
                 let value_width = byte_str.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, byte_str);
+                offset = enc_consume!(buf, offset; encode_bytes, byte_str);
                 stream_done!(offset)
             }
             Tlv::LinkLayerFrameCounter(ref frame_counter) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, frame_counter.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *frame_counter);
                 stream_done!(offset)
             }
             Tlv::MleFrameCounter(ref frame_counter) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, frame_counter.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *frame_counter);
                 stream_done!(offset)
             }
             Tlv::Address16(ref mac_address) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, mac_address.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *mac_address);
                 stream_done!(offset)
             }
             Tlv::LeaderData {
@@ -185,7 +172,7 @@ impl Tlv<'_> {
                     + mem::size_of::<u8>()
                     + mem::size_of::<u8>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, partition_id.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, partition_id);
                 offset = enc_consume!(buf, offset; encode_u8, weighting);
                 offset = enc_consume!(buf, offset; encode_u8, data_version);
                 offset = enc_consume!(buf, offset; encode_u8, stable_data_version);
@@ -246,7 +233,7 @@ impl Tlv<'_> {
                 offset = enc_consume!(buf, offset; encode_u8, id_sequence);
                 offset = enc_consume!(buf, offset; encode_u8, active_routers);
                 if let Some(ref buf_size) = sed_buffer_size {
-                    offset = enc_consume!(buf, offset; encode_u16, buf_size.to_be());
+                    offset = enc_consume!(buf, offset; encode_u16, *buf_size);
                 }
                 if let Some(ref datagram_cnt) = sed_datagram_count {
                     offset = enc_consume!(buf, offset; encode_u8, *datagram_cnt);
@@ -316,12 +303,12 @@ impl Tlv<'_> {
             }
             TlvType::Challenge => {
                 let mut byte_str = [0u8; 8];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut byte_str);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut byte_str);
                 stream_done!(offset, Tlv::Challenge(byte_str))
             }
             TlvType::Response => {
                 let mut byte_str = [0u8; 8];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut byte_str);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut byte_str);
                 stream_done!(offset, Tlv::Response(byte_str))
             }
             TlvType::LinkLayerFrameCounter => {
@@ -375,13 +362,13 @@ impl Tlv<'_> {
                 let (offset, active_routers) = dec_try!(buf, offset; decode_u8);
                 let mut offset = offset;
                 let mut sed_buffer_size = None;
-                if offset + mem::size_of::<u16>() < length as usize {
+                if offset + mem::size_of::<u16>() <= TL_WIDTH + length as usize {
                     let (new_offset, sed_buffer_size_raw) = dec_try!(buf, offset; decode_u16);
                     offset = new_offset;
                     sed_buffer_size = Some(sed_buffer_size_raw);
                 }
                 let mut sed_datagram_count = None;
-                if offset + mem::size_of::<u8>() < length as usize {
+                if offset + mem::size_of::<u8>() <= TL_WIDTH + length as usize {
                     let (new_offset, sed_datagram_count_raw) = dec_try!(buf, offset; decode_u8);
                     offset = new_offset;
                     sed_datagram_count = Some(sed_datagram_count_raw);
@@ -584,7 +571,7 @@ impl NetworkDataTlv<'_> {
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width, stable);
                 offset = enc_consume!(buf, offset; encode_u8, domain_id);
                 offset = enc_consume!(buf, offset; encode_u8, prefix_length_bits);
-                offset = enc_consume!(buf, offset; encode_bytes_be, &prefix);
+                offset = enc_consume!(buf, offset; encode_bytes, &prefix);
                 offset = enc_consume!(buf, offset; encode_bytes, sub_tlvs);
                 stream_done!(offset)
             }
@@ -594,7 +581,7 @@ impl NetworkDataTlv<'_> {
             } => {
                 let value_width = com_length as usize;
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width, stable);
-                offset = enc_consume!(buf, offset; encode_bytes_be, &com_data);
+                offset = enc_consume!(buf, offset; encode_bytes, &com_data);
                 stream_done!(offset)
             }
             NetworkDataTlv::Service {
@@ -618,9 +605,9 @@ impl NetworkDataTlv<'_> {
                 };
                 let first_byte: u8 = t_bit | (0b1111 & s_id);
                 offset = enc_consume!(buf, offset; encode_u8, first_byte);
-                offset = enc_consume!(buf, offset; encode_u32, s_enterprise_number.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, s_enterprise_number);
                 offset = enc_consume!(buf, offset; encode_u8, s_service_data_length);
-                offset = enc_consume!(buf, offset; encode_bytes_be, &s_service_data);
+                offset = enc_consume!(buf, offset; encode_bytes, &s_service_data);
                 offset = enc_consume!(buf, offset; encode_bytes, sub_tlvs);
                 stream_done!(offset)
             }
@@ -652,7 +639,7 @@ impl NetworkDataTlv<'_> {
                 let (offset, domain_id) = dec_try!(buf, offset; decode_u8);
                 let (offset, prefix_length_bits) = dec_try!(buf, offset; decode_u8);
                 let mut prefix = [0u8; 3];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut prefix);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut prefix);
                 stream_done!(
                     offset + length as usize,
                     (
@@ -669,7 +656,7 @@ impl NetworkDataTlv<'_> {
             NetworkDataTlvType::CommissioningData => {
                 let (offset, com_length) = dec_try!(buf, offset; decode_u8);
                 let mut com_data = [0u8; MAX_VALUE_FIELD_LENGTH];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut com_data);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut com_data);
                 stream_done!(
                     offset,
                     (
@@ -688,7 +675,7 @@ impl NetworkDataTlv<'_> {
                 let (offset, s_enterprise_number) = dec_try!(buf, offset; decode_u32);
                 let (offset, s_service_data_length) = dec_try!(buf, offset; decode_u8);
                 let mut s_service_data = [0u8; MAX_VALUE_FIELD_LENGTH];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut s_service_data);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut s_service_data);
                 stream_done!(
                     offset + length as usize,
                     (
@@ -883,7 +870,7 @@ impl HasRouteTlvValue {
     /// Serializes this Has Route TLV value into `buf`.
     pub fn encode(&self, buf: &mut [u8]) -> SResult {
         stream_len_cond!(buf, 3);
-        let mut offset = enc_consume!(buf, 0; encode_u16, self.r_border_router_16.to_be());
+        let mut offset = enc_consume!(buf, 0; encode_u16, self.r_border_router_16);
         let last_byte = ((self.r_preference & 0b11) as u8) << 6;
         offset = enc_consume!(buf, offset; encode_u8, last_byte);
         stream_done!(offset)
@@ -930,8 +917,8 @@ impl BorderRouterTlvValue {
     /// Serializes this Border Route TLV value into `buf`.
     pub fn encode(&self, buf: &mut [u8]) -> SResult {
         stream_len_cond!(buf, 4); // Each Border Router TLV value is 32 bits wide.
-        let mut offset = enc_consume!(buf, 0; encode_u16, self.p_border_router_16.to_be());
-        offset = enc_consume!(buf, offset; encode_u16, self.p_bits.to_be());
+        let mut offset = enc_consume!(buf, 0; encode_u16, self.p_border_router_16);
+        offset = enc_consume!(buf, offset; encode_u16, self.p_bits);
         stream_done!(offset)
     }
 
@@ -969,8 +956,8 @@ impl ServiceSubTlv {
             } => {
                 let value_width = mem::size_of::<u16>() + s_server_data.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width, stable);
-                offset = enc_consume!(buf, offset; encode_u16, s_server_16.to_be());
-                offset = enc_consume!(buf, offset; encode_bytes_be, &s_server_data);
+                offset = enc_consume!(buf, offset; encode_u16, s_server_16);
+                offset = enc_consume!(buf, offset; encode_bytes, &s_server_data);
                 stream_done!(offset)
             }
         }
@@ -1000,7 +987,7 @@ impl ServiceSubTlv {
             ServiceSubTlvType::Server => {
                 let (offset, s_server_16) = dec_try!(buf, offset; decode_u16);
                 let mut s_server_data = [0u8; MAX_VALUE_FIELD_LENGTH];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut s_server_data);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut s_server_data);
                 stream_done!(
                     offset,
                     (
@@ -1094,77 +1081,77 @@ impl NetworkManagementTlv<'_> {
                 let value_width = mem::size_of::<u8>() + mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
                 offset = enc_consume!(buf, offset; encode_u8, channel_page);
-                offset = enc_consume!(buf, offset; encode_u16, channel.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, channel);
                 stream_done!(offset)
             }
             NetworkManagementTlv::PanId(ref pan_id) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, pan_id.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *pan_id);
                 stream_done!(offset)
             }
             NetworkManagementTlv::ExtendedPanId(ref extended_pan_id) => {
                 let value_width = extended_pan_id.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, extended_pan_id);
+                offset = enc_consume!(buf, offset; encode_bytes, extended_pan_id);
                 stream_done!(offset)
             }
             NetworkManagementTlv::NetworkName(ref network_name) => {
                 stream_cond!(network_name.len() <= 16);
                 let value_width = network_name.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, network_name);
+                offset = enc_consume!(buf, offset; encode_bytes, network_name);
                 stream_done!(offset)
             }
             NetworkManagementTlv::Pskc(ref pskc) => {
                 stream_cond!(pskc.len() <= 16);
                 let value_width = pskc.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, pskc);
+                offset = enc_consume!(buf, offset; encode_bytes, pskc);
                 stream_done!(offset)
             }
             NetworkManagementTlv::NetworkMasterKey(ref network_key) => {
                 let value_width = network_key.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, network_key);
+                offset = enc_consume!(buf, offset; encode_bytes, network_key);
                 stream_done!(offset)
             }
             NetworkManagementTlv::NetworkKeySequenceCounter(ref counter) => {
                 let value_width = counter.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, counter);
+                offset = enc_consume!(buf, offset; encode_bytes, counter);
                 stream_done!(offset)
             }
             NetworkManagementTlv::NetworkMeshLocalPrefix(ref prefix) => {
                 let value_width = prefix.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, prefix);
+                offset = enc_consume!(buf, offset; encode_bytes, prefix);
                 stream_done!(offset)
             }
             NetworkManagementTlv::SteeringData(ref bloom_filter) => {
                 stream_cond!(bloom_filter.len() <= 16);
                 let value_width = bloom_filter.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, bloom_filter);
+                offset = enc_consume!(buf, offset; encode_bytes, bloom_filter);
                 stream_done!(offset)
             }
             NetworkManagementTlv::BorderAgentLocator(ref rloc_16) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, rloc_16.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *rloc_16);
                 stream_done!(offset)
             }
             NetworkManagementTlv::CommissionerId(ref commissioner_id) => {
                 stream_cond!(commissioner_id.len() <= 64);
                 let value_width = commissioner_id.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, commissioner_id);
+                offset = enc_consume!(buf, offset; encode_bytes, commissioner_id);
                 stream_done!(offset)
             }
             NetworkManagementTlv::CommissionerSessionId(ref session_id) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, session_id.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *session_id);
                 stream_done!(offset)
             }
             NetworkManagementTlv::SecurityPolicy {
@@ -1173,7 +1160,7 @@ impl NetworkManagementTlv<'_> {
             } => {
                 let value_width = mem::size_of::<u16>() + mem::size_of::<u8>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, rotation_time.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, rotation_time);
                 offset = enc_consume!(buf, offset; encode_u8, policy_bits);
                 stream_done!(offset)
             }
@@ -1184,16 +1171,16 @@ impl NetworkManagementTlv<'_> {
             } => {
                 let value_width = timestamp_seconds.len() + mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, &timestamp_seconds);
+                offset = enc_consume!(buf, offset; encode_bytes, &timestamp_seconds);
                 let u_bit_val = if u_bit { 1u16 } else { 0u16 };
                 let end_bytes = (timestamp_ticks << 1) | u_bit_val;
-                offset = enc_consume!(buf, offset; encode_u16, end_bytes.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, end_bytes);
                 stream_done!(offset)
             }
             NetworkManagementTlv::CommissionerUdpPort(ref udp_port) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, udp_port.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *udp_port);
                 stream_done!(offset)
             }
             NetworkManagementTlv::PendingTimestamp {
@@ -1203,16 +1190,16 @@ impl NetworkManagementTlv<'_> {
             } => {
                 let value_width = timestamp_seconds.len() + mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, &timestamp_seconds);
+                offset = enc_consume!(buf, offset; encode_bytes, &timestamp_seconds);
                 let u_bit_val = if u_bit { 1u16 } else { 0u16 };
                 let end_bytes = (timestamp_ticks << 1) | u_bit_val;
-                offset = enc_consume!(buf, offset; encode_u16, end_bytes.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, end_bytes);
                 stream_done!(offset)
             }
             NetworkManagementTlv::DelayTimer(ref time_remaining) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, time_remaining.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *time_remaining);
                 stream_done!(offset)
             }
             NetworkManagementTlv::ChannelMask(ref entries) => {
@@ -1259,27 +1246,27 @@ impl NetworkManagementTlv<'_> {
             }
             NetworkManagementTlvType::ExtendedPanId => {
                 let mut extended_pan_id = [0u8; 8];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut extended_pan_id);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut extended_pan_id);
                 stream_done!(offset, NetworkManagementTlv::ExtendedPanId(extended_pan_id))
             }
             NetworkManagementTlvType::NetworkName => {
                 let mut network_name = [0u8; 16];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut network_name);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut network_name);
                 stream_done!(offset, NetworkManagementTlv::NetworkName(network_name))
             }
             NetworkManagementTlvType::Pskc => {
                 let mut pskc = [0u8; 16];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut pskc);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut pskc);
                 stream_done!(offset, NetworkManagementTlv::Pskc(pskc))
             }
             NetworkManagementTlvType::NetworkMasterKey => {
                 let mut network_key = [0u8; 16];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut network_key);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut network_key);
                 stream_done!(offset, NetworkManagementTlv::NetworkMasterKey(network_key))
             }
             NetworkManagementTlvType::NetworkKeySequenceCounter => {
                 let mut counter = [0u8; 4];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut counter);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut counter);
                 stream_done!(
                     offset,
                     NetworkManagementTlv::NetworkKeySequenceCounter(counter)
@@ -1287,12 +1274,12 @@ impl NetworkManagementTlv<'_> {
             }
             NetworkManagementTlvType::NetworkMeshLocalPrefix => {
                 let mut prefix = [0u8; 8];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut prefix);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut prefix);
                 stream_done!(offset, NetworkManagementTlv::NetworkMeshLocalPrefix(prefix))
             }
             NetworkManagementTlvType::SteeringData => {
                 let mut bloom_filter = [0u8; 16];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut bloom_filter);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut bloom_filter);
                 stream_done!(offset, NetworkManagementTlv::SteeringData(bloom_filter))
             }
             NetworkManagementTlvType::BorderAgentLocator => {
@@ -1301,7 +1288,7 @@ impl NetworkManagementTlv<'_> {
             }
             NetworkManagementTlvType::CommissionerId => {
                 let mut commissioner_id = [0u8; 64];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut commissioner_id);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut commissioner_id);
                 stream_done!(
                     offset,
                     NetworkManagementTlv::CommissionerId(commissioner_id)
@@ -1327,7 +1314,7 @@ impl NetworkManagementTlv<'_> {
             }
             NetworkManagementTlvType::ActiveTimestamp => {
                 let mut timestamp_seconds = [0u8; 3];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut timestamp_seconds);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut timestamp_seconds);
                 let (offset, timestamp_ticks) = dec_try!(buf, offset; decode_u16);
                 stream_done!(
                     offset,
@@ -1344,7 +1331,7 @@ impl NetworkManagementTlv<'_> {
             }
             NetworkManagementTlvType::PendingTimestamp => {
                 let mut timestamp_seconds = [0u8; 3];
-                let offset = dec_consume!(buf; decode_bytes_be, &mut timestamp_seconds);
+                let offset = dec_consume!(buf; decode_bytes, &mut timestamp_seconds);
                 let (offset, timestamp_ticks) = dec_try!(buf, offset; decode_u16);
                 stream_done!(
                     offset,
@@ -1480,7 +1467,7 @@ impl ChannelMaskEntry {
     pub fn encode(&self, buf: &mut [u8]) -> SResult {
         let mut offset = enc_consume!(buf, 0; encode_u8, self.channel_page);
         offset = enc_consume!(buf, offset; encode_u8, self.mask_length);
-        offset = enc_consume!(buf, offset; encode_bytes_be, &self.channel_mask);
+        offset = enc_consume!(buf, offset; encode_bytes, &self.channel_mask);
         stream_done!(offset)
     }
 
@@ -1489,7 +1476,7 @@ impl ChannelMaskEntry {
         let (offset, channel_page) = dec_try!(buf; decode_u8);
         let (offset, mask_length) = dec_try!(buf, offset; decode_u8);
         let mut channel_mask = [0u8; MAX_VALUE_FIELD_LENGTH];
-        let offset = dec_consume!(buf, offset; decode_bytes_be, &mut channel_mask);
+        let offset = dec_consume!(buf, offset; decode_bytes, &mut channel_mask);
         stream_done!(
             offset,
             ChannelMaskEntry {
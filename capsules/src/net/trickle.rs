@@ -0,0 +1,174 @@
+//! This file contains `Trickle`, an implementation of the Trickle algorithm
+//! (RFC 6206) on top of an alarm. Trickle schedules the transmissions of
+//! protocols that keep state consistent between neighbors, such as the DIO
+//! messages of RPL: while neighbors agree, transmissions become
+//! exponentially rarer, and when an inconsistency is detected they quickly
+//! become frequent again.
+//!
+//! Time is divided into intervals, starting at `Imin` and doubling after
+//! each interval up to `Imax`. In each interval the client is asked to
+//! transmit once, at a random time in the second half of the interval,
+//! unless it has already heard `k` (the redundancy constant) consistent
+//! transmissions from its neighbors in that interval. The client reports
+//! consistent transmissions with `consistent`, and inconsistent
+//! transmissions or other events that should speed up dissemination with
+//! `inconsistent`, which resets the interval to `Imin`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let trickle = static_init!(
+//!     Trickle<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     Trickle::new(trickle_virtual_alarm, seed)
+//! );
+//! trickle_virtual_alarm.set_alarm_client(trickle);
+//! trickle.set_client(client);
+//! trickle.configure(4096, 8, 10);
+//! trickle.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm};
+
+/// Intervals are limited to this length, about 70 minutes, so that they fit
+/// in the ticks of the alarm.
+pub const MAX_INTERVAL_MS: u32 = 1 << 22;
+
+pub trait TrickleClient {
+    /// Called when the client should transmit.
+    fn transmit(&self);
+}
+
+pub struct Trickle<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn TrickleClient>,
+    imin_ms: Cell<u32>,
+    imax_ms: Cell<u32>,
+    redundancy: Cell<u8>,
+    interval_ms: Cell<u32>,
+    counter: Cell<u8>,
+    /// Milliseconds from the transmission time to the end of the current
+    /// interval, until the transmission time has passed
+    remaining_ms: OptionalCell<u32>,
+    running: Cell<bool>,
+    random: Cell<u32>,
+}
+
+impl<'a, A: Alarm<'a>> Trickle<'a, A> {
+    /// `seed` seeds the generator of the random transmission times. It
+    /// should be different on each node, for instance derived from its MAC
+    /// address, so that neighbors do not transmit at the same time.
+    pub fn new(alarm: &'a A, seed: u32) -> Trickle<'a, A> {
+        Trickle {
+            alarm: alarm,
+            client: OptionalCell::empty(),
+            imin_ms: Cell::new(1000),
+            imax_ms: Cell::new(1000),
+            redundancy: Cell::new(0),
+            interval_ms: Cell::new(1000),
+            counter: Cell::new(0),
+            remaining_ms: OptionalCell::empty(),
+            running: Cell::new(false),
+            random: Cell::new(seed | 1),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn TrickleClient) {
+        self.client.set(client);
+    }
+
+    /// Sets the parameters of the algorithm: `Imin` in milliseconds, the
+    /// number of times it doubles to give `Imax`, and the redundancy
+    /// constant `k`, with 0 meaning that transmissions are never
+    /// suppressed. Takes effect at the next interval.
+    pub fn configure(&self, imin_ms: u32, doublings: u8, redundancy: u8) {
+        let imin_ms = imin_ms.max(1).min(MAX_INTERVAL_MS);
+        let imax_ms = (imin_ms as u64) << doublings.min(32);
+        self.imin_ms.set(imin_ms);
+        self.imax_ms.set(imax_ms.min(MAX_INTERVAL_MS as u64) as u32);
+        self.redundancy.set(redundancy);
+    }
+
+    /// Starts the timer with an interval of `Imin`.
+    pub fn start(&self) {
+        self.running.set(true);
+        self.interval_ms.set(self.imin_ms.get());
+        self.begin_interval();
+    }
+
+    pub fn stop(&self) {
+        self.running.set(false);
+        self.remaining_ms.clear();
+        self.alarm.disarm();
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.get()
+    }
+
+    /// Records a consistent transmission heard from a neighbor.
+    pub fn consistent(&self) {
+        self.counter.set(self.counter.get().saturating_add(1));
+    }
+
+    /// Records an inconsistent transmission or an external event, which
+    /// resets the interval to `Imin` unless it is already `Imin`.
+    pub fn inconsistent(&self) {
+        if self.running.get() && self.interval_ms.get() != self.imin_ms.get() {
+            self.interval_ms.set(self.imin_ms.get());
+            self.begin_interval();
+        }
+    }
+
+    /// The length of the current interval in milliseconds.
+    pub fn interval_ms(&self) -> u32 {
+        self.interval_ms.get()
+    }
+
+    fn begin_interval(&self) {
+        let interval = self.interval_ms.get();
+        let half = interval / 2;
+        let t = half + self.next_random() % (interval - half);
+        self.counter.set(0);
+        self.remaining_ms.set(interval - t);
+        self.alarm.set_alarm(self.alarm.now(), A::ticks_from_ms(t));
+    }
+
+    /// xorshift32, which is enough to spread transmissions out.
+    fn next_random(&self) -> u32 {
+        let mut x = self.random.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random.set(x);
+        x
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for Trickle<'a, A> {
+    fn alarm(&self) {
+        if !self.running.get() {
+            return;
+        }
+        match self.remaining_ms.take() {
+            Some(remaining_ms) => {
+                // Set the alarm for the end of the interval first, since the
+                // client may reset the interval while transmitting
+                self.alarm
+                    .set_alarm(self.alarm.now(), A::ticks_from_ms(remaining_ms));
+                let redundancy = self.redundancy.get();
+                if redundancy == 0 || self.counter.get() < redundancy {
+                    self.client.map(|client| client.transmit());
+                }
+            }
+            None => {
+                let interval = self.interval_ms.get() as u64 * 2;
+                self.interval_ms
+                    .set(interval.min(self.imax_ms.get() as u64) as u32);
+                self.begin_interval();
+            }
+        }
+    }
+}
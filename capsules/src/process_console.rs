@@ -5,13 +5,30 @@
 //! --------
 //!
 //! This module provides a simple text-based console to inspect and control
-//! which processes are running. The console has five commands:
+//! which processes are running. The console has these commands:
 //!  - 'help' prints the available commands and arguments
 //!  - 'status' prints the current system status
 //!  - 'list' lists the current processes with their IDs and running state
 //!  - 'stop n' stops the process with name n
 //!  - 'start n' starts the stopped process with name n
 //!  - 'fault n' forces the process with name n into a fault state
+//!  - 'restart n' restarts the process with name n from its beginning,
+//!    whatever its state
+//!  - 'terminate n' stops the process with name n and frees its grants; it is
+//!    not restarted
+//!  - 'process n' prints the memory map, registers and MPU configuration of
+//!    the process with name n
+//!  - 'kernel' prints the memory used by the kernel and by each process
+//!  - 'reboot' resets the board, if the board gave the console a function
+//!    to do so
+//!
+//! ### Line editing
+//!
+//! The left and right arrow keys, Home and End (or Ctrl-A and Ctrl-E) move
+//! the cursor, and Backspace and Delete remove characters. The up and down
+//! arrow keys go through the last commands. Tab completes the name of a
+//! command, or of a process in the argument of a command, as far as all
+//! names that start with what was typed agree.
 //!
 //! ### `list` Command Fields:
 //!
@@ -28,6 +45,27 @@
 //! - `State`: The state the process is in.
 //! - `Grants`: The number of grants that have been initialized for the process
 //!   out of the total number of grants defined by the kernel.
+//! - `CPU`: How long the process has run, in milliseconds, over all its
+//!   restarts.
+//! - `Stack` and `Heap`: The most stack and heap memory the process has used
+//!   since it last started, in bytes, or `-` if the process did not tell the
+//!   kernel where its stack or heap starts.
+//!
+//! ### `kernel` Command Fields:
+//!
+//! If the board passed the addresses of the kernel's sections, `kernel`
+//! prints the size of the kernel's code and read-only data in flash, and of
+//! its stack, data and BSS in RAM. For each process it prints the size of its
+//! memory, how much of it is below the process's memory break (`App`), and
+//! how much the kernel took from the end of it for grants and the process's
+//! kernel state (`Grants`).
+//!
+//! ### `status` Command Fields:
+//!
+//! Besides process counts, `status` prints how long the kernel has been up,
+//! asleep, and awake without running processes, if the board gave the kernel
+//! a clock, and how many subscribe, command and allow syscalls each process
+//! made to each driver.
 //!
 //! Setup
 //! -----
@@ -49,7 +87,10 @@
 //!                  &mut console::WRITE_BUF,
 //!                  &mut console::READ_BUF,
 //!                  &mut console::COMMAND_BUF,
+//!                  &mut console::HISTORY_BUF,
 //!                  kernel,
+//!                  None,
+//!                  Some(reset),
 //!                  Capability));
 //! hil::uart::UART::set_client(&usart::USART0, pconsole);
 //!
@@ -57,12 +98,19 @@
 //! pconsole.start();
 //! ```
 //!
+//! The two options are the addresses of the kernel's sections, which `kernel`
+//! prints, and the function `reboot` calls to reset the board.
+//!
 //! Buffer use and output
 //! ---------------------
-//! `ProcessConsole` does not use its own write buffer for output:
+//! `ProcessConsole` does not use its own write buffer for most output:
 //! it uses the debug!() buffer, so as not to repeat all of its buffering and
 //! to maintain a correct ordering with debug!() calls. The write buffer of
-//! `ProcessConsole` is used solely for echoing what someone types.
+//! `ProcessConsole` is used for echoing what someone types, and for the output
+//! of `process`, which is too long for the debug!() buffer. That output is
+//! sent one buffer at a time, formatting it again for each buffer, so values
+//! that change while it is sent, such as those of a running process, may not
+//! agree with each other.
 //!
 //! Using ProcessConsole
 //! --------------------
@@ -90,9 +138,9 @@
 //! Initialization complete. Entering main loop
 //! Hello World!
 //! list
-//! PID    Name    Quanta  Syscalls  Dropped Callbacks  Restarts    State  Grants
-//! 00     blink        0       113                  0         0  Yielded    1/12
-//! 01     c_hello      0         8                  0         0  Yielded    3/12
+//! PID    Name    Quanta  Syscalls  Dropped Callbacks  Restarts    State  Grants     CPU  Stack   Heap
+//! 00     blink        0       113                  0         0  Yielded    1/12      14    312      0
+//! 01     c_hello      0         8                  0         0  Yielded    3/12       2    504   1024
 //! ```
 //!
 //! To get a general view of the system, use the status command:
@@ -102,6 +150,10 @@
 //! Total processes: 2
 //! Active processes: 2
 //! Timeslice expirations: 0
+//! Uptime: 60012 ms, asleep: 59871 ms, in kernel: 125 ms
+//! Syscalls by driver:
+//!   blink        0x0: 40 0x2: 73
+//!   c_hello      0x1: 8
 //! ```
 //!
 //! and you can control processes with the `start` and `stop` commands:
@@ -110,27 +162,89 @@
 //! stop blink
 //! Process blink stopped
 //! ```
+//!
+//! To see where the memory goes, use `kernel`:
+//!
+//! ```text
+//! kernel
+//! Kernel flash: 75264 bytes of code and read-only data at 0x00010000
+//! Kernel RAM: 8192 bytes of stack, 212 bytes of data, 30460 bytes of BSS
+//! Process memory (bytes):
+//!   Name              Memory     App  Grants
+//!   blink               8192    4632    1052
+//!   c_hello             8192    4632     760
+//! Grants of all processes: 1812 bytes
+//! ```
 
 use core::cell::Cell;
 use core::cmp;
+use core::fmt;
 use core::str;
 use kernel::capabilities::ProcessManagementCapability;
 use kernel::common::cells::TakeCell;
 use kernel::debug;
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
-use kernel::Kernel;
+use kernel::procs::ProcessType;
 use kernel::ReturnCode;
+use kernel::{AppId, Kernel};
 
-// Since writes are character echoes, we do not need more than 4 bytes:
-// the longest write is 3 bytes for a backspace (backspace, space, backspace).
-pub static mut WRITE_BUF: [u8; 4] = [0; 4];
+// Writes are character echoes, redraws of the command line, which take at
+// most four times the length of a command, and pieces of the output of
+// `process`.
+pub static mut WRITE_BUF: [u8; 128] = [0; 128];
 // Since reads are byte-by-byte, to properly echo what's typed,
 // we can use a very small read buffer.
 pub static mut READ_BUF: [u8; 4] = [0; 4];
-// Commands can be up to 32 bytes long: since commands themselves are 4-5
-// characters, limiting arguments to 25 bytes or so seems fine for now.
+// Commands can be up to 32 bytes long: since commands themselves are 4-9
+// characters, limiting arguments to 20 bytes or so seems fine for now.
 pub static mut COMMAND_BUF: [u8; 32] = [0; 32];
+// Room for the last four commands, each as long as `COMMAND_BUF`.
+pub static mut HISTORY_BUF: [u8; 4 * 32] = [0; 4 * 32];
+
+/// The commands, and whether they take the name of a process.
+const COMMANDS: [(&str, bool); 11] = [
+    ("help", false),
+    ("status", false),
+    ("list", false),
+    ("stop", true),
+    ("start", true),
+    ("fault", true),
+    ("restart", true),
+    ("terminate", true),
+    ("process", true),
+    ("kernel", false),
+    ("reboot", false),
+];
+
+const VALID_COMMANDS: &str =
+    "Valid commands are: help status list stop start fault restart terminate process kernel reboot";
+
+/// Addresses of the kernel's sections, from the symbols of the board's linker
+/// script.
+#[derive(Copy, Clone)]
+pub struct KernelAddresses {
+    pub stack_start: *const u8,
+    pub stack_end: *const u8,
+    pub text_start: *const u8,
+    pub text_end: *const u8,
+    pub relocate_start: *const u8,
+    pub relocate_end: *const u8,
+    pub bss_start: *const u8,
+    pub bss_end: *const u8,
+}
+
+/// What the previous bytes of an escape sequence were.
+#[derive(Copy, Clone, PartialEq)]
+enum EscapeState {
+    None,
+    /// ESC
+    Escape,
+    /// ESC [
+    Bracket,
+    /// ESC [ 3, which the Delete key sends before `~`.
+    Delete,
+}
 
 pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     uart: &'a dyn uart::UartData<'a>,
@@ -139,16 +253,44 @@ pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     rx_in_progress: Cell<bool>,
     rx_buffer: TakeCell<'static, [u8]>,
     command_buffer: TakeCell<'static, [u8]>,
+    /// Length of the command being typed.
     command_index: Cell<usize>,
+    /// Position of the cursor in the command being typed.
+    cursor: Cell<usize>,
+    /// Position of the cursor, and length of the command line, on the
+    /// terminal.
+    shown_cursor: Cell<usize>,
+    shown_len: Cell<usize>,
+    /// Where the command line first differs from the one on the terminal,
+    /// if it does.
+    redraw_from: Cell<Option<usize>>,
+    escape: Cell<EscapeState>,
+
+    /// The last commands, each in a slot the size of the command buffer and
+    /// ended by a 0, starting with the most recent.
+    history_buffer: TakeCell<'static, [u8]>,
+    history_slots: usize,
+    /// The command of `history_buffer` that was brought up with the arrow
+    /// keys, if one was.
+    history_index: Cell<Option<usize>>,
+
+    /// The process whose state `process` is printing, and how many bytes of
+    /// it were sent.
+    printing: Cell<Option<(AppId, usize)>>,
 
     /// Flag to mark that the process console is active and has called receive
     /// from the underlying UART.
     running: Cell<bool>,
 
+    /// Internal flag that a newline was typed, and needs to be echoed.
+    entered: Cell<bool>,
+
     /// Internal flag that the process console should parse the command it just
     /// received after finishing echoing the last newline character.
     execute: Cell<bool>,
     kernel: &'static Kernel,
+    kernel_addresses: Option<KernelAddresses>,
+    reset_function: Option<fn() -> !>,
     capability: C,
 }
 
@@ -158,9 +300,16 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
         tx_buffer: &'static mut [u8],
         rx_buffer: &'static mut [u8],
         cmd_buffer: &'static mut [u8],
+        history_buffer: &'static mut [u8],
         kernel: &'static Kernel,
+        kernel_addresses: Option<KernelAddresses>,
+        reset_function: Option<fn() -> !>,
         capability: C,
     ) -> ProcessConsole<'a, C> {
+        let history_slots = history_buffer
+            .len()
+            .checked_div(cmd_buffer.len())
+            .unwrap_or(0);
         ProcessConsole {
             uart: uart,
             tx_in_progress: Cell::new(false),
@@ -169,9 +318,21 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
             rx_buffer: TakeCell::new(rx_buffer),
             command_buffer: TakeCell::new(cmd_buffer),
             command_index: Cell::new(0),
+            cursor: Cell::new(0),
+            shown_cursor: Cell::new(0),
+            shown_len: Cell::new(0),
+            redraw_from: Cell::new(None),
+            escape: Cell::new(EscapeState::None),
+            history_buffer: TakeCell::new(history_buffer),
+            history_slots: history_slots,
+            history_index: Cell::new(None),
+            printing: Cell::new(None),
             running: Cell::new(false),
+            entered: Cell::new(false),
             execute: Cell::new(false),
             kernel: kernel,
+            kernel_addresses: kernel_addresses,
+            reset_function: reset_function,
             capability: capability,
         }
     }
@@ -190,146 +351,503 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
 
     // Process the command in the command buffer and clear the buffer.
     fn read_command(&self) {
+        let len = self.command_index.get();
         self.command_buffer.map(|command| {
-            let mut terminator = 0;
-            let len = command.len();
-            for i in 0..len {
-                if command[i] == 0 {
-                    terminator = i;
-                    break;
-                }
+            if len > 0 {
+                self.save_in_history(&command[..len]);
             }
-            //debug!("Command: {}-{} {:?}", start, terminator, command);
-            // A command is valid only if it starts inside the buffer,
-            // ends before the beginning of the buffer, and ends after
-            // it starts.
-            if terminator > 0 {
-                let cmd_str = str::from_utf8(&command[0..terminator]);
-                match cmd_str {
-                    Ok(s) => {
-                        let clean_str = s.trim();
-                        if clean_str.starts_with("help") {
-                            debug!("Welcome to the process console.");
-                            debug!("Valid commands are: help status list stop start fault");
-                        } else if clean_str.starts_with("start") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.resume();
-                                            debug!("Process {} resumed.", name);
-                                        }
-                                    },
-                                );
-                            });
-                        } else if clean_str.starts_with("stop") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.stop();
-                                            debug!("Process {} stopped", proc_name);
-                                        }
-                                    },
-                                );
-                            });
-                        } else if clean_str.starts_with("fault") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.set_fault_state();
-                                            debug!("Process {} now faulted", proc_name);
-                                        }
-                                    },
-                                );
-                            });
-                        } else if clean_str.starts_with("list") {
-                            debug!(" PID    Name                Quanta  Syscalls  Dropped Callbacks  Restarts    State  Grants");
-                            self.kernel
-                                .process_each_capability(&self.capability, |proc| {
-                                    let info: KernelInfo = KernelInfo::new(self.kernel);
-
-                                    let pname = proc.get_process_name();
-                                    let appid = proc.appid();
-                                    let (grants_used, grants_total) = info.number_app_grant_uses(appid, &self.capability);
-
-                                    debug!(
-                                        "  {:?}\t{:<20}{:6}{:10}{:19}{:10}  {:?}{:5}/{}",
-                                        appid,
-                                        pname,
-                                        proc.debug_timeslice_expiration_count(),
-                                        proc.debug_syscall_count(),
-                                        proc.debug_dropped_callback_count(),
-                                        proc.get_restart_count(),
-                                        proc.get_state(),
-                                        grants_used,
-                                        grants_total
-                                    );
-                                });
-                        } else if clean_str.starts_with("status") {
-                            let info: KernelInfo = KernelInfo::new(self.kernel);
-                            debug!(
-                                "Total processes: {}",
-                                info.number_loaded_processes(&self.capability)
-                            );
-                            debug!(
-                                "Active processes: {}",
-                                info.number_active_processes(&self.capability)
-                            );
-                            debug!(
-                                "Timeslice expirations: {}",
-                                info.timeslice_expirations(&self.capability)
-                            );
-                        } else {
-                            debug!("Valid commands are: help status list stop start fault");
-                        }
-                    }
-                    Err(_e) => debug!("Invalid command: {:?}", command),
-                }
+            match str::from_utf8(&command[..len]) {
+                Ok(s) => self.run_command(s.trim()),
+                Err(_e) => debug!("Invalid command: {:?}", &command[..len]),
             }
         });
-        self.command_buffer.map(|command| {
-            command[0] = 0;
-        });
         self.command_index.set(0);
+        self.cursor.set(0);
+        self.history_index.set(None);
     }
 
-    fn write_byte(&self, byte: u8) -> ReturnCode {
-        if self.tx_in_progress.get() {
-            ReturnCode::EBUSY
-        } else {
-            self.tx_in_progress.set(true);
-            self.tx_buffer.take().map(|buffer| {
-                buffer[0] = byte;
-                self.uart.transmit_buffer(buffer, 1);
+    fn run_command(&self, command: &str) {
+        let mut words = command.split_whitespace();
+        let (command, argument) = match words.next() {
+            Some(command) => (command, words.next()),
+            None => return,
+        };
+        match (command, argument) {
+            ("help", _) => {
+                debug!("Welcome to the process console.");
+                debug!("{}", VALID_COMMANDS);
+            }
+            ("start", Some(name)) => self.with_process(name, |proc| {
+                proc.resume();
+                debug!("Process {} resumed.", name);
+            }),
+            ("stop", Some(name)) => self.with_process(name, |proc| {
+                proc.stop();
+                debug!("Process {} stopped", name);
+            }),
+            ("fault", Some(name)) => self.with_process(name, |proc| {
+                proc.set_fault_state(kernel::procs::FaultReason::Forced);
+                debug!("Process {} now faulted", name);
+            }),
+            ("restart", Some(name)) => self.with_process(name, |proc| {
+                if proc.force_restart() {
+                    debug!("Process {} restarted", name);
+                } else {
+                    debug!("Process {} could not be restarted", name);
+                }
+            }),
+            ("terminate", Some(name)) => self.with_process(name, |proc| {
+                proc.terminate();
+                debug!("Process {} terminated", name);
+            }),
+            ("process", Some(name)) => {
+                self.with_process(name, |proc| {
+                    self.printing.set(Some((proc.appid(), 0)));
+                });
+                self.print_next();
+            }
+            ("list", _) => self.print_list(),
+            ("status", _) => self.print_status(),
+            ("kernel", _) => self.print_kernel(),
+            ("reboot", _) => match self.reset_function {
+                Some(reset) => reset(),
+                None => debug!("Reboot is not supported on this board"),
+            },
+            _ => debug!("{}", VALID_COMMANDS),
+        }
+    }
+
+    /// Call `f` with the process named `name`.
+    fn with_process<F: Fn(&dyn ProcessType)>(&self, name: &str, f: F) {
+        let found = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                if proc.get_process_name() == name {
+                    found.set(true);
+                    f(proc);
+                }
             });
-            ReturnCode::SUCCESS
+        if !found.get() {
+            debug!("No process named {}", name);
         }
     }
 
-    fn write_bytes(&self, bytes: &[u8]) -> ReturnCode {
+    fn print_list(&self) {
+        debug!(" PID    Name                Quanta  Syscalls  Dropped Callbacks  Restarts    State  Grants     CPU  Stack   Heap");
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                let info: KernelInfo = KernelInfo::new(self.kernel);
+
+                let pname = proc.get_process_name();
+                let appid = proc.appid();
+                let (grants_used, grants_total) =
+                    info.number_app_grant_uses(appid, &self.capability);
+                let cpu_time_ms = info.app_cpu_time_us(appid, &self.capability) / 1000;
+                let (stack, heap) = info.app_memory_high_water(appid, &self.capability);
+
+                debug!(
+                    "  {:?}\t{:<20}{:6}{:10}{:19}{:10}  {:?}{:5}/{:<4}{:>6}{:>7}{:>7}",
+                    appid,
+                    pname,
+                    proc.debug_timeslice_expiration_count(),
+                    proc.debug_syscall_count(),
+                    proc.debug_dropped_callback_count(),
+                    proc.get_restart_count(),
+                    proc.get_state(),
+                    grants_used,
+                    grants_total,
+                    cpu_time_ms,
+                    OptionalSize(stack),
+                    OptionalSize(heap)
+                );
+            });
+    }
+
+    fn print_status(&self) {
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        debug!(
+            "Total processes: {}",
+            info.number_loaded_processes(&self.capability)
+        );
+        debug!(
+            "Active processes: {}",
+            info.number_active_processes(&self.capability)
+        );
+        debug!(
+            "Timeslice expirations: {}",
+            info.timeslice_expirations(&self.capability)
+        );
+        if let (Some(uptime), Some(sleep_time), Some(kernel_time)) = (
+            info.uptime_us(&self.capability),
+            info.sleep_time_us(&self.capability),
+            info.kernel_time_us(&self.capability),
+        ) {
+            debug!(
+                "Uptime: {} ms, asleep: {} ms, in kernel: {} ms",
+                uptime / 1000,
+                sleep_time / 1000,
+                kernel_time / 1000
+            );
+        }
+        debug!("Syscalls by driver:");
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                let histogram = info.app_syscall_histogram(proc.appid(), &self.capability);
+                debug!("  {:<12}{}", proc.get_process_name(), histogram);
+            });
+    }
+
+    fn print_kernel(&self) {
+        match self.kernel_addresses {
+            Some(addresses) => {
+                debug!(
+                    "Kernel flash: {} bytes of code and read-only data at {:#010X}",
+                    addresses.text_end as usize - addresses.text_start as usize,
+                    addresses.text_start as usize
+                );
+                debug!(
+                    "Kernel RAM: {} bytes of stack, {} bytes of data, {} bytes of BSS",
+                    addresses.stack_end as usize - addresses.stack_start as usize,
+                    addresses.relocate_end as usize - addresses.relocate_start as usize,
+                    addresses.bss_end as usize - addresses.bss_start as usize
+                );
+            }
+            None => debug!("Kernel memory: unknown"),
+        }
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        let total_grants = Cell::new(0);
+        debug!("Process memory (bytes):");
+        debug!("  Name              Memory     App  Grants");
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                let (app, grants, memory) = info.app_memory_usage(proc.appid(), &self.capability);
+                total_grants.set(total_grants.get() + grants);
+                debug!(
+                    "  {:<16}{:>8}{:>8}{:>8}",
+                    proc.get_process_name(),
+                    memory,
+                    app,
+                    grants
+                );
+            });
+        debug!("Grants of all processes: {} bytes", total_grants.get());
+    }
+
+    /// Send the next piece of the state of the process `process` is printing.
+    /// Returns false if there is nothing left to send.
+    fn print_next(&self) -> bool {
+        let (appid, sent) = match self.printing.take() {
+            Some(printing) => printing,
+            None => return false,
+        };
         if self.tx_in_progress.get() {
-            ReturnCode::EBUSY
+            self.printing.set(Some((appid, sent)));
+            return true;
+        }
+        self.tx_buffer.take().map_or(false, |buffer| {
+            let mut writer = BufferWriter::new(buffer, sent);
+            KernelInfo::new(self.kernel).print_app_state(appid, &mut writer, &self.capability);
+            let (len, total) = (writer.len(), writer.position);
+            if len == 0 {
+                self.tx_buffer.replace(buffer);
+                return false;
+            }
+            if sent + len < total {
+                self.printing.set(Some((appid, sent + len)));
+            }
+            self.transmit(buffer, len) == ReturnCode::SUCCESS
+        })
+    }
+
+    /// Write the bytes `write` puts in the write buffer, unless it is in use.
+    fn write_with<F: FnOnce(&mut BufferWriter)>(&self, write: F) -> ReturnCode {
+        if self.tx_in_progress.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.tx_buffer
+            .take()
+            .map_or(ReturnCode::ERESERVE, |buffer| {
+                let mut writer = BufferWriter::new(buffer, 0);
+                write(&mut writer);
+                let len = writer.len();
+                if len == 0 {
+                    self.tx_buffer.replace(buffer);
+                    ReturnCode::SUCCESS
+                } else {
+                    self.transmit(buffer, len)
+                }
+            })
+    }
+
+    fn transmit(&self, buffer: &'static mut [u8], len: usize) -> ReturnCode {
+        self.tx_in_progress.set(true);
+        let (result, buffer) = self.uart.transmit_buffer(buffer, len);
+        if let Some(buffer) = buffer {
+            self.tx_buffer.replace(buffer);
+            self.tx_in_progress.set(false);
+        }
+        result
+    }
+
+    /// Bring the command line on the terminal up to date with the command
+    /// buffer, and echo the newline of an entered command, unless the write
+    /// buffer is in use. Changes made while it is in use are echoed together
+    /// when it is free again.
+    fn echo(&self) {
+        let len = self.command_index.get();
+        let cursor = if self.entered.get() {
+            len
         } else {
-            self.tx_in_progress.set(true);
-            self.tx_buffer.take().map(|buffer| {
-                let len = cmp::min(bytes.len(), buffer.len());
-                // Copy elements of `bytes` into `buffer`
-                (&mut buffer[..len]).copy_from_slice(&bytes[..len]);
-                self.uart.transmit_buffer(buffer, len);
-            });
-            ReturnCode::SUCCESS
+            self.cursor.get()
+        };
+        let shown_cursor = self.shown_cursor.get();
+        let shown_len = self.shown_len.get();
+        let redraw_from = self.redraw_from.get();
+        let result = self.command_buffer.map_or(ReturnCode::ERESERVE, |command| {
+            self.write_with(|writer| {
+                // Move the cursor of the terminal to where the line changed,
+                // or to the cursor, printing what it moves over, which did
+                // not change.
+                let start = redraw_from.unwrap_or(cursor);
+                if start < shown_cursor {
+                    writer.repeat(b'\x08', shown_cursor - start);
+                } else {
+                    writer.write_bytes(&command[shown_cursor..start]);
+                }
+                if redraw_from.is_some() {
+                    // Print the rest of the line, blank out what is left of
+                    // a longer line, and move back to the cursor.
+                    let end = cmp::max(len, shown_len);
+                    writer.write_bytes(&command[start..len]);
+                    writer.repeat(b' ', end - len);
+                    writer.repeat(b'\x08', end - cursor);
+                }
+                if self.entered.get() {
+                    writer.write_bytes(b"\r\n");
+                }
+            })
+        });
+        if result == ReturnCode::EBUSY {
+            return;
+        }
+        self.shown_cursor.set(cursor);
+        self.shown_len.set(len);
+        self.redraw_from.set(None);
+        if self.entered.get() {
+            self.entered.set(false);
+            self.shown_cursor.set(0);
+            self.shown_len.set(0);
+            if self.tx_in_progress.get() {
+                // Run the command once the newline is sent, so its output
+                // comes after it.
+                self.execute.set(true);
+            } else {
+                self.read_command();
+            }
+        }
+    }
+
+    /// Note that the command line changed from `position` on.
+    fn changed_from(&self, position: usize) {
+        let redraw_from = self.redraw_from.get().unwrap_or(position);
+        self.redraw_from.set(Some(cmp::min(redraw_from, position)));
+    }
+
+    fn insert(&self, byte: u8) {
+        let len = self.command_index.get();
+        let cursor = self.cursor.get();
+        self.command_buffer.map(|command| {
+            // Keep the last byte for the 0 that ends the command in the
+            // history.
+            if len < command.len() - 1 {
+                command.copy_within(cursor..len, cursor + 1);
+                command[cursor] = byte;
+                self.command_index.set(len + 1);
+                self.cursor.set(cursor + 1);
+                self.changed_from(cursor);
+            }
+        });
+    }
+
+    /// Remove the byte at `position`.
+    fn remove(&self, position: usize) {
+        let len = self.command_index.get();
+        self.command_buffer.map(|command| {
+            command.copy_within(position + 1..len, position);
+        });
+        self.command_index.set(len - 1);
+        self.cursor.set(position);
+        self.changed_from(position);
+    }
+
+    /// Replace the command line with `line`.
+    fn replace_line(&self, line: &[u8]) {
+        self.command_buffer.map(|command| {
+            let len = cmp::min(line.len(), command.len() - 1);
+            command[..len].copy_from_slice(&line[..len]);
+            self.command_index.set(len);
+            self.cursor.set(len);
+        });
+        self.changed_from(0);
+    }
+
+    fn save_in_history(&self, line: &[u8]) {
+        let slots = self.history_slots;
+        if slots == 0 {
+            return;
+        }
+        self.history_buffer.map(|history| {
+            let slot_len = history.len() / slots;
+            if history.starts_with(line) && history[line.len()] == 0 {
+                // Same as the last command.
+                return;
+            }
+            history.copy_within(..(slots - 1) * slot_len, slot_len);
+            history[..line.len()].copy_from_slice(line);
+            history[line.len()] = 0;
+        });
+    }
+
+    /// Bring up command `index` of the history, or an empty line if `index`
+    /// is `None`. Does nothing if the history has no such command.
+    fn show_history(&self, index: Option<usize>) {
+        let index = match index {
+            Some(index) => index,
+            None => {
+                self.history_index.set(None);
+                self.replace_line(&[]);
+                return;
+            }
+        };
+        let slots = self.history_slots;
+        if index >= slots {
+            return;
+        }
+        self.history_buffer.map(|history| {
+            let slot_len = history.len() / slots;
+            let slot = &history[index * slot_len..(index + 1) * slot_len];
+            let len = slot.iter().position(|&byte| byte == 0).unwrap_or(0);
+            if len > 0 {
+                self.replace_line(&slot[..len]);
+                self.history_index.set(Some(index));
+            }
+        });
+    }
+
+    /// Complete the word before the cursor with the names of the commands,
+    /// or with the names of the processes if it is the argument of a command
+    /// that takes one.
+    fn complete(&self) {
+        let len = self.command_index.get();
+        if self.cursor.get() != len {
+            return;
         }
+        self.command_buffer.map(|command| {
+            let line = match str::from_utf8(&command[..len]) {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            let word_start = line.rfind(' ').map_or(0, |space| space + 1);
+            let word = &line[word_start..];
+            let mut words = line[..word_start].split_whitespace();
+            let (completion, count) = match (words.next(), words.next()) {
+                (None, _) => common_prefix(
+                    COMMANDS
+                        .iter()
+                        .map(|&(name, _)| name)
+                        .filter(|name| name.starts_with(word)),
+                ),
+                (Some(command), None)
+                    if COMMANDS
+                        .iter()
+                        .any(|&(name, takes_process)| name == command && takes_process) =>
+                {
+                    let names: Cell<(Option<&'static str>, usize)> = Cell::new((None, 0));
+                    self.kernel
+                        .process_each_capability(&self.capability, |proc| {
+                            let name = proc.get_process_name();
+                            if name.starts_with(word) {
+                                let (common, count) = names.get();
+                                let (common, _) =
+                                    common_prefix(common.into_iter().chain(Some(name)));
+                                names.set((common, count + 1));
+                            }
+                        });
+                    names.get()
+                }
+                _ => (None, 0),
+            };
+            let completion = match completion {
+                Some(completion) => &completion.as_bytes()[word.len()..],
+                None => return,
+            };
+            // A unique name is complete, so start the next word.
+            let space: &[u8] = if count == 1 { b" " } else { b"" };
+            let mut end = len;
+            for &byte in completion.iter().chain(space) {
+                // Keep the last byte for the 0 that ends the command in the
+                // history.
+                if end < command.len() - 1 {
+                    command[end] = byte;
+                    end += 1;
+                }
+            }
+            self.command_index.set(end);
+            self.cursor.set(end);
+        });
+        self.changed_from(len);
+    }
+
+    fn handle_byte(&self, byte: u8) {
+        if self.entered.get() || self.execute.get() {
+            // The command was entered, but did not run yet.
+            return;
+        }
+        let len = self.command_index.get();
+        let cursor = self.cursor.get();
+        match self.escape.replace(EscapeState::None) {
+            EscapeState::Escape => {
+                if byte == b'[' {
+                    self.escape.set(EscapeState::Bracket);
+                }
+            }
+            EscapeState::Bracket => match byte {
+                b'A' => {
+                    let next = self.history_index.get().map_or(0, |index| index + 1);
+                    self.show_history(Some(next));
+                }
+                b'B' => {
+                    if let Some(index) = self.history_index.get() {
+                        self.show_history(index.checked_sub(1));
+                    }
+                }
+                b'C' if cursor < len => self.cursor.set(cursor + 1),
+                b'D' if cursor > 0 => self.cursor.set(cursor - 1),
+                b'H' => self.cursor.set(0),
+                b'F' => self.cursor.set(len),
+                b'3' => self.escape.set(EscapeState::Delete),
+                _ => {}
+            },
+            EscapeState::Delete => {
+                if byte == b'~' && cursor < len {
+                    self.remove(cursor);
+                }
+            }
+            EscapeState::None => match byte {
+                b'\n' | b'\r' => self.entered.set(true),
+                b'\x08' | b'\x7f' if cursor > 0 => self.remove(cursor - 1),
+                b'\x1b' => self.escape.set(EscapeState::Escape),
+                b'\t' => self.complete(),
+                b'\x01' => self.cursor.set(0),
+                b'\x05' => self.cursor.set(len),
+                // For some reason, sometimes reads return > 127 but no error,
+                // which causes utf-8 decoding failure, so only take printable
+                // ASCII. -pal
+                b' '..=b'~' => self.insert(byte),
+                _ => {}
+            },
+        }
+        self.echo();
     }
 }
 
@@ -344,6 +862,12 @@ impl<'a, C: ProcessManagementCapability> uart::TransmitClient for ProcessConsole
             self.execute.set(false);
             self.read_command();
         }
+
+        // Echo what was typed while the buffer was in use, once the output of
+        // `process` is sent.
+        if !self.print_next() {
+            self.echo();
+        }
     }
 }
 impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<'a, C> {
@@ -357,30 +881,7 @@ impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<
         if error == uart::Error::None {
             match rx_len {
                 0 => debug!("ProcessConsole had read of 0 bytes"),
-                1 => {
-                    self.command_buffer.map(|command| {
-                        let index = self.command_index.get() as usize;
-                        if read_buf[0] == ('\n' as u8) || read_buf[0] == ('\r' as u8) {
-                            self.execute.set(true);
-                            self.write_bytes(&['\r' as u8, '\n' as u8]);
-                        } else if read_buf[0] == ('\x08' as u8) && index > 0 {
-                            // Backspace, echo and remove last byte
-                            // Note echo is '\b \b' to erase
-                            self.write_bytes(&['\x08' as u8, ' ' as u8, '\x08' as u8]);
-                            command[index - 1] = '\0' as u8;
-                            self.command_index.set(index - 1);
-                        } else if index < (command.len() - 1) && read_buf[0] < 128 {
-                            // For some reason, sometimes reads return > 127 but no error,
-                            // which causes utf-8 decoding failure, so check byte is < 128. -pal
-
-                            // Echo the byte and store it
-                            self.write_byte(read_buf[0]);
-                            command[index] = read_buf[0];
-                            self.command_index.set(index + 1);
-                            command[index + 1] = 0;
-                        }
-                    });
-                }
+                1 => self.handle_byte(read_buf[0]),
                 _ => debug!(
                     "ProcessConsole issues reads of 1 byte, but receive_complete was length {}",
                     rx_len
@@ -391,3 +892,90 @@ impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<
         self.uart.receive_buffer(read_buf, 1);
     }
 }
+
+/// Returns the longest prefix that all `names` share, and how many names
+/// there are.
+fn common_prefix<I: Iterator<Item = &'static str>>(names: I) -> (Option<&'static str>, usize) {
+    let mut count = 0;
+    let common = names.fold(None, |common: Option<&'static str>, name| {
+        count += 1;
+        Some(match common {
+            None => name,
+            Some(common) => {
+                let len = common
+                    .bytes()
+                    .zip(name.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                &common[..len]
+            }
+        })
+    });
+    (common, count)
+}
+
+/// Writes formatted output into a buffer, leaving out its first `skip`
+/// bytes, and counts all bytes of the output. Output longer than the buffer
+/// can be sent in pieces by formatting it again for each piece, skipping
+/// what was sent.
+pub(crate) struct BufferWriter<'b> {
+    buffer: &'b mut [u8],
+    skip: usize,
+    /// Number of bytes of output so far.
+    position: usize,
+}
+
+impl<'b> BufferWriter<'b> {
+    pub(crate) fn new(buffer: &'b mut [u8], skip: usize) -> BufferWriter<'b> {
+        BufferWriter {
+            buffer: buffer,
+            skip: skip,
+            position: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if let Some(index) = self.position.checked_sub(self.skip) {
+            if index < self.buffer.len() {
+                self.buffer[index] = byte;
+            }
+        }
+        self.position += 1;
+    }
+
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(byte);
+        }
+    }
+
+    fn repeat(&mut self, byte: u8, count: usize) {
+        for _ in 0..count {
+            self.push(byte);
+        }
+    }
+
+    /// Number of bytes in the buffer.
+    pub(crate) fn len(&self) -> usize {
+        cmp::min(self.position.saturating_sub(self.skip), self.buffer.len())
+    }
+}
+
+impl fmt::Write for BufferWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Formats a size in bytes, or `-` if it is not known.
+struct OptionalSize(Option<usize>);
+
+impl fmt::Display for OptionalSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(size) => fmt::Display::fmt(&size, f),
+            None => f.pad("-"),
+        }
+    }
+}
@@ -0,0 +1,368 @@
+//! Persistent record of process faults.
+//!
+//! `ProcessFaultLog` is a process restart policy that records every fault
+//! (the fault reason, program counter, process state and application name)
+//! in a log in flash before passing the decision on to another restart
+//! policy. The most recent `MAX_FAULT_RECORDS` faults are kept in memory.
+//! When the board boots, the records are read back from the log, so faults
+//! that happened before a reboot (for example one caused by a watchdog or a
+//! restart policy that panics) can be inspected with `for_each_record()`.
+//!
+//! When the restart policy decides to panic, the process is stopped instead,
+//! and the kernel panics once the record has been written to the log. Until
+//! the log is loaded, records cannot be written, so the kernel panics
+//! immediately.
+//!
+//! The log should be circular, so that old records are overwritten once it
+//! is full.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! storage_volume!(FAULT_LOG_VOLUME, 2);
+//! pub static mut FAULT_LOG_BUFFER: [u8; capsules::process_fault_log::RECORD_SIZE] =
+//!     [0; capsules::process_fault_log::RECORD_SIZE];
+//!
+//! let fault_log = static_init!(
+//!     capsules::process_fault_log::ProcessFaultLog<'static, Log<'static, FLASHCALW>>,
+//!     capsules::process_fault_log::ProcessFaultLog::new(
+//!         log,
+//!         restart_policy,
+//!         &mut FAULT_LOG_BUFFER
+//!     )
+//! );
+//! log.set_read_client(fault_log);
+//! log.set_append_client(fault_log);
+//! fault_log.load();
+//!
+//! kernel::procs::load_processes(
+//!     board_kernel,
+//!     chip,
+//!     app_flash,
+//!     app_memory,
+//!     kernel::procs::FaultResponse::Restart(fault_log),
+//!     &process_mgmt_cap,
+//! );
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::convert::TryInto;
+use core::str;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::log::{LogRead, LogReadClient, LogWrite, LogWriteClient};
+use kernel::procs::{
+    FaultAction, FaultReason, FaultRecord, ProcessRestartPolicy, ProcessType, State,
+};
+use kernel::ReturnCode;
+
+/// Number of fault records kept in memory.
+pub const MAX_FAULT_RECORDS: usize = 8;
+/// Size of a fault record in the log.
+pub const RECORD_SIZE: usize = 28;
+/// Number of bytes of the application name kept in a record.
+const NAME_LEN: usize = 16;
+/// Version of the record format.
+const RECORD_VERSION: u8 = 1;
+
+/// A fault recorded by `ProcessFaultLog`.
+#[derive(Copy, Clone, Debug)]
+pub struct FaultLogRecord {
+    pub reason: FaultReason,
+    pub pc: usize,
+    pub state: State,
+    pub restart_count: usize,
+    name: [u8; NAME_LEN],
+    name_len: usize,
+}
+
+impl FaultLogRecord {
+    fn from_fault(fault: &FaultRecord) -> FaultLogRecord {
+        // Truncate the name at a character boundary.
+        let mut name_len = cmp::min(fault.process_name.len(), NAME_LEN);
+        while !fault.process_name.is_char_boundary(name_len) {
+            name_len -= 1;
+        }
+        let mut name = [0; NAME_LEN];
+        name[..name_len].copy_from_slice(&fault.process_name.as_bytes()[..name_len]);
+        FaultLogRecord {
+            reason: fault.reason,
+            pc: fault.pc,
+            state: fault.state,
+            restart_count: fault.restart_count,
+            name: name,
+            name_len: name_len,
+        }
+    }
+
+    /// The name of the application, truncated to 16 bytes.
+    pub fn process_name(&self) -> &str {
+        str::from_utf8(&self.name[..self.name_len]).unwrap_or("")
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0] = RECORD_VERSION;
+        buf[1] = match self.reason {
+            FaultReason::HardwareFault => 0,
+            FaultReason::StackOverflow => 1,
+            FaultReason::InvalidSyscall => 2,
+            FaultReason::ContextSwitchFailed => 3,
+            FaultReason::Forced => 4,
+            FaultReason::WatchdogTimeout => 5,
+        };
+        buf[2] = match self.state {
+            State::Running => 0,
+            State::Yielded => 1,
+            State::StoppedRunning => 2,
+            State::StoppedYielded => 3,
+            State::StoppedFaulted => 4,
+            State::Fault => 5,
+            State::Unstarted => 6,
+        };
+        buf[3] = self.name_len as u8;
+        buf[4..8].copy_from_slice(&(self.pc as u32).to_le_bytes());
+        buf[8..12].copy_from_slice(&(self.restart_count as u32).to_le_bytes());
+        buf[12..RECORD_SIZE].copy_from_slice(&self.name);
+    }
+
+    fn decode(buf: &[u8]) -> Option<FaultLogRecord> {
+        if buf.len() < RECORD_SIZE || buf[0] != RECORD_VERSION {
+            return None;
+        }
+        let reason = match buf[1] {
+            0 => FaultReason::HardwareFault,
+            1 => FaultReason::StackOverflow,
+            2 => FaultReason::InvalidSyscall,
+            3 => FaultReason::ContextSwitchFailed,
+            4 => FaultReason::Forced,
+            5 => FaultReason::WatchdogTimeout,
+            _ => return None,
+        };
+        let state = match buf[2] {
+            0 => State::Running,
+            1 => State::Yielded,
+            2 => State::StoppedRunning,
+            3 => State::StoppedYielded,
+            4 => State::StoppedFaulted,
+            5 => State::Fault,
+            6 => State::Unstarted,
+            _ => return None,
+        };
+        let mut name = [0; NAME_LEN];
+        name.copy_from_slice(&buf[12..RECORD_SIZE]);
+        Some(FaultLogRecord {
+            reason: reason,
+            pc: u32::from_le_bytes(buf[4..8].try_into().ok()?) as usize,
+            state: state,
+            restart_count: u32::from_le_bytes(buf[8..12].try_into().ok()?) as usize,
+            name: name,
+            name_len: cmp::min(buf[3] as usize, NAME_LEN),
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum LogState {
+    /// Records have not been read from the log yet.
+    Unloaded,
+    /// Reading records from the log.
+    Loading,
+    Idle,
+    /// Appending a record to the log.
+    Appending,
+    /// Making an appended record persistent.
+    Syncing,
+}
+
+pub struct ProcessFaultLog<'a, L: LogRead<'a> + LogWrite<'a>> {
+    log: &'a L,
+    /// Policy that decides whether processes are restarted.
+    policy: &'a dyn ProcessRestartPolicy,
+    /// The most recent records, oldest first starting at `next`.
+    records: [Cell<Option<FaultLogRecord>>; MAX_FAULT_RECORDS],
+    /// Index in `records` of the next record.
+    next: Cell<usize>,
+    /// Number of the most recent records that are not in the log yet.
+    unsaved: Cell<usize>,
+    state: Cell<LogState>,
+    buffer: TakeCell<'static, [u8]>,
+    /// Name of a process whose fault panics the kernel once the record is
+    /// in the log.
+    panic_pending: OptionalCell<&'static str>,
+}
+
+impl<'a, L: LogRead<'a> + LogWrite<'a>> ProcessFaultLog<'a, L> {
+    pub fn new(
+        log: &'a L,
+        policy: &'a dyn ProcessRestartPolicy,
+        buffer: &'static mut [u8],
+    ) -> ProcessFaultLog<'a, L> {
+        ProcessFaultLog {
+            log: log,
+            policy: policy,
+            records: Default::default(),
+            next: Cell::new(0),
+            unsaved: Cell::new(0),
+            state: Cell::new(LogState::Unloaded),
+            buffer: TakeCell::new(buffer),
+            panic_pending: OptionalCell::empty(),
+        }
+    }
+
+    /// Read the records saved before the board booted from the log. Boards
+    /// call this once while booting. Faults are only saved to the log once
+    /// loading completes.
+    pub fn load(&self) -> ReturnCode {
+        if self.state.get() != LogState::Unloaded {
+            return ReturnCode::EALREADY;
+        }
+        self.state.set(LogState::Loading);
+        self.read_next();
+        ReturnCode::SUCCESS
+    }
+
+    /// Call `f` on each record, from the oldest to the most recent.
+    pub fn for_each_record<F: FnMut(&FaultLogRecord)>(&self, mut f: F) {
+        for i in 0..MAX_FAULT_RECORDS {
+            let index = (self.next.get() + i) % MAX_FAULT_RECORDS;
+            if let Some(record) = self.records[index].get() {
+                f(&record);
+            }
+        }
+    }
+
+    fn push(&self, record: FaultLogRecord) {
+        let index = self.next.get();
+        self.records[index].set(Some(record));
+        self.next.set((index + 1) % MAX_FAULT_RECORDS);
+    }
+
+    fn read_next(&self) {
+        let result = self
+            .buffer
+            .take()
+            .map_or(Err(ReturnCode::ERESERVE), |buffer| {
+                let length = buffer.len();
+                self.log.read(buffer, length).map_err(|(error, buffer)| {
+                    buffer.map(|buffer| self.buffer.replace(buffer));
+                    error
+                })
+            });
+        // Reading fails once the end of the log has been reached.
+        if result.is_err() {
+            self.state.set(LogState::Idle);
+            self.save_next();
+        }
+    }
+
+    /// Append the oldest unsaved record to the log.
+    fn save_next(&self) {
+        let unsaved = self.unsaved.get();
+        if unsaved == 0 || self.state.get() != LogState::Idle {
+            return;
+        }
+        let index = (self.next.get() + MAX_FAULT_RECORDS - unsaved) % MAX_FAULT_RECORDS;
+        let record = match self.records[index].get() {
+            Some(record) => record,
+            None => return,
+        };
+        self.buffer.take().map(|buffer| {
+            record.encode(buffer);
+            match self.log.append(buffer, RECORD_SIZE) {
+                Ok(()) => self.state.set(LogState::Appending),
+                Err((_, buffer)) => {
+                    buffer.map(|buffer| self.buffer.replace(buffer));
+                    // Give up on this record.
+                    self.unsaved.set(unsaved - 1);
+                    self.panic_if_saved();
+                }
+            }
+        });
+    }
+
+    /// Make a panic that the restart policy asked for, once no records are
+    /// waiting to be written.
+    fn panic_if_saved(&self) {
+        if self.unsaved.get() == 0 {
+            self.panic_pending
+                .take()
+                .map(|name| panic!("Process {} had a fault", name));
+        }
+    }
+}
+
+impl<'a, L: LogRead<'a> + LogWrite<'a>> ProcessRestartPolicy for ProcessFaultLog<'a, L> {
+    fn should_restart(&self, process: &dyn ProcessType) -> bool {
+        self.policy.should_restart(process)
+    }
+
+    fn fault_action(&self, process: &dyn ProcessType, fault: &FaultRecord) -> FaultAction {
+        self.push(FaultLogRecord::from_fault(fault));
+        self.unsaved
+            .set(cmp::min(self.unsaved.get() + 1, MAX_FAULT_RECORDS));
+        let action = self.policy.fault_action(process, fault);
+        if action == FaultAction::Panic && self.state.get() != LogState::Unloaded {
+            self.panic_pending.set(fault.process_name);
+            self.save_next();
+            self.panic_if_saved();
+            return FaultAction::Stop;
+        }
+        self.save_next();
+        action
+    }
+}
+
+impl<'a, L: LogRead<'a> + LogWrite<'a>> LogReadClient for ProcessFaultLog<'a, L> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, error: ReturnCode) {
+        // Older records must not be placed after faults that happened while
+        // loading, so loading stops at the first such fault.
+        if error == ReturnCode::SUCCESS && self.unsaved.get() == 0 {
+            if let Some(record) = FaultLogRecord::decode(&buffer[..length]) {
+                self.push(record);
+            }
+        }
+        self.buffer.replace(buffer);
+        if self.state.get() == LogState::Loading {
+            if self.unsaved.get() == 0 {
+                self.read_next();
+            } else {
+                self.state.set(LogState::Idle);
+                self.save_next();
+            }
+        }
+    }
+
+    fn seek_done(&self, _error: ReturnCode) {}
+}
+
+impl<'a, L: LogRead<'a> + LogWrite<'a>> LogWriteClient for ProcessFaultLog<'a, L> {
+    fn append_done(
+        &self,
+        buffer: &'static mut [u8],
+        _length: usize,
+        _records_lost: bool,
+        error: ReturnCode,
+    ) {
+        self.buffer.replace(buffer);
+        if error == ReturnCode::SUCCESS && self.log.sync() == ReturnCode::SUCCESS {
+            self.state.set(LogState::Syncing);
+        } else {
+            self.state.set(LogState::Idle);
+            self.unsaved.set(self.unsaved.get().saturating_sub(1));
+            self.panic_if_saved();
+            self.save_next();
+        }
+    }
+
+    fn sync_done(&self, _error: ReturnCode) {
+        self.state.set(LogState::Idle);
+        self.unsaved.set(self.unsaved.get().saturating_sub(1));
+        self.panic_if_saved();
+        self.save_next();
+    }
+
+    fn erase_done(&self, _error: ReturnCode) {}
+}
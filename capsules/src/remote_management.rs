@@ -0,0 +1,962 @@
+//! Binary protocol for inspecting and controlling processes over a UART.
+//!
+//! The process console is meant for people typing commands. This capsule
+//! offers the same operations, and some more, to programs such as test rigs
+//! and `tools/remote-management`, as framed binary requests and responses on a
+//! `hil::uart::UartData` device. This can be a UART of the chip, a
+//! `UartDevice` of a `MuxUart` that nobody else receives on, or the USB
+//! CDC-ACM serial port (`capsules::usb::cdc::CdcAcm`).
+//!
+//! Besides what the process console can do, the protocol reads the
+//! introspection counters of the kernel and of every process, lists the
+//! processes with their TBF metadata, and installs and erases applications.
+//! Applications are installed like with the `app_loader` driver: through a
+//! `DynamicProcessManagement` implementation and a `NonvolatileStorage`
+//! covering application flash, which the board passes with
+//! `set_process_manager()`.
+//!
+//! Framing
+//! -------
+//!
+//! Every request and response is a frame: the payload followed by its
+//! CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xFFFF, see
+//! `crc16()`), encoded with Consistent Overhead Byte Stuffing (COBS, see
+//! `cobs_encode()`) and followed by a zero byte. Encoded frames contain no
+//! other zero bytes, so after lost bytes the next frame starts after the next
+//! zero. Frames that cannot be decoded, that are longer than `MAX_FRAME`, or
+//! whose CRC is wrong are dropped and counted, and the host notices the
+//! missing response. Payloads are at most `MAX_PAYLOAD` bytes long.
+//!
+//! All integers are little-endian. A request is
+//!
+//! ```text
+//! +---------------+--------------+-----------+
+//! | sequence (u8) | command (u8) | arguments |
+//! +---------------+--------------+-----------+
+//! ```
+//!
+//! and its response repeats the sequence number and the command with the top
+//! bit set, followed by the result, a `ReturnCode` converted to `isize`:
+//!
+//! ```text
+//! +---------------+---------------------+-------------+------+
+//! | sequence (u8) | command | 0x80 (u8) | result (i8) | data |
+//! +---------------+---------------------+-------------+------+
+//! ```
+//!
+//! The data is only there if the result is 0 (`SUCCESS`). Requests are
+//! handled one at a time, so a host must wait for the response to a request
+//! before it sends the next one.
+//!
+//! Commands
+//! --------
+//!
+//! Processes are selected by their package name, which is the rest of the
+//! request (`name` below). Unlike process identifiers, names stay the same
+//! when processes restart. Requests for a process that does not exist fail
+//! with `EINVAL`.
+//!
+//! | Command              | Arguments         | Data of the response                                   |
+//! |----------------------|-------------------|--------------------------------------------------------|
+//! | `0x01` Info          |                   | protocol version (u8), `MAX_PAYLOAD` (u16), dropped frames (u32) |
+//! | `0x02` List          | index (u8)        | the process at `index`, see below                      |
+//! | `0x03` Start         | name              |                                                        |
+//! | `0x04` Stop          | name              |                                                        |
+//! | `0x05` Fault         | name              |                                                        |
+//! | `0x06` Restart       | name              |                                                        |
+//! | `0x07` Terminate     | name              |                                                        |
+//! | `0x08` Kernel        |                   | uptime, time asleep and time in the kernel without running processes in µs (u64 each, `u64::MAX` if the board gave the kernel no clock), loaded and active processes, timeslice expirations and deadline misses (u32 each) |
+//! | `0x09` Counters      | name              | syscalls, dropped callbacks, restarts, timeslice expirations and deadline misses (u32 each), CPU time in µs (u64), stack and heap high-water marks (u32 each, `u32::MAX` if not known), grants used and grants in total (u16 each), app, grant and total memory (u32 each) |
+//! | `0x0A` Syscalls      | name              | syscalls to other drivers (u32), then driver number and syscall count (u32 each) of every driver in the syscall histogram |
+//! | `0x0B` State         | offset (u32), name | the memory map, registers and MPU configuration the process console's `process` command prints, from `offset` on; a chunk shorter than `MAX_PAYLOAD - 3` bytes is the last |
+//! | `0x0C` Kernel memory |                   | start and end of the kernel's stack, text, relocated data and BSS (u32 each), or `ENOSUPPORT` |
+//! | `0x10` Install       | length (u32)      | address of the flash for a new application of `length` bytes (u32) |
+//! | `0x11` Write         | offset (u32), data | writes `data` at `offset` in the new application      |
+//! | `0x12` Load          |                   | creates the process of the new application, and returns its identifier (u32) |
+//! | `0x13` Abort         |                   | stops installing the new application                   |
+//! | `0x14` Erase         | name              | terminates the process and marks its flash unused      |
+//! | `0x1F` Reboot        |                   | resets the board after sending the response, or fails with `ENOSUPPORT` |
+//!
+//! `List` fails with `EINVAL` if there are not more than `index` processes.
+//! Otherwise it returns the identifier of the process (u32), its state (u8,
+//! in the order of `kernel::procs::State`, starting with `Running` as 0), its
+//! number of restarts (u32), the address and length of its TBF in flash (u32
+//! each), the length of the TBF header and the protected region after it
+//! (u32), the address and length of its memory (u32 each), the number of
+//! writeable flash regions in its TBF header (u8), and then its name.
+//!
+//! Install, Write and Load follow the steps of the `app_loader` driver. They
+//! fail with `ENOSUPPORT` if the board did not call `set_process_manager()`.
+//! As with the driver, the process manager only creates the process if the
+//! board's credentials checker accepts the new application.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::{capabilities, hil, static_init};
+//! # use capsules::remote_management::{self, RemoteManagement};
+//!
+//! pub struct Capability;
+//! unsafe impl capabilities::ProcessManagementCapability for Capability {}
+//!
+//! let remote_management = static_init!(
+//!     RemoteManagement<'static, Capability>,
+//!     RemoteManagement::new(
+//!         cdc,
+//!         &mut remote_management::TX_BUF,
+//!         &mut remote_management::RX_BUF,
+//!         &mut remote_management::FRAME_BUF,
+//!         board_kernel,
+//!         None,
+//!         Some(reset),
+//!         Capability,
+//!     )
+//! );
+//! hil::uart::Transmit::set_transmit_client(cdc, remote_management);
+//! hil::uart::Receive::set_receive_client(cdc, remote_management);
+//!
+//! // Optionally, to install and erase applications:
+//! remote_management.set_process_manager(
+//!     process_manager,
+//!     nv_to_page,
+//!     &mut remote_management::FLASH_BUF,
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_page, remote_management);
+//!
+//! remote_management.start();
+//! ```
+//!
+//! The two options are the addresses of the kernel's sections and the
+//! function that resets the board, as for the process console.
+
+use core::cell::Cell;
+use core::convert::TryInto;
+use enum_primitive::cast::FromPrimitive;
+use enum_primitive::enum_from_primitive;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::hil::uart;
+use kernel::introspection::KernelInfo;
+use kernel::procs::{AppFlashRegion, DynamicProcessManagement, ProcessType};
+use kernel::{AppId, Kernel, ReturnCode};
+
+use crate::process_console::{BufferWriter, KernelAddresses};
+
+/// Version of the protocol, returned by the `Info` command.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Maximum length of the payload of a frame.
+pub const MAX_PAYLOAD: usize = 250;
+
+/// Maximum length of an encoded frame, including the CRC and the zero byte
+/// at its end.
+pub const MAX_FRAME: usize = 256;
+
+/// Bit set in the command of a response.
+pub const RESPONSE: u8 = 0x80;
+
+/// Length of the sequence number, command and result of a response.
+const RESPONSE_HEADER_LENGTH: usize = 3;
+
+pub static mut TX_BUF: [u8; MAX_FRAME] = [0; MAX_FRAME];
+pub static mut RX_BUF: [u8; 1] = [0; 1];
+pub static mut FRAME_BUF: [u8; MAX_FRAME] = [0; MAX_FRAME];
+/// Buffer for writing new applications to flash.
+pub static mut FLASH_BUF: [u8; MAX_PAYLOAD] = [0; MAX_PAYLOAD];
+
+enum_from_primitive! {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Info = 0x01,
+    List = 0x02,
+    Start = 0x03,
+    Stop = 0x04,
+    Fault = 0x05,
+    Restart = 0x06,
+    Terminate = 0x07,
+    Kernel = 0x08,
+    Counters = 0x09,
+    Syscalls = 0x0A,
+    State = 0x0B,
+    KernelMemory = 0x0C,
+    Install = 0x10,
+    Write = 0x11,
+    Load = 0x12,
+    Abort = 0x13,
+    Erase = 0x14,
+    Reboot = 0x1F,
+}
+}
+
+/// CRC-16/CCITT-FALSE of `data`.
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |crc, &byte| {
+        let mut crc = crc ^ (u16::from(byte) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// COBS-encode `data` into `output`, without the zero byte that ends a
+/// frame. Returns the length of the encoded data, or `None` if `output` is
+/// too short.
+pub fn cobs_encode(data: &[u8], output: &mut [u8]) -> Option<usize> {
+    // Each block starts with one more than the number of non-zero bytes that
+    // follow it, and stands for these bytes and a zero, unless the block is
+    // full or ends the data.
+    let mut code_index = 0;
+    let mut position = 1;
+    let mut code = 1;
+    for &byte in data {
+        if byte != 0 {
+            *output.get_mut(position)? = byte;
+            position += 1;
+            code += 1;
+        }
+        if byte == 0 || code == 0xff {
+            *output.get_mut(code_index)? = code;
+            code_index = position;
+            position += 1;
+            code = 1;
+        }
+    }
+    *output.get_mut(code_index)? = code;
+    Some(position)
+}
+
+/// Decode the COBS-encoded `frame`, without the zero byte at its end, in
+/// place. Returns the length of the decoded data, or `None` if the frame is
+/// invalid.
+pub fn cobs_decode(frame: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < frame.len() {
+        let code = frame[read] as usize;
+        if code == 0 || read + code > frame.len() {
+            return None;
+        }
+        frame.copy_within(read + 1..read + code, write);
+        read += code;
+        write += code - 1;
+        if code < 0xff && read < frame.len() {
+            frame[write] = 0;
+            write += 1;
+        }
+    }
+    Some(write)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Flash has been chosen for a new application, which the host is
+    /// writing.
+    Setup,
+    /// Writing a chunk of the new application.
+    Write,
+    /// Writing a padding entry next to the new application.
+    Padding,
+    /// Marking the flash of an erased application as padding.
+    Unload,
+}
+
+/// What `List` returns about a process.
+#[derive(Clone, Copy)]
+struct ProcessEntry {
+    id: usize,
+    state: kernel::procs::State,
+    restarts: usize,
+    flash_start: usize,
+    flash_length: usize,
+    header_length: usize,
+    memory_start: usize,
+    memory_length: usize,
+    writeable_flash_regions: usize,
+    name: &'static str,
+}
+
+impl ProcessEntry {
+    fn new(process: &dyn ProcessType) -> ProcessEntry {
+        let flash_start = process.flash_start() as usize;
+        let memory_start = process.mem_start() as usize;
+        ProcessEntry {
+            id: process.appid().id(),
+            state: process.get_state(),
+            restarts: process.get_restart_count(),
+            flash_start: flash_start,
+            flash_length: process.flash_end() as usize - flash_start,
+            header_length: process.flash_non_protected_start() as usize - flash_start,
+            memory_start: memory_start,
+            memory_length: process.mem_end() as usize - memory_start,
+            writeable_flash_regions: process.number_writeable_flash_regions(),
+            name: process.get_process_name(),
+        }
+    }
+}
+
+pub struct RemoteManagement<'a, C: ProcessManagementCapability> {
+    uart: &'a dyn uart::UartData<'a>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    /// The frame being received. Requests are decoded in place, and replaced
+    /// by their response.
+    frame_buffer: TakeCell<'static, [u8]>,
+    frame_length: Cell<usize>,
+    /// Whether bytes of the frame being received were dropped because the
+    /// frame is too long.
+    frame_overflow: Cell<bool>,
+    dropped_frames: Cell<u32>,
+    /// Sequence number and command of the request being handled.
+    request: Cell<(u8, u8)>,
+    /// Length of a response in the frame buffer that waits for the transmit
+    /// buffer.
+    pending_response: Cell<Option<usize>>,
+    /// Whether to reset the board once the response is sent.
+    reboot: Cell<bool>,
+    manager: OptionalCell<&'a dyn DynamicProcessManagement>,
+    storage: OptionalCell<&'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>>,
+    flash_buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    /// Flash of the application being installed. Padding entries are removed
+    /// once they have been written.
+    region: Cell<Option<AppFlashRegion>>,
+    kernel: &'static Kernel,
+    kernel_addresses: Option<KernelAddresses>,
+    reset_function: Option<fn() -> !>,
+    capability: C,
+}
+
+impl<'a, C: ProcessManagementCapability> RemoteManagement<'a, C> {
+    pub fn new(
+        uart: &'a dyn uart::UartData<'a>,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        frame_buffer: &'static mut [u8],
+        kernel: &'static Kernel,
+        kernel_addresses: Option<KernelAddresses>,
+        reset_function: Option<fn() -> !>,
+        capability: C,
+    ) -> RemoteManagement<'a, C> {
+        RemoteManagement {
+            uart: uart,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            frame_buffer: TakeCell::new(frame_buffer),
+            frame_length: Cell::new(0),
+            frame_overflow: Cell::new(false),
+            dropped_frames: Cell::new(0),
+            request: Cell::new((0, 0)),
+            pending_response: Cell::new(None),
+            reboot: Cell::new(false),
+            manager: OptionalCell::empty(),
+            storage: OptionalCell::empty(),
+            flash_buffer: TakeCell::empty(),
+            state: Cell::new(State::Idle),
+            region: Cell::new(None),
+            kernel: kernel,
+            kernel_addresses: kernel_addresses,
+            reset_function: reset_function,
+            capability: capability,
+        }
+    }
+
+    /// Allow installing and erasing applications. `buffer` must be at least
+    /// `MAX_PAYLOAD` bytes long.
+    pub fn set_process_manager(
+        &self,
+        manager: &'a dyn DynamicProcessManagement,
+        storage: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>,
+        buffer: &'static mut [u8],
+    ) {
+        self.manager.set(manager);
+        self.storage.set(storage);
+        self.flash_buffer.replace(buffer);
+    }
+
+    /// Start receiving requests.
+    pub fn start(&self) -> ReturnCode {
+        self.receive();
+        ReturnCode::SUCCESS
+    }
+
+    fn receive(&self) {
+        self.rx_buffer.take().map(|buffer| {
+            let (result, buffer) = self.uart.receive_buffer(buffer, 1);
+            if result != ReturnCode::SUCCESS {
+                buffer.map(|buffer| self.rx_buffer.replace(buffer));
+            }
+        });
+    }
+
+    /// Add a received byte to the frame. Returns whether to receive the next
+    /// byte, which waits until the response to a complete request is sent.
+    fn byte_received(&self, byte: u8) -> bool {
+        if byte != 0 {
+            let position = self.frame_length.get();
+            let stored = self.frame_buffer.map_or(false, |frame| {
+                frame.get_mut(position).map_or(false, |entry| {
+                    *entry = byte;
+                    true
+                })
+            });
+            if stored {
+                self.frame_length.set(position + 1);
+            } else {
+                self.frame_overflow.set(true);
+            }
+            return true;
+        }
+
+        let length = self.frame_length.replace(0);
+        if self.frame_overflow.replace(false) {
+            self.drop_frame();
+            return true;
+        }
+        if length == 0 {
+            // Hosts may send zero bytes to end a partial frame.
+            return true;
+        }
+        self.frame_buffer.map_or(true, |frame| {
+            let payload_length = match cobs_decode(&mut frame[..length]) {
+                Some(decoded) if decoded >= 4 => decoded - 2,
+                _ => {
+                    self.drop_frame();
+                    return true;
+                }
+            };
+            let crc = u16::from_le_bytes([frame[payload_length], frame[payload_length + 1]]);
+            if crc != crc16(&frame[..payload_length]) {
+                self.drop_frame();
+                return true;
+            }
+
+            self.request.set((frame[0], frame[1]));
+            match self.execute(frame, payload_length) {
+                Some((result, data_length)) => self.respond(frame, result, data_length),
+                // The response is sent once the operation is complete.
+                None => false,
+            }
+        })
+    }
+
+    fn drop_frame(&self) {
+        self.dropped_frames
+            .set(self.dropped_frames.get().wrapping_add(1));
+    }
+
+    /// Run the request in `frame`, and write the data of its response after
+    /// the response header. Returns the result and the length of the data,
+    /// or `None` if the response is sent once a flash operation completes.
+    fn execute(&self, frame: &mut [u8], length: usize) -> Option<(ReturnCode, usize)> {
+        let command = match Command::from_u8(frame[1]) {
+            Some(command) => command,
+            None => return Some((ReturnCode::ENOSUPPORT, 0)),
+        };
+        let info = KernelInfo::new(self.kernel);
+        // Arguments must be read before the data of the response overwrites
+        // them.
+        let arguments = &frame[2..length];
+        let result = match command {
+            Command::Info => {
+                let mut data = response_data(frame);
+                data.write_bytes(&[PROTOCOL_VERSION]);
+                data.write_bytes(&(MAX_PAYLOAD as u16).to_le_bytes());
+                data.write_bytes(&self.dropped_frames.get().to_le_bytes());
+                Ok(data.len())
+            }
+            Command::List => match arguments.get(0) {
+                Some(&index) => match self.process_at(index as usize) {
+                    Some(entry) => {
+                        let mut data = response_data(frame);
+                        data.write_bytes(&(entry.id as u32).to_le_bytes());
+                        data.write_bytes(&[entry.state as u8]);
+                        data.write_bytes(&(entry.restarts as u32).to_le_bytes());
+                        data.write_bytes(&(entry.flash_start as u32).to_le_bytes());
+                        data.write_bytes(&(entry.flash_length as u32).to_le_bytes());
+                        data.write_bytes(&(entry.header_length as u32).to_le_bytes());
+                        data.write_bytes(&(entry.memory_start as u32).to_le_bytes());
+                        data.write_bytes(&(entry.memory_length as u32).to_le_bytes());
+                        data.write_bytes(&[entry.writeable_flash_regions as u8]);
+                        data.write_bytes(entry.name.as_bytes());
+                        Ok(data.len())
+                    }
+                    None => Err(ReturnCode::EINVAL),
+                },
+                None => Err(ReturnCode::EINVAL),
+            },
+            Command::Start => self.with_process(arguments, |process| {
+                process.resume();
+                ReturnCode::SUCCESS
+            }),
+            Command::Stop => self.with_process(arguments, |process| {
+                process.stop();
+                ReturnCode::SUCCESS
+            }),
+            Command::Fault => self.with_process(arguments, |process| {
+                process.set_fault_state(kernel::procs::FaultReason::Forced);
+                ReturnCode::SUCCESS
+            }),
+            Command::Restart => self.with_process(arguments, |process| {
+                if process.force_restart() {
+                    ReturnCode::SUCCESS
+                } else {
+                    ReturnCode::FAIL
+                }
+            }),
+            Command::Terminate => self.with_process(arguments, |process| {
+                process.terminate();
+                ReturnCode::SUCCESS
+            }),
+            Command::Kernel => {
+                let capability = &self.capability;
+                let mut data = response_data(frame);
+                for time in [
+                    info.uptime_us(capability),
+                    info.sleep_time_us(capability),
+                    info.kernel_time_us(capability),
+                ]
+                .iter()
+                {
+                    data.write_bytes(&time.unwrap_or(u64::MAX).to_le_bytes());
+                }
+                for count in [
+                    info.number_loaded_processes(capability),
+                    info.number_active_processes(capability),
+                    info.timeslice_expirations(capability),
+                    info.deadline_misses(capability),
+                ]
+                .iter()
+                {
+                    data.write_bytes(&(*count as u32).to_le_bytes());
+                }
+                Ok(data.len())
+            }
+            Command::Counters => self.find_process(arguments).map(|app| {
+                let capability = &self.capability;
+                let mut data = response_data(frame);
+                for count in [
+                    info.number_app_syscalls(app, capability),
+                    info.number_app_dropped_callbacks(app, capability),
+                    info.number_app_restarts(app, capability),
+                    info.number_app_timeslice_expirations(app, capability),
+                    info.number_app_deadline_misses(app, capability),
+                ]
+                .iter()
+                {
+                    data.write_bytes(&(*count as u32).to_le_bytes());
+                }
+                data.write_bytes(&info.app_cpu_time_us(app, capability).to_le_bytes());
+                let (stack, heap) = info.app_memory_high_water(app, capability);
+                for high_water in [stack, heap].iter() {
+                    let high_water = high_water.map_or(u32::MAX, |size| size as u32);
+                    data.write_bytes(&high_water.to_le_bytes());
+                }
+                let (used, total) = info.number_app_grant_uses(app, capability);
+                data.write_bytes(&(used as u16).to_le_bytes());
+                data.write_bytes(&(total as u16).to_le_bytes());
+                let (app_memory, grant_memory, total_memory) =
+                    info.app_memory_usage(app, capability);
+                for size in [app_memory, grant_memory, total_memory].iter() {
+                    data.write_bytes(&(*size as u32).to_le_bytes());
+                }
+                data.len()
+            }),
+            Command::Syscalls => self.find_process(arguments).map(|app| {
+                let histogram = info.app_syscall_histogram(app, &self.capability);
+                let mut data = response_data(frame);
+                data.write_bytes(&(histogram.other_drivers as u32).to_le_bytes());
+                for (driver_number, count) in histogram.iter() {
+                    data.write_bytes(&(driver_number as u32).to_le_bytes());
+                    data.write_bytes(&(count as u32).to_le_bytes());
+                }
+                data.len()
+            }),
+            Command::State => match u32_argument(arguments, 0) {
+                Some(offset) => self.find_process(&arguments[4..]).map(|app| {
+                    let mut data = BufferWriter::new(
+                        &mut frame[RESPONSE_HEADER_LENGTH..MAX_PAYLOAD],
+                        offset as usize,
+                    );
+                    info.print_app_state(app, &mut data, &self.capability);
+                    data.len()
+                }),
+                None => Err(ReturnCode::EINVAL),
+            },
+            Command::KernelMemory => match self.kernel_addresses {
+                Some(addresses) => {
+                    let mut data = response_data(frame);
+                    for address in [
+                        addresses.stack_start,
+                        addresses.stack_end,
+                        addresses.text_start,
+                        addresses.text_end,
+                        addresses.relocate_start,
+                        addresses.relocate_end,
+                        addresses.bss_start,
+                        addresses.bss_end,
+                    ]
+                    .iter()
+                    {
+                        data.write_bytes(&(*address as u32).to_le_bytes());
+                    }
+                    Ok(data.len())
+                }
+                None => Err(ReturnCode::ENOSUPPORT),
+            },
+            Command::Install | Command::Write | Command::Load | Command::Abort | Command::Erase
+                if self.manager.is_none() || self.storage.is_none() =>
+            {
+                Err(ReturnCode::ENOSUPPORT)
+            }
+            Command::Install => match (u32_argument(arguments, 0), self.state.get()) {
+                (Some(length), State::Idle) | (Some(length), State::Setup) => self
+                    .manager
+                    .map_or(Err(ReturnCode::ENOSUPPORT), |manager| {
+                        manager.find_flash_region(length as usize)
+                    })
+                    .map(|region| {
+                        // Installing another application abandons the
+                        // previous one.
+                        self.region.set(Some(region));
+                        self.state.set(State::Setup);
+                        let mut data = response_data(frame);
+                        data.write_bytes(&(region.app.0 as u32).to_le_bytes());
+                        data.len()
+                    }),
+                _ => Err(ReturnCode::EINVAL),
+            },
+            Command::Write => match (u32_argument(arguments, 0), self.state.get()) {
+                (Some(offset), State::Setup) => {
+                    match self.write(offset as usize, &arguments[4..]) {
+                        ReturnCode::SUCCESS => return None,
+                        error => Err(error),
+                    }
+                }
+                _ => Err(ReturnCode::EINVAL),
+            },
+            Command::Load if self.state.get() == State::Setup => match self.continue_load() {
+                Ok(None) => return None,
+                Ok(Some(identifier)) => {
+                    let mut data = response_data(frame);
+                    data.write_bytes(&(identifier as u32).to_le_bytes());
+                    Ok(data.len())
+                }
+                Err(error) => Err(error),
+            },
+            Command::Abort if self.state.get() == State::Setup => {
+                self.state.set(State::Idle);
+                self.region.set(None);
+                Ok(0)
+            }
+            Command::Erase if self.state.get() == State::Idle => {
+                match self
+                    .find_process(arguments)
+                    .map(|app| self.unload(app.id()))
+                {
+                    Ok(ReturnCode::SUCCESS) => return None,
+                    Ok(error) | Err(error) => Err(error),
+                }
+            }
+            Command::Load | Command::Abort | Command::Erase => Err(ReturnCode::EINVAL),
+            Command::Reboot => match self.reset_function {
+                Some(_) => {
+                    self.reboot.set(true);
+                    Ok(0)
+                }
+                None => Err(ReturnCode::ENOSUPPORT),
+            },
+        };
+        Some(match result {
+            Ok(data_length) => (ReturnCode::SUCCESS, data_length),
+            Err(error) => (error, 0),
+        })
+    }
+
+    /// The `index`th process.
+    fn process_at(&self, index: usize) -> Option<ProcessEntry> {
+        let entry = Cell::new(None);
+        let count = Cell::new(0);
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if count.get() == index {
+                    entry.set(Some(ProcessEntry::new(process)));
+                }
+                count.set(count.get() + 1);
+            });
+        entry.get()
+    }
+
+    /// Call `f` with the process named `name`.
+    fn with_process<F: Fn(&dyn ProcessType) -> ReturnCode>(
+        &self,
+        name: &[u8],
+        f: F,
+    ) -> Result<usize, ReturnCode> {
+        let result = Cell::new(ReturnCode::EINVAL);
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.get_process_name().as_bytes() == name {
+                    result.set(f(process));
+                }
+            });
+        match result.get() {
+            ReturnCode::SUCCESS => Ok(0),
+            error => Err(error),
+        }
+    }
+
+    fn find_process(&self, name: &[u8]) -> Result<AppId, ReturnCode> {
+        let app = Cell::new(None);
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.get_process_name().as_bytes() == name {
+                    app.set(Some(process.appid()));
+                }
+            });
+        app.get().ok_or(ReturnCode::EINVAL)
+    }
+
+    /// Write `data` at `offset` in the flash of the new application.
+    fn write(&self, offset: usize, data: &[u8]) -> ReturnCode {
+        let (app_start, app_length) = match self.region.get() {
+            Some(region) => region.app,
+            None => return ReturnCode::EINVAL,
+        };
+        if data.is_empty()
+            || offset
+                .checked_add(data.len())
+                .map_or(true, |end| end > app_length)
+        {
+            return ReturnCode::EINVAL;
+        }
+
+        self.storage.map_or(ReturnCode::ENOSUPPORT, |storage| {
+            self.flash_buffer
+                .take()
+                .map_or(ReturnCode::EBUSY, |buffer| {
+                    if data.len() > buffer.len() {
+                        self.flash_buffer.replace(buffer);
+                        return ReturnCode::ESIZE;
+                    }
+                    buffer[..data.len()].copy_from_slice(data);
+                    let result = storage.write(buffer, app_start + offset, data.len());
+                    if result == ReturnCode::SUCCESS {
+                        self.state.set(State::Write);
+                    }
+                    result
+                })
+        })
+    }
+
+    /// Write a padding header at `address` for `length` bytes of unused flash.
+    fn write_padding(&self, address: usize, length: usize) -> ReturnCode {
+        self.storage.map_or(ReturnCode::ENOSUPPORT, |storage| {
+            self.flash_buffer
+                .take()
+                .map_or(ReturnCode::EBUSY, |buffer| {
+                    let header = kernel::procs::padding_header(length);
+                    buffer[..header.len()].copy_from_slice(&header);
+                    storage.write(buffer, address, header.len())
+                })
+        })
+    }
+
+    /// Write the next padding entry around the new application, or create the
+    /// process once all have been written. Returns the identifier of the new
+    /// process, or `None` while padding is being written.
+    fn continue_load(&self) -> Result<Option<usize>, ReturnCode> {
+        let mut region = self.region.get().ok_or(ReturnCode::EINVAL)?;
+        let padding = region
+            .padding_before
+            .take()
+            .or_else(|| region.padding_after.take());
+
+        match padding {
+            Some((address, length)) => match self.write_padding(address, length) {
+                ReturnCode::SUCCESS => {
+                    self.region.set(Some(region));
+                    self.state.set(State::Padding);
+                    Ok(None)
+                }
+                error => {
+                    self.state.set(State::Idle);
+                    self.region.set(None);
+                    Err(error)
+                }
+            },
+            None => {
+                self.state.set(State::Idle);
+                self.region.set(None);
+                let result = self.manager.map_or(Err(ReturnCode::ENOSUPPORT), |manager| {
+                    match manager.load_process(region.app) {
+                        Ok(Some(appid)) => Ok(Some(appid.id())),
+                        Ok(None) => Err(ReturnCode::EINVAL),
+                        Err(_) => Err(ReturnCode::FAIL),
+                    }
+                });
+                result
+            }
+        }
+    }
+
+    /// Stop the process with the given identifier and mark its flash unused.
+    fn unload(&self, identifier: usize) -> ReturnCode {
+        let result = self.manager.map_or(Err(ReturnCode::ENOSUPPORT), |manager| {
+            manager.unload_process(identifier)
+        });
+        match result {
+            Ok(app_flash) => {
+                let result = self.write_padding(app_flash.as_ptr() as usize, app_flash.len());
+                if result == ReturnCode::SUCCESS {
+                    self.state.set(State::Unload);
+                }
+                result
+            }
+            Err(error) => error,
+        }
+    }
+
+    /// Send the response to the current request after a flash operation, and
+    /// receive the next request.
+    fn complete(&self, result: ReturnCode, data: &[u8]) {
+        let sent = self.frame_buffer.map_or(false, |frame| {
+            frame[RESPONSE_HEADER_LENGTH..RESPONSE_HEADER_LENGTH + data.len()]
+                .copy_from_slice(data);
+            self.respond(frame, result, data.len())
+        });
+        if sent {
+            self.receive();
+        }
+    }
+
+    /// Write the header of the response to the current request in front of
+    /// its data in `frame`, and send the response. Returns whether the
+    /// response was passed to the UART.
+    fn respond(&self, frame: &mut [u8], result: ReturnCode, data_length: usize) -> bool {
+        let (sequence, command) = self.request.get();
+        let data_length = if result == ReturnCode::SUCCESS {
+            data_length
+        } else {
+            0
+        };
+        frame[0] = sequence;
+        frame[1] = command | RESPONSE;
+        frame[2] = isize::from(result) as u8;
+        let length = RESPONSE_HEADER_LENGTH + data_length;
+        let crc = crc16(&frame[..length]);
+        frame[length..length + 2].copy_from_slice(&crc.to_le_bytes());
+        self.transmit(frame, length + 2)
+    }
+
+    /// Encode the first `length` bytes of `frame` and transmit them. Returns
+    /// false if the transmit buffer is in use, in which case the frame is
+    /// sent once the current transmission completes.
+    fn transmit(&self, frame: &[u8], length: usize) -> bool {
+        match self.tx_buffer.take() {
+            Some(buffer) => {
+                let limit = buffer.len().saturating_sub(1);
+                let encoded = cobs_encode(&frame[..length], &mut buffer[..limit]);
+                if let Some(encoded) = encoded {
+                    buffer[encoded] = 0;
+                    let (result, buffer) = self.uart.transmit_buffer(buffer, encoded + 1);
+                    if result != ReturnCode::SUCCESS {
+                        // The response is lost, and the host will retry.
+                        buffer.map(|buffer| self.tx_buffer.replace(buffer));
+                    }
+                } else {
+                    self.tx_buffer.replace(buffer);
+                }
+                true
+            }
+            None => {
+                self.pending_response.set(Some(length));
+                false
+            }
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> hil::nonvolatile_storage::NonvolatileStorageClient<'static>
+    for RemoteManagement<'a, C>
+{
+    fn read_done(&self, _buffer: &'static mut [u8], _length: usize) {}
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.flash_buffer.replace(buffer);
+
+        match self.state.get() {
+            State::Write => {
+                self.state.set(State::Setup);
+                self.complete(ReturnCode::SUCCESS, &[]);
+            }
+            State::Padding => match self.continue_load() {
+                Ok(None) => {}
+                Ok(Some(identifier)) => {
+                    self.complete(ReturnCode::SUCCESS, &(identifier as u32).to_le_bytes())
+                }
+                Err(error) => self.complete(error, &[]),
+            },
+            State::Unload => {
+                self.state.set(State::Idle);
+                self.complete(ReturnCode::SUCCESS, &[]);
+            }
+            State::Idle | State::Setup => {}
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> uart::TransmitClient for RemoteManagement<'a, C> {
+    fn transmitted_buffer(&self, buffer: &'static mut [u8], _tx_len: usize, _rcode: ReturnCode) {
+        self.tx_buffer.replace(buffer);
+
+        if self.reboot.get() {
+            self.reset_function.map(|reset| reset());
+        }
+        if let Some(length) = self.pending_response.take() {
+            self.frame_buffer.map(|frame| self.transmit(frame, length));
+            self.receive();
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for RemoteManagement<'a, C> {
+    fn received_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        rx_len: usize,
+        _rcode: ReturnCode,
+        error: uart::Error,
+    ) {
+        let byte = buffer[0];
+        self.rx_buffer.replace(buffer);
+        let next = if error == uart::Error::None && rx_len == 1 {
+            self.byte_received(byte)
+        } else {
+            true
+        };
+        if next {
+            self.receive();
+        }
+    }
+}
+
+/// Writer for the data of the response in `frame`.
+fn response_data(frame: &mut [u8]) -> BufferWriter {
+    BufferWriter::new(&mut frame[RESPONSE_HEADER_LENGTH..MAX_PAYLOAD], 0)
+}
+
+/// The little-endian `u32` at `index` in `arguments`.
+fn u32_argument(arguments: &[u8], index: usize) -> Option<u32> {
+    arguments
+        .get(index..index + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_le_bytes)
+}
@@ -0,0 +1,184 @@
+//! Process restart policy that waits longer after each fault before
+//! restarting a process.
+//!
+//! The first restart of a process happens after `base_ms` milliseconds, and
+//! the delay doubles with every restart up to `max_ms`. A process that keeps
+//! faulting therefore uses little CPU time, while a process that faults once
+//! after running for a long time is back quickly. After `max_restarts`
+//! restarts the process is no longer restarted.
+//!
+//! Processes waiting for their restart are stopped, and their grants are
+//! freed. Up to `MAX_PENDING_RESTARTS` processes can wait at the same time;
+//! further processes are restarted immediately.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::{capabilities, static_init};
+//!
+//! struct ProcessMgmtCap;
+//! unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+//! let restart_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let restart_policy = static_init!(
+//!     capsules::restart_backoff::RestartBackoff<
+//!         'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!         ProcessMgmtCap,
+//!     >,
+//!     capsules::restart_backoff::RestartBackoff::new(
+//!         board_kernel,
+//!         restart_alarm,
+//!         ProcessMgmtCap,
+//!         100,
+//!         60_000,
+//!         20
+//!     )
+//! );
+//! restart_alarm.set_alarm_client(restart_policy);
+//!
+//! kernel::procs::load_processes(
+//!     board_kernel,
+//!     chip,
+//!     app_flash,
+//!     app_memory,
+//!     kernel::procs::FaultResponse::Restart(restart_policy),
+//!     &process_mgmt_cap,
+//! );
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
+use kernel::procs::{FaultAction, FaultRecord, ProcessRestartPolicy, ProcessType};
+use kernel::{AppId, Kernel};
+
+/// Number of processes that can wait for a delayed restart at the same time.
+pub const MAX_PENDING_RESTARTS: usize = 8;
+
+pub struct RestartBackoff<'a, A: Alarm<'a>, C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    alarm: &'a A,
+    capability: C,
+    base_ms: u32,
+    max_ms: u32,
+    max_restarts: usize,
+    /// Processes waiting to be restarted, with the time their delay started
+    /// and its length.
+    pending: [Cell<Option<(AppId, A::Ticks, A::Ticks)>>; MAX_PENDING_RESTARTS],
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> RestartBackoff<'a, A, C> {
+    pub fn new(
+        kernel: &'static Kernel,
+        alarm: &'a A,
+        capability: C,
+        base_ms: u32,
+        max_ms: u32,
+        max_restarts: usize,
+    ) -> RestartBackoff<'a, A, C> {
+        RestartBackoff {
+            kernel: kernel,
+            alarm: alarm,
+            capability: capability,
+            base_ms: base_ms,
+            max_ms: max_ms,
+            max_restarts: max_restarts,
+            pending: Default::default(),
+        }
+    }
+
+    /// Delay before restarting a process that has been restarted
+    /// `restart_count` times.
+    fn delay_ms(&self, restart_count: usize) -> u32 {
+        if restart_count >= 32 {
+            return self.max_ms;
+        }
+        cmp::min(self.base_ms.saturating_mul(1 << restart_count), self.max_ms)
+    }
+
+    fn ticks_from_ms(ms: u32) -> A::Ticks {
+        A::Ticks::from((ms as u64 * A::Frequency::frequency() as u64 / 1000) as u32)
+    }
+
+    /// Arm the alarm for the next pending restart.
+    fn arm(&self) {
+        let now = self.alarm.now();
+        let next = self
+            .pending
+            .iter()
+            .filter_map(|pending| pending.get())
+            .map(|(_, start, delay)| {
+                let elapsed = now.wrapping_sub(start);
+                if elapsed >= delay {
+                    A::Ticks::from(0)
+                } else {
+                    delay.wrapping_sub(elapsed)
+                }
+            })
+            .min();
+        match next {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                self.alarm.disarm();
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessRestartPolicy
+    for RestartBackoff<'a, A, C>
+{
+    fn should_restart(&self, process: &dyn ProcessType) -> bool {
+        process.get_restart_count() < self.max_restarts
+    }
+
+    fn fault_action(&self, process: &dyn ProcessType, _fault: &FaultRecord) -> FaultAction {
+        if !self.should_restart(process) {
+            return FaultAction::Stop;
+        }
+        let delay_ms = self.delay_ms(process.get_restart_count());
+        if delay_ms == 0 {
+            return FaultAction::Restart;
+        }
+
+        match self.pending.iter().find(|pending| pending.get().is_none()) {
+            Some(slot) => {
+                slot.set(Some((
+                    process.appid(),
+                    self.alarm.now(),
+                    Self::ticks_from_ms(delay_ms),
+                )));
+                self.arm();
+                FaultAction::RestartLater
+            }
+            None => FaultAction::Restart,
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> time::AlarmClient
+    for RestartBackoff<'a, A, C>
+{
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        for slot in self.pending.iter() {
+            if let Some((appid, start, delay)) = slot.get() {
+                if now.wrapping_sub(start) >= delay {
+                    slot.set(None);
+                    self.kernel
+                        .process_each_capability(&self.capability, |process| {
+                            if process.appid() == appid {
+                                process.try_restart();
+                            }
+                        });
+                }
+            }
+        }
+        self.arm();
+    }
+}
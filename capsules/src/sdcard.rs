@@ -70,6 +70,7 @@ pub struct SDCard<'a, A: hil::time::Alarm<'a>> {
 
     is_initialized: Cell<bool>,
     card_type: Cell<SDCardType>,
+    total_blocks: Cell<u32>,
 
     detect_pin: Cell<Option<&'a dyn hil::gpio::InterruptPin<'a>>>,
 
@@ -79,6 +80,9 @@ pub struct SDCard<'a, A: hil::time::Alarm<'a>> {
     client: OptionalCell<&'static dyn SDCardClient>,
     client_buffer: TakeCell<'static, [u8]>,
     client_offset: Cell<usize>,
+
+    storage_client: OptionalCell<&'a dyn hil::block_storage::BlockStorageClient>,
+    storage_operation: Cell<StorageOperation>,
 }
 
 /// SD card command codes
@@ -95,6 +99,9 @@ enum SDCmd {
     CMD18_ReadMultiple = 18,              //         Read multiple blocks
     CMD24_WriteSingle = 24,               //          Write single block
     CMD25_WriteMultiple = 25,             //        Write multiple blocks
+    CMD32_EraseStart = 32,                //            Set first block to erase
+    CMD33_EraseEnd = 33,                  //              Set last block to erase
+    CMD38_Erase = 38,                     //                 Erase selected blocks
     CMD55_ManufSpecificCommand = 55,      // Next command will be manufacturer specific
     CMD58_ReadOCR = 58,                   //              Read operation condition register (OCR)
     ACMD41_ManufSpecificInit = 0x80 + 41, // Manufacturer specific Init
@@ -138,6 +145,11 @@ enum SpiState {
     WriteBlockResponse,
     WriteBlockBusy,
     WaitWriteBlockBusy,
+
+    StartErase { end: u32 },
+    EraseEnd,
+    EraseResponse,
+    WaitEraseBusy,
 }
 
 /// Alarm states
@@ -155,6 +167,7 @@ enum AlarmState {
     WaitForDataBlocks { count: u32 },
 
     WaitForWriteBusy,
+    WaitForEraseBusy,
 }
 
 /// Error codes returned if an SD card transaction fails
@@ -165,6 +178,17 @@ enum ErrorCode {
     ReadFailure = -3,
     WriteFailure = -4,
     TimeoutFailure = -5,
+    EraseFailure = -6,
+}
+
+/// Block storage operation in progress, whose completion goes to the
+/// `BlockStorageClient` instead of the `SDCardClient`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StorageOperation {
+    None,
+    Read,
+    Write,
+    Erase,
 }
 
 /// SD card types, determined during initialization
@@ -234,12 +258,99 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
             alarm_count: Cell::new(0),
             is_initialized: Cell::new(false),
             card_type: Cell::new(SDCardType::Uninitialized),
+            total_blocks: Cell::new(0),
             detect_pin: Cell::new(pin),
             txbuffer: TakeCell::new(txbuffer),
             rxbuffer: TakeCell::new(rxbuffer),
             client: OptionalCell::empty(),
             client_buffer: TakeCell::empty(),
             client_offset: Cell::new(0),
+            storage_client: OptionalCell::empty(),
+            storage_operation: Cell::new(StorageOperation::None),
+        }
+    }
+
+    /// send a read completion to the client that started the read
+    fn read_complete(&self, buffer: &'static mut [u8], len: usize) {
+        if self.storage_operation.replace(StorageOperation::None) == StorageOperation::Read {
+            self.storage_client.map(move |client| {
+                client.read_done(buffer, ReturnCode::SUCCESS);
+            });
+        } else {
+            self.client.map(move |client| {
+                client.read_done(buffer, len);
+            });
+        }
+    }
+
+    /// send a write completion to the client that started the write
+    fn write_complete(&self, buffer: &'static mut [u8]) {
+        if self.storage_operation.replace(StorageOperation::None) == StorageOperation::Write {
+            self.storage_client.map(move |client| {
+                client.write_done(buffer, ReturnCode::SUCCESS);
+            });
+        } else {
+            self.client.map(move |client| {
+                client.write_done(buffer);
+            });
+        }
+    }
+
+    /// send an error to the client of the operation that failed. Block
+    ///  storage clients get their buffer back instead
+    fn operation_failed(&self, error: ErrorCode) {
+        match self.storage_operation.replace(StorageOperation::None) {
+            StorageOperation::None => {
+                self.client.map(move |client| {
+                    client.error(error as u32);
+                });
+            }
+            StorageOperation::Read => {
+                self.client_buffer.take().map(|buffer| {
+                    self.storage_client.map(move |client| {
+                        client.read_done(buffer, ReturnCode::FAIL);
+                    });
+                });
+            }
+            StorageOperation::Write => {
+                self.client_buffer.take().map(|buffer| {
+                    self.storage_client.map(move |client| {
+                        client.write_done(buffer, ReturnCode::FAIL);
+                    });
+                });
+            }
+            StorageOperation::Erase => {
+                self.storage_client.map(move |client| {
+                    client.erase_done(ReturnCode::FAIL);
+                });
+            }
+        }
+    }
+
+    /// check that a block storage operation can start now, with a buffer of
+    ///  `buffer_len` bytes if it needs one
+    fn check_storage_operation(
+        &self,
+        buffer_len: Option<usize>,
+        block: usize,
+        count: usize,
+    ) -> ReturnCode {
+        if !self.is_installed() {
+            ReturnCode::EUNINSTALLED
+        } else if !self.is_initialized() {
+            ReturnCode::ERESERVE
+        } else if count == 0 || block + count > self.total_blocks.get() as usize {
+            ReturnCode::EINVAL
+        } else if buffer_len.map_or(false, |len| len < count * 512) {
+            ReturnCode::ESIZE
+        } else if self.state.get() != SpiState::Idle
+            || self.alarm_state.get() != AlarmState::Idle
+            || self.txbuffer.is_none()
+            || self.rxbuffer.is_none()
+        {
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
         }
     }
 
@@ -447,9 +558,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -517,9 +626,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -546,9 +653,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -613,9 +718,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -651,9 +754,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -674,9 +775,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -724,6 +823,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     // initialization complete
                     self.state.set(SpiState::Idle);
                     self.is_initialized.set(true);
+                    self.total_blocks.set((total_size / 512) as u32);
 
                     // perform callback
                     self.client.map(move |client| {
@@ -736,9 +836,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -763,9 +861,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::ReadFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::ReadFailure);
                 }
             }
 
@@ -793,9 +889,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::ReadFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::ReadFailure);
                 }
             }
 
@@ -806,23 +900,22 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
 
                 // read finished, perform callback
                 self.state.set(SpiState::Idle);
-                self.rxbuffer.map(|read_buffer| {
-                    self.client_buffer.take().map(move |buffer| {
-                        // copy data to user buffer
-                        // Limit to minimum length between buffer, read_buffer,
-                        // and 512 (block size)
+                self.client_buffer.take().map(move |buffer| {
+                    // copy data to user buffer
+                    // Limit to minimum length between buffer, read_buffer,
+                    // and 512 (block size)
+                    let read_len = self.rxbuffer.map_or(0, |read_buffer| {
                         for (client_byte, &read_byte) in
                             buffer.iter_mut().zip(read_buffer.iter()).take(512)
                         {
                             *client_byte = read_byte;
                         }
-
-                        // callback
-                        let read_len = cmp::min(read_buffer.len(), cmp::min(buffer.len(), 512));
-                        self.client.map(move |client| {
-                            client.read_done(buffer, read_len);
-                        });
+                        cmp::min(read_buffer.len(), cmp::min(buffer.len(), 512))
                     });
+
+                    // callback, after the buffers are back so that the
+                    //  client can start the next operation
+                    self.read_complete(buffer, read_len);
                 });
             }
 
@@ -851,9 +944,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::ReadFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::ReadFailure);
                 }
             }
 
@@ -902,9 +993,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
 
                     // read finished, perform callback
                     self.client_buffer.take().map(move |buffer| {
-                        self.client.map(move |client| {
-                            client.read_done(buffer, self.client_offset.get());
-                        });
+                        self.read_complete(buffer, self.client_offset.get());
                     });
                 } else {
                     // error, send callback and quit
@@ -913,9 +1002,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::ReadFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::ReadFailure);
                 }
             }
 
@@ -966,9 +1053,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                         self.state.set(SpiState::Idle);
                         self.alarm_state.set(AlarmState::Idle);
                         self.alarm_count.set(0);
-                        self.client.map(move |client| {
-                            client.error(ErrorCode::WriteFailure as u32);
-                        });
+                        self.operation_failed(ErrorCode::WriteFailure);
                     }
                 } else {
                     // error, send callback and quit
@@ -977,9 +1062,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::WriteFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::WriteFailure);
                 }
             }
 
@@ -1001,9 +1084,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::WriteFailure as u32);
-                    });
+                    self.operation_failed(ErrorCode::WriteFailure);
                 }
             }
 
@@ -1018,9 +1099,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_count.set(0);
                     self.client_buffer.take().map(move |buffer| {
-                        self.client.map(move |client| {
-                            client.write_done(buffer);
-                        });
+                        self.write_complete(buffer);
                     });
                 } else {
                     // replace buffers
@@ -1034,6 +1113,87 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                 }
             }
 
+            SpiState::StartErase { end } => {
+                // check response
+                let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
+
+                if r1 == SUCCESS_STATUS {
+                    // set the last block to erase
+                    self.state.set(SpiState::EraseEnd);
+                    self.send_command(SDCmd::CMD33_EraseEnd, end, write_buffer, read_buffer, 10);
+                } else {
+                    // error, send callback and quit
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
+                    self.state.set(SpiState::Idle);
+                    self.alarm_state.set(AlarmState::Idle);
+                    self.alarm_count.set(0);
+                    self.operation_failed(ErrorCode::EraseFailure);
+                }
+            }
+
+            SpiState::EraseEnd => {
+                // check response
+                let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
+
+                if r1 == SUCCESS_STATUS {
+                    // erase the selected blocks
+                    self.state.set(SpiState::EraseResponse);
+                    self.send_command(SDCmd::CMD38_Erase, 0x0, write_buffer, read_buffer, 10);
+                } else {
+                    // error, send callback and quit
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
+                    self.state.set(SpiState::Idle);
+                    self.alarm_state.set(AlarmState::Idle);
+                    self.alarm_count.set(0);
+                    self.operation_failed(ErrorCode::EraseFailure);
+                }
+            }
+
+            SpiState::EraseResponse => {
+                // check response
+                let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
+
+                if r1 == SUCCESS_STATUS {
+                    // the card holds the line low until the erase is done
+                    self.state.set(SpiState::WaitEraseBusy);
+                    self.read_bytes(write_buffer, read_buffer, 1);
+                } else {
+                    // error, send callback and quit
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
+                    self.state.set(SpiState::Idle);
+                    self.alarm_state.set(AlarmState::Idle);
+                    self.alarm_count.set(0);
+                    self.operation_failed(ErrorCode::EraseFailure);
+                }
+            }
+
+            SpiState::WaitEraseBusy => {
+                // check if line is still held low (busy state)
+                let busy = read_buffer[0] == 0x00;
+
+                // replace buffers
+                self.txbuffer.replace(write_buffer);
+                self.rxbuffer.replace(read_buffer);
+
+                if busy {
+                    // erasing takes longer than writing, try again after 10 ms
+                    self.alarm_state.set(AlarmState::WaitForEraseBusy);
+                    let delay = A::ticks_from_ms(10);
+                    self.alarm.set_alarm(self.alarm.now(), delay);
+                } else {
+                    // erase finished, perform callback
+                    self.state.set(SpiState::Idle);
+                    self.alarm_count.set(0);
+                    self.storage_operation.set(StorageOperation::None);
+                    self.storage_client.map(move |client| {
+                        client.erase_done(ReturnCode::SUCCESS);
+                    });
+                }
+            }
+
             SpiState::Idle => {
                 // receiving an event from Idle means something was killed
 
@@ -1053,9 +1213,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
             self.state.set(SpiState::Idle);
             self.alarm_state.set(AlarmState::Idle);
             self.alarm_count.set(0);
-            self.client.map(move |client| {
-                client.error(ErrorCode::TimeoutFailure as u32);
-            });
+            self.operation_failed(ErrorCode::TimeoutFailure);
         } else {
             self.alarm_count.set(repeats + 1);
         }
@@ -1171,6 +1329,18 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                 self.alarm_state.set(AlarmState::Idle);
             }
 
+            AlarmState::WaitForEraseBusy => {
+                self.txbuffer.take().map(|write_buffer| {
+                    self.rxbuffer.take().map(move |read_buffer| {
+                        // check if sd card is busy
+                        self.state.set(SpiState::WaitEraseBusy);
+                        self.read_bytes(write_buffer, read_buffer, 1);
+                    });
+                });
+
+                self.alarm_state.set(AlarmState::Idle);
+            }
+
             AlarmState::Idle => {
                 // receiving an event from Idle means something was killed
                 // do nothing
@@ -1325,6 +1495,98 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
     }
 }
 
+/// Erasing, which only block storage clients can use
+impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
+    fn erase_blocks(&self, sector: u32, count: u32) -> ReturnCode {
+        self.txbuffer.take().map_or(ReturnCode::ENOMEM, |txbuffer| {
+            self.rxbuffer
+                .take()
+                .map_or(ReturnCode::ENOMEM, move |rxbuffer| {
+                    // convert block addresses to byte addresses for non-block
+                    //  access cards
+                    let mut start = sector;
+                    let mut end = sector + count - 1;
+                    if self.card_type.get() != SDCardType::SDv2BlockAddressable {
+                        start *= 512;
+                        end *= 512;
+                    }
+
+                    // set the first block to erase
+                    self.state.set(SpiState::StartErase { end: end });
+                    self.send_command(SDCmd::CMD32_EraseStart, start, txbuffer, rxbuffer, 10);
+
+                    // command started successfully
+                    ReturnCode::SUCCESS
+                })
+        })
+    }
+}
+
+/// Block storage interface, for capsules that build on the SD card
+impl<'a, A: hil::time::Alarm<'a>> hil::block_storage::BlockStorage<'a> for SDCard<'a, A> {
+    fn set_client(&self, client: &'a dyn hil::block_storage::BlockStorageClient) {
+        self.storage_client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        512
+    }
+
+    fn number_of_blocks(&self) -> usize {
+        if self.is_initialized() {
+            self.total_blocks.get() as usize
+        } else {
+            0
+        }
+    }
+
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: usize,
+        count: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        match self.check_storage_operation(Some(buffer.len()), block, count) {
+            ReturnCode::SUCCESS => {
+                self.storage_operation.set(StorageOperation::Read);
+                self.read_blocks(buffer, block as u32, count as u32);
+                Ok(())
+            }
+            error => Err((error, buffer)),
+        }
+    }
+
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: usize,
+        count: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if count > 1 {
+            // multi-block writes are unimplemented
+            return Err((ReturnCode::ENOSUPPORT, buffer));
+        }
+        match self.check_storage_operation(Some(buffer.len()), block, count) {
+            ReturnCode::SUCCESS => {
+                self.storage_operation.set(StorageOperation::Write);
+                self.write_blocks(buffer, block as u32, count as u32);
+                Ok(())
+            }
+            error => Err((error, buffer)),
+        }
+    }
+
+    fn erase_blocks(&self, block: usize, count: usize) -> ReturnCode {
+        match self.check_storage_operation(None, block, count) {
+            ReturnCode::SUCCESS => {
+                self.storage_operation.set(StorageOperation::Erase);
+                SDCard::erase_blocks(self, block as u32, count as u32)
+            }
+            error => error,
+        }
+    }
+}
+
 /// Handle callbacks from the SPI peripheral
 impl<'a, A: hil::time::Alarm<'a>> hil::spi::SpiMasterClient for SDCard<'a, A> {
     fn read_write_done(
@@ -1356,9 +1618,7 @@ impl<'a, A: hil::time::Alarm<'a>> hil::gpio::Client for SDCard<'a, A> {
             //  send an error callback
             self.state.set(SpiState::Idle);
             self.alarm_state.set(AlarmState::Idle);
-            self.client.map(move |client| {
-                client.error(ErrorCode::CardStateChanged as u32);
-            });
+            self.operation_failed(ErrorCode::CardStateChanged);
         }
 
         // either the card is new or gone, in either case it isn't initialized
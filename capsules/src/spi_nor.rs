@@ -0,0 +1,971 @@
+//! Driver for JEDEC SPI NOR flash chips.
+//!
+//! Instead of being written for one part, this driver reads the Serial Flash
+//! Discoverable Parameters (SFDP, JESD216) of the chip when `init()` is
+//! called. The Basic Flash Parameter Table tells it the size of the chip, the
+//! opcode that erases a 4 KiB sector, the size of a program page, the typical
+//! erase and program times and whether the chip uses 3 or 4 address bytes.
+//! Chips larger than 16 MiB are accessed with 4-byte addresses, either with
+//! the 4-byte opcodes from the 4-byte Address Instruction Table or by
+//! switching the chip to 4-byte address mode.
+//!
+//! The flash is exposed through `hil::flash::Flash` with 4 KiB pages, so it
+//! can be used with `nonvolatile_to_pages`, `log` and `virtual_flash`.
+//! Writing a page erases the sector first. Flash operations fail with EOFF
+//! until `init()` has finished, and with ENODEVICE if the chip does not have
+//! usable SFDP tables. For such chips, boards can provide the parameters
+//! with `configure()` instead.
+//!
+//! All transfers use a single data line, since that is what
+//! `hil::spi::SpiMasterDevice` provides. Chips with quad I/O still support
+//! these commands, as long as they are not in a quad-only (QPI) mode.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use capsules::virtual_alarm::VirtualMuxAlarm;
+//!
+//! let spi_nor_spi = static_init!(
+//!     capsules::virtual_spi::VirtualSpiMasterDevice<'static, nrf52::spi::SPIM>,
+//!     capsules::virtual_spi::VirtualSpiMasterDevice::new(mux_spi, &nrf5x::gpio::PORT[17])
+//! );
+//! let spi_nor_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let spi_nor = static_init!(
+//!     capsules::spi_nor::SpiNor<
+//!         'static,
+//!         capsules::virtual_spi::VirtualSpiMasterDevice<'static, nrf52::spi::SPIM>,
+//!         VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     >,
+//!     capsules::spi_nor::SpiNor::new(
+//!         spi_nor_spi,
+//!         spi_nor_alarm,
+//!         &mut capsules::spi_nor::TXBUFFER,
+//!         &mut capsules::spi_nor::RXBUFFER,
+//!     )
+//! );
+//! spi_nor_spi.set_client(spi_nor);
+//! spi_nor_alarm.set_alarm_client(spi_nor);
+//! spi_nor.init();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::ops::{Index, IndexMut};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::ReturnCode;
+
+/// Size of the pages of the flash, which are the 4 KiB sectors of the chip.
+pub const SECTOR_SIZE: usize = 4096;
+
+/// Largest number of bytes read or programmed in one transfer.
+const CHUNK_SIZE: usize = 256;
+/// Opcode, up to four address bytes and a dummy byte.
+const MAX_HEADER_SIZE: usize = 6;
+const BUFFER_SIZE: usize = CHUNK_SIZE + MAX_HEADER_SIZE;
+
+pub static mut TXBUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+pub static mut RXBUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+
+const SPI_SPEED: u32 = 8000000;
+
+/// Number of SFDP parameter headers read. Tables described by later headers
+/// are not used.
+const MAX_PARAMETER_HEADERS: usize = 8;
+/// Number of DWORDs of the Basic Flash Parameter Table that are used.
+const BFPT_DWORDS: usize = 16;
+
+/// Times used if the SFDP tables do not specify them.
+const DEFAULT_ERASE_TIME_US: u32 = 50_000;
+const DEFAULT_PROGRAM_TIME_US: u32 = 1_000;
+
+/// A 4 KiB sector of the flash chip, the unit in which it is read, written
+/// and erased through `hil::flash::Flash`.
+///
+/// ```
+/// # use capsules::spi_nor::SpiNorSector;
+///
+/// static mut PAGEBUFFER: SpiNorSector = SpiNorSector::new();
+/// ```
+pub struct SpiNorSector(pub [u8; SECTOR_SIZE]);
+
+impl SpiNorSector {
+    pub const fn new() -> SpiNorSector {
+        SpiNorSector([0; SECTOR_SIZE])
+    }
+}
+
+impl Default for SpiNorSector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Index<usize> for SpiNorSector {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for SpiNorSector {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for SpiNorSector {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[allow(dead_code)]
+enum Opcodes {
+    WREN = 0x06,   // Write Enable
+    RDSR = 0x05,   // Read Status Register
+    READ = 0x03,   // Normal Read
+    PP = 0x02,     // Page Program
+    SE = 0x20,     // 4 KiB Sector Erase
+    READ4 = 0x13,  // Normal Read with 4-byte address
+    PP4 = 0x12,    // Page Program with 4-byte address
+    EN4B = 0xb7,   // Enter 4-byte address mode
+    RDSFDP = 0x5a, // Read SFDP
+}
+
+/// How a chip is switched to 4-byte address mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Enter4ByteMode {
+    /// The chip already uses the configured address size.
+    None,
+    /// Issue EN4B (0xB7).
+    Enter,
+    /// Issue write enable, then EN4B.
+    WriteEnableEnter,
+}
+
+/// Geometry, opcodes and timing of a flash chip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpiNorParameters {
+    /// Size of the chip in bytes.
+    pub size: u32,
+    /// Size of a program page in bytes. Larger pages are programmed 256
+    /// bytes at a time.
+    pub page_size: u32,
+    /// Number of address bytes, 3 or 4.
+    pub address_bytes: u8,
+    pub read_opcode: u8,
+    pub program_opcode: u8,
+    /// Opcode that erases a 4 KiB sector.
+    pub erase_opcode: u8,
+    pub enter_4byte: Enter4ByteMode,
+    /// Typical time to erase a sector, in microseconds.
+    pub erase_time_us: u32,
+    /// Typical time to program a page, in microseconds.
+    pub program_time_us: u32,
+    /// Whether the chip supports reads with four data lines. Informational
+    /// only: this driver uses one data line.
+    pub quad_read: bool,
+}
+
+impl SpiNorParameters {
+    /// Parameters from the Basic Flash Parameter Table in `bfpt`, whose
+    /// DWORDs are little-endian.
+    fn from_bfpt(bfpt: &[u8]) -> Option<SpiNorParameters> {
+        let dword = |n: usize| -> Option<u32> {
+            let bytes = bfpt.get((n - 1) * 4..n * 4)?;
+            Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+        let dword1 = dword(1)?;
+        let dword2 = dword(2)?;
+
+        let size_bits: u64 = if dword2 & (1 << 31) == 0 {
+            dword2 as u64 + 1
+        } else {
+            let exponent = dword2 & 0x7fff_ffff;
+            if exponent >= 35 {
+                return None;
+            }
+            1u64 << exponent
+        };
+        let size = (size_bits / 8) as u32;
+        if (size as usize) < SECTOR_SIZE {
+            return None;
+        }
+
+        // Find the erase type that erases 4 KiB, and its typical time.
+        let mut erase = None;
+        if let (Some(dword8), Some(dword9)) = (dword(8), dword(9)) {
+            let types = (dword8 as u64) | (dword9 as u64) << 32;
+            for i in 0..4 {
+                let size_exponent = (types >> (16 * i)) as u8;
+                let opcode = (types >> (16 * i + 8)) as u8;
+                if size_exponent == 12 {
+                    erase = Some((i, opcode));
+                    break;
+                }
+            }
+        }
+        let (erase_type, erase_opcode) = match erase {
+            Some((i, opcode)) => (Some(i), opcode),
+            None if dword1 & 0x3 == 0x1 => (None, (dword1 >> 8) as u8),
+            None => return None,
+        };
+        let erase_time_us = match (erase_type, dword(10)) {
+            (Some(i), Some(dword10)) => {
+                let field = dword10 >> (4 + 7 * i);
+                let count = (field & 0x1f) + 1;
+                let unit_us = match (field >> 5) & 0x3 {
+                    0 => 1_000,
+                    1 => 16_000,
+                    2 => 128_000,
+                    _ => 1_000_000,
+                };
+                count * unit_us
+            }
+            _ => DEFAULT_ERASE_TIME_US,
+        };
+
+        let (page_size, program_time_us) = match dword(11) {
+            Some(dword11) => {
+                let count = ((dword11 >> 8) & 0x1f) + 1;
+                let unit_us = if dword11 & (1 << 13) == 0 { 8 } else { 64 };
+                (1 << ((dword11 >> 4) & 0xf), count * unit_us)
+            }
+            None => (256, DEFAULT_PROGRAM_TIME_US),
+        };
+
+        // 3-byte addresses reach 16 MiB.
+        let needs_4byte = size > 1 << 24;
+        let (address_bytes, enter_4byte) = match (dword1 >> 17) & 0x3 {
+            0 if !needs_4byte => (3, Enter4ByteMode::None),
+            1 if !needs_4byte => (3, Enter4ByteMode::None),
+            // The chip can be switched to 4-byte address mode, which is
+            // only used if the 4-byte opcodes are not available.
+            1 => {
+                let methods = dword(16).map_or(0, |dword16| dword16 >> 24);
+                if methods & 0x2 != 0 && methods & 0x1 == 0 {
+                    (4, Enter4ByteMode::WriteEnableEnter)
+                } else {
+                    // EN4B is the most common method, so it is also used
+                    // if the table does not say.
+                    (4, Enter4ByteMode::Enter)
+                }
+            }
+            2 => (4, Enter4ByteMode::None),
+            _ => return None,
+        };
+
+        Some(SpiNorParameters {
+            size: size,
+            page_size: page_size,
+            address_bytes: address_bytes,
+            read_opcode: Opcodes::READ as u8,
+            program_opcode: Opcodes::PP as u8,
+            erase_opcode: erase_opcode,
+            enter_4byte: enter_4byte,
+            erase_time_us: erase_time_us,
+            program_time_us: program_time_us,
+            quad_read: dword1 & (1 << 22) != 0 || dword1 & (1 << 21) != 0,
+        })
+    }
+
+    /// Switch to the 4-byte opcodes of the 4-byte Address Instruction Table
+    /// in `table`, if it has all the opcodes needed.
+    fn apply_4bait(&mut self, table: &[u8], erase_size_exponents: [u8; 4]) {
+        if table.len() < 8 {
+            return;
+        }
+        let supported = u32::from_le_bytes([table[0], table[1], table[2], table[3]]);
+        let erase_type = match erase_size_exponents.iter().position(|&e| e == 12) {
+            Some(i) => i,
+            None => return,
+        };
+        if supported & (1 << 0) != 0
+            && supported & (1 << 6) != 0
+            && supported & (1 << (9 + erase_type)) != 0
+        {
+            self.read_opcode = Opcodes::READ4 as u8;
+            self.program_opcode = Opcodes::PP4 as u8;
+            self.erase_opcode = table[4 + erase_type];
+            self.enter_4byte = Enter4ByteMode::None;
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Uninitialized,
+    /// The chip has no usable SFDP tables.
+    Failed,
+
+    ReadSfdpHeader,
+    ReadBfpt {
+        /// Length of the table in bytes.
+        len: usize,
+        /// Address and length in bytes of the 4-byte Address Instruction
+        /// Table.
+        fourbait: Option<(u32, usize)>,
+    },
+    Read4bait,
+    Enter4ByteWriteEnable,
+    Enter4Byte,
+
+    Idle,
+
+    ReadSector {
+        sector_index: u32,
+        offset: usize,
+    },
+
+    EraseWriteEnable {
+        sector_index: u32,
+        write: bool,
+    },
+    Erase {
+        sector_index: u32,
+        write: bool,
+    },
+    EraseWait {
+        sector_index: u32,
+        write: bool,
+    },
+
+    ProgramWriteEnable {
+        sector_index: u32,
+        offset: usize,
+    },
+    Program {
+        sector_index: u32,
+        offset: usize,
+    },
+    ProgramWait {
+        sector_index: u32,
+        offset: usize,
+    },
+}
+
+pub struct SpiNor<'a, S: hil::spi::SpiMasterDevice + 'a, A: hil::time::Alarm<'a> + 'a> {
+    spi: &'a S,
+    alarm: &'a A,
+    state: Cell<State>,
+    parameters: Cell<Option<SpiNorParameters>>,
+    /// Sizes (as powers of two) of the four erase types in the BFPT.
+    erase_size_exponents: Cell<[u8; 4]>,
+    txbuffer: TakeCell<'static, [u8]>,
+    rxbuffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn hil::flash::Client<SpiNor<'a, S, A>>>,
+    client_sector: TakeCell<'static, SpiNorSector>,
+}
+
+impl<'a, S: hil::spi::SpiMasterDevice + 'a, A: hil::time::Alarm<'a> + 'a> SpiNor<'a, S, A> {
+    pub fn new(
+        spi: &'a S,
+        alarm: &'a A,
+        txbuffer: &'static mut [u8],
+        rxbuffer: &'static mut [u8],
+    ) -> SpiNor<'a, S, A> {
+        SpiNor {
+            spi: spi,
+            alarm: alarm,
+            state: Cell::new(State::Uninitialized),
+            parameters: Cell::new(None),
+            erase_size_exponents: Cell::new([0; 4]),
+            txbuffer: TakeCell::new(txbuffer),
+            rxbuffer: TakeCell::new(rxbuffer),
+            client: OptionalCell::empty(),
+            client_sector: TakeCell::empty(),
+        }
+    }
+
+    /// Read the SFDP tables of the chip to learn its parameters. Flash
+    /// operations can be used once this finishes.
+    pub fn init(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Uninitialized | State::Failed => {}
+            _ => return ReturnCode::EALREADY,
+        }
+        self.configure_spi();
+        self.state.set(State::ReadSfdpHeader);
+        self.read_sfdp(0, 8 + 8 * MAX_PARAMETER_HEADERS)
+    }
+
+    /// Use `parameters` instead of reading them from the chip, for chips
+    /// without SFDP tables.
+    pub fn configure(&self, parameters: SpiNorParameters) -> ReturnCode {
+        match self.state.get() {
+            State::Uninitialized | State::Failed => {}
+            _ => return ReturnCode::EALREADY,
+        }
+        self.configure_spi();
+        self.parameters.set(Some(parameters));
+        self.enter_4byte()
+    }
+
+    /// The parameters of the chip, once they are known.
+    pub fn parameters(&self) -> Option<SpiNorParameters> {
+        self.parameters.get()
+    }
+
+    /// Number of 4 KiB pages of the flash.
+    pub fn number_of_pages(&self) -> usize {
+        self.parameters
+            .get()
+            .map_or(0, |parameters| parameters.size as usize / SECTOR_SIZE)
+    }
+
+    fn configure_spi(&self) {
+        self.spi.configure(
+            hil::spi::ClockPolarity::IdleLow,
+            hil::spi::ClockPhase::SampleLeading,
+            SPI_SPEED,
+        );
+    }
+
+    /// Read `len` bytes of SFDP data at `address`. They start at
+    /// `MAX_HEADER_SIZE - 1` in the read buffer.
+    fn read_sfdp(&self, address: u32, len: usize) -> ReturnCode {
+        self.txbuffer
+            .take()
+            .map_or(ReturnCode::ERESERVE, |txbuffer| {
+                self.rxbuffer
+                    .take()
+                    .map_or(ReturnCode::ERESERVE, move |rxbuffer| {
+                        // Opcode, 3 address bytes and a dummy byte.
+                        txbuffer[0] = Opcodes::RDSFDP as u8;
+                        txbuffer[1] = (address >> 16) as u8;
+                        txbuffer[2] = (address >> 8) as u8;
+                        txbuffer[3] = address as u8;
+                        txbuffer[4] = 0;
+                        let len = cmp::min(len, CHUNK_SIZE);
+                        self.spi.read_write_bytes(txbuffer, Some(rxbuffer), len + 5)
+                    })
+            })
+    }
+
+    /// Switch the chip to 4-byte address mode if needed, then become idle.
+    fn enter_4byte(&self) -> ReturnCode {
+        let mode = self
+            .parameters
+            .get()
+            .map_or(Enter4ByteMode::None, |parameters| parameters.enter_4byte);
+        match mode {
+            Enter4ByteMode::None => {
+                self.state.set(State::Idle);
+                ReturnCode::SUCCESS
+            }
+            Enter4ByteMode::Enter => {
+                self.state.set(State::Enter4Byte);
+                self.send_command(Opcodes::EN4B as u8)
+            }
+            Enter4ByteMode::WriteEnableEnter => {
+                self.state.set(State::Enter4ByteWriteEnable);
+                self.send_command(Opcodes::WREN as u8)
+            }
+        }
+    }
+
+    fn send_command(&self, opcode: u8) -> ReturnCode {
+        self.txbuffer
+            .take()
+            .map_or(ReturnCode::ERESERVE, |txbuffer| {
+                txbuffer[0] = opcode;
+                self.spi.read_write_bytes(txbuffer, None, 1)
+            })
+    }
+
+    /// Write an opcode followed by `address` to `buffer`. Returns the number
+    /// of bytes written.
+    fn write_header(
+        buffer: &mut [u8],
+        parameters: &SpiNorParameters,
+        opcode: u8,
+        address: u32,
+    ) -> usize {
+        buffer[0] = opcode;
+        if parameters.address_bytes == 4 {
+            buffer[1..5].copy_from_slice(&address.to_be_bytes());
+            5
+        } else {
+            buffer[1..4].copy_from_slice(&address.to_be_bytes()[1..]);
+            4
+        }
+    }
+
+    /// Size of the chunks a sector is programmed in.
+    fn program_chunk_size(parameters: &SpiNorParameters) -> usize {
+        cmp::min(parameters.page_size as usize, CHUNK_SIZE)
+    }
+
+    fn read_chunk(
+        &self,
+        sector_index: u32,
+        offset: usize,
+        txbuffer: &'static mut [u8],
+        rxbuffer: &'static mut [u8],
+    ) -> ReturnCode {
+        match self.parameters.get() {
+            Some(parameters) => {
+                let address = sector_index * SECTOR_SIZE as u32 + offset as u32;
+                let header =
+                    Self::write_header(txbuffer, &parameters, parameters.read_opcode, address);
+                self.state.set(State::ReadSector {
+                    sector_index,
+                    offset,
+                });
+                self.spi
+                    .read_write_bytes(txbuffer, Some(rxbuffer), header + CHUNK_SIZE)
+            }
+            None => {
+                self.txbuffer.replace(txbuffer);
+                self.rxbuffer.replace(rxbuffer);
+                ReturnCode::EOFF
+            }
+        }
+    }
+
+    /// Check that flash operations can start.
+    fn check_idle(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Idle => ReturnCode::SUCCESS,
+            State::Uninitialized => ReturnCode::EOFF,
+            State::Failed => ReturnCode::ENODEVICE,
+            _ => ReturnCode::EBUSY,
+        }
+    }
+
+    fn check_sector(&self, sector_index: u32) -> ReturnCode {
+        if (sector_index as usize) < self.number_of_pages() {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::EINVAL
+        }
+    }
+
+    fn read_sector(
+        &self,
+        sector_index: u32,
+        sector: &'static mut SpiNorSector,
+    ) -> Result<(), (ReturnCode, &'static mut SpiNorSector)> {
+        let mut retval = self.check_idle();
+        if retval == ReturnCode::SUCCESS {
+            retval = self.check_sector(sector_index);
+        }
+        if retval == ReturnCode::SUCCESS {
+            retval = self
+                .txbuffer
+                .take()
+                .map_or(ReturnCode::ERESERVE, |txbuffer| {
+                    self.rxbuffer
+                        .take()
+                        .map_or(ReturnCode::ERESERVE, move |rxbuffer| {
+                            self.read_chunk(sector_index, 0, txbuffer, rxbuffer)
+                        })
+                });
+        }
+
+        if retval == ReturnCode::SUCCESS {
+            self.client_sector.replace(sector);
+            Ok(())
+        } else {
+            Err((retval, sector))
+        }
+    }
+
+    /// Erase a sector, and then program it with `client_sector` if `write`.
+    fn erase_sector(&self, sector_index: u32, write: bool) -> ReturnCode {
+        let retval = self.check_idle();
+        if retval != ReturnCode::SUCCESS {
+            return retval;
+        }
+        let retval = self.check_sector(sector_index);
+        if retval != ReturnCode::SUCCESS {
+            return retval;
+        }
+        self.state.set(State::EraseWriteEnable {
+            sector_index,
+            write,
+        });
+        let retval = self.send_command(Opcodes::WREN as u8);
+        if retval != ReturnCode::SUCCESS {
+            self.state.set(State::Idle);
+        }
+        retval
+    }
+
+    fn write_sector(
+        &self,
+        sector_index: u32,
+        sector: &'static mut SpiNorSector,
+    ) -> Result<(), (ReturnCode, &'static mut SpiNorSector)> {
+        let retval = self.erase_sector(sector_index, true);
+        if retval == ReturnCode::SUCCESS {
+            self.client_sector.replace(sector);
+            Ok(())
+        } else {
+            Err((retval, sector))
+        }
+    }
+
+    /// Wait `us` microseconds before checking whether the chip finished.
+    fn wait(&self, us: u32) {
+        let delay = A::ticks_from_us(cmp::max(us, 1));
+        self.alarm.set_alarm(self.alarm.now(), delay);
+    }
+
+    /// Finish an operation and report it to the client.
+    fn operation_done(&self, error: hil::flash::Error, write: bool) {
+        self.state.set(State::Idle);
+        self.client.map(|client| {
+            if write {
+                self.client_sector.take().map(|sector| {
+                    client.write_complete(sector, error);
+                });
+            } else {
+                client.erase_complete(error);
+            }
+        });
+    }
+
+    fn sfdp_failed(&self) {
+        self.parameters.set(None);
+        self.state.set(State::Failed);
+    }
+}
+
+impl<'a, S: hil::spi::SpiMasterDevice + 'a, A: hil::time::Alarm<'a> + 'a> hil::spi::SpiMasterClient
+    for SpiNor<'a, S, A>
+{
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+    ) {
+        match self.state.get() {
+            State::ReadSfdpHeader => {
+                let read_buffer = match read_buffer {
+                    Some(read_buffer) => read_buffer,
+                    None => return,
+                };
+                let header = &read_buffer[5..];
+                let mut bfpt = None;
+                let mut fourbait = None;
+                if &header[0..4] == b"SFDP" {
+                    let headers = cmp::min(header[6] as usize + 1, MAX_PARAMETER_HEADERS);
+                    for parameter in header[8..8 + 8 * headers].chunks_exact(8) {
+                        let id = (parameter[7] as u16) << 8 | parameter[0] as u16;
+                        let len = parameter[3] as usize * 4;
+                        let pointer =
+                            u32::from_le_bytes([parameter[4], parameter[5], parameter[6], 0]);
+                        match id {
+                            0xff00 if bfpt.is_none() => bfpt = Some((pointer, len)),
+                            0xff84 if fourbait.is_none() => fourbait = Some((pointer, len)),
+                            _ => {}
+                        }
+                    }
+                }
+                self.txbuffer.replace(write_buffer);
+                self.rxbuffer.replace(read_buffer);
+                match bfpt {
+                    Some((pointer, len)) => {
+                        let len = cmp::min(len, BFPT_DWORDS * 4);
+                        self.state.set(State::ReadBfpt { len, fourbait });
+                        if self.read_sfdp(pointer, len) != ReturnCode::SUCCESS {
+                            self.sfdp_failed();
+                        }
+                    }
+                    None => self.sfdp_failed(),
+                }
+            }
+            State::ReadBfpt { len, fourbait } => {
+                let read_buffer = match read_buffer {
+                    Some(read_buffer) => read_buffer,
+                    None => return,
+                };
+                let bfpt = &read_buffer[5..5 + len];
+                let parameters = SpiNorParameters::from_bfpt(bfpt);
+                // Sizes of the erase types, in DWORDs 8 and 9.
+                let mut exponents = [0; 4];
+                for (i, exponent) in exponents.iter_mut().enumerate() {
+                    *exponent = bfpt.get(28 + 2 * i).copied().unwrap_or(0);
+                }
+                self.erase_size_exponents.set(exponents);
+                self.txbuffer.replace(write_buffer);
+                self.rxbuffer.replace(read_buffer);
+
+                match parameters {
+                    Some(parameters) => {
+                        self.parameters.set(Some(parameters));
+                        let retval = match fourbait {
+                            Some((pointer, len)) if parameters.address_bytes == 4 => {
+                                self.state.set(State::Read4bait);
+                                self.read_sfdp(pointer, cmp::min(len, 8))
+                            }
+                            _ => self.enter_4byte(),
+                        };
+                        if retval != ReturnCode::SUCCESS {
+                            self.sfdp_failed();
+                        }
+                    }
+                    None => self.sfdp_failed(),
+                }
+            }
+            State::Read4bait => {
+                read_buffer.map(|read_buffer| {
+                    let exponents = self.erase_size_exponents.get();
+                    if let Some(mut parameters) = self.parameters.get() {
+                        parameters.apply_4bait(&read_buffer[5..13], exponents);
+                        self.parameters.set(Some(parameters));
+                    }
+                    self.rxbuffer.replace(read_buffer);
+                });
+                self.txbuffer.replace(write_buffer);
+                if self.enter_4byte() != ReturnCode::SUCCESS {
+                    self.sfdp_failed();
+                }
+            }
+            State::Enter4ByteWriteEnable => {
+                self.state.set(State::Enter4Byte);
+                write_buffer[0] = Opcodes::EN4B as u8;
+                self.spi.read_write_bytes(write_buffer, None, 1);
+            }
+            State::Enter4Byte => {
+                self.txbuffer.replace(write_buffer);
+                self.state.set(State::Idle);
+            }
+            State::ReadSector {
+                sector_index,
+                offset,
+            } => {
+                let read_buffer = match read_buffer {
+                    Some(read_buffer) => read_buffer,
+                    None => return,
+                };
+                let header = self
+                    .parameters
+                    .get()
+                    .map_or(4, |parameters| parameters.address_bytes as usize + 1);
+                self.client_sector.map(|sector| {
+                    sector.0[offset..offset + CHUNK_SIZE]
+                        .copy_from_slice(&read_buffer[header..header + CHUNK_SIZE]);
+                });
+
+                let offset = offset + CHUNK_SIZE;
+                if offset == SECTOR_SIZE {
+                    self.state.set(State::Idle);
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
+                    self.client.map(|client| {
+                        self.client_sector.take().map(|sector| {
+                            client.read_complete(sector, hil::flash::Error::CommandComplete);
+                        });
+                    });
+                } else {
+                    self.read_chunk(sector_index, offset, write_buffer, read_buffer);
+                }
+            }
+            State::EraseWriteEnable {
+                sector_index,
+                write,
+            } => {
+                self.parameters.get().map(move |parameters| {
+                    let header = Self::write_header(
+                        write_buffer,
+                        &parameters,
+                        parameters.erase_opcode,
+                        sector_index * SECTOR_SIZE as u32,
+                    );
+                    self.state.set(State::Erase {
+                        sector_index,
+                        write,
+                    });
+                    self.spi.read_write_bytes(write_buffer, None, header);
+                });
+            }
+            State::Erase {
+                sector_index,
+                write,
+            } => {
+                self.txbuffer.replace(write_buffer);
+                self.state.set(State::EraseWait {
+                    sector_index,
+                    write,
+                });
+                self.wait(
+                    self.parameters
+                        .get()
+                        .map_or(DEFAULT_ERASE_TIME_US, |parameters| parameters.erase_time_us),
+                );
+            }
+            State::EraseWait {
+                sector_index,
+                write,
+            } => {
+                read_buffer.map(move |read_buffer| {
+                    let busy = read_buffer[1] & 0x01 == 0x01;
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
+                    if busy {
+                        // Check again after a fraction of the typical time.
+                        self.wait(
+                            self.parameters
+                                .get()
+                                .map_or(DEFAULT_ERASE_TIME_US, |parameters| {
+                                    parameters.erase_time_us
+                                })
+                                / 8,
+                        );
+                    } else if write {
+                        self.state.set(State::ProgramWriteEnable {
+                            sector_index,
+                            offset: 0,
+                        });
+                        if self.send_command(Opcodes::WREN as u8) != ReturnCode::SUCCESS {
+                            self.operation_done(hil::flash::Error::FlashError, true);
+                        }
+                    } else {
+                        self.operation_done(hil::flash::Error::CommandComplete, false);
+                    }
+                });
+            }
+            State::ProgramWriteEnable {
+                sector_index,
+                offset,
+            } => {
+                self.parameters.get().map(move |parameters| {
+                    let chunk = Self::program_chunk_size(&parameters);
+                    let header = Self::write_header(
+                        write_buffer,
+                        &parameters,
+                        parameters.program_opcode,
+                        sector_index * SECTOR_SIZE as u32 + offset as u32,
+                    );
+                    self.client_sector.map(|sector| {
+                        write_buffer[header..header + chunk]
+                            .copy_from_slice(&sector.0[offset..offset + chunk]);
+                    });
+                    self.state.set(State::Program {
+                        sector_index,
+                        offset,
+                    });
+                    self.spi
+                        .read_write_bytes(write_buffer, None, header + chunk);
+                });
+            }
+            State::Program {
+                sector_index,
+                offset,
+            } => {
+                self.txbuffer.replace(write_buffer);
+                self.state.set(State::ProgramWait {
+                    sector_index,
+                    offset,
+                });
+                self.wait(
+                    self.parameters
+                        .get()
+                        .map_or(DEFAULT_PROGRAM_TIME_US, |parameters| {
+                            parameters.program_time_us
+                        }),
+                );
+            }
+            State::ProgramWait {
+                sector_index,
+                offset,
+            } => {
+                read_buffer.map(move |read_buffer| {
+                    let busy = read_buffer[1] & 0x01 == 0x01;
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
+                    let (chunk, program_time_us) = self.parameters.get().map_or(
+                        (CHUNK_SIZE, DEFAULT_PROGRAM_TIME_US),
+                        |parameters| {
+                            (
+                                Self::program_chunk_size(&parameters),
+                                parameters.program_time_us,
+                            )
+                        },
+                    );
+                    if busy {
+                        self.wait(program_time_us / 4);
+                    } else if offset + chunk == SECTOR_SIZE {
+                        // The chip disables writes after each program.
+                        self.operation_done(hil::flash::Error::CommandComplete, true);
+                    } else {
+                        self.state.set(State::ProgramWriteEnable {
+                            sector_index,
+                            offset: offset + chunk,
+                        });
+                        if self.send_command(Opcodes::WREN as u8) != ReturnCode::SUCCESS {
+                            self.operation_done(hil::flash::Error::FlashError, true);
+                        }
+                    }
+                });
+            }
+            State::Uninitialized | State::Failed | State::Idle => {
+                self.txbuffer.replace(write_buffer);
+                read_buffer.map(|read_buffer| self.rxbuffer.replace(read_buffer));
+            }
+        }
+    }
+}
+
+impl<'a, S: hil::spi::SpiMasterDevice + 'a, A: hil::time::Alarm<'a> + 'a> hil::time::AlarmClient
+    for SpiNor<'a, S, A>
+{
+    fn alarm(&self) {
+        // Check whether the erase or program operation has finished.
+        self.txbuffer.take().map(|write_buffer| {
+            self.rxbuffer.take().map(move |read_buffer| {
+                write_buffer[0] = Opcodes::RDSR as u8;
+                self.spi
+                    .read_write_bytes(write_buffer, Some(read_buffer), 2);
+            });
+        });
+    }
+}
+
+impl<
+        'a,
+        S: hil::spi::SpiMasterDevice + 'a,
+        A: hil::time::Alarm<'a> + 'a,
+        C: hil::flash::Client<Self>,
+    > hil::flash::HasClient<'a, C> for SpiNor<'a, S, A>
+{
+    fn set_client(&self, client: &'a C) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, S: hil::spi::SpiMasterDevice + 'a, A: hil::time::Alarm<'a> + 'a> hil::flash::Flash
+    for SpiNor<'a, S, A>
+{
+    type Page = SpiNorSector;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        self.read_sector(page_number as u32, buf)
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        self.write_sector(page_number as u32, buf)
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        self.erase_sector(page_number as u32, false)
+    }
+}
@@ -0,0 +1,53 @@
+//! Test that processes survive interrupts at any point of a context switch.
+//!
+//! The test sets an alarm a few ticks in the future whenever the previous one
+//! fires, so that interrupts keep arriving while the kernel switches to and
+//! from processes. On chips that use `cortexm4::syscall::SysCallFp`, run it
+//! with processes that use the FPU: an interrupt handler returning with the
+//! wrong frame corrupts the kernel stack, which ends in a kernel panic, and a
+//! process whose floating-point registers are not restored computes wrong
+//! results. Depends on a working UART and debug! macro.
+
+use core::cell::Cell;
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient};
+
+/// Number of interrupts between progress messages.
+const REPORT_INTERVAL: usize = 10000;
+
+pub struct TestInterruptStorm<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    count: Cell<usize>,
+}
+
+impl<'a, A: Alarm<'a>> TestInterruptStorm<'a, A> {
+    pub fn new(alarm: &'a A) -> TestInterruptStorm<'a, A> {
+        TestInterruptStorm {
+            alarm: alarm,
+            count: Cell::new(0),
+        }
+    }
+
+    pub fn run(&self) {
+        debug!("Starting interrupt storm.");
+        self.set_next_alarm();
+    }
+
+    fn set_next_alarm(&self) {
+        // Vary the delay, so that interrupts hit different instructions of
+        // the context switch.
+        let delay = A::Ticks::from((self.count.get() % 7) as u32 + 1);
+        self.alarm.set_alarm(self.alarm.now(), delay);
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for TestInterruptStorm<'a, A> {
+    fn alarm(&self) {
+        let count = self.count.get() + 1;
+        self.count.set(count);
+        if count % REPORT_INTERVAL == 0 {
+            debug!("Interrupt storm: {} interrupts", count);
+        }
+        self.set_next_alarm();
+    }
+}
@@ -2,6 +2,7 @@ pub mod aes;
 pub mod aes_ccm;
 pub mod alarm;
 pub mod alarm_edge_cases;
+pub mod interrupt_storm;
 pub mod random_alarm;
 pub mod random_timer;
 pub mod rng;
@@ -0,0 +1,243 @@
+//! Virtualize the AES-CCM interface to enable multiple users of one AES-CCM
+//! engine.
+//!
+//! `MuxAES128CCM` serializes the operations of its `VirtualAES128CCM` users.
+//! Each user keeps its own key and nonce, which are loaded into the engine
+//! right before each of its operations, so for example the 802.15.4 framer and
+//! Thread MLE can secure their frames with different keys.
+//!
+//! An operation that is started while the engine is busy is queued, and
+//! `crypt()` returns `SUCCESS`. If the engine then refuses the operation, the
+//! error is reported through `crypt_done()`, with `tag_is_valid` false.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use kernel::hil::symmetric_encryption::AES128CCM;
+//!
+//! let mux_aes_ccm = static_init!(
+//!     capsules::virtual_aes_ccm::MuxAES128CCM<'static, AES128CCM<'static, Aes>>,
+//!     capsules::virtual_aes_ccm::MuxAES128CCM::new(aes_ccm)
+//! );
+//! aes_ccm.set_client(mux_aes_ccm);
+//!
+//! let framer_aes_ccm = static_init!(
+//!     capsules::virtual_aes_ccm::VirtualAES128CCM<'static, AES128CCM<'static, Aes>>,
+//!     capsules::virtual_aes_ccm::VirtualAES128CCM::new(mux_aes_ccm)
+//! );
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM, AES128_KEY_SIZE, CCM_NONCE_LENGTH};
+use kernel::ReturnCode;
+
+/// Handle keeping a list of the users of the AES-CCM engine and serialize
+/// their operations. After each completed operation the list is checked to
+/// see if another user has an operation queued.
+pub struct MuxAES128CCM<'a, A: AES128CCM<'a>> {
+    aes_ccm: &'a A,
+    users: List<'a, VirtualAES128CCM<'a, A>>,
+    inflight: OptionalCell<&'a VirtualAES128CCM<'a, A>>,
+}
+
+impl<'a, A: AES128CCM<'a>> MuxAES128CCM<'a, A> {
+    pub const fn new(aes_ccm: &'a A) -> MuxAES128CCM<'a, A> {
+        MuxAES128CCM {
+            aes_ccm: aes_ccm,
+            users: List::new(),
+            inflight: OptionalCell::empty(),
+        }
+    }
+
+    /// Load the key and nonce of `user` and start its operation.
+    fn start(
+        &self,
+        user: &'a VirtualAES128CCM<'a, A>,
+        buf: &'static mut [u8],
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let op = user.operation.get();
+        let res = self.aes_ccm.set_key(&user.key.get());
+        if res != ReturnCode::SUCCESS {
+            return (res, Some(buf));
+        }
+        let res = self.aes_ccm.set_nonce(&user.nonce.get());
+        if res != ReturnCode::SUCCESS {
+            return (res, Some(buf));
+        }
+        self.inflight.set(user);
+        let (res, buf) = self.aes_ccm.crypt(
+            buf,
+            op.a_off,
+            op.m_off,
+            op.m_len,
+            op.mic_len,
+            op.confidential,
+            op.encrypting,
+        );
+        if res != ReturnCode::SUCCESS {
+            self.inflight.clear();
+        }
+        (res, buf)
+    }
+
+    /// Start the queued operations in turn until one is accepted by the
+    /// engine, reporting those that are refused to their users.
+    fn do_next_op(&self) {
+        while self.inflight.is_none() {
+            let user = match self.users.iter().find(|user| user.buffer.is_some()) {
+                Some(user) => user,
+                None => return,
+            };
+            user.buffer.take().map(|buf| {
+                let (res, buf) = self.start(user, buf);
+                if res != ReturnCode::SUCCESS {
+                    buf.map(|buf| user.crypt_done(buf, res, false));
+                }
+            });
+        }
+    }
+}
+
+impl<'a, A: AES128CCM<'a>> CCMClient for MuxAES128CCM<'a, A> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        self.inflight.take().map(move |user| {
+            user.crypt_done(buf, res, tag_is_valid);
+        });
+        self.do_next_op();
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Operation {
+    a_off: usize,
+    m_off: usize,
+    m_len: usize,
+    mic_len: usize,
+    confidential: bool,
+    encrypting: bool,
+}
+
+/// Keep the key, nonce and queued operation of one user of the AES-CCM
+/// engine.
+pub struct VirtualAES128CCM<'a, A: AES128CCM<'a>> {
+    mux: &'a MuxAES128CCM<'a, A>,
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    nonce: Cell<[u8; CCM_NONCE_LENGTH]>,
+    /// Buffer of the operation waiting for the engine.
+    buffer: TakeCell<'static, [u8]>,
+    operation: Cell<Operation>,
+    next: ListLink<'a, VirtualAES128CCM<'a, A>>,
+    client: OptionalCell<&'a dyn CCMClient>,
+}
+
+impl<'a, A: AES128CCM<'a>> VirtualAES128CCM<'a, A> {
+    pub const fn new(mux: &'a MuxAES128CCM<'a, A>) -> VirtualAES128CCM<'a, A> {
+        VirtualAES128CCM {
+            mux: mux,
+            key: Cell::new([0; AES128_KEY_SIZE]),
+            nonce: Cell::new([0; CCM_NONCE_LENGTH]),
+            buffer: TakeCell::empty(),
+            operation: Cell::new(Operation {
+                a_off: 0,
+                m_off: 0,
+                m_len: 0,
+                mic_len: 0,
+                confidential: false,
+                encrypting: false,
+            }),
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn is_inflight(&self) -> bool {
+        self.mux
+            .inflight
+            .map_or(false, |user| core::ptr::eq(*user, self))
+    }
+}
+
+impl<'a, A: AES128CCM<'a>> ListNode<'a, VirtualAES128CCM<'a, A>> for VirtualAES128CCM<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, VirtualAES128CCM<'a, A>> {
+        &self.next
+    }
+}
+
+impl<'a, A: AES128CCM<'a>> CCMClient for VirtualAES128CCM<'a, A> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        self.client
+            .map(move |client| client.crypt_done(buf, res, tag_is_valid));
+    }
+}
+
+impl<'a, A: AES128CCM<'a>> AES128CCM<'a> for VirtualAES128CCM<'a, A> {
+    fn set_client(&'a self, client: &'a dyn CCMClient) {
+        if self.client.is_none() {
+            self.mux.users.push_head(self);
+        }
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> ReturnCode {
+        if key.len() != AES128_KEY_SIZE {
+            return ReturnCode::EINVAL;
+        }
+        let mut new_key = [0; AES128_KEY_SIZE];
+        new_key.copy_from_slice(key);
+        self.key.set(new_key);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> ReturnCode {
+        if nonce.len() != CCM_NONCE_LENGTH {
+            return ReturnCode::EINVAL;
+        }
+        let mut new_nonce = [0; CCM_NONCE_LENGTH];
+        new_nonce.copy_from_slice(nonce);
+        self.nonce.set(new_nonce);
+        ReturnCode::SUCCESS
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        mic_len: usize,
+        confidential: bool,
+        encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.buffer.is_some() || self.is_inflight() {
+            return (ReturnCode::EBUSY, Some(buf));
+        }
+        self.operation.set(Operation {
+            a_off: a_off,
+            m_off: m_off,
+            m_len: m_len,
+            mic_len: mic_len,
+            confidential: confidential,
+            encrypting: encrypting,
+        });
+        if self.mux.inflight.is_none() {
+            // The mux needs the `'a` reference to this user, which it has in
+            // its list once the client is set.
+            match self
+                .mux
+                .users
+                .iter()
+                .find(|user| core::ptr::eq(*user, self))
+            {
+                Some(user) => self.mux.start(user, buf),
+                None => (ReturnCode::EOFF, Some(buf)),
+            }
+        } else {
+            self.buffer.replace(buf);
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+}
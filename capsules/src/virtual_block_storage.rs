@@ -0,0 +1,297 @@
+//! Virtualize block storage devices.
+//!
+//! `MuxBlockDevice` provides shared access to a block storage device, such as
+//! an SD card, from multiple clients in the kernel. For instance, a board may
+//! keep a FAT filesystem at the start of an SD card and a log in its last
+//! blocks. Each client uses a `BlockDeviceUser`, which gives it a range of
+//! blocks of the device, numbered from 0, so that clients cannot change each
+//! other's blocks. Operations of different users run one at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! # use kernel::{hil, static_init};
+//!
+//! // Create the mux.
+//! let mux_block_device = static_init!(
+//!     capsules::virtual_block_storage::MuxBlockDevice<'static, SDCard<'static, Alarm>>,
+//!     capsules::virtual_block_storage::MuxBlockDevice::new(sdcard));
+//! hil::block_storage::BlockStorage::set_client(sdcard, mux_block_device);
+//!
+//! // A user of the whole device.
+//! let block_device = static_init!(
+//!     capsules::virtual_block_storage::BlockDeviceUser<'static, SDCard<'static, Alarm>>,
+//!     capsules::virtual_block_storage::BlockDeviceUser::new(mux_block_device, 0, usize::MAX));
+//! block_device.setup();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::ptr;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient};
+use kernel::ReturnCode;
+
+/// Keeps the list of users of a block device, and serializes their
+/// requests. After each completed request the list is checked for another
+/// user with a request waiting.
+pub struct MuxBlockDevice<'a, D: BlockStorage<'a>> {
+    device: &'a D,
+    users: List<'a, BlockDeviceUser<'a, D>>,
+    inflight: OptionalCell<&'a BlockDeviceUser<'a, D>>,
+}
+
+impl<'a, D: BlockStorage<'a>> BlockStorageClient for MuxBlockDevice<'a, D> {
+    fn read_done(&self, buffer: &'static mut [u8], result: ReturnCode) {
+        self.inflight.take().map(move |user| {
+            user.client
+                .map(move |client| client.read_done(buffer, result));
+        });
+        self.do_next_op();
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], result: ReturnCode) {
+        self.inflight.take().map(move |user| {
+            user.client
+                .map(move |client| client.write_done(buffer, result));
+        });
+        self.do_next_op();
+    }
+
+    fn erase_done(&self, result: ReturnCode) {
+        self.inflight.take().map(|user| {
+            user.client.map(|client| client.erase_done(result));
+        });
+        self.do_next_op();
+    }
+}
+
+impl<'a, D: BlockStorage<'a>> MuxBlockDevice<'a, D> {
+    pub const fn new(device: &'a D) -> MuxBlockDevice<'a, D> {
+        MuxBlockDevice {
+            device: device,
+            users: List::new(),
+            inflight: OptionalCell::empty(),
+        }
+    }
+
+    /// Start the request of `user` on the device. Returns the error, and the
+    /// buffer of the request, if it did not start.
+    fn start(
+        &self,
+        user: &'a BlockDeviceUser<'a, D>,
+    ) -> Result<(), (ReturnCode, Option<&'static mut [u8]>)> {
+        let operation = user.operation.replace(Op::Idle);
+        let result = match operation {
+            Op::Idle => return Ok(()),
+            Op::Read(block, count) => {
+                user.buffer
+                    .take()
+                    .map_or(Err((ReturnCode::ERESERVE, None)), |buffer| {
+                        self.device
+                            .read_blocks(buffer, user.first_block + block, count)
+                            .map_err(|(error, buffer)| (error, Some(buffer)))
+                    })
+            }
+            Op::Write(block, count) => {
+                user.buffer
+                    .take()
+                    .map_or(Err((ReturnCode::ERESERVE, None)), |buffer| {
+                        self.device
+                            .write_blocks(buffer, user.first_block + block, count)
+                            .map_err(|(error, buffer)| (error, Some(buffer)))
+                    })
+            }
+            Op::Erase(block, count) => {
+                match self.device.erase_blocks(user.first_block + block, count) {
+                    ReturnCode::SUCCESS => Ok(()),
+                    error => Err((error, None)),
+                }
+            }
+        };
+        if result.is_ok() {
+            self.inflight.set(user);
+        }
+        result
+    }
+
+    /// Start the request that `user` just made, if the device is free.
+    fn start_now(
+        &self,
+        user: &BlockDeviceUser<'a, D>,
+    ) -> Result<(), (ReturnCode, Option<&'static mut [u8]>)> {
+        if self.inflight.is_some() {
+            return Ok(());
+        }
+        // Users can only make requests through the reference they got when
+        // they were created, so find it in the list.
+        match self.users.iter().find(|node| ptr::eq(*node, user)) {
+            Some(user) => self.start(user),
+            None => {
+                // `setup()` was not called.
+                user.operation.set(Op::Idle);
+                Err((ReturnCode::ERESERVE, user.buffer.take()))
+            }
+        }
+    }
+
+    /// Start waiting requests until one is running on the device, or none
+    /// is left. Requests that fail to start complete with their error.
+    fn do_next_op(&self) {
+        while self.inflight.is_none() {
+            let user = match self
+                .users
+                .iter()
+                .find(|user| user.operation.get() != Op::Idle)
+            {
+                Some(user) => user,
+                None => return,
+            };
+            let operation = user.operation.get();
+            if let Err((error, buffer)) = self.start(user) {
+                user.client.map(move |client| match (operation, buffer) {
+                    (Op::Read(..), Some(buffer)) => client.read_done(buffer, error),
+                    (Op::Write(..), Some(buffer)) => client.write_done(buffer, error),
+                    (Op::Erase(..), _) => client.erase_done(error),
+                    _ => {}
+                });
+            }
+        }
+    }
+}
+
+/// Request of a user, with the first block, relative to the user's blocks,
+/// and the number of blocks.
+#[derive(Copy, Clone, PartialEq)]
+enum Op {
+    Idle,
+    Read(usize, usize),
+    Write(usize, usize),
+    Erase(usize, usize),
+}
+
+/// Keeps the state of each user of a block device. Users have blocks
+/// `first_block` to `first_block + number_of_blocks - 1` of the device, or
+/// the blocks from `first_block` to the end of the device, if it has fewer.
+pub struct BlockDeviceUser<'a, D: BlockStorage<'a>> {
+    mux: &'a MuxBlockDevice<'a, D>,
+    first_block: usize,
+    number_of_blocks: usize,
+    buffer: TakeCell<'static, [u8]>,
+    operation: Cell<Op>,
+    next: ListLink<'a, BlockDeviceUser<'a, D>>,
+    client: OptionalCell<&'a dyn BlockStorageClient>,
+}
+
+impl<'a, D: BlockStorage<'a>> BlockDeviceUser<'a, D> {
+    pub const fn new(
+        mux: &'a MuxBlockDevice<'a, D>,
+        first_block: usize,
+        number_of_blocks: usize,
+    ) -> BlockDeviceUser<'a, D> {
+        BlockDeviceUser {
+            mux: mux,
+            first_block: first_block,
+            number_of_blocks: number_of_blocks,
+            buffer: TakeCell::empty(),
+            operation: Cell::new(Op::Idle),
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Must be called right after `static_init!()`.
+    pub fn setup(&'a self) {
+        self.mux.users.push_head(self);
+    }
+
+    /// Check that a request can be made, and that its blocks are the user's.
+    fn check(&self, block: usize, count: usize) -> ReturnCode {
+        let is_inflight = self.mux.inflight.map_or(false, |user| ptr::eq(*user, self));
+        if self.operation.get() != Op::Idle || is_inflight {
+            ReturnCode::EBUSY
+        } else if count == 0 || block + count > self.number_of_blocks() {
+            ReturnCode::EINVAL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    /// Queue a request with a buffer, and start it if the device is free.
+    fn request(
+        &self,
+        operation: Op,
+        buffer: &'static mut [u8],
+        block: usize,
+        count: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        match self.check(block, count) {
+            ReturnCode::SUCCESS => {}
+            error => return Err((error, buffer)),
+        }
+        if buffer.len() < count * self.block_size() {
+            return Err((ReturnCode::ESIZE, buffer));
+        }
+        self.buffer.replace(buffer);
+        self.operation.set(operation);
+        self.mux
+            .start_now(self)
+            .map_err(|(error, buffer)| (error, buffer.unwrap_or(&mut [])))
+    }
+}
+
+impl<'a, D: BlockStorage<'a>> ListNode<'a, BlockDeviceUser<'a, D>> for BlockDeviceUser<'a, D> {
+    fn next(&'a self) -> &'a ListLink<'a, BlockDeviceUser<'a, D>> {
+        &self.next
+    }
+}
+
+impl<'a, D: BlockStorage<'a>> BlockStorage<'a> for BlockDeviceUser<'a, D> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient) {
+        self.client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        self.mux.device.block_size()
+    }
+
+    fn number_of_blocks(&self) -> usize {
+        let device_blocks = self.mux.device.number_of_blocks();
+        cmp::min(
+            self.number_of_blocks,
+            device_blocks.saturating_sub(self.first_block),
+        )
+    }
+
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: usize,
+        count: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        self.request(Op::Read(block, count), buffer, block, count)
+    }
+
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: usize,
+        count: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        self.request(Op::Write(block, count), buffer, block, count)
+    }
+
+    fn erase_blocks(&self, block: usize, count: usize) -> ReturnCode {
+        match self.check(block, count) {
+            ReturnCode::SUCCESS => {}
+            error => return error,
+        }
+        self.operation.set(Op::Erase(block, count));
+        match self.mux.start_now(self) {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err((error, _)) => error,
+        }
+    }
+}
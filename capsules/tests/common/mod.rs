@@ -0,0 +1,114 @@
+//! Fixtures shared by the tests of the networking stack.
+//!
+//! Not every test uses every fixture.
+#![allow(dead_code)]
+
+use std::cell::{Cell, RefCell};
+
+use capsules::net::ieee802154::{KeyId, SecurityLevel};
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use capsules::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use capsules::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::ReturnCode;
+use tock_hil_mock::leak;
+
+struct Capability;
+unsafe impl NetworkCapabilityCreationCapability for Capability {}
+
+/// Create a network capability that allows all addresses and ports.
+pub fn net_cap() -> &'static NetworkCapability {
+    leak(NetworkCapability::new(
+        AddrRange::Any,
+        PortRange::Any,
+        PortRange::Any,
+        &Capability,
+    ))
+}
+
+/// A packet passed to a `MockIP6Sender`.
+pub struct SentPacket {
+    pub src: IPAddr,
+    pub dst: IPAddr,
+    pub hop_limit: u8,
+    /// `TransportHeader::Raw` for forwarded packets
+    pub header: TransportHeader,
+    pub payload: Vec<u8>,
+}
+
+/// Implements `IP6Sender` by recording the packets passed to it. The test
+/// completes each send by calling `send_done` on the sending capsule.
+pub struct MockIP6Sender {
+    src_addr: Cell<IPAddr>,
+    security: Cell<Option<(SecurityLevel, KeyId)>>,
+    sent: RefCell<Vec<SentPacket>>,
+}
+
+impl MockIP6Sender {
+    pub fn new() -> MockIP6Sender {
+        MockIP6Sender {
+            src_addr: Cell::new(IPAddr::new()),
+            security: Cell::new(None),
+            sent: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The link-layer security most recently set by the capsule.
+    pub fn security(&self) -> Option<(SecurityLevel, KeyId)> {
+        self.security.get()
+    }
+
+    /// Take the packets passed to the sender since the last call.
+    pub fn take_sent(&self) -> Vec<SentPacket> {
+        self.sent.borrow_mut().drain(..).collect()
+    }
+}
+
+impl<'a> IP6Sender<'a> for MockIP6Sender {
+    fn set_client(&self, _client: &'a dyn IP6SendClient) {}
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    fn set_header(&mut self, _ip6_header: IP6Header) {}
+
+    fn set_security(&self, security: Option<(SecurityLevel, KeyId)>) {
+        self.security.set(security);
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> ReturnCode {
+        self.sent.borrow_mut().push(SentPacket {
+            src: self.src_addr.get(),
+            dst: dst,
+            hop_limit: IP6Header::default().hop_limit,
+            header: transport_header,
+            payload: payload[..].to_vec(),
+        });
+        ReturnCode::SUCCESS
+    }
+
+    fn forward(
+        &self,
+        ip6_header: IP6Header,
+        payload: &LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> ReturnCode {
+        self.sent.borrow_mut().push(SentPacket {
+            src: ip6_header.src_addr,
+            dst: ip6_header.dst_addr,
+            hop_limit: ip6_header.hop_limit,
+            header: TransportHeader::Raw(ip6_header.next_header, payload.len() as u16),
+            payload: payload[..].to_vec(),
+        });
+        ReturnCode::SUCCESS
+    }
+}
@@ -0,0 +1,570 @@
+//! Tests of `FatFilesystem` on mock block storage, with volumes formatted by
+//! the test, whose contents are checked with a separate FAT reader.
+
+use std::cell::{Cell, RefCell};
+
+use capsules::fat::filesystem::{short_name, Completion, Directory, FatClient, File};
+use capsules::fat::FatFilesystem;
+use kernel::hil::block_storage::BlockStorage;
+use kernel::ReturnCode;
+use tock_hil_mock::block_storage::{MockBlockStorage, BLOCK_SIZE};
+use tock_hil_mock::{leak, leak_buffer};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// Layout of a volume made by `format()`.
+#[derive(Clone, Copy, Debug)]
+struct Layout {
+    fat_type: FatType,
+    start: usize,
+    sectors_per_cluster: usize,
+    reserved: usize,
+    fat_sectors: usize,
+    root_entries: usize,
+}
+
+impl Layout {
+    fn fat_start(&self) -> usize {
+        self.start + self.reserved
+    }
+
+    fn root_start(&self) -> usize {
+        self.fat_start() + 2 * self.fat_sectors
+    }
+
+    fn data_start(&self) -> usize {
+        self.root_start() + self.root_entries * 32 / BLOCK_SIZE
+    }
+
+    fn cluster_offset(&self, cluster: usize) -> usize {
+        (self.data_start() + (cluster - 2) * self.sectors_per_cluster) * BLOCK_SIZE
+    }
+
+    fn cluster_bytes(&self) -> usize {
+        self.sectors_per_cluster * BLOCK_SIZE
+    }
+
+    /// FAT entry of `cluster` in FAT copy `fat`.
+    fn fat_entry(&self, image: &[u8], fat: usize, cluster: usize) -> usize {
+        let fat = &image[(self.fat_start() + fat * self.fat_sectors) * BLOCK_SIZE..];
+        match self.fat_type {
+            FatType::Fat12 => {
+                let pair = u16::from_le_bytes([fat[cluster * 3 / 2], fat[cluster * 3 / 2 + 1]]);
+                if cluster % 2 == 1 {
+                    (pair >> 4) as usize
+                } else {
+                    (pair & 0xfff) as usize
+                }
+            }
+            FatType::Fat16 => u16::from_le_bytes([fat[cluster * 2], fat[cluster * 2 + 1]]) as usize,
+            FatType::Fat32 => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(&fat[cluster * 4..cluster * 4 + 4]);
+                u32::from_le_bytes(bytes) as usize & 0x0fff_ffff
+            }
+        }
+    }
+
+    fn is_end(&self, entry: usize) -> bool {
+        match self.fat_type {
+            FatType::Fat12 => entry >= 0xff8,
+            FatType::Fat16 => entry >= 0xfff8,
+            FatType::Fat32 => entry >= 0x0fff_fff8,
+        }
+    }
+
+    /// The clusters of the chain that starts with `cluster`, checking that
+    /// both FATs agree.
+    fn chain(&self, image: &[u8], mut cluster: usize) -> Vec<usize> {
+        let mut chain = Vec::new();
+        while cluster != 0 && !self.is_end(cluster) {
+            chain.push(cluster);
+            let next = self.fat_entry(image, 0, cluster);
+            assert_eq!(next, self.fat_entry(image, 1, cluster));
+            cluster = next;
+        }
+        chain
+    }
+
+    /// The contents of a directory, or of the root directory if `cluster` is
+    /// 0, as (name, first cluster, size) of each entry.
+    fn list(&self, image: &[u8], cluster: usize) -> Vec<(String, usize, usize)> {
+        let mut data = Vec::new();
+        if cluster == 0 && self.fat_type != FatType::Fat32 {
+            let start = self.root_start() * BLOCK_SIZE;
+            data.extend_from_slice(&image[start..start + self.root_entries * 32]);
+        } else {
+            let cluster = if cluster == 0 { 2 } else { cluster };
+            for cluster in self.chain(image, cluster) {
+                let start = self.cluster_offset(cluster);
+                data.extend_from_slice(&image[start..start + self.cluster_bytes()]);
+            }
+        }
+        data.chunks(32)
+            .take_while(|entry| entry[0] != 0)
+            .filter(|entry| entry[0] != 0xe5)
+            .map(|entry| {
+                let name = String::from_utf8_lossy(&entry[..11]).into_owned();
+                let cluster = u16::from_le_bytes([entry[26], entry[27]]) as usize
+                    | (u16::from_le_bytes([entry[20], entry[21]]) as usize) << 16;
+                let size = u32::from_le_bytes([entry[28], entry[29], entry[30], entry[31]]);
+                (name, cluster, size as usize)
+            })
+            .collect()
+    }
+
+    /// Find a file by its path of 11 character names, and return its
+    /// contents.
+    fn read_file(&self, image: &[u8], path: &[&str]) -> Vec<u8> {
+        let mut cluster = 0;
+        let mut size = 0;
+        for name in path {
+            let entry = self
+                .list(image, cluster)
+                .into_iter()
+                .find(|entry| entry.0 == *name)
+                .unwrap_or_else(|| panic!("{} not found", name));
+            cluster = entry.1;
+            size = entry.2;
+        }
+        let mut data = Vec::new();
+        for cluster in self.chain(image, cluster) {
+            let start = self.cluster_offset(cluster);
+            data.extend_from_slice(&image[start..start + self.cluster_bytes()]);
+        }
+        assert!(data.len() >= size);
+        assert!(data.len() < size + self.cluster_bytes() || size == 0);
+        data.truncate(size);
+        data
+    }
+}
+
+/// Make a volume of `sectors` sectors, starting at sector `start`. A volume
+/// that does not start at sector 0 gets a master boot record.
+fn format(
+    blocks: usize,
+    start: usize,
+    sectors: usize,
+    sectors_per_cluster: usize,
+) -> (MockBlockStorage, Layout) {
+    let fat32 = sectors / sectors_per_cluster >= 65525;
+    let (reserved, root_entries) = if fat32 { (32, 0) } else { (1, 512) };
+    let clusters = sectors / sectors_per_cluster;
+    let fat_type = if clusters < 4085 {
+        FatType::Fat12
+    } else if fat32 {
+        FatType::Fat32
+    } else {
+        FatType::Fat16
+    };
+    let fat_bytes = match fat_type {
+        FatType::Fat12 => (clusters + 2) * 3 / 2 + 1,
+        FatType::Fat16 => (clusters + 2) * 2,
+        FatType::Fat32 => (clusters + 2) * 4,
+    };
+    let layout = Layout {
+        fat_type: fat_type,
+        start: start,
+        sectors_per_cluster: sectors_per_cluster,
+        reserved: reserved,
+        fat_sectors: (fat_bytes + BLOCK_SIZE - 1) / BLOCK_SIZE,
+        root_entries: root_entries,
+    };
+
+    let device = MockBlockStorage::new(blocks);
+    let mut boot = vec![0; BLOCK_SIZE];
+    boot[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+    boot[3..11].copy_from_slice(b"MSWIN4.1");
+    boot[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+    boot[13] = sectors_per_cluster as u8;
+    boot[14..16].copy_from_slice(&(reserved as u16).to_le_bytes());
+    boot[16] = 2;
+    boot[17..19].copy_from_slice(&(root_entries as u16).to_le_bytes());
+    boot[21] = 0xf8;
+    boot[32..36].copy_from_slice(&(sectors as u32).to_le_bytes());
+    if fat32 {
+        boot[36..40].copy_from_slice(&(layout.fat_sectors as u32).to_le_bytes());
+        boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+        boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+    } else {
+        boot[22..24].copy_from_slice(&(layout.fat_sectors as u16).to_le_bytes());
+    }
+    boot[510..].copy_from_slice(&[0x55, 0xaa]);
+    device.write_contents(start * BLOCK_SIZE, &boot);
+
+    if fat32 {
+        let mut fsinfo = vec![0; BLOCK_SIZE];
+        fsinfo[..4].copy_from_slice(b"RRaA");
+        fsinfo[484..488].copy_from_slice(b"rrAa");
+        fsinfo[488..492].copy_from_slice(&(clusters as u32 - 1).to_le_bytes());
+        fsinfo[492..496].copy_from_slice(&3u32.to_le_bytes());
+        fsinfo[510..].copy_from_slice(&[0x55, 0xaa]);
+        device.write_contents((start + 1) * BLOCK_SIZE, &fsinfo);
+    }
+
+    let fat: &[u8] = match fat_type {
+        FatType::Fat12 => &[0xf8, 0xff, 0xff],
+        FatType::Fat16 => &[0xf8, 0xff, 0xff, 0xff],
+        // Cluster 2 holds the root directory.
+        FatType::Fat32 => &[
+            0xf8, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f,
+        ],
+    };
+    for copy in 0..2 {
+        let offset = (layout.fat_start() + copy * layout.fat_sectors) * BLOCK_SIZE;
+        device.write_contents(offset, fat);
+    }
+
+    if start != 0 {
+        let mut mbr = vec![0; BLOCK_SIZE];
+        mbr[446 + 4] = if fat32 { 0x0c } else { 0x06 };
+        mbr[446 + 8..446 + 12].copy_from_slice(&(start as u32).to_le_bytes());
+        mbr[446 + 12..446 + 16].copy_from_slice(&(sectors as u32).to_le_bytes());
+        mbr[510..].copy_from_slice(&[0x55, 0xaa]);
+        device.write_contents(0, &mbr);
+    }
+    (device, layout)
+}
+
+#[derive(Default)]
+struct Client {
+    /// Data that write operations write.
+    data: RefCell<Vec<u8>>,
+    /// Data that read operations read.
+    read: RefCell<Vec<u8>>,
+    result: Cell<Option<Result<Completion, ReturnCode>>>,
+}
+
+impl FatClient for Client {
+    fn read_data(&self, offset: usize, data: &[u8]) {
+        let mut read = self.read.borrow_mut();
+        assert_eq!(read.len(), offset);
+        read.extend_from_slice(data);
+    }
+
+    fn write_data(&self, offset: usize, data: &mut [u8]) {
+        let len = data.len();
+        data.copy_from_slice(&self.data.borrow()[offset..offset + len]);
+    }
+
+    fn operation_done(&self, result: Result<Completion, ReturnCode>) {
+        assert_eq!(self.result.replace(Some(result)), None);
+    }
+}
+
+struct Fixture {
+    filesystem: &'static FatFilesystem<'static>,
+    device: &'static MockBlockStorage,
+    client: &'static Client,
+    layout: Layout,
+}
+
+impl Fixture {
+    fn new((device, layout): (MockBlockStorage, Layout)) -> Fixture {
+        let device = leak(device);
+        let filesystem = leak(FatFilesystem::new(device, leak_buffer(&[0; BLOCK_SIZE])));
+        let client = leak(Client::default());
+        device.set_client(filesystem);
+        filesystem.set_client(client);
+        let fixture = Fixture {
+            filesystem: filesystem,
+            device: device,
+            client: client,
+            layout: layout,
+        };
+        assert_eq!(filesystem.mount(), ReturnCode::SUCCESS);
+        assert_eq!(fixture.wait(), Ok(Completion::Mounted));
+        fixture
+    }
+
+    /// Complete device operations until the filesystem operation ends.
+    fn wait(&self) -> Result<Completion, ReturnCode> {
+        while self.device.complete() {}
+        self.client.result.take().expect("operation did not end")
+    }
+
+    fn directory(&self, parent: Directory, name: &str) -> Directory {
+        let name = short_name(name.as_bytes()).unwrap();
+        assert_eq!(
+            self.filesystem.open_directory(parent, name, true),
+            ReturnCode::SUCCESS
+        );
+        match self.wait() {
+            Ok(Completion::Directory(directory)) => directory,
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    fn open(
+        &self,
+        directory: Directory,
+        name: &str,
+        create: bool,
+        truncate: bool,
+    ) -> Result<File, ReturnCode> {
+        let name = short_name(name.as_bytes()).unwrap();
+        assert_eq!(
+            self.filesystem.open(directory, name, create, truncate),
+            ReturnCode::SUCCESS
+        );
+        match self.wait() {
+            Ok(Completion::File(file)) => Ok(file),
+            Ok(result) => panic!("unexpected result {:?}", result),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn write(&self, file: File, data: &[u8]) -> Result<(File, usize), ReturnCode> {
+        self.client.data.replace(data.to_vec());
+        assert_eq!(self.filesystem.write(file, data.len()), ReturnCode::SUCCESS);
+        match self.wait() {
+            Ok(Completion::Written(file, len)) => Ok((file, len)),
+            Ok(result) => panic!("unexpected result {:?}", result),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn read(&self, file: File, len: usize) -> (File, Vec<u8>) {
+        self.client.read.replace(Vec::new());
+        assert_eq!(self.filesystem.read(file, len), ReturnCode::SUCCESS);
+        match self.wait() {
+            Ok(Completion::Read(file, read)) => {
+                let data = self.client.read.replace(Vec::new());
+                assert_eq!(data.len(), read);
+                (file, data)
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    fn list(&self, directory: Directory) -> Vec<(String, usize)> {
+        let mut entries = Vec::new();
+        loop {
+            assert_eq!(
+                self.filesystem.read_directory(directory, entries.len()),
+                ReturnCode::SUCCESS
+            );
+            match self.wait() {
+                Ok(Completion::Entry(Some(entry))) => {
+                    let mut name = [0; 12];
+                    let len = entry.format_name(&mut name);
+                    entries.push((
+                        String::from_utf8_lossy(&name[..len]).into_owned(),
+                        entry.size as usize,
+                    ));
+                }
+                Ok(Completion::Entry(None)) => return entries,
+                result => panic!("unexpected result {:?}", result),
+            }
+        }
+    }
+}
+
+fn pattern(len: usize, seed: usize) -> Vec<u8> {
+    (0..len).map(|i| ((i * 7 + seed) % 251) as u8).collect()
+}
+
+#[test]
+fn short_names() {
+    assert_eq!(short_name(b"log.txt"), Ok(*b"LOG     TXT"));
+    assert_eq!(short_name(b"DATA_01"), Ok(*b"DATA_01    "));
+    assert_eq!(short_name(b"a.b.c"), Err(ReturnCode::EINVAL));
+    assert_eq!(short_name(b"toolongname"), Err(ReturnCode::EINVAL));
+    assert_eq!(short_name(b"log.text"), Err(ReturnCode::EINVAL));
+    assert_eq!(short_name(b"a b"), Err(ReturnCode::EINVAL));
+    assert_eq!(short_name(b"../x"), Err(ReturnCode::EINVAL));
+    assert_eq!(short_name(b".txt"), Err(ReturnCode::EINVAL));
+}
+
+#[test]
+fn fat16_files() {
+    let fixture = Fixture::new(format(8192, 0, 8192, 1));
+    assert_eq!(fixture.layout.fat_type, FatType::Fat16);
+    let root = fixture.filesystem.root().unwrap();
+    let directory = fixture.directory(root, "apps");
+    assert_eq!(fixture.directory(root, "APPS"), directory);
+
+    assert_eq!(
+        fixture.open(directory, "log.txt", false, false),
+        Err(ReturnCode::EINVAL)
+    );
+    let file = fixture.open(directory, "log.txt", true, false).unwrap();
+    assert_eq!(file.size(), 0);
+
+    // Write across sectors and clusters, in pieces that do not line up
+    // with them.
+    let data = pattern(3000, 0);
+    let (file, len) = fixture.write(file, &data[..700]).unwrap();
+    assert_eq!(len, 700);
+    let (mut file, _) = fixture.write(file, &data[700..]).unwrap();
+    assert_eq!((file.size(), file.position()), (3000, 3000));
+
+    assert_eq!(file.seek(3001), ReturnCode::EINVAL);
+    assert_eq!(file.seek(500), ReturnCode::SUCCESS);
+    let (file, read) = fixture.read(file, 1000);
+    assert_eq!(read, &data[500..1500]);
+    assert_eq!(file.position(), 1500);
+    let (_, read) = fixture.read(file, 5000);
+    assert_eq!(read, &data[1500..]);
+
+    // Overwrite part of the file.
+    let mut file = fixture.open(directory, "LOG.TXT", false, false).unwrap();
+    assert_eq!(file.size(), 3000);
+    file.seek(1000);
+    let (_, _) = fixture.write(file, &[0xaa; 10]).unwrap();
+    let mut expected = data.clone();
+    expected[1000..1010].copy_from_slice(&[0xaa; 10]);
+
+    let image = fixture.device.contents();
+    let layout = fixture.layout;
+    assert_eq!(
+        layout.read_file(&image, &["APPS       ", "LOG     TXT"]),
+        expected
+    );
+    let entries = layout.list(&image, 0);
+    assert_eq!(entries.len(), 1);
+    let apps = layout.list(&image, entries[0].1);
+    assert_eq!(apps[0].0, ".          ");
+    assert_eq!(apps[1].0, "..         ");
+    assert_eq!(apps[1].1, 0);
+    assert_eq!(
+        fixture.list(directory),
+        vec![(String::from("LOG.TXT"), 3000)]
+    );
+
+    // Truncating frees the clusters.
+    let clusters = layout.chain(&image, layout.list(&image, entries[0].1)[2].1);
+    assert_eq!(clusters.len(), 6);
+    let file = fixture.open(directory, "log.txt", true, true).unwrap();
+    assert_eq!(file.size(), 0);
+    let image = fixture.device.contents();
+    for cluster in clusters {
+        assert_eq!(layout.fat_entry(&image, 0, cluster), 0);
+        assert_eq!(layout.fat_entry(&image, 1, cluster), 0);
+    }
+    assert_eq!(fixture.list(directory), vec![(String::from("LOG.TXT"), 0)]);
+}
+
+#[test]
+fn fat12_entries_across_sectors() {
+    let fixture = Fixture::new(format(4000, 0, 4000, 1));
+    assert_eq!(fixture.layout.fat_type, FatType::Fat12);
+    let root = fixture.filesystem.root().unwrap();
+    let file = fixture.open(root, "big.bin", true, false).unwrap();
+
+    // 400 clusters, so that some FAT entries span two sectors.
+    let data = pattern(400 * BLOCK_SIZE, 3);
+    let (file, len) = fixture.write(file, &data).unwrap();
+    assert_eq!(len, data.len());
+
+    let image = fixture.device.contents();
+    assert_eq!(fixture.layout.read_file(&image, &["BIG     BIN"]), data);
+
+    let mut file = file;
+    file.seek(341 * BLOCK_SIZE - 10);
+    let (_, read) = fixture.read(file, 20);
+    assert_eq!(read, &data[341 * BLOCK_SIZE - 10..341 * BLOCK_SIZE + 10]);
+}
+
+#[test]
+fn fat32_partition() {
+    let fixture = Fixture::new(format(2048 + 70000, 2048, 70000, 1));
+    assert_eq!(fixture.layout.fat_type, FatType::Fat32);
+    let image = fixture.device.contents();
+    // Mounting marks the free cluster count unknown.
+    let fsinfo = (2048 + 1) * BLOCK_SIZE;
+    assert_eq!(image[fsinfo + 488..fsinfo + 496], [0xff; 8]);
+
+    let root = fixture.filesystem.root().unwrap();
+    let directory = fixture.directory(root, "apps");
+    let directory = fixture.directory(directory, "logger");
+    let file = fixture.open(directory, "data.csv", true, false).unwrap();
+    let data = pattern(1500, 5);
+    fixture.write(file, &data).unwrap();
+
+    let image = fixture.device.contents();
+    let layout = fixture.layout;
+    assert_eq!(
+        layout.read_file(&image, &["APPS       ", "LOGGER     ", "DATA    CSV"]),
+        data
+    );
+    // ".." of a directory in the root refers to cluster 0.
+    let apps = layout.list(&image, 0)[0].1;
+    let logger = layout.list(&image, apps)[2].1;
+    assert_eq!(
+        layout.list(&image, logger)[1],
+        (String::from("..         "), apps, 0)
+    );
+    assert_eq!(layout.list(&image, apps)[1].1, 0);
+}
+
+#[test]
+fn directories_grow() {
+    let fixture = Fixture::new(format(8192, 0, 8192, 1));
+    let root = fixture.filesystem.root().unwrap();
+    let directory = fixture.directory(root, "apps");
+    let mut names = Vec::new();
+    for i in 0..40 {
+        let name = format!("FILE{}.TXT", i);
+        fixture.open(directory, &name, true, false).unwrap();
+        names.push((name, 0));
+    }
+    assert_eq!(fixture.list(directory), names);
+
+    let image = fixture.device.contents();
+    let layout = fixture.layout;
+    let apps = layout.list(&image, 0)[0].1;
+    // 42 entries with "." and "..", in 16 entry clusters.
+    assert_eq!(layout.chain(&image, apps).len(), 3);
+    assert_eq!(layout.list(&image, apps).len(), 42);
+}
+
+#[test]
+fn full_volume() {
+    // 132 clusters of 2 sectors, fewer than the data needs.
+    let fixture = Fixture::new(format(300, 0, 300, 2));
+    let root = fixture.filesystem.root().unwrap();
+    let file = fixture.open(root, "fill", true, false).unwrap();
+    let data = pattern(300 * BLOCK_SIZE, 0);
+    let clusters = (300 - fixture.layout.data_start()) / 2;
+    let (file, len) = fixture.write(file, &data).unwrap();
+    assert_eq!(len, clusters * 2 * BLOCK_SIZE);
+    assert_eq!(file.size(), len);
+    assert_eq!(fixture.write(file, &[1]), Err(ReturnCode::ENOMEM));
+
+    let image = fixture.device.contents();
+    assert_eq!(
+        fixture.layout.read_file(&image, &["FILL       "]),
+        &data[..len]
+    );
+}
+
+#[test]
+fn device_errors() {
+    let fixture = Fixture::new(format(8192, 0, 8192, 1));
+    let root = fixture.filesystem.root().unwrap();
+    fixture.device.fail_next_completion();
+    assert_eq!(
+        fixture.filesystem.open(root, *b"LOG     TXT", true, false),
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(fixture.wait(), Err(ReturnCode::FAIL));
+    assert!(fixture.open(root, "log.txt", true, false).is_ok());
+
+    let unformatted = Fixture {
+        filesystem: leak(FatFilesystem::new(
+            leak(MockBlockStorage::new(16)),
+            leak_buffer(&[0; BLOCK_SIZE]),
+        )),
+        ..fixture
+    };
+    assert_eq!(
+        unformatted.filesystem.read_directory(root, 0),
+        ReturnCode::EOFF
+    );
+}
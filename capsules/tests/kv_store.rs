@@ -0,0 +1,292 @@
+//! Tests of `FlashKVStore` with memory-mapped mock flash.
+
+use std::cell::RefCell;
+
+use capsules::kv_store::FlashKVStore;
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+    DynamicDeferredCallClientState,
+};
+use kernel::hil::flash::{self, HasClient};
+use kernel::hil::kv_store::{KVStore, KVStoreClient};
+use kernel::ReturnCode;
+use tock_hil_mock::flash::{MockFlash, MockPage};
+use tock_hil_mock::{leak, leak_buffer};
+
+#[derive(Debug, PartialEq)]
+enum Done {
+    Get(ReturnCode, Vec<u8>),
+    Set(ReturnCode),
+    Delete(ReturnCode),
+    GarbageCollect(ReturnCode),
+}
+
+#[derive(Default)]
+struct Client {
+    done: RefCell<Vec<Done>>,
+}
+
+impl KVStoreClient for Client {
+    fn get_done(&self, result: ReturnCode, value: &'static mut [u8], length: usize) {
+        let length = length.min(value.len());
+        self.done
+            .borrow_mut()
+            .push(Done::Get(result, value[..length].to_vec()));
+    }
+
+    fn set_done(&self, result: ReturnCode, _value: &'static mut [u8]) {
+        self.done.borrow_mut().push(Done::Set(result));
+    }
+
+    fn delete_done(&self, result: ReturnCode) {
+        self.done.borrow_mut().push(Done::Delete(result));
+    }
+
+    fn garbage_collect_done(&self, result: ReturnCode) {
+        self.done.borrow_mut().push(Done::GarbageCollect(result));
+    }
+}
+
+struct Store {
+    store: &'static FlashKVStore<'static, MockFlash>,
+    flash: &'static MockFlash,
+    client: &'static Client,
+    handle: DeferredCallHandle,
+}
+
+impl Store {
+    /// Mount a store on the contents of `flash`, as after a reboot.
+    fn mount(flash: &'static MockFlash) -> Store {
+        let slots = Box::leak(Box::new([DynamicDeferredCallClientState::default()]));
+        let deferred_caller = leak(DynamicDeferredCall::new(slots));
+        let store = leak(FlashKVStore::new(
+            flash.memory(),
+            flash,
+            Box::leak(Box::new(MockPage::default())),
+            deferred_caller,
+        ));
+        let handle = deferred_caller.register(store).unwrap();
+        store.initialize_callback_handle(handle);
+        let client = leak(Client::default());
+        store.set_client(client);
+        flash.set_client(store);
+        Store {
+            store: store,
+            flash: flash,
+            client: client,
+            handle: handle,
+        }
+    }
+
+    /// Complete flash operations until none is in progress, then make the
+    /// deferred call that reports the result.
+    fn run(&self) -> Done {
+        while self.flash.complete() {}
+        self.store.call(self.handle);
+        let mut done = self.client.done.borrow_mut();
+        assert_eq!(done.len(), 1);
+        done.pop().unwrap()
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Vec<u8>, ReturnCode> {
+        assert!(self.store.get(key, leak_buffer(&[0; 512])).is_ok());
+        match self.run() {
+            Done::Get(ReturnCode::SUCCESS, value) => Ok(value),
+            Done::Get(result, _) => Err(result),
+            done => panic!("unexpected {:?}", done),
+        }
+    }
+
+    fn set(&self, key: &[u8], value: &[u8]) -> ReturnCode {
+        assert!(self.store.set(key, leak_buffer(value), value.len()).is_ok());
+        match self.run() {
+            Done::Set(result) => result,
+            done => panic!("unexpected {:?}", done),
+        }
+    }
+
+    fn delete(&self, key: &[u8]) -> ReturnCode {
+        assert_eq!(self.store.delete(key), ReturnCode::SUCCESS);
+        match self.run() {
+            Done::Delete(result) => result,
+            done => panic!("unexpected {:?}", done),
+        }
+    }
+
+    fn garbage_collect(&self) -> ReturnCode {
+        assert_eq!(self.store.garbage_collect(), ReturnCode::SUCCESS);
+        match self.run() {
+            Done::GarbageCollect(result) => result,
+            done => panic!("unexpected {:?}", done),
+        }
+    }
+}
+
+/// Whether `value` is stored anywhere in the flash.
+fn in_flash(flash: &MockFlash, value: &[u8]) -> bool {
+    flash
+        .memory()
+        .windows(value.len())
+        .any(|window| window == value)
+}
+
+const OLD: [u8; 200] = [0xaa; 200];
+const NEW: [u8; 210] = [0xbb; 210];
+const FILLER: [u8; 270] = [0xcc; 270];
+
+/// Store `OLD` and then `NEW` as the value of "k", but lose power after
+/// `NEW` is written to a different page, before `OLD` is removed. `OLD`
+/// shares its page with the value of "f".
+fn spread_key() -> &'static MockFlash {
+    let flash = leak(MockFlash::new_mapped(4));
+    let store = Store::mount(flash);
+    assert_eq!(store.set(b"k", &OLD), ReturnCode::SUCCESS);
+    assert_eq!(store.set(b"f", &FILLER), ReturnCode::SUCCESS);
+    assert!(store.store.set(b"k", leak_buffer(&NEW), NEW.len()).is_ok());
+    assert!(flash.complete());
+    flash.lose_power();
+    flash
+}
+
+#[test]
+fn set_get_and_delete() {
+    let flash = leak(MockFlash::new_mapped(4));
+    let store = Store::mount(flash);
+
+    assert_eq!(store.get(b"key"), Err(ReturnCode::FAIL));
+    assert_eq!(store.set(b"key", b"one"), ReturnCode::SUCCESS);
+    assert_eq!(store.get(b"key"), Ok(b"one".to_vec()));
+    assert_eq!(store.set(b"key", b"two"), ReturnCode::SUCCESS);
+    assert_eq!(store.get(b"key"), Ok(b"two".to_vec()));
+    assert_eq!(store.delete(b"key"), ReturnCode::SUCCESS);
+    assert_eq!(store.get(b"key"), Err(ReturnCode::FAIL));
+    assert_eq!(store.delete(b"key"), ReturnCode::FAIL);
+    assert!(!in_flash(flash, b"one"));
+    assert!(!in_flash(flash, b"two"));
+}
+
+#[test]
+fn values_survive_remount() {
+    let flash = leak(MockFlash::new_mapped(4));
+    let store = Store::mount(flash);
+    assert_eq!(store.set(b"a", b"alpha"), ReturnCode::SUCCESS);
+    assert_eq!(store.set(b"b", b"beta"), ReturnCode::SUCCESS);
+    assert_eq!(store.delete(b"a"), ReturnCode::SUCCESS);
+
+    let store = Store::mount(flash);
+    assert_eq!(store.get(b"a"), Err(ReturnCode::FAIL));
+    assert_eq!(store.get(b"b"), Ok(b"beta".to_vec()));
+    assert_eq!(store.set(b"b", b"gamma"), ReturnCode::SUCCESS);
+    assert_eq!(store.get(b"b"), Ok(b"gamma".to_vec()));
+}
+
+#[test]
+fn garbage_collection_reclaims_space() {
+    let flash = leak(MockFlash::new_mapped(4));
+    let store = Store::mount(flash);
+
+    // Fill the store.
+    let mut keys = Vec::new();
+    loop {
+        let key = format!("key{}", keys.len()).into_bytes();
+        match store.set(&key, &[keys.len() as u8; 150]) {
+            ReturnCode::SUCCESS => keys.push(key),
+            result => {
+                assert_eq!(result, ReturnCode::ENOMEM);
+                break;
+            }
+        }
+    }
+    assert!(keys.len() >= 6);
+
+    // Values can still be replaced and removed in a full store.
+    assert_eq!(store.set(&keys[0], &[0xff; 150]), ReturnCode::SUCCESS);
+    for key in keys.iter().skip(1).step_by(2) {
+        assert_eq!(store.delete(key), ReturnCode::SUCCESS);
+    }
+    assert_eq!(store.garbage_collect(), ReturnCode::SUCCESS);
+
+    // The space of the removed values can be used again.
+    for i in 0..keys.len() / 2 {
+        let key = format!("new{}", i).into_bytes();
+        assert_eq!(store.set(&key, &[0x55; 150]), ReturnCode::SUCCESS);
+    }
+    assert_eq!(store.get(&keys[0]), Ok(vec![0xff; 150]));
+    for (i, key) in keys.iter().enumerate().skip(2).step_by(2) {
+        assert_eq!(store.get(key), Ok(vec![i as u8; 150]));
+    }
+}
+
+#[test]
+fn power_loss_during_set_keeps_a_value() {
+    let flash = spread_key();
+    let store = Store::mount(flash);
+    assert_eq!(store.get(b"k"), Ok(NEW.to_vec()));
+    assert_eq!(store.get(b"f"), Ok(FILLER.to_vec()));
+    assert!(in_flash(flash, &OLD));
+
+    // The outdated value is removed when the key is written again.
+    assert_eq!(store.set(b"k", b"newer"), ReturnCode::SUCCESS);
+    assert!(!in_flash(flash, &OLD));
+    assert!(!in_flash(flash, &NEW));
+    assert_eq!(store.get(b"k"), Ok(b"newer".to_vec()));
+    assert_eq!(store.get(b"f"), Ok(FILLER.to_vec()));
+}
+
+#[test]
+fn power_loss_during_delete_does_not_restore_old_value() {
+    let mut cut = 0;
+    loop {
+        let flash = spread_key();
+        let store = Store::mount(flash);
+        assert_eq!(store.store.delete(b"k"), ReturnCode::SUCCESS);
+        for _ in 0..cut {
+            flash.complete();
+        }
+        let finished = !flash.is_busy();
+
+        // Lose power after `cut` flash operations.
+        flash.lose_power();
+        let store = Store::mount(flash);
+        let value = store.get(b"k");
+        if cut == 0 {
+            assert_eq!(value, Ok(NEW.to_vec()));
+        } else {
+            // The first write stores the tombstone.
+            assert_eq!(value, Err(ReturnCode::FAIL), "power lost after {}", cut);
+        }
+        if cut == 1 {
+            // The tombstone hides the old value that is still in flash.
+            assert!(in_flash(flash, &OLD));
+        }
+        assert_eq!(store.get(b"f"), Ok(FILLER.to_vec()));
+
+        // Garbage collection removes what the delete left behind.
+        assert_eq!(store.garbage_collect(), ReturnCode::SUCCESS);
+        if cut > 0 {
+            assert!(!in_flash(flash, &OLD));
+            assert!(!in_flash(flash, &NEW));
+        }
+        assert_eq!(store.set(b"k", b"again"), ReturnCode::SUCCESS);
+        assert_eq!(store.get(b"k"), Ok(b"again".to_vec()));
+
+        if finished {
+            break;
+        }
+        cut += 1;
+    }
+    assert!(cut >= 3);
+}
+
+#[test]
+fn stray_read_completion_is_ignored() {
+    let flash = leak(MockFlash::new_mapped(4));
+    let store = Store::mount(flash);
+    flash::Client::read_complete(
+        store.store,
+        Box::leak(Box::new(MockPage::default())),
+        flash::Error::CommandComplete,
+    );
+    assert_eq!(store.set(b"key", b"value"), ReturnCode::SUCCESS);
+    assert_eq!(store.get(b"key"), Ok(b"value".to_vec()));
+}
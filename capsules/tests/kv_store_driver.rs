@@ -0,0 +1,172 @@
+//! Tests of `KVStoreDriver` on a `FlashKVStore` with mock flash and
+//! processes.
+
+use capsules::kv_store::FlashKVStore;
+use capsules::kv_store_driver::KVStoreDriver;
+use kernel::capabilities::MemoryAllocationCapability;
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+    DynamicDeferredCallClientState,
+};
+use kernel::hil::flash::HasClient;
+use kernel::hil::kv_store::KVStore;
+use kernel::procs::ProcessType;
+use kernel::{Driver, ReturnCode};
+use tock_hil_mock::flash::{MockFlash, MockPage};
+use tock_hil_mock::process::{mock_kernel, MockProcess, SharedBuffer};
+use tock_hil_mock::{leak, leak_buffer};
+
+struct Capability;
+unsafe impl MemoryAllocationCapability for Capability {}
+
+const GET: usize = 1;
+const SET: usize = 2;
+const DELETE: usize = 3;
+
+struct Fixture {
+    driver: &'static KVStoreDriver<'static>,
+    store: &'static FlashKVStore<'static, MockFlash>,
+    flash: &'static MockFlash,
+    handle: DeferredCallHandle,
+    processes: Vec<&'static MockProcess>,
+}
+
+fn setup() -> Fixture {
+    let (kernel, processes) = mock_kernel(&["alpha", "beta"]);
+    let flash = leak(MockFlash::new_mapped(4));
+    let slots = Box::leak(Box::new([DynamicDeferredCallClientState::default()]));
+    let deferred_caller = leak(DynamicDeferredCall::new(slots));
+    let store = leak(FlashKVStore::new(
+        flash.memory(),
+        flash,
+        Box::leak(Box::new(MockPage::default())),
+        deferred_caller,
+    ));
+    let handle = deferred_caller.register(store).unwrap();
+    store.initialize_callback_handle(handle);
+    flash.set_client(store);
+    let driver = leak(KVStoreDriver::new(
+        store,
+        kernel.create_grant(&Capability),
+        leak_buffer(&[0; 128]),
+    ));
+    store.set_client(driver);
+    Fixture {
+        driver: driver,
+        store: store,
+        flash: flash,
+        handle: handle,
+        processes: processes,
+    }
+}
+
+impl Fixture {
+    /// Share `key` and a value buffer holding `value` with the driver on
+    /// behalf of `process`, and return the value buffer.
+    fn allow(&self, process: usize, key: &[u8], value: &[u8]) -> SharedBuffer {
+        let process = self.processes[process];
+        let (key, _) = process.share(key);
+        let (value, buffer) = process.share(value);
+        assert_eq!(
+            self.driver.allow(process.appid(), 0, Some(key)),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(
+            self.driver.allow(process.appid(), 1, Some(value)),
+            ReturnCode::SUCCESS
+        );
+        buffer
+    }
+
+    fn command(&self, process: usize, command_num: usize, arg1: usize) -> ReturnCode {
+        self.driver
+            .command(command_num, arg1, 0, self.processes[process].appid())
+    }
+
+    /// Complete the operation the store is executing.
+    fn run(&self) {
+        while self.flash.complete() {}
+        self.store.call(self.handle);
+    }
+
+    /// Set the value of `key` for `process` and wait until it is stored.
+    fn set(&self, process: usize, key: &[u8], value: &[u8]) {
+        self.allow(process, key, value);
+        assert_eq!(self.command(process, SET, value.len()), ReturnCode::SUCCESS);
+        self.run();
+    }
+
+    /// Read the value of `key` for `process` into a buffer of `len` zeros.
+    fn get(&self, process: usize, key: &[u8], len: usize) -> Vec<u8> {
+        let buffer = self.allow(process, key, &vec![0; len]);
+        assert_eq!(self.command(process, GET, 0), ReturnCode::SUCCESS);
+        self.run();
+        buffer.contents()
+    }
+}
+
+/// Whether `data` is stored anywhere in the flash.
+fn in_flash(flash: &MockFlash, data: &[u8]) -> bool {
+    flash
+        .memory()
+        .windows(data.len())
+        .any(|window| window == data)
+}
+
+#[test]
+fn keys_are_namespaced_by_package_name() {
+    let fixture = setup();
+    fixture.set(0, b"key", b"one");
+    assert!(in_flash(fixture.flash, b"alpha\0key"));
+
+    // The other process does not see the value.
+    assert_eq!(fixture.get(1, b"key", 3), vec![0; 3]);
+    fixture.set(1, b"key", b"two");
+    assert!(in_flash(fixture.flash, b"beta\0key"));
+
+    assert_eq!(fixture.get(0, b"key", 3), b"one");
+    assert_eq!(fixture.get(1, b"key", 3), b"two");
+
+    // Deleting the value of one process leaves the other.
+    fixture.allow(1, b"key", &[]);
+    assert_eq!(fixture.command(1, DELETE, 0), ReturnCode::SUCCESS);
+    fixture.run();
+    assert!(!in_flash(fixture.flash, b"beta\0key"));
+    assert_eq!(fixture.get(0, b"key", 3), b"one");
+}
+
+#[test]
+fn operations_of_processes_are_queued() {
+    let fixture = setup();
+    fixture.allow(0, b"key", b"one");
+    fixture.allow(1, b"key", b"two");
+    assert_eq!(fixture.command(0, SET, 3), ReturnCode::SUCCESS);
+    assert_eq!(fixture.command(1, SET, 3), ReturnCode::SUCCESS);
+    // A process can only start one operation at a time.
+    assert_eq!(fixture.command(0, GET, 0), ReturnCode::EBUSY);
+    assert_eq!(fixture.command(1, GET, 0), ReturnCode::EBUSY);
+
+    // The operation of the second process starts when the first completes.
+    fixture.run();
+    assert!(in_flash(fixture.flash, b"alpha\0key"));
+    assert!(!in_flash(fixture.flash, b"beta\0key"));
+    fixture.run();
+    assert!(in_flash(fixture.flash, b"beta\0key"));
+
+    assert_eq!(fixture.get(0, b"key", 3), b"one");
+    assert_eq!(fixture.get(1, b"key", 3), b"two");
+}
+
+#[test]
+fn invalid_requests_are_rejected() {
+    let fixture = setup();
+    // No key has been shared.
+    assert_eq!(fixture.command(0, GET, 0), ReturnCode::ERESERVE);
+    // The length is larger than the value buffer.
+    fixture.allow(0, b"key", b"one");
+    assert_eq!(fixture.command(0, SET, 4), ReturnCode::EINVAL);
+    // The namespaced key is too long for the store.
+    fixture.allow(0, &[b'k'; 64], b"one");
+    assert_eq!(fixture.command(0, SET, 3), ReturnCode::EINVAL);
+    assert!(!fixture.flash.is_busy());
+}
@@ -0,0 +1,548 @@
+//! Tests of the ICMPv6 echo responder, 6LoWPAN Neighbor Discovery and the
+//! neighbor cache, with a mock IP sender and alarm.
+
+mod common;
+
+use capsules::net::icmpv6::icmpv6::{na_flags, ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use capsules::net::icmpv6::icmpv6_nd::{
+    aro_status, pio_flags, NDOption, NeighborDiscovery, ALL_NODES_ADDR, ALL_ROUTERS_ADDR,
+    MAX_UNICAST_SOLICIT, ND_TIMER_MS, REGISTRATION_LIFETIME, RTR_SOLICITATION_INTERVAL,
+};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use capsules::net::ipv6::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_recv::IP6RecvClient;
+use capsules::net::ipv6::ipv6_send::IP6SendClient;
+use capsules::net::ipv6::neighbor_cache::{NeighborCache, NeighborEntry, BROADCAST_MAC_ADDR};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::Alarm;
+use kernel::ReturnCode;
+use tock_hil_mock::alarm::MockAlarm;
+use tock_hil_mock::{leak, leak_buffer};
+
+use common::{MockIP6Sender, SentPacket};
+
+const NODE_MAC: MacAddress = MacAddress::Long([0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+const ROUTER_MAC: MacAddress = MacAddress::Short(0x1234);
+const PEER_MAC: MacAddress = MacAddress::Short(0x00aa);
+const PREFIX: [u8; 8] = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 1];
+
+/// An ICMPv6 message passed to the IP layer.
+#[derive(Clone)]
+struct Sent {
+    src: IPAddr,
+    dst: IPAddr,
+    header: ICMP6Header,
+    payload: Vec<u8>,
+}
+
+impl Sent {
+    fn new(packet: SentPacket) -> Sent {
+        let header = match packet.header {
+            TransportHeader::ICMP(header) => header,
+            _ => panic!("not an ICMPv6 message"),
+        };
+        Sent {
+            src: packet.src,
+            dst: packet.dst,
+            header: header,
+            payload: packet.payload,
+        }
+    }
+}
+
+struct Node {
+    nd: &'static NeighborDiscovery<'static, MockAlarm<'static>>,
+    cache: &'static NeighborCache<'static>,
+    ip: &'static MockIP6Sender,
+    alarm: &'static MockAlarm<'static>,
+}
+
+impl Node {
+    fn new() -> Node {
+        let ip = leak(MockIP6Sender::new());
+        let alarm = leak(MockAlarm::new());
+        let cache = leak(NeighborCache::new(Box::leak(Box::new(
+            [None; 4] as [Option<NeighborEntry>; 4],
+        ))));
+        let nd = leak(NeighborDiscovery::new(
+            ip,
+            cache,
+            alarm,
+            NODE_MAC,
+            &[],
+            leak_buffer(&[0; 64]),
+            common::net_cap(),
+        ));
+        alarm.set_alarm_client(nd);
+        Node {
+            nd: nd,
+            cache: cache,
+            ip: ip,
+            alarm: alarm,
+        }
+    }
+
+    /// Takes the messages passed to the IP layer, completing each send.
+    fn take_sent(&self) -> Vec<Sent> {
+        let mut sent = Vec::new();
+        loop {
+            let next = self.ip.take_sent();
+            if next.is_empty() {
+                return sent;
+            }
+            for packet in next {
+                sent.push(Sent::new(packet));
+                self.nd.send_done(ReturnCode::SUCCESS);
+            }
+        }
+    }
+
+    fn receive(&self, src: IPAddr, dst: IPAddr, header: ICMP6Header, payload: &[u8]) {
+        let mut ip_header = IP6Header::new();
+        ip_header.src_addr = src;
+        ip_header.dst_addr = dst;
+        ip_header.set_next_header(ip6_nh::ICMP);
+        ip_header.set_payload_len((8 + payload.len()) as u16);
+        let mut buf = vec![0; 8 + payload.len()];
+        header.encode(&mut buf, 0).done().unwrap();
+        buf[8..].copy_from_slice(payload);
+        self.nd.receive(ip_header, &buf);
+    }
+
+    fn advance_seconds(&self, seconds: u32) {
+        for _ in 0..seconds {
+            self.alarm.advance_ms(ND_TIMER_MS);
+        }
+    }
+}
+
+fn link_local(mac: MacAddress) -> IPAddr {
+    IPAddr::generate_from_mac(mac)
+}
+
+fn global_addr() -> IPAddr {
+    let mut addr = link_local(NODE_MAC);
+    addr.set_prefix(&PREFIX, 64);
+    addr
+}
+
+fn options(buf: &[u8]) -> Vec<NDOption> {
+    let mut options = Vec::new();
+    let mut off = 0;
+    while off < buf.len() {
+        let (len, option) = NDOption::decode(&buf[off..]).done().unwrap();
+        options.push(option);
+        off += len;
+    }
+    options
+}
+
+fn encode_options(options: &[NDOption]) -> Vec<u8> {
+    let mut buf = vec![0; 128];
+    let mut off = 0;
+    for option in options {
+        off = option.encode(&mut buf, off).done().unwrap().0;
+    }
+    buf.truncate(off);
+    buf
+}
+
+fn router_advertisement(router_lifetime: u16) -> (ICMP6Header, Vec<u8>) {
+    let mut header = ICMP6Header::new(ICMP6Type::Type134);
+    header.set_options(ICMP6HeaderOptions::Type134 {
+        hop_limit: 64,
+        flags: 0,
+        router_lifetime: router_lifetime,
+    });
+    let mut prefix = IPAddr::new();
+    prefix.0[..8].copy_from_slice(&PREFIX);
+    let mut payload = vec![0; 8];
+    payload.extend(encode_options(&[
+        NDOption::SourceLLAddr(ROUTER_MAC),
+        NDOption::PrefixInfo {
+            prefix_len: 64,
+            flags: pio_flags::ON_LINK | pio_flags::AUTONOMOUS,
+            valid_lifetime: 3600,
+            preferred_lifetime: 3600,
+            prefix: prefix,
+        },
+    ]));
+    (header, payload)
+}
+
+fn registration_reply(status: u8) -> (ICMP6Header, Vec<u8>) {
+    let mut header = ICMP6Header::new(ICMP6Type::Type136);
+    header.set_options(ICMP6HeaderOptions::Type136 {
+        flags: na_flags::ROUTER | na_flags::SOLICITED,
+    });
+    let mut payload = global_addr().0.to_vec();
+    payload.extend(encode_options(&[NDOption::AddrRegistration {
+        status: status,
+        lifetime: REGISTRATION_LIFETIME,
+        eui64: [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77],
+    }]));
+    (header, payload)
+}
+
+/// Runs the node through solicitation, advertisement and registration.
+fn attach(node: &Node) {
+    node.nd.start();
+    node.take_sent();
+    let (header, payload) = router_advertisement(1800);
+    node.receive(link_local(ROUTER_MAC), ALL_NODES_ADDR, header, &payload);
+    node.take_sent();
+    let (header, payload) = registration_reply(aro_status::SUCCESS);
+    node.receive(link_local(ROUTER_MAC), global_addr(), header, &payload);
+}
+
+/// RFC 1071 checksum over the IPv6 pseudo-header and an ICMPv6 message.
+fn reference_checksum(src: IPAddr, dst: IPAddr, message: &[u8]) -> u16 {
+    let mut data = Vec::new();
+    data.extend_from_slice(&src.0);
+    data.extend_from_slice(&dst.0);
+    data.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    data.extend_from_slice(&[0, 0, 0, ip6_nh::ICMP]);
+    data.extend_from_slice(message);
+    if data.len() % 2 == 1 {
+        data.push(0);
+    }
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|word| (word[0] as u32) << 8 | word[1] as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
+    }
+    !sum as u16
+}
+
+#[test]
+fn icmp_header_round_trip() {
+    let mut header = ICMP6Header::new(ICMP6Type::Type128);
+    header.set_options(ICMP6HeaderOptions::Type128 {
+        id: 0x1234,
+        seqno: 0x5678,
+    });
+    header.set_cksum(0xabcd);
+    let mut buf = [0; 8];
+    assert_eq!(header.encode(&mut buf, 0).done().unwrap().0, 8);
+    assert_eq!(buf, [128, 0, 0xab, 0xcd, 0x12, 0x34, 0x56, 0x78]);
+
+    let (off, decoded) = ICMP6Header::decode(&buf).done().unwrap();
+    assert_eq!(off, 8);
+    assert_eq!(decoded.get_cksum(), 0xabcd);
+    match decoded.get_options() {
+        ICMP6HeaderOptions::Type128 { id, seqno } => assert_eq!((id, seqno), (0x1234, 0x5678)),
+        _ => panic!("wrong type"),
+    }
+
+    let buf = [134, 0, 0, 0, 64, 0x80, 0x07, 0x08];
+    match ICMP6Header::decode(&buf).done().unwrap().1.get_options() {
+        ICMP6HeaderOptions::Type134 {
+            hop_limit,
+            flags,
+            router_lifetime,
+        } => assert_eq!((hop_limit, flags, router_lifetime), (64, 0x80, 0x0708)),
+        _ => panic!("wrong type"),
+    }
+}
+
+#[test]
+fn icmp_checksum_matches_reference() {
+    let src = link_local(PEER_MAC);
+    let dst = link_local(NODE_MAC);
+    let mut header = ICMP6Header::new(ICMP6Type::Type128);
+    header.set_options(ICMP6HeaderOptions::Type128 { id: 7, seqno: 1 });
+    let data = leak_buffer(b"odd-length ping");
+    let data_len = data.len();
+    let mut packet = IP6Packet::new(IPPayload {
+        header: TransportHeader::ICMP(header),
+        payload: leak_buffer(&[0; 64]),
+    });
+    packet.header.src_addr = src;
+    packet.header.dst_addr = dst;
+    packet.set_payload(TransportHeader::ICMP(header), &LeasableBuffer::new(data));
+    packet.set_transport_checksum();
+
+    let mut buf = [0; 128];
+    let len = packet.encode(&mut buf).done().unwrap().0;
+    assert_eq!(len, 40 + 8 + data_len);
+    let mut message = buf[40..len].to_vec();
+    let cksum = u16::from_be_bytes([message[2], message[3]]);
+    message[2] = 0;
+    message[3] = 0;
+    assert_eq!(cksum, reference_checksum(src, dst, &message));
+
+    let (off, ip_header) = IP6Header::decode(&buf[..len]).done().unwrap();
+    assert_eq!(
+        ip_header.check_transport_checksum(&buf[off..len]),
+        ReturnCode::SUCCESS
+    );
+    buf[len - 1] ^= 1;
+    assert_eq!(
+        ip_header.check_transport_checksum(&buf[off..len]),
+        ReturnCode::FAIL
+    );
+}
+
+#[test]
+fn mac_from_iid_inverts_generate_from_mac() {
+    for mac in [NODE_MAC, ROUTER_MAC].iter() {
+        assert_eq!(link_local(*mac).mac_from_iid(), *mac);
+    }
+}
+
+#[test]
+fn neighbor_cache_next_hop() {
+    let cache = NeighborCache::new(Box::leak(Box::new([None; 2] as [Option<NeighborEntry>; 2])));
+    let router = link_local(ROUTER_MAC);
+    let peer = link_local(PEER_MAC);
+
+    assert_eq!(cache.next_hop(ALL_NODES_ADDR), Some(BROADCAST_MAC_ADDR));
+    assert_eq!(cache.next_hop(peer), Some(PEER_MAC));
+    assert_eq!(cache.next_hop(global_addr()), None);
+
+    assert!(cache.update(router, ROUTER_MAC, true, Some(10)));
+    assert_eq!(cache.next_hop(global_addr()), Some(ROUTER_MAC));
+
+    // A cache entry takes precedence over the interface identifier
+    let other = MacAddress::Short(0x0bbb);
+    assert!(cache.update(peer, other, false, Some(5)));
+    assert_eq!(cache.next_hop(peer), Some(other));
+
+    // When full, the non-router entry is replaced, never the router
+    assert!(cache.update(global_addr(), other, false, Some(20)));
+    assert_eq!(cache.lookup(peer), None);
+    assert!(cache.lookup(router).is_some());
+
+    cache.tick(10);
+    assert_eq!(cache.default_router(), None);
+    assert_eq!(cache.next_hop(IPAddr([0x20; 16])), None);
+    assert_eq!(cache.lookup(global_addr()).unwrap().lifetime, Some(10));
+}
+
+#[test]
+fn neighbor_cache_static_entries_do_not_expire() {
+    let cache = NeighborCache::new(Box::leak(Box::new([None; 1] as [Option<NeighborEntry>; 1])));
+    let router = link_local(ROUTER_MAC);
+    assert!(cache.update(router, ROUTER_MAC, true, None));
+    cache.tick(u32::MAX);
+    assert!(cache.update(router, ROUTER_MAC, true, Some(1)));
+    cache.tick(100);
+    assert_eq!(cache.default_router().unwrap().lifetime, None);
+    assert!(!cache.update(link_local(PEER_MAC), PEER_MAC, false, Some(1)));
+}
+
+#[test]
+fn answers_echo_requests() {
+    let node = Node::new();
+    let mut header = ICMP6Header::new(ICMP6Type::Type128);
+    header.set_options(ICMP6HeaderOptions::Type128 { id: 42, seqno: 3 });
+    node.receive(link_local(PEER_MAC), link_local(NODE_MAC), header, b"hello");
+
+    let sent = node.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].src, link_local(NODE_MAC));
+    assert_eq!(sent[0].dst, link_local(PEER_MAC));
+    assert_eq!(sent[0].payload, b"hello");
+    match sent[0].header.get_options() {
+        ICMP6HeaderOptions::Type129 { id, seqno } => assert_eq!((id, seqno), (42, 3)),
+        _ => panic!("not an echo reply"),
+    }
+
+    // Requests for other nodes are ignored, and multicast requests are
+    // answered from the link-local address
+    node.receive(link_local(PEER_MAC), link_local(ROUTER_MAC), header, b"");
+    assert!(node.take_sent().is_empty());
+    node.receive(link_local(PEER_MAC), ALL_NODES_ADDR, header, b"");
+    let sent = node.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].src, link_local(NODE_MAC));
+}
+
+#[test]
+fn solicits_routers_with_backoff() {
+    let node = Node::new();
+    node.nd.start();
+    let sent = node.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].src, link_local(NODE_MAC));
+    assert_eq!(sent[0].dst, ALL_ROUTERS_ADDR);
+    assert_eq!(sent[0].header.get_type_as_int(), 133);
+    assert_eq!(
+        options(&sent[0].payload),
+        vec![NDOption::SourceLLAddr(NODE_MAC)]
+    );
+
+    let mut times = Vec::new();
+    for second in 1..=200 {
+        node.advance_seconds(1);
+        if !node.take_sent().is_empty() {
+            times.push(second);
+        }
+    }
+    // Three more solicitations every RTR_SOLICITATION_INTERVAL, then the
+    // interval doubles up to MAX_RTR_SOLICITATION_INTERVAL
+    assert_eq!(times, vec![10, 20, 30, 50, 90, 150]);
+    assert_eq!(RTR_SOLICITATION_INTERVAL, 10);
+}
+
+#[test]
+fn registers_global_address() {
+    let node = Node::new();
+    node.nd.start();
+    node.take_sent();
+
+    let (header, payload) = router_advertisement(1800);
+    node.receive(link_local(ROUTER_MAC), ALL_NODES_ADDR, header, &payload);
+    let router = node.cache.default_router().unwrap();
+    assert_eq!(router.ip_addr, link_local(ROUTER_MAC));
+    assert_eq!(router.mac_addr, ROUTER_MAC);
+    assert_eq!(router.lifetime, Some(1800));
+
+    let sent = node.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].header.get_type_as_int(), 135);
+    assert_eq!(sent[0].src, global_addr());
+    assert_eq!(sent[0].dst, link_local(ROUTER_MAC));
+    assert_eq!(sent[0].payload[..16], global_addr().0);
+    assert_eq!(
+        options(&sent[0].payload[16..]),
+        vec![
+            NDOption::SourceLLAddr(NODE_MAC),
+            NDOption::AddrRegistration {
+                status: 0,
+                lifetime: REGISTRATION_LIFETIME,
+                eui64: [0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77],
+            }
+        ]
+    );
+    assert_eq!(node.nd.get_global_addr(), None);
+
+    let (header, payload) = registration_reply(aro_status::SUCCESS);
+    node.receive(link_local(ROUTER_MAC), global_addr(), header, &payload);
+    assert_eq!(node.nd.get_global_addr(), Some(global_addr()));
+
+    // No more solicitations while the router is valid, and the registration
+    // is renewed a minute before it expires
+    node.advance_seconds(REGISTRATION_LIFETIME as u32 * 60 - 61);
+    assert!(node.take_sent().is_empty());
+    node.advance_seconds(1);
+    let sent = node.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].header.get_type_as_int(), 135);
+}
+
+#[test]
+fn failed_registration_drops_address() {
+    let node = Node::new();
+    node.nd.start();
+    node.take_sent();
+    let (header, payload) = router_advertisement(1800);
+    node.receive(link_local(ROUTER_MAC), ALL_NODES_ADDR, header, &payload);
+    node.take_sent();
+
+    let (header, payload) = registration_reply(aro_status::DUPLICATE);
+    node.receive(link_local(ROUTER_MAC), global_addr(), header, &payload);
+    assert_eq!(node.nd.get_global_addr(), None);
+    node.advance_seconds(5);
+    assert!(node.take_sent().is_empty());
+}
+
+#[test]
+fn unanswered_registration_restarts_solicitation() {
+    let node = Node::new();
+    node.nd.start();
+    node.take_sent();
+    let (header, payload) = router_advertisement(1800);
+    node.receive(link_local(ROUTER_MAC), ALL_NODES_ADDR, header, &payload);
+    assert_eq!(node.take_sent().len(), 1);
+
+    for _ in 1..MAX_UNICAST_SOLICIT {
+        node.advance_seconds(1);
+        let sent = node.take_sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].header.get_type_as_int(), 135);
+    }
+    node.advance_seconds(1);
+    let sent = node.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].header.get_type_as_int(), 133);
+    assert_eq!(node.cache.default_router(), None);
+}
+
+#[test]
+fn router_expiry_restarts_solicitation() {
+    let node = Node::new();
+    node.nd.start();
+    node.take_sent();
+    let (header, payload) = router_advertisement(30);
+    node.receive(link_local(ROUTER_MAC), ALL_NODES_ADDR, header, &payload);
+    node.take_sent();
+    let (header, payload) = registration_reply(aro_status::SUCCESS);
+    node.receive(link_local(ROUTER_MAC), global_addr(), header, &payload);
+
+    node.advance_seconds(29);
+    assert!(node.take_sent().is_empty());
+    node.advance_seconds(1);
+    let sent = node.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].header.get_type_as_int(), 133);
+    assert_eq!(node.nd.get_global_addr(), None);
+}
+
+#[test]
+fn answers_neighbor_solicitations() {
+    let node = Node::new();
+    attach(&node);
+    node.take_sent();
+
+    let header = ICMP6Header::new(ICMP6Type::Type135);
+    let mut payload = global_addr().0.to_vec();
+    payload.extend(encode_options(&[NDOption::SourceLLAddr(PEER_MAC)]));
+    let peer = link_local(PEER_MAC);
+    node.receive(peer, global_addr(), header, &payload);
+
+    let sent = node.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].src, global_addr());
+    assert_eq!(sent[0].dst, peer);
+    match sent[0].header.get_options() {
+        ICMP6HeaderOptions::Type136 { flags } => {
+            assert_eq!(flags, na_flags::SOLICITED | na_flags::OVERRIDE)
+        }
+        _ => panic!("not a neighbor advertisement"),
+    }
+    assert_eq!(sent[0].payload[..16], global_addr().0);
+    assert_eq!(
+        options(&sent[0].payload[16..]),
+        vec![NDOption::TargetLLAddr(NODE_MAC)]
+    );
+    assert_eq!(node.cache.lookup(peer).unwrap().mac_addr, PEER_MAC);
+    assert!(node.cache.default_router().is_some());
+
+    // Solicitations for other targets are not answered
+    let mut payload = link_local(ROUTER_MAC).0.to_vec();
+    payload.extend(encode_options(&[NDOption::SourceLLAddr(PEER_MAC)]));
+    node.receive(peer, global_addr(), header, &payload);
+    assert!(node.take_sent().is_empty());
+}
+
+#[test]
+fn ignores_forwarded_neighbor_discovery() {
+    let node = Node::new();
+    node.nd.start();
+    node.take_sent();
+    let (header, payload) = router_advertisement(1800);
+    let mut ip_header = IP6Header::new();
+    ip_header.src_addr = link_local(ROUTER_MAC);
+    ip_header.dst_addr = ALL_NODES_ADDR;
+    ip_header.set_next_header(ip6_nh::ICMP);
+    ip_header.set_hop_limit(254);
+    let mut buf = vec![0; 8];
+    header.encode(&mut buf, 0).done().unwrap();
+    buf.extend(payload);
+    node.nd.receive(ip_header, &buf);
+    assert_eq!(node.cache.default_router(), None);
+}
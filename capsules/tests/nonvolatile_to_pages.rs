@@ -0,0 +1,130 @@
+//! Tests of `NonvolatileToPages` with mock flash.
+
+use std::cell::Cell;
+
+use capsules::nonvolatile_to_pages::NonvolatileToPages;
+use kernel::hil::flash::HasClient;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::ReturnCode;
+use tock_hil_mock::flash::{FlashOperation, MockFlash, MockPage, PAGE_SIZE};
+use tock_hil_mock::{leak, leak_buffer};
+
+#[derive(Default)]
+struct Client {
+    read: Cell<Option<(&'static [u8], usize)>>,
+    written: Cell<Option<usize>>,
+}
+
+impl NonvolatileStorageClient<'static> for Client {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.read.set(Some((buffer, length)));
+    }
+
+    fn write_done(&self, _buffer: &'static mut [u8], length: usize) {
+        self.written.set(Some(length));
+    }
+}
+
+fn setup() -> (
+    &'static NonvolatileToPages<'static, MockFlash>,
+    &'static MockFlash,
+    &'static Client,
+) {
+    let flash = leak(MockFlash::new(4));
+    let pagebuffer = Box::leak(Box::new(MockPage::default()));
+    let storage = leak(NonvolatileToPages::new(flash, pagebuffer));
+    let client = leak(Client::default());
+    flash.set_client(storage);
+    storage.set_client(client);
+    (storage, flash, client)
+}
+
+/// Complete flash operations until none is in progress.
+fn run(flash: &MockFlash) {
+    while flash.complete() {}
+}
+
+#[test]
+fn read_across_pages() {
+    let (storage, flash, client) = setup();
+    let data: Vec<u8> = (0..16).collect();
+    flash.write_contents(PAGE_SIZE - 8, &data);
+
+    assert_eq!(
+        storage.read(leak_buffer(&[0; 16]), PAGE_SIZE - 8, 16),
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(storage.read(leak_buffer(&[0; 1]), 0, 1), ReturnCode::EBUSY);
+    run(flash);
+
+    assert_eq!(
+        flash.operations(),
+        vec![FlashOperation::Read(0), FlashOperation::Read(1)]
+    );
+    let (buffer, length) = client.read.get().unwrap();
+    assert_eq!(length, 16);
+    assert_eq!(buffer, &data[..]);
+}
+
+#[test]
+fn unaligned_write_preserves_page() {
+    let (storage, flash, client) = setup();
+    flash.write_contents(0, &[0x11; PAGE_SIZE]);
+
+    assert_eq!(
+        storage.write(leak_buffer(&[0x22; 4]), 10, 4),
+        ReturnCode::SUCCESS
+    );
+    run(flash);
+
+    assert_eq!(
+        flash.operations(),
+        vec![FlashOperation::Read(0), FlashOperation::Write(0)]
+    );
+    assert_eq!(client.written.get(), Some(4));
+    let contents = flash.contents();
+    assert_eq!(
+        &contents[8..16],
+        &[0x11, 0x11, 0x22, 0x22, 0x22, 0x22, 0x11, 0x11]
+    );
+}
+
+#[test]
+fn aligned_write_skips_read() {
+    let (storage, flash, client) = setup();
+
+    let data = vec![0x5a; PAGE_SIZE + 2];
+    assert_eq!(
+        storage.write(leak_buffer(&data), PAGE_SIZE, PAGE_SIZE + 2),
+        ReturnCode::SUCCESS
+    );
+    run(flash);
+
+    assert_eq!(
+        flash.operations(),
+        vec![
+            FlashOperation::Write(1),
+            FlashOperation::Read(2),
+            FlashOperation::Write(2)
+        ]
+    );
+    assert_eq!(client.written.get(), Some(PAGE_SIZE + 2));
+    assert_eq!(
+        &flash.contents()[PAGE_SIZE..2 * PAGE_SIZE + 3],
+        {
+            let mut expected = data.clone();
+            expected.push(0xff);
+            expected
+        }
+        .as_slice()
+    );
+}
+
+#[test]
+fn failed_read_returns_error() {
+    let (storage, flash, _client) = setup();
+
+    flash.fail_next_call(ReturnCode::FAIL);
+    assert_eq!(storage.read(leak_buffer(&[0; 4]), 0, 4), ReturnCode::FAIL);
+    assert!(!flash.is_busy());
+}
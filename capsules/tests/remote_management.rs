@@ -0,0 +1,323 @@
+//! Tests of the remote management protocol with a mock UART and flash.
+
+use std::cell::Cell;
+
+use capsules::nonvolatile_to_pages::NonvolatileToPages;
+use capsules::process_console::KernelAddresses;
+use capsules::remote_management::{
+    cobs_decode, cobs_encode, crc16, Command, RemoteManagement, MAX_FRAME, MAX_PAYLOAD, RESPONSE,
+};
+use kernel::capabilities::{ExternalProcessCapability, ProcessManagementCapability};
+use kernel::hil::flash::HasClient;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::hil::uart::{Receive, Transmit};
+use kernel::procs::{
+    padding_header, AppFlashRegion, DynamicProcessManagement, ProcessLoadError, ProcessType,
+};
+use kernel::{AppId, Kernel, ReturnCode};
+use tock_hil_mock::flash::{MockFlash, MockPage, PAGE_SIZE};
+use tock_hil_mock::uart::MockUart;
+use tock_hil_mock::{leak, leak_buffer};
+
+struct Capability;
+unsafe impl ProcessManagementCapability for Capability {}
+unsafe impl ExternalProcessCapability for Capability {}
+
+/// Places every application at the start of the second flash page.
+struct Manager {
+    kernel: &'static Kernel,
+    loaded: Cell<Option<(usize, usize)>>,
+}
+
+impl DynamicProcessManagement for Manager {
+    fn find_flash_region(&self, length: usize) -> Result<AppFlashRegion, ReturnCode> {
+        if length > PAGE_SIZE {
+            return Err(ReturnCode::ENOMEM);
+        }
+        Ok(AppFlashRegion {
+            app: (PAGE_SIZE, length),
+            padding_before: Some((0, PAGE_SIZE)),
+            padding_after: Some((PAGE_SIZE + length, 3 * PAGE_SIZE - length)),
+        })
+    }
+
+    fn load_process(&self, app: (usize, usize)) -> Result<Option<AppId>, ProcessLoadError> {
+        self.loaded.set(Some(app));
+        Ok(Some(AppId::new_external(self.kernel, 42, 0, &Capability)))
+    }
+
+    fn unload_process(&self, _identifier: usize) -> Result<&'static [u8], ReturnCode> {
+        Err(ReturnCode::EINVAL)
+    }
+}
+
+type Remote = RemoteManagement<'static, Capability>;
+
+fn setup(
+    kernel_addresses: Option<KernelAddresses>,
+) -> (&'static Remote, &'static MockUart<'static>, &'static Kernel) {
+    let processes: &'static mut [Option<&'static dyn ProcessType>] = Box::leak(Box::new([]));
+    let kernel = leak(Kernel::new(processes));
+    let uart = leak(MockUart::new());
+    let remote: &Remote = leak(RemoteManagement::new(
+        uart,
+        leak_buffer(&[0; MAX_FRAME]),
+        leak_buffer(&[0; 1]),
+        leak_buffer(&[0; MAX_FRAME]),
+        kernel,
+        kernel_addresses,
+        None,
+        Capability,
+    ));
+    uart.set_transmit_client(remote);
+    uart.set_receive_client(remote);
+    assert_eq!(remote.start(), ReturnCode::SUCCESS);
+    (remote, uart, kernel)
+}
+
+/// Encode a request with its CRC and the zero byte that ends it.
+fn frame(sequence: u8, command: u8, arguments: &[u8]) -> Vec<u8> {
+    let mut payload = vec![sequence, command];
+    payload.extend_from_slice(arguments);
+    let crc = crc16(&payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    let mut encoded = vec![0; MAX_FRAME];
+    let length = cobs_encode(&payload, &mut encoded).unwrap();
+    encoded.truncate(length);
+    encoded.push(0);
+    encoded
+}
+
+/// Decode the response the capsule transmitted, complete the transmission,
+/// and return the sequence number, command, result and data.
+fn response(uart: &MockUart) -> (u8, u8, i8, Vec<u8>) {
+    let mut transmitted = uart.take_transmitted();
+    assert!(uart.complete_transmit());
+    assert_eq!(transmitted.pop(), Some(0));
+    assert!(!transmitted.contains(&0));
+    let length = cobs_decode(&mut transmitted).unwrap();
+    let (payload, crc) = transmitted[..length].split_at(length - 2);
+    assert_eq!(crc, &crc16(payload).to_le_bytes());
+    (
+        payload[0],
+        payload[1],
+        payload[2] as i8,
+        payload[3..].to_vec(),
+    )
+}
+
+fn request(uart: &MockUart, sequence: u8, command: Command, arguments: &[u8]) -> (i8, Vec<u8>) {
+    uart.receive_bytes(&frame(sequence, command as u8, arguments));
+    let (response_sequence, response_command, result, data) = response(uart);
+    assert_eq!(response_sequence, sequence);
+    assert_eq!(response_command, command as u8 | RESPONSE);
+    (result, data)
+}
+
+#[test]
+fn cobs_and_crc() {
+    // The check value of CRC-16/CCITT-FALSE.
+    assert_eq!(crc16(b"123456789"), 0x29b1);
+
+    let mut long = vec![0x11; 600];
+    long[300] = 0;
+    for data in [
+        vec![],
+        vec![0],
+        vec![0, 0],
+        vec![1, 0, 2, 0],
+        vec![0x11; 254],
+        vec![0x11; 255],
+        long,
+    ]
+    .iter()
+    {
+        let mut encoded = vec![0; data.len() + data.len() / 254 + 1];
+        let length = cobs_encode(data, &mut encoded).unwrap();
+        assert_eq!(length, encoded.len());
+        assert!(!encoded.contains(&0));
+        assert_eq!(cobs_decode(&mut encoded), Some(data.len()));
+        assert_eq!(&encoded[..data.len()], &data[..]);
+    }
+
+    assert_eq!(cobs_encode(&[1, 2, 3], &mut [0; 3]), None);
+    assert_eq!(cobs_decode(&mut [3, 1]), None);
+    assert_eq!(cobs_decode(&mut [0, 1]), None);
+}
+
+#[test]
+fn info_and_errors() {
+    let (_remote, uart, _kernel) = setup(None);
+    assert!(uart.is_receiving());
+
+    let (result, data) = request(uart, 1, Command::Info, &[]);
+    assert_eq!(result, 0);
+    assert_eq!(data, [1, MAX_PAYLOAD as u8, 0, 0, 0, 0, 0]);
+
+    // Frames with a wrong CRC, that cannot be decoded, or that are too long
+    // are dropped.
+    let mut corrupted = frame(2, Command::Info as u8, &[]);
+    corrupted[1] ^= 0x40;
+    uart.receive_bytes(&corrupted);
+    uart.receive_bytes(&[5, 1, 0]);
+    uart.receive_bytes(&[1; MAX_FRAME + 1]);
+    uart.receive_bytes(&[0]);
+    assert!(uart.transmitted().is_empty());
+
+    let (result, data) = request(uart, 3, Command::Info, &[]);
+    assert_eq!(result, 0);
+    assert_eq!(&data[3..], &3u32.to_le_bytes());
+
+    assert_eq!(request(uart, 4, Command::List, &[0]).0, -6);
+    assert_eq!(request(uart, 5, Command::Stop, b"nobody").0, -6);
+    assert_eq!(request(uart, 6, Command::Counters, b"nobody").0, -6);
+    assert_eq!(request(uart, 7, Command::KernelMemory, &[]).0, -10);
+    assert_eq!(request(uart, 8, Command::Reboot, &[]).0, -10);
+    assert_eq!(request(uart, 9, Command::Install, &[0, 1, 0, 0]).0, -10);
+
+    // Unknown commands are answered, too.
+    uart.receive_bytes(&frame(10, 0x7e, &[]));
+    assert_eq!(response(uart), (10, 0xfe, -10, vec![]));
+}
+
+#[test]
+fn kernel_counters_and_memory() {
+    let addresses = KernelAddresses {
+        stack_start: 0x2000_0000 as *const u8,
+        stack_end: 0x2000_1000 as *const u8,
+        text_start: 0x1_0000 as *const u8,
+        text_end: 0x2_0000 as *const u8,
+        relocate_start: 0x2000_1000 as *const u8,
+        relocate_end: 0x2000_1100 as *const u8,
+        bss_start: 0x2000_1100 as *const u8,
+        bss_end: 0x2000_2000 as *const u8,
+    };
+    let (_remote, uart, _kernel) = setup(Some(addresses));
+
+    let (result, data) = request(uart, 0, Command::Kernel, &[]);
+    assert_eq!(result, 0);
+    assert_eq!(data.len(), 3 * 8 + 4 * 4);
+    // The kernel has no clock.
+    assert_eq!(&data[..24], &[0xff; 24][..]);
+    assert_eq!(&data[24..], &[0; 16][..]);
+
+    let (result, data) = request(uart, 1, Command::KernelMemory, &[]);
+    assert_eq!(result, 0);
+    let words: Vec<u32> = data
+        .chunks(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect();
+    assert_eq!(
+        words,
+        [
+            0x2000_0000,
+            0x2000_1000,
+            0x1_0000,
+            0x2_0000,
+            0x2000_1000,
+            0x2000_1100,
+            0x2000_1100,
+            0x2000_2000
+        ]
+    );
+}
+
+#[test]
+fn requests_wait_for_transmit() {
+    let (_remote, uart, _kernel) = setup(None);
+
+    // The second request arrives before the first response is sent, so its
+    // response waits, and so does receiving the next request.
+    uart.receive_bytes(&frame(1, Command::Info as u8, &[]));
+    uart.receive_bytes(&frame(2, Command::Info as u8, &[]));
+    assert!(!uart.is_receiving());
+    assert_eq!(response(uart).0, 1);
+    assert!(uart.is_receiving());
+    assert_eq!(response(uart).0, 2);
+    assert!(!uart.is_transmitting());
+}
+
+#[test]
+fn install_application() {
+    let (remote, uart, kernel) = setup(None);
+    let flash = leak(MockFlash::new(4));
+    let storage = leak(NonvolatileToPages::new(
+        flash,
+        Box::leak(Box::new(MockPage::default())),
+    ));
+    flash.set_client(storage);
+    storage.set_client(remote);
+    let manager = leak(Manager {
+        kernel: kernel,
+        loaded: Cell::new(None),
+    });
+    remote.set_process_manager(manager, storage, leak_buffer(&[0; MAX_PAYLOAD]));
+
+    // Writing needs flash to be chosen first.
+    assert_eq!(request(uart, 0, Command::Write, &[0; 5]).0, -6);
+    assert_eq!(request(uart, 0, Command::Load, &[]).0, -6);
+    assert_eq!(
+        request(uart, 1, Command::Install, &4096u32.to_le_bytes()).0,
+        -9
+    );
+
+    let (result, data) = request(uart, 2, Command::Install, &300u32.to_le_bytes());
+    assert_eq!(result, 0);
+    assert_eq!(data, (PAGE_SIZE as u32).to_le_bytes());
+
+    let image: Vec<u8> = (0..300).map(|i| (i % 251) as u8 + 1).collect();
+    let chunk = MAX_PAYLOAD - 2 - 4;
+    let mut sequence = 3;
+    for offset in (0..image.len()).step_by(chunk) {
+        let end = image.len().min(offset + chunk);
+        let mut arguments = (offset as u32).to_le_bytes().to_vec();
+        arguments.extend_from_slice(&image[offset..end]);
+        uart.receive_bytes(&frame(sequence, Command::Write as u8, &arguments));
+
+        // The response follows the flash write, and the next request waits
+        // for it.
+        assert!(uart.transmitted().is_empty());
+        assert!(!uart.is_receiving());
+        while flash.complete() {}
+        assert_eq!(
+            response(uart),
+            (sequence, Command::Write as u8 | RESPONSE, 0, vec![])
+        );
+        assert!(uart.is_receiving());
+        sequence += 1;
+    }
+
+    // Writes must stay within the application.
+    let mut arguments = 299u32.to_le_bytes().to_vec();
+    arguments.extend_from_slice(&[1, 2]);
+    assert_eq!(request(uart, sequence, Command::Write, &arguments).0, -6);
+
+    // Loading writes the padding before and after the application.
+    uart.receive_bytes(&frame(sequence, Command::Load as u8, &[]));
+    assert!(uart.transmitted().is_empty());
+    while flash.complete() {}
+    assert_eq!(
+        response(uart),
+        (
+            sequence,
+            Command::Load as u8 | RESPONSE,
+            0,
+            42u32.to_le_bytes().to_vec()
+        )
+    );
+    assert_eq!(manager.loaded.get(), Some((PAGE_SIZE, 300)));
+
+    let contents = flash.contents();
+    let before = padding_header(PAGE_SIZE);
+    let after = padding_header(3 * PAGE_SIZE - 300);
+    assert_eq!(&contents[..before.len()], &before[..]);
+    assert_eq!(&contents[PAGE_SIZE..PAGE_SIZE + 300], &image[..]);
+    assert_eq!(
+        &contents[PAGE_SIZE + 300..PAGE_SIZE + 300 + after.len()],
+        &after[..]
+    );
+
+    // The installation is finished.
+    assert_eq!(request(uart, 0, Command::Abort, &[]).0, -6);
+    assert_eq!(request(uart, 0, Command::Erase, b"nobody").0, -6);
+}
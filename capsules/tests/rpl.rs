@@ -0,0 +1,1169 @@
+//! Tests of RPL routing, the Trickle timer, the routing table, source
+//! routing headers and link estimation in the neighbor cache, with a mock IP
+//! sender and alarms.
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+
+use capsules::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use capsules::net::ipv6::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvClient, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::IP6SendClient;
+use capsules::net::ipv6::neighbor_cache::{NeighborCache, NeighborEntry, ETX_DIVISOR, INITIAL_ETX};
+use capsules::net::ipv6::routing_header::{
+    encode_source_route, source_route_len, visit_next_hop, RoutingHeader, SOURCE_ROUTING,
+};
+use capsules::net::ipv6::routing_table::{Route, RoutingTable};
+use capsules::net::rpl::rpl::{
+    dao_ack_status, join_message, mop, pio_flags, rpl_code, split_message, Dao, DaoAck, Dio,
+    DodagConfig, PrefixInfo, RplOption, ALL_RPL_NODES_ADDR, INFINITE_RANK,
+};
+use capsules::net::rpl::rpl_node::{
+    RplNode, DAO_ACK_TIMEOUT, DAO_MAX_TRANSMISSIONS, DEFAULT_INSTANCE_ID, RPL_TIMER_MS,
+};
+use capsules::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+use capsules::net::trickle::{Trickle, TrickleClient};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::Alarm;
+use kernel::ReturnCode;
+use tock_hil_mock::alarm::MockAlarm;
+use tock_hil_mock::{leak, leak_buffer};
+
+use common::{MockIP6Sender, SentPacket};
+
+const NODE_MAC: MacAddress = MacAddress::Long([0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+const ROOT_MAC: MacAddress = MacAddress::Short(0x0001);
+const ROUTER_MAC: MacAddress = MacAddress::Short(0x0002);
+const CHILD_MAC: MacAddress = MacAddress::Short(0x0003);
+const PREFIX: [u8; 8] = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 1];
+const VERSION: u8 = 240;
+
+/// An RPL message passed to the IP layer, with its body reassembled.
+#[derive(Clone)]
+struct Sent {
+    src: IPAddr,
+    dst: IPAddr,
+    code: u8,
+    body: Vec<u8>,
+}
+
+impl Sent {
+    fn new(packet: SentPacket) -> Sent {
+        let header = match packet.header {
+            TransportHeader::ICMP(header) => header,
+            _ => panic!("not an ICMPv6 message"),
+        };
+        let mut body = [0; 256];
+        let len = join_message(&header, &packet.payload, &mut body).expect("not an RPL message");
+        Sent {
+            src: packet.src,
+            dst: packet.dst,
+            code: header.get_code(),
+            body: body[..len].to_vec(),
+        }
+    }
+
+    fn dio(&self) -> (Dio, Vec<RplOption>) {
+        assert_eq!(self.code, rpl_code::DIO);
+        let (off, dio) = Dio::decode(&self.body).done().unwrap();
+        (dio, options(&self.body[off..]))
+    }
+
+    fn dao(&self) -> (Dao, Vec<RplOption>) {
+        assert_eq!(self.code, rpl_code::DAO);
+        let (off, dao) = Dao::decode(&self.body).done().unwrap();
+        (dao, options(&self.body[off..]))
+    }
+}
+
+struct Node {
+    rpl: &'static RplNode<'static, MockAlarm<'static>>,
+    cache: &'static NeighborCache<'static>,
+    routes: &'static RoutingTable<'static>,
+    ip: &'static MockIP6Sender,
+    alarm: &'static MockAlarm<'static>,
+    trickle_alarm: &'static MockAlarm<'static>,
+}
+
+impl Node {
+    fn new(local_addrs: &'static [IPAddr]) -> Node {
+        let ip = leak(MockIP6Sender::new());
+        let alarm: &MockAlarm = leak(MockAlarm::new());
+        let trickle_alarm = leak(MockAlarm::new());
+        let cache = leak(NeighborCache::new(Box::leak(Box::new(
+            [None; 8] as [Option<NeighborEntry>; 8],
+        ))));
+        let routes = leak(RoutingTable::new(Box::leak(Box::new(
+            [None; 8] as [Option<Route>; 8],
+        ))));
+        let trickle = leak(Trickle::new(trickle_alarm, 0x1234));
+        trickle_alarm.set_alarm_client(trickle);
+        let rpl = leak(RplNode::new(
+            ip,
+            cache,
+            routes,
+            trickle,
+            alarm,
+            NODE_MAC,
+            local_addrs,
+            leak_buffer(&[0; 128]),
+            leak_buffer(&[0; 256]),
+            common::net_cap(),
+        ));
+        trickle.set_client(rpl);
+        alarm.set_alarm_client(rpl);
+        Node {
+            rpl: rpl,
+            cache: cache,
+            routes: routes,
+            ip: ip,
+            alarm: alarm,
+            trickle_alarm: trickle_alarm,
+        }
+    }
+
+    /// Takes the packets passed to the IP layer, completing each send.
+    fn take_packets(&self) -> Vec<SentPacket> {
+        let mut sent = Vec::new();
+        loop {
+            let next = self.ip.take_sent();
+            if next.is_empty() {
+                return sent;
+            }
+            for packet in next {
+                sent.push(packet);
+                self.rpl.send_done(ReturnCode::SUCCESS);
+            }
+        }
+    }
+
+    /// Takes the messages passed to the IP layer, completing each send.
+    fn take_sent(&self) -> Vec<Sent> {
+        self.take_packets().into_iter().map(Sent::new).collect()
+    }
+
+    fn receive_packet(
+        &self,
+        src: IPAddr,
+        dst: IPAddr,
+        next_header: u8,
+        hop_limit: u8,
+        payload: &[u8],
+    ) {
+        let mut ip_header = IP6Header::new();
+        ip_header.src_addr = src;
+        ip_header.dst_addr = dst;
+        ip_header.set_next_header(next_header);
+        ip_header.set_hop_limit(hop_limit);
+        ip_header.set_payload_len(payload.len() as u16);
+        self.rpl.receive(ip_header, payload);
+    }
+
+    fn receive(&self, src: IPAddr, dst: IPAddr, code: u8, body: &[u8]) {
+        let mut body = body.to_vec();
+        let body_len = body.len();
+        let (header, len) = split_message(code, &mut body, body_len);
+        let mut ip_header = IP6Header::new();
+        ip_header.src_addr = src;
+        ip_header.dst_addr = dst;
+        ip_header.set_next_header(ip6_nh::ICMP);
+        ip_header.set_payload_len((8 + len) as u16);
+        let mut buf = vec![0; 8 + len];
+        header.encode(&mut buf, 0).done().unwrap();
+        buf[8..].copy_from_slice(&body[..len]);
+        self.rpl.receive(ip_header, &buf);
+    }
+
+    fn receive_dio(&self, src_mac: MacAddress, rank: u16, version: u8, mode_of_operation: u8) {
+        let body = dio_body(rank, version, mode_of_operation);
+        self.receive(
+            link_local(src_mac),
+            ALL_RPL_NODES_ADDR,
+            rpl_code::DIO,
+            &body,
+        );
+    }
+
+    fn advance_seconds(&self, seconds: u32) {
+        for _ in 0..seconds {
+            self.alarm.advance_ms(RPL_TIMER_MS);
+            self.trickle_alarm.advance_ms(RPL_TIMER_MS);
+        }
+    }
+}
+
+fn link_local(mac: MacAddress) -> IPAddr {
+    IPAddr::generate_from_mac(mac)
+}
+
+fn global(mac: MacAddress) -> IPAddr {
+    let mut addr = link_local(mac);
+    addr.set_prefix(&PREFIX, 64);
+    addr
+}
+
+fn options(buf: &[u8]) -> Vec<RplOption> {
+    let mut options = Vec::new();
+    let mut off = 0;
+    while off < buf.len() {
+        let (len, option) = RplOption::decode(&buf[off..]).done().unwrap();
+        options.push(option);
+        off += len;
+    }
+    options
+}
+
+fn encode(options: &[RplOption], buf: &mut Vec<u8>) {
+    let mut tmp = vec![0; 256];
+    let mut off = 0;
+    for option in options {
+        off = option.encode(&mut tmp, off).done().unwrap().0;
+    }
+    buf.extend_from_slice(&tmp[..off]);
+}
+
+fn config() -> DodagConfig {
+    DodagConfig {
+        dio_interval_min: 10,
+        dio_interval_doublings: 2,
+        dio_redundancy: 0,
+        ..DodagConfig::default()
+    }
+}
+
+fn prefix_info() -> PrefixInfo {
+    let mut prefix = IPAddr::new();
+    prefix.0[..8].copy_from_slice(&PREFIX);
+    PrefixInfo {
+        prefix_len: 64,
+        flags: pio_flags::AUTONOMOUS,
+        valid_lifetime: 3600,
+        preferred_lifetime: 3600,
+        prefix: prefix,
+    }
+}
+
+fn dio_body(rank: u16, version: u8, mode_of_operation: u8) -> Vec<u8> {
+    let dio = Dio {
+        instance_id: DEFAULT_INSTANCE_ID,
+        version: version,
+        rank: rank,
+        grounded: true,
+        mode_of_operation: mode_of_operation,
+        preference: 0,
+        dtsn: 0,
+        dodag_id: global(ROOT_MAC),
+    };
+    let mut body = vec![0; Dio::LEN];
+    dio.encode(&mut body, 0).done().unwrap();
+    encode(
+        &[
+            RplOption::DodagConfig(config()),
+            RplOption::PrefixInfo(prefix_info()),
+        ],
+        &mut body,
+    );
+    body
+}
+
+/// A DAO advertising `target`, which has the parent `parent` in non-storing
+/// mode.
+fn dao_body(sequence: u8, target: IPAddr, path_lifetime: u8, parent: Option<IPAddr>) -> Vec<u8> {
+    let mut body = vec![0; 4];
+    Dao {
+        instance_id: DEFAULT_INSTANCE_ID,
+        ack_requested: true,
+        sequence: sequence,
+        dodag_id: None,
+    }
+    .encode(&mut body, 0)
+    .done()
+    .unwrap();
+    encode(
+        &[
+            RplOption::Target {
+                prefix_len: 128,
+                prefix: target,
+            },
+            RplOption::TransitInfo {
+                external: false,
+                path_control: 0,
+                path_sequence: 1,
+                path_lifetime: path_lifetime,
+                parent: parent,
+            },
+        ],
+        &mut body,
+    );
+    body
+}
+
+/// A UDP header followed by four bytes of data.
+const UDP_DATAGRAM: [u8; 12] = [0x12, 0x34, 0x56, 0x78, 0, 12, 0, 0, 1, 2, 3, 4];
+
+fn dao_ack_body(sequence: u8, status: u8) -> Vec<u8> {
+    let mut body = vec![0; 4];
+    DaoAck {
+        instance_id: DEFAULT_INSTANCE_ID,
+        sequence: sequence,
+        status: status,
+        dodag_id: None,
+    }
+    .encode(&mut body, 0)
+    .done()
+    .unwrap();
+    body
+}
+
+fn targets(options: &[RplOption]) -> Vec<(IPAddr, u8)> {
+    options
+        .iter()
+        .filter_map(|option| match *option {
+            RplOption::Target { prefix, prefix_len } => Some((prefix, prefix_len)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Joins the DODAG of the root through a DIO, and takes the DIS, DIOs and
+/// DAO sent along the way. Returns the DAO.
+fn join(node: &Node, mode_of_operation: u8) -> Sent {
+    node.rpl.start();
+    node.take_sent();
+    node.receive_dio(ROOT_MAC, 256, VERSION, mode_of_operation);
+    node.advance_seconds(1);
+    node.take_sent()
+        .into_iter()
+        .find(|sent| sent.code == rpl_code::DAO)
+        .expect("no DAO")
+}
+
+#[test]
+fn rpl_message_round_trip() {
+    let dio = Dio {
+        instance_id: 0x1e,
+        version: 241,
+        rank: 0x0300,
+        grounded: true,
+        mode_of_operation: mop::STORING,
+        preference: 3,
+        dtsn: 9,
+        dodag_id: global(ROOT_MAC),
+    };
+    let mut buf = [0; 32];
+    assert_eq!(dio.encode(&mut buf, 0).done().unwrap().0, Dio::LEN);
+    assert_eq!(
+        &buf[..8],
+        &[0x1e, 241, 0x03, 0x00, 0x80 | 2 << 3 | 3, 9, 0, 0]
+    );
+    assert_eq!(Dio::decode(&buf).done().unwrap(), (Dio::LEN, dio));
+
+    let dao = Dao {
+        instance_id: 0x1e,
+        ack_requested: true,
+        sequence: 7,
+        dodag_id: Some(global(ROOT_MAC)),
+    };
+    assert_eq!(dao.encode(&mut buf, 0).done().unwrap().0, 20);
+    assert_eq!(&buf[..4], &[0x1e, 0xc0, 0, 7]);
+    assert_eq!(Dao::decode(&buf).done().unwrap(), (20, dao));
+
+    let all_options = [
+        RplOption::Pad(1),
+        RplOption::Pad(3),
+        RplOption::DodagConfig(config()),
+        RplOption::Target {
+            prefix_len: 64,
+            prefix: global(ROOT_MAC),
+        },
+        RplOption::TransitInfo {
+            external: false,
+            path_control: 0,
+            path_sequence: 3,
+            path_lifetime: 30,
+            parent: Some(global(ROUTER_MAC)),
+        },
+        RplOption::PrefixInfo(prefix_info()),
+    ];
+    let mut body = Vec::new();
+    encode(&all_options, &mut body);
+    assert_eq!(body.len(), 1 + 3 + 16 + 12 + 22 + 32);
+    let mut decoded = options(&body);
+    // Only the prefix is kept from target addresses
+    if let RplOption::Target { prefix, .. } = &mut decoded[3] {
+        assert_eq!(prefix.0[8..], [0; 8]);
+        prefix.0[8..].copy_from_slice(&global(ROOT_MAC).0[8..]);
+    }
+    assert_eq!(decoded, all_options);
+}
+
+#[test]
+fn rpl_messages_are_split_around_icmp_header() {
+    let mut body = dio_body(256, VERSION, mop::STORING);
+    let original = body.clone();
+    let len = body.len();
+    let (header, payload_len) = split_message(rpl_code::DIO, &mut body, len);
+    assert_eq!(header.get_type_as_int(), 155);
+    assert_eq!(header.get_code(), rpl_code::DIO);
+    assert_eq!(payload_len, len - 4);
+
+    let mut encoded = [0; 8];
+    header.encode(&mut encoded, 0).done().unwrap();
+    assert_eq!(&encoded[4..], &original[..4]);
+    let decoded = ICMP6Header::decode(&encoded).done().unwrap().1;
+    match decoded.get_options() {
+        ICMP6HeaderOptions::Type155 { .. } => {}
+        _ => panic!("wrong type"),
+    }
+
+    let mut joined = [0; 128];
+    let joined_len = join_message(&decoded, &body[..payload_len], &mut joined).unwrap();
+    assert_eq!(&joined[..joined_len], &original[..]);
+}
+
+struct TrickleCounter {
+    transmissions: Cell<u32>,
+}
+
+impl TrickleClient for TrickleCounter {
+    fn transmit(&self) {
+        self.transmissions.set(self.transmissions.get() + 1);
+    }
+}
+
+#[test]
+fn trickle_doubles_interval_and_resets() {
+    let alarm: &MockAlarm = leak(MockAlarm::new());
+    let trickle = leak(Trickle::new(alarm, 42));
+    let counter = leak(TrickleCounter {
+        transmissions: Cell::new(0),
+    });
+    alarm.set_alarm_client(trickle);
+    trickle.set_client(counter);
+    trickle.configure(1000, 2, 0);
+    trickle.start();
+
+    // One transmission in each interval, between half of it and its end
+    alarm.advance_ms(499);
+    assert_eq!(counter.transmissions.get(), 0);
+    alarm.advance_ms(500);
+    assert_eq!(counter.transmissions.get(), 1);
+    assert_eq!(trickle.interval_ms(), 1000);
+    alarm.advance_ms(1);
+    assert_eq!(trickle.interval_ms(), 2000);
+    alarm.advance_ms(2000);
+    assert_eq!(counter.transmissions.get(), 2);
+    assert_eq!(trickle.interval_ms(), 4000);
+    // Imax is reached after two doublings
+    alarm.advance_ms(8000);
+    assert_eq!(counter.transmissions.get(), 4);
+    assert_eq!(trickle.interval_ms(), 4000);
+
+    trickle.inconsistent();
+    assert_eq!(trickle.interval_ms(), 1000);
+    alarm.advance_ms(1000);
+    assert_eq!(counter.transmissions.get(), 5);
+
+    trickle.stop();
+    alarm.advance_ms(10000);
+    assert_eq!(counter.transmissions.get(), 5);
+}
+
+#[test]
+fn trickle_suppresses_redundant_transmissions() {
+    let alarm: &MockAlarm = leak(MockAlarm::new());
+    let trickle = leak(Trickle::new(alarm, 7));
+    let counter = leak(TrickleCounter {
+        transmissions: Cell::new(0),
+    });
+    alarm.set_alarm_client(trickle);
+    trickle.set_client(counter);
+    trickle.configure(1000, 0, 2);
+    trickle.start();
+
+    trickle.consistent();
+    trickle.consistent();
+    alarm.advance_ms(1000);
+    assert_eq!(counter.transmissions.get(), 0);
+    // The counter starts over in each interval
+    trickle.consistent();
+    alarm.advance_ms(1000);
+    assert_eq!(counter.transmissions.get(), 1);
+}
+
+#[test]
+fn routing_table_longest_prefix_match() {
+    let routes = RoutingTable::new(Box::leak(Box::new([None; 3] as [Option<Route>; 3])));
+    let mut prefix = IPAddr::new();
+    prefix.0[..8].copy_from_slice(&PREFIX);
+    let default_hop = link_local(ROOT_MAC);
+    let child_hop = link_local(CHILD_MAC);
+
+    assert_eq!(routes.next_hop(global(CHILD_MAC)), None);
+    assert!(routes.add(IPAddr::new(), 0, default_hop, None));
+    assert!(routes.add(prefix, 64, default_hop, Some(10)));
+    assert!(routes.add(global(CHILD_MAC), 128, child_hop, Some(5)));
+    assert!(!routes.add(global(ROUTER_MAC), 128, child_hop, None));
+
+    assert_eq!(routes.next_hop(global(CHILD_MAC)), Some(child_hop));
+    assert_eq!(
+        routes
+            .lookup(global(ROUTER_MAC))
+            .map(|route| route.prefix_len),
+        Some(64)
+    );
+    assert_eq!(routes.next_hop(ALL_RPL_NODES_ADDR), None);
+    assert_eq!(routes.next_hop(link_local(CHILD_MAC)), None);
+
+    // Replacing a route keeps its slot
+    assert!(routes.add(prefix, 64, child_hop, Some(10)));
+    assert_eq!(routes.next_hop(global(ROUTER_MAC)), Some(child_hop));
+
+    routes.tick(5);
+    assert_eq!(routes.next_hop(global(CHILD_MAC)), Some(child_hop));
+    routes.remove_next_hop(child_hop);
+    assert_eq!(routes.next_hop(global(CHILD_MAC)), Some(default_hop));
+    routes.tick(1000);
+    assert_eq!(routes.next_hop(global(CHILD_MAC)), Some(default_hop));
+    routes.remove(IPAddr::new(), 0);
+    assert_eq!(routes.next_hop(global(CHILD_MAC)), None);
+}
+
+#[test]
+fn neighbor_cache_estimates_etx() {
+    let cache = NeighborCache::new(Box::leak(Box::new([None; 2] as [Option<NeighborEntry>; 2])));
+    let router = link_local(ROUTER_MAC);
+    assert!(cache.update(router, ROUTER_MAC, false, Some(60)));
+    assert_eq!(cache.link_etx(router), Some(INITIAL_ETX));
+
+    for _ in 0..32 {
+        cache.report_transmission(ROUTER_MAC, true);
+    }
+    let good = cache.link_etx(router).unwrap();
+    assert!(good < ETX_DIVISOR + ETX_DIVISOR / 8);
+    cache.report_transmission(ROUTER_MAC, false);
+    assert!(cache.link_etx(router).unwrap() > good);
+
+    // Refreshing the entry keeps the estimate
+    let etx = cache.link_etx(router);
+    assert!(cache.update(router, ROUTER_MAC, false, Some(60)));
+    assert_eq!(cache.link_etx(router), etx);
+    cache.report_transmission(CHILD_MAC, false);
+    assert_eq!(cache.link_etx(router), etx);
+}
+
+#[test]
+fn rpl_solicits_until_joined() {
+    let node = Node::new(&[]);
+    node.rpl.start();
+    let sent = node.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].code, rpl_code::DIS);
+    assert_eq!(sent[0].dst, ALL_RPL_NODES_ADDR);
+    assert_eq!(sent[0].src, link_local(NODE_MAC));
+    assert_eq!(sent[0].body.len(), 4);
+
+    node.advance_seconds(29);
+    assert!(node.take_sent().is_empty());
+    node.advance_seconds(1);
+    assert_eq!(node.take_sent()[0].code, rpl_code::DIS);
+}
+
+#[test]
+fn rpl_joins_dodag_and_advertises_route() {
+    let node = Node::new(&[]);
+    let dao = join(&node, mop::STORING);
+
+    let root = link_local(ROOT_MAC);
+    assert_eq!(node.rpl.get_preferred_parent(), Some(root));
+    assert_eq!(node.rpl.get_dodag_id(), Some(global(ROOT_MAC)));
+    assert_eq!(node.rpl.get_global_addr(), Some(global(NODE_MAC)));
+    // Rank of the root plus the initial ETX of the link
+    assert_eq!(node.rpl.get_rank(), 256 + INITIAL_ETX);
+    assert_eq!(node.routes.next_hop(global(CHILD_MAC)), Some(root));
+    assert_eq!(node.cache.link_etx(root), Some(INITIAL_ETX));
+
+    // In storing mode the DAO goes to the parent
+    assert_eq!(dao.src, link_local(NODE_MAC));
+    assert_eq!(dao.dst, root);
+    let (base, options) = dao.dao();
+    assert!(base.ack_requested);
+    assert_eq!(targets(&options), vec![(global(NODE_MAC), 128)]);
+    match options.last() {
+        Some(RplOption::TransitInfo {
+            path_lifetime,
+            parent: None,
+            ..
+        }) => assert_eq!(*path_lifetime, config().default_lifetime),
+        _ => panic!("no transit information"),
+    }
+
+    // The node advertises the DODAG with its own rank
+    node.advance_seconds(2);
+    let sent = node.take_sent();
+    let dio = sent
+        .iter()
+        .find(|sent| sent.code == rpl_code::DIO)
+        .expect("no DIO");
+    assert_eq!(dio.dst, ALL_RPL_NODES_ADDR);
+    let (base, options) = dio.dio();
+    assert_eq!(base.rank, 256 + INITIAL_ETX);
+    assert_eq!(base.version, VERSION);
+    assert!(options.contains(&RplOption::DodagConfig(config())));
+    assert!(options.contains(&RplOption::PrefixInfo(prefix_info())));
+}
+
+#[test]
+fn rpl_non_storing_dao_goes_to_root() {
+    let node = Node::new(&[]);
+    let dao = join(&node, mop::NON_STORING);
+    assert_eq!(dao.src, global(NODE_MAC));
+    assert_eq!(dao.dst, global(ROOT_MAC));
+    let (_, options) = dao.dao();
+    assert_eq!(targets(&options), vec![(global(NODE_MAC), 128)]);
+    match options.last() {
+        Some(RplOption::TransitInfo { parent, .. }) => assert_eq!(*parent, Some(global(ROOT_MAC))),
+        _ => panic!("no transit information"),
+    }
+}
+
+#[test]
+fn rpl_advertises_configured_addresses() {
+    let local_addrs = leak([link_local(NODE_MAC), global(CHILD_MAC)]);
+    let node = Node::new(local_addrs);
+    let (_, options) = join(&node, mop::STORING).dao();
+    assert_eq!(
+        targets(&options),
+        vec![(global(NODE_MAC), 128), (global(CHILD_MAC), 128)]
+    );
+}
+
+#[test]
+fn rpl_prefers_parent_with_better_link() {
+    let node = Node::new(&[]);
+    join(&node, mop::STORING);
+    let root = link_local(ROOT_MAC);
+    let router = link_local(ROUTER_MAC);
+
+    // A router with a slightly worse rank is not worth switching to
+    node.receive_dio(ROUTER_MAC, 300, VERSION, mop::STORING);
+    assert_eq!(node.rpl.get_preferred_parent(), Some(root));
+
+    // Once frames to the root go unacknowledged, the router is preferred
+    for _ in 0..8 {
+        node.cache.report_transmission(ROOT_MAC, false);
+    }
+    node.receive_dio(ROUTER_MAC, 300, VERSION, mop::STORING);
+    assert_eq!(node.rpl.get_preferred_parent(), Some(router));
+    assert_eq!(node.routes.next_hop(global(CHILD_MAC)), Some(router));
+    assert_eq!(node.rpl.get_rank(), 300 + INITIAL_ETX);
+    node.advance_seconds(1);
+    let dao = node
+        .take_sent()
+        .into_iter()
+        .find(|sent| sent.code == rpl_code::DAO)
+        .expect("no DAO");
+    assert_eq!(dao.dst, router);
+}
+
+#[test]
+fn rpl_ignores_parents_with_higher_rank() {
+    let node = Node::new(&[]);
+    join(&node, mop::STORING);
+    let rank = node.rpl.get_rank();
+    node.receive_dio(ROUTER_MAC, rank, VERSION, mop::STORING);
+    for _ in 0..8 {
+        node.cache.report_transmission(ROOT_MAC, false);
+    }
+    node.receive_dio(ROOT_MAC, 256, VERSION, mop::STORING);
+    assert_eq!(node.rpl.get_preferred_parent(), Some(link_local(ROOT_MAC)));
+}
+
+#[test]
+fn rpl_acknowledged_dao_is_refreshed() {
+    let node = Node::new(&[]);
+    let dao = join(&node, mop::STORING);
+    let (base, _) = dao.dao();
+    node.receive(
+        link_local(ROOT_MAC),
+        link_local(NODE_MAC),
+        rpl_code::DAO_ACK,
+        &dao_ack_body(base.sequence, dao_ack_status::ACCEPTED),
+    );
+
+    // No retransmission, but a refresh halfway through the route lifetime
+    let lifetime = config().route_lifetime().unwrap();
+    node.advance_seconds(lifetime / 2 - 1);
+    assert!(node
+        .take_sent()
+        .iter()
+        .all(|sent| sent.code != rpl_code::DAO));
+    node.advance_seconds(1);
+    assert!(node
+        .take_sent()
+        .iter()
+        .any(|sent| sent.code == rpl_code::DAO));
+}
+
+#[test]
+fn rpl_detaches_from_unreachable_parent() {
+    let node = Node::new(&[]);
+    join(&node, mop::STORING);
+
+    // The DAO is retransmitted until the parent is given up
+    for _ in 1..DAO_MAX_TRANSMISSIONS {
+        node.advance_seconds(DAO_ACK_TIMEOUT);
+        assert!(node
+            .take_sent()
+            .iter()
+            .any(|sent| sent.code == rpl_code::DAO));
+    }
+    assert!(node.rpl.get_preferred_parent().is_some());
+    node.advance_seconds(DAO_ACK_TIMEOUT);
+    assert_eq!(node.rpl.get_preferred_parent(), None);
+    assert_eq!(node.rpl.get_rank(), INFINITE_RANK);
+    assert_eq!(node.routes.next_hop(global(CHILD_MAC)), None);
+
+    // The node poisons its sub-DODAG and looks for a new parent
+    let sent = node.take_sent();
+    let (dio, _) = sent
+        .iter()
+        .rev()
+        .find(|sent| sent.code == rpl_code::DIO)
+        .expect("no DIO")
+        .dio();
+    assert_eq!(dio.rank, INFINITE_RANK);
+    assert!(sent.iter().any(|sent| sent.code == rpl_code::DIS));
+
+    node.receive_dio(ROUTER_MAC, 512, VERSION, mop::STORING);
+    assert_eq!(
+        node.rpl.get_preferred_parent(),
+        Some(link_local(ROUTER_MAC))
+    );
+}
+
+#[test]
+fn rpl_poisoned_parent_is_dropped() {
+    let node = Node::new(&[]);
+    join(&node, mop::STORING);
+    node.receive_dio(ROUTER_MAC, 512, VERSION, mop::STORING);
+    node.receive_dio(ROOT_MAC, INFINITE_RANK, VERSION, mop::STORING);
+    // The router is further from the root, but is all that is left
+    assert_eq!(node.rpl.get_preferred_parent(), None);
+    assert_eq!(node.rpl.get_rank(), INFINITE_RANK);
+    node.take_sent();
+    node.receive_dio(ROUTER_MAC, 512, VERSION, mop::STORING);
+    assert_eq!(
+        node.rpl.get_preferred_parent(),
+        Some(link_local(ROUTER_MAC))
+    );
+}
+
+#[test]
+fn rpl_new_version_rejoins() {
+    let node = Node::new(&[]);
+    join(&node, mop::STORING);
+    node.receive_dio(ROUTER_MAC, 300, VERSION, mop::STORING);
+
+    // Older versions are ignored
+    node.receive_dio(ROUTER_MAC, 256, VERSION - 1, mop::STORING);
+    assert_eq!(node.rpl.get_preferred_parent(), Some(link_local(ROOT_MAC)));
+
+    node.receive_dio(ROUTER_MAC, 512, VERSION + 1, mop::STORING);
+    assert_eq!(
+        node.rpl.get_preferred_parent(),
+        Some(link_local(ROUTER_MAC))
+    );
+    assert_eq!(node.rpl.get_rank(), 512 + INITIAL_ETX);
+    node.advance_seconds(2);
+    let sent = node.take_sent();
+    let (dio, _) = sent
+        .iter()
+        .find(|sent| sent.code == rpl_code::DIO)
+        .expect("no DIO")
+        .dio();
+    assert_eq!(dio.version, VERSION + 1);
+}
+
+#[test]
+fn rpl_answers_unicast_dis() {
+    let node = Node::new(&[]);
+    join(&node, mop::STORING);
+    let child = link_local(CHILD_MAC);
+    node.receive(child, link_local(NODE_MAC), rpl_code::DIS, &[0, 0, 1, 0]);
+    let sent = node.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].dst, child);
+    assert_eq!(sent[0].dio().0.rank, node.rpl.get_rank());
+}
+
+#[test]
+fn rpl_stores_routes_of_children() {
+    let node = Node::new(&[]);
+    join(&node, mop::STORING);
+    let child = link_local(CHILD_MAC);
+
+    let body = dao_body(42, global(CHILD_MAC), 2, None);
+    node.receive(child, link_local(NODE_MAC), rpl_code::DAO, &body);
+
+    assert_eq!(node.routes.next_hop(global(CHILD_MAC)), Some(child));
+    let sent = node.take_sent();
+    let ack = sent
+        .iter()
+        .find(|sent| sent.code == rpl_code::DAO_ACK)
+        .expect("no DAO-ACK");
+    assert_eq!(ack.dst, child);
+    let (_, ack) = DaoAck::decode(&ack.body).done().unwrap();
+    assert_eq!((ack.sequence, ack.status), (42, dao_ack_status::ACCEPTED));
+
+    // The route to the child is advertised to the parent
+    node.advance_seconds(1);
+    let (_, options) = node
+        .take_sent()
+        .into_iter()
+        .find(|sent| sent.code == rpl_code::DAO)
+        .expect("no DAO")
+        .dao();
+    assert_eq!(
+        targets(&options),
+        vec![(global(NODE_MAC), 128), (global(CHILD_MAC), 128)]
+    );
+
+    // Routes expire after the path lifetime
+    node.advance_seconds(2 * config().lifetime_unit as u32);
+    assert_ne!(node.routes.next_hop(global(CHILD_MAC)), Some(child));
+}
+
+#[test]
+fn rpl_root_advertises_dodag() {
+    let node = Node::new(&[]);
+    node.rpl.start_root(global(ROOT_MAC), mop::STORING);
+    assert_eq!(node.rpl.get_dodag_id(), Some(global(ROOT_MAC)));
+    assert_eq!(node.rpl.get_rank(), 256);
+
+    node.advance_seconds(8);
+    let sent = node.take_sent();
+    let (dio, options) = sent
+        .iter()
+        .find(|sent| sent.code == rpl_code::DIO)
+        .expect("no DIO")
+        .dio();
+    assert_eq!(dio.rank, 256);
+    assert_eq!(dio.mode_of_operation, mop::STORING);
+    assert_eq!(dio.dodag_id, global(ROOT_MAC));
+    assert!(options.iter().any(|option| match option {
+        RplOption::PrefixInfo(info) => info.prefix.0[..8] == global(ROOT_MAC).0[..8],
+        _ => false,
+    }));
+    // The root never looks for a parent
+    assert!(sent.iter().all(|sent| sent.code != rpl_code::DIS));
+}
+
+#[test]
+fn rpl_forwards_packets_for_other_nodes() {
+    let node = Node::new(&[]);
+    node.rpl.start();
+    node.take_sent();
+    // Nodes outside of a DODAG do not forward
+    node.receive_packet(
+        global(CHILD_MAC),
+        global(ROOT_MAC),
+        ip6_nh::UDP,
+        64,
+        &UDP_DATAGRAM,
+    );
+    assert!(node.take_packets().is_empty());
+
+    join(&node, mop::STORING);
+    node.receive_packet(
+        global(CHILD_MAC),
+        global(ROOT_MAC),
+        ip6_nh::UDP,
+        64,
+        &UDP_DATAGRAM,
+    );
+    let sent = node.take_packets();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].src, global(CHILD_MAC));
+    assert_eq!(sent[0].dst, global(ROOT_MAC));
+    assert_eq!(sent[0].hop_limit, 63);
+    match sent[0].header {
+        TransportHeader::Raw(next_header, len) => assert_eq!(
+            (next_header, len as usize),
+            (ip6_nh::UDP, UDP_DATAGRAM.len())
+        ),
+        _ => panic!("not forwarded"),
+    }
+    assert_eq!(sent[0].payload, UDP_DATAGRAM);
+
+    // Packets for the node, link-local packets and packets that ran out of
+    // hops are not forwarded
+    node.receive_packet(
+        global(CHILD_MAC),
+        global(NODE_MAC),
+        ip6_nh::UDP,
+        64,
+        &UDP_DATAGRAM,
+    );
+    node.receive_packet(
+        link_local(CHILD_MAC),
+        link_local(ROOT_MAC),
+        ip6_nh::UDP,
+        64,
+        &UDP_DATAGRAM,
+    );
+    node.receive_packet(
+        global(CHILD_MAC),
+        global(ROOT_MAC),
+        ip6_nh::UDP,
+        1,
+        &UDP_DATAGRAM,
+    );
+    assert!(node.take_packets().is_empty());
+}
+
+#[test]
+fn rpl_forwarding_waits_for_messages() {
+    let node = Node::new(&[]);
+    join(&node, mop::STORING);
+    let child = link_local(CHILD_MAC);
+    let body = dao_body(42, global(CHILD_MAC), 2, None);
+    node.receive(child, link_local(NODE_MAC), rpl_code::DAO, &body);
+    // The DAO-ACK is being sent, so the packet waits, and the next one is
+    // dropped
+    node.receive_packet(
+        global(ROOT_MAC),
+        global(CHILD_MAC),
+        ip6_nh::UDP,
+        64,
+        &UDP_DATAGRAM,
+    );
+    node.receive_packet(
+        global(ROOT_MAC),
+        global(ROUTER_MAC),
+        ip6_nh::UDP,
+        64,
+        &UDP_DATAGRAM,
+    );
+    let sent = node.take_packets();
+    assert_eq!(sent.len(), 2);
+    assert!(matches!(sent[0].header, TransportHeader::ICMP(_)));
+    assert_eq!(sent[1].dst, global(CHILD_MAC));
+}
+
+#[test]
+fn rpl_forwards_along_source_route() {
+    let node = Node::new(&[]);
+    join(&node, mop::NON_STORING);
+    let hops = [global(CHILD_MAC)];
+    let mut payload = vec![0; source_route_len(&global(NODE_MAC), &hops)];
+    encode_source_route(&mut payload, ip6_nh::UDP, &global(NODE_MAC), &hops)
+        .done()
+        .unwrap();
+    payload.extend_from_slice(&UDP_DATAGRAM);
+    node.receive_packet(
+        global(ROOT_MAC),
+        global(NODE_MAC),
+        ip6_nh::ROUTING,
+        64,
+        &payload,
+    );
+
+    let sent = node.take_packets();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].dst, global(CHILD_MAC));
+    assert_eq!(sent[0].hop_limit, 63);
+    let (_, header) = RoutingHeader::decode(&sent[0].payload).done().unwrap();
+    assert_eq!(header.segments_left, 0);
+    // The node took the place of the child in the route
+    let elided = (payload[4] >> 4) as usize;
+    let mut expected = payload.clone();
+    expected[3] = 0;
+    expected[8..24 - elided].copy_from_slice(&global(NODE_MAC).0[elided..]);
+    assert_eq!(sent[0].payload, expected);
+
+    // A route with no hops left is not forwarded again
+    node.receive_packet(
+        global(ROOT_MAC),
+        global(NODE_MAC),
+        ip6_nh::ROUTING,
+        64,
+        &expected,
+    );
+    assert!(node.take_packets().is_empty());
+}
+
+#[test]
+fn rpl_non_storing_root_source_routes() {
+    let node = Node::new(&[]);
+    let root = global(NODE_MAC);
+    node.rpl.start_root(root, mop::NON_STORING);
+
+    // The router is a child of the root, and the child a child of the
+    // router
+    let router = global(ROUTER_MAC);
+    let child = global(CHILD_MAC);
+    node.receive(
+        router,
+        root,
+        rpl_code::DAO,
+        &dao_body(1, router, 2, Some(root)),
+    );
+    node.receive(
+        child,
+        root,
+        rpl_code::DAO,
+        &dao_body(2, child, 2, Some(router)),
+    );
+
+    let mut hops = [IPAddr::new(); 4];
+    assert_eq!(node.routes.source_route(child, &mut hops), Some(2));
+    assert_eq!(hops[..2], [router, child]);
+    assert_eq!(node.routes.source_route(router, &mut hops), None);
+    assert_eq!(node.routes.next_hop(child), Some(link_local(ROUTER_MAC)));
+    assert_eq!(node.routes.next_hop(router), Some(link_local(ROUTER_MAC)));
+
+    // DAOs are acknowledged end to end
+    let acks: Vec<Sent> = node
+        .take_sent()
+        .into_iter()
+        .filter(|sent| sent.code == rpl_code::DAO_ACK)
+        .collect();
+    assert_eq!(acks.len(), 2);
+    assert_eq!((acks[1].src, acks[1].dst), (root, child));
+
+    // Packets for the sub-DODAG of the child are source-routed, and the
+    // root forwards them down
+    let mut prefix = child;
+    prefix.0[15] = 0;
+    let mut body = dao_body(3, prefix, 2, Some(child));
+    body[7] = 120;
+    node.receive(child, root, rpl_code::DAO, &body);
+    let mut dst = child;
+    dst.0[15] = 0x42;
+    assert_eq!(node.routes.source_route(dst, &mut hops), Some(3));
+    assert_eq!(hops[..3], [router, child, dst]);
+    node.take_sent();
+    node.receive_packet(router, dst, ip6_nh::UDP, 64, &UDP_DATAGRAM);
+    assert_eq!(node.take_packets()[0].dst, dst);
+
+    // A No-Path DAO removes the route
+    node.receive(
+        child,
+        root,
+        rpl_code::DAO,
+        &dao_body(4, child, 0, Some(router)),
+    );
+    assert_eq!(node.routes.source_route(child, &mut hops), None);
+}
+
+#[test]
+fn source_route_header_round_trip() {
+    let router = global(ROUTER_MAC);
+    let hops = [global(CHILD_MAC), global(NODE_MAC)];
+    // The nine bytes the hops share with the destination are elided, and
+    // the addresses are padded to a multiple of eight bytes
+    let len = source_route_len(&router, &hops);
+    assert_eq!(len, 8 + 16);
+    let mut buf = vec![0; len];
+    assert_eq!(
+        encode_source_route(&mut buf, ip6_nh::UDP, &router, &hops)
+            .done()
+            .unwrap()
+            .0,
+        len
+    );
+    assert_eq!(
+        &buf[..8],
+        &[ip6_nh::UDP, 2, SOURCE_ROUTING, 2, 0x99, 2 << 4, 0, 0]
+    );
+    let header = RoutingHeader::decode(&buf).done().unwrap().1;
+    assert_eq!(header.len(), len);
+
+    let mut dst = router;
+    assert_eq!(visit_next_hop(&mut buf, &mut dst), Ok(()));
+    assert_eq!(dst, global(CHILD_MAC));
+    assert_eq!(visit_next_hop(&mut buf, &mut dst), Ok(()));
+    assert_eq!(dst, global(NODE_MAC));
+    assert_eq!(buf[3], 0);
+    assert_eq!(visit_next_hop(&mut buf, &mut dst), Err(()));
+
+    // Addresses that share no prefix are carried whole
+    let hops = [link_local(CHILD_MAC)];
+    assert_eq!(source_route_len(&router, &hops), 24);
+    let mut buf = vec![0; 24];
+    encode_source_route(&mut buf, ip6_nh::UDP, &router, &hops)
+        .done()
+        .unwrap();
+    assert_eq!(buf[4], 0);
+    let mut dst = router;
+    assert_eq!(visit_next_hop(&mut buf, &mut dst), Ok(()));
+    assert_eq!(dst, link_local(CHILD_MAC));
+    assert_eq!(&buf[8..24], &router.0);
+}
+
+struct ReceivedPacket {
+    packets: RefCell<Vec<(u8, IPAddr, Vec<u8>)>>,
+}
+
+impl IP6RecvClient for ReceivedPacket {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        self.packets.borrow_mut().push((
+            header.get_next_header(),
+            header.get_dst_addr(),
+            payload.to_vec(),
+        ));
+    }
+}
+
+#[test]
+fn source_routed_packet_reaches_transport_layer() {
+    let router = global(ROUTER_MAC);
+    let child = global(CHILD_MAC);
+    let echo = ICMP6Header::new(ICMP6Type::Type128);
+    let mut packet = IP6Packet::new(IPPayload::new(
+        TransportHeader::ICMP(echo),
+        leak_buffer(&[0; 64]),
+    ));
+    packet.header.src_addr = global(ROOT_MAC);
+    packet.header.dst_addr = child;
+    packet.set_payload(
+        TransportHeader::ICMP(echo),
+        &LeasableBuffer::new(leak_buffer(b"hello")),
+    );
+    packet.set_transport_checksum();
+
+    // The root sends the packet to the router, which forwards it to the
+    // child
+    let hops = [child];
+    let len = source_route_len(&router, &hops);
+    assert!(
+        packet.insert_header(ip6_nh::ROUTING, len, |buf, next_header| {
+            encode_source_route(buf, next_header, &router, &hops);
+        })
+    );
+    packet.header.dst_addr = router;
+    assert_eq!(packet.header.get_next_header(), ip6_nh::ROUTING);
+    assert_eq!(packet.header.get_payload_len() as usize, len + 8 + 5);
+    let mut buf = [0; 128];
+    let total_len = packet.encode(&mut buf).done().unwrap().0;
+    assert_eq!(total_len, 40 + len + 8 + 5);
+    let mut dst = router;
+    assert_eq!(visit_next_hop(&mut buf[40..total_len], &mut dst), Ok(()));
+    buf[24..40].copy_from_slice(&dst.0);
+
+    // The child skips the routing header, and the checksum matches
+    let receiver = IP6RecvStruct::new();
+    let client = leak(ReceivedPacket {
+        packets: RefCell::new(Vec::new()),
+    });
+    receiver.set_client(client);
+    receiver.receive(&buf, total_len, ReturnCode::SUCCESS);
+    let packets = client.packets.borrow();
+    assert_eq!(packets.len(), 1);
+    assert_eq!((packets[0].0, packets[0].1), (ip6_nh::ICMP, child));
+    assert_eq!(&packets[0].2[8..], b"hello");
+}
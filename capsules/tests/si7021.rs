@@ -0,0 +1,109 @@
+//! Tests of the SI7021 driver with mock I2C and alarm.
+
+use std::cell::Cell;
+
+use capsules::si7021::SI7021;
+use kernel::hil::sensors::{HumidityClient, HumidityDriver, TemperatureClient, TemperatureDriver};
+use kernel::ReturnCode;
+use tock_hil_mock::alarm::MockAlarm;
+use tock_hil_mock::i2c::{I2CResponse, I2CTransaction, MockI2CDevice};
+use tock_hil_mock::{leak, leak_buffer};
+
+#[derive(Default)]
+struct Readings {
+    temperature: Cell<Option<usize>>,
+    humidity: Cell<Option<usize>>,
+}
+
+impl TemperatureClient for Readings {
+    fn callback(&self, value: usize) {
+        self.temperature.set(Some(value));
+    }
+}
+
+impl HumidityClient for Readings {
+    fn callback(&self, value: usize) {
+        self.humidity.set(Some(value));
+    }
+}
+
+type Sensor = SI7021<'static, MockAlarm<'static>>;
+
+fn setup() -> (
+    &'static Sensor,
+    &'static MockI2CDevice<'static>,
+    &'static MockAlarm<'static>,
+    &'static Readings,
+) {
+    let i2c = leak(MockI2CDevice::new());
+    let alarm = leak(MockAlarm::new());
+    let si7021: &Sensor = leak(SI7021::new(i2c, alarm, leak_buffer(&[0; 14])));
+    let readings = leak(Readings::default());
+    i2c.set_client(si7021);
+    kernel::hil::time::Alarm::set_alarm_client(alarm, si7021);
+    TemperatureDriver::set_client(si7021, readings);
+    HumidityDriver::set_client(si7021, readings);
+    (si7021, i2c, alarm, readings)
+}
+
+#[test]
+fn read_temperature() {
+    let (si7021, i2c, alarm, readings) = setup();
+
+    assert_eq!(si7021.read_temperature(), ReturnCode::SUCCESS);
+    assert_eq!(
+        i2c.take_transactions(),
+        vec![I2CTransaction::Write(vec![0xf3])]
+    );
+    assert!(i2c.complete());
+
+    // The conversion takes up to 20 ms, during which the bus is released.
+    assert!(!i2c.is_enabled());
+    alarm.advance_ms(19);
+    assert!(i2c.transactions().is_empty());
+    alarm.advance_ms(1);
+    assert!(i2c.is_enabled());
+    assert_eq!(i2c.take_transactions(), vec![I2CTransaction::Read(2)]);
+
+    assert!(i2c.complete());
+    assert_eq!(i2c.take_transactions(), vec![I2CTransaction::Read(2)]);
+    i2c.push_response(I2CResponse::ok(&[0x66, 0x66]));
+    assert!(i2c.complete());
+
+    assert_eq!(readings.temperature.get(), Some(2343));
+    assert!(!i2c.is_enabled());
+    assert!(!i2c.is_busy());
+}
+
+#[test]
+fn humidity_waits_for_temperature() {
+    let (si7021, i2c, alarm, readings) = setup();
+
+    assert_eq!(si7021.read_temperature(), ReturnCode::SUCCESS);
+    assert_eq!(si7021.read_humidity(), ReturnCode::SUCCESS);
+    assert_eq!(si7021.read_humidity(), ReturnCode::EBUSY);
+
+    assert!(i2c.complete());
+    alarm.advance_ms(20);
+    assert!(i2c.complete());
+    i2c.push_response(I2CResponse::ok(&[0x66, 0x66]));
+    i2c.take_transactions();
+    assert!(i2c.complete());
+    assert_eq!(readings.temperature.get(), Some(2343));
+
+    // The humidity measurement starts once the temperature is read.
+    assert!(i2c.complete());
+    alarm.advance_ms(20);
+    assert!(i2c.complete());
+    i2c.push_response(I2CResponse::ok(&[0x80, 0x00]));
+    assert!(i2c.complete());
+    assert_eq!(
+        i2c.take_transactions(),
+        vec![
+            I2CTransaction::Write(vec![0xf5]),
+            I2CTransaction::Read(2),
+            I2CTransaction::Read(2),
+        ]
+    );
+    assert_eq!(readings.humidity.get(), Some(5650));
+}
@@ -0,0 +1,343 @@
+//! Tests of `SpiNor` with a simulated flash chip behind a mock SPI device.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use capsules::spi_nor::{Enter4ByteMode, SpiNor, SpiNorSector, SECTOR_SIZE};
+use kernel::hil::flash::{self, Flash, HasClient};
+use kernel::hil::time::Alarm;
+use kernel::ReturnCode;
+use tock_hil_mock::alarm::MockAlarm;
+use tock_hil_mock::leak;
+use tock_hil_mock::spi::MockSpiMasterDevice;
+
+type Driver = SpiNor<'static, MockSpiMasterDevice<'static>, MockAlarm<'static>>;
+
+/// A flash chip that answers the commands the driver sends.
+struct Chip {
+    sfdp: Vec<u8>,
+    /// Bytes that are not erased.
+    memory: RefCell<BTreeMap<usize, u8>>,
+    address_bytes: Cell<usize>,
+    write_enabled: Cell<bool>,
+    /// Number of status reads that still report the chip as busy.
+    busy: Cell<u32>,
+    opcodes: RefCell<Vec<u8>>,
+}
+
+impl Chip {
+    fn new(sfdp: Vec<u8>) -> Chip {
+        Chip {
+            sfdp: sfdp,
+            memory: RefCell::new(BTreeMap::new()),
+            address_bytes: Cell::new(3),
+            write_enabled: Cell::new(false),
+            busy: Cell::new(0),
+            opcodes: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn read(&self, address: usize) -> u8 {
+        *self.memory.borrow().get(&address).unwrap_or(&0xff)
+    }
+
+    fn address(write: &[u8], len: usize) -> usize {
+        write[1..1 + len]
+            .iter()
+            .fold(0, |address, byte| address << 8 | *byte as usize)
+    }
+
+    fn respond(&self, write: &[u8]) -> Vec<u8> {
+        let mut response = vec![0; write.len()];
+        self.opcodes.borrow_mut().push(write[0]);
+        let address_bytes = match write[0] {
+            0x13 | 0x12 | 0x21 => 4,
+            _ => self.address_bytes.get(),
+        };
+        let data = 1 + address_bytes;
+        match write[0] {
+            0x5a => {
+                let address = Chip::address(write, 3);
+                for (i, byte) in response[5..].iter_mut().enumerate() {
+                    *byte = self.sfdp.get(address + i).copied().unwrap_or(0xff);
+                }
+            }
+            0x03 | 0x13 => {
+                let address = Chip::address(write, address_bytes);
+                for (i, byte) in response[data..].iter_mut().enumerate() {
+                    *byte = self.read(address + i);
+                }
+            }
+            0x02 | 0x12 => {
+                assert!(self.write_enabled.replace(false));
+                let address = Chip::address(write, address_bytes);
+                for (i, byte) in write[data..].iter().enumerate() {
+                    let old = self.read(address + i);
+                    self.memory.borrow_mut().insert(address + i, old & byte);
+                }
+                self.busy.set(1);
+            }
+            0x20 | 0x21 => {
+                assert!(self.write_enabled.replace(false));
+                let address = Chip::address(write, address_bytes);
+                assert_eq!(address % SECTOR_SIZE, 0);
+                let mut memory = self.memory.borrow_mut();
+                let erased = memory.split_off(&address);
+                memory.extend(erased.range(address + SECTOR_SIZE..));
+                self.busy.set(2);
+            }
+            0x06 => self.write_enabled.set(true),
+            0x05 => {
+                let busy = self.busy.get();
+                response[1] = if busy > 0 { 0x01 } else { 0x00 };
+                self.busy.set(busy.saturating_sub(1));
+            }
+            0xb7 => self.address_bytes.set(4),
+            opcode => panic!("unexpected opcode {:#x}", opcode),
+        }
+        response
+    }
+
+    /// Answer transfers and fire the alarm until the driver is idle.
+    fn run(&self, spi: &MockSpiMasterDevice, alarm: &MockAlarm) {
+        loop {
+            if spi.is_busy() {
+                let transfer = spi.take_transfers().pop().unwrap();
+                spi.push_response(&self.respond(&transfer.write));
+                spi.complete();
+            } else if alarm.ticks_until_fire().is_some() {
+                alarm.fire();
+            } else {
+                return;
+            }
+        }
+    }
+}
+
+/// SFDP tables with a Basic Flash Parameter Table, and optionally a 4-byte
+/// Address Instruction Table.
+fn sfdp(bfpt: &[u32], fourbait: Option<&[u32]>) -> Vec<u8> {
+    let headers = if fourbait.is_some() { 2 } else { 1 };
+    let mut sfdp = b"SFDP".to_vec();
+    sfdp.extend_from_slice(&[0x06, 0x01, headers - 1, 0xff]);
+    sfdp.extend_from_slice(&[0x00, 0x06, 0x01, bfpt.len() as u8, 0x30, 0x00, 0x00, 0xff]);
+    if let Some(fourbait) = fourbait {
+        sfdp.extend_from_slice(&[
+            0x84,
+            0x00,
+            0x01,
+            fourbait.len() as u8,
+            0x80,
+            0x00,
+            0x00,
+            0xff,
+        ]);
+    }
+    sfdp.resize(0x30, 0xff);
+    for dword in bfpt {
+        sfdp.extend_from_slice(&dword.to_le_bytes());
+    }
+    if let Some(fourbait) = fourbait {
+        sfdp.resize(0x80, 0xff);
+        for dword in fourbait {
+            sfdp.extend_from_slice(&dword.to_le_bytes());
+        }
+    }
+    sfdp
+}
+
+/// A 64 KiB chip with 3-byte addresses, which supports quad reads.
+fn small_bfpt() -> Vec<u32> {
+    let mut bfpt = vec![0; 16];
+    // 4 KiB erase with 0x20, 3-byte addresses, 1-1-4 reads.
+    bfpt[0] = 0xfff1_20e5;
+    // 512 Kib.
+    bfpt[1] = 0x0007_ffff;
+    // Erase types: 4 KiB with 0x20, 32 KiB with 0x52, 64 KiB with 0xd8.
+    bfpt[7] = 0x520f_200c;
+    bfpt[8] = 0x0000_d810;
+    // 4 KiB erases take 2 * 16 ms.
+    bfpt[9] = 1 << 4 | 1 << 9;
+    // 256 byte pages, programmed in 16 * 64 us.
+    bfpt[10] = 0x2f80;
+    bfpt
+}
+
+#[derive(Default)]
+struct Client {
+    read: Cell<Option<flash::Error>>,
+    written: Cell<Option<flash::Error>>,
+    erased: Cell<Option<flash::Error>>,
+    sector: RefCell<Option<&'static mut SpiNorSector>>,
+}
+
+impl flash::Client<Driver> for Client {
+    fn read_complete(&self, page: &'static mut SpiNorSector, error: flash::Error) {
+        self.read.set(Some(error));
+        self.sector.replace(Some(page));
+    }
+
+    fn write_complete(&self, page: &'static mut SpiNorSector, error: flash::Error) {
+        self.written.set(Some(error));
+        self.sector.replace(Some(page));
+    }
+
+    fn erase_complete(&self, error: flash::Error) {
+        self.erased.set(Some(error));
+    }
+}
+
+fn setup(
+    chip: Chip,
+) -> (
+    &'static Driver,
+    &'static Chip,
+    &'static MockSpiMasterDevice<'static>,
+    &'static MockAlarm<'static>,
+    &'static Client,
+) {
+    let spi = leak(MockSpiMasterDevice::new());
+    let alarm = leak(MockAlarm::new());
+    let txbuffer = Box::leak(vec![0; 262].into_boxed_slice());
+    let rxbuffer = Box::leak(vec![0; 262].into_boxed_slice());
+    let driver = leak(SpiNor::new(spi, alarm, txbuffer, rxbuffer));
+    let client = leak(Client::default());
+    spi.set_client(driver);
+    alarm.set_alarm_client(driver);
+    driver.set_client(client);
+    (driver, leak(chip), spi, alarm, client)
+}
+
+fn sector() -> &'static mut SpiNorSector {
+    Box::leak(Box::new(SpiNorSector::new()))
+}
+
+#[test]
+fn init_reads_sfdp() {
+    let (driver, chip, spi, alarm, _) = setup(Chip::new(sfdp(&small_bfpt(), None)));
+    assert_eq!(
+        driver.read_page(0, sector()).map_err(|(error, _)| error),
+        Err(ReturnCode::EOFF)
+    );
+
+    assert_eq!(driver.init(), ReturnCode::SUCCESS);
+    chip.run(spi, alarm);
+
+    let parameters = driver.parameters().unwrap();
+    assert_eq!(parameters.size, 64 * 1024);
+    assert_eq!(parameters.page_size, 256);
+    assert_eq!(parameters.address_bytes, 3);
+    assert_eq!(parameters.erase_opcode, 0x20);
+    assert_eq!(parameters.read_opcode, 0x03);
+    assert_eq!(parameters.erase_time_us, 32_000);
+    assert_eq!(parameters.program_time_us, 1024);
+    assert_eq!(parameters.enter_4byte, Enter4ByteMode::None);
+    assert!(parameters.quad_read);
+    assert_eq!(driver.number_of_pages(), 16);
+}
+
+#[test]
+fn write_read_and_erase_page() {
+    let (driver, chip, spi, alarm, client) = setup(Chip::new(sfdp(&small_bfpt(), None)));
+    driver.init();
+    chip.run(spi, alarm);
+
+    let page = sector();
+    for (i, byte) in page.0.iter_mut().enumerate() {
+        *byte = (i % 251) as u8;
+    }
+    assert!(driver.write_page(3, page).is_ok());
+    assert_eq!(driver.erase_page(4), ReturnCode::EBUSY);
+    chip.run(spi, alarm);
+    assert_eq!(client.written.get(), Some(flash::Error::CommandComplete));
+    for i in 0..SECTOR_SIZE {
+        assert_eq!(chip.read(3 * SECTOR_SIZE + i), (i % 251) as u8);
+    }
+
+    let page = client.sector.borrow_mut().take().unwrap();
+    page.0.iter_mut().for_each(|byte| *byte = 0);
+    assert!(driver.read_page(3, page).is_ok());
+    chip.run(spi, alarm);
+    assert_eq!(client.read.get(), Some(flash::Error::CommandComplete));
+    let page = client.sector.borrow_mut().take().unwrap();
+    assert!(page
+        .0
+        .iter()
+        .enumerate()
+        .all(|(i, byte)| *byte == (i % 251) as u8));
+
+    assert_eq!(driver.erase_page(3), ReturnCode::SUCCESS);
+    chip.run(spi, alarm);
+    assert_eq!(client.erased.get(), Some(flash::Error::CommandComplete));
+    assert!(chip.memory.borrow().is_empty());
+
+    assert_eq!(driver.erase_page(16), ReturnCode::EINVAL);
+}
+
+#[test]
+fn large_chip_uses_4byte_opcodes() {
+    let mut bfpt = small_bfpt();
+    // 3- or 4-byte addresses, 32 MiB.
+    bfpt[0] = 0xfff3_20e5;
+    bfpt[1] = 0x8000_0000 | 28;
+    // Reads, programs and type 1 erases with 4-byte addresses, using 0x21.
+    let fourbait = [1 << 0 | 1 << 6 | 1 << 9, 0xdc5c_0021];
+    let (driver, chip, spi, alarm, client) = setup(Chip::new(sfdp(&bfpt, Some(&fourbait))));
+    driver.init();
+    chip.run(spi, alarm);
+
+    let parameters = driver.parameters().unwrap();
+    assert_eq!(parameters.size, 32 * 1024 * 1024);
+    assert_eq!(parameters.address_bytes, 4);
+    assert_eq!(parameters.read_opcode, 0x13);
+    assert_eq!(parameters.program_opcode, 0x12);
+    assert_eq!(parameters.erase_opcode, 0x21);
+    assert_eq!(parameters.enter_4byte, Enter4ByteMode::None);
+
+    // The last sector is above 16 MiB.
+    let last = driver.number_of_pages() - 1;
+    chip.opcodes.borrow_mut().clear();
+    assert!(driver.write_page(last, sector()).is_ok());
+    chip.run(spi, alarm);
+    assert_eq!(client.written.get(), Some(flash::Error::CommandComplete));
+    assert_eq!(chip.read(last * SECTOR_SIZE), 0);
+    assert_eq!(chip.address_bytes.get(), 3);
+    assert!(chip.opcodes.borrow().contains(&0x21));
+    assert!(!chip.opcodes.borrow().contains(&0x20));
+}
+
+#[test]
+fn large_chip_without_4byte_opcodes_enters_4byte_mode() {
+    let mut bfpt = small_bfpt();
+    bfpt[0] = 0xfff3_20e5;
+    bfpt[1] = 0x8000_0000 | 28;
+    // Enter 4-byte address mode with EN4B.
+    bfpt[15] = 0x0100_0000;
+    let (driver, chip, spi, alarm, client) = setup(Chip::new(sfdp(&bfpt, None)));
+    driver.init();
+    chip.run(spi, alarm);
+
+    let parameters = driver.parameters().unwrap();
+    assert_eq!(parameters.address_bytes, 4);
+    assert_eq!(parameters.read_opcode, 0x03);
+    assert_eq!(parameters.enter_4byte, Enter4ByteMode::Enter);
+    assert_eq!(chip.address_bytes.get(), 4);
+
+    let last = driver.number_of_pages() - 1;
+    chip.memory
+        .borrow_mut()
+        .insert(last * SECTOR_SIZE + 1, 0x5a);
+    assert!(driver.read_page(last, sector()).is_ok());
+    chip.run(spi, alarm);
+    assert_eq!(client.read.get(), Some(flash::Error::CommandComplete));
+    assert_eq!(client.sector.borrow().as_ref().unwrap().0[1], 0x5a);
+}
+
+#[test]
+fn chip_without_sfdp() {
+    let (driver, chip, spi, alarm, _) = setup(Chip::new(Vec::new()));
+    driver.init();
+    chip.run(spi, alarm);
+    assert_eq!(driver.parameters(), None);
+    assert_eq!(driver.erase_page(0), ReturnCode::ENODEVICE);
+}
@@ -0,0 +1,478 @@
+//! Tests of the TCP state machine: the three-way handshake, data transfer,
+//! retransmission and the close paths, with a mock IP sender and alarm.
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+
+use capsules::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use capsules::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use capsules::net::ipv6::ipv6_recv::IP6RecvClient;
+use capsules::net::ipv6::ipv6_send::IP6SendClient;
+use capsules::net::tcp::tcp::{tcp_flags, TCPHeader};
+use capsules::net::tcp::tcp_mux::{MuxTcp, TCPStack};
+use capsules::net::tcp::tcp_socket::{TCPClient, TCPSocket, TcpState, TCP_TIMER_MS};
+use kernel::hil::time::Alarm;
+use kernel::ReturnCode;
+use tock_hil_mock::alarm::MockAlarm;
+use tock_hil_mock::{leak, leak_buffer};
+
+use common::{MockIP6Sender, SentPacket};
+
+const LOCAL_ADDR: IPAddr = IPAddr([
+    0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xfe, 0, 0, 0x01,
+]);
+const PEER_ADDR: IPAddr = IPAddr([
+    0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xfe, 0, 0, 0x02,
+]);
+const PEER_PORT: u16 = 1234;
+const SERVER_PORT: u16 = 80;
+const PEER_ISS: u32 = 5000;
+const BUF_LEN: usize = 64;
+
+/// A segment passed to the IP layer.
+struct Sent {
+    dst: IPAddr,
+    header: TCPHeader,
+    payload: Vec<u8>,
+}
+
+impl Sent {
+    fn new(packet: SentPacket) -> Sent {
+        let header = match packet.header {
+            TransportHeader::TCP(header) => header,
+            _ => panic!("not a TCP segment"),
+        };
+        Sent {
+            dst: packet.dst,
+            header: header,
+            payload: packet.payload,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Connected,
+    Received,
+    Sent,
+    Closed(ReturnCode),
+}
+
+struct Client {
+    events: RefCell<Vec<Event>>,
+}
+
+impl<'a> TCPClient<'a> for Client {
+    fn connected(&self, _socket: &'a TCPSocket<'a>) {
+        self.events.borrow_mut().push(Event::Connected);
+    }
+
+    fn received(&self, _socket: &'a TCPSocket<'a>) {
+        self.events.borrow_mut().push(Event::Received);
+    }
+
+    fn sent(&self, _socket: &'a TCPSocket<'a>) {
+        self.events.borrow_mut().push(Event::Sent);
+    }
+
+    fn closed(&self, _socket: &'a TCPSocket<'a>, result: ReturnCode) {
+        self.events.borrow_mut().push(Event::Closed(result));
+    }
+}
+
+struct Stack {
+    mux: &'static MuxTcp<'static, MockAlarm<'static>>,
+    socket: &'static TCPSocket<'static>,
+    ip: &'static MockIP6Sender,
+    alarm: &'static MockAlarm<'static>,
+    client: &'static Client,
+    /// Local port and initial sequence number of the connection
+    local_port: Cell<u16>,
+    iss: Cell<u32>,
+}
+
+impl Stack {
+    fn new() -> Stack {
+        let ip = leak(MockIP6Sender::new());
+        let alarm = leak(MockAlarm::new());
+        let mux = leak(MuxTcp::new(
+            ip,
+            alarm,
+            leak_buffer(&[0; BUF_LEN]),
+            common::net_cap(),
+        ));
+        alarm.set_alarm_client(mux);
+        let socket = leak(TCPSocket::new(
+            0,
+            leak_buffer(&[0; BUF_LEN]),
+            leak_buffer(&[0; BUF_LEN]),
+        ));
+        let client = leak(Client {
+            events: RefCell::new(Vec::new()),
+        });
+        socket.set_client(client);
+        mux.add_socket(socket);
+        Stack {
+            mux: mux,
+            socket: socket,
+            ip: ip,
+            alarm: alarm,
+            client: client,
+            local_port: Cell::new(0),
+            iss: Cell::new(0),
+        }
+    }
+
+    /// Takes the segments passed to the IP layer, completing each send.
+    fn take_sent(&self) -> Vec<Sent> {
+        let mut sent = Vec::new();
+        loop {
+            let next = self.ip.take_sent();
+            if next.is_empty() {
+                return sent;
+            }
+            for packet in next {
+                sent.push(Sent::new(packet));
+                self.mux.send_done(ReturnCode::SUCCESS);
+            }
+        }
+    }
+
+    fn take_one(&self) -> Sent {
+        let mut sent = self.take_sent();
+        assert_eq!(sent.len(), 1);
+        sent.pop().unwrap()
+    }
+
+    fn take_events(&self) -> Vec<Event> {
+        self.client.events.borrow_mut().drain(..).collect()
+    }
+
+    fn receive_from(&self, src_port: u16, header: TCPHeader, payload: &[u8]) {
+        let mut header = header;
+        header.set_src_port(src_port);
+        let mut buf = vec![0; header.get_hdr_size() + payload.len()];
+        let off = header.encode(&mut buf, 0).done().unwrap().0;
+        buf[off..].copy_from_slice(payload);
+        let mut ip_header = IP6Header::new();
+        ip_header.src_addr = PEER_ADDR;
+        ip_header.dst_addr = LOCAL_ADDR;
+        ip_header.set_next_header(ip6_nh::TCP);
+        ip_header.set_payload_len(buf.len() as u16);
+        self.mux.receive(ip_header, &buf);
+    }
+
+    /// Receives a segment from the peer of the connection.
+    fn receive(&self, seq: u32, ack: u32, flags: u16, payload: &[u8]) {
+        let mut header = TCPHeader::new();
+        header.set_dst_port(self.local_port.get());
+        header.set_seq_num(seq);
+        header.set_ack_num(ack);
+        header.set_flags(flags);
+        header.set_window(1024);
+        self.receive_from(PEER_PORT, header, payload);
+    }
+
+    fn advance_ms(&self, ms: u32) {
+        self.alarm.advance_ms(ms);
+    }
+
+    /// Actively opens a connection to the peer and completes the handshake.
+    fn connected() -> Stack {
+        let stack = Stack::new();
+        assert_eq!(
+            stack.mux.connect(stack.socket, PEER_ADDR, PEER_PORT),
+            ReturnCode::SUCCESS
+        );
+        let syn = stack.take_one();
+        stack.local_port.set(syn.header.get_src_port());
+        stack.iss.set(syn.header.get_seq_num());
+        stack.receive(
+            PEER_ISS,
+            stack.iss.get() + 1,
+            tcp_flags::SYN | tcp_flags::ACK,
+            &[],
+        );
+        stack.take_sent();
+        assert_eq!(stack.take_events(), vec![Event::Connected]);
+        stack
+    }
+}
+
+#[test]
+fn tcp_active_open() {
+    let stack = Stack::new();
+    assert_eq!(
+        stack.mux.connect(stack.socket, PEER_ADDR, PEER_PORT),
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(stack.socket.get_state(), TcpState::SynSent);
+
+    let syn = stack.take_one();
+    assert_eq!(syn.dst, PEER_ADDR);
+    assert_eq!(syn.header.get_dst_port(), PEER_PORT);
+    assert!(syn.header.get_src_port() >= 49152);
+    assert_eq!(syn.header.get_flags(), tcp_flags::SYN);
+    assert_eq!(syn.header.get_mss(), Some(BUF_LEN as u16));
+    assert!(syn.payload.is_empty());
+    stack.local_port.set(syn.header.get_src_port());
+    let iss = syn.header.get_seq_num();
+
+    // A SYN-ACK acknowledging something else is answered with a reset
+    stack.receive(PEER_ISS, iss + 5, tcp_flags::SYN | tcp_flags::ACK, &[]);
+    let rst = stack.take_one();
+    assert_eq!(rst.header.get_flags(), tcp_flags::RST);
+    assert_eq!(rst.header.get_seq_num(), iss + 5);
+    assert_eq!(stack.socket.get_state(), TcpState::SynSent);
+
+    stack.receive(PEER_ISS, iss + 1, tcp_flags::SYN | tcp_flags::ACK, &[]);
+    assert_eq!(stack.socket.get_state(), TcpState::Established);
+    assert_eq!(stack.take_events(), vec![Event::Connected]);
+    let ack = stack.take_one();
+    assert_eq!(ack.header.get_flags(), tcp_flags::ACK);
+    assert_eq!(ack.header.get_seq_num(), iss + 1);
+    assert_eq!(ack.header.get_ack_num(), PEER_ISS + 1);
+    assert_eq!(ack.header.get_window(), BUF_LEN as u16);
+}
+
+#[test]
+fn tcp_passive_open() {
+    let stack = Stack::new();
+    assert_eq!(
+        stack.mux.listen(stack.socket, SERVER_PORT),
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(stack.socket.get_state(), TcpState::Listen);
+    stack.local_port.set(SERVER_PORT);
+
+    stack.receive(PEER_ISS, 0, tcp_flags::SYN, &[]);
+    assert_eq!(stack.socket.get_state(), TcpState::SynReceived);
+    let syn_ack = stack.take_one();
+    assert_eq!(syn_ack.dst, PEER_ADDR);
+    assert_eq!(syn_ack.header.get_src_port(), SERVER_PORT);
+    assert_eq!(syn_ack.header.get_dst_port(), PEER_PORT);
+    assert_eq!(syn_ack.header.get_flags(), tcp_flags::SYN | tcp_flags::ACK);
+    assert_eq!(syn_ack.header.get_ack_num(), PEER_ISS + 1);
+    assert_eq!(syn_ack.header.get_mss(), Some(BUF_LEN as u16));
+    let iss = syn_ack.header.get_seq_num();
+    assert!(stack.take_events().is_empty());
+
+    stack.receive(PEER_ISS + 1, iss + 1, tcp_flags::ACK, &[]);
+    assert_eq!(stack.socket.get_state(), TcpState::Established);
+    assert_eq!(stack.socket.get_remote_endpoint(), (PEER_ADDR, PEER_PORT));
+    assert_eq!(stack.take_events(), vec![Event::Connected]);
+    assert!(stack.take_sent().is_empty());
+}
+
+#[test]
+fn tcp_transfers_data() {
+    let stack = Stack::connected();
+    let iss = stack.iss.get();
+
+    assert_eq!(stack.mux.send(stack.socket, b"hello"), 5);
+    let data = stack.take_one();
+    assert_eq!(data.header.get_flags(), tcp_flags::ACK | tcp_flags::PSH);
+    assert_eq!(data.header.get_seq_num(), iss + 1);
+    assert_eq!(data.payload, b"hello");
+    assert_eq!(stack.socket.bytes_unacked(), 5);
+
+    stack.receive(PEER_ISS + 1, iss + 6, tcp_flags::ACK, &[]);
+    assert_eq!(stack.take_events(), vec![Event::Sent]);
+    assert_eq!(stack.socket.bytes_unacked(), 0);
+    assert!(stack.take_sent().is_empty());
+
+    stack.receive(PEER_ISS + 1, iss + 6, tcp_flags::ACK, b"world");
+    assert_eq!(stack.take_events(), vec![Event::Received]);
+    let ack = stack.take_one();
+    assert_eq!(ack.header.get_ack_num(), PEER_ISS + 6);
+    assert_eq!(ack.header.get_window(), (BUF_LEN - 5) as u16);
+    let mut buf = [0; 16];
+    assert_eq!(stack.mux.recv(stack.socket, &mut buf), 5);
+    assert_eq!(&buf[..5], b"world");
+
+    // Data that does not start at the next expected byte is dropped and
+    // acknowledged with what was expected
+    stack.receive(PEER_ISS + 10, iss + 6, tcp_flags::ACK, b"later");
+    assert!(stack.take_events().is_empty());
+    let ack = stack.take_one();
+    assert_eq!(ack.header.get_ack_num(), PEER_ISS + 6);
+    assert_eq!(stack.socket.bytes_available(), 0);
+}
+
+#[test]
+fn tcp_retransmits_unacknowledged_data() {
+    let stack = Stack::connected();
+    let iss = stack.iss.get();
+
+    stack.mux.send(stack.socket, b"hello");
+    stack.take_one();
+
+    // Nothing is resent before the retransmission timeout
+    stack.advance_ms(900);
+    assert!(stack.take_sent().is_empty());
+    stack.advance_ms(100);
+    let retransmission = stack.take_one();
+    assert_eq!(retransmission.header.get_seq_num(), iss + 1);
+    assert_eq!(retransmission.payload, b"hello");
+
+    // The timeout doubles after each retransmission
+    stack.advance_ms(1900);
+    assert!(stack.take_sent().is_empty());
+    stack.advance_ms(100);
+    assert_eq!(stack.take_one().payload, b"hello");
+
+    // An acknowledgment stops the timer
+    stack.receive(PEER_ISS + 1, iss + 6, tcp_flags::ACK, &[]);
+    assert_eq!(stack.take_events(), vec![Event::Sent]);
+    stack.advance_ms(10 * TCP_TIMER_MS);
+    assert!(stack.alarm.ticks_until_fire().is_none());
+    assert!(stack.take_sent().is_empty());
+}
+
+#[test]
+fn tcp_gives_up_after_max_retries() {
+    let stack = Stack::connected();
+    stack.mux.send(stack.socket, b"hello");
+    stack.take_one();
+
+    let mut retransmissions = 0;
+    for _ in 0..600 {
+        stack.advance_ms(1000);
+        retransmissions += stack.take_sent().len();
+        if stack.socket.get_state() == TcpState::Closed {
+            break;
+        }
+    }
+    assert_eq!(stack.socket.get_state(), TcpState::Closed);
+    assert_eq!(retransmissions, 8);
+    assert_eq!(stack.take_events(), vec![Event::Closed(ReturnCode::FAIL)]);
+    assert!(stack.alarm.ticks_until_fire().is_none());
+}
+
+#[test]
+fn tcp_retransmits_syn() {
+    let stack = Stack::new();
+    stack.mux.connect(stack.socket, PEER_ADDR, PEER_PORT);
+    let iss = stack.take_one().header.get_seq_num();
+
+    stack.advance_ms(1000);
+    let syn = stack.take_one();
+    assert_eq!(syn.header.get_flags(), tcp_flags::SYN);
+    assert_eq!(syn.header.get_seq_num(), iss);
+    assert_eq!(stack.socket.get_state(), TcpState::SynSent);
+}
+
+#[test]
+fn tcp_active_close() {
+    let stack = Stack::connected();
+    let iss = stack.iss.get();
+
+    assert_eq!(stack.mux.close(stack.socket), ReturnCode::SUCCESS);
+    let fin = stack.take_one();
+    assert_eq!(fin.header.get_flags(), tcp_flags::FIN | tcp_flags::ACK);
+    assert_eq!(fin.header.get_seq_num(), iss + 1);
+    assert_eq!(stack.socket.get_state(), TcpState::FinWait1);
+    assert_eq!(stack.mux.close(stack.socket), ReturnCode::EALREADY);
+
+    stack.receive(PEER_ISS + 1, iss + 2, tcp_flags::ACK, &[]);
+    assert_eq!(stack.socket.get_state(), TcpState::FinWait2);
+
+    stack.receive(PEER_ISS + 1, iss + 2, tcp_flags::FIN | tcp_flags::ACK, &[]);
+    assert_eq!(stack.socket.get_state(), TcpState::TimeWait);
+    assert!(stack.socket.is_remote_closed());
+    assert_eq!(stack.take_events(), vec![Event::Received]);
+    let ack = stack.take_one();
+    assert_eq!(ack.header.get_flags(), tcp_flags::ACK);
+    assert_eq!(ack.header.get_ack_num(), PEER_ISS + 2);
+
+    stack.advance_ms(2000);
+    assert_eq!(stack.socket.get_state(), TcpState::Closed);
+    assert_eq!(
+        stack.take_events(),
+        vec![Event::Closed(ReturnCode::SUCCESS)]
+    );
+}
+
+#[test]
+fn tcp_sends_fin_after_buffered_data() {
+    let stack = Stack::connected();
+    let iss = stack.iss.get();
+
+    stack.mux.send(stack.socket, b"bye");
+    stack.mux.close(stack.socket);
+    let sent = stack.take_sent();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0].payload, b"bye");
+    assert!(sent[1].header.has_flags(tcp_flags::FIN));
+    assert_eq!(sent[1].header.get_seq_num(), iss + 4);
+
+    // Lost data and FIN are both sent again after a timeout
+    stack.advance_ms(1000);
+    let sent = stack.take_sent();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0].payload, b"bye");
+    assert!(sent[1].header.has_flags(tcp_flags::FIN));
+}
+
+#[test]
+fn tcp_passive_close() {
+    let stack = Stack::connected();
+    let iss = stack.iss.get();
+
+    stack.receive(PEER_ISS + 1, iss + 1, tcp_flags::FIN | tcp_flags::ACK, &[]);
+    assert_eq!(stack.socket.get_state(), TcpState::CloseWait);
+    assert!(stack.socket.is_remote_closed());
+    assert_eq!(stack.take_events(), vec![Event::Received]);
+    assert_eq!(stack.take_one().header.get_ack_num(), PEER_ISS + 2);
+
+    stack.mux.close(stack.socket);
+    let fin = stack.take_one();
+    assert_eq!(fin.header.get_flags(), tcp_flags::FIN | tcp_flags::ACK);
+    assert_eq!(stack.socket.get_state(), TcpState::LastAck);
+
+    stack.receive(PEER_ISS + 2, iss + 2, tcp_flags::ACK, &[]);
+    assert_eq!(stack.socket.get_state(), TcpState::Closed);
+    assert_eq!(
+        stack.take_events(),
+        vec![Event::Closed(ReturnCode::SUCCESS)]
+    );
+    assert!(stack.take_sent().is_empty());
+}
+
+#[test]
+fn tcp_resets_segments_without_socket() {
+    let stack = Stack::new();
+    stack.mux.listen(stack.socket, SERVER_PORT);
+
+    let mut header = TCPHeader::new();
+    header.set_dst_port(SERVER_PORT + 1);
+    header.set_seq_num(PEER_ISS);
+    header.set_flags(tcp_flags::SYN);
+    stack.receive_from(PEER_PORT, header, &[]);
+    let rst = stack.take_one();
+    assert_eq!(rst.dst, PEER_ADDR);
+    assert_eq!(rst.header.get_src_port(), SERVER_PORT + 1);
+    assert_eq!(rst.header.get_dst_port(), PEER_PORT);
+    assert_eq!(rst.header.get_flags(), tcp_flags::RST | tcp_flags::ACK);
+    assert_eq!(rst.header.get_ack_num(), PEER_ISS + 1);
+    assert_eq!(stack.socket.get_state(), TcpState::Listen);
+}
+
+#[test]
+fn tcp_closes_on_reset() {
+    let stack = Stack::connected();
+    let iss = stack.iss.get();
+
+    // A reset outside the window is ignored
+    stack.receive(PEER_ISS + 100, iss + 1, tcp_flags::RST, &[]);
+    assert_eq!(stack.socket.get_state(), TcpState::Established);
+
+    stack.receive(PEER_ISS + 1, iss + 1, tcp_flags::RST, &[]);
+    assert_eq!(stack.socket.get_state(), TcpState::Closed);
+    assert_eq!(
+        stack.take_events(),
+        vec![Event::Closed(ReturnCode::ECANCEL)]
+    );
+    assert!(stack.take_sent().is_empty());
+}
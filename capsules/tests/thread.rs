@@ -0,0 +1,1356 @@
+//! Tests of the Thread MLE message format, key derivation and attach
+//! procedure of a sleepy end device, with mock IP senders, MAC device,
+//! AES-CCM engine and alarm. The data requests of the device are sent
+//! through a real framer over a mock MAC layer.
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+
+use capsules::ieee802154::device::{MacDevice, RxClient, TxClient};
+use capsules::ieee802154::framer::{DeviceProcedure, Frame, Framer, KeyProcedure};
+use capsules::ieee802154::mac::Mac;
+use capsules::net::ieee802154::{FrameType, Header, KeyId, MacAddress, PanID, SecurityLevel};
+use capsules::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use capsules::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use capsules::net::ipv6::ipv6_recv::IP6RecvClient;
+use capsules::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use capsules::net::ipv6::neighbor_cache::{NeighborCache, NeighborEntry};
+use capsules::net::thread::data_poll::{
+    DataPoll, FAST_POLL_PERIOD_MS, RETRY_PERIOD_MS, RX_WINDOW_MS,
+};
+use capsules::net::thread::mle::{
+    command, key_index, link_local_from_ext_addr, nonce, split_tlvs, AuxHeader, AUX_HEADER_LEN,
+    CRYPT_BUF_LEN, MIC_LEN, MLE_PORT, SECURITY_SUITE_SECURED, SECURITY_SUITE_UNSECURED,
+};
+use capsules::net::thread::mle_attach::{
+    AttachState, MleAttach, NetworkKeys, CHILD_ID_RESPONSE_TIMEOUT_MS,
+    CHILD_UPDATE_RESPONSE_TIMEOUT_MS, DEFAULT_CHILD_TIMEOUT, PARENT_REQUEST_REED_TIMEOUT_MS,
+    PARENT_REQUEST_ROUTER_TIMEOUT_MS, TX_BUF_LEN,
+};
+use capsules::net::thread::sha256::{hmac_sha256, Sha256};
+use capsules::net::thread::tlv::{NetworkManagementTlv, Tlv, TlvType};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::common::cells::TakeCell;
+use kernel::hil::radio;
+use kernel::hil::rng::Random;
+use kernel::hil::symmetric_encryption::AES128CCM;
+use kernel::hil::time::Alarm;
+use kernel::ReturnCode;
+use tock_hil_mock::aes_ccm::{tag, MockAES128CCM};
+use tock_hil_mock::alarm::MockAlarm;
+use tock_hil_mock::{leak, leak_buffer};
+
+use common::{MockIP6Sender, SentPacket};
+
+const NODE_EXT: [u8; 8] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+const PARENT_EXT: [u8; 8] = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+const OTHER_EXT: [u8; 8] = [0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99];
+const PARENT_RLOC16: u16 = 0x0400;
+const OTHER_RLOC16: u16 = 0x0800;
+const PARENT_CHALLENGE: [u8; 8] = [0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8];
+const MESH_LOCAL_PREFIX: [u8; 8] = [0xfd, 0, 0x0d, 0xb8, 0, 0, 0, 0];
+const MASTER_KEY: [u8; 16] = [
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+];
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+/// A UDP datagram passed to an IP sender.
+struct Sent {
+    src: IPAddr,
+    dst: IPAddr,
+    src_port: u16,
+    dst_port: u16,
+    payload: Vec<u8>,
+}
+
+impl Sent {
+    fn new(packet: SentPacket) -> Sent {
+        let header = match packet.header {
+            TransportHeader::UDP(header) => header,
+            _ => panic!("not a UDP datagram"),
+        };
+        Sent {
+            src: packet.src,
+            dst: packet.dst,
+            src_port: header.get_src_port(),
+            dst_port: header.get_dst_port(),
+            payload: packet.payload,
+        }
+    }
+}
+
+fn keys(key_sequence: u32) -> NetworkKeys {
+    NetworkKeys::derive(&MASTER_KEY, key_sequence)
+}
+
+/// An MLE message sent by the node, after checking its security.
+struct Message {
+    dst: IPAddr,
+    frame_counter: u32,
+    command: u8,
+    tlvs: Vec<u8>,
+}
+
+impl Message {
+    fn tlv(&self, tlv_type: TlvType) -> Option<Tlv> {
+        let tlv_type = tlv_type as u8;
+        split_tlvs(&self.tlvs)
+            .find(|tlv| tlv[0] == tlv_type)
+            .map(|tlv| Tlv::decode(tlv).done().unwrap().1)
+    }
+
+    fn challenge(&self) -> [u8; 8] {
+        match self.tlv(TlvType::Challenge) {
+            Some(Tlv::Challenge(challenge)) => challenge,
+            _ => panic!("no challenge"),
+        }
+    }
+
+    fn scan_mask(&self) -> u8 {
+        match self.tlv(TlvType::ScanMask) {
+            Some(Tlv::ScanMask(scan_mask)) => scan_mask,
+            _ => panic!("no scan mask"),
+        }
+    }
+}
+
+/// A MAC command frame sent by the node.
+struct Command {
+    dst_addr: Option<MacAddress>,
+    src_addr: Option<MacAddress>,
+    key_id: Option<KeyId>,
+    command_id: u8,
+}
+
+/// Records the configuration MLE gives the MAC layer.
+struct MockMac {
+    address: Cell<u16>,
+    pan: Cell<u16>,
+    frame_counter: Cell<u32>,
+    commits: Cell<usize>,
+}
+
+impl<'a> MacDevice<'a> for MockMac {
+    fn set_transmit_client(&self, _client: &'a dyn TxClient) {}
+    fn set_receive_client(&self, _client: &'a dyn RxClient) {}
+    fn set_key_procedure(&self, _key_procedure: &'a dyn KeyProcedure) {}
+    fn set_device_procedure(&self, _device_procedure: &'a dyn DeviceProcedure) {}
+
+    fn get_address(&self) -> u16 {
+        self.address.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        NODE_EXT
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
+    fn get_frame_counter(&self) -> u32 {
+        self.frame_counter.get()
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.address.set(addr);
+    }
+
+    fn set_address_long(&self, _addr: [u8; 8]) {}
+
+    fn set_pan(&self, id: u16) {
+        self.pan.set(id);
+    }
+
+    fn config_commit(&self) {
+        self.commits.set(self.commits.get() + 1);
+    }
+
+    fn is_on(&self) -> bool {
+        true
+    }
+
+    fn set_rx_on_when_idle(&self, _on: bool) {}
+
+    fn prepare_data_frame(
+        &self,
+        buf: &'static mut [u8],
+        _dst_pan: PanID,
+        _dst_addr: MacAddress,
+        _src_pan: PanID,
+        _src_addr: MacAddress,
+        _security_needed: Option<(SecurityLevel, KeyId)>,
+    ) -> Result<Frame, &'static mut [u8]> {
+        Err(buf)
+    }
+
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        _dst_pan: PanID,
+        _dst_addr: MacAddress,
+        _src_pan: PanID,
+        _src_addr: MacAddress,
+        _security_needed: Option<(SecurityLevel, KeyId)>,
+        _command_id: u8,
+    ) -> Result<Frame, &'static mut [u8]> {
+        Err(buf)
+    }
+
+    fn transmit(&self, frame: Frame) -> (ReturnCode, Option<&'static mut [u8]>) {
+        (ReturnCode::FAIL, Some(frame.into_buf()))
+    }
+}
+
+/// The MAC layer under the framer of the data requests. It shares the
+/// addresses of the `MockMac`, and records the frames it transmits and
+/// whether the receiver stays on when idle.
+struct MockRadioMac {
+    device: &'static MockMac,
+    rx_on_when_idle: Cell<bool>,
+    frames: RefCell<Vec<Vec<u8>>>,
+    /// Buffer of the frame being transmitted
+    buf: TakeCell<'static, [u8]>,
+}
+
+impl Mac for MockRadioMac {
+    fn initialize(&self, _mac_buf: &'static mut [u8]) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn set_config_client(&self, _client: &'static dyn radio::ConfigClient) {}
+    fn set_transmit_client(&self, _client: &'static dyn radio::TxClient) {}
+    fn set_receive_client(&self, _client: &'static dyn radio::RxClient) {}
+    fn set_receive_buffer(&self, _buffer: &'static mut [u8]) {}
+
+    fn get_address(&self) -> u16 {
+        self.device.address.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        NODE_EXT
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.device.pan.get()
+    }
+
+    fn set_address(&self, _addr: u16) {}
+    fn set_address_long(&self, _addr: [u8; 8]) {}
+    fn set_pan(&self, _id: u16) {}
+    fn config_commit(&self) {}
+
+    fn is_on(&self) -> bool {
+        true
+    }
+
+    fn set_rx_on_when_idle(&self, on: bool) {
+        self.rx_on_when_idle.set(on);
+    }
+
+    fn transmit(
+        &self,
+        full_mac_frame: &'static mut [u8],
+        frame_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.frames
+            .borrow_mut()
+            .push(full_mac_frame[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len].to_vec());
+        self.buf.replace(full_mac_frame);
+        (ReturnCode::SUCCESS, None)
+    }
+}
+
+/// Returns consecutive numbers, so that challenges differ.
+struct MockRandom {
+    next: Cell<u32>,
+}
+
+impl<'a> Random<'a> for MockRandom {
+    fn initialize(&'a self) {}
+
+    fn reseed(&self, seed: u32) {
+        self.next.set(seed);
+    }
+
+    fn random(&self) -> u32 {
+        let value = self.next.get();
+        self.next.set(value.wrapping_add(1));
+        value
+    }
+}
+
+type NodeAlarm = VirtualMuxAlarm<'static, MockAlarm<'static>>;
+
+struct Node {
+    mle: &'static MleAttach<'static, NodeAlarm>,
+    ip: &'static MockIP6Sender,
+    data_ip: &'static MockIP6Sender,
+    mac: &'static MockMac,
+    cache: &'static NeighborCache<'static>,
+    aes: &'static MockAES128CCM<'static>,
+    alarm: &'static MockAlarm<'static>,
+    radio_mac: &'static MockRadioMac,
+    framer: &'static Framer<'static, MockRadioMac, MockAES128CCM<'static>>,
+    framer_aes: &'static MockAES128CCM<'static>,
+}
+
+impl Node {
+    fn new() -> Node {
+        Node::with_key_sequence(0)
+    }
+
+    fn with_key_sequence(key_sequence: u32) -> Node {
+        let ip = leak(MockIP6Sender::new());
+        let data_ip = leak(MockIP6Sender::new());
+        let data_senders: &'static [&'static dyn IP6Sender<'static>] =
+            Box::leak(Box::new([data_ip as &dyn IP6Sender<'static>]));
+        let mac = leak(MockMac {
+            address: Cell::new(0xffff),
+            pan: Cell::new(0xabcd),
+            frame_counter: Cell::new(77),
+            commits: Cell::new(0),
+        });
+        let cache = leak(NeighborCache::new(Box::leak(Box::new(
+            [None; 8] as [Option<NeighborEntry>; 8],
+        ))));
+        let aes = leak(MockAES128CCM::new());
+        let alarm: &MockAlarm = leak(MockAlarm::new());
+        let mux_alarm = leak(MuxAlarm::new(alarm));
+        alarm.set_alarm_client(mux_alarm);
+        let mle_alarm = leak(VirtualMuxAlarm::new(mux_alarm));
+        let poll_alarm = leak(VirtualMuxAlarm::new(mux_alarm));
+        let rng = leak(MockRandom {
+            next: Cell::new(0x01020304),
+        });
+        let radio_mac = leak(MockRadioMac {
+            device: mac,
+            rx_on_when_idle: Cell::new(true),
+            frames: RefCell::new(Vec::new()),
+            buf: TakeCell::empty(),
+        });
+        let framer_aes = leak(MockAES128CCM::new());
+        let framer = leak(Framer::new(radio_mac, framer_aes));
+        framer_aes.set_client(framer);
+        let data_poll = leak(DataPoll::new(
+            framer,
+            poll_alarm,
+            leak_buffer(&[0; radio::MAX_BUF_SIZE]),
+        ));
+        framer.set_transmit_client(data_poll);
+        poll_alarm.set_alarm_client(data_poll);
+        let mle = leak(MleAttach::new(
+            ip,
+            data_senders,
+            mac,
+            cache,
+            aes,
+            mle_alarm,
+            rng,
+            data_poll,
+            MASTER_KEY,
+            key_sequence,
+            leak_buffer(&[0; CRYPT_BUF_LEN]),
+            leak_buffer(&[0; TX_BUF_LEN]),
+            common::net_cap(),
+        ));
+        aes.set_client(mle);
+        mle_alarm.set_alarm_client(mle);
+        framer.set_key_procedure(mle);
+        framer.set_device_procedure(mle);
+        data_poll.set_client(mle);
+        Node {
+            mle: mle,
+            ip: ip,
+            data_ip: data_ip,
+            mac: mac,
+            cache: cache,
+            aes: aes,
+            alarm: alarm,
+            radio_mac: radio_mac,
+            framer: framer,
+            framer_aes: framer_aes,
+        }
+    }
+
+    /// Takes the MLE messages passed to the IP layer, completing each
+    /// encryption and send, and checks that they are secured with the MLE
+    /// key of the current Key Sequence.
+    fn take_sent(&self) -> Vec<Message> {
+        let mut messages = Vec::new();
+        loop {
+            while self.aes.complete() {}
+            let next = self.ip.take_sent();
+            if next.is_empty() {
+                return messages;
+            }
+            let keys = keys(self.mle.get_key_sequence());
+            for packet in next {
+                messages.push(open(&Sent::new(packet), &keys));
+                self.mle.send_done(ReturnCode::SUCCESS);
+            }
+        }
+    }
+
+    /// Takes the MAC command frames the framer passed to the MAC layer.
+    fn take_commands(&self) -> Vec<Command> {
+        while self.framer_aes.complete() {}
+        self.radio_mac
+            .frames
+            .borrow_mut()
+            .drain(..)
+            .map(|frame| {
+                // The mock AES-CCM engine leaves the payload in the clear
+                let (_, (header, offset)) = Header::decode(&frame, true).done().unwrap();
+                assert_eq!(header.frame_type, FrameType::MACCommand);
+                Command {
+                    dst_addr: header.dst_addr,
+                    src_addr: header.src_addr,
+                    key_id: header.security.map(|security| security.key_id),
+                    command_id: frame[offset],
+                }
+            })
+            .collect()
+    }
+
+    /// Completes the transmission of the frame the MAC layer holds.
+    fn transmit_done(&self, acked: bool) {
+        let buf = self.radio_mac.buf.take().unwrap();
+        radio::TxClient::send_done(self.framer, buf, acked, ReturnCode::SUCCESS);
+    }
+
+    fn take_one(&self) -> Message {
+        let mut messages = self.take_sent();
+        assert_eq!(messages.len(), 1);
+        messages.remove(0)
+    }
+
+    /// Passes a datagram to MLE as the IP layer would, and completes its
+    /// decryption.
+    fn receive_datagram(&self, src: IPAddr, payload: &[u8]) {
+        // The header is written by hand in network byte order
+        let mut buf = vec![0; 8 + payload.len()];
+        buf[0..2].copy_from_slice(&MLE_PORT.to_be_bytes());
+        buf[2..4].copy_from_slice(&MLE_PORT.to_be_bytes());
+        buf[4..6].copy_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        buf[8..].copy_from_slice(payload);
+        let mut ip_header = IP6Header::new();
+        ip_header.src_addr = src;
+        ip_header.dst_addr = link_local_from_ext_addr(NODE_EXT);
+        ip_header.set_next_header(ip6_nh::UDP);
+        ip_header.set_payload_len(buf.len() as u16);
+        self.mle.receive(ip_header, &buf);
+        while self.aes.complete() {}
+    }
+
+    fn receive(&self, src_ext: [u8; 8], frame_counter: u32, command: u8, tlvs: &[Tlv]) {
+        let keys = keys(self.mle.get_key_sequence());
+        let payload = seal(&keys, src_ext, frame_counter, command, tlvs);
+        self.receive_datagram(link_local_from_ext_addr(src_ext), &payload);
+    }
+
+    /// Starts attaching and returns the challenge of the Parent Request.
+    fn start(&self) -> [u8; 8] {
+        self.mle.start();
+        self.take_one().challenge()
+    }
+
+    fn advance_ms(&self, ms: u32) {
+        self.alarm.advance_ms(ms);
+    }
+}
+
+/// Checks the security of a message sent by the node with `keys` and
+/// decodes it.
+fn open(sent: &Sent, keys: &NetworkKeys) -> Message {
+    assert_eq!(sent.src, link_local_from_ext_addr(NODE_EXT));
+    assert_eq!((sent.src_port, sent.dst_port), (MLE_PORT, MLE_PORT));
+    let payload = &sent.payload;
+    assert_eq!(payload[0], SECURITY_SUITE_SECURED);
+    let (_, aux_header) = AuxHeader::decode(&payload[1..]).done().unwrap();
+    assert_eq!(
+        aux_header,
+        AuxHeader::new(aux_header.frame_counter, keys.key_sequence)
+    );
+    let m_end = payload.len() - MIC_LEN;
+    let m_data = &payload[1 + AUX_HEADER_LEN..m_end];
+    let a_data = [
+        &sent.src.0[..],
+        &sent.dst.0[..],
+        &payload[1..1 + AUX_HEADER_LEN],
+    ]
+    .concat();
+    let expected = tag(
+        &keys.mle_key,
+        &nonce(&NODE_EXT, aux_header.frame_counter),
+        &a_data,
+        m_data,
+        MIC_LEN,
+    );
+    assert_eq!(&payload[m_end..], &expected[..]);
+    Message {
+        dst: sent.dst,
+        frame_counter: aux_header.frame_counter,
+        command: m_data[0],
+        tlvs: m_data[1..].to_vec(),
+    }
+}
+
+fn encode(tlvs: &[Tlv]) -> Vec<u8> {
+    let mut buf = vec![0; 256];
+    let mut off = 0;
+    for tlv in tlvs {
+        off += tlv.encode(&mut buf[off..]).done().unwrap().0;
+    }
+    buf.truncate(off);
+    buf
+}
+
+/// Builds a secured MLE message from `src_ext` to the node, as the
+/// payload of a UDP datagram.
+fn seal(
+    keys: &NetworkKeys,
+    src_ext: [u8; 8],
+    frame_counter: u32,
+    command: u8,
+    tlvs: &[Tlv],
+) -> Vec<u8> {
+    let mut aux = [0; AUX_HEADER_LEN];
+    AuxHeader::new(frame_counter, keys.key_sequence)
+        .encode(&mut aux)
+        .done()
+        .unwrap();
+    let mut m_data = vec![command];
+    m_data.extend(encode(tlvs));
+    let a_data = [
+        &link_local_from_ext_addr(src_ext).0[..],
+        &link_local_from_ext_addr(NODE_EXT).0[..],
+        &aux[..],
+    ]
+    .concat();
+    let mut payload = vec![SECURITY_SUITE_SECURED];
+    payload.extend(&aux);
+    payload.extend(MockAES128CCM::seal(
+        &keys.mle_key,
+        &nonce(&src_ext, frame_counter),
+        &a_data,
+        &m_data,
+        MIC_LEN,
+    ));
+    payload
+}
+
+fn parent_response_tlvs(
+    rloc16: u16,
+    response: [u8; 8],
+    link_margin: u8,
+    parent_priority: u8,
+    link_quality_3: u8,
+) -> Vec<Tlv<'static>> {
+    vec![
+        Tlv::SourceAddress(rloc16),
+        Tlv::LeaderData {
+            partition_id: 1,
+            weighting: 64,
+            data_version: 0,
+            stable_data_version: 0,
+            leader_router_id: 1,
+        },
+        Tlv::LinkLayerFrameCounter(0),
+        Tlv::MleFrameCounter(0),
+        Tlv::Response(response),
+        Tlv::Challenge(PARENT_CHALLENGE),
+        Tlv::LinkMargin(link_margin),
+        Tlv::Connectivity {
+            parent_priority: parent_priority,
+            link_quality_3: link_quality_3,
+            link_quality_2: 0,
+            link_quality_1: 0,
+            leader_cost: 1,
+            id_sequence: 1,
+            active_routers: 2,
+            sed_buffer_size: None,
+            sed_datagram_count: None,
+        },
+        Tlv::Version(2),
+    ]
+}
+
+fn parent_response(node: &Node, ext: [u8; 8], rloc16: u16, frame_counter: u32, challenge: [u8; 8]) {
+    node.receive(
+        ext,
+        frame_counter,
+        command::PARENT_RESPONSE,
+        &parent_response_tlvs(rloc16, challenge, 30, 0, 1),
+    );
+}
+
+fn child_id_response(node: &Node, frame_counter: u32, rloc16: u16) {
+    let dataset = [
+        encode_network_management(&NetworkManagementTlv::PanId(0xface)),
+        encode_network_management(&NetworkManagementTlv::NetworkMeshLocalPrefix(
+            MESH_LOCAL_PREFIX,
+        )),
+    ]
+    .concat();
+    node.receive(
+        PARENT_EXT,
+        frame_counter,
+        command::CHILD_ID_RESPONSE,
+        &[
+            Tlv::SourceAddress(PARENT_RLOC16),
+            Tlv::LeaderData {
+                partition_id: 1,
+                weighting: 64,
+                data_version: 0,
+                stable_data_version: 0,
+                leader_router_id: 1,
+            },
+            Tlv::Address16(rloc16),
+            Tlv::ActiveOperationalDataset(&dataset),
+        ],
+    );
+}
+
+fn encode_network_management(tlv: &NetworkManagementTlv) -> Vec<u8> {
+    let mut buf = vec![0; 64];
+    let (len, _) = tlv.encode(&mut buf).done().unwrap();
+    buf.truncate(len);
+    buf
+}
+
+/// Attaches the node to the parent, returning the Child ID Request.
+fn attach(node: &Node) -> Message {
+    let challenge = node.start();
+    parent_response(node, PARENT_EXT, PARENT_RLOC16, 10, challenge);
+    node.advance_ms(PARENT_REQUEST_ROUTER_TIMEOUT_MS);
+    let request = node.take_one();
+    child_id_response(node, 11, PARENT_RLOC16 | 0x02);
+    assert_eq!(node.mle.get_state(), AttachState::Attached);
+    request
+}
+
+#[test]
+fn mle_aux_header_round_trip() {
+    let aux_header = AuxHeader::new(0x01020304, 0x80);
+    let mut buf = [0; AUX_HEADER_LEN];
+    aux_header.encode(&mut buf).done().unwrap();
+    assert_eq!(buf, [0x15, 0x04, 0x03, 0x02, 0x01, 0, 0, 0, 0x80, 1]);
+    assert_eq!(AuxHeader::decode(&buf).done().unwrap().1, aux_header);
+    assert_eq!(key_index(0), 1);
+    assert_eq!(key_index(5), 6);
+
+    // Only key ID mode 2 at security level 5 is accepted
+    buf[0] = 0x0d;
+    assert!(AuxHeader::decode(&buf).done().is_none());
+
+    let nonce = nonce(&PARENT_EXT, 0x01020304);
+    assert_eq!(&nonce[..8], &PARENT_EXT);
+    assert_eq!(&nonce[8..], &[1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn mle_parent_request_asks_routers_then_reeds() {
+    let node = Node::new();
+    node.mle.start();
+    let request = node.take_one();
+    assert_eq!(node.mle.get_state(), AttachState::ParentRequestRouters);
+    assert_eq!(request.dst, ALL_ROUTERS);
+    assert_eq!(request.command, command::PARENT_REQUEST);
+    assert_eq!(request.scan_mask(), 0x80);
+    assert!(matches!(request.tlv(TlvType::Mode), Some(Tlv::Mode(0x04))));
+    assert!(matches!(
+        request.tlv(TlvType::Version),
+        Some(Tlv::Version(2))
+    ));
+    let challenge = request.challenge();
+
+    node.advance_ms(PARENT_REQUEST_ROUTER_TIMEOUT_MS - 1);
+    assert!(node.take_sent().is_empty());
+    node.advance_ms(1);
+    let request = node.take_one();
+    assert_eq!(node.mle.get_state(), AttachState::ParentRequestReeds);
+    assert_eq!(request.scan_mask(), 0xc0);
+    assert_ne!(request.challenge(), challenge);
+    assert!(request.frame_counter > 0);
+}
+
+#[test]
+fn mle_backs_off_until_a_parent_responds() {
+    let node = Node::new();
+    node.start();
+    node.advance_ms(PARENT_REQUEST_ROUTER_TIMEOUT_MS);
+    node.take_one();
+    node.advance_ms(PARENT_REQUEST_REED_TIMEOUT_MS);
+    assert_eq!(node.mle.get_state(), AttachState::Backoff);
+
+    for backoff in [1000, 2000, 4000].iter() {
+        node.advance_ms(backoff - 1);
+        assert!(node.take_sent().is_empty());
+        node.advance_ms(1);
+        assert_eq!(node.take_one().scan_mask(), 0x80);
+        node.advance_ms(PARENT_REQUEST_ROUTER_TIMEOUT_MS);
+        assert_eq!(node.take_one().scan_mask(), 0xc0);
+        node.advance_ms(PARENT_REQUEST_REED_TIMEOUT_MS);
+        assert!(node.take_sent().is_empty());
+        assert_eq!(node.mle.get_state(), AttachState::Backoff);
+    }
+}
+
+#[test]
+fn mle_selects_best_parent() {
+    let node = Node::new();
+    let challenge = node.start();
+    // Link quality 2, high priority
+    node.receive(
+        OTHER_EXT,
+        1,
+        command::PARENT_RESPONSE,
+        &parent_response_tlvs(OTHER_RLOC16, challenge, 15, 0x40, 9),
+    );
+    // Link quality 3, low priority
+    node.receive(
+        [0x32; 8],
+        1,
+        command::PARENT_RESPONSE,
+        &parent_response_tlvs(0x0c00, challenge, 25, 0xc0, 9),
+    );
+    // Link quality 3, medium priority
+    node.receive(
+        PARENT_EXT,
+        1,
+        command::PARENT_RESPONSE,
+        &parent_response_tlvs(PARENT_RLOC16, challenge, 25, 0x00, 1),
+    );
+    // Link quality 3, medium priority, fewer good links
+    node.receive(
+        [0x42; 8],
+        1,
+        command::PARENT_RESPONSE,
+        &parent_response_tlvs(0x1000, challenge, 25, 0x00, 0),
+    );
+    assert!(node.take_sent().is_empty());
+
+    node.mac.frame_counter.set(1234);
+    node.advance_ms(PARENT_REQUEST_ROUTER_TIMEOUT_MS);
+    let request = node.take_one();
+    assert_eq!(node.mle.get_state(), AttachState::ChildIdRequest);
+    assert_eq!(request.dst, link_local_from_ext_addr(PARENT_EXT));
+    assert_eq!(request.command, command::CHILD_ID_REQUEST);
+    assert!(matches!(
+        request.tlv(TlvType::Response),
+        Some(Tlv::Response(PARENT_CHALLENGE))
+    ));
+    assert!(matches!(
+        request.tlv(TlvType::LinkLayerFrameCounter),
+        Some(Tlv::LinkLayerFrameCounter(1234))
+    ));
+    match request.tlv(TlvType::MleFrameCounter) {
+        Some(Tlv::MleFrameCounter(frame_counter)) => {
+            assert_eq!(frame_counter, request.frame_counter)
+        }
+        _ => panic!("no MLE frame counter"),
+    }
+    assert!(matches!(request.tlv(TlvType::Mode), Some(Tlv::Mode(0x04))));
+    match request.tlv(TlvType::Timeout) {
+        Some(Tlv::Timeout(timeout)) => assert_eq!(timeout, DEFAULT_CHILD_TIMEOUT),
+        _ => panic!("no timeout"),
+    }
+    match request.tlv(TlvType::TlvRequest) {
+        Some(Tlv::TlvRequest(tlvs)) => assert_eq!(tlvs, &[10, 12]),
+        _ => panic!("no TLV request"),
+    }
+}
+
+#[test]
+fn mle_ignores_unsolicited_parent_responses() {
+    let node = Node::new();
+    let challenge = node.start();
+    let mut wrong_challenge = challenge;
+    wrong_challenge[0] ^= 1;
+    parent_response(&node, PARENT_EXT, PARENT_RLOC16, 1, wrong_challenge);
+    // Only routers may answer the first Parent Request
+    parent_response(&node, OTHER_EXT, OTHER_RLOC16 | 0x01, 1, challenge);
+    node.advance_ms(PARENT_REQUEST_ROUTER_TIMEOUT_MS);
+    let request = node.take_one();
+    assert_eq!(request.command, command::PARENT_REQUEST);
+
+    // A REED can answer the second one
+    parent_response(
+        &node,
+        OTHER_EXT,
+        OTHER_RLOC16 | 0x01,
+        2,
+        request.challenge(),
+    );
+    node.advance_ms(PARENT_REQUEST_REED_TIMEOUT_MS);
+    let request = node.take_one();
+    assert_eq!(request.command, command::CHILD_ID_REQUEST);
+    assert_eq!(request.dst, link_local_from_ext_addr(OTHER_EXT));
+}
+
+#[test]
+fn mle_drops_messages_with_bad_security() {
+    let node = Node::with_key_sequence(2);
+    let challenge = node.start();
+    let tlvs = parent_response_tlvs(PARENT_RLOC16, challenge, 30, 0, 1);
+
+    // Wrong key
+    let wrong_keys = NetworkKeys {
+        mle_key: [0x33; 16],
+        ..keys(2)
+    };
+    let payload = seal(&wrong_keys, PARENT_EXT, 1, command::PARENT_RESPONSE, &tlvs);
+    node.receive_datagram(link_local_from_ext_addr(PARENT_EXT), &payload);
+
+    // Key Sequence older than the previous one, which is not decrypted at
+    // all
+    node.aes.take_operations();
+    let payload = seal(&keys(0), PARENT_EXT, 2, command::PARENT_RESPONSE, &tlvs);
+    node.receive_datagram(link_local_from_ext_addr(PARENT_EXT), &payload);
+    assert!(node.aes.operations().is_empty());
+
+    // Tampered message
+    let mut payload = seal(&keys(2), PARENT_EXT, 3, command::PARENT_RESPONSE, &tlvs);
+    let last = payload.len() - MIC_LEN - 1;
+    payload[last] ^= 1;
+    node.receive_datagram(link_local_from_ext_addr(PARENT_EXT), &payload);
+
+    // Unsecured message
+    let mut payload = vec![SECURITY_SUITE_UNSECURED, command::PARENT_RESPONSE];
+    payload.extend(encode(&tlvs));
+    node.receive_datagram(link_local_from_ext_addr(PARENT_EXT), &payload);
+
+    node.advance_ms(PARENT_REQUEST_ROUTER_TIMEOUT_MS);
+    assert_eq!(node.take_one().command, command::PARENT_REQUEST);
+    assert_eq!(node.mle.get_state(), AttachState::ParentRequestReeds);
+}
+
+#[test]
+fn mle_attach_configures_mac_and_ip() {
+    let node = Node::new();
+    assert_eq!(node.data_ip.security(), None);
+    attach(&node);
+
+    assert_eq!(node.mle.get_rloc16(), Some(0x0402));
+    assert_eq!(
+        node.mle.get_parent(),
+        Some(link_local_from_ext_addr(PARENT_EXT))
+    );
+    assert_eq!(node.mle.get_mesh_local_prefix(), Some(MESH_LOCAL_PREFIX));
+    assert_eq!(node.mac.address.get(), 0x0402);
+    assert_eq!(node.mac.pan.get(), 0xface);
+    assert!(node.mac.commits.get() > 0);
+    assert_eq!(
+        node.data_ip.security(),
+        Some((SecurityLevel::EncMic32, KeyId::Index(1)))
+    );
+    // MLE messages stay unsecured at the link layer
+    assert_eq!(node.ip.security(), None);
+
+    let entry = node
+        .cache
+        .lookup(link_local_from_ext_addr(PARENT_EXT))
+        .unwrap();
+    assert_eq!(entry.mac_addr, MacAddress::Short(PARENT_RLOC16));
+    assert!(entry.is_router);
+    assert_eq!(entry.lifetime, None);
+    let mut global = link_local_from_ext_addr(OTHER_EXT);
+    global.set_prefix(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0], 64);
+    assert_eq!(
+        node.cache.next_hop(global),
+        Some(MacAddress::Short(PARENT_RLOC16))
+    );
+
+    assert_eq!(
+        node.mle
+            .lookup_key(SecurityLevel::EncMic32, KeyId::Index(1)),
+        Some(keys(0).mac_key)
+    );
+    // Neighbors may already use the next Key Sequence
+    assert_eq!(
+        node.mle
+            .lookup_key(SecurityLevel::EncMic32, KeyId::Index(2)),
+        Some(keys(1).mac_key)
+    );
+    assert_eq!(
+        node.mle
+            .lookup_key(SecurityLevel::EncMic32, KeyId::Index(3)),
+        None
+    );
+    assert_eq!(
+        node.mle.lookup_key(SecurityLevel::Mic32, KeyId::Index(1)),
+        None
+    );
+    assert_eq!(
+        node.mle.lookup_addr_long(MacAddress::Short(PARENT_RLOC16)),
+        Some(PARENT_EXT)
+    );
+    assert_eq!(
+        node.mle.lookup_addr_long(MacAddress::Long(PARENT_EXT)),
+        Some(PARENT_EXT)
+    );
+    assert_eq!(
+        node.mle.lookup_addr_long(MacAddress::Short(OTHER_RLOC16)),
+        None
+    );
+}
+
+#[test]
+fn mle_checks_child_id_response() {
+    let node = Node::new();
+    let challenge = node.start();
+    parent_response(&node, PARENT_EXT, PARENT_RLOC16, 10, challenge);
+    node.advance_ms(PARENT_REQUEST_ROUTER_TIMEOUT_MS);
+    node.take_one();
+
+    // Replayed frame counter
+    child_id_response(&node, 10, PARENT_RLOC16 | 0x02);
+    // Address of a router, or of a child of another router
+    child_id_response(&node, 11, PARENT_RLOC16);
+    child_id_response(&node, 12, OTHER_RLOC16 | 0x02);
+    // Another device
+    node.receive(
+        OTHER_EXT,
+        13,
+        command::CHILD_ID_RESPONSE,
+        &[Tlv::Address16(PARENT_RLOC16 | 0x02)],
+    );
+    assert_eq!(node.mle.get_state(), AttachState::ChildIdRequest);
+    assert_eq!(node.mle.get_rloc16(), None);
+    assert_eq!(node.mac.commits.get(), 0);
+
+    node.advance_ms(CHILD_ID_RESPONSE_TIMEOUT_MS);
+    assert_eq!(node.mle.get_state(), AttachState::Backoff);
+    assert!(node.take_sent().is_empty());
+    node.advance_ms(1000);
+    assert_eq!(node.take_one().command, command::PARENT_REQUEST);
+}
+
+#[test]
+fn sha256_matches_fips_examples() {
+    let mut hash = Sha256::new();
+    hash.update(b"abc");
+    assert_eq!(
+        hash.finish(),
+        [
+            0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae,
+            0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61,
+            0xf2, 0x00, 0x15, 0xad
+        ]
+    );
+
+    // Two blocks once padded, hashed in uneven parts
+    let data = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    let mut hash = Sha256::new();
+    for part in data.chunks(13) {
+        hash.update(part);
+    }
+    assert_eq!(
+        hash.finish(),
+        [
+            0x24, 0x8d, 0x6a, 0x61, 0xd2, 0x06, 0x38, 0xb8, 0xe5, 0xc0, 0x26, 0x93, 0x0c, 0x3e,
+            0x60, 0x39, 0xa3, 0x3c, 0xe4, 0x59, 0x64, 0xff, 0x21, 0x67, 0xf6, 0xec, 0xed, 0xd4,
+            0x19, 0xdb, 0x06, 0xc1
+        ]
+    );
+}
+
+#[test]
+fn hmac_sha256_matches_rfc4231() {
+    // Test Case 1
+    assert_eq!(
+        hmac_sha256(&[0x0b; 20], &[b"Hi There"]),
+        [
+            0xb0, 0x34, 0x4c, 0x61, 0xd8, 0xdb, 0x38, 0x53, 0x5c, 0xa8, 0xaf, 0xce, 0xaf, 0x0b,
+            0xf1, 0x2b, 0x88, 0x1d, 0xc2, 0x00, 0xc9, 0x83, 0x3d, 0xa7, 0x26, 0xe9, 0x37, 0x6c,
+            0x2e, 0x32, 0xcf, 0xf7
+        ]
+    );
+    // Test Case 2, with the data in two parts
+    assert_eq!(
+        hmac_sha256(b"Jefe", &[b"what do ya want ", b"for nothing?"]),
+        [
+            0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+            0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+            0x64, 0xec, 0x38, 0x43
+        ]
+    );
+    // Test Case 6, with a key longer than a block
+    assert_eq!(
+        hmac_sha256(
+            &[0xaa; 131],
+            &[b"Test Using Larger Than Block-Size Key - Hash Key First"]
+        ),
+        [
+            0x60, 0xe4, 0x31, 0x59, 0x1e, 0xe0, 0xb6, 0x7f, 0x0d, 0x8a, 0x26, 0xaa, 0xcb, 0xf5,
+            0xb7, 0x7f, 0x8e, 0x0b, 0xc6, 0x21, 0x37, 0x28, 0xc5, 0x14, 0x05, 0x46, 0x04, 0x0f,
+            0x0e, 0xe3, 0x7f, 0x54
+        ]
+    );
+}
+
+#[test]
+fn thread_keys_match_spec_vectors() {
+    // Thread 1.1.1 Specification, Section 7.1.4
+    assert_eq!(
+        keys(0),
+        NetworkKeys {
+            key_sequence: 0,
+            mle_key: [
+                0x54, 0x45, 0xf4, 0x15, 0x8f, 0xd7, 0x59, 0x12, 0x17, 0x58, 0x09, 0xf8, 0xb5, 0x7a,
+                0x66, 0xa4
+            ],
+            mac_key: [
+                0xde, 0x89, 0xc5, 0x3a, 0xf3, 0x82, 0xb4, 0x21, 0xe0, 0xfd, 0xe5, 0xa9, 0xba, 0xe3,
+                0xbe, 0xf0
+            ],
+        }
+    );
+    assert_eq!(
+        keys(1),
+        NetworkKeys {
+            key_sequence: 1,
+            mle_key: [
+                0x8f, 0x4c, 0xd1, 0xa2, 0x7d, 0x95, 0xc0, 0x7d, 0x12, 0xdb, 0x89, 0x74, 0xbd, 0x61,
+                0x5c, 0x13
+            ],
+            mac_key: [
+                0x9b, 0xe0, 0xd1, 0xaf, 0x7b, 0xd8, 0x73, 0x50, 0xde, 0xab, 0xcd, 0xd0, 0x7f, 0xeb,
+                0xb9, 0xd5
+            ],
+        }
+    );
+}
+
+#[test]
+fn mle_switches_to_newer_key_sequence() {
+    let node = Node::new();
+    attach(&node);
+
+    // The parent asks for an update with the keys of the next Key Sequence,
+    // starting its frame counter over
+    let payload = seal(
+        &keys(1),
+        PARENT_EXT,
+        0,
+        command::CHILD_UPDATE_REQUEST,
+        &[
+            Tlv::SourceAddress(PARENT_RLOC16),
+            Tlv::Challenge(PARENT_CHALLENGE),
+        ],
+    );
+    node.receive_datagram(link_local_from_ext_addr(PARENT_EXT), &payload);
+    assert_eq!(node.mle.get_key_sequence(), 1);
+    let response = node.take_one();
+    assert_eq!(response.command, command::CHILD_UPDATE_RESPONSE);
+    assert_eq!(response.frame_counter, 0);
+    assert!(matches!(
+        response.tlv(TlvType::Response),
+        Some(Tlv::Response(PARENT_CHALLENGE))
+    ));
+    assert_eq!(
+        node.data_ip.security(),
+        Some((SecurityLevel::EncMic32, KeyId::Index(2)))
+    );
+    assert_eq!(
+        node.mle
+            .lookup_key(SecurityLevel::EncMic32, KeyId::Index(2)),
+        Some(keys(1).mac_key)
+    );
+    // Neighbors may still use the previous Key Sequence
+    assert_eq!(
+        node.mle
+            .lookup_key(SecurityLevel::EncMic32, KeyId::Index(1)),
+        Some(keys(0).mac_key)
+    );
+
+    // Messages of the previous Key Sequence are now replays
+    let payload = seal(
+        &keys(0),
+        PARENT_EXT,
+        50,
+        command::CHILD_UPDATE_REQUEST,
+        &[Tlv::SourceAddress(PARENT_RLOC16)],
+    );
+    node.receive_datagram(link_local_from_ext_addr(PARENT_EXT), &payload);
+    assert!(node.take_sent().is_empty());
+    assert_eq!(node.mle.get_key_sequence(), 1);
+
+    // Data requests use the new keys too
+    node.advance_ms(FAST_POLL_PERIOD_MS);
+    let commands = node.take_commands();
+    assert_eq!(commands.len(), 1);
+    assert_eq!(commands[0].key_id, Some(KeyId::Index(2)));
+}
+
+#[test]
+fn mle_sleepy_end_device_polls_parent() {
+    let node = Node::new();
+    node.start();
+    assert!(node.radio_mac.rx_on_when_idle.get());
+    parent_response(
+        &node,
+        PARENT_EXT,
+        PARENT_RLOC16,
+        10,
+        [0x01, 0x02, 0x03, 0x04, 0x01, 0x02, 0x03, 0x05],
+    );
+    node.advance_ms(PARENT_REQUEST_ROUTER_TIMEOUT_MS);
+    node.take_one();
+    // The parent holds the Child ID Response until the device polls
+    assert!(!node.radio_mac.rx_on_when_idle.get());
+    node.advance_ms(FAST_POLL_PERIOD_MS - 1);
+    assert!(node.take_commands().is_empty());
+    node.advance_ms(1);
+    let commands = node.take_commands();
+    assert_eq!(commands.len(), 1);
+    assert_eq!(commands[0].dst_addr, Some(MacAddress::Short(PARENT_RLOC16)));
+    // The device has no short address yet
+    assert_eq!(commands[0].src_addr, Some(MacAddress::Long(NODE_EXT)));
+    assert_eq!(commands[0].key_id, Some(KeyId::Index(1)));
+    assert_eq!(commands[0].command_id, 0x04);
+    assert!(node.radio_mac.rx_on_when_idle.get());
+    node.transmit_done(true);
+    child_id_response(&node, 11, PARENT_RLOC16 | 0x02);
+    assert_eq!(node.mle.get_state(), AttachState::Attached);
+
+    // The receiver stays on for a while after each acknowledged poll
+    node.advance_ms(RX_WINDOW_MS - 1);
+    assert!(node.radio_mac.rx_on_when_idle.get());
+    node.advance_ms(1);
+    assert!(!node.radio_mac.rx_on_when_idle.get());
+
+    node.advance_ms(FAST_POLL_PERIOD_MS);
+    let commands = node.take_commands();
+    assert_eq!(commands.len(), 1);
+    assert_eq!(commands[0].src_addr, Some(MacAddress::Short(0x0402)));
+    // A poll that is not acknowledged is retried
+    node.transmit_done(false);
+    assert!(!node.radio_mac.rx_on_when_idle.get());
+    node.advance_ms(RETRY_PERIOD_MS - 1);
+    assert!(node.take_commands().is_empty());
+    node.advance_ms(1);
+    assert_eq!(node.take_commands().len(), 1);
+}
+
+#[test]
+fn mle_minimal_end_device_does_not_poll() {
+    let node = Node::new();
+    node.mle.set_mode(0x0c);
+    attach(&node);
+    node.advance_ms(10000);
+    assert!(node.take_commands().is_empty());
+    assert!(node.radio_mac.rx_on_when_idle.get());
+}
+
+#[test]
+fn mle_acknowledged_polls_keep_child_attached() {
+    let node = Node::new();
+    node.mle.set_timeout(12);
+    attach(&node);
+    // Without the polls, a Child Update Request would be sent after 6s
+    for _ in 0..300 {
+        node.advance_ms(100);
+        for _ in node.take_commands() {
+            node.transmit_done(true);
+        }
+    }
+    assert!(node.take_sent().is_empty());
+    assert_eq!(node.mle.get_state(), AttachState::Attached);
+}
+
+fn child_update_response(node: &Node, frame_counter: u32, tlvs: &[Tlv]) {
+    node.receive(
+        PARENT_EXT,
+        frame_counter,
+        command::CHILD_UPDATE_RESPONSE,
+        tlvs,
+    );
+}
+
+#[test]
+fn mle_sends_child_update_request_at_half_timeout() {
+    let node = Node::new();
+    node.mle.set_timeout(10);
+    node.mle.set_mode(0x0c);
+    attach(&node);
+
+    node.advance_ms(4000);
+    assert!(node.take_sent().is_empty());
+    node.advance_ms(1000);
+    let request = node.take_one();
+    assert_eq!(node.mle.get_state(), AttachState::ChildUpdateRequest);
+    assert_eq!(node.mle.get_rloc16(), Some(0x0402));
+    assert_eq!(request.dst, link_local_from_ext_addr(PARENT_EXT));
+    assert_eq!(request.command, command::CHILD_UPDATE_REQUEST);
+    assert!(matches!(
+        request.tlv(TlvType::SourceAddress),
+        Some(Tlv::SourceAddress(0x0402))
+    ));
+    assert!(matches!(request.tlv(TlvType::Mode), Some(Tlv::Mode(0x0c))));
+    assert!(matches!(
+        request.tlv(TlvType::Timeout),
+        Some(Tlv::Timeout(10))
+    ));
+    assert!(matches!(
+        request.tlv(TlvType::LeaderData),
+        Some(Tlv::LeaderData {
+            partition_id: 1, ..
+        })
+    ));
+    let challenge = request.challenge();
+
+    // Wrong challenge, replayed frame counter
+    let mut wrong_challenge = challenge;
+    wrong_challenge[0] ^= 1;
+    child_update_response(&node, 12, &[Tlv::Response(wrong_challenge)]);
+    child_update_response(&node, 11, &[Tlv::Response(challenge)]);
+    assert_eq!(node.mle.get_state(), AttachState::ChildUpdateRequest);
+
+    // The parent may change the timeout
+    child_update_response(&node, 13, &[Tlv::Response(challenge), Tlv::Timeout(20)]);
+    assert_eq!(node.mle.get_state(), AttachState::Attached);
+    node.advance_ms(9000);
+    assert!(node.take_sent().is_empty());
+    node.advance_ms(1000);
+    assert_eq!(node.take_one().command, command::CHILD_UPDATE_REQUEST);
+}
+
+#[test]
+fn mle_attaches_again_when_parent_rejects_child_update() {
+    let node = Node::new();
+    node.mle.set_mode(0x0c);
+    attach(&node);
+    node.mle.set_timeout(30);
+    let request = node.take_one();
+    assert_eq!(request.command, command::CHILD_UPDATE_REQUEST);
+    assert!(matches!(
+        request.tlv(TlvType::Timeout),
+        Some(Tlv::Timeout(30))
+    ));
+
+    child_update_response(&node, 12, &[Tlv::Status(1)]);
+    assert_eq!(node.take_one().command, command::PARENT_REQUEST);
+    assert_eq!(node.mle.get_state(), AttachState::ParentRequestRouters);
+}
+
+#[test]
+fn mle_set_mode_sends_child_update_request() {
+    let node = Node::new();
+    attach(&node);
+    assert!(!node.radio_mac.rx_on_when_idle.get());
+
+    // Becoming a minimal end device stops the polls
+    node.mle.set_mode(0x0c);
+    let request = node.take_one();
+    assert_eq!(request.command, command::CHILD_UPDATE_REQUEST);
+    assert!(matches!(request.tlv(TlvType::Mode), Some(Tlv::Mode(0x0c))));
+    assert!(node.radio_mac.rx_on_when_idle.get());
+    child_update_response(&node, 12, &[Tlv::Response(request.challenge())]);
+    assert_eq!(node.mle.get_state(), AttachState::Attached);
+}
+
+#[test]
+fn mle_answers_child_update_request_of_parent() {
+    let node = Node::new();
+    node.mle.set_mode(0x0c);
+    attach(&node);
+    node.mac.frame_counter.set(99);
+
+    node.receive(
+        PARENT_EXT,
+        12,
+        command::CHILD_UPDATE_REQUEST,
+        &[
+            Tlv::SourceAddress(PARENT_RLOC16),
+            Tlv::Challenge(PARENT_CHALLENGE),
+        ],
+    );
+    let response = node.take_one();
+    assert_eq!(response.dst, link_local_from_ext_addr(PARENT_EXT));
+    assert_eq!(response.command, command::CHILD_UPDATE_RESPONSE);
+    assert!(matches!(
+        response.tlv(TlvType::SourceAddress),
+        Some(Tlv::SourceAddress(0x0402))
+    ));
+    assert!(matches!(response.tlv(TlvType::Mode), Some(Tlv::Mode(0x0c))));
+    assert!(matches!(
+        response.tlv(TlvType::Timeout),
+        Some(Tlv::Timeout(DEFAULT_CHILD_TIMEOUT))
+    ));
+    assert!(matches!(
+        response.tlv(TlvType::Response),
+        Some(Tlv::Response(PARENT_CHALLENGE))
+    ));
+    assert!(matches!(
+        response.tlv(TlvType::LinkLayerFrameCounter),
+        Some(Tlv::LinkLayerFrameCounter(99))
+    ));
+    match response.tlv(TlvType::MleFrameCounter) {
+        Some(Tlv::MleFrameCounter(frame_counter)) => {
+            assert_eq!(frame_counter, response.frame_counter)
+        }
+        _ => panic!("no MLE frame counter"),
+    }
+
+    // Replayed request
+    node.receive(
+        PARENT_EXT,
+        12,
+        command::CHILD_UPDATE_REQUEST,
+        &[Tlv::Challenge(PARENT_CHALLENGE)],
+    );
+    assert!(node.take_sent().is_empty());
+
+    // Without a challenge, there is nothing to return
+    node.receive(
+        PARENT_EXT,
+        13,
+        command::CHILD_UPDATE_REQUEST,
+        &[Tlv::SourceAddress(PARENT_RLOC16)],
+    );
+    let response = node.take_one();
+    assert_eq!(response.command, command::CHILD_UPDATE_RESPONSE);
+    assert!(response.tlv(TlvType::Response).is_none());
+    assert!(response.tlv(TlvType::MleFrameCounter).is_none());
+}
+
+#[test]
+fn mle_attaches_again_after_unanswered_child_updates() {
+    let node = Node::new();
+    node.mle.set_timeout(10);
+    node.mle.set_mode(0x0c);
+    let request = attach(&node);
+    assert!(matches!(
+        request.tlv(TlvType::Timeout),
+        Some(Tlv::Timeout(10))
+    ));
+    assert!(matches!(request.tlv(TlvType::Mode), Some(Tlv::Mode(0x0c))));
+
+    node.advance_ms(5000);
+    let mut challenges = vec![node.take_one().challenge()];
+    for _ in 1..3 {
+        node.advance_ms(CHILD_UPDATE_RESPONSE_TIMEOUT_MS - 1);
+        assert!(node.take_sent().is_empty());
+        node.advance_ms(1);
+        let request = node.take_one();
+        assert_eq!(request.command, command::CHILD_UPDATE_REQUEST);
+        assert!(!challenges.contains(&request.challenge()));
+        challenges.push(request.challenge());
+    }
+    assert_eq!(node.mle.get_state(), AttachState::ChildUpdateRequest);
+    node.advance_ms(CHILD_UPDATE_RESPONSE_TIMEOUT_MS);
+    let request = node.take_one();
+    assert_eq!(request.command, command::PARENT_REQUEST);
+    assert_eq!(node.mle.get_state(), AttachState::ParentRequestRouters);
+
+    // The same parent answers again with newer frame counters
+    parent_response(&node, PARENT_EXT, PARENT_RLOC16, 20, request.challenge());
+    node.advance_ms(PARENT_REQUEST_ROUTER_TIMEOUT_MS);
+    node.take_one();
+    child_id_response(&node, 21, PARENT_RLOC16 | 0x03);
+    assert_eq!(node.mle.get_state(), AttachState::Attached);
+    assert_eq!(node.mac.address.get(), 0x0403);
+}
@@ -0,0 +1,171 @@
+//! Tests of sharing mock block storage with `MuxBlockDevice`.
+
+use std::cell::RefCell;
+
+use capsules::virtual_block_storage::{BlockDeviceUser, MuxBlockDevice};
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient};
+use kernel::ReturnCode;
+use tock_hil_mock::block_storage::{BlockOperation, MockBlockStorage, BLOCK_SIZE};
+use tock_hil_mock::{leak, leak_buffer};
+
+#[derive(Debug, PartialEq)]
+enum Done {
+    Read(Vec<u8>, ReturnCode),
+    Write(ReturnCode),
+    Erase(ReturnCode),
+}
+
+#[derive(Default)]
+struct Client {
+    done: RefCell<Vec<Done>>,
+}
+
+impl Client {
+    fn take(&self) -> Vec<Done> {
+        self.done.replace(Vec::new())
+    }
+}
+
+impl BlockStorageClient for Client {
+    fn read_done(&self, buffer: &'static mut [u8], result: ReturnCode) {
+        self.done
+            .borrow_mut()
+            .push(Done::Read(buffer.to_vec(), result));
+    }
+
+    fn write_done(&self, _buffer: &'static mut [u8], result: ReturnCode) {
+        self.done.borrow_mut().push(Done::Write(result));
+    }
+
+    fn erase_done(&self, result: ReturnCode) {
+        self.done.borrow_mut().push(Done::Erase(result));
+    }
+}
+
+type User = BlockDeviceUser<'static, MockBlockStorage>;
+
+fn setup(
+    regions: &[(usize, usize)],
+) -> (
+    &'static MockBlockStorage,
+    Vec<(&'static User, &'static Client)>,
+) {
+    let device = leak(MockBlockStorage::new(16));
+    let mux = leak(MuxBlockDevice::new(device));
+    device.set_client(mux);
+    let users = regions
+        .iter()
+        .map(|&(first_block, number_of_blocks)| {
+            let user = leak(BlockDeviceUser::new(mux, first_block, number_of_blocks));
+            user.setup();
+            let client = leak(Client::default());
+            user.set_client(client);
+            (&*user, &*client)
+        })
+        .collect();
+    (device, users)
+}
+
+#[test]
+fn users_have_separate_blocks() {
+    let (device, users) = setup(&[(0, 4), (4, usize::MAX)]);
+    let (first, first_client) = users[0];
+    let (second, second_client) = users[1];
+    assert_eq!(first.number_of_blocks(), 4);
+    assert_eq!(second.number_of_blocks(), 12);
+
+    assert!(second
+        .write_blocks(leak_buffer(&[7; BLOCK_SIZE]), 0, 1)
+        .is_ok());
+    assert!(device.complete());
+    assert_eq!(second_client.take(), [Done::Write(ReturnCode::SUCCESS)]);
+    assert_eq!(device.take_operations(), [BlockOperation::Write(4, 1)]);
+    assert_eq!(device.contents()[4 * BLOCK_SIZE], 7);
+
+    let (error, _) = first
+        .read_blocks(leak_buffer(&[0; 2 * BLOCK_SIZE]), 3, 2)
+        .unwrap_err();
+    assert_eq!(error, ReturnCode::EINVAL);
+    assert_eq!(first.erase_blocks(4, 1), ReturnCode::EINVAL);
+    assert_eq!(second.erase_blocks(11, 2), ReturnCode::EINVAL);
+    let (error, _) = first
+        .read_blocks(leak_buffer(&[0; BLOCK_SIZE]), 0, 2)
+        .unwrap_err();
+    assert_eq!(error, ReturnCode::ESIZE);
+    assert_eq!(first_client.take(), []);
+    assert_eq!(device.take_operations(), []);
+}
+
+#[test]
+fn requests_are_queued() {
+    let (device, users) = setup(&[(0, 8), (8, 8)]);
+    let (first, first_client) = users[0];
+    let (second, second_client) = users[1];
+    device.write_contents(8 * BLOCK_SIZE, &[3; BLOCK_SIZE]);
+
+    assert!(first
+        .write_blocks(leak_buffer(&[1; 2 * BLOCK_SIZE]), 0, 2)
+        .is_ok());
+    assert!(second
+        .read_blocks(leak_buffer(&[0; BLOCK_SIZE]), 0, 1)
+        .is_ok());
+    let (error, _) = second
+        .read_blocks(leak_buffer(&[0; BLOCK_SIZE]), 0, 1)
+        .unwrap_err();
+    assert_eq!(error, ReturnCode::EBUSY);
+    assert_eq!(first.erase_blocks(0, 1), ReturnCode::EBUSY);
+    assert_eq!(device.operations(), [BlockOperation::Write(0, 2)]);
+
+    // The read starts when the write completes.
+    assert!(device.complete());
+    assert_eq!(first_client.take(), [Done::Write(ReturnCode::SUCCESS)]);
+    assert_eq!(second_client.take(), []);
+    assert_eq!(first.erase_blocks(1, 1), ReturnCode::SUCCESS);
+    assert!(device.complete());
+    assert_eq!(
+        second_client.take(),
+        [Done::Read(vec![3; BLOCK_SIZE], ReturnCode::SUCCESS)]
+    );
+    assert!(device.complete());
+    assert_eq!(first_client.take(), [Done::Erase(ReturnCode::SUCCESS)]);
+    assert!(!device.complete());
+    assert_eq!(
+        device.take_operations(),
+        [
+            BlockOperation::Write(0, 2),
+            BlockOperation::Read(8, 1),
+            BlockOperation::Erase(1, 1),
+        ]
+    );
+    assert_eq!(device.contents()[BLOCK_SIZE], 0xff);
+}
+
+#[test]
+fn device_errors() {
+    let (device, users) = setup(&[(0, 8), (8, 8)]);
+    let (first, first_client) = users[0];
+    let (second, second_client) = users[1];
+
+    // Errors of requests that start at once are returned.
+    device.fail_next_call(ReturnCode::ENOSUPPORT);
+    assert_eq!(first.erase_blocks(0, 1), ReturnCode::ENOSUPPORT);
+
+    // Errors of queued requests go to the client.
+    assert!(first
+        .read_blocks(leak_buffer(&[0; BLOCK_SIZE]), 0, 1)
+        .is_ok());
+    assert!(second
+        .write_blocks(leak_buffer(&[0; BLOCK_SIZE]), 0, 1)
+        .is_ok());
+    device.fail_next_completion();
+    device.fail_next_call(ReturnCode::EINVAL);
+    assert!(device.complete());
+    assert_eq!(
+        first_client.take(),
+        [Done::Read(vec![0; BLOCK_SIZE], ReturnCode::FAIL)]
+    );
+    // The write was started when the read completed.
+    assert_eq!(second_client.take(), [Done::Write(ReturnCode::EINVAL)]);
+    assert!(!device.is_busy());
+    assert_eq!(device.take_operations(), [BlockOperation::Read(0, 1)]);
+}
@@ -7,7 +7,7 @@ use kernel::InterruptService;
 
 pub struct Apollo3<I: InterruptService<()> + 'static> {
     mpu: cortexm4::mpu::MPU,
-    userspace_kernel_boundary: cortexm4::syscall::SysCall,
+    userspace_kernel_boundary: cortexm4::syscall::SysCallFp,
     scheduler_timer: cortexm4::systick::SysTick,
     interrupt_service: &'static I,
 }
@@ -16,7 +16,7 @@ impl<I: InterruptService<()> + 'static> Apollo3<I> {
     pub unsafe fn new(interrupt_service: &'static I) -> Self {
         Self {
             mpu: cortexm4::mpu::MPU::new(),
-            userspace_kernel_boundary: cortexm4::syscall::SysCall::new(),
+            userspace_kernel_boundary: cortexm4::syscall::SysCallFp::new(),
             scheduler_timer: cortexm4::systick::SysTick::new_with_calibration(48_000_000),
             interrupt_service,
         }
@@ -85,7 +85,7 @@ impl kernel::InterruptService<()> for Apollo3DefaultPeripherals {
 
 impl<I: InterruptService<()> + 'static> Chip for Apollo3<I> {
     type MPU = cortexm4::mpu::MPU;
-    type UserspaceKernelBoundary = cortexm4::syscall::SysCall;
+    type UserspaceKernelBoundary = cortexm4::syscall::SysCallFp;
     type SchedulerTimer = cortexm4::systick::SysTick;
     type WatchDog = ();
 
@@ -123,7 +123,7 @@ impl<I: InterruptService<()> + 'static> Chip for Apollo3<I> {
         &()
     }
 
-    fn userspace_kernel_boundary(&self) -> &cortexm4::syscall::SysCall {
+    fn userspace_kernel_boundary(&self) -> &cortexm4::syscall::SysCallFp {
         &self.userspace_kernel_boundary
     }
 
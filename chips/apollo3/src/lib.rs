@@ -19,7 +19,7 @@ pub mod stimer;
 pub mod uart;
 
 use cortexm4::{
-    generic_isr, hard_fault_handler, scb, svc_handler, systick_handler, unhandled_interrupt,
+    generic_isr, hard_fault_handler, scb, svc_handler_fp, systick_handler, unhandled_interrupt,
 };
 
 extern "C" {
@@ -49,7 +49,7 @@ pub static BASE_VECTORS: [unsafe extern "C" fn(); 16] = [
     unhandled_interrupt,
     unhandled_interrupt,
     unhandled_interrupt,
-    svc_handler,         // SVC
+    svc_handler_fp,      // SVC
     unhandled_interrupt, // DebugMon
     unhandled_interrupt,
     unhandled_interrupt, // PendSV
@@ -100,11 +100,9 @@ pub unsafe fn init() {
     // To be safe we unconditionally set the vector table.
     scb::set_vector_table_offset(BASE_VECTORS.as_ptr() as *const ());
 
-    // Disable the FPU (it might be enalbed by a prior stage)
-    scb::disable_fpca();
-
-    // This ensures the FPU is actually disabled
-    llvm_asm!("svc 0xff" : : : "r0","r1","r2","r3","r12" : "volatile" );
+    // Let processes use the FPU. A prior stage might have left it enabled
+    // for the kernel.
+    scb::enable_fpu();
 
     cortexm4::nvic::disable_all();
     cortexm4::nvic::clear_all_pending();
@@ -115,7 +113,7 @@ pub unsafe fn init() {
 #[cfg(not(any(target_arch = "arm", target_os = "none")))]
 pub unsafe fn init() {
     // Prevent unused code warning.
-    scb::disable_fpca();
+    scb::enable_fpu();
 
     unimplemented!()
 }
@@ -21,7 +21,8 @@ use crate::timer;
 use crate::uart;
 use crate::usbdev;
 
-PMPConfigMacro!(4);
+// Ibex on EarlGrey has 16 PMP entries and supports Smepmp.
+PMPConfigMacro!(16);
 
 pub struct EarlGrey<A: 'static + Alarm<'static>> {
     userspace_kernel_boundary: SysCall,
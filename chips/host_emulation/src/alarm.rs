@@ -0,0 +1,94 @@
+//! Alarm driven by the host's monotonic clock.
+//!
+//! Ticks are microseconds since the alarm was created, so the 32-bit
+//! counter wraps after about 71 minutes.
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Freq1MHz, Ticks, Ticks32, Time};
+use kernel::ReturnCode;
+
+pub struct HostAlarm<'a> {
+    start: Instant,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+    armed: Cell<bool>,
+    reference: Cell<Ticks32>,
+    dt: Cell<Ticks32>,
+}
+
+impl<'a> HostAlarm<'a> {
+    pub fn new() -> HostAlarm<'a> {
+        HostAlarm {
+            start: Instant::now(),
+            client: OptionalCell::empty(),
+            armed: Cell::new(false),
+            reference: Cell::new(Ticks32::from(0)),
+            dt: Cell::new(Ticks32::from(0)),
+        }
+    }
+
+    /// Time left until the alarm fires, or `None` if it is not armed.
+    pub fn time_until_fire(&self) -> Option<Duration> {
+        if !self.armed.get() {
+            return None;
+        }
+        let elapsed = self.now().wrapping_sub(self.reference.get());
+        let remaining = if elapsed >= self.dt.get() {
+            0
+        } else {
+            self.dt.get().wrapping_sub(elapsed).into_u32()
+        };
+        Some(Duration::from_micros(remaining as u64))
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.time_until_fire() == Some(Duration::from_micros(0))
+    }
+
+    pub fn handle_interrupt(&self) {
+        if self.is_pending() {
+            self.armed.set(false);
+            self.client.map(|client| client.alarm());
+        }
+    }
+}
+
+impl Time for HostAlarm<'_> {
+    type Frequency = Freq1MHz;
+    type Ticks = Ticks32;
+
+    fn now(&self) -> Ticks32 {
+        Ticks32::from(self.start.elapsed().as_micros() as u32)
+    }
+}
+
+impl<'a> Alarm<'a> for HostAlarm<'a> {
+    fn set_alarm_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+        self.reference.set(reference);
+        self.dt.set(dt);
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> Ticks32 {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn disarm(&self) -> ReturnCode {
+        self.armed.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Ticks32 {
+        Ticks32::from(1)
+    }
+}
@@ -0,0 +1,88 @@
+//! The emulated chip.
+
+use std::fmt::Write;
+use std::sync::Arc;
+
+use kernel::Chip;
+
+use crate::alarm::HostAlarm;
+use crate::scheduler_timer::HostSchedulerTimer;
+use crate::syscall::SysCall;
+use crate::uart::HostUart;
+use crate::wakeup::Wakeup;
+
+pub struct HostChip {
+    pub alarm: HostAlarm<'static>,
+    pub uart: HostUart<'static>,
+    userspace_kernel_boundary: SysCall,
+    scheduler_timer: HostSchedulerTimer,
+    wakeup: Arc<Wakeup>,
+}
+
+impl HostChip {
+    pub fn new() -> HostChip {
+        let wakeup = Arc::new(Wakeup::new());
+        HostChip {
+            alarm: HostAlarm::new(),
+            uart: HostUart::new(wakeup.clone()),
+            userspace_kernel_boundary: SysCall::new(),
+            scheduler_timer: HostSchedulerTimer::new(),
+            wakeup: wakeup,
+        }
+    }
+}
+
+impl Chip for HostChip {
+    type MPU = ();
+    type UserspaceKernelBoundary = SysCall;
+    type SchedulerTimer = HostSchedulerTimer;
+    type WatchDog = ();
+
+    fn service_pending_interrupts(&self) {
+        while self.has_pending_interrupts() {
+            self.alarm.handle_interrupt();
+            self.uart.handle_interrupt();
+        }
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        self.alarm.is_pending() || self.uart.is_pending()
+    }
+
+    fn mpu(&self) -> &Self::MPU {
+        &()
+    }
+
+    fn scheduler_timer(&self) -> &Self::SchedulerTimer {
+        &self.scheduler_timer
+    }
+
+    fn watchdog(&self) -> &Self::WatchDog {
+        &()
+    }
+
+    fn userspace_kernel_boundary(&self) -> &SysCall {
+        &self.userspace_kernel_boundary
+    }
+
+    fn sleep(&self) {
+        // Sleep until the alarm fires or another thread provides data.
+        self.wakeup.wait(self.alarm.time_until_fire());
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        // Peripherals only change state on the kernel thread.
+        f()
+    }
+
+    unsafe fn print_state(&self, writer: &mut dyn Write) {
+        let _ = writer.write_fmt(format_args!(
+            "\r\n---| Host Emulation |---\
+             \r\n Alarm armed: {}\r\n",
+            kernel::hil::time::Alarm::is_armed(&self.alarm),
+        ));
+    }
+}
@@ -0,0 +1,31 @@
+//! Emulated chip for running the Tock kernel as a Linux process.
+//!
+//! This crate implements the interfaces the kernel needs from a chip on top
+//! of the Rust standard library, so that the kernel, its schedulers and
+//! capsules can run (and be tested) on a development machine:
+//!
+//! - `chip::HostChip` implements `kernel::Chip`. Interrupts are events raised
+//!   by the emulated peripherals, and sleeping blocks the kernel thread until
+//!   the next event.
+//! - `syscall::SysCall` implements `UserspaceKernelBoundary`. Each process is
+//!   a thread running Rust code written against the `userspace` module, and
+//!   only one of the kernel and the processes runs at a time.
+//! - `alarm::HostAlarm` is a 1 MHz alarm based on the system clock.
+//! - `uart::HostUart` writes to any `std::io::Write` and reads from data
+//!   pushed by another thread, for example one reading from stdin or a pty.
+//! - `scheduler_timer::HostSchedulerTimer` measures process timeslices.
+//!
+//! There is no memory protection; the MPU is `()`. Processes are not
+//! preempted either: a process runs until its next system call, and the
+//! kernel then checks whether its timeslice expired.
+//!
+//! Processes are loaded from TBF images created by `tbf::create_app_flash()`.
+
+pub mod alarm;
+pub mod chip;
+pub mod scheduler_timer;
+pub mod syscall;
+pub mod tbf;
+pub mod uart;
+pub mod userspace;
+pub mod wakeup;
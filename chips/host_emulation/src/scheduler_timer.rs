@@ -0,0 +1,47 @@
+//! Scheduler timer that measures timeslices with the host's clock.
+//!
+//! Processes are not preempted on the host, so the timer never raises an
+//! interrupt; the kernel only learns that a timeslice expired when the process
+//! makes its next system call.
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use kernel::SchedulerTimer;
+
+#[derive(Default)]
+pub struct HostSchedulerTimer {
+    deadline: Cell<Option<Instant>>,
+}
+
+impl HostSchedulerTimer {
+    pub fn new() -> HostSchedulerTimer {
+        HostSchedulerTimer::default()
+    }
+}
+
+impl SchedulerTimer for HostSchedulerTimer {
+    fn start(&self, us: u32) {
+        self.deadline
+            .set(Some(Instant::now() + Duration::from_micros(us as u64)));
+    }
+
+    fn reset(&self) {
+        self.deadline.set(None);
+    }
+
+    fn arm(&self) {}
+
+    fn disarm(&self) {}
+
+    fn get_remaining_us(&self) -> Option<u32> {
+        self.deadline.get().and_then(|deadline| {
+            let now = Instant::now();
+            if now < deadline {
+                Some((deadline - now).as_micros() as u32)
+            } else {
+                None
+            }
+        })
+    }
+}
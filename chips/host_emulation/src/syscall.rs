@@ -0,0 +1,240 @@
+//! Kernel-userspace boundary for processes running as host threads.
+//!
+//! Each process is a thread that runs the Rust functions of a host
+//! application (see the `userspace` module). A context switch to a process
+//! sends it the return value of its last system call or a function to call,
+//! and then blocks the kernel until the process makes its next system call.
+//! The kernel and its processes therefore never run at the same time, just
+//! like on a single-core microcontroller.
+//!
+//! Function pointers take the place of code addresses. Callbacks are the
+//! address of the function to call, and the entry point of a process (the
+//! `init_fn` in its TBF header) points to a word in flash that holds the
+//! address of its main function.
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::ptr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use kernel::procs::{FunctionCall, FunctionCallSource};
+use kernel::syscall::{self, ContextSwitchReason};
+
+use crate::userspace;
+
+/// What a process does when the kernel switches to it.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Resume {
+    /// Return from the current system call with this value.
+    Return(isize),
+    /// Call `function` with `arguments`.
+    Call {
+        function: usize,
+        arguments: [usize; 4],
+    },
+}
+
+/// Why a process gave control back to the kernel.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Trap {
+    Syscall { number: u8, arguments: [usize; 4] },
+    Fault,
+}
+
+struct ProcessThread {
+    resume: Sender<Resume>,
+    trap: Receiver<Trap>,
+}
+
+/// Per-process state kept by the kernel.
+#[derive(Default)]
+pub struct HostStoredState {
+    /// Index of the thread running the process in `SysCall::threads`.
+    thread: Option<usize>,
+    /// How the process continues when it is switched to next.
+    resume: Option<Resume>,
+    /// The function the process executes (its main function or a callback).
+    function: usize,
+    syscall_count: usize,
+}
+
+/// Implementation of the `UserspaceKernelBoundary` for the host.
+#[derive(Default)]
+pub struct SysCall {
+    threads: RefCell<Vec<Option<ProcessThread>>>,
+}
+
+impl SysCall {
+    pub fn new() -> SysCall {
+        SysCall::default()
+    }
+
+    fn spawn(&self, function: usize, arguments: [usize; 4]) -> usize {
+        let (resume_sender, resume_receiver) = mpsc::channel();
+        let (trap_sender, trap_receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name(String::from("tock-process"))
+            .spawn(move || userspace::run(resume_receiver, trap_sender, function, arguments))
+            .expect("cannot create process thread");
+
+        let mut threads = self.threads.borrow_mut();
+        let process_thread = ProcessThread {
+            resume: resume_sender,
+            trap: trap_receiver,
+        };
+        match threads.iter().position(|thread| thread.is_none()) {
+            Some(index) => {
+                threads[index] = Some(process_thread);
+                index
+            }
+            None => {
+                threads.push(Some(process_thread));
+                threads.len() - 1
+            }
+        }
+    }
+}
+
+impl kernel::syscall::UserspaceKernelBoundary for SysCall {
+    type StoredState = HostStoredState;
+
+    unsafe fn initialize_process(
+        &self,
+        stack_pointer: *const usize,
+        _stack_size: usize,
+        state: &mut Self::StoredState,
+    ) -> Result<*const usize, ()> {
+        // Dropping the channels of the previous thread of a restarted process
+        // stops it for good the next time it talks to the kernel.
+        if let Some(index) = state.thread.take() {
+            self.threads.borrow_mut()[index] = None;
+        }
+        *state = HostStoredState::default();
+        Ok(stack_pointer)
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _stack_pointer: *const usize,
+        state: &mut Self::StoredState,
+        return_value: isize,
+    ) {
+        state.resume = Some(Resume::Return(return_value));
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        stack_pointer: *const usize,
+        _remaining_stack_memory: usize,
+        state: &mut Self::StoredState,
+        callback: FunctionCall,
+    ) -> Result<*mut usize, *mut usize> {
+        let function = match callback.source {
+            // The entry point holds the address of the main function.
+            FunctionCallSource::Kernel => ptr::read_unaligned(callback.pc as *const usize),
+            FunctionCallSource::Driver(_) => callback.pc,
+        };
+        state.resume = Some(Resume::Call {
+            function: function,
+            arguments: [
+                callback.argument0,
+                callback.argument1,
+                callback.argument2,
+                callback.argument3,
+            ],
+        });
+        Ok(stack_pointer as *mut usize)
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        stack_pointer: *const usize,
+        state: &mut Self::StoredState,
+    ) -> (*mut usize, ContextSwitchReason) {
+        let stack_pointer = stack_pointer as *mut usize;
+        let resume = state.resume.take();
+        if let Some(Resume::Call { function, .. }) = resume {
+            state.function = function;
+        }
+
+        let index = match (state.thread, resume) {
+            (Some(index), Some(resume)) => {
+                let threads = self.threads.borrow();
+                let sent = threads[index]
+                    .as_ref()
+                    .map_or(false, |thread| thread.resume.send(resume).is_ok());
+                if !sent {
+                    return (stack_pointer, ContextSwitchReason::Fault);
+                }
+                index
+            }
+            // The process is waiting for the kernel, so it cannot continue
+            // without a return value or a function to call.
+            (Some(_), None) => return (stack_pointer, ContextSwitchReason::Interrupted),
+            (
+                None,
+                Some(Resume::Call {
+                    function,
+                    arguments,
+                }),
+            ) => {
+                let index = self.spawn(function, arguments);
+                state.thread = Some(index);
+                index
+            }
+            // A process without a thread must start by calling its main
+            // function.
+            (None, _) => return (stack_pointer, ContextSwitchReason::Fault),
+        };
+
+        let trap = self.threads.borrow()[index]
+            .as_ref()
+            .and_then(|thread| thread.trap.recv().ok());
+        let reason = match trap {
+            Some(Trap::Syscall { number, arguments }) => {
+                state.syscall_count += 1;
+                match syscall::arguments_to_syscall(
+                    number,
+                    arguments[0],
+                    arguments[1],
+                    arguments[2],
+                    arguments[3],
+                ) {
+                    Some(syscall) => ContextSwitchReason::SyscallFired { syscall: syscall },
+                    None => ContextSwitchReason::InvalidSyscall,
+                }
+            }
+            // The thread ended without talking to the kernel, which happens
+            // when it panics.
+            Some(Trap::Fault) | None => ContextSwitchReason::Fault,
+        };
+        (stack_pointer, reason)
+    }
+
+    unsafe fn get_process_pc(
+        &self,
+        _stack_pointer: *const usize,
+        state: &Self::StoredState,
+    ) -> usize {
+        state.function
+    }
+
+    unsafe fn print_context(
+        &self,
+        _stack_pointer: *const usize,
+        state: &Self::StoredState,
+        writer: &mut dyn Write,
+    ) {
+        let _ = writer.write_fmt(format_args!(
+            "\r\n Host process thread: {}\
+             \r\n Current function: {:#x}\
+             \r\n System calls: {}\r\n",
+            state
+                .thread
+                .map_or(String::from("not started"), |index| index.to_string()),
+            state.function,
+            state.syscall_count,
+        ));
+    }
+}
@@ -0,0 +1,100 @@
+//! TBF images and process memory for host applications.
+//!
+//! `create_app_flash()` lays out applications the way they would be stored in
+//! the flash of a microcontroller, so the kernel loads them with
+//! `kernel::procs::load_processes()`. Instead of code, each image contains
+//! the address of the main function of the application, which is what its
+//! `init_fn` points to.
+
+use crate::userspace::Callback;
+
+/// An application to run on the host.
+#[derive(Clone, Copy)]
+pub struct HostApp {
+    /// Package name in the TBF header.
+    pub name: &'static str,
+    /// Function the process starts with.
+    pub main: Callback,
+    /// Minimum amount of memory (in bytes) the process needs.
+    pub minimum_ram_size: u32,
+    /// Package names of the processes that may use this application as an
+    /// IPC service. If empty, every process may.
+    pub ipc_clients: &'static [&'static str],
+}
+
+const TBF_VERSION: u16 = 2;
+const TBF_BASE_LENGTH: usize = 16;
+const TBF_FLAG_ENABLE: u32 = 1;
+const TLV_TYPE_MAIN: u16 = 1;
+const TLV_MAIN_LENGTH: usize = 12;
+const TLV_TYPE_PACKAGE_NAME: u16 = 3;
+const TLV_TYPE_IPC_CLIENTS: u16 = 12;
+
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn push_tlv(image: &mut Vec<u8>, tlv_type: u16, value: &[u8]) {
+    image.extend_from_slice(&tlv_type.to_le_bytes());
+    image.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    image.extend_from_slice(value);
+    image.resize(align4(image.len()), 0);
+}
+
+/// Create the TBF image of `app`.
+pub fn create_tbf(app: &HostApp) -> Vec<u8> {
+    // The entry point is right after the header, and there is no protected
+    // region.
+    let mut tlvs = Vec::new();
+    let mut main = [0; TLV_MAIN_LENGTH];
+    main[8..12].copy_from_slice(&app.minimum_ram_size.to_le_bytes());
+    push_tlv(&mut tlvs, TLV_TYPE_MAIN, &main);
+    push_tlv(&mut tlvs, TLV_TYPE_PACKAGE_NAME, app.name.as_bytes());
+    if !app.ipc_clients.is_empty() {
+        let mut clients = Vec::new();
+        for client in app.ipc_clients {
+            clients.push(client.len() as u8);
+            clients.extend_from_slice(client.as_bytes());
+        }
+        push_tlv(&mut tlvs, TLV_TYPE_IPC_CLIENTS, &clients);
+    }
+
+    let header_size = TBF_BASE_LENGTH + tlvs.len();
+    let total_size = header_size + std::mem::size_of::<usize>();
+
+    let mut image = Vec::with_capacity(total_size);
+    image.extend_from_slice(&TBF_VERSION.to_le_bytes());
+    image.extend_from_slice(&(header_size as u16).to_le_bytes());
+    image.extend_from_slice(&(total_size as u32).to_le_bytes());
+    image.extend_from_slice(&TBF_FLAG_ENABLE.to_le_bytes());
+    // Checksum, filled in below.
+    image.extend_from_slice(&[0; 4]);
+    image.extend_from_slice(&tlvs);
+
+    let checksum = image
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .fold(0, |checksum, word| checksum ^ word);
+    image[12..16].copy_from_slice(&checksum.to_le_bytes());
+
+    image.extend_from_slice(&(app.main as usize).to_le_bytes());
+    image
+}
+
+/// Create flash containing `apps`, followed by the end of the app list.
+pub fn create_app_flash(apps: &[HostApp]) -> &'static [u8] {
+    let mut flash = Vec::new();
+    for app in apps {
+        flash.extend_from_slice(&create_tbf(app));
+    }
+    // An invalid header marks the end of the apps.
+    flash.extend_from_slice(&[0; TBF_BASE_LENGTH]);
+    Box::leak(flash.into_boxed_slice())
+}
+
+/// Create `size` bytes of memory for processes.
+pub fn create_app_memory(size: usize) -> &'static mut [u8] {
+    // Process memory must be word aligned.
+    let words = Box::leak(vec![0u64; (size + 7) / 8].into_boxed_slice());
+    unsafe { std::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, size) }
+}
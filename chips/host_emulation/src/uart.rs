@@ -0,0 +1,268 @@
+//! UART backed by host I/O.
+//!
+//! Transmitted bytes are written to a `std::io::Write` (stdout by default)
+//! as soon as a transmission starts, and the transmit callback follows as an
+//! interrupt. Received bytes come from a `UartInput`, which other threads use
+//! to push data; `attach_reader()` starts a thread that forwards everything
+//! read from stdin, a pty, or any other `std::io::Read`.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::uart;
+use kernel::ReturnCode;
+
+use crate::wakeup::Wakeup;
+
+/// Handle for passing received data to a `HostUart` from any thread.
+#[derive(Clone)]
+pub struct UartInput {
+    data: Arc<Mutex<VecDeque<u8>>>,
+    wakeup: Arc<Wakeup>,
+}
+
+impl UartInput {
+    /// Queue `bytes` to be received by the UART.
+    pub fn push(&self, bytes: &[u8]) {
+        self.data.lock().unwrap().extend(bytes);
+        self.wakeup.notify();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.lock().unwrap().is_empty()
+    }
+
+    /// Move queued bytes into `buf`, returning how many were copied.
+    fn pop_into(&self, buf: &mut [u8]) -> usize {
+        let mut data = self.data.lock().unwrap();
+        let count = buf.len().min(data.len());
+        for (dst, src) in buf.iter_mut().zip(data.drain(..count)) {
+            *dst = src;
+        }
+        count
+    }
+}
+
+/// Output that keeps everything the UART transmits, e.g. for tests.
+#[derive(Clone, Default)]
+pub struct CapturedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl CapturedOutput {
+    pub fn new() -> CapturedOutput {
+        CapturedOutput::default()
+    }
+
+    /// Everything transmitted so far.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct HostUart<'a> {
+    output: RefCell<Box<dyn Write>>,
+    input: UartInput,
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_word_pending: Cell<bool>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_position: Cell<usize>,
+    rx_aborting: Cell<bool>,
+}
+
+impl<'a> HostUart<'a> {
+    pub fn new(wakeup: Arc<Wakeup>) -> HostUart<'a> {
+        HostUart {
+            output: RefCell::new(Box::new(io::stdout())),
+            input: UartInput {
+                data: Arc::new(Mutex::new(VecDeque::new())),
+                wakeup: wakeup,
+            },
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_word_pending: Cell::new(false),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_position: Cell::new(0),
+            rx_aborting: Cell::new(false),
+        }
+    }
+
+    /// Send transmitted bytes to `output` instead of stdout.
+    pub fn set_output(&self, output: Box<dyn Write>) {
+        *self.output.borrow_mut() = output;
+    }
+
+    /// Handle other threads can use to provide received bytes.
+    pub fn input(&self) -> UartInput {
+        self.input.clone()
+    }
+
+    /// Start a thread that passes everything read from `reader` to the UART.
+    pub fn attach_reader<R: Read + Send + 'static>(&self, mut reader: R) {
+        let input = self.input();
+        thread::spawn(move || {
+            let mut buf = [0; 64];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(count) => input.push(&buf[..count]),
+                }
+            }
+        });
+    }
+
+    fn write_output(&self, bytes: &[u8]) {
+        let mut output = self.output.borrow_mut();
+        // Like bytes sent on a disconnected UART, output that cannot be
+        // written is lost.
+        let _ = output.write_all(bytes);
+        let _ = output.flush();
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.tx_buffer.is_some()
+            || self.tx_word_pending.get()
+            || (self.rx_buffer.is_some() && (self.rx_aborting.get() || !self.input.is_empty()))
+    }
+
+    pub fn handle_interrupt(&self) {
+        if self.tx_word_pending.replace(false) {
+            self.tx_client
+                .map(|client| client.transmitted_word(ReturnCode::SUCCESS));
+        }
+        self.tx_buffer.take().map(|buffer| {
+            self.tx_client.map(move |client| {
+                client.transmitted_buffer(buffer, self.tx_len.get(), ReturnCode::SUCCESS)
+            });
+        });
+
+        let done = self.rx_buffer.map_or(false, |buffer| {
+            let position = self.rx_position.get();
+            let count = self
+                .input
+                .pop_into(&mut buffer[position..self.rx_len.get()]);
+            self.rx_position.set(position + count);
+            self.rx_aborting.get() || self.rx_position.get() == self.rx_len.get()
+        });
+        if done {
+            let (rval, error) = if self.rx_aborting.replace(false) {
+                (ReturnCode::ECANCEL, uart::Error::Aborted)
+            } else {
+                (ReturnCode::SUCCESS, uart::Error::None)
+            };
+            self.rx_buffer.take().map(|buffer| {
+                self.rx_client.map(move |client| {
+                    client.received_buffer(buffer, self.rx_position.get(), rval, error)
+                });
+            });
+        }
+    }
+}
+
+impl uart::Configure for HostUart<'_> {
+    fn configure(&self, _params: uart::Parameters) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> uart::Transmit<'a> for HostUart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.tx_buffer.is_some() || self.tx_word_pending.get() {
+            return (ReturnCode::EBUSY, Some(tx_buffer));
+        }
+        if tx_len > tx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(tx_buffer));
+        }
+        self.write_output(&tx_buffer[..tx_len]);
+        self.tx_len.set(tx_len);
+        self.tx_buffer.replace(tx_buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn transmit_word(&self, word: u32) -> ReturnCode {
+        if self.tx_buffer.is_some() || self.tx_word_pending.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.write_output(&[word as u8]);
+        self.tx_word_pending.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        // Transmissions complete immediately, so only the callback is
+        // outstanding.
+        if self.tx_buffer.is_some() || self.tx_word_pending.get() {
+            ReturnCode::FAIL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::Receive<'a> for HostUart<'a> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.rx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(rx_buffer));
+        }
+        if rx_len > rx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(rx_buffer));
+        }
+        self.rx_len.set(rx_len);
+        self.rx_position.set(0);
+        self.rx_buffer.replace(rx_buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        if self.rx_buffer.is_some() {
+            self.rx_aborting.set(true);
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::Uart<'a> for HostUart<'a> {}
+impl<'a> uart::UartData<'a> for HostUart<'a> {}
@@ -0,0 +1,181 @@
+//! System call interface for host applications.
+//!
+//! Host applications are Rust functions that run as Tock processes on the
+//! emulated chip. Their main function is called with the same arguments as
+//! `_start` of a process on a microcontroller: the address of the
+//! application in flash, the start and size of its memory, and its initial
+//! memory break. From there, the application uses the functions in this
+//! module to make system calls.
+//!
+//! Callbacks have the `Callback` type and run when the application calls
+//! `yield_for_callback()`, just like on a microcontroller.
+//!
+//! Buffers passed to `allow()` must be in the memory of the process, so
+//! applications get them from `allocate()`. The kernel only accesses these
+//! buffers while the process waits for a system call to return, when the
+//! application cannot use them.
+//!
+//! ```rust
+//! use host_emulation::userspace;
+//!
+//! fn app_main(_app_start: usize, _memory_start: usize, _memory_len: usize, _app_break: usize) {
+//!     let buffer = userspace::allocate(5).unwrap();
+//!     buffer.copy_from_slice(b"hello");
+//!     userspace::allow(1, 1, buffer.as_mut_ptr(), buffer.len());
+//!     userspace::command(1, 1, buffer.len(), 0);
+//! }
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+
+use crate::syscall::{Resume, Trap};
+
+/// A function the kernel calls in a process: either the main function of an
+/// application or a callback. Callbacks receive the three values passed by the
+/// capsule followed by the `appdata` given to `subscribe()`.
+pub type Callback = fn(usize, usize, usize, usize);
+
+const YIELD: u8 = 0;
+const SUBSCRIBE: u8 = 1;
+const COMMAND: u8 = 2;
+const ALLOW: u8 = 3;
+const MEMOP: u8 = 4;
+
+struct KernelLink {
+    resume: Receiver<Resume>,
+    trap: Sender<Trap>,
+}
+
+thread_local! {
+    static KERNEL: RefCell<Option<KernelLink>> = RefCell::new(None);
+    /// Start and end of the memory `allocate()` can hand out.
+    static HEAP: Cell<(usize, usize)> = Cell::new((0, 0));
+}
+
+/// Body of a process thread.
+pub(crate) fn run(
+    resume: Receiver<Resume>,
+    trap: Sender<Trap>,
+    function: usize,
+    arguments: [usize; 4],
+) {
+    KERNEL.with(|kernel| {
+        *kernel.borrow_mut() = Some(KernelLink {
+            resume: resume,
+            trap: trap,
+        })
+    });
+    // `allocate()` hands out the memory below the initial memory break.
+    HEAP.with(|heap| heap.set((arguments[1], arguments[3])));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        call(function, arguments);
+        // Like `_start` of a process on a microcontroller, the main function
+        // of an application does not return, so keep handling callbacks.
+        loop {
+            yield_for_callback();
+        }
+    }));
+    if result.is_err() {
+        fault();
+    }
+}
+
+fn call(function: usize, arguments: [usize; 4]) {
+    // The kernel only calls functions that were created from a `Callback`.
+    let function: Callback = unsafe { std::mem::transmute(function) };
+    function(arguments[0], arguments[1], arguments[2], arguments[3]);
+}
+
+/// Stop this thread for good. This happens when the kernel no longer
+/// runs the process, for example because it was restarted or terminated.
+fn stop() -> ! {
+    loop {
+        thread::park();
+    }
+}
+
+fn syscall(number: u8, arguments: [usize; 4]) -> isize {
+    let resume = KERNEL.with(|kernel| {
+        kernel.borrow().as_ref().and_then(|kernel| {
+            kernel
+                .trap
+                .send(Trap::Syscall {
+                    number: number,
+                    arguments: arguments,
+                })
+                .ok()?;
+            kernel.resume.recv().ok()
+        })
+    });
+    match resume {
+        Some(Resume::Return(value)) => value,
+        Some(Resume::Call {
+            function,
+            arguments,
+        }) => {
+            call(function, arguments);
+            0
+        }
+        None => stop(),
+    }
+}
+
+/// Make the process fault, like an invalid memory access would on a
+/// microcontroller.
+pub fn fault() -> ! {
+    KERNEL.with(|kernel| {
+        kernel.borrow().as_ref().map(|kernel| {
+            let _ = kernel.trap.send(Trap::Fault);
+        })
+    });
+    stop()
+}
+
+/// Wait until the kernel calls a callback, and return after it ran.
+pub fn yield_for_callback() {
+    syscall(YIELD, [0; 4]);
+}
+
+pub fn subscribe(
+    driver: usize,
+    subscribe_num: usize,
+    callback: Option<Callback>,
+    appdata: usize,
+) -> isize {
+    let callback = callback.map_or(0, |callback| callback as usize);
+    syscall(SUBSCRIBE, [driver, subscribe_num, callback, appdata])
+}
+
+pub fn command(driver: usize, command_num: usize, arg1: usize, arg2: usize) -> isize {
+    syscall(COMMAND, [driver, command_num, arg1, arg2])
+}
+
+/// Share `len` bytes at `buffer` with a capsule. The buffer must be in the
+/// memory of the process; use a null `buffer` to revoke a previous allow.
+pub fn allow(driver: usize, allow_num: usize, buffer: *mut u8, len: usize) -> isize {
+    syscall(ALLOW, [driver, allow_num, buffer as usize, len])
+}
+
+pub fn memop(operand: usize, arg: usize) -> isize {
+    syscall(MEMOP, [operand, arg, 0, 0])
+}
+
+/// Take `len` bytes from the memory of the process, moving the memory break
+/// of the process if needed. Returns `None` if the process cannot get more
+/// memory.
+pub fn allocate(len: usize) -> Option<&'static mut [u8]> {
+    let (next, end) = HEAP.with(|heap| heap.get());
+    let start = (next + 7) & !7;
+    if start + len > end {
+        // Move the break with `sbrk`, which returns the previous break.
+        if memop(1, start + len - end) < 0 {
+            return None;
+        }
+    }
+    HEAP.with(|heap| heap.set((start + len, end.max(start + len))));
+    Some(unsafe { slice::from_raw_parts_mut(start as *mut u8, len) })
+}
@@ -0,0 +1,38 @@
+//! Events that wake the kernel from sleep.
+//!
+//! Peripherals that receive data from other threads call `notify()` on the
+//! chip's `Wakeup` once the data is available, which ends `HostChip::sleep()`.
+
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct Wakeup {
+    pending: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Wakeup {
+    pub fn new() -> Wakeup {
+        Wakeup::default()
+    }
+
+    /// Wake the kernel, or make its next `wait()` return immediately.
+    pub fn notify(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        *pending = true;
+        self.condvar.notify_all();
+    }
+
+    /// Block until `notify()` is called or until `timeout` elapses.
+    pub fn wait(&self, timeout: Option<Duration>) {
+        let mut pending = self.pending.lock().unwrap();
+        if !*pending {
+            pending = match timeout {
+                Some(timeout) => self.condvar.wait_timeout(pending, timeout).unwrap().0,
+                None => self.condvar.wait(pending).unwrap(),
+            };
+        }
+        *pending = false;
+    }
+}
@@ -8,7 +8,7 @@ use kernel::debug;
 
 pub struct NRF52<I: InterruptService> {
     mpu: cortexm4::mpu::MPU,
-    userspace_kernel_boundary: cortexm4::syscall::SysCall,
+    userspace_kernel_boundary: cortexm4::syscall::SysCallFp,
     scheduler_timer: cortexm4::systick::SysTick,
     interrupt_service: I,
 }
@@ -17,7 +17,7 @@ impl<I: InterruptService> NRF52<I> {
     pub unsafe fn new(interrupt_service: I) -> NRF52<I> {
         NRF52 {
             mpu: cortexm4::mpu::MPU::new(),
-            userspace_kernel_boundary: cortexm4::syscall::SysCall::new(),
+            userspace_kernel_boundary: cortexm4::syscall::SysCallFp::new(),
             // The NRF52's systick is uncalibrated, but is clocked from the
             // 64Mhz CPU clock.
             scheduler_timer: cortexm4::systick::SysTick::new_with_calibration(64000000),
@@ -28,7 +28,7 @@ impl<I: InterruptService> NRF52<I> {
 
 impl<I: InterruptService> kernel::Chip for NRF52<I> {
     type MPU = cortexm4::mpu::MPU;
-    type UserspaceKernelBoundary = cortexm4::syscall::SysCall;
+    type UserspaceKernelBoundary = cortexm4::syscall::SysCallFp;
     type SchedulerTimer = cortexm4::systick::SysTick;
     type WatchDog = ();
 
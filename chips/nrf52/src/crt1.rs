@@ -1,5 +1,6 @@
 use cortexm4::{
-    generic_isr, hard_fault_handler, nvic, scb, svc_handler, systick_handler, unhandled_interrupt,
+    generic_isr, hard_fault_handler, nvic, scb, svc_handler_fp, systick_handler,
+    unhandled_interrupt,
 };
 use tock_rt0;
 
@@ -57,7 +58,7 @@ pub static BASE_VECTORS: [unsafe extern "C" fn(); 16] = [
     // Reserved
     unhandled_interrupt,
     // SVCall
-    svc_handler,
+    svc_handler_fp,
     // Reserved for Debug
     unhandled_interrupt,
     // Reserved
@@ -156,5 +157,8 @@ pub unsafe extern "C" fn init() {
     // To be safe we unconditionally set the vector table.
     scb::set_vector_table_offset(BASE_VECTORS.as_ptr() as *const ());
 
+    // Let processes use the FPU.
+    scb::enable_fpu();
+
     nvic::enable_all();
 }
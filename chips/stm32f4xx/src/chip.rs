@@ -20,7 +20,7 @@ use crate::deferred_calls::DeferredCallTask;
 
 pub struct Stm32f4xx {
     mpu: cortexm4::mpu::MPU,
-    userspace_kernel_boundary: cortexm4::syscall::SysCall,
+    userspace_kernel_boundary: cortexm4::syscall::SysCallFp,
     scheduler_timer: cortexm4::systick::SysTick,
 }
 
@@ -28,7 +28,7 @@ impl Stm32f4xx {
     pub unsafe fn new() -> Stm32f4xx {
         Stm32f4xx {
             mpu: cortexm4::mpu::MPU::new(),
-            userspace_kernel_boundary: cortexm4::syscall::SysCall::new(),
+            userspace_kernel_boundary: cortexm4::syscall::SysCallFp::new(),
             scheduler_timer: cortexm4::systick::SysTick::new(),
         }
     }
@@ -36,7 +36,7 @@ impl Stm32f4xx {
 
 impl Chip for Stm32f4xx {
     type MPU = cortexm4::mpu::MPU;
-    type UserspaceKernelBoundary = cortexm4::syscall::SysCall;
+    type UserspaceKernelBoundary = cortexm4::syscall::SysCallFp;
     type SchedulerTimer = cortexm4::systick::SysTick;
     type WatchDog = ();
 
@@ -119,7 +119,7 @@ impl Chip for Stm32f4xx {
         &()
     }
 
-    fn userspace_kernel_boundary(&self) -> &cortexm4::syscall::SysCall {
+    fn userspace_kernel_boundary(&self) -> &cortexm4::syscall::SysCallFp {
         &self.userspace_kernel_boundary
     }
 
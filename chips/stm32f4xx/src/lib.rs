@@ -26,7 +26,7 @@ pub mod syscfg;
 pub mod tim2;
 pub mod usart;
 
-use cortexm4::{hard_fault_handler, svc_handler, systick_handler, unhandled_interrupt};
+use cortexm4::{hard_fault_handler, scb, svc_handler_fp, systick_handler, unhandled_interrupt};
 
 extern "C" {
     // _estack is not really a function, but it makes the types work
@@ -55,7 +55,7 @@ pub static BASE_VECTORS: [unsafe extern "C" fn(); 16] = [
     unhandled_interrupt,
     unhandled_interrupt,
     unhandled_interrupt,
-    svc_handler,         // SVC
+    svc_handler_fp,      // SVC
     unhandled_interrupt, // DebugMon
     unhandled_interrupt,
     unhandled_interrupt, // PendSV
@@ -74,6 +74,9 @@ pub unsafe fn init() {
     tock_rt0::init_data(&mut _etext, &mut _srelocate, &mut _erelocate);
     tock_rt0::zero_bss(&mut _szero, &mut _ezero);
 
+    // Let processes use the FPU.
+    scb::enable_fpu();
+
     cortexm4::nvic::disable_all();
     cortexm4::nvic::clear_all_pending();
 }
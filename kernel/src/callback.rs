@@ -151,6 +151,14 @@ impl AppId {
             (start, end)
         })
     }
+
+    /// Returns the package name of the app from its TBF header, or an empty
+    /// string if the app no longer exists. Unlike `id()`, the name stays the
+    /// same when the app restarts or the board reboots.
+    pub fn get_process_name(&self) -> &'static str {
+        self.kernel
+            .process_map_or("", *self, |process| process.get_process_name())
+    }
 }
 
 /// Type to uniquely identify a callback subscription across all drivers.
@@ -69,6 +69,10 @@ pub unsafe trait MemoryAllocationCapability {}
 /// restricted.
 pub unsafe trait ExternalProcessCapability {}
 
+/// The `WatchdogCapability` capability allows the holder to stop the kernel
+/// from tickling the hardware watchdog, which then resets the board.
+pub unsafe trait WatchdogCapability {}
+
 /// The `UdpDriverCapability` capability allows the holder to use two functions
 /// only allowed by the UDP driver. The first is the `driver_send_to()` function
 /// in udp_send.rs, which does not require being bound to a single port, since
@@ -0,0 +1,437 @@
+//! Loading and unloading processes at runtime.
+//!
+//! Processes are normally created once at boot by `load_processes()`. The
+//! `DynamicProcessManager` additionally allows trusted kernel code (e.g. a
+//! capsule that receives new applications over a bus) to load a TBF that was
+//! written to application flash after boot into an empty slot of the processes
+//! array, and to terminate a process and reclaim its slot and memory.
+//!
+//! The manager does not write flash itself, as flash drivers live in chip
+//! crates and capsules. Instead, `find_flash_region()` chooses where a new
+//! application of a given size must be written, including any padding
+//! entries that keep the linked list of TBFs in flash valid. After a process
+//! is unloaded, its flash should be marked free by writing a padding header
+//! (see `padding_header()`) over the start of its TBF.
+//!
+//! Process memory is allocated out of a set of free regions. Initially this is
+//! the `app_memory` given to the manager. When a process is unloaded its
+//! memory is returned to the free regions and merged with adjacent free
+//! memory. Boards that also load processes at boot must give the manager a
+//! different part of the process memory than they pass to `load_processes()`.
+//!
+//! Before a process is created, the credentials of the new application are
+//! checked with the board's `AppCredentialsChecker`, just as
+//! `load_and_check_processes()` does at boot. Boards that load applications
+//! without credentials pass a checker that does not require them.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let (boot_memory, dynamic_memory) = APP_MEMORY.split_at_mut(APP_MEMORY.len() / 2);
+//! kernel::procs::load_processes(
+//!     board_kernel,
+//!     chip,
+//!     app_flash,
+//!     boot_memory,
+//!     FAULT_RESPONSE,
+//!     &process_management_capability,
+//! )
+//! .unwrap();
+//! let process_manager = static_init!(
+//!     kernel::procs::DynamicProcessManager<sam4l::chip::Sam4l>,
+//!     kernel::procs::DynamicProcessManager::new(
+//!         board_kernel,
+//!         chip,
+//!         app_flash,
+//!         dynamic_memory,
+//!         FAULT_RESPONSE,
+//!         credentials_checker,
+//!         &process_management_capability,
+//!     )
+//! );
+//! ```
+
+use core::convert::TryInto;
+use core::slice;
+
+use crate::callback::AppId;
+use crate::capabilities::ProcessManagementCapability;
+use crate::common::cells::MapCell;
+use crate::config;
+use crate::debug;
+use crate::platform::Chip;
+use crate::process::{self, FaultResponse, Process, ProcessLoadError};
+use crate::process_checker::AppCredentialsChecker;
+use crate::returncode::ReturnCode;
+use crate::sched::Kernel;
+use crate::tbfheader;
+
+/// Length of the TBF header created by `padding_header()`.
+pub const PADDING_HEADER_LENGTH: usize = tbfheader::TBF_PADDING_HEADER_LENGTH;
+
+/// Maximum number of disjoint free memory regions the manager keeps track
+/// of. If memory is freed when all are in use, the smallest region is lost.
+const MAX_FREE_MEMORY_REGIONS: usize = 8;
+
+/// Create the TBF header of a padding entry spanning `total_size` bytes of
+/// application flash.
+pub fn padding_header(total_size: usize) -> [u8; PADDING_HEADER_LENGTH] {
+    tbfheader::create_padding_header(total_size as u32)
+}
+
+/// Where a new application must be written in flash. All regions are given
+/// as absolute address and length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AppFlashRegion {
+    /// The flash for the TBF of the new application. The start is aligned to
+    /// the length rounded up to a power of two, as required by MPUs.
+    pub app: (usize, usize),
+    /// Unused flash before the application that needs a padding header,
+    /// created by aligning the application.
+    pub padding_before: Option<(usize, usize)>,
+    /// Unused flash after the application that needs a padding header, if
+    /// the application was placed in a larger padding entry.
+    pub padding_after: Option<(usize, usize)>,
+}
+
+/// Interface for loading and unloading processes at runtime. Only code that
+/// was given a reference to the `DynamicProcessManager`, which requires the
+/// `ProcessManagementCapability` to create, can use it.
+pub trait DynamicProcessManagement {
+    /// Find unused application flash for a TBF of `length` bytes. The flash
+    /// is not reserved, so callers must write and load one application at a
+    /// time.
+    fn find_flash_region(&self, length: usize) -> Result<AppFlashRegion, ReturnCode>;
+
+    /// Create a process for the TBF stored in `app`, the flash (address and
+    /// length) returned in `AppFlashRegion::app` by `find_flash_region()`,
+    /// and add it to an empty slot of the processes array. The TBF must span
+    /// exactly that flash, and its credentials must be accepted by the
+    /// credentials checker. Returns `Ok(None)` if the TBF is padding or a
+    /// disabled application.
+    fn load_process(&self, app: (usize, usize)) -> Result<Option<AppId>, ProcessLoadError>;
+
+    /// Terminate the process with the identifier `identifier` (see
+    /// `AppId::id()`), remove it from the processes array and free its
+    /// memory. Returns the flash of the process so the caller can reclaim it.
+    fn unload_process(&self, identifier: usize) -> Result<&'static [u8], ReturnCode>;
+}
+
+/// A region of process memory. Unused entries have a length of zero.
+#[derive(Clone, Copy, Default)]
+struct MemoryRegion {
+    start: usize,
+    length: usize,
+}
+
+impl MemoryRegion {
+    fn end(&self) -> usize {
+        self.start + self.length
+    }
+}
+
+pub struct DynamicProcessManager<C: 'static + Chip> {
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    fault_response: FaultResponse,
+    checker: &'static dyn AppCredentialsChecker,
+    /// Process memory not in use by any process. This memory is owned by the
+    /// manager, so slices into it may be created when loading a process.
+    free_memory: MapCell<[MemoryRegion; MAX_FREE_MEMORY_REGIONS]>,
+}
+
+impl<C: 'static + Chip> DynamicProcessManager<C> {
+    pub fn new(
+        kernel: &'static Kernel,
+        chip: &'static C,
+        app_flash: &'static [u8],
+        app_memory: &'static mut [u8],
+        fault_response: FaultResponse,
+        checker: &'static dyn AppCredentialsChecker,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> DynamicProcessManager<C> {
+        let mut free_memory = [MemoryRegion::default(); MAX_FREE_MEMORY_REGIONS];
+        free_memory[0] = MemoryRegion {
+            start: app_memory.as_mut_ptr() as usize,
+            length: app_memory.len(),
+        };
+        DynamicProcessManager {
+            kernel: kernel,
+            chip: chip,
+            app_flash: app_flash,
+            fault_response: fault_response,
+            checker: checker,
+            free_memory: MapCell::new(free_memory),
+        }
+    }
+
+    /// Place an application of `length` bytes in `free_length` bytes of free
+    /// flash starting at `start`. If the free flash is the end of the app
+    /// linked list, no padding is needed after the application.
+    fn fit_app(
+        start: usize,
+        free_length: usize,
+        length: usize,
+        end_of_list: bool,
+    ) -> Option<AppFlashRegion> {
+        let alignment = length.checked_next_power_of_two()?;
+        let end = start.checked_add(free_length)?;
+
+        let mut app_start = start.checked_add(alignment - 1)? & !(alignment - 1);
+        if app_start > start && app_start - start < PADDING_HEADER_LENGTH {
+            // Not enough space for a padding header before the application.
+            app_start = app_start.checked_add(alignment)?;
+        }
+        let app_end = app_start.checked_add(length)?;
+        if app_end > end {
+            return None;
+        }
+
+        let padding_after = if end_of_list || app_end == end {
+            None
+        } else if end - app_end < PADDING_HEADER_LENGTH {
+            return None;
+        } else {
+            Some((app_end, end - app_end))
+        };
+
+        Some(AppFlashRegion {
+            app: (app_start, length),
+            padding_before: if app_start > start {
+                Some((start, app_start - start))
+            } else {
+                None
+            },
+            padding_after: padding_after,
+        })
+    }
+
+    /// Return `region` to the free memory, merging it with adjacent free
+    /// regions.
+    fn free(&self, region: MemoryRegion) {
+        if region.length == 0 {
+            return;
+        }
+        self.free_memory.map(|free_memory| {
+            let mut region = region;
+            // Free regions never touch each other, so at most one region ends
+            // where `region` starts and at most one starts where it ends.
+            for free in free_memory.iter_mut().filter(|free| free.length > 0) {
+                if free.end() == region.start {
+                    region.start = free.start;
+                    region.length += free.length;
+                    *free = MemoryRegion::default();
+                } else if region.end() == free.start {
+                    region.length += free.length;
+                    *free = MemoryRegion::default();
+                }
+            }
+
+            let smallest = free_memory
+                .iter_mut()
+                .min_by_key(|free| free.length)
+                .filter(|smallest| smallest.length < region.length);
+            match smallest {
+                Some(slot) => *slot = region,
+                None => {
+                    if config::CONFIG.debug_load_processes {
+                        debug!(
+                            "Lost {} bytes of process memory at {:#010X}",
+                            region.length, region.start
+                        );
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl<C: 'static + Chip> DynamicProcessManagement for DynamicProcessManager<C> {
+    fn find_flash_region(&self, length: usize) -> Result<AppFlashRegion, ReturnCode> {
+        if length < PADDING_HEADER_LENGTH {
+            return Err(ReturnCode::EINVAL);
+        }
+
+        let flash_start = self.app_flash.as_ptr() as usize;
+        let mut offset = 0;
+        loop {
+            let remaining_flash = self.app_flash.get(offset..).unwrap_or(&[]);
+            let lengths = remaining_flash
+                .get(0..8)
+                .and_then(|s| s.try_into().ok())
+                .map(tbfheader::parse_tbf_header_lengths);
+
+            match lengths {
+                Some(Ok((version, header_length, entry_length))) => {
+                    // Padding entries can be replaced by new applications.
+                    let is_padding = remaining_flash
+                        .get(0..header_length as usize)
+                        .and_then(|header| tbfheader::parse_tbf_header(header, version).ok())
+                        .map_or(false, |header| !header.is_app());
+                    if is_padding {
+                        let region = Self::fit_app(
+                            flash_start + offset,
+                            entry_length as usize,
+                            length,
+                            false,
+                        );
+                        if let Some(region) = region {
+                            return Ok(region);
+                        }
+                    }
+                    offset += entry_length as usize;
+                }
+                Some(Err(tbfheader::InitialTbfParseError::InvalidHeader(entry_length)))
+                    if entry_length > 0 =>
+                {
+                    offset += entry_length as usize;
+                }
+                _ => {
+                    // This is the end of the app linked list, and the rest of
+                    // application flash is unused.
+                    return Self::fit_app(
+                        flash_start + offset,
+                        self.app_flash.len().saturating_sub(offset),
+                        length,
+                        true,
+                    )
+                    .ok_or(ReturnCode::ENOMEM);
+                }
+            }
+        }
+    }
+
+    fn load_process(&self, app: (usize, usize)) -> Result<Option<AppId>, ProcessLoadError> {
+        let (app_address, app_length) = app;
+        let remaining_flash = app_address
+            .checked_sub(self.app_flash.as_ptr() as usize)
+            .and_then(|offset| self.app_flash.get(offset..))
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+        let test_header_slice: &'static [u8; 8] = remaining_flash
+            .get(0..8)
+            .and_then(|s| s.try_into().ok())
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+
+        let (version, header_length, entry_length) =
+            match tbfheader::parse_tbf_header_lengths(test_header_slice) {
+                Ok((v, hl, el)) => (v, hl, el),
+                Err(tbfheader::InitialTbfParseError::InvalidHeader(_)) => return Ok(None),
+                Err(tbfheader::InitialTbfParseError::UnableToParse) => {
+                    return Err(ProcessLoadError::TbfHeaderParseFailure(
+                        tbfheader::TbfParseError::UnsupportedVersion(u16::from_le_bytes([
+                            test_header_slice[0],
+                            test_header_slice[1],
+                        ])),
+                    ))
+                }
+            };
+        // A TBF that does not span exactly the flash it was written to would
+        // either cover the applications after it, or leave flash outside the
+        // linked list of TBFs.
+        if entry_length as usize != app_length {
+            return Err(ProcessLoadError::NotEnoughFlash);
+        }
+        let app_flash = remaining_flash
+            .get(0..app_length)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+        process::check_credentials(self.checker, app_flash, header_length as usize, version)?;
+
+        let index = self
+            .kernel
+            .with_process_slots(|procs| procs.iter().position(|proc| proc.get().is_none()))
+            .ok_or(ProcessLoadError::NoProcessSlot)?;
+
+        for i in 0..MAX_FREE_MEMORY_REGIONS {
+            let region = self
+                .free_memory
+                .map_or(MemoryRegion::default(), |free_memory| free_memory[i]);
+            if region.length == 0 {
+                continue;
+            }
+
+            // The free regions are owned by the manager and no other
+            // references to them exist.
+            let memory =
+                unsafe { slice::from_raw_parts_mut(region.start as *mut u8, region.length) };
+            let result = unsafe {
+                Process::create(
+                    self.kernel,
+                    self.chip,
+                    app_flash,
+                    header_length as usize,
+                    version,
+                    memory,
+                    self.fault_response,
+                    index,
+                )
+            };
+            match result {
+                Ok((Some(process), unused_memory)) => {
+                    // The process uses memory in the middle of the region, so
+                    // keep the unused memory after it and free the padding
+                    // before it.
+                    self.free_memory.map(|free_memory| {
+                        free_memory[i] = MemoryRegion {
+                            start: unused_memory.as_ptr() as usize,
+                            length: unused_memory.len(),
+                        }
+                    });
+                    self.free(MemoryRegion {
+                        start: region.start,
+                        length: process.mem_start() as usize - region.start,
+                    });
+
+                    if config::CONFIG.debug_load_processes {
+                        debug!(
+                            "Loaded process[{}] from flash={:#010X}-{:#010X} = {:?}",
+                            index,
+                            app_flash.as_ptr() as usize,
+                            app_flash.as_ptr() as usize + app_flash.len() - 1,
+                            process.get_process_name()
+                        );
+                    }
+                    self.kernel
+                        .with_process_slots(|procs| procs[index].set(Some(process)));
+                    return Ok(Some(process.appid()));
+                }
+                Ok((None, _)) => return Ok(None),
+                // This region is too small, or not where the process requires
+                // its memory to be. Try the next one.
+                Err(ProcessLoadError::NotEnoughMemory)
+                | Err(ProcessLoadError::MemoryAddressMismatch { .. }) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Err(ProcessLoadError::NotEnoughMemory)
+    }
+
+    fn unload_process(&self, identifier: usize) -> Result<&'static [u8], ReturnCode> {
+        let flash_start = self.app_flash.as_ptr() as usize;
+        self.kernel.with_process_slots(|procs| {
+            let index = procs
+                .iter()
+                .position(|proc| proc.get().map_or(false, |p| p.appid().id() == identifier))
+                .ok_or(ReturnCode::EINVAL)?;
+            let process = procs[index].get().ok_or(ReturnCode::EINVAL)?;
+
+            let app_flash = (process.flash_start() as usize)
+                .checked_sub(flash_start)
+                .and_then(|start| {
+                    let end = process.flash_end() as usize - flash_start;
+                    self.app_flash.get(start..end)
+                })
+                .ok_or(ReturnCode::EINVAL)?;
+            let memory = MemoryRegion {
+                start: process.mem_start() as usize,
+                length: process.mem_end() as usize - process.mem_start() as usize,
+            };
+
+            process.terminate();
+            procs[index].set(None);
+            self.free(memory);
+            Ok(app_flash)
+        })
+    }
+}
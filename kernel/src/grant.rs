@@ -1,5 +1,6 @@
 //! Data structure to store a list of userspace applications.
 
+use core::cell::Cell;
 use core::marker::PhantomData;
 use core::mem::{align_of, size_of};
 use core::ops::{Deref, DerefMut};
@@ -349,8 +350,8 @@ impl<T: Default> Grant<T> {
 pub struct Iter<'a, T: 'a + Default> {
     grant: &'a Grant<T>,
     subiter: core::iter::FilterMap<
-        core::slice::Iter<'a, Option<&'static dyn ProcessType>>,
-        fn(&Option<&'static dyn ProcessType>) -> Option<&'static dyn ProcessType>,
+        core::slice::Iter<'a, Cell<Option<&'static dyn ProcessType>>>,
+        fn(&Cell<Option<&'static dyn ProcessType>>) -> Option<&'static dyn ProcessType>,
     >,
 }
 
@@ -0,0 +1,71 @@
+//! Interface for storage devices that are read and written in fixed-size
+//! blocks, such as SD cards and external flash chips.
+//!
+//! Blocks are numbered from 0, and every operation transfers whole blocks
+//! between the device and a buffer that holds at least
+//! `count * block_size()` bytes. Operations complete asynchronously, and
+//! return the buffer to the client with the result. A device runs one
+//! operation at a time; `capsules::virtual_block_storage` shares a device
+//! between several clients.
+//!
+//! Blocks can be written without erasing them first. Erasing tells the
+//! device that the contents of blocks are no longer needed, which lets flash
+//! devices prepare them for faster writes.
+
+use crate::returncode::ReturnCode;
+
+pub trait BlockStorage<'a> {
+    /// Set the client, which is called when operations complete.
+    fn set_client(&self, client: &'a dyn BlockStorageClient);
+
+    /// Size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Number of blocks on the device, or 0 if the device is not ready.
+    fn number_of_blocks(&self) -> usize;
+
+    /// Read `count` blocks, starting with block `block`, into `buffer`.
+    ///
+    /// Returns the buffer with ESIZE if it is too small, EINVAL if the blocks
+    /// are not on the device, EBUSY if another operation is in progress, and
+    /// ENOSUPPORT if the device cannot read `count` blocks at once.
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: usize,
+        count: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])>;
+
+    /// Write `count` blocks from `buffer`, starting with block `block`.
+    ///
+    /// Returns the buffer with the same errors as `read_blocks`.
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: usize,
+        count: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])>;
+
+    /// Erase `count` blocks, starting with block `block`. The contents of
+    /// erased blocks are undefined until they are written.
+    ///
+    /// Returns EINVAL if the blocks are not on the device, EBUSY if another
+    /// operation is in progress, and ENOSUPPORT if the device cannot erase.
+    fn erase_blocks(&self, block: usize, count: usize) -> ReturnCode;
+}
+
+/// Client interface for block storage.
+pub trait BlockStorageClient {
+    /// A read finished. `result` is SUCCESS if the buffer holds the blocks,
+    /// and FAIL if the device failed to read them. Shared devices may also
+    /// report the errors of `read_blocks()` here, for reads that were queued.
+    fn read_done(&self, buffer: &'static mut [u8], result: ReturnCode);
+
+    /// A write finished. `result` is SUCCESS if the blocks were written, and
+    /// FAIL if the device failed to write them.
+    fn write_done(&self, buffer: &'static mut [u8], result: ReturnCode);
+
+    /// An erase finished. `result` is SUCCESS if the blocks were erased, and
+    /// FAIL if the device failed to erase them.
+    fn erase_done(&self, result: ReturnCode);
+}
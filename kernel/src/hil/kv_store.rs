@@ -0,0 +1,73 @@
+//! Interface for a persistent key-value store.
+//!
+//! Keys and values are byte strings. Values persist across reboots, and a
+//! value is either fully stored or not at all, even if power is lost during
+//! an update. Implementations limit the length of keys and values (see
+//! `max_key_len()` and `max_value_len()`).
+
+use crate::returncode::ReturnCode;
+
+/// A persistent store of values identified by keys.
+pub trait KVStore<'a> {
+    /// Set the client for key-value store operations. The client will be
+    /// called when operations complete.
+    fn set_client(&self, client: &'a dyn KVStoreClient);
+
+    /// Read the value stored for `key` into `value`. `key` is only used
+    /// during the call.
+    ///
+    /// Returns `EBUSY` if another operation is in progress and `EINVAL` if
+    /// the key is empty or too long.
+    fn get(
+        &self,
+        key: &[u8],
+        value: &'static mut [u8],
+    ) -> Result<(), (ReturnCode, &'static mut [u8])>;
+
+    /// Store the first `length` bytes of `value` for `key`, replacing any
+    /// value previously stored for it. `key` is only used during the call.
+    ///
+    /// Returns `EBUSY` if another operation is in progress, `EINVAL` if the
+    /// key is empty or too long or `length` exceeds the buffer, and `ESIZE` if
+    /// the value is too long.
+    fn set(
+        &self,
+        key: &[u8],
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])>;
+
+    /// Remove the value stored for `key`.
+    fn delete(&self, key: &[u8]) -> ReturnCode;
+
+    /// Reclaim storage held by deleted and replaced values. Stores also do
+    /// this when they run out of space during `set()`.
+    fn garbage_collect(&self) -> ReturnCode;
+
+    /// Maximum length of a key in bytes.
+    fn max_key_len(&self) -> usize;
+
+    /// Maximum length of a value in bytes, given a key of `key_len` bytes.
+    fn max_value_len(&self, key_len: usize) -> usize;
+}
+
+/// Receive callbacks from `KVStore`.
+pub trait KVStoreClient {
+    /// A `get()` completed. `length` is the length of the stored value. The
+    /// result is `ESIZE` if the value did not fit in `value`, in which case
+    /// the beginning of the value was read, and `FAIL` if no value is stored
+    /// for the key.
+    fn get_done(&self, result: ReturnCode, value: &'static mut [u8], length: usize);
+
+    /// A `set()` completed. The result is `ENOMEM` if the store is full, and
+    /// `FAIL` if flash could not be written. If the operation failed, the
+    /// previous value (if any) is still stored.
+    fn set_done(&self, result: ReturnCode, value: &'static mut [u8]);
+
+    /// A `delete()` completed. The result is `FAIL` if no value was stored for
+    /// the key.
+    fn delete_done(&self, result: ReturnCode);
+
+    /// A `garbage_collect()` completed.
+    fn garbage_collect_done(&self, result: ReturnCode);
+}
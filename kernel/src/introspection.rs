@@ -9,15 +9,101 @@
 //! functions. This prevents arbitrary capsules from being able to use this
 //! module, and only capsules that the board author has explicitly passed the
 //! correct capabilities to can use it.
+//!
+//! The time the kernel spends sleeping is only measured if the board gives
+//! the kernel a clock with `Kernel::set_clock()`.
 
 use core::cell::Cell;
+use core::fmt::Write;
 
 use crate::callback::AppId;
 use crate::capabilities::ProcessManagementCapability;
-use crate::common::cells::NumericCellExt;
+use crate::common::cells::{NumericCellExt, OptionalCell};
+use crate::hil::time::{Frequency, Ticks, Time};
 use crate::process;
 use crate::sched::Kernel;
 
+/// A free-running clock, which the kernel uses to measure how long it is
+/// awake and asleep. Every `hil::time::Time`, such as an alarm, is one.
+pub trait KernelClock {
+    /// The current value of the clock.
+    fn ticks(&self) -> u32;
+
+    /// Ticks from `earlier`, a value `ticks()` returned, until now.
+    fn ticks_since(&self, earlier: u32) -> u32;
+
+    /// Ticks per second.
+    fn frequency(&self) -> u32;
+}
+
+impl<T: Time> KernelClock for T {
+    fn ticks(&self) -> u32 {
+        self.now().into_u32()
+    }
+
+    fn ticks_since(&self, earlier: u32) -> u32 {
+        self.now().wrapping_sub(T::Ticks::from(earlier)).into_u32()
+    }
+
+    fn frequency(&self) -> u32 {
+        T::Frequency::frequency()
+    }
+}
+
+/// How long the kernel has been running and sleeping, in ticks of its
+/// clock. The clock is read at least once per iteration of the kernel loop,
+/// so it must not wrap around while the kernel sleeps or a process runs.
+pub(crate) struct KernelTime {
+    clock: OptionalCell<&'static dyn KernelClock>,
+    last_ticks: Cell<u32>,
+    uptime_ticks: Cell<u64>,
+    sleep_ticks: Cell<u64>,
+}
+
+impl KernelTime {
+    pub(crate) const fn new() -> KernelTime {
+        KernelTime {
+            clock: OptionalCell::empty(),
+            last_ticks: Cell::new(0),
+            uptime_ticks: Cell::new(0),
+            sleep_ticks: Cell::new(0),
+        }
+    }
+
+    pub(crate) fn set_clock(&self, clock: &'static dyn KernelClock) {
+        self.last_ticks.set(clock.ticks());
+        self.clock.set(clock);
+    }
+
+    /// Add the time since the clock was last read to the uptime, and return
+    /// it.
+    pub(crate) fn update(&self) -> u64 {
+        self.clock.map_or(0, |clock| {
+            let elapsed = clock.ticks_since(self.last_ticks.get());
+            self.last_ticks
+                .set(self.last_ticks.get().wrapping_add(elapsed));
+            self.uptime_ticks
+                .set(self.uptime_ticks.get() + elapsed as u64);
+            elapsed as u64
+        })
+    }
+
+    /// Run `sleep`, counting the time it takes as time asleep.
+    pub(crate) fn sleep<F: FnOnce()>(&self, sleep: F) {
+        self.update();
+        sleep();
+        let slept = self.update();
+        self.sleep_ticks.set(self.sleep_ticks.get() + slept);
+    }
+
+    fn to_us(&self, ticks: u64) -> Option<u64> {
+        self.clock.map(|clock| {
+            let frequency = clock.frequency() as u64;
+            ticks / frequency * 1_000_000 + ticks % frequency * 1_000_000 / frequency
+        })
+    }
+}
+
 /// This struct provides the inspection functions.
 pub struct KernelInfo {
     kernel: &'static Kernel,
@@ -123,6 +209,93 @@ impl KernelInfo {
             .process_map_or(0, app, |process| process.debug_timeslice_expiration_count())
     }
 
+    /// Returns the number of times this app missed its deadline. Only
+    /// real-time schedulers give processes deadlines.
+    pub fn number_app_deadline_misses(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_deadline_miss_count())
+    }
+
+    /// Returns the syscall histogram of the app: how many subscribe, command
+    /// and allow syscalls it made to each driver.
+    pub fn app_syscall_histogram(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> process::SyscallHistogram {
+        self.kernel
+            .process_map_or(process::SyscallHistogram::default(), app, |process| {
+                process.debug_syscall_histogram()
+            })
+    }
+
+    /// Returns how many microseconds the app has run, over all its restarts.
+    /// Time is measured with the scheduler timer, so it does not include
+    /// time the app ran with a cooperative scheduler.
+    pub fn app_cpu_time_us(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> u64 {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_cpu_time_us())
+    }
+
+    /// Returns the (stack, heap) high-water marks of the app: the most stack
+    /// memory it used and the largest size of its heap in bytes, since it
+    /// last started. Either is `None` if the app did not tell the kernel
+    /// where it starts.
+    pub fn app_memory_high_water(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> (Option<usize>, Option<usize>) {
+        self.kernel.process_map_or((None, None), app, |process| {
+            (
+                process.debug_peak_stack_usage(),
+                process.debug_peak_heap_usage(),
+            )
+        })
+    }
+
+    /// Returns the (app, grant, total) memory of the app in bytes: the memory
+    /// below its memory break, the memory the kernel took from the end of the
+    /// app's memory for grants and the app's kernel state, and the size of
+    /// the app's memory.
+    pub fn app_memory_usage(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> (usize, usize, usize) {
+        self.kernel.process_map_or((0, 0, 0), app, |process| {
+            let start = process.mem_start() as usize;
+            let end = process.mem_end() as usize;
+            (
+                process.app_memory_break() as usize - start,
+                end - process.kernel_memory_break() as usize,
+                end - start,
+            )
+        })
+    }
+
+    /// Prints the memory map of the app, the registers it saved on its stack
+    /// when it last switched to the kernel, and its MPU configuration to
+    /// `writer`.
+    pub fn print_app_state(
+        &self,
+        app: AppId,
+        writer: &mut dyn Write,
+        _capability: &dyn ProcessManagementCapability,
+    ) {
+        self.kernel.process_map_or((), app, |process| unsafe {
+            process.print_full_process(writer);
+        });
+    }
+
     /// Returns a tuple of the (the number of grants in the grant region this
     /// app has allocated, total number of grants that exist in the system).
     pub fn number_app_grant_uses(
@@ -159,4 +332,52 @@ impl KernelInfo {
         });
         count.get()
     }
+
+    /// Returns the total number of times all processes have missed their
+    /// deadlines.
+    pub fn deadline_misses(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.debug_deadline_miss_count());
+        });
+        count.get()
+    }
+
+    /// Returns whether the kernel stopped tickling the hardware watchdog
+    /// because something holding a `WatchdogCapability` starved it.
+    pub fn watchdog_starved(&self, _capability: &dyn ProcessManagementCapability) -> bool {
+        self.kernel.watchdog_starved()
+    }
+
+    /// Returns how many microseconds the kernel has run since the board gave
+    /// it a clock, or `None` if it has no clock.
+    pub fn uptime_us(&self, _capability: &dyn ProcessManagementCapability) -> Option<u64> {
+        let time = self.kernel.time();
+        time.update();
+        time.to_us(time.uptime_ticks.get())
+    }
+
+    /// Returns how many microseconds the kernel has slept, or `None` if it
+    /// has no clock.
+    pub fn sleep_time_us(&self, _capability: &dyn ProcessManagementCapability) -> Option<u64> {
+        let time = self.kernel.time();
+        time.to_us(time.sleep_ticks.get())
+    }
+
+    /// Returns how many microseconds the kernel has been awake without
+    /// running a process, or `None` if it has no clock. Time processes ran
+    /// with a cooperative scheduler counts as kernel time.
+    pub fn kernel_time_us(&self, capability: &dyn ProcessManagementCapability) -> Option<u64> {
+        let process_time: Cell<u64> = Cell::new(0);
+        self.kernel.process_each(|process| {
+            process_time.set(process_time.get() + process.debug_cpu_time_us());
+        });
+        let uptime = self.uptime_us(capability)?;
+        let sleep_time = self.sleep_time_us(capability)?;
+        Some(
+            uptime
+                .saturating_sub(sleep_time)
+                .saturating_sub(process_time.get()),
+        )
+    }
 }
@@ -2,19 +2,67 @@
 //!
 //! This is a special syscall driver that allows userspace applications to
 //! share memory.
+//!
+//! Processes refer to each other by an IPC identifier, which the kernel
+//! assigns when it creates a process. It stays the same when the process
+//! restarts, and is never given to another process, so it cannot refer to a
+//! different application than the one that was discovered, even if that
+//! application was unloaded and another one loaded in its place. Services are
+//! discovered by their package name. If several processes have the same name,
+//! discovery finds the first one.
+//!
+//! A service can restrict which processes may use it by listing the package
+//! names of its clients in its TBF header. Other processes cannot discover
+//! it, notify it, share memory with it or send messages to it; for them, it
+//! behaves as if it did not exist. Package names are only trustworthy if the
+//! board checks the credentials of the processes it loads.
+//!
+//! `IPCMessaging` is a second driver, at `MESSAGE_DRIVER_NUM`, where the
+//! kernel copies messages between processes instead. A client sends a message
+//! from its send buffer to a service, and the kernel copies it into the
+//! service's receive buffer once the service has allowed that buffer and it
+//! is free. Each service has a queue of bounded depth for messages waiting
+//! for the receive buffer, and each client can have a single request
+//! outstanding at a time. The service replies to a message by its
+//! identifier, and the kernel copies the reply into the client's reply
+//! buffer. When a service restarts, the clients that discovered it or wait
+//! for a reply from it are told, and their requests to it end.
+
+use core::cell::Cell;
 
 use crate::callback::{AppId, Callback};
 use crate::capabilities::MemoryAllocationCapability;
 use crate::driver::Driver;
 use crate::grant::Grant;
 use crate::mem::{AppSlice, Shared};
-use crate::process;
+use crate::process::{self, ProcessType};
 use crate::returncode::ReturnCode;
 use crate::sched::Kernel;
 
 /// Syscall number
 pub const DRIVER_NUM: usize = 0x10000;
 
+/// Find the process with the IPC identifier `identifier`.
+fn lookup_process(kernel: &'static Kernel, identifier: usize) -> Option<&'static dyn ProcessType> {
+    kernel
+        .get_process_iter()
+        .find(|process| process.ipc_identifier() == identifier)
+}
+
+/// Check whether `service` allows `client` to use it.
+fn permits(kernel: &Kernel, service: &dyn ProcessType, client: AppId) -> bool {
+    kernel.process_map_or(false, client, |client| {
+        service.permits_ipc_client(client.get_process_name())
+    })
+}
+
+/// Check whether the service `service` allows `client` to use it.
+fn permits_client(kernel: &Kernel, service: AppId, client: &dyn ProcessType) -> bool {
+    kernel.process_map_or(false, service, |service| {
+        service.permits_ipc_client(client.get_process_name())
+    })
+}
+
 /// Enum to mark which type of callback is scheduled for the IPC mechanism.
 #[derive(Copy, Clone, Debug)]
 pub enum IPCCallbackType {
@@ -60,6 +108,10 @@ impl IPC {
         otherapp: AppId,
         cb_type: IPCCallbackType,
     ) {
+        let other_identifier = self
+            .data
+            .kernel
+            .process_map_or(0, otherapp, |process| process.ipc_identifier());
         self.data
             .enter(appid, |mydata, _| {
                 let callback = match cb_type {
@@ -86,13 +138,13 @@ impl IPC {
                                         Some(ref slice) => {
                                             slice.expose_to(appid);
                                             callback.schedule(
-                                                otherapp.id() + 1,
+                                                other_identifier,
                                                 slice.len(),
                                                 slice.ptr() as usize,
                                             );
                                         }
                                         None => {
-                                            callback.schedule(otherapp.id() + 1, 0, 0);
+                                            callback.schedule(other_identifier, 0, 0);
                                         }
                                     }
                                 }
@@ -140,12 +192,11 @@ impl Driver for IPC {
             // Once subscribed, the client will receive callbacks when the
             // service process calls notify_client().
             svc_id => {
-                // The app passes in a number which is the app identifier of the
-                // other app (shifted by one).
-                let app_identifier = svc_id - 1;
                 // We first have to see if that identifier corresponds to a
-                // valid application by asking the kernel to do a lookup for us.
-                let otherapp = self.data.kernel.lookup_app_by_identifier(app_identifier);
+                // service this app may use.
+                let otherapp = lookup_process(self.data.kernel, svc_id)
+                    .filter(|service| permits(self.data.kernel, *service, app_id))
+                    .map(|service| service.appid());
 
                 self.data
                     .enter(app_id, |data, _| {
@@ -172,7 +223,9 @@ impl Driver for IPC {
     /// In either case, the target_id is the same number as provided in a notify
     /// callback or as returned by allow.
     ///
-    /// Returns EINVAL if the other process doesn't exist.
+    /// Returns EINVAL if the other process doesn't exist, or if the service
+    /// (the other process when notifying a service, this process when
+    /// notifying a client) does not allow the client to use it.
     fn command(
         &self,
         target_id: usize,
@@ -186,21 +239,18 @@ impl Driver for IPC {
             IPCCallbackType::Client
         };
 
-        let app_identifier = target_id - 1;
-
-        self.data
-            .kernel
-            .lookup_app_by_identifier(app_identifier)
-            .map_or(ReturnCode::EINVAL, |otherapp| {
-                self.data
-                    .kernel
-                    .process_map_or(ReturnCode::EINVAL, otherapp, |target| {
-                        let ret = target.enqueue_task(process::Task::IPC((appid, cb_type)));
-                        match ret {
-                            true => ReturnCode::SUCCESS,
-                            false => ReturnCode::FAIL,
-                        }
-                    })
+        let kernel = self.data.kernel;
+        lookup_process(kernel, target_id)
+            .filter(|target| match cb_type {
+                IPCCallbackType::Service => permits(kernel, *target, appid),
+                IPCCallbackType::Client => permits_client(kernel, appid, *target),
+            })
+            .map_or(ReturnCode::EINVAL, |target| {
+                let ret = target.enqueue_task(process::Task::IPC((appid, cb_type)));
+                match ret {
+                    true => ReturnCode::SUCCESS,
+                    false => ReturnCode::FAIL,
+                }
             })
     }
 
@@ -215,8 +265,9 @@ impl Driver for IPC {
     ///
     /// If allow is called with target_id >= 1, it is a share command where the
     /// application is explicitly sharing a slice with an IPC service (as
-    /// specified by the target_id). allow() simply allows both processes to
-    /// access the buffer, it does not signal the service.
+    /// specified by the target_id), or a service is sharing a slice with one
+    /// of its clients. allow() simply allows both processes to access the
+    /// buffer, it does not signal the other process.
     fn allow(
         &self,
         appid: AppId,
@@ -231,9 +282,10 @@ impl Driver for IPC {
                         // are slices equal?
                         if s.len() == slice_data.len()
                             && s.iter().zip(slice_data.iter()).all(|(c1, c2)| c1 == c2)
+                            && permits(self.data.kernel, p, appid)
                         {
                             ReturnCode::SuccessWithValue {
-                                value: (p.appid().id() as usize) + 1,
+                                value: p.ipc_identifier(),
                             }
                         } else {
                             ReturnCode::FAIL
@@ -252,9 +304,13 @@ impl Driver for IPC {
             .enter(appid, |data, _| {
                 // Lookup the index of the app based on the passed in
                 // identifier. This also let's us check that the other app is
-                // actually valid.
-                let app_identifier = target_id - 1;
-                let otherapp = self.data.kernel.lookup_app_by_identifier(app_identifier);
+                // actually valid, and allows us to share memory with it.
+                let kernel = self.data.kernel;
+                let otherapp = lookup_process(kernel, target_id)
+                    .filter(|target| {
+                        permits(kernel, *target, appid) || permits_client(kernel, appid, *target)
+                    })
+                    .map(|target| target.appid());
 
                 match otherapp.map_or(None, |oa| oa.index()) {
                     Some(i) => {
@@ -272,3 +328,588 @@ impl Driver for IPC {
             .unwrap_or(ReturnCode::EBUSY)
     }
 }
+
+/// Syscall number of the message-passing interface.
+pub const MESSAGE_DRIVER_NUM: usize = 0x10002;
+
+/// Maximum number of messages that can wait for a service's receive buffer.
+pub const MAX_QUEUE_DEPTH: usize = 8;
+
+/// Maximum number of services a client is told about when they restart.
+/// Once a client discovered more services, the oldest are forgotten.
+const MAX_SERVICES: usize = 8;
+
+/// Events passed as the first argument of the client callback.
+const EVENT_DELIVERED: usize = 0;
+const EVENT_REPLY: usize = 1;
+const EVENT_FAILED: usize = 2;
+const EVENT_RESTARTED: usize = 3;
+
+/// A message a client has sent and not yet received a reply for.
+#[derive(Copy, Clone)]
+struct Request {
+    service: AppId,
+    message_id: usize,
+    length: usize,
+    /// Whether the message has been copied to the service.
+    delivered: bool,
+}
+
+/// State that is stored in each process's grant region to support message
+/// passing.
+#[derive(Default)]
+struct MessageData {
+    /// Called when a message has been copied into the receive buffer.
+    service_callback: Option<Callback>,
+    /// Called when a message this process sent is delivered, replied to, or
+    /// fails.
+    client_callback: Option<Callback>,
+    receive_buffer: Option<AppSlice<Shared, u8>>,
+    reply_buffer: Option<AppSlice<Shared, u8>>,
+    send_buffer: Option<AppSlice<Shared, u8>>,
+    reply_receive_buffer: Option<AppSlice<Shared, u8>>,
+    /// Number of messages that can wait for the receive buffer. Zero if the
+    /// process is not a service.
+    queue_depth: usize,
+    /// Clients whose messages wait for the receive buffer, oldest first.
+    queue: [Option<AppId>; MAX_QUEUE_DEPTH],
+    /// Whether the receive buffer holds a message the service has not
+    /// released yet.
+    receive_buffer_full: bool,
+    request: Option<Request>,
+    /// IPC identifiers of the services this process discovered, newest
+    /// first.
+    services: [Option<usize>; MAX_SERVICES],
+}
+
+impl MessageData {
+    fn enqueue(&mut self, client: AppId) -> ReturnCode {
+        match self.queue[..self.queue_depth]
+            .iter_mut()
+            .find(|slot| slot.is_none())
+        {
+            Some(slot) => {
+                *slot = Some(client);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    fn dequeue(&mut self) -> Option<AppId> {
+        let client = self.queue[0].take();
+        self.queue.rotate_left(1);
+        client
+    }
+
+    fn remove(&mut self, client: AppId) {
+        if let Some(index) = self.queue.iter().position(|slot| *slot == Some(client)) {
+            self.queue[index] = None;
+            self.queue[index..].rotate_left(1);
+        }
+    }
+
+    fn add_service(&mut self, identifier: usize) {
+        let index = self
+            .services
+            .iter()
+            .position(|service| *service == Some(identifier))
+            .unwrap_or(MAX_SERVICES - 1);
+        self.services[..=index].rotate_right(1);
+        self.services[0] = Some(identifier);
+    }
+}
+
+/// Message-passing IPC, where the kernel copies bounded messages between
+/// processes.
+pub struct IPCMessaging {
+    data: Grant<MessageData>,
+    /// Identifier of the next message sent. Never zero.
+    next_message_id: Cell<usize>,
+}
+
+impl IPCMessaging {
+    pub fn new(
+        kernel: &'static Kernel,
+        capability: &dyn MemoryAllocationCapability,
+    ) -> IPCMessaging {
+        IPCMessaging {
+            data: kernel.create_grant(capability),
+            next_message_id: Cell::new(1),
+        }
+    }
+
+    fn allocate_message_id(&self) -> usize {
+        let id = self.next_message_id.get();
+        self.next_message_id
+            .set(if id == usize::MAX { 1 } else { id + 1 });
+        id
+    }
+
+    /// Copy queued messages into the receive buffer of `service` until it is
+    /// full or no message is waiting. Messages stay queued while the service
+    /// has not allowed a receive buffer.
+    fn deliver(&self, service: AppId) {
+        let _ = self.data.enter(service, |sdata, _| {
+            while !sdata.receive_buffer_full && sdata.receive_buffer.is_some() {
+                let client = match sdata.dequeue() {
+                    Some(client) => client,
+                    None => break,
+                };
+                // Clients that exited or cancelled their request are skipped.
+                let _ = self.data.enter(client, |cdata, _| {
+                    let request = match cdata.request {
+                        Some(request) if request.service == service && !request.delivered => {
+                            request
+                        }
+                        _ => return,
+                    };
+                    let length = request.length;
+                    let client_identifier = self
+                        .data
+                        .kernel
+                        .process_map_or(0, client, |process| process.ipc_identifier());
+                    match (cdata.send_buffer.as_ref(), sdata.receive_buffer.as_mut()) {
+                        (Some(src), Some(dst)) if length <= src.len() && length <= dst.len() => {
+                            dst.as_mut()[..length].copy_from_slice(&src.as_ref()[..length]);
+                            sdata.receive_buffer_full = true;
+                            cdata.request = Some(Request {
+                                delivered: true,
+                                ..request
+                            });
+                            cdata.client_callback.map(|mut callback| {
+                                callback.schedule(EVENT_DELIVERED, request.message_id, length)
+                            });
+                            sdata.service_callback.map(|mut callback| {
+                                callback.schedule(client_identifier, request.message_id, length)
+                            });
+                        }
+                        _ => {
+                            cdata.request = None;
+                            cdata.client_callback.map(|mut callback| {
+                                callback.schedule(
+                                    EVENT_FAILED,
+                                    request.message_id,
+                                    usize::from(ReturnCode::ESIZE),
+                                )
+                            });
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    /// Queue a message of `length` bytes from `client` for the service with
+    /// the identifier `service_id`.
+    fn send(&self, client: AppId, service_id: usize, length: usize) -> ReturnCode {
+        let service = match lookup_process(self.data.kernel, service_id) {
+            Some(service)
+                if service.appid() != client && permits(self.data.kernel, service, client) =>
+            {
+                service.appid()
+            }
+            _ => return ReturnCode::EINVAL,
+        };
+
+        let ready = self
+            .data
+            .enter(client, |cdata, _| {
+                // A service that restarted lost its queue, so requests to it
+                // are dropped.
+                if let Some(request) = cdata.request {
+                    if !self.data.kernel.appid_is_valid(&request.service) {
+                        cdata.request = None;
+                    }
+                }
+                if cdata.request.is_some() {
+                    ReturnCode::EBUSY
+                } else if length > cdata.send_buffer.as_ref().map_or(0, |buffer| buffer.len()) {
+                    ReturnCode::ESIZE
+                } else {
+                    ReturnCode::SUCCESS
+                }
+            })
+            .unwrap_or(ReturnCode::ENOMEM);
+        if ready != ReturnCode::SUCCESS {
+            return ready;
+        }
+
+        let queued = self
+            .data
+            .enter(service, |sdata, _| {
+                if sdata.queue_depth == 0 {
+                    ReturnCode::EINVAL
+                } else {
+                    sdata.enqueue(client)
+                }
+            })
+            .unwrap_or(ReturnCode::EINVAL);
+        if queued != ReturnCode::SUCCESS {
+            return queued;
+        }
+
+        let message_id = self.allocate_message_id();
+        let _ = self.data.enter(client, |cdata, _| {
+            cdata.request = Some(Request {
+                service: service,
+                message_id: message_id,
+                length: length,
+                delivered: false,
+            });
+        });
+        self.deliver(service);
+        ReturnCode::SuccessWithValue { value: message_id }
+    }
+
+    /// Copy `length` bytes from the reply buffer of `service` to the client
+    /// waiting for a reply to `message_id`.
+    fn reply(&self, service: AppId, message_id: usize, length: usize) -> ReturnCode {
+        let client = self.data.iter().find_map(|grant| {
+            grant.enter(|cdata, _| match cdata.request {
+                Some(request)
+                    if request.service == service
+                        && request.message_id == message_id
+                        && request.delivered =>
+                {
+                    Some(cdata.appid())
+                }
+                _ => None,
+            })
+        });
+        let client = match client {
+            Some(client) => client,
+            None => return ReturnCode::EINVAL,
+        };
+
+        self.data
+            .enter(service, |sdata, _| {
+                let src = match sdata.reply_buffer.as_ref() {
+                    Some(src) if length <= src.len() => src,
+                    _ => return ReturnCode::ESIZE,
+                };
+                self.data
+                    .enter(client, |cdata, _| {
+                        let dst = match cdata.reply_receive_buffer.as_mut() {
+                            Some(dst) if length <= dst.len() => dst,
+                            _ => return ReturnCode::ESIZE,
+                        };
+                        dst.as_mut()[..length].copy_from_slice(&src.as_ref()[..length]);
+                        cdata.request = None;
+                        cdata
+                            .client_callback
+                            .map(|mut callback| callback.schedule(EVENT_REPLY, message_id, length));
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or(ReturnCode::EINVAL)
+            })
+            .unwrap_or(ReturnCode::ENOMEM)
+    }
+
+    /// Find a registered service by the name in the send buffer of `appid`.
+    fn discover(&self, appid: AppId, length: usize) -> ReturnCode {
+        let found = self
+            .data
+            .enter(appid, |data, _| match data.send_buffer.as_ref() {
+                Some(name) if length <= name.len() => {
+                    let name = &name.as_ref()[..length];
+                    self.data.kernel.process_until(|p| {
+                        if p.get_process_name().as_bytes() == name
+                            && p.appid() != appid
+                            && permits(self.data.kernel, p, appid)
+                        {
+                            ReturnCode::SuccessWithValue {
+                                value: p.ipc_identifier(),
+                            }
+                        } else {
+                            ReturnCode::FAIL
+                        }
+                    })
+                }
+                _ => ReturnCode::ESIZE,
+            })
+            .unwrap_or(ReturnCode::ENOMEM);
+        let identifier = match found {
+            ReturnCode::SuccessWithValue { value } => value,
+            ReturnCode::FAIL => return ReturnCode::EINVAL,
+            error => return error,
+        };
+
+        match lookup_process(self.data.kernel, identifier) {
+            Some(service) => {
+                let registered = self
+                    .data
+                    .enter(service.appid(), |sdata, _| sdata.queue_depth > 0)
+                    .unwrap_or(false);
+                if registered {
+                    let _ = self
+                        .data
+                        .enter(appid, |data, _| data.add_service(identifier));
+                    ReturnCode::SuccessWithValue { value: identifier }
+                } else {
+                    ReturnCode::EINVAL
+                }
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    /// Tell clients that `service` restarted. Its queue and receive buffer
+    /// were lost, so requests to it end.
+    pub(crate) fn service_restarted(&self, service: &dyn ProcessType) {
+        let identifier = service.ipc_identifier();
+        let appid = service.appid();
+        self.data.each(|cdata| {
+            // The restarted service is still at the same index, but its old
+            // `AppId` is no longer valid.
+            let message_id = match cdata.request {
+                Some(request) if request.service.index == appid.index => {
+                    cdata.request = None;
+                    request.message_id
+                }
+                _ => 0,
+            };
+            if message_id != 0 || cdata.services.contains(&Some(identifier)) {
+                cdata
+                    .client_callback
+                    .map(|mut callback| callback.schedule(EVENT_RESTARTED, message_id, identifier));
+            }
+        });
+    }
+
+    /// Withdraw the outstanding request of `client`.
+    fn cancel(&self, client: AppId) -> ReturnCode {
+        let request = self
+            .data
+            .enter(client, |cdata, _| cdata.request.take())
+            .unwrap_or(None);
+        match request {
+            Some(request) => {
+                if !request.delivered {
+                    let _ = self
+                        .data
+                        .enter(request.service, |sdata, _| sdata.remove(client));
+                }
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+}
+
+impl Driver for IPCMessaging {
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Service callback, called with the client identifier, message
+    ///        identifier and length when a message has been copied into the
+    ///        receive buffer.
+    /// - `1`: Client callback, called with an event (`0` delivered, `1`
+    ///        reply, `2` failed), the message identifier and the length of
+    ///        the message or reply, or an error code if the message failed.
+    ///        Called with event `3`, the identifier of the message that
+    ///        ended or `0`, and the service identifier when a service this
+    ///        process discovered or sent a message to restarts.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        self.data
+            .enter(app_id, |data, _| match subscribe_num {
+                0 => {
+                    data.service_callback = callback;
+                    ReturnCode::SUCCESS
+                }
+                1 => {
+                    data.client_callback = callback;
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ENOSUPPORT,
+            })
+            .unwrap_or(ReturnCode::ENOMEM)
+    }
+
+    /// Setup buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Receive buffer of a service. Queued messages are delivered
+    ///        once it is allowed.
+    /// - `1`: Reply buffer of a service.
+    /// - `2`: Send buffer of a client, also used for service names.
+    /// - `3`: Buffer a client receives replies in.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        let rval = self
+            .data
+            .enter(appid, |data, _| {
+                match allow_num {
+                    0 => data.receive_buffer = slice,
+                    1 => data.reply_buffer = slice,
+                    2 => data.send_buffer = slice,
+                    3 => data.reply_receive_buffer = slice,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or(ReturnCode::ENOMEM);
+        // Deliver messages that waited for a receive buffer.
+        if allow_num == 0 && rval == ReturnCode::SUCCESS {
+            self.deliver(appid);
+        }
+        rval
+    }
+
+    /// Send and receive messages.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Register as a service, named by the package name, with up to
+    ///        `arg1` messages waiting for the receive buffer.
+    /// - `2`: Release the receive buffer so the next message can be
+    ///        delivered.
+    /// - `3`: Reply to message `arg1` with `arg2` bytes of the reply buffer.
+    /// - `4`: Find the service named by the first `arg1` bytes of the send
+    ///        buffer. Returns its identifier.
+    /// - `5`: Send `arg2` bytes of the send buffer to service `arg1`. Returns
+    ///        the message identifier, EBUSY if a request is outstanding, or
+    ///        ENOMEM if the service's queue is full.
+    /// - `6`: Cancel the outstanding request.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                if arg1 == 0 || arg1 > MAX_QUEUE_DEPTH {
+                    return ReturnCode::EINVAL;
+                }
+                self.data
+                    .enter(appid, |data, _| {
+                        if data.queue_depth > 0 {
+                            ReturnCode::EALREADY
+                        } else {
+                            data.queue_depth = arg1;
+                            ReturnCode::SUCCESS
+                        }
+                    })
+                    .unwrap_or(ReturnCode::ENOMEM)
+            }
+
+            2 => {
+                let rval = self
+                    .data
+                    .enter(appid, |data, _| {
+                        if data.queue_depth == 0 {
+                            ReturnCode::EINVAL
+                        } else {
+                            data.receive_buffer_full = false;
+                            ReturnCode::SUCCESS
+                        }
+                    })
+                    .unwrap_or(ReturnCode::ENOMEM);
+                if rval == ReturnCode::SUCCESS {
+                    self.deliver(appid);
+                }
+                rval
+            }
+
+            3 => self.reply(appid, arg1, arg2),
+
+            4 => self.discover(appid, arg1),
+
+            5 => self.send(appid, arg1, arg2),
+
+            6 => self.cancel(appid),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::callback::CallbackId;
+    use crate::capabilities::MainLoopCapability;
+    use crate::process::test::create_process_at;
+    use crate::process::{FunctionCallSource, Task};
+    use core::ptr::NonNull;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    struct Capability;
+    unsafe impl MemoryAllocationCapability for Capability {}
+    unsafe impl MainLoopCapability for Capability {}
+
+    fn client_callback(process: &dyn ProcessType) -> Option<Callback> {
+        Some(Callback::new(
+            process.appid(),
+            CallbackId {
+                driver_num: MESSAGE_DRIVER_NUM,
+                subscribe_num: 1,
+            },
+            0,
+            NonNull::dangling(),
+        ))
+    }
+
+    /// Arguments of the callbacks drivers scheduled for `process`.
+    fn callbacks(process: &dyn ProcessType) -> Vec<(usize, usize, usize)> {
+        let mut callbacks = Vec::new();
+        while let Some(task) = process.dequeue_task() {
+            if let Task::FunctionCall(call) = task {
+                if let FunctionCallSource::Driver(_) = call.source {
+                    callbacks.push((call.argument0, call.argument1, call.argument2));
+                }
+            }
+        }
+        callbacks
+    }
+
+    #[test]
+    fn restart_ends_requests_to_service() {
+        let processes: &'static mut [Option<&'static dyn ProcessType>] =
+            Box::leak(Box::new([None; 3]));
+        let kernel: &'static Kernel = Box::leak(Box::new(Kernel::new(processes)));
+        let ipc: &'static IPCMessaging =
+            Box::leak(Box::new(IPCMessaging::new(kernel, &Capability)));
+        kernel.set_ipc_messaging(ipc, &Capability);
+        let service = create_process_at(kernel, 0);
+        let client = create_process_at(kernel, 1);
+        let bystander = create_process_at(kernel, 2);
+
+        assert_eq!(ipc.command(1, 1, 0, service.appid()), ReturnCode::SUCCESS);
+        for process in [client, bystander].iter() {
+            assert_eq!(
+                ipc.subscribe(1, client_callback(*process), process.appid()),
+                ReturnCode::SUCCESS
+            );
+        }
+
+        // Without a receive buffer, the message waits in the queue.
+        let message_id = match ipc.command(5, service.ipc_identifier(), 0, client.appid()) {
+            ReturnCode::SuccessWithValue { value } => value,
+            rval => panic!("sending failed: {:?}", rval),
+        };
+        assert!(callbacks(client).is_empty());
+
+        assert!(service.force_restart());
+        assert_eq!(
+            callbacks(client),
+            [(EVENT_RESTARTED, message_id, service.ipc_identifier())]
+        );
+        assert!(callbacks(bystander).is_empty());
+        // The request ended, so there is nothing to cancel.
+        assert_eq!(ipc.command(6, 0, 0, client.appid()), ReturnCode::EINVAL);
+    }
+}
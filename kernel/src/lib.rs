@@ -96,21 +96,25 @@ pub mod debug;
 pub mod hil;
 pub mod introspection;
 pub mod ipc;
+pub mod process_checker;
 pub mod syscall;
+pub mod syscall_filter;
 
 mod callback;
 mod config;
 mod driver;
+mod dynamic_process;
 mod grant;
 mod mem;
 mod memop;
 mod platform;
 mod process;
+mod process_loader;
 mod returncode;
 mod sched;
 mod tbfheader;
 
-pub use crate::callback::{AppId, Callback};
+pub use crate::callback::{AppId, Callback, CallbackId};
 pub use crate::driver::Driver;
 pub use crate::grant::Grant;
 pub use crate::mem::{AppSlice, Private, Shared};
@@ -120,6 +124,7 @@ pub use crate::platform::{mpu, Chip, InterruptService, Platform};
 pub use crate::platform::{ClockInterface, NoClockControl, NO_CLOCK_CONTROL};
 pub use crate::returncode::ReturnCode;
 pub use crate::sched::cooperative::{CoopProcessNode, CooperativeSched};
+pub use crate::sched::edf::{EDFProcessNode, EDFSched, RealtimeParameters, SchedulingPolicy};
 pub use crate::sched::mlfq::{MLFQProcessNode, MLFQSched};
 pub use crate::sched::priority::PrioritySched;
 pub use crate::sched::round_robin::{RoundRobinProcessNode, RoundRobinSched};
@@ -131,9 +136,15 @@ pub use crate::sched::{Kernel, Scheduler};
 // processes.
 /// Publicly available process-related objects.
 pub mod procs {
+    pub use crate::dynamic_process::{
+        padding_header, AppFlashRegion, DynamicProcessManagement, DynamicProcessManager,
+        PADDING_HEADER_LENGTH,
+    };
     pub use crate::process::{
-        load_processes, AlwaysRestart, Error, FaultResponse, FunctionCall, FunctionCallSource,
-        Process, ProcessLoadError, ProcessRestartPolicy, ProcessType, State, Task,
-        ThresholdRestart, ThresholdRestartThenPanic,
+        load_and_check_processes, load_processes, AlwaysRestart, CommandPermissions, Error,
+        FaultAction, FaultReason, FaultRecord, FaultResponse, FunctionCall, FunctionCallSource,
+        Process, ProcessLoadError, ProcessRestartPolicy, ProcessType, State, SyscallHistogram,
+        Task, ThresholdRestart, ThresholdRestartThenPanic, SYSCALL_HISTOGRAM_DRIVERS,
     };
+    pub use crate::process_loader::{ProcessLoader, ProcessLoadingClient};
 }
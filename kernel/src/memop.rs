@@ -36,6 +36,9 @@ use crate::returncode::ReturnCode;
 ///   where the app has put the start of its heap. This is not strictly
 ///   necessary for correct operation, but allows for better debugging if the
 ///   app crashes.
+/// - `12`: Specify the lowest address the app stack may use. The kernel
+///   protects the memory below it with an MPU guard region, so that the app
+///   faults as soon as its stack overflows.
 pub(crate) fn memop(process: &dyn ProcessType, op_type: usize, r1: usize) -> ReturnCode {
     match op_type {
         // Op Type 0: BRK
@@ -108,6 +111,14 @@ pub(crate) fn memop(process: &dyn ProcessType, op_type: usize, r1: usize) -> Ret
             ReturnCode::SUCCESS
         }
 
+        // Op Type 12: Specify the bottom of the app stack.
+        12 => {
+            match process.set_stack_bottom(r1 as *const u8) {
+                Ok(()) => ReturnCode::SUCCESS,
+                Err(err) => err.into(),
+            }
+        }
+
         _ => ReturnCode::ENOSUPPORT,
     }
 }
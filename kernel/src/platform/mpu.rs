@@ -251,6 +251,48 @@ pub trait MPU {
         }
     }
 
+    /// Allocates a guard region below the stack of a process.
+    ///
+    /// An implementation must allocate a region of at least `min_guard_size`
+    /// bytes that starts at or above `memory_start` and ends at or below
+    /// `stack_bottom`, as close to `stack_bottom` as the alignment rules of
+    /// the MPU allow, and store it in `config`. User mode must not be able to
+    /// access the region, even though it overlaps the app memory region, so
+    /// that the process faults as soon as its stack grows past the guard. A
+    /// guard region that was allocated before in `config` is replaced.
+    ///
+    /// # Arguments
+    ///
+    /// - `memory_start`:   lowest address the guard region may cover
+    /// - `stack_bottom`:   lowest address of the stack
+    /// - `min_guard_size`: minimum size of the guard region
+    /// - `config`:         MPU region configuration
+    ///
+    /// # Return Value
+    ///
+    /// Returns the start and size of the guard region. If the MPU cannot
+    /// protect such a region, returns None. If None is returned no changes are
+    /// made.
+    #[allow(unused_variables)]
+    fn allocate_stack_guard_region(
+        &self,
+        memory_start: *const u8,
+        stack_bottom: *const u8,
+        min_guard_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<Region> {
+        None
+    }
+
+    /// Removes the guard region below the stack of a process from `config`,
+    /// if it has one, so that the MPU region can be used again.
+    ///
+    /// # Arguments
+    ///
+    /// - `config`: MPU region configuration
+    #[allow(unused_variables)]
+    fn remove_stack_guard_region(&self, config: &mut Self::MpuConfig) {}
+
     /// Configures the MPU with the provided region configuration.
     ///
     /// An implementation must ensure that all memory locations not covered by
@@ -316,9 +358,11 @@ pub trait KernelMPU {
     ///
     /// # Return Value
     ///
-    /// Returns the start and size of the requested memory region. If it is
-    /// infeasible to allocate the MPU region, returns None. If None is
-    /// returned no changes are made.
+    /// Returns the start and size of the allocated MPU region, which covers
+    /// the requested memory but can be larger if the MPU cannot protect it
+    /// exactly. Callers must check that the region does not cover memory
+    /// that needs other permissions. If it is infeasible to allocate the MPU
+    /// region, returns None. If None is returned no changes are made.
     #[allow(unused_variables)]
     fn allocate_kernel_region(
         &self,
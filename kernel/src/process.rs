@@ -17,7 +17,9 @@ use crate::ipc;
 use crate::mem::{AppSlice, Shared};
 use crate::platform::mpu::{self, MPU};
 use crate::platform::Chip;
+use crate::process_checker::{AppCredentialsChecker, CheckResult};
 use crate::returncode::ReturnCode;
+use crate::sched::edf::RealtimeParameters;
 use crate::sched::Kernel;
 use crate::syscall::{self, Syscall, UserspaceKernelBoundary};
 use crate::tbfheader;
@@ -56,6 +58,19 @@ pub enum ProcessLoadError {
         expected_address: u32,
     },
 
+    /// The credentials checker rejected the credentials of the process, so
+    /// the process was not loaded.
+    CredentialsRejected,
+
+    /// The credentials checker requires processes to have accepted
+    /// credentials, and none of the credentials of the process (if any) were
+    /// accepted, so the process was not loaded.
+    CredentialsNotAccepted,
+
+    /// All slots in the processes array are in use, so no further process can
+    /// be loaded.
+    NoProcessSlot,
+
     /// Process loading error due (likely) to a bug in the kernel. If you get
     /// this error please open a bug report.
     InternalError,
@@ -109,6 +124,16 @@ impl fmt::Debug for ProcessLoadError {
                 actual_address, expected_address
             ),
 
+            ProcessLoadError::CredentialsRejected => {
+                write!(f, "App credentials rejected by credentials checker")
+            }
+
+            ProcessLoadError::CredentialsNotAccepted => {
+                write!(f, "App has no credentials accepted by credentials checker")
+            }
+
+            ProcessLoadError::NoProcessSlot => write!(f, "No free slot in the processes array"),
+
             ProcessLoadError::InternalError => write!(f, "Error in kernel. Likely a bug."),
         }
     }
@@ -122,7 +147,7 @@ impl fmt::Debug for ProcessLoadError {
 /// through Tock Binary Format (TBF) headers. Processes are given memory out of
 /// the `app_memory` buffer until either the memory is exhausted or the
 /// allocated number of processes are created. A reference to each process is
-/// stored in the provided `procs` array. How process faults are handled by the
+/// stored in the processes array the `kernel` was created with. How process faults are handled by the
 /// kernel must be provided and is assigned to every created process.
 ///
 /// This function is made `pub` so that board files can use it, but loading
@@ -137,9 +162,45 @@ pub fn load_processes<C: Chip>(
     chip: &'static C,
     app_flash: &'static [u8],
     app_memory: &'static mut [u8],
-    procs: &'static mut [Option<&'static dyn ProcessType>],
     fault_response: FaultResponse,
     _capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_processes_advanced(kernel, chip, app_flash, app_memory, fault_response, None)
+}
+
+/// Like `load_processes()`, but only loads processes whose credentials are
+/// approved by `checker`.
+///
+/// Processes whose credentials are rejected, or that lack credentials the
+/// checker requires, are skipped (and reported with `debug!()`), and loading
+/// continues with the next process in flash. Other errors stop process
+/// loading, as with `load_processes()`.
+pub fn load_and_check_processes<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &'static mut [u8],
+    fault_response: FaultResponse,
+    checker: &dyn AppCredentialsChecker,
+    _capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_processes_advanced(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        fault_response,
+        Some(checker),
+    )
+}
+
+fn load_processes_advanced<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &'static mut [u8],
+    fault_response: FaultResponse,
+    checker: Option<&dyn AppCredentialsChecker>,
 ) -> Result<(), ProcessLoadError> {
     if config::CONFIG.debug_load_processes {
         debug!(
@@ -154,8 +215,9 @@ pub fn load_processes<C: Chip>(
     let mut remaining_flash = app_flash;
     let mut remaining_memory = app_memory;
 
-    // Try to discover up to `procs.len()` processes in flash.
-    for i in 0..procs.len() {
+    // Try to discover up to one process per slot of the processes array.
+    let num_procs = kernel.with_process_slots(|procs| procs.len());
+    for i in 0..num_procs {
         // Get the first eight bytes of flash to check if there is another
         // app.
         let test_header_slice = match remaining_flash.get(0..8) {
@@ -205,6 +267,34 @@ pub fn load_processes<C: Chip>(
             .get(entry_flash.len()..)
             .ok_or(ProcessLoadError::NotEnoughFlash)?;
 
+        // If the board provided a credentials checker, make sure the app is
+        // authorized before giving it any memory.
+        if let Some(checker) = checker {
+            if header_length > 0 {
+                match check_credentials(checker, entry_flash, header_length as usize, version) {
+                    Ok(()) => {}
+                    Err(err)
+                        if matches!(
+                            err,
+                            ProcessLoadError::CredentialsRejected
+                                | ProcessLoadError::CredentialsNotAccepted
+                        ) =>
+                    {
+                        if config::CONFIG.debug_load_processes {
+                            debug!(
+                                "Process in flash={:#010X}-{:#010X} not loaded: {:?}",
+                                entry_flash.as_ptr() as usize,
+                                entry_flash.as_ptr() as usize + entry_flash.len() - 1,
+                                err
+                            );
+                        }
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
         // Need to reassign remaining_memory in every iteration so the compiler
         // knows it will not be re-borrowed.
         remaining_memory = if header_length > 0 {
@@ -242,7 +332,7 @@ pub fn load_processes<C: Chip>(
                 }
 
                 // Save the reference to this process in the processes array.
-                procs[i] = Some(process);
+                kernel.with_process_slots(|procs| procs[i].set(Some(process)));
             });
             unused_memory
         } else {
@@ -255,6 +345,66 @@ pub fn load_processes<C: Chip>(
     Ok(())
 }
 
+/// Check the credentials footers of the app in `app_flash` with `checker`.
+///
+/// Returns `Ok(())` if the app may be loaded. Padding and disabled apps are
+/// never loaded, so their credentials are not checked.
+pub(crate) fn check_credentials(
+    checker: &dyn AppCredentialsChecker,
+    app_flash: &'static [u8],
+    header_length: usize,
+    version: u16,
+) -> Result<(), ProcessLoadError> {
+    let header_flash = app_flash
+        .get(0..header_length)
+        .ok_or(ProcessLoadError::NotEnoughFlash)?;
+    let tbf_header = tbfheader::parse_tbf_header(header_flash, version)?;
+    if !tbf_header.is_app() || !tbf_header.enabled() {
+        return Ok(());
+    }
+
+    let binary_end = tbf_header.get_binary_end() as usize;
+    let integrity_region = app_flash
+        .get(0..binary_end)
+        .ok_or(ProcessLoadError::NotEnoughFlash)?;
+    let mut footers = app_flash
+        .get(binary_end..)
+        .ok_or(ProcessLoadError::NotEnoughFlash)?;
+
+    while footers.len() > 0 {
+        let (credentials, footer_len) = tbfheader::parse_tbf_footer(footers)?;
+        if let Some(credentials) = credentials {
+            match checker.check_credentials(&credentials, integrity_region) {
+                CheckResult::Accept => return Ok(()),
+                CheckResult::Reject => return Err(ProcessLoadError::CredentialsRejected),
+                CheckResult::Pass => {}
+            }
+        }
+        footers = footers
+            .get(footer_len as usize..)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+    }
+
+    if checker.require_credentials() {
+        Err(ProcessLoadError::CredentialsNotAccepted)
+    } else {
+        Ok(())
+    }
+}
+
+/// The permissions a process declared for a driver in its TBF header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandPermissions {
+    /// The process did not declare any permissions, so the board decides
+    /// which system calls it may use.
+    NoPermsAtAll,
+    /// The process declared permissions, but not for this driver.
+    NoPermsThisDriver,
+    /// The process may use this driver. Bit `i` of the mask allows command
+    /// number `offset * 64 + i`, for the `offset` that was requested.
+    Mask(u64),
+}
+
 /// This trait is implemented by process structs.
 pub trait ProcessType {
     /// Returns the process's identifier
@@ -308,16 +458,52 @@ pub trait ProcessType {
     /// `StoppedYielded` -> `Yielded`.
     fn resume(&self);
 
-    /// Put this process in the fault state. This will trigger the
-    /// `FaultResponse` for this process to occur.
-    fn set_fault_state(&self);
+    /// Put this process in the fault state because of `reason`. This will
+    /// trigger the `FaultResponse` for this process to occur.
+    fn set_fault_state(&self, reason: FaultReason);
+
+    /// Stop the process and free its grants and pending tasks. Unlike
+    /// `set_fault_state()`, the process is never restarted.
+    fn terminate(&self);
+
+    /// Restart a process whose restart policy chose
+    /// `FaultAction::RestartLater` after it faulted. Returns `false` if the
+    /// process is not waiting to be restarted or could not be restarted.
+    fn try_restart(&self) -> bool;
+
+    /// Stop the process, free its grants and pending tasks, and start it again
+    /// from the beginning, whatever its state and restart policy. Returns
+    /// `false` if the process could not be restarted, in which case it is
+    /// left stopped.
+    fn force_restart(&self) -> bool;
 
     /// Returns how many times this process has been restarted.
     fn get_restart_count(&self) -> usize;
 
+    /// Returns information about the most recent fault of this process.
+    fn get_last_fault(&self) -> Option<FaultRecord>;
+
     /// Get the name of the process. Used for IPC.
     fn get_process_name(&self) -> &'static str;
 
+    /// Get the identifier other processes use to refer to this process over
+    /// IPC. It is never zero, is not shared with any other process, and does
+    /// not change when the process restarts.
+    fn ipc_identifier(&self) -> usize;
+
+    /// Get the permissions the process declared for driver `driver_num` in
+    /// its TBF header. The returned mask covers the command numbers starting
+    /// at `offset * 64`.
+    fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions;
+
+    /// Get the period, deadline and budget the process declared in its TBF
+    /// header, if it has real-time requirements.
+    fn get_realtime_parameters(&self) -> Option<RealtimeParameters>;
+
+    /// Whether the process allows the process named `client_name` to use it
+    /// as an IPC service, based on the clients listed in its TBF header.
+    fn permits_ipc_client(&self, client_name: &str) -> bool;
+
     // memop operations
 
     /// Change the location of the program break and reallocate the MPU region
@@ -349,6 +535,10 @@ pub trait ProcessType {
     /// process.
     fn flash_end(&self) -> *const u8;
 
+    /// The first address after the memory the process can access, which the
+    /// process moves with `brk` and `sbrk`.
+    fn app_memory_break(&self) -> *const u8;
+
     /// The lowest address of the grant region for the process.
     fn kernel_memory_break(&self) -> *const u8;
 
@@ -369,6 +559,19 @@ pub trait ProcessType {
     /// Also optional.
     fn update_heap_start_pointer(&self, heap_pointer: *const u8);
 
+    /// Tell the kernel the lowest address the stack of the process may use,
+    /// and protect the memory below it with an MPU guard region, so that the
+    /// process faults with `FaultReason::StackOverflow` as soon as its stack
+    /// overflows.
+    ///
+    /// No guard region is needed if the stack starts at the beginning of
+    /// process memory, since the process cannot access the memory below it.
+    /// Calling this again replaces the guard region.
+    fn set_stack_bottom(&self, stack_bottom: *const u8) -> Result<(), Error>;
+
+    /// The MPU guard region below the stack of the process, if it has one.
+    fn stack_guard(&self) -> Option<mpu::Region>;
+
     // additional memop like functions
 
     /// Creates an `AppSlice` from the given offset and size in process memory.
@@ -489,9 +692,133 @@ pub trait ProcessType {
     /// Increment the number of times the process has exceeded its timeslice.
     fn debug_timeslice_expired(&self);
 
+    /// Returns how many times this process has missed its deadline.
+    fn debug_deadline_miss_count(&self) -> usize;
+
+    /// Increment the number of times the process has missed its deadline.
+    fn debug_deadline_missed(&self);
+
     /// Increment the number of times the process called a syscall and record
     /// the last syscall that was called.
     fn debug_syscall_called(&self, last_syscall: Syscall);
+
+    /// Returns how many subscribe, command and allow syscalls this process
+    /// made to each driver.
+    fn debug_syscall_histogram(&self) -> SyscallHistogram;
+
+    /// Returns how many microseconds this process has run, over all its
+    /// restarts.
+    fn debug_cpu_time_us(&self) -> u64;
+
+    /// Add the time that the process ran for when it last executed.
+    fn debug_cpu_time_add(&self, execution_time_us: u32);
+
+    /// Returns the most stack memory this process has used, in bytes, if the
+    /// process told the kernel where its stack starts.
+    fn debug_peak_stack_usage(&self) -> Option<usize>;
+
+    /// Returns the largest size the heap of this process had, in bytes, if
+    /// the process told the kernel where its heap starts.
+    fn debug_peak_heap_usage(&self) -> Option<usize>;
+}
+
+/// Number of drivers whose syscalls `SyscallHistogram` counts separately.
+pub const SYSCALL_HISTOGRAM_DRIVERS: usize = 8;
+
+/// Number of subscribe, command and allow syscalls a process made to each
+/// driver. The first `SYSCALL_HISTOGRAM_DRIVERS` drivers that the process
+/// used are counted separately, and the others together.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyscallHistogram {
+    /// Driver numbers and their syscall counts, in the order the process
+    /// first used the drivers. Unused entries have a count of 0.
+    pub drivers: [(usize, usize); SYSCALL_HISTOGRAM_DRIVERS],
+    /// Syscalls to drivers that are not in `drivers`.
+    pub other_drivers: usize,
+}
+
+impl SyscallHistogram {
+    /// The drivers with their syscall counts.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.drivers.iter().cloned().filter(|&(_, count)| count > 0)
+    }
+
+    fn record(&mut self, driver_number: usize) {
+        for entry in self.drivers.iter_mut() {
+            if entry.1 == 0 {
+                *entry = (driver_number, 1);
+                return;
+            } else if entry.0 == driver_number {
+                entry.1 += 1;
+                return;
+            }
+        }
+        self.other_drivers += 1;
+    }
+}
+
+impl fmt::Display for SyscallHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut separator = "";
+        for (driver_number, count) in self.iter() {
+            write!(f, "{}{:#x}: {}", separator, driver_number, count)?;
+            separator = " ";
+        }
+        if self.other_drivers > 0 {
+            write!(f, "{}other: {}", separator, self.other_drivers)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a process faulted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FaultReason {
+    /// The process triggered a hardware fault, for example by accessing
+    /// memory outside of its MPU regions or executing an invalid instruction.
+    HardwareFault,
+    /// The process grew its stack beyond the start of its memory, or its
+    /// stack is too small for the kernel to deliver a callback.
+    StackOverflow,
+    /// The process called a system call that does not exist.
+    InvalidSyscall,
+    /// The kernel was unable to switch to the process.
+    ContextSwitchFailed,
+    /// The kernel faulted the process on purpose, for example from the
+    /// process console.
+    Forced,
+    /// The process did not send a heartbeat to its application watchdog
+    /// before its timeout.
+    WatchdogTimeout,
+}
+
+/// Information about a fault of a process, passed to its
+/// `ProcessRestartPolicy`.
+#[derive(Copy, Clone, Debug)]
+pub struct FaultRecord {
+    pub reason: FaultReason,
+    /// Program counter of the process when it faulted, or 0 if it is not
+    /// known.
+    pub pc: usize,
+    /// State of the process before it faulted.
+    pub state: State,
+    /// How many times the process had been restarted before this fault.
+    pub restart_count: usize,
+    pub process_name: &'static str,
+}
+
+/// What the kernel does with a process after it faulted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FaultAction {
+    /// Restart the process immediately.
+    Restart,
+    /// Leave the process stopped. The policy restarts it later with
+    /// `ProcessType::try_restart()`, for example after a delay.
+    RestartLater,
+    /// Leave the process stopped.
+    Stop,
+    /// Panic the kernel.
+    Panic,
 }
 
 /// Generic trait for implementing process restart policies.
@@ -503,6 +830,18 @@ pub trait ProcessRestartPolicy {
     ///
     /// Returns `true` if the process should be restarted, `false` otherwise.
     fn should_restart(&self, process: &dyn ProcessType) -> bool;
+
+    /// Decide what to do with `process` after the fault described by
+    /// `fault`. Policies that depend on the reason of the fault, or that
+    /// delay restarts, implement this. By default, the process is restarted
+    /// if `should_restart()` returns `true`.
+    fn fault_action(&self, process: &dyn ProcessType, _fault: &FaultRecord) -> FaultAction {
+        if self.should_restart(process) {
+            FaultAction::Restart
+        } else {
+            FaultAction::Stop
+        }
+    }
 }
 
 /// Implementation of `ProcessRestartPolicy` that uses a threshold to decide
@@ -771,6 +1110,20 @@ struct ProcessDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How many times this process still had work to do when its deadline
+    /// passed.
+    deadline_miss_count: usize,
+
+    /// How many syscalls the process made to each driver.
+    syscall_histogram: SyscallHistogram,
+
+    /// How long the process has run, over all its restarts, as measured by
+    /// the scheduler timer.
+    cpu_time_us: u64,
+
+    /// How high the app break has ever been.
+    max_app_break: *const u8,
 }
 
 /// A type for userspace processes in Tock.
@@ -779,6 +1132,10 @@ pub struct Process<'a, C: 'static + Chip> {
     /// table.
     app_id: Cell<AppId>,
 
+    /// Identifier of this process for IPC. Unlike the identifier in `app_id`,
+    /// it is kept when the process restarts.
+    ipc_identifier: usize,
+
     /// Pointer to the main Kernel struct.
     kernel: &'static Kernel,
 
@@ -840,6 +1197,13 @@ pub struct Process<'a, C: 'static + Chip> {
     current_stack_pointer: Cell<*const u8>,
     original_stack_pointer: *const u8,
 
+    /// Lowest address the stack may use. The stack overflowed if the stack
+    /// pointer is below it.
+    stack_bottom: Cell<*const u8>,
+
+    /// MPU region below `stack_bottom` that the process cannot access.
+    stack_guard: Cell<Option<mpu::Region>>,
+
     /// Process flash segment. This is the region of nonvolatile flash that
     /// the process occupies.
     flash: &'static [u8],
@@ -881,6 +1245,12 @@ pub struct Process<'a, C: 'static + Chip> {
     /// determine if the process should be restarted or not.
     restart_count: Cell<usize>,
 
+    /// The most recent fault of the process.
+    last_fault: Cell<Option<FaultRecord>>,
+
+    /// Whether the restart policy will restart the process later.
+    restart_pending: Cell<bool>,
+
     /// Name of the app.
     process_name: &'static str,
 
@@ -978,13 +1348,32 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         }
     }
 
-    fn set_fault_state(&self) {
+    fn set_fault_state(&self, reason: FaultReason) {
+        let previous_state = self.state.get();
         self.state.update(State::Fault);
 
+        // A hardware fault with the stack pointer below the bottom of the
+        // stack is most likely the result of a stack overflow, into the
+        // guard region or out of process memory.
+        let stack_overflowed = self.debug.map_or(false, |debug| {
+            debug.min_stack_pointer < self.stack_bottom.get()
+        });
+        let reason = match reason {
+            FaultReason::HardwareFault if stack_overflowed => FaultReason::StackOverflow,
+            _ => reason,
+        };
+        self.last_fault.set(Some(FaultRecord {
+            reason: reason,
+            pc: self.fault_pc(),
+            state: previous_state,
+            restart_count: self.restart_count.get(),
+            process_name: self.process_name,
+        }));
+
         match self.fault_response {
             FaultResponse::Panic => {
                 // process faulted. Panic and print status
-                panic!("Process {} had a fault", self.process_name);
+                panic!("Process {} had a fault ({:?})", self.process_name, reason);
             }
             FaultResponse::Restart(_) => {
                 self.restart(State::StoppedFaulted);
@@ -1000,10 +1389,56 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         }
     }
 
+    /// Stop and clear a process's state.
+    ///
+    /// This will end the process, but does not reset it such that it could be
+    /// restarted and run again. This function instead frees grants and any
+    /// queued tasks for this process, but leaves the debug information about
+    /// the process and other state intact.
+    fn terminate(&self) {
+        // Remove the tasks that were scheduled for the app from the
+        // amount of work queue.
+        let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
+        for _ in 0..tasks_len {
+            self.kernel.decrement_work();
+        }
+
+        // And remove those tasks
+        self.tasks.map(|tasks| {
+            tasks.empty();
+        });
+
+        // Clear any grant regions this app has setup with any capsules.
+        unsafe {
+            self.grant_ptrs_reset();
+        }
+
+        // Mark the app as stopped so the scheduler won't try to run it.
+        self.state.update(State::StoppedFaulted);
+        self.restart_pending.set(false);
+    }
+
+    fn try_restart(&self) -> bool {
+        if !self.restart_pending.get() || self.state.get() != State::StoppedFaulted {
+            return false;
+        }
+        self.restart_pending.set(false);
+        self.reset()
+    }
+
+    fn force_restart(&self) -> bool {
+        self.terminate();
+        self.reset()
+    }
+
     fn get_restart_count(&self) -> usize {
         self.restart_count.get()
     }
 
+    fn get_last_fault(&self) -> Option<FaultRecord> {
+        self.last_fault.get()
+    }
+
     fn dequeue_task(&self) -> Option<Task> {
         self.tasks.map_or(None, |tasks| {
             tasks.dequeue().map(|cb| {
@@ -1033,6 +1468,10 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         unsafe { self.flash.as_ptr().add(self.flash.len()) }
     }
 
+    fn app_memory_break(&self) -> *const u8 {
+        self.app_break.get()
+    }
+
     fn kernel_memory_break(&self) -> *const u8 {
         self.kernel_memory_break.get()
     }
@@ -1065,6 +1504,58 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         }
     }
 
+    fn set_stack_bottom(&self, stack_bottom: *const u8) -> Result<(), Error> {
+        if !self.is_active() {
+            return Err(Error::InactiveApp);
+        }
+        if stack_bottom < self.mem_start() || stack_bottom > self.app_break.get() {
+            return Err(Error::AddressOutOfBounds);
+        }
+
+        let memory_start = self.mem_start();
+        let mpu = self.chip.mpu();
+        self.mpu_config.map_or(Err(Error::KernelError), |config| {
+            if stack_bottom == memory_start {
+                // The process cannot access the memory below its stack, so
+                // the guard region is freed.
+                mpu.remove_stack_guard_region(config);
+                self.stack_guard.set(None);
+                self.stack_bottom.set(stack_bottom);
+                return Ok(());
+            }
+
+            // The MPU replaces the previous guard region, if any.
+            match mpu.allocate_stack_guard_region(
+                memory_start,
+                stack_bottom,
+                Self::MIN_STACK_GUARD_SIZE,
+                config,
+            ) {
+                Some(guard)
+                    if guard.start_address() >= memory_start
+                        && guard.start_address().wrapping_add(guard.size()) <= stack_bottom =>
+                {
+                    self.stack_bottom.set(stack_bottom);
+                    self.stack_guard.set(Some(guard));
+                    Ok(())
+                }
+                // A guard overlapping the stack would fault the process when
+                // it uses its stack.
+                Some(_) => {
+                    mpu.remove_stack_guard_region(config);
+                    self.stack_guard.set(None);
+                    self.stack_bottom.set(memory_start);
+                    Err(Error::KernelError)
+                }
+                None => Err(Error::OutOfMemory),
+            }
+        })
+    }
+
+    fn stack_guard(&self) -> Option<mpu::Region> {
+        self.stack_guard.get()
+    }
+
     fn setup_mpu(&self) {
         self.mpu_config.map(|config| {
             self.chip.mpu().configure_mpu(&config, &self.appid());
@@ -1134,7 +1625,14 @@ impl<C: Chip> ProcessType for Process<'_, C> {
                 } else {
                     let old_break = self.app_break.get();
                     self.app_break.set(new_break);
-                    self.chip.mpu().configure_mpu(&config, &self.appid());
+                    self.debug.map(|debug| {
+                        if new_break > debug.max_app_break {
+                            debug.max_app_break = new_break;
+                        }
+                    });
+                    // The MPU is configured with the new region before the
+                    // process runs again, not now, since the process regions
+                    // may also restrict the kernel.
                     Ok(old_break)
                 }
             })
@@ -1261,10 +1759,26 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         *grant_pointer_pointer = grant_ptr;
     }
 
+    fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions {
+        self.header.get_command_permissions(driver_num, offset)
+    }
+
     fn get_process_name(&self) -> &'static str {
         self.process_name
     }
 
+    fn ipc_identifier(&self) -> usize {
+        self.ipc_identifier
+    }
+
+    fn get_realtime_parameters(&self) -> Option<RealtimeParameters> {
+        self.header.get_realtime_parameters()
+    }
+
+    fn permits_ipc_client(&self, client_name: &str) -> bool {
+        self.header.permits_ipc_client(client_name)
+    }
+
     unsafe fn set_syscall_return_value(&self, return_value: isize) {
         self.stored_state.map(|stored_state| {
             self.chip
@@ -1275,9 +1789,9 @@ impl<C: Chip> ProcessType for Process<'_, C> {
 
     unsafe fn set_process_function(&self, callback: FunctionCall) {
         // First we need to get how much memory is available for this app's
-        // stack. Since the stack is at the bottom of the process's memory
-        // region, this is straightforward.
-        let remaining_stack_bytes = self.sp() as usize - self.memory.as_ptr() as usize;
+        // stack, which grows down towards its bottom.
+        let remaining_stack_bytes =
+            (self.sp() as usize).saturating_sub(self.stack_bottom.get() as usize);
 
         // Next we should see if we can actually add the frame to the process's
         // stack. Architecture-specific code handles actually doing the push
@@ -1319,12 +1833,12 @@ impl<C: Chip> ProcessType for Process<'_, C> {
                         debug.min_stack_pointer = bad_stack_bottom;
                     }
                 });
-                self.set_fault_state();
+                self.set_fault_state(FaultReason::StackOverflow);
             }
 
             None => {
                 // We should never be here since `stored_state` should always be occupied.
-                self.set_fault_state();
+                self.set_fault_state(FaultReason::ContextSwitchFailed);
             }
         }
     }
@@ -1373,13 +1887,59 @@ impl<C: Chip> ProcessType for Process<'_, C> {
             .map(|debug| debug.timeslice_expiration_count += 1);
     }
 
+    fn debug_deadline_miss_count(&self) -> usize {
+        self.debug.map_or(0, |debug| debug.deadline_miss_count)
+    }
+
+    fn debug_deadline_missed(&self) {
+        self.debug.map(|debug| debug.deadline_miss_count += 1);
+    }
+
     fn debug_syscall_called(&self, last_syscall: Syscall) {
         self.debug.map(|debug| {
             debug.syscall_count += 1;
             debug.last_syscall = Some(last_syscall);
+            match last_syscall {
+                Syscall::SUBSCRIBE { driver_number, .. }
+                | Syscall::COMMAND { driver_number, .. }
+                | Syscall::ALLOW { driver_number, .. } => {
+                    debug.syscall_histogram.record(driver_number);
+                }
+                _ => {}
+            }
         });
     }
 
+    fn debug_syscall_histogram(&self) -> SyscallHistogram {
+        self.debug
+            .map_or(SyscallHistogram::default(), |debug| debug.syscall_histogram)
+    }
+
+    fn debug_cpu_time_us(&self) -> u64 {
+        self.debug.map_or(0, |debug| debug.cpu_time_us)
+    }
+
+    fn debug_cpu_time_add(&self, execution_time_us: u32) {
+        self.debug
+            .map(|debug| debug.cpu_time_us += execution_time_us as u64);
+    }
+
+    fn debug_peak_stack_usage(&self) -> Option<usize> {
+        self.debug.map_or(None, |debug| {
+            debug
+                .app_stack_start_pointer
+                .map(|start| (start as usize).saturating_sub(debug.min_stack_pointer as usize))
+        })
+    }
+
+    fn debug_peak_heap_usage(&self) -> Option<usize> {
+        self.debug.map_or(None, |debug| {
+            debug
+                .app_heap_start_pointer
+                .map(|start| (debug.max_app_break as usize).saturating_sub(start as usize))
+        })
+    }
+
     unsafe fn print_memory_map(&self, writer: &mut dyn Write) {
         // Flash
         let flash_end = self.flash.as_ptr().add(self.flash.len()) as usize;
@@ -1431,6 +1991,19 @@ impl<C: Chip> ProcessType for Process<'_, C> {
             Some(syscall) => writer.write_fmt(format_args!(" Last Syscall: {:?}", syscall)),
             None => writer.write_str(" Last Syscall: None"),
         };
+        if let Some(fault) = self.last_fault.get() {
+            let _ = writer.write_fmt(format_args!(
+                "\r\n Last Fault: {:?} at {:#010X}",
+                fault.reason, fault.pc
+            ));
+        }
+        if let Some(guard) = self.stack_guard.get() {
+            let _ = writer.write_fmt(format_args!(
+                "\r\n Stack Guard: {:#010X}-{:#010X}",
+                guard.start_address() as usize,
+                guard.start_address() as usize + guard.size()
+            ));
+        }
 
         let _ = writer.write_fmt(format_args!(
             "\
@@ -1604,6 +2177,15 @@ impl<C: 'static + Chip> Process<'_, C> {
     // Memory offset to make room for this process's metadata.
     const PROCESS_STRUCT_OFFSET: usize = mem::size_of::<Process<C>>();
 
+    // Smallest guard region below the stack of a process. Larger stack frames
+    // can skip a guard of this size, but most overflows grow the stack a few
+    // words at a time.
+    const MIN_STACK_GUARD_SIZE: usize = 32;
+
+    // Largest amount of state an architecture saves on the process stack
+    // when switching to the kernel (eight words on Cortex-M).
+    const SAVED_STATE_SIZE: usize = 8 * mem::size_of::<usize>();
+
     pub(crate) unsafe fn create(
         kernel: &'static Kernel,
         chip: &'static C,
@@ -1896,6 +2478,9 @@ impl<C: 'static + Chip> Process<'_, C> {
         process
             .app_id
             .set(AppId::new(kernel, unique_identifier, index));
+        // Process identifiers are never reused, so neither are IPC
+        // identifiers. Zero is used by IPC to discover services.
+        process.ipc_identifier = unique_identifier + 1;
         process.kernel = kernel;
         process.chip = chip;
         process.allow_high_water_mark = Cell::new(app_memory.as_ptr());
@@ -1908,6 +2493,8 @@ impl<C: 'static + Chip> Process<'_, C> {
         process.original_app_break = initial_sbrk_pointer;
         process.current_stack_pointer = Cell::new(initial_stack_pointer);
         process.original_stack_pointer = initial_stack_pointer;
+        process.stack_bottom = Cell::new(process.memory.as_ptr());
+        process.stack_guard = Cell::new(None);
 
         process.flash = app_flash;
 
@@ -1916,6 +2503,8 @@ impl<C: 'static + Chip> Process<'_, C> {
         process.state = ProcessStateCell::new(process.kernel);
         process.fault_response = fault_response;
         process.restart_count = Cell::new(0);
+        process.last_fault = Cell::new(None);
+        process.restart_pending = Cell::new(false);
 
         process.mpu_config = MapCell::new(mpu_config);
         process.mpu_regions = [
@@ -1939,6 +2528,10 @@ impl<C: 'static + Chip> Process<'_, C> {
             last_syscall: None,
             dropped_callback_count: 0,
             timeslice_expiration_count: 0,
+            deadline_miss_count: 0,
+            syscall_histogram: SyscallHistogram::default(),
+            cpu_time_us: 0,
+            max_app_break: initial_sbrk_pointer,
         });
 
         let flash_protected_size = process.header.get_protected_size() as usize;
@@ -2026,8 +2619,21 @@ impl<C: 'static + Chip> Process<'_, C> {
                 // leave it in the stopped faulted state by returning
                 // immediately. This has the same effect as using the
                 // `FaultResponse::Stop` policy.
-                if !restart_policy.should_restart(self) {
-                    return;
+                let action = match self.last_fault.get() {
+                    Some(fault) => restart_policy.fault_action(self, &fault),
+                    None if restart_policy.should_restart(self) => FaultAction::Restart,
+                    None => FaultAction::Stop,
+                };
+                match action {
+                    FaultAction::Restart => {}
+                    FaultAction::RestartLater => {
+                        self.restart_pending.set(true);
+                        return;
+                    }
+                    FaultAction::Stop => return,
+                    FaultAction::Panic => {
+                        panic!("Process {} had a fault", self.process_name);
+                    }
                 }
             }
 
@@ -2039,6 +2645,13 @@ impl<C: 'static + Chip> Process<'_, C> {
             }
         }
 
+        self.reset();
+    }
+
+    /// Reset the state of the process and queue its `_start` function, as
+    /// part of restarting it. Returns `false` if the process could not be
+    /// reset, in which case it is left in its current state.
+    fn reset(&self) -> bool {
         // We need a new process identifier for this process since the restarted
         // version is in effect a new process. This is also necessary to
         // invalidate any stored `AppId`s that point to the old version of the
@@ -2055,6 +2668,9 @@ impl<C: 'static + Chip> Process<'_, C> {
             debug.last_syscall = None;
             debug.dropped_callback_count = 0;
             debug.timeslice_expiration_count = 0;
+            debug.deadline_miss_count = 0;
+            debug.syscall_histogram = SyscallHistogram::default();
+            debug.max_app_break = self.original_app_break;
         });
 
         // We are going to start this process over again, so need the init_fn
@@ -2074,6 +2690,8 @@ impl<C: 'static + Chip> Process<'_, C> {
         self.current_stack_pointer.set(self.original_stack_pointer);
         self.allow_high_water_mark
             .set(self.original_allow_high_water_mark);
+        self.stack_bottom.set(self.memory.as_ptr());
+        self.stack_guard.set(None);
 
         // Reset MPU region configuration.
         // TODO: ideally, this would be moved into a helper function used by both
@@ -2125,7 +2743,7 @@ impl<C: 'static + Chip> Process<'_, C> {
                 // happen since we were able to start the process before, but at
                 // this point it is better to leave the app faulted and not
                 // schedule it.
-                return;
+                return false;
             }
         }
 
@@ -2150,7 +2768,7 @@ impl<C: 'static + Chip> Process<'_, C> {
                 // point the app is no longer valid. The best thing we
                 // can do now is leave the app as still faulted and not
                 // schedule it.
-                return;
+                return false;
             }
         };
 
@@ -2178,34 +2796,10 @@ impl<C: 'static + Chip> Process<'_, C> {
 
         // Mark that the process is ready to run.
         self.kernel.increment_work();
-    }
-
-    /// Stop and clear a process's state.
-    ///
-    /// This will end the process, but does not reset it such that it could be
-    /// restarted and run again. This function instead frees grants and any
-    /// queued tasks for this process, but leaves the debug information about
-    /// the process and other state intact.
-    fn terminate(&self) {
-        // Remove the tasks that were scheduled for the app from the
-        // amount of work queue.
-        let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
-        for _ in 0..tasks_len {
-            self.kernel.decrement_work();
-        }
-
-        // And remove those tasks
-        self.tasks.map(|tasks| {
-            tasks.empty();
-        });
-
-        // Clear any grant regions this app has setup with any capsules.
-        unsafe {
-            self.grant_ptrs_reset();
-        }
 
-        // Mark the app as stopped so the scheduler won't try to run it.
-        self.state.update(State::StoppedFaulted);
+        // Processes using this one over IPC need to know it lost its state.
+        self.kernel.process_restarted(self);
+        true
     }
 
     /// Get the current stack pointer as a pointer.
@@ -2250,6 +2844,22 @@ impl<C: 'static + Chip> Process<'_, C> {
         }
     }
 
+    /// Returns the program counter of the process, or 0 if its stack pointer
+    /// does not leave room for the state saved by the architecture.
+    fn fault_pc(&self) -> usize {
+        let stack_pointer = self.current_stack_pointer.get();
+        if stack_pointer < self.mem_start()
+            || stack_pointer.wrapping_add(Self::SAVED_STATE_SIZE) > self.app_break.get()
+        {
+            return 0;
+        }
+        self.stored_state.map_or(0, |stored_state| unsafe {
+            self.chip
+                .userspace_kernel_boundary()
+                .get_process_pc(self.sp(), stored_state)
+        })
+    }
+
     fn debug_set_max_stack_depth(&self) {
         self.debug.map(|debug| {
             if self.current_stack_pointer.get() < debug.min_stack_pointer {
@@ -2273,3 +2883,480 @@ impl<C: 'static + Chip> Process<'_, C> {
         current_state != State::StoppedFaulted && current_state != State::Fault
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::process_checker::TbfFooterV2CredentialsType;
+    use crate::tbfheader::test::{build_tbf, credentials};
+    use std::boxed::Box;
+    use std::vec;
+
+    /// Accepts and rejects credentials by their format, and passes the rest.
+    struct Checker {
+        accept: Option<TbfFooterV2CredentialsType>,
+        reject: Option<TbfFooterV2CredentialsType>,
+        require: bool,
+    }
+
+    impl AppCredentialsChecker for Checker {
+        fn check_credentials(
+            &self,
+            credentials: &tbfheader::TbfFooterV2Credentials,
+            integrity_region: &'static [u8],
+        ) -> CheckResult {
+            // The integrity region is the header and the binary.
+            assert_eq!(integrity_region.len() % 4, 0);
+            if Some(credentials.format()) == self.accept {
+                CheckResult::Accept
+            } else if Some(credentials.format()) == self.reject {
+                CheckResult::Reject
+            } else {
+                CheckResult::Pass
+            }
+        }
+
+        fn require_credentials(&self) -> bool {
+            self.require
+        }
+    }
+
+    const CREDENTIALS: u16 = 128;
+    const SHA256: Option<TbfFooterV2CredentialsType> = Some(TbfFooterV2CredentialsType::SHA256);
+    const SHA512: Option<TbfFooterV2CredentialsType> = Some(TbfFooterV2CredentialsType::SHA512);
+
+    fn check(checker: &Checker, footers: &[(u16, &[u8])]) -> Result<(), ProcessLoadError> {
+        let (tbf, header_len) = build_tbf(&[], 32, footers);
+        check_credentials(checker, tbf, header_len as usize, 2)
+    }
+
+    #[test]
+    fn credentials_accepted() {
+        let checker = Checker {
+            accept: SHA256,
+            reject: None,
+            require: true,
+        };
+        // Reserved space and unknown footers are passed over.
+        let footers: [(u16, &[u8]); 3] = [
+            (CREDENTIALS, &credentials(0, &[0; 8])),
+            (200, &[0; 4]),
+            (CREDENTIALS, &credentials(1, &[0; 32])),
+        ];
+        assert!(check(&checker, &footers).is_ok());
+    }
+
+    #[test]
+    fn credentials_rejected() {
+        let checker = Checker {
+            accept: SHA256,
+            reject: SHA512,
+            require: false,
+        };
+        // The first credentials that are accepted or rejected decide.
+        let footers: [(u16, &[u8]); 2] = [
+            (CREDENTIALS, &credentials(3, &[0; 64])),
+            (CREDENTIALS, &credentials(1, &[0; 32])),
+        ];
+        assert!(matches!(
+            check(&checker, &footers),
+            Err(ProcessLoadError::CredentialsRejected)
+        ));
+    }
+
+    #[test]
+    fn credentials_not_accepted() {
+        let mut checker = Checker {
+            accept: SHA512,
+            reject: None,
+            require: true,
+        };
+        let footers: [(u16, &[u8]); 1] = [(CREDENTIALS, &credentials(1, &[0; 32]))];
+        assert!(matches!(
+            check(&checker, &footers),
+            Err(ProcessLoadError::CredentialsNotAccepted)
+        ));
+        assert!(matches!(
+            check(&checker, &[]),
+            Err(ProcessLoadError::CredentialsNotAccepted)
+        ));
+
+        // Without required credentials, apps that pass are loaded.
+        checker.require = false;
+        assert!(check(&checker, &footers).is_ok());
+        assert!(check(&checker, &[]).is_ok());
+    }
+
+    #[test]
+    fn credentials_malformed_footer() {
+        let checker = Checker {
+            accept: SHA256,
+            reject: None,
+            require: false,
+        };
+        let footers: [(u16, &[u8]); 2] = [
+            (CREDENTIALS, &credentials(1, &[0; 16])),
+            (CREDENTIALS, &credentials(1, &[0; 32])),
+        ];
+        assert!(matches!(
+            check(&checker, &footers),
+            Err(ProcessLoadError::TbfHeaderParseFailure(
+                tbfheader::TbfParseError::BadTlvEntry(128)
+            ))
+        ));
+    }
+
+    #[test]
+    fn credentials_of_disabled_app_are_not_checked() {
+        let checker = Checker {
+            accept: None,
+            reject: SHA256,
+            require: true,
+        };
+        let (tbf, header_len) = build_tbf(&[], 32, &[(CREDENTIALS, &credentials(1, &[0; 32]))]);
+        // Clear the enable flag, and fix the checksum.
+        let mut tbf = tbf.to_vec();
+        tbf[8] = 0;
+        tbf[12] ^= 1;
+        let tbf = crate::tbfheader::test::leak(tbf);
+        assert!(check_credentials(&checker, tbf, header_len as usize, 2).is_ok());
+    }
+
+    /// The stack guard of a process, as the MPU was configured for it.
+    #[derive(Default)]
+    struct GuardConfig {
+        guard: Option<mpu::Region>,
+    }
+
+    impl fmt::Display for GuardConfig {
+        fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            Ok(())
+        }
+    }
+
+    /// MPU that places process memory at the start of the memory it is
+    /// given, and guard regions at 64 byte boundaries.
+    struct GuardMpu {
+        configured_guard: Cell<Option<mpu::Region>>,
+    }
+
+    impl MPU for GuardMpu {
+        type MpuConfig = GuardConfig;
+
+        fn allocate_app_memory_region(
+            &self,
+            unallocated_memory_start: *const u8,
+            unallocated_memory_size: usize,
+            _min_memory_size: usize,
+            _initial_app_memory_size: usize,
+            _initial_kernel_memory_size: usize,
+            _permissions: mpu::Permissions,
+            _config: &mut GuardConfig,
+        ) -> Option<(*const u8, usize)> {
+            Some((unallocated_memory_start, unallocated_memory_size))
+        }
+
+        fn allocate_stack_guard_region(
+            &self,
+            memory_start: *const u8,
+            stack_bottom: *const u8,
+            min_guard_size: usize,
+            config: &mut GuardConfig,
+        ) -> Option<mpu::Region> {
+            let size = max(min_guard_size, 64);
+            let end = stack_bottom as usize & !63;
+            let start = end.checked_sub(size)?;
+            if start < memory_start as usize {
+                return None;
+            }
+            let guard = mpu::Region::new(start as *const u8, size);
+            config.guard = Some(guard);
+            Some(guard)
+        }
+
+        fn remove_stack_guard_region(&self, config: &mut GuardConfig) {
+            config.guard = None;
+        }
+
+        fn configure_mpu(&self, config: &GuardConfig, _app_id: &AppId) {
+            self.configured_guard.set(config.guard);
+        }
+    }
+
+    /// Returns to the kernel with a fault at `fault_stack_pointer`.
+    struct FaultingBoundary {
+        fault_stack_pointer: Cell<*mut usize>,
+    }
+
+    impl UserspaceKernelBoundary for FaultingBoundary {
+        type StoredState = ();
+
+        unsafe fn initialize_process(
+            &self,
+            stack_pointer: *const usize,
+            _stack_size: usize,
+            _state: &mut (),
+        ) -> Result<*const usize, ()> {
+            Ok(stack_pointer)
+        }
+
+        unsafe fn set_syscall_return_value(
+            &self,
+            _stack_pointer: *const usize,
+            _state: &mut (),
+            _return_value: isize,
+        ) {
+        }
+
+        unsafe fn set_process_function(
+            &self,
+            stack_pointer: *const usize,
+            _remaining_stack_memory: usize,
+            _state: &mut (),
+            _callback: FunctionCall,
+        ) -> Result<*mut usize, *mut usize> {
+            Ok(stack_pointer as *mut usize)
+        }
+
+        unsafe fn switch_to_process(
+            &self,
+            _stack_pointer: *const usize,
+            _state: &mut (),
+        ) -> (*mut usize, syscall::ContextSwitchReason) {
+            (
+                self.fault_stack_pointer.get(),
+                syscall::ContextSwitchReason::Fault,
+            )
+        }
+
+        unsafe fn get_process_pc(&self, _stack_pointer: *const usize, _state: &()) -> usize {
+            0
+        }
+
+        unsafe fn print_context(
+            &self,
+            _stack_pointer: *const usize,
+            _state: &(),
+            _writer: &mut dyn Write,
+        ) {
+        }
+    }
+
+    struct GuardChip {
+        mpu: GuardMpu,
+        boundary: FaultingBoundary,
+    }
+
+    impl Chip for GuardChip {
+        type MPU = GuardMpu;
+        type UserspaceKernelBoundary = FaultingBoundary;
+        type SchedulerTimer = ();
+        type WatchDog = ();
+
+        fn service_pending_interrupts(&self) {}
+
+        fn has_pending_interrupts(&self) -> bool {
+            false
+        }
+
+        fn mpu(&self) -> &GuardMpu {
+            &self.mpu
+        }
+
+        fn scheduler_timer(&self) -> &() {
+            &()
+        }
+
+        fn watchdog(&self) -> &() {
+            &()
+        }
+
+        fn userspace_kernel_boundary(&self) -> &FaultingBoundary {
+            &self.boundary
+        }
+
+        fn sleep(&self) {}
+
+        unsafe fn atomic<F, R>(&self, f: F) -> R
+        where
+            F: FnOnce() -> R,
+        {
+            f()
+        }
+
+        unsafe fn print_state(&self, _writer: &mut dyn Write) {}
+    }
+
+    fn location(region: Option<mpu::Region>) -> Option<(*const u8, usize)> {
+        region.map(|region| (region.start_address(), region.size()))
+    }
+
+    fn create_process() -> (&'static GuardChip, &'static dyn ProcessType) {
+        create_process_in(Box::leak(Box::new(Kernel::new(&mut []))), 0)
+    }
+
+    /// Create a process and add it to slot `index` of the processes array of
+    /// `kernel`.
+    pub(crate) fn create_process_at(
+        kernel: &'static Kernel,
+        index: usize,
+    ) -> &'static dyn ProcessType {
+        let (_, process) = create_process_in(kernel, index);
+        kernel.with_process_slots(|procs| procs[index].set(Some(process)));
+        process
+    }
+
+    fn create_process_in(
+        kernel: &'static Kernel,
+        index: usize,
+    ) -> (&'static GuardChip, &'static dyn ProcessType) {
+        let chip: &'static GuardChip = Box::leak(Box::new(GuardChip {
+            mpu: GuardMpu {
+                configured_guard: Cell::new(None),
+            },
+            boundary: FaultingBoundary {
+                fault_stack_pointer: Cell::new(ptr::null_mut()),
+            },
+        }));
+        let (tbf, header_len) = build_tbf(&[], 32, &[]);
+        // Word-aligned process memory.
+        let words: &'static mut [u64] = Box::leak(vec![0u64; 1024].into_boxed_slice());
+        let memory = unsafe { slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, 8192) };
+        let (process, _) = unsafe {
+            Process::create(
+                kernel,
+                chip,
+                tbf,
+                header_len as usize,
+                2,
+                memory,
+                FaultResponse::Stop,
+                index,
+            )
+        }
+        .unwrap();
+        (chip, process.unwrap())
+    }
+
+    #[test]
+    fn stack_guard_is_configured() {
+        let (chip, process) = create_process();
+        let stack_bottom = process.mem_start().wrapping_add(1000);
+        assert!(process.set_stack_bottom(stack_bottom).is_ok());
+
+        let guard = process.stack_guard().unwrap();
+        assert!(guard.start_address() >= process.mem_start());
+        assert!(guard.start_address().wrapping_add(guard.size()) <= stack_bottom);
+        process.setup_mpu();
+        assert_eq!(
+            location(chip.mpu.configured_guard.get()),
+            location(Some(guard))
+        );
+
+        // Moving the stack bottom replaces the guard.
+        let stack_bottom = process.mem_start().wrapping_add(2000);
+        assert!(process.set_stack_bottom(stack_bottom).is_ok());
+        let moved_guard = process.stack_guard().unwrap();
+        assert!(moved_guard.start_address() > guard.start_address());
+        assert!(moved_guard.start_address().wrapping_add(moved_guard.size()) <= stack_bottom);
+        process.setup_mpu();
+        assert_eq!(
+            location(chip.mpu.configured_guard.get()),
+            location(Some(moved_guard))
+        );
+
+        // A stack at the start of process memory needs no guard.
+        assert!(process.set_stack_bottom(process.mem_start()).is_ok());
+        assert!(process.stack_guard().is_none());
+        process.setup_mpu();
+        assert!(chip.mpu.configured_guard.get().is_none());
+    }
+
+    #[test]
+    fn stack_guard_that_does_not_fit() {
+        let (chip, process) = create_process();
+        let stack_bottom = process.mem_start().wrapping_add(1000);
+        assert!(process.set_stack_bottom(stack_bottom).is_ok());
+        let guard = process.stack_guard();
+
+        // There is no room for a guard below a stack that starts 32 bytes
+        // into process memory. The previous guard stays in place.
+        assert!(matches!(
+            process.set_stack_bottom(process.mem_start().wrapping_add(32)),
+            Err(Error::OutOfMemory)
+        ));
+        assert_eq!(location(process.stack_guard()), location(guard));
+        process.setup_mpu();
+        assert_eq!(location(chip.mpu.configured_guard.get()), location(guard));
+    }
+
+    #[test]
+    fn overflow_into_stack_guard() {
+        let (chip, process) = create_process();
+        assert!(process
+            .set_stack_bottom(process.mem_start().wrapping_add(1024))
+            .is_ok());
+        let guard = process.stack_guard().unwrap();
+
+        // The process faults with its stack pointer in the guard region.
+        chip.boundary
+            .fault_stack_pointer
+            .set(guard.start_address().wrapping_add(guard.size() - 8) as *mut usize);
+        let reason = unsafe { process.switch_to() };
+        assert!(matches!(reason, Some(syscall::ContextSwitchReason::Fault)));
+        process.set_fault_state(FaultReason::HardwareFault);
+
+        assert_eq!(
+            process.get_last_fault().map(|fault| fault.reason),
+            Some(FaultReason::StackOverflow)
+        );
+        assert_eq!(process.get_state(), State::StoppedFaulted);
+    }
+
+    #[test]
+    fn fault_above_stack_bottom_is_not_overflow() {
+        let (chip, process) = create_process();
+        let stack_bottom = process.mem_start().wrapping_add(1024);
+        assert!(process.set_stack_bottom(stack_bottom).is_ok());
+
+        chip.boundary
+            .fault_stack_pointer
+            .set(stack_bottom.wrapping_add(64) as *mut usize);
+        unsafe { process.switch_to() };
+        process.set_fault_state(FaultReason::HardwareFault);
+
+        assert_eq!(
+            process.get_last_fault().map(|fault| fault.reason),
+            Some(FaultReason::HardwareFault)
+        );
+    }
+
+    #[test]
+    fn ipc_identifier_survives_restart() {
+        let (_, process) = create_process();
+        let ipc_identifier = process.ipc_identifier();
+        let appid = process.appid();
+        assert_ne!(ipc_identifier, 0);
+
+        assert!(process.force_restart());
+        assert_ne!(process.appid(), appid);
+        assert_eq!(process.ipc_identifier(), ipc_identifier);
+    }
+
+    #[test]
+    fn ipc_identifiers_are_not_reused() {
+        let kernel: &'static Kernel = Box::leak(Box::new(Kernel::new(&mut [])));
+        let (_, first) = create_process_in(kernel, 0);
+        // Loading another process after the first one was unloaded must not
+        // give it the identifier of the first one.
+        first.terminate();
+        let (_, second) = create_process_in(kernel, 0);
+        assert_ne!(second.ipc_identifier(), first.ipc_identifier());
+
+        // Restarting does not hand out identifiers of other processes either.
+        assert!(first.force_restart());
+        assert_ne!(first.ipc_identifier(), second.ipc_identifier());
+    }
+}
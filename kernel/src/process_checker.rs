@@ -0,0 +1,87 @@
+//! Mechanism for checking the credentials of applications before they are
+//! loaded.
+//!
+//! Applications can carry credentials (e.g. a hash, an HMAC or a signature)
+//! in footers that follow the application binary in its TBF. Boards that only
+//! want to run authorized applications implement `AppCredentialsChecker` and
+//! load their processes with `load_and_check_processes()`. Before each enabled
+//! application is loaded, its credentials footers are passed to the checker in
+//! order until one is accepted or rejected.
+//!
+//! Checking credentials may require hardware that operates asynchronously,
+//! such as a digest engine. For this, boards implement
+//! `AsyncAppCredentialsChecker` instead, and load processes with a
+//! `ProcessLoader` (see `process_loader.rs`), which checks and loads processes
+//! one at a time as the checker reports its results.
+//!
+//! The credentials cover the integrity region of an application, which spans
+//! from the start of its TBF header to the end of its binary. The header
+//! checksum is not a security mechanism: a checker must compute the hash or
+//! verify the signature over the integrity region itself.
+
+use crate::returncode::ReturnCode;
+
+pub use crate::tbfheader::{TbfFooterV2Credentials, TbfFooterV2CredentialsType};
+
+/// The decision of an `AppCredentialsChecker` on one set of credentials.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckResult {
+    /// The credentials are valid and the application may be loaded.
+    Accept,
+    /// The checker does not understand or cannot verify these credentials.
+    /// The next credentials footer of the application is checked instead.
+    Pass,
+    /// The credentials are invalid (e.g. the hash does not match) and the
+    /// application must not be loaded.
+    Reject,
+}
+
+/// Policy deciding which applications the kernel loads, implemented by
+/// boards.
+pub trait AppCredentialsChecker {
+    /// Check one set of credentials of an application. `integrity_region` is
+    /// the part of the application flash covered by the credentials.
+    fn check_credentials(
+        &self,
+        credentials: &TbfFooterV2Credentials,
+        integrity_region: &'static [u8],
+    ) -> CheckResult;
+
+    /// Whether an application must have accepted credentials to be loaded.
+    /// If this returns `false`, applications whose credentials all pass (or
+    /// that have none) are loaded as well. Rejected credentials always
+    /// prevent loading.
+    fn require_credentials(&self) -> bool;
+}
+
+/// Client of an `AsyncAppCredentialsChecker`.
+pub trait AsyncAppCredentialsCheckerClient<'a> {
+    /// Called when checking `credentials` completes. An error means the
+    /// checker was unable to perform the check, and is treated like
+    /// `CheckResult::Pass`.
+    fn check_done(
+        &self,
+        result: Result<CheckResult, ReturnCode>,
+        credentials: TbfFooterV2Credentials,
+    );
+}
+
+/// Asynchronous variant of `AppCredentialsChecker`, implemented by boards
+/// whose checks depend on asynchronous hardware.
+pub trait AsyncAppCredentialsChecker<'a> {
+    fn set_client(&self, client: &'a dyn AsyncAppCredentialsCheckerClient<'a>);
+
+    /// Start checking one set of credentials of an application, covering
+    /// `integrity_region`. If this returns `Ok(())`, the result is delivered
+    /// through `check_done()`. Returns `ENOSUPPORT` if the checker does not
+    /// handle credentials of this format, and `EBUSY` if a check is already
+    /// in progress; in both cases no callback follows.
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials,
+        integrity_region: &'static [u8],
+    ) -> Result<(), ReturnCode>;
+
+    /// Whether an application must have accepted credentials to be loaded.
+    fn require_credentials(&self) -> bool;
+}
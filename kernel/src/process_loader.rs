@@ -0,0 +1,381 @@
+//! Asynchronous process loading.
+//!
+//! `load_processes()` creates all processes in a single synchronous loop over
+//! flash, so it cannot wait for hardware that checks application credentials
+//! asynchronously, such as a digest engine. The `ProcessLoader` instead walks
+//! the TBFs in flash as a state machine: it passes the credentials of each
+//! enabled application to an `AsyncAppCredentialsChecker`, and only creates
+//! the process once the checker has accepted it (or, if the checker does not
+//! require credentials, once all credentials of the application passed).
+//!
+//! The outcome for every application is reported to the board through a
+//! `ProcessLoadingClient`. Processes are added to the processes array as they
+//! are loaded, so the kernel may already run loaded processes while others
+//! are still being checked.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let loader = static_init!(
+//!     kernel::procs::ProcessLoader<'static, earlgrey::chip::EarlGrey>,
+//!     kernel::procs::ProcessLoader::new(
+//!         board_kernel,
+//!         chip,
+//!         core::slice::from_raw_parts(
+//!             &_sapps as *const u8,
+//!             &_eapps as *const u8 as usize - &_sapps as *const u8 as usize,
+//!         ),
+//!         &mut APP_MEMORY,
+//!         FAULT_RESPONSE,
+//!         checker,
+//!         &process_management_capability,
+//!     )
+//! );
+//! checker.set_client(loader);
+//! loader.set_client(board_loading_client);
+//! loader.start();
+//! ```
+
+use core::cell::Cell;
+use core::convert::TryInto;
+
+use crate::capabilities::ProcessManagementCapability;
+use crate::common::cells::{OptionalCell, TakeCell};
+use crate::config;
+use crate::debug;
+use crate::platform::Chip;
+use crate::process::{FaultResponse, Process, ProcessLoadError};
+use crate::process_checker::{
+    AsyncAppCredentialsChecker, AsyncAppCredentialsCheckerClient, CheckResult,
+    TbfFooterV2Credentials,
+};
+use crate::returncode::ReturnCode;
+use crate::sched::Kernel;
+use crate::tbfheader;
+
+/// Receives the results of asynchronous process loading.
+pub trait ProcessLoadingClient {
+    /// Loading the application stored in `app_flash` finished. `result` is
+    /// `Ok(())` if a process was created for it. Padding and disabled
+    /// applications are skipped and not reported.
+    fn process_loaded(&self, app_flash: &'static [u8], result: Result<(), ProcessLoadError>);
+
+    /// All applications in flash have been handled, or loading had to stop
+    /// early (in which case the last `process_loaded()` call reported why).
+    fn loading_complete(&self);
+}
+
+/// The application whose credentials are being checked.
+#[derive(Clone, Copy)]
+struct PendingApp {
+    app_flash: &'static [u8],
+    header_length: usize,
+    version: u16,
+    /// The part of the app covered by its credentials.
+    integrity_region: &'static [u8],
+    /// Footers of the app that have not been checked yet.
+    footers: &'static [u8],
+}
+
+/// State machine that checks and loads processes one at a time.
+pub struct ProcessLoader<'a, C: 'static + Chip> {
+    kernel: &'static Kernel,
+    chip: &'static C,
+    fault_response: FaultResponse,
+    checker: &'a dyn AsyncAppCredentialsChecker<'a>,
+    client: OptionalCell<&'a dyn ProcessLoadingClient>,
+    /// Flash that has not been searched for applications yet.
+    remaining_flash: Cell<&'static [u8]>,
+    /// Memory not given to any process yet. This is empty once loading
+    /// stopped due to an error, as process creation consumes the memory.
+    remaining_memory: TakeCell<'static, [u8]>,
+    /// Index in the processes array of the next process to be loaded.
+    index: Cell<usize>,
+    pending: OptionalCell<PendingApp>,
+    /// Credentials of the pending application are being checked.
+    checking: Cell<bool>,
+    /// Result of a credentials check that has not been handled yet.
+    check_result: OptionalCell<Result<CheckResult, ReturnCode>>,
+    /// `run()` is executing.
+    running: Cell<bool>,
+}
+
+impl<'a, C: 'static + Chip> ProcessLoader<'a, C> {
+    pub fn new(
+        kernel: &'static Kernel,
+        chip: &'static C,
+        app_flash: &'static [u8],
+        app_memory: &'static mut [u8],
+        fault_response: FaultResponse,
+        checker: &'a dyn AsyncAppCredentialsChecker<'a>,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> ProcessLoader<'a, C> {
+        ProcessLoader {
+            kernel: kernel,
+            chip: chip,
+            fault_response: fault_response,
+            checker: checker,
+            client: OptionalCell::empty(),
+            remaining_flash: Cell::new(app_flash),
+            remaining_memory: TakeCell::new(app_memory),
+            index: Cell::new(0),
+            pending: OptionalCell::empty(),
+            checking: Cell::new(false),
+            check_result: OptionalCell::empty(),
+            running: Cell::new(false),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn ProcessLoadingClient) {
+        self.client.set(client);
+    }
+
+    /// Start loading processes. The client is notified as each application
+    /// is handled.
+    pub fn start(&self) {
+        self.run();
+    }
+
+    fn report(&self, app_flash: &'static [u8], result: Result<(), ProcessLoadError>) {
+        if config::CONFIG.debug_load_processes {
+            if let Err(ref err) = result {
+                debug!(
+                    "Process in flash={:#010X}-{:#010X} not loaded: {:?}",
+                    app_flash.as_ptr() as usize,
+                    app_flash.as_ptr() as usize + app_flash.len() - 1,
+                    err
+                );
+            }
+        }
+        self.client
+            .map(|client| client.process_loaded(app_flash, result));
+    }
+
+    fn finish(&self) {
+        self.client.map(|client| client.loading_complete());
+    }
+
+    /// Run the state machine until it waits for the checker or all
+    /// applications have been handled.
+    ///
+    /// A checker may call `check_done()` from within `check_credentials()`.
+    /// The result is then handled by this loop rather than by a nested call,
+    /// so stack use does not grow with the number of applications.
+    fn run(&self) {
+        if self.running.replace(true) {
+            return;
+        }
+        loop {
+            if let Some(result) = self.check_result.take() {
+                self.handle_check_result(result);
+            } else if self.checking.get() {
+                // Wait for `check_done()`.
+                break;
+            } else if self.pending.is_some() {
+                self.check_next_footer();
+            } else if !self.next_app() {
+                break;
+            }
+        }
+        self.running.set(false);
+    }
+
+    /// Find the next enabled application in flash and make it the pending
+    /// application. Returns false once loading is complete.
+    fn next_app(&self) -> bool {
+        let procs_len = self.kernel.with_process_slots(|procs| procs.len());
+        if self.index.get() >= procs_len || self.remaining_memory.is_none() {
+            self.finish();
+            return false;
+        }
+
+        match self.discover_app() {
+            Ok(Some(app)) => self.pending.set(app),
+            Ok(None) => {}
+            Err(None) => {
+                self.finish();
+                return false;
+            }
+            Err(Some((app_flash, err))) => self.report(app_flash, Err(err)),
+        }
+        true
+    }
+
+    /// Advance past the next TBF in flash. Returns the application if it is
+    /// one that should be loaded, `Ok(None)` for padding and disabled apps,
+    /// `Err(None)` at the end of the applications in flash, and the error for
+    /// an application that cannot be loaded.
+    fn discover_app(
+        &self,
+    ) -> Result<Option<PendingApp>, Option<(&'static [u8], ProcessLoadError)>> {
+        let remaining_flash = self.remaining_flash.get();
+        let test_header_slice: &'static [u8; 8] = remaining_flash
+            .get(0..8)
+            .and_then(|s| s.try_into().ok())
+            .ok_or(None)?;
+
+        let (version, header_length, entry_length) =
+            match tbfheader::parse_tbf_header_lengths(test_header_slice) {
+                Ok((v, hl, el)) => (v, hl, el),
+                // Skip over apps whose header is invalid.
+                Err(tbfheader::InitialTbfParseError::InvalidHeader(entry_length)) => {
+                    (0, 0, entry_length)
+                }
+                Err(tbfheader::InitialTbfParseError::UnableToParse) => return Err(None),
+            };
+
+        let app_flash = remaining_flash.get(0..entry_length as usize).ok_or(None)?;
+        self.remaining_flash
+            .set(remaining_flash.get(app_flash.len()..).ok_or(None)?);
+        if header_length == 0 {
+            return Ok(None);
+        }
+
+        let with_app = |err: ProcessLoadError| Some((app_flash, err));
+        let header_flash = app_flash
+            .get(0..header_length as usize)
+            .ok_or(ProcessLoadError::NotEnoughFlash)
+            .map_err(with_app)?;
+        let tbf_header = tbfheader::parse_tbf_header(header_flash, version)
+            .map_err(|err| with_app(err.into()))?;
+        if !tbf_header.is_app() || !tbf_header.enabled() {
+            return Ok(None);
+        }
+
+        let binary_end = tbf_header.get_binary_end() as usize;
+        Ok(Some(PendingApp {
+            app_flash: app_flash,
+            header_length: header_length as usize,
+            version: version,
+            integrity_region: app_flash
+                .get(0..binary_end)
+                .ok_or(ProcessLoadError::NotEnoughFlash)
+                .map_err(with_app)?,
+            footers: app_flash
+                .get(binary_end..)
+                .ok_or(ProcessLoadError::NotEnoughFlash)
+                .map_err(with_app)?,
+        }))
+    }
+
+    /// Pass the next credentials of the pending application to the checker.
+    /// Once the footers are exhausted, the application is loaded if the
+    /// checker does not require credentials.
+    fn check_next_footer(&self) {
+        let mut app = match self.pending.take() {
+            Some(app) => app,
+            None => return,
+        };
+        if app.footers.len() == 0 {
+            if self.checker.require_credentials() {
+                self.report(app.app_flash, Err(ProcessLoadError::CredentialsNotAccepted));
+            } else {
+                self.load(app);
+            }
+            return;
+        }
+
+        let credentials = match tbfheader::parse_tbf_footer(app.footers) {
+            Ok((credentials, footer_len)) => {
+                app.footers = app.footers.get(footer_len as usize..).unwrap_or(&[]);
+                credentials
+            }
+            Err(err) => {
+                self.report(app.app_flash, Err(err.into()));
+                return;
+            }
+        };
+        self.pending.set(app);
+
+        if let Some(credentials) = credentials {
+            // Set before the call, as the checker may finish within it.
+            self.checking.set(true);
+            if self
+                .checker
+                .check_credentials(credentials, app.integrity_region)
+                .is_err()
+            {
+                // The checker cannot check these credentials: `run()` tries
+                // the next ones.
+                self.checking.set(false);
+            }
+        }
+    }
+
+    fn handle_check_result(&self, result: Result<CheckResult, ReturnCode>) {
+        match result {
+            Ok(CheckResult::Accept) => {
+                self.pending.take().map(|app| self.load(app));
+            }
+            Ok(CheckResult::Reject) => {
+                self.pending.take().map(|app| {
+                    self.report(app.app_flash, Err(ProcessLoadError::CredentialsRejected));
+                });
+            }
+            // `run()` continues with the next credentials of the pending
+            // application.
+            Ok(CheckResult::Pass) | Err(_) => {}
+        }
+    }
+
+    /// Create the process for an application whose credentials were
+    /// approved.
+    fn load(&self, app: PendingApp) {
+        let remaining_memory = match self.remaining_memory.take() {
+            Some(remaining_memory) => remaining_memory,
+            None => return,
+        };
+        let index = self.index.get();
+
+        let result = unsafe {
+            Process::create(
+                self.kernel,
+                self.chip,
+                app.app_flash,
+                app.header_length,
+                app.version,
+                remaining_memory,
+                self.fault_response,
+                index,
+            )
+        };
+        match result {
+            Ok((process_option, unused_memory)) => {
+                self.remaining_memory.replace(unused_memory);
+                process_option.map(|process| {
+                    if config::CONFIG.debug_load_processes {
+                        debug!(
+                            "Loaded process[{}] from flash={:#010X}-{:#010X} = {:?}",
+                            index,
+                            app.app_flash.as_ptr() as usize,
+                            app.app_flash.as_ptr() as usize + app.app_flash.len() - 1,
+                            process.get_process_name()
+                        );
+                    }
+                    self.kernel
+                        .with_process_slots(|procs| procs[index].set(Some(process)));
+                    self.index.set(index + 1);
+                    self.report(app.app_flash, Ok(()));
+                });
+                // No process is created for padding or a disabled app. Like
+                // `discover_app()`, skip it without reporting to the client.
+            }
+            // The memory given to `create()` is lost, so no further processes
+            // can be loaded. `next_app()` will finish.
+            Err(err) => self.report(app.app_flash, Err(err)),
+        }
+    }
+}
+
+impl<'a, C: 'static + Chip> AsyncAppCredentialsCheckerClient<'a> for ProcessLoader<'a, C> {
+    fn check_done(
+        &self,
+        result: Result<CheckResult, ReturnCode>,
+        _credentials: TbfFooterV2Credentials,
+    ) {
+        self.checking.set(false);
+        self.check_result.set(result);
+        self.run();
+    }
+}
@@ -4,20 +4,23 @@
 //! different scheduler implementations.
 
 pub(crate) mod cooperative;
+pub(crate) mod edf;
 pub(crate) mod mlfq;
 pub(crate) mod priority;
 pub(crate) mod round_robin;
 
 use core::cell::Cell;
+use core::cmp;
 use core::ptr::NonNull;
 
 use crate::callback::{AppId, Callback, CallbackId};
 use crate::capabilities;
-use crate::common::cells::NumericCellExt;
+use crate::common::cells::{NumericCellExt, OptionalCell};
 use crate::common::dynamic_deferred_call::DynamicDeferredCall;
 use crate::config;
 use crate::debug;
 use crate::grant::Grant;
+use crate::introspection;
 use crate::ipc;
 use crate::memop;
 use crate::platform::mpu::MPU;
@@ -112,6 +115,13 @@ pub enum SchedulingDecision {
     /// cooperatively.
     RunProcess((AppId, Option<u32>)),
 
+    /// Tell the kernel to run the specified process, which has a deadline
+    /// `deadline_us` microseconds from now, for at most `budget_us`
+    /// microseconds: `RunProcessWithDeadline((appid, budget_us,
+    /// deadline_us))`. If the process is still running at its deadline, the
+    /// scheduler is told that the process missed it.
+    RunProcessWithDeadline((AppId, u32, u32)),
+
     /// Tell the kernel to go to sleep. Notably, if the scheduler asks the
     /// kernel to sleep when kernel tasks are ready, the kernel will not sleep,
     /// and will instead restart the main loop and call `next()` again.
@@ -124,8 +134,10 @@ pub struct Kernel {
     /// outstanding callbacks and processes in the Running state.
     work: Cell<usize>,
 
-    /// This holds a pointer to the static array of Process pointers.
-    processes: &'static [Option<&'static dyn process::ProcessType>],
+    /// This holds a pointer to the static array of Process pointers. The
+    /// slots are cells so that processes can be added and removed after boot
+    /// through `with_process_slots()` while the kernel uses the array.
+    processes: &'static [Cell<Option<&'static dyn process::ProcessType>>],
 
     /// A counter which keeps track of how many process identifiers have been
     /// created. This is used to create new unique identifiers for processes.
@@ -141,6 +153,15 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+
+    /// How long the kernel has been awake and asleep.
+    time: introspection::KernelTime,
+
+    /// Whether the kernel loop has stopped tickling the hardware watchdog.
+    watchdog_starved: Cell<bool>,
+
+    /// The message-passing IPC driver, which is told when processes restart.
+    ipc_messaging: OptionalCell<&'static ipc::IPCMessaging>,
 }
 
 /// Enum used to inform scheduler why a process stopped executing (aka why
@@ -160,6 +181,11 @@ pub enum StoppedExecutingReason {
     /// The process was preempted because its timeslice expired.
     TimesliceExpired,
 
+    /// The process was preempted because it reached its deadline before it
+    /// ran out of work. Only returned for processes run with
+    /// `SchedulingDecision::RunProcessWithDeadline`.
+    DeadlineMissed,
+
     /// The process returned because it was preempted by the kernel. This can
     /// mean that kernel work became ready (most likely because an interrupt
     /// fired and the kernel thread needs to execute the bottom half of the
@@ -169,16 +195,64 @@ pub enum StoppedExecutingReason {
 }
 
 impl Kernel {
-    pub fn new(processes: &'static [Option<&'static dyn process::ProcessType>]) -> Kernel {
+    pub fn new(processes: &'static mut [Option<&'static dyn process::ProcessType>]) -> Kernel {
         Kernel {
             work: Cell::new(0),
-            processes,
+            processes: Cell::from_mut(processes).as_slice_of_cells(),
             process_identifier_max: Cell::new(0),
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            time: introspection::KernelTime::new(),
+            watchdog_starved: Cell::new(false),
+            ipc_messaging: OptionalCell::empty(),
         }
     }
 
+    /// Give the kernel a free-running clock to measure how long it is awake
+    /// and asleep, which `introspection::KernelInfo` reports.
+    pub fn set_clock(
+        &self,
+        clock: &'static dyn introspection::KernelClock,
+        _capability: &dyn capabilities::MainLoopCapability,
+    ) {
+        self.time.set_clock(clock);
+    }
+
+    /// Tell the message-passing IPC driver when processes restart, so that it
+    /// can notify their clients.
+    pub fn set_ipc_messaging(
+        &self,
+        ipc_messaging: &'static ipc::IPCMessaging,
+        _capability: &dyn capabilities::MainLoopCapability,
+    ) {
+        self.ipc_messaging.set(ipc_messaging);
+    }
+
+    /// Called by a process once it has been reset to start over.
+    pub(crate) fn process_restarted(&self, process: &dyn process::ProcessType) {
+        self.ipc_messaging
+            .map(|ipc_messaging| ipc_messaging.service_restarted(process));
+    }
+
+    pub(crate) fn time(&self) -> &introspection::KernelTime {
+        &self.time
+    }
+
+    /// Stop tickling the hardware watchdog, or start again. While the
+    /// watchdog is starved it keeps running when the chip sleeps, so it
+    /// resets the board unless tickling resumes before it expires.
+    pub fn starve_watchdog(
+        &self,
+        starve: bool,
+        _capability: &dyn capabilities::WatchdogCapability,
+    ) {
+        self.watchdog_starved.set(starve);
+    }
+
+    pub(crate) fn watchdog_starved(&self) -> bool {
+        self.watchdog_starved.get()
+    }
+
     /// Something was scheduled for a process, so there is more work to do.
     ///
     /// This is only exposed in the core kernel crate.
@@ -252,7 +326,7 @@ impl Kernel {
             .map_or(None, |process_entry| {
                 // Check if there is any process state here, or if the entry is
                 // `None`.
-                process_entry.map_or(None, |process| {
+                process_entry.get().map_or(None, |process| {
                     // Check that the process stored here matches the identifier
                     // in the `appid`.
                     if process.appid() == appid {
@@ -272,9 +346,9 @@ impl Kernel {
         F: Fn(&dyn process::ProcessType),
     {
         for process in self.processes.iter() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    closure(*p);
+                    closure(p);
                 }
                 None => {}
             }
@@ -285,13 +359,15 @@ impl Kernel {
     pub(crate) fn get_process_iter(
         &self,
     ) -> core::iter::FilterMap<
-        core::slice::Iter<Option<&dyn process::ProcessType>>,
-        fn(&Option<&'static dyn process::ProcessType>) -> Option<&'static dyn process::ProcessType>,
+        core::slice::Iter<Cell<Option<&'static dyn process::ProcessType>>>,
+        fn(
+            &Cell<Option<&'static dyn process::ProcessType>>,
+        ) -> Option<&'static dyn process::ProcessType>,
     > {
         fn keep_some(
-            &x: &Option<&'static dyn process::ProcessType>,
+            x: &Cell<Option<&'static dyn process::ProcessType>>,
         ) -> Option<&'static dyn process::ProcessType> {
-            x
+            x.get()
         }
         self.processes.iter().filter_map(keep_some)
     }
@@ -310,9 +386,9 @@ impl Kernel {
         F: Fn(&dyn process::ProcessType),
     {
         for process in self.processes.iter() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    closure(*p);
+                    closure(p);
                 }
                 None => {}
             }
@@ -328,9 +404,9 @@ impl Kernel {
         F: Fn(&dyn process::ProcessType) -> ReturnCode,
     {
         for process in self.processes.iter() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    let ret = closure(*p);
+                    let ret = closure(p);
                     if ret != ReturnCode::FAIL {
                         return ret;
                     }
@@ -341,20 +417,18 @@ impl Kernel {
         ReturnCode::FAIL
     }
 
-    /// Retrieve the `AppId` of the given app based on its identifier. This is
-    /// useful if an app identifier is passed to the kernel from somewhere (such
-    /// as from userspace) and needs to be expanded to a full `AppId` for use
-    /// with other APIs.
-    pub(crate) fn lookup_app_by_identifier(&self, identifier: usize) -> Option<AppId> {
-        self.processes.iter().find_map(|&p| {
-            p.map_or(None, |p2| {
-                if p2.appid().id() == identifier {
-                    Some(p2.appid())
-                } else {
-                    None
-                }
-            })
-        })
+    /// Run a closure on the slots of the processes array. This is how
+    /// processes are added to the array, at boot or at runtime, and removed
+    /// from it again.
+    ///
+    /// The slots are only lent for the duration of the closure. Process
+    /// identifiers are tied to their slot index, so a process must not be
+    /// moved to a different slot.
+    pub(crate) fn with_process_slots<F, R>(&self, closure: F) -> R
+    where
+        F: FnOnce(&[Cell<Option<&'static dyn process::ProcessType>>]) -> R,
+    {
+        closure(self.processes)
     }
 
     /// Checks if the provided `AppId` is still valid given the processes stored
@@ -365,7 +439,8 @@ impl Kernel {
     /// verify that the referenced app is still at the correct index.
     pub(crate) fn appid_is_valid(&self, appid: &AppId) -> bool {
         self.processes.get(appid.index).map_or(false, |p| {
-            p.map_or(false, |process| process.appid().id() == appid.id())
+            p.get()
+                .map_or(false, |process| process.appid().id() == appid.id())
         })
     }
 
@@ -444,75 +519,122 @@ impl Kernel {
     /// apps.
     pub fn hardfault_all_apps<C: capabilities::ProcessManagementCapability>(&self, _c: &C) {
         for p in self.processes.iter() {
-            p.map(|process| {
-                process.set_fault_state();
+            p.get().map(|process| {
+                process.set_fault_state(process::FaultReason::Forced);
             });
         }
     }
 
-    /// Main loop of the OS.
+    /// Perform one iteration of the core Tock kernel loop.
     ///
-    /// Most of the behavior of this loop is controlled by the `Scheduler`
-    /// implementation in use.
-    pub fn kernel_loop<P: Platform, C: Chip, SC: Scheduler<C>>(
+    /// This function is responsible for three main operations:
+    ///
+    /// 1. Check if the kernel itself has any work to be done and if the
+    ///    scheduler wants to complete that work now. If so, it allows the
+    ///    kernel to run.
+    /// 2. Check if any processes have any work to be done, and if so if the
+    ///    scheduler wants to allow any processes to run now, and if so which
+    ///    one.
+    /// 3. After ensuring the scheduler does not want to complete any kernel or
+    ///    process work (or there is no work to be done), and there are no
+    ///    outstanding interrupts to handle, put the chip to sleep.
+    ///
+    /// This function has one configuration option: `no_sleep`. If that
+    /// argument is set to true, the kernel will never attempt to put the chip
+    /// to sleep, and this function can be called again immediately. This is
+    /// useful when the kernel is driven by a test harness rather than running
+    /// forever in `kernel_loop()`.
+    pub fn kernel_loop_operation<P: Platform, C: Chip, SC: Scheduler<C>>(
         &self,
         platform: &P,
         chip: &C,
         ipc: Option<&ipc::IPC>,
         scheduler: &SC,
+        no_sleep: bool,
         _capability: &dyn capabilities::MainLoopCapability,
-    ) -> ! {
-        chip.watchdog().setup();
-        loop {
-            chip.watchdog().tickle();
-            unsafe {
-                // Ask the scheduler if we should do tasks inside of the kernel,
-                // such as handle interrupts. A scheduler may want to prioritize
-                // processes instead, or there may be no kernel work to do.
-                match scheduler.do_kernel_work_now(chip) {
-                    true => {
-                        // Execute kernel work. This includes handling
-                        // interrupts and is how code in the chips/ and capsules
-                        // crates is able to execute.
-                        scheduler.execute_kernel_work(chip);
-                    }
-                    false => {
-                        // No kernel work ready, so ask scheduler for a process.
-                        match scheduler.next(self) {
-                            SchedulingDecision::RunProcess((appid, timeslice_us)) => {
-                                self.process_map_or((), appid, |process| {
-                                    let (reason, time_executed) = self.do_process(
-                                        platform,
-                                        chip,
-                                        scheduler,
-                                        process,
-                                        ipc,
-                                        timeslice_us,
-                                    );
-                                    scheduler.result(reason, time_executed);
-                                });
+    ) {
+        self.time.update();
+        unsafe {
+            // Ask the scheduler if we should do tasks inside of the kernel,
+            // such as handle interrupts. A scheduler may want to prioritize
+            // processes instead, or there may be no kernel work to do.
+            match scheduler.do_kernel_work_now(chip) {
+                true => {
+                    // Execute kernel work. This includes handling
+                    // interrupts and is how code in the chips/ and capsules
+                    // crates is able to execute.
+                    scheduler.execute_kernel_work(chip);
+                }
+                false => {
+                    // No kernel work ready, so ask scheduler for a process.
+                    match scheduler.next(self) {
+                        SchedulingDecision::RunProcess((appid, timeslice_us)) => {
+                            self.process_map_or((), appid, |process| {
+                                let (reason, time_executed) = self.do_process(
+                                    platform,
+                                    chip,
+                                    scheduler,
+                                    process,
+                                    ipc,
+                                    timeslice_us,
+                                );
+                                time_executed.map(|time| process.debug_cpu_time_add(time));
+                                scheduler.result(reason, time_executed);
+                            });
+                        }
+                        SchedulingDecision::RunProcessWithDeadline((
+                            appid,
+                            budget_us,
+                            deadline_us,
+                        )) => {
+                            self.process_map_or((), appid, |process| {
+                                // The timeslice ends at the deadline, unless
+                                // the budget runs out first.
+                                let (mut reason, time_executed) = self.do_process(
+                                    platform,
+                                    chip,
+                                    scheduler,
+                                    process,
+                                    ipc,
+                                    Some(cmp::min(budget_us, deadline_us)),
+                                );
+                                if reason == StoppedExecutingReason::TimesliceExpired
+                                    && deadline_us <= budget_us
+                                {
+                                    reason = StoppedExecutingReason::DeadlineMissed;
+                                }
+                                time_executed.map(|time| process.debug_cpu_time_add(time));
+                                scheduler.result(reason, time_executed);
+                            });
+                        }
+                        SchedulingDecision::TrySleep => {
+                            if no_sleep {
+                                return;
                             }
-                            SchedulingDecision::TrySleep => {
-                                chip.atomic(|| {
-                                    // Cannot sleep if interrupts are pending,
-                                    // as on most platforms unhandled interrupts
-                                    // will wake the device. Also, if the only
-                                    // pending interrupt occurred after the
-                                    // scheduler decided to put the chip to
-                                    // sleep, but before this atomic section
-                                    // starts, the interrupt will not be
-                                    // serviced and the chip will never wake
-                                    // from sleep.
-                                    if !chip.has_pending_interrupts()
-                                        && !DynamicDeferredCall::global_instance_calls_pending()
-                                            .unwrap_or(false)
-                                    {
+                            chip.atomic(|| {
+                                // Cannot sleep if interrupts are pending,
+                                // as on most platforms unhandled interrupts
+                                // will wake the device. Also, if the only
+                                // pending interrupt occurred after the
+                                // scheduler decided to put the chip to
+                                // sleep, but before this atomic section
+                                // starts, the interrupt will not be
+                                // serviced and the chip will never wake
+                                // from sleep.
+                                if !chip.has_pending_interrupts()
+                                    && !DynamicDeferredCall::global_instance_calls_pending()
+                                        .unwrap_or(false)
+                                {
+                                    let starved = self.watchdog_starved.get();
+                                    if !starved {
                                         chip.watchdog().suspend();
-                                        chip.sleep();
+                                    }
+                                    self.time.sleep(|| chip.sleep());
+                                    if !starved {
                                         chip.watchdog().resume();
                                     }
-                                });
-                            }
+                                }
+                            });
                         }
                     }
                 }
@@ -520,6 +642,27 @@ impl Kernel {
         }
     }
 
+    /// Main loop of the OS.
+    ///
+    /// Most of the behavior of this loop is controlled by the `Scheduler`
+    /// implementation in use.
+    pub fn kernel_loop<P: Platform, C: Chip, SC: Scheduler<C>>(
+        &self,
+        platform: &P,
+        chip: &C,
+        ipc: Option<&ipc::IPC>,
+        scheduler: &SC,
+        capability: &dyn capabilities::MainLoopCapability,
+    ) -> ! {
+        chip.watchdog().setup();
+        loop {
+            if !self.watchdog_starved.get() {
+                chip.watchdog().tickle();
+            }
+            self.kernel_loop_operation(platform, chip, ipc, scheduler, false, capability);
+        }
+    }
+
     /// Transfer control from the kernel to a userspace process.
     ///
     /// This function is called by the main kernel loop to run userspace code.
@@ -624,7 +767,10 @@ impl Kernel {
                     match context_switch_reason {
                         Some(ContextSwitchReason::Fault) => {
                             // Let process deal with it as appropriate.
-                            process.set_fault_state();
+                            process.set_fault_state(process::FaultReason::HardwareFault);
+                        }
+                        Some(ContextSwitchReason::InvalidSyscall) => {
+                            process.set_fault_state(process::FaultReason::InvalidSyscall);
                         }
                         Some(ContextSwitchReason::SyscallFired { syscall }) => {
                             process.debug_syscall_called(syscall);
@@ -817,7 +963,7 @@ impl Kernel {
                             // Something went wrong when switching to this
                             // process. Indicate this by putting it in a fault
                             // state.
-                            process.set_fault_state();
+                            process.set_fault_state(process::FaultReason::ContextSwitchFailed);
                         }
                     }
                 }
@@ -0,0 +1,377 @@
+//! Real-time scheduler for Tock: earliest deadline first or rate monotonic.
+//!
+//! Processes with real-time requirements run periodically. Each has a period,
+//! a relative deadline and a budget. At the start of each period the process
+//! gets its budget of CPU time, which it must use before the deadline: a
+//! process that still has work to do when its deadline passes misses the
+//! deadline. A process that used its budget is not scheduled again until its
+//! next period starts, so a misbehaving process cannot take CPU time from the
+//! others.
+//!
+//! Among the ready processes that have budget left and whose deadline has not
+//! passed, the scheduler runs:
+//!
+//! - with `SchedulingPolicy::EarliestDeadlineFirst`, the process whose
+//!   deadline is closest.
+//! - with `SchedulingPolicy::RateMonotonic`, the process with the shortest
+//!   period.
+//!
+//! Ties go to the process that comes first in the processes array. A process
+//! that becomes ready while a lower priority process runs (for example
+//! because of IPC) preempts it.
+//!
+//! Processes without real-time requirements run round-robin, with a fixed
+//! timeslice, when no real-time process can run.
+//!
+//! Real-time requirements come from the board, which can give them for
+//! processes by name, and otherwise from the `Realtime` element of the TBF
+//! header of the process. Periods start when the scheduler first sees a
+//! process, and start over when the process restarts.
+//!
+//! Deadline misses are reported to the scheduler as
+//! `StoppedExecutingReason::DeadlineMissed` when the deadline passes while the
+//! process runs, and are counted for each process. `KernelInfo` provides the
+//! counts.
+
+use crate::callback::AppId;
+use crate::common::cells::OptionalCell;
+use crate::common::dynamic_deferred_call::DynamicDeferredCall;
+use crate::common::list::{List, ListLink, ListNode};
+use crate::hil::time::{self, Frequency, Ticks};
+use crate::platform::Chip;
+use crate::process::ProcessType;
+use crate::sched::{
+    Kernel, Scheduler, SchedulingDecision, StoppedExecutingReason, MIN_QUANTA_THRESHOLD_US,
+};
+use core::cell::Cell;
+use core::ptr;
+
+/// Timing requirements of a real-time process. All values are in
+/// microseconds, and `MIN_BUDGET_US <= budget_us <= deadline_us <= period_us`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RealtimeParameters {
+    /// How often the process has work to do.
+    pub period_us: u32,
+    /// Time from the start of each period by which the work must be done.
+    pub deadline_us: u32,
+    /// CPU time the process may use in each period.
+    pub budget_us: u32,
+}
+
+impl RealtimeParameters {
+    /// The smallest budget. The kernel does not start a process for a
+    /// timeslice of `MIN_QUANTA_THRESHOLD_US` or less, so a process with a
+    /// smaller budget would never run.
+    pub const MIN_BUDGET_US: u32 = MIN_QUANTA_THRESHOLD_US + 1;
+
+    /// Whether the requirements can be met by the scheduler.
+    pub fn is_valid(&self) -> bool {
+        self.budget_us >= Self::MIN_BUDGET_US
+            && self.budget_us <= self.deadline_us
+            && self.deadline_us <= self.period_us
+    }
+}
+
+/// How the scheduler orders real-time processes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedulingPolicy {
+    /// The process with the closest deadline runs first.
+    EarliestDeadlineFirst,
+    /// The process with the shortest period runs first.
+    RateMonotonic,
+}
+
+/// Nodes store per-process state
+pub struct EDFProcessNode<'a> {
+    proc: &'static Option<&'static dyn ProcessType>,
+    /// The process the state below belongs to. The state is reset when the
+    /// slot holds another process, or the process restarted.
+    appid: OptionalCell<AppId>,
+    parameters: Cell<Option<RealtimeParameters>>,
+    /// Alarm ticks at the start of the current period.
+    release: Cell<u32>,
+    /// CPU time used in the current period.
+    used_us: Cell<u32>,
+    /// Whether the deadline of the current period has passed.
+    deadline_passed: Cell<bool>,
+    next: ListLink<'a, EDFProcessNode<'a>>,
+}
+
+impl<'a> EDFProcessNode<'a> {
+    pub fn new(proc: &'static Option<&'static dyn ProcessType>) -> EDFProcessNode<'a> {
+        EDFProcessNode {
+            proc,
+            appid: OptionalCell::empty(),
+            parameters: Cell::new(None),
+            release: Cell::new(0),
+            used_us: Cell::new(0),
+            deadline_passed: Cell::new(false),
+            next: ListLink::empty(),
+        }
+    }
+}
+
+impl<'a> ListNode<'a, EDFProcessNode<'a>> for EDFProcessNode<'a> {
+    fn next(&'a self) -> &'static ListLink<'a, EDFProcessNode<'a>> {
+        &self.next
+    }
+}
+
+pub struct EDFSched<'a, A: 'static + time::Alarm<'static>> {
+    alarm: &'static A,
+    policy: SchedulingPolicy,
+    /// Real-time requirements set by the board for processes by name. These
+    /// take precedence over those in TBF headers.
+    board_parameters: &'static [(&'static str, RealtimeParameters)],
+    pub processes: List<'a, EDFProcessNode<'a>>,
+    running: OptionalCell<&'a EDFProcessNode<'a>>,
+}
+
+impl<'a, A: 'static + time::Alarm<'static>> EDFSched<'a, A> {
+    /// How long a process without real-time requirements can run before
+    /// being preempted.
+    pub const BEST_EFFORT_TIMESLICE_US: u32 = 10000;
+
+    /// Panics if the board gives invalid requirements for a process.
+    pub fn new(
+        alarm: &'static A,
+        policy: SchedulingPolicy,
+        board_parameters: &'static [(&'static str, RealtimeParameters)],
+    ) -> Self {
+        for (name, parameters) in board_parameters.iter() {
+            if !parameters.is_valid() {
+                panic!("Invalid real-time requirements for process {}", name);
+            }
+        }
+        Self {
+            alarm,
+            policy,
+            board_parameters,
+            processes: List::new(),
+            running: OptionalCell::empty(),
+        }
+    }
+
+    fn ticks_to_us(ticks: A::Ticks) -> u32 {
+        let us = ticks.into_u32() as u64 * 1_000_000 / A::Frequency::frequency() as u64;
+        if us > u32::MAX as u64 {
+            u32::MAX
+        } else {
+            us as u32
+        }
+    }
+
+    /// Bring the state of `node` up to date at time `now`: reset it if the
+    /// slot holds a new process, note a passed deadline, and start the
+    /// current period.
+    fn update(&self, node: &EDFProcessNode<'a>, now: A::Ticks) {
+        let process = match node.proc {
+            Some(process) => process,
+            None => {
+                node.appid.clear();
+                node.parameters.set(None);
+                return;
+            }
+        };
+
+        let appid = process.appid();
+        if !node.appid.contains(&appid) {
+            let name = process.get_process_name();
+            let parameters = self
+                .board_parameters
+                .iter()
+                .find(|(process_name, _)| *process_name == name)
+                .map(|(_, parameters)| *parameters)
+                .or_else(|| process.get_realtime_parameters());
+            node.appid.set(appid);
+            node.parameters.set(parameters);
+            node.release.set(now.into_u32());
+            node.used_us.set(0);
+            node.deadline_passed.set(false);
+            return;
+        }
+
+        let parameters = match node.parameters.get() {
+            Some(parameters) => parameters,
+            None => return,
+        };
+        let release = A::Ticks::from(node.release.get());
+        let elapsed = now.wrapping_sub(release);
+        if elapsed >= A::ticks_from_us(parameters.deadline_us) && !node.deadline_passed.get() {
+            node.deadline_passed.set(true);
+            if process.ready() {
+                process.debug_deadline_missed();
+            }
+        }
+        let period = A::ticks_from_us(parameters.period_us).into_u32().max(1);
+        if elapsed.into_u32() >= period {
+            // Skip to the period `now` is in.
+            let periods = elapsed.into_u32() / period;
+            node.release.set(
+                release
+                    .wrapping_add(A::Ticks::from(periods * period))
+                    .into_u32(),
+            );
+            node.used_us.set(0);
+            node.deadline_passed.set(false);
+        }
+    }
+
+    /// Whether the real-time process of `node` can run now.
+    fn is_eligible(&self, node: &EDFProcessNode<'a>) -> bool {
+        node.parameters.get().map_or(false, |parameters| {
+            !node.deadline_passed.get()
+                && parameters.budget_us.saturating_sub(node.used_us.get()) > MIN_QUANTA_THRESHOLD_US
+                && node.proc.map_or(false, |process| process.ready())
+        })
+    }
+
+    /// Ticks from `now` until the deadline of the current period.
+    fn until_deadline(&self, node: &EDFProcessNode<'a>, now: A::Ticks) -> A::Ticks {
+        let deadline = node.parameters.get().map_or(0, |p| p.deadline_us);
+        let elapsed = now.wrapping_sub(A::Ticks::from(node.release.get()));
+        A::ticks_from_us(deadline).wrapping_sub(elapsed)
+    }
+
+    /// Priority of the real-time process of `node`. Lower values run first.
+    fn priority(&self, node: &EDFProcessNode<'a>, now: A::Ticks) -> u32 {
+        match self.policy {
+            SchedulingPolicy::EarliestDeadlineFirst => {
+                Self::ticks_to_us(self.until_deadline(node, now))
+            }
+            SchedulingPolicy::RateMonotonic => node.parameters.get().map_or(0, |p| p.period_us),
+        }
+    }
+
+    /// Set the alarm for the next time a ready real-time process has to be
+    /// looked at: when its deadline passes or, if it cannot run now, when its
+    /// next period starts.
+    fn arm(&self, now: A::Ticks) {
+        let next = self
+            .processes
+            .iter()
+            .filter(|node| node.proc.map_or(false, |process| process.ready()))
+            .filter_map(|node| {
+                let parameters = node.parameters.get()?;
+                if self.is_eligible(node) {
+                    Some(self.until_deadline(node, now))
+                } else {
+                    let elapsed = now.wrapping_sub(A::Ticks::from(node.release.get()));
+                    Some(A::ticks_from_us(parameters.period_us).wrapping_sub(elapsed))
+                }
+            })
+            .min();
+        match next {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Move `node` to the tail of the list, so that other best-effort
+    /// processes run before it again.
+    fn move_to_tail(&self, node: &'a EDFProcessNode<'a>) {
+        while let Some(head) = self.processes.pop_head() {
+            self.processes.push_tail(head);
+            if ptr::eq(head, node) {
+                break;
+            }
+        }
+    }
+}
+
+impl<'a, A: 'static + time::Alarm<'static>, C: Chip> Scheduler<C> for EDFSched<'a, A> {
+    fn next(&self, kernel: &Kernel) -> SchedulingDecision {
+        let now = self.alarm.now();
+        for node in self.processes.iter() {
+            self.update(node, now);
+        }
+        self.arm(now);
+
+        if kernel.processes_blocked() {
+            // No processes ready
+            return SchedulingDecision::TrySleep;
+        }
+
+        let realtime = self
+            .processes
+            .iter()
+            .filter(|node| self.is_eligible(node))
+            .min_by_key(|node| self.priority(node, now));
+        if let Some(node) = realtime {
+            let parameters = node.parameters.get().unwrap(); // Eligible nodes have parameters.
+            let appid = node.proc.unwrap().appid(); // Eligible nodes have a process.
+            self.running.set(node);
+            return SchedulingDecision::RunProcessWithDeadline((
+                appid,
+                parameters.budget_us - node.used_us.get(),
+                Self::ticks_to_us(self.until_deadline(node, now)),
+            ));
+        }
+
+        let best_effort = self.processes.iter().find(|node| {
+            node.parameters.get().is_none() && node.proc.map_or(false, |process| process.ready())
+        });
+        match best_effort {
+            Some(node) => {
+                self.running.set(node);
+                SchedulingDecision::RunProcess((
+                    node.proc.unwrap().appid(),
+                    Some(Self::BEST_EFFORT_TIMESLICE_US),
+                ))
+            }
+            // The ready processes have used their budget, or their deadline
+            // has passed. The alarm wakes the kernel up for their next
+            // period.
+            None => SchedulingDecision::TrySleep,
+        }
+    }
+
+    fn result(&self, result: StoppedExecutingReason, execution_time_us: Option<u32>) {
+        self.running.take().map(|node| {
+            if node.parameters.get().is_some() {
+                node.used_us.set(
+                    node.used_us
+                        .get()
+                        .saturating_add(execution_time_us.unwrap_or(0)),
+                );
+                if result == StoppedExecutingReason::DeadlineMissed && !node.deadline_passed.get() {
+                    node.deadline_passed.set(true);
+                    node.proc.map(|process| process.debug_deadline_missed());
+                }
+            } else if result != StoppedExecutingReason::KernelPreemption {
+                self.move_to_tail(node);
+            }
+        });
+    }
+
+    unsafe fn continue_process(&self, _: AppId, chip: &C) -> bool {
+        if chip.has_pending_interrupts()
+            || DynamicDeferredCall::global_instance_calls_pending().unwrap_or(false)
+        {
+            return false;
+        }
+        // Also stop if a higher priority process has become ready, as a
+        // syscall of this process can make another process ready.
+        let now = self.alarm.now();
+        self.running.map_or(true, |running| {
+            let running_priority = running
+                .parameters
+                .get()
+                .map(|_| self.priority(running, now));
+            !self.processes.iter().any(|node| {
+                !ptr::eq(*running, node)
+                    && self.is_eligible(node)
+                    && running_priority.map_or(true, |priority| self.priority(node, now) < priority)
+            })
+        })
+    }
+}
+
+impl<'a, A: 'static + time::Alarm<'static>> time::AlarmClient for EDFSched<'a, A> {
+    fn alarm(&self) {
+        // The alarm only wakes the kernel up, so that `next()` looks at the
+        // processes again.
+    }
+}
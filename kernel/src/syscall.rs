@@ -56,6 +56,8 @@ pub enum ContextSwitchReason {
     SyscallFired { syscall: Syscall },
     /// Process triggered the hardfault handler.
     Fault,
+    /// Process called a system call that does not exist.
+    InvalidSyscall,
     /// Process interrupted (e.g. by a hardware event)
     Interrupted,
 }
@@ -150,6 +152,15 @@ pub trait UserspaceKernelBoundary {
         state: &mut Self::StoredState,
     ) -> (*mut usize, ContextSwitchReason);
 
+    /// Return the program counter of a process identified by its stack
+    /// pointer, e.g. the address of the instruction that faulted. The kernel
+    /// only calls this if the stack pointer points to process memory.
+    unsafe fn get_process_pc(
+        &self,
+        stack_pointer: *const usize,
+        state: &Self::StoredState,
+    ) -> usize;
+
     /// Display architecture specific (e.g. CPU registers or status flags) data
     /// for a process identified by its stack pointer.
     unsafe fn print_context(
@@ -0,0 +1,109 @@
+//! System call filters that enforce the permissions declared by processes.
+//!
+//! Processes can list the drivers and commands they need in a permissions TLV
+//! in their TBF header. The filters in this module implement
+//! `Platform::filter_syscall()` based on those permissions, so that a
+//! compromised process cannot use drivers it did not declare (e.g. `AppFlash`
+//! or `Ipc`). A declared driver may be used through subscribe and allow, and
+//! through the commands whose numbers are set in the permission mask. Note
+//! that command 0 (checking whether a driver exists) must be declared as
+//! well. Memory operations are always allowed.
+//!
+//! The two filters differ in how they treat processes that have no
+//! permissions TLV at all: `TbfHeaderFilterDefaultAllow` allows them every
+//! system call, which keeps applications built before permissions existed
+//! working, while `TbfHeaderFilterDefaultDeny` only allows them memory
+//! operations.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! impl Platform for Imix {
+//!     fn filter_syscall(
+//!         &self,
+//!         process: &dyn kernel::procs::ProcessType,
+//!         syscall: &kernel::syscall::Syscall,
+//!     ) -> Result<(), kernel::ReturnCode> {
+//!         use kernel::syscall_filter::SyscallFilter;
+//!         kernel::syscall_filter::TbfHeaderFilterDefaultAllow.filter_syscall(process, syscall)
+//!     }
+//! }
+//! ```
+
+use crate::process::{CommandPermissions, ProcessType};
+use crate::returncode::ReturnCode;
+use crate::syscall::Syscall;
+
+/// A policy deciding which system calls a process may make, suitable for
+/// implementing `Platform::filter_syscall()`.
+pub trait SyscallFilter {
+    /// Return `Ok(())` if `process` may make `syscall`, or the error to
+    /// return to the process otherwise.
+    fn filter_syscall(
+        &self,
+        process: &dyn ProcessType,
+        syscall: &Syscall,
+    ) -> Result<(), ReturnCode>;
+}
+
+/// Enforce TBF header permissions, and allow all system calls for processes
+/// without permissions.
+pub struct TbfHeaderFilterDefaultAllow;
+
+/// Enforce TBF header permissions, and only allow memory operations for
+/// processes without permissions.
+pub struct TbfHeaderFilterDefaultDeny;
+
+/// Check `syscall` against the permissions of `process`. `default` is the
+/// result for processes that did not declare any permissions.
+fn check_permissions(
+    process: &dyn ProcessType,
+    syscall: &Syscall,
+    default: Result<(), ReturnCode>,
+) -> Result<(), ReturnCode> {
+    let (driver_number, command_number) = match *syscall {
+        Syscall::COMMAND {
+            driver_number,
+            subdriver_number,
+            ..
+        } => (driver_number, Some(subdriver_number)),
+        Syscall::SUBSCRIBE { driver_number, .. } | Syscall::ALLOW { driver_number, .. } => {
+            (driver_number, None)
+        }
+        Syscall::YIELD | Syscall::MEMOP { .. } => return Ok(()),
+    };
+
+    let offset = command_number.map_or(0, |command| command / 64);
+    match process.get_command_permissions(driver_number, offset) {
+        CommandPermissions::NoPermsAtAll => default,
+        // Hide drivers the process did not declare.
+        CommandPermissions::NoPermsThisDriver => Err(ReturnCode::ENODEVICE),
+        CommandPermissions::Mask(allowed_commands) => match command_number {
+            Some(command) if (allowed_commands >> (command % 64)) & 1 == 0 => {
+                Err(ReturnCode::ENOSUPPORT)
+            }
+            _ => Ok(()),
+        },
+    }
+}
+
+impl SyscallFilter for TbfHeaderFilterDefaultAllow {
+    fn filter_syscall(
+        &self,
+        process: &dyn ProcessType,
+        syscall: &Syscall,
+    ) -> Result<(), ReturnCode> {
+        check_permissions(process, syscall, Ok(()))
+    }
+}
+
+impl SyscallFilter for TbfHeaderFilterDefaultDeny {
+    fn filter_syscall(
+        &self,
+        process: &dyn ProcessType,
+        syscall: &Syscall,
+    ) -> Result<(), ReturnCode> {
+        check_permissions(process, syscall, Err(ReturnCode::ENODEVICE))
+    }
+}
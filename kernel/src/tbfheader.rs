@@ -8,6 +8,9 @@ use core::fmt;
 use core::iter::Iterator;
 use core::{mem, str};
 
+use crate::process::CommandPermissions;
+use crate::sched::edf::RealtimeParameters;
+
 /// Takes a value and rounds it up to be aligned % 4
 macro_rules! align4 {
     ($e:expr) => {
@@ -100,6 +103,10 @@ impl fmt::Debug for TbfParseError {
 
 // TBF structure
 
+/// Maximum number of driver permissions stored for an app. A permissions TLV
+/// with more entries is rejected.
+pub(crate) const MAX_DRIVER_PERMISSIONS: usize = 8;
+
 /// TBF fields that must be present in all v2 headers.
 #[derive(Clone, Copy, Debug)]
 pub(crate) struct TbfHeaderV2Base {
@@ -117,6 +124,11 @@ pub(crate) enum TbfHeaderTypes {
     TbfHeaderWriteableFlashRegions = 2,
     TbfHeaderPackageName = 3,
     TbfHeaderFixedAddresses = 5,
+    TbfHeaderPermissions = 6,
+    TbfHeaderProgram = 9,
+    TbfHeaderRealtime = 11,
+    TbfHeaderIpcClients = 12,
+    TbfFooterCredentials = 128,
 
     /// Some field in the header that we do not understand. Since the TLV format
     /// specifies the length of each section, if we get a field we do not
@@ -176,6 +188,94 @@ pub(crate) struct TbfHeaderV2FixedAddresses {
     start_process_flash: u32,
 }
 
+/// Permission for a process to use a driver.
+///
+/// Grants access to the subscribe and allow calls of driver `driver_number`,
+/// and to the command calls whose command numbers are set in the
+/// `allowed_commands` bitmask. Bit `i` of the mask allows command number
+/// `offset * 64 + i`, so several entries for the same driver can cover
+/// command numbers above 63.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TbfHeaderDriverPermission {
+    driver_number: u32,
+    offset: u32,
+    allowed_commands: u64,
+}
+
+/// The v2 program section for apps.
+///
+/// This supersedes the main section, adding the offset at which the
+/// application binary ends. Anything between the end of the binary and the
+/// end of the TBF is a sequence of footers (e.g. credentials), which are not
+/// covered by the header checksum.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TbfHeaderV2Program {
+    init_fn_offset: u32,
+    protected_size: u32,
+    minimum_ram_size: u32,
+    /// Offset from the start of the TBF (i.e. including the header) of the
+    /// end of the application binary, and thus of the first footer.
+    binary_end_offset: u32,
+    version: u32,
+}
+
+/// Timing requirements of a process with real-time deadlines, used by the
+/// EDF scheduler. All values are in microseconds.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TbfHeaderV2Realtime {
+    period_us: u32,
+    deadline_us: u32,
+    budget_us: u32,
+}
+
+/// Formats of credentials stored in a TBF footer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TbfFooterV2CredentialsType {
+    /// Space reserved for credentials to be added later, e.g. by a signing
+    /// tool. Carries no credentials and should be passed over by checkers.
+    Reserved = 0,
+    /// SHA-256 hash of the integrity region.
+    SHA256 = 1,
+    /// SHA-384 hash of the integrity region.
+    SHA384 = 2,
+    /// SHA-512 hash of the integrity region.
+    SHA512 = 3,
+    /// HMAC-SHA256 of the integrity region, with a key known to the board.
+    HmacSha256 = 4,
+    /// ECDSA signature (r followed by s) over NIST P-256 of the SHA-256 hash
+    /// of the integrity region.
+    EcdsaNistP256 = 5,
+    /// 3072-bit RSA public key followed by an RSA signature of the SHA-512
+    /// hash of the integrity region.
+    Rsa3072Key = 6,
+    /// 4096-bit RSA public key followed by an RSA signature of the SHA-512
+    /// hash of the integrity region.
+    Rsa4096Key = 7,
+}
+
+/// Credentials for an application, found in a TBF footer.
+///
+/// The credentials cover the integrity region of the application: the TBF
+/// header and the application binary, up to the first footer.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfFooterV2Credentials {
+    format: TbfFooterV2CredentialsType,
+    data: &'static [u8],
+}
+
+impl TbfFooterV2Credentials {
+    /// The format of the credentials, which determines how `data` must be
+    /// interpreted.
+    pub fn format(&self) -> TbfFooterV2CredentialsType {
+        self.format
+    }
+
+    /// The credentials themselves, e.g. the hash or signature.
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+}
+
 // Conversion functions from slices to the various TBF fields.
 
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Base {
@@ -221,6 +321,11 @@ impl core::convert::TryFrom<u16> for TbfHeaderTypes {
             2 => Ok(TbfHeaderTypes::TbfHeaderWriteableFlashRegions),
             3 => Ok(TbfHeaderTypes::TbfHeaderPackageName),
             5 => Ok(TbfHeaderTypes::TbfHeaderFixedAddresses),
+            6 => Ok(TbfHeaderTypes::TbfHeaderPermissions),
+            9 => Ok(TbfHeaderTypes::TbfHeaderProgram),
+            11 => Ok(TbfHeaderTypes::TbfHeaderRealtime),
+            12 => Ok(TbfHeaderTypes::TbfHeaderIpcClients),
+            128 => Ok(TbfHeaderTypes::TbfFooterCredentials),
             _ => Ok(TbfHeaderTypes::Unknown),
         }
     }
@@ -308,18 +413,162 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2FixedAddresses {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderDriverPermission {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderDriverPermission, Self::Error> {
+        Ok(TbfHeaderDriverPermission {
+            driver_number: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            offset: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            allowed_commands: u64::from_le_bytes(
+                b.get(8..16)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Program {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Program, Self::Error> {
+        Ok(TbfHeaderV2Program {
+            init_fn_offset: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            protected_size: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            minimum_ram_size: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            binary_end_offset: u32::from_le_bytes(
+                b.get(12..16)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            version: u32::from_le_bytes(
+                b.get(16..20)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Realtime {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Realtime, Self::Error> {
+        Ok(TbfHeaderV2Realtime {
+            period_us: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            deadline_us: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            budget_us: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl core::convert::TryFrom<u32> for TbfFooterV2CredentialsType {
+    type Error = TbfParseError;
+
+    fn try_from(h: u32) -> Result<TbfFooterV2CredentialsType, Self::Error> {
+        match h {
+            0 => Ok(TbfFooterV2CredentialsType::Reserved),
+            1 => Ok(TbfFooterV2CredentialsType::SHA256),
+            2 => Ok(TbfFooterV2CredentialsType::SHA384),
+            3 => Ok(TbfFooterV2CredentialsType::SHA512),
+            4 => Ok(TbfFooterV2CredentialsType::HmacSha256),
+            5 => Ok(TbfFooterV2CredentialsType::EcdsaNistP256),
+            6 => Ok(TbfFooterV2CredentialsType::Rsa3072Key),
+            7 => Ok(TbfFooterV2CredentialsType::Rsa4096Key),
+            _ => Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfFooterCredentials as usize,
+            )),
+        }
+    }
+}
+
+impl core::convert::TryFrom<&'static [u8]> for TbfFooterV2Credentials {
+    type Error = TbfParseError;
+
+    fn try_from(b: &'static [u8]) -> Result<TbfFooterV2Credentials, Self::Error> {
+        let format: TbfFooterV2CredentialsType = u32::from_le_bytes(
+            b.get(0..4)
+                .ok_or(TbfParseError::NotEnoughFlash)?
+                .try_into()?,
+        )
+        .try_into()?;
+        let data = b.get(4..).ok_or(TbfParseError::NotEnoughFlash)?;
+
+        // Except for reserved space, credentials have a fixed length.
+        let expected_len = match format {
+            TbfFooterV2CredentialsType::Reserved => data.len(),
+            TbfFooterV2CredentialsType::SHA256 => 32,
+            TbfFooterV2CredentialsType::SHA384 => 48,
+            TbfFooterV2CredentialsType::SHA512 => 64,
+            TbfFooterV2CredentialsType::HmacSha256 => 32,
+            TbfFooterV2CredentialsType::EcdsaNistP256 => 64,
+            TbfFooterV2CredentialsType::Rsa3072Key => 768,
+            TbfFooterV2CredentialsType::Rsa4096Key => 1024,
+        };
+        if data.len() != expected_len {
+            return Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfFooterCredentials as usize,
+            ));
+        }
+        Ok(TbfFooterV2Credentials {
+            format: format,
+            data: data,
+        })
+    }
+}
+
 /// Single header that can contain all parts of a v2 header.
 ///
 /// Note, this struct limits the number of writeable regions an app can have to
-/// four since we need to statically know the length of the array to store in
-/// this type.
+/// four, and the number of driver permissions to `MAX_DRIVER_PERMISSIONS`,
+/// since we need to statically know the length of the arrays to store in this
+/// type.
 #[derive(Clone, Copy, Debug)]
 pub(crate) struct TbfHeaderV2 {
     base: TbfHeaderV2Base,
     main: Option<TbfHeaderV2Main>,
+    program: Option<TbfHeaderV2Program>,
     package_name: Option<&'static str>,
     writeable_regions: Option<[Option<TbfHeaderV2WriteableFlashRegion>; 4]>,
     fixed_addresses: Option<TbfHeaderV2FixedAddresses>,
+    permissions: Option<[Option<TbfHeaderDriverPermission>; MAX_DRIVER_PERMISSIONS]>,
+    realtime: Option<TbfHeaderV2Realtime>,
+    /// Package names of the processes that may use this process as an IPC
+    /// service, each preceded by its length in one byte.
+    ipc_clients: Option<&'static [u8]>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -360,7 +609,10 @@ impl TbfHeader {
     /// needed for this app.
     pub(crate) fn get_minimum_app_ram_size(&self) -> u32 {
         match *self {
-            TbfHeader::TbfHeaderV2(hd) => hd.main.map_or(0, |m| m.minimum_ram_size),
+            TbfHeader::TbfHeaderV2(hd) => match hd.program {
+                Some(p) => p.minimum_ram_size,
+                None => hd.main.map_or(0, |m| m.minimum_ram_size),
+            },
             _ => 0,
         }
     }
@@ -370,7 +622,11 @@ impl TbfHeader {
     pub(crate) fn get_protected_size(&self) -> u32 {
         match *self {
             TbfHeader::TbfHeaderV2(hd) => {
-                hd.main.map_or(0, |m| m.protected_size) + (hd.base.header_size as u32)
+                let protected_size = match hd.program {
+                    Some(p) => p.protected_size,
+                    None => hd.main.map_or(0, |m| m.protected_size),
+                };
+                protected_size + (hd.base.header_size as u32)
             }
             _ => 0,
         }
@@ -381,12 +637,28 @@ impl TbfHeader {
     pub(crate) fn get_init_function_offset(&self) -> u32 {
         match *self {
             TbfHeader::TbfHeaderV2(hd) => {
-                hd.main.map_or(0, |m| m.init_fn_offset) + (hd.base.header_size as u32)
+                let init_fn_offset = match hd.program {
+                    Some(p) => p.init_fn_offset,
+                    None => hd.main.map_or(0, |m| m.init_fn_offset),
+                };
+                init_fn_offset + (hd.base.header_size as u32)
             }
             _ => 0,
         }
     }
 
+    /// Get the offset from the beginning of the app's flash region at which
+    /// the app binary ends and the footers begin. Without a program header
+    /// there are no footers, and the binary extends to the end of the app.
+    pub(crate) fn get_binary_end(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .program
+                .map_or(hd.base.total_size, |p| p.binary_end_offset),
+            TbfHeader::Padding(base) => base.total_size,
+        }
+    }
+
     /// Get the name of the app.
     pub(crate) fn get_package_name(&self) -> Option<&'static str> {
         match *self {
@@ -434,6 +706,73 @@ impl TbfHeader {
         }
     }
 
+    /// Get the permissions the app declared for driver `driver_num`. `offset`
+    /// selects which 64 command numbers the returned mask covers, starting at
+    /// `offset * 64`.
+    pub(crate) fn get_command_permissions(
+        &self,
+        driver_num: usize,
+        offset: usize,
+    ) -> CommandPermissions {
+        let permissions = match self {
+            TbfHeader::TbfHeaderV2(hd) => match hd.permissions {
+                Some(permissions) => permissions,
+                None => return CommandPermissions::NoPermsAtAll,
+            },
+            _ => return CommandPermissions::NoPermsAtAll,
+        };
+
+        let mut found_driver = false;
+        for permission in permissions.iter().flatten() {
+            if permission.driver_number as usize == driver_num {
+                if permission.offset as usize == offset {
+                    return CommandPermissions::Mask(permission.allowed_commands);
+                }
+                found_driver = true;
+            }
+        }
+        if found_driver {
+            // The driver is allowed, but none of these commands are.
+            CommandPermissions::Mask(0)
+        } else {
+            CommandPermissions::NoPermsThisDriver
+        }
+    }
+
+    /// Get the period, deadline and budget of the app, if it has real-time
+    /// requirements.
+    pub(crate) fn get_realtime_parameters(&self) -> Option<RealtimeParameters> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.realtime.map(|realtime| RealtimeParameters {
+                period_us: realtime.period_us,
+                deadline_us: realtime.deadline_us,
+                budget_us: realtime.budget_us,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether the app allows the process named `client_name` to use it as an
+    /// IPC service. Apps that do not list their clients allow every process.
+    pub(crate) fn permits_ipc_client(&self, client_name: &str) -> bool {
+        let mut entries = match self {
+            TbfHeader::TbfHeaderV2(hd) => match hd.ipc_clients {
+                Some(clients) => clients,
+                None => return true,
+            },
+            _ => return true,
+        };
+        // The entries were validated when the header was parsed.
+        while let Some((&len, rest)) = entries.split_first() {
+            let (name, rest) = rest.split_at(len as usize);
+            if name == client_name.as_bytes() {
+                return true;
+            }
+            entries = rest;
+        }
+        false
+    }
+
     /// Get the address in flash this process was specifically compiled for. If
     /// the process is position independent, return `None`.
     pub(crate) fn get_fixed_address_flash(&self) -> Option<u32> {
@@ -503,6 +842,26 @@ pub(crate) fn parse_tbf_header_lengths(
     }
 }
 
+/// Length of a TBF header that only describes padding.
+pub(crate) const TBF_PADDING_HEADER_LENGTH: usize = 16;
+
+/// Create a version 2 TBF header for a padding entry of `total_size` bytes.
+/// Process loading skips over padding entries, so this is used to mark flash
+/// inside the app linked list as unused.
+pub(crate) fn create_padding_header(total_size: u32) -> [u8; TBF_PADDING_HEADER_LENGTH] {
+    let version: u32 = 2;
+    let header_size = TBF_PADDING_HEADER_LENGTH as u32;
+    let flags: u32 = 0;
+
+    let mut header = [0; TBF_PADDING_HEADER_LENGTH];
+    header[0..4].copy_from_slice(&(version | header_size << 16).to_le_bytes());
+    header[4..8].copy_from_slice(&total_size.to_le_bytes());
+    header[8..12].copy_from_slice(&flags.to_le_bytes());
+    let checksum = (version | header_size << 16) ^ total_size ^ flags;
+    header[12..16].copy_from_slice(&checksum.to_le_bytes());
+    header
+}
+
 /// Parse a TBF header stored in flash.
 ///
 /// The `header` must be a slice that only contains the TBF header. The caller
@@ -559,10 +918,16 @@ pub(crate) fn parse_tbf_header(
                 // Places to save fields that we parse out of the header
                 // options.
                 let mut main_pointer: Option<TbfHeaderV2Main> = None;
+                let mut program_pointer: Option<TbfHeaderV2Program> = None;
                 let mut wfr_pointer: [Option<TbfHeaderV2WriteableFlashRegion>; 4] =
                     Default::default();
                 let mut app_name_str = "";
                 let mut fixed_address_pointer: Option<TbfHeaderV2FixedAddresses> = None;
+                let mut permissions_pointer: Option<
+                    [Option<TbfHeaderDriverPermission>; MAX_DRIVER_PERMISSIONS],
+                > = None;
+                let mut realtime_pointer: Option<TbfHeaderV2Realtime> = None;
+                let mut ipc_clients_pointer: Option<&'static [u8]> = None;
 
                 // Iterate the remainder of the header looking for TLV entries.
                 while remaining.len() > 0 {
@@ -643,6 +1008,96 @@ pub(crate) fn parse_tbf_header(
                             }
                         }
 
+                        TbfHeaderTypes::TbfHeaderPermissions => {
+                            // Length must be a multiple of the size of a
+                            // permission entry.
+                            let perm_len = mem::size_of::<TbfHeaderDriverPermission>();
+                            // Only `MAX_DRIVER_PERMISSIONS` entries can be
+                            // stored. Rather than dropping the others, which
+                            // would hide drivers the app declared, the header
+                            // is rejected.
+                            if tlv_header.length as usize % perm_len == 0
+                                && tlv_header.length as usize / perm_len <= MAX_DRIVER_PERMISSIONS
+                            {
+                                let perm_slice = remaining
+                                    .get(0..tlv_header.length as usize)
+                                    .ok_or(TbfParseError::NotEnoughFlash)?;
+
+                                let mut permissions: [Option<TbfHeaderDriverPermission>;
+                                    MAX_DRIVER_PERMISSIONS] = Default::default();
+                                for (permission, entry) in permissions
+                                    .iter_mut()
+                                    .zip(perm_slice.chunks_exact(perm_len))
+                                {
+                                    *permission = Some(entry.try_into()?);
+                                }
+                                permissions_pointer = Some(permissions);
+                            } else {
+                                return Err(TbfParseError::BadTlvEntry(tlv_header.tipe as usize));
+                            }
+                        }
+
+                        TbfHeaderTypes::TbfHeaderProgram => {
+                            let entry_len = mem::size_of::<TbfHeaderV2Program>();
+                            if tlv_header.length as usize == entry_len {
+                                let program: TbfHeaderV2Program = remaining.try_into()?;
+                                // The binary cannot end inside the header or
+                                // past the end of the app.
+                                if program.binary_end_offset < tbf_header_base.header_size as u32
+                                    || program.binary_end_offset > tbf_header_base.total_size
+                                {
+                                    return Err(TbfParseError::BadTlvEntry(
+                                        tlv_header.tipe as usize,
+                                    ));
+                                }
+                                program_pointer = Some(program);
+                            } else {
+                                return Err(TbfParseError::BadTlvEntry(tlv_header.tipe as usize));
+                            }
+                        }
+
+                        TbfHeaderTypes::TbfHeaderRealtime => {
+                            let entry_len = mem::size_of::<TbfHeaderV2Realtime>();
+                            if tlv_header.length as usize == entry_len {
+                                let realtime: TbfHeaderV2Realtime = remaining.try_into()?;
+                                // The deadline must be within the period, and
+                                // the budget must fit before the deadline and
+                                // be long enough to run the process.
+                                let parameters = RealtimeParameters {
+                                    period_us: realtime.period_us,
+                                    deadline_us: realtime.deadline_us,
+                                    budget_us: realtime.budget_us,
+                                };
+                                if !parameters.is_valid() {
+                                    return Err(TbfParseError::BadTlvEntry(
+                                        tlv_header.tipe as usize,
+                                    ));
+                                }
+                                realtime_pointer = Some(realtime);
+                            } else {
+                                return Err(TbfParseError::BadTlvEntry(tlv_header.tipe as usize));
+                            }
+                        }
+
+                        TbfHeaderTypes::TbfHeaderIpcClients => {
+                            let clients = remaining
+                                .get(0..tlv_header.length as usize)
+                                .ok_or(TbfParseError::NotEnoughFlash)?;
+                            // Every name must be valid UTF-8 and fit in the
+                            // TLV.
+                            let mut entries = clients;
+                            while let Some((&len, rest)) = entries.split_first() {
+                                let name = rest
+                                    .get(..len as usize)
+                                    .ok_or(TbfParseError::BadTlvEntry(tlv_header.tipe as usize))?;
+                                str::from_utf8(name).or(Err(TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                )))?;
+                                entries = &rest[len as usize..];
+                            }
+                            ipc_clients_pointer = Some(clients);
+                        }
+
                         _ => {}
                     }
 
@@ -657,9 +1112,13 @@ pub(crate) fn parse_tbf_header(
                 let tbf_header = TbfHeaderV2 {
                     base: tbf_header_base,
                     main: main_pointer,
+                    program: program_pointer,
                     package_name: Some(app_name_str),
                     writeable_regions: Some(wfr_pointer),
                     fixed_addresses: fixed_address_pointer,
+                    permissions: permissions_pointer,
+                    realtime: realtime_pointer,
+                    ipc_clients: ipc_clients_pointer,
                 };
 
                 Ok(TbfHeader::TbfHeaderV2(tbf_header))
@@ -668,3 +1127,308 @@ pub(crate) fn parse_tbf_header(
         _ => Err(TbfParseError::UnsupportedVersion(version)),
     }
 }
+
+/// Parse one TBF footer from the start of `footers`, which must begin at the
+/// end of the app binary (see `TbfHeader::get_binary_end()`) or at the end of
+/// the previous footer.
+///
+/// ## Return
+///
+/// Returns the credentials if the footer holds credentials, or `None` for any
+/// other footer, along with the total length of the footer so that the caller
+/// can advance to the next one.
+pub(crate) fn parse_tbf_footer(
+    footers: &'static [u8],
+) -> Result<(Option<TbfFooterV2Credentials>, u32), TbfParseError> {
+    let tlv_header: TbfHeaderTlv = footers
+        .get(0..4)
+        .ok_or(TbfParseError::NotEnoughFlash)?
+        .try_into()?;
+    let data = footers
+        .get(4..4 + tlv_header.length as usize)
+        .ok_or(TbfParseError::NotEnoughFlash)?;
+    let footer_len = 4 + align4!(tlv_header.length as u32);
+
+    match tlv_header.tipe {
+        TbfHeaderTypes::TbfFooterCredentials => Ok((Some(data.try_into()?), footer_len)),
+        _ => Ok((None, footer_len)),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// Append a TLV entry with `data` padded to 4 bytes to `buf`.
+    pub(crate) fn push_tlv(buf: &mut Vec<u8>, tipe: u16, data: &[u8]) {
+        buf.extend_from_slice(&tipe.to_le_bytes());
+        buf.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        buf.extend_from_slice(data);
+        buf.resize(align4!(buf.len()), 0);
+    }
+
+    /// A credentials footer value of `format` holding `data`.
+    pub(crate) fn credentials(format: u32, data: &[u8]) -> Vec<u8> {
+        let mut value = format.to_le_bytes().to_vec();
+        value.extend_from_slice(data);
+        value
+    }
+
+    /// Build a TBF of an enabled app with the header TLVs `tlvs`, followed
+    /// by a program header, a binary of `binary_len` bytes and the TLV
+    /// `footers`. Returns the TBF and the length of its header.
+    pub(crate) fn build_tbf(
+        tlvs: &[(u16, &[u8])],
+        binary_len: usize,
+        footers: &[(u16, &[u8])],
+    ) -> (&'static [u8], u16) {
+        let mut header = Vec::new();
+        for &(tipe, data) in tlvs {
+            push_tlv(&mut header, tipe, data);
+        }
+        let header_len = 16 + header.len() + 4 + mem::size_of::<TbfHeaderV2Program>();
+        let mut program = Vec::new();
+        for field in &[0, 0, 0, (header_len + binary_len) as u32, 0] {
+            program.extend_from_slice(&u32::to_le_bytes(*field));
+        }
+        push_tlv(
+            &mut header,
+            TbfHeaderTypes::TbfHeaderProgram as u16,
+            &program,
+        );
+
+        let mut tbf = std::vec![0; 16];
+        tbf.extend_from_slice(&header);
+        tbf.resize(header_len + binary_len, 0);
+        for &(tipe, data) in footers {
+            push_tlv(&mut tbf, tipe, data);
+        }
+        let total_len = tbf.len() as u32;
+        tbf[0..4].copy_from_slice(&(2 | (header_len as u32) << 16).to_le_bytes());
+        tbf[4..8].copy_from_slice(&total_len.to_le_bytes());
+        tbf[8..12].copy_from_slice(&1u32.to_le_bytes());
+        let checksum = tbf[..header_len]
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|&(i, _)| i != 3)
+            .fold(0, |checksum, (_, word)| {
+                checksum ^ u32::from_le_bytes(word.try_into().unwrap())
+            });
+        tbf[12..16].copy_from_slice(&checksum.to_le_bytes());
+        (Box::leak(tbf.into_boxed_slice()), header_len as u16)
+    }
+
+    pub(crate) fn leak(buf: Vec<u8>) -> &'static [u8] {
+        Box::leak(buf.into_boxed_slice())
+    }
+
+    const CREDENTIALS: u16 = TbfHeaderTypes::TbfFooterCredentials as u16;
+
+    #[test]
+    fn parse_credentials_footer() {
+        let mut footers = Vec::new();
+        push_tlv(&mut footers, CREDENTIALS, &credentials(1, &[0xab; 32]));
+        push_tlv(&mut footers, CREDENTIALS, &credentials(0, &[0; 6]));
+        let footers = leak(footers);
+
+        let (credentials, len) = parse_tbf_footer(footers).unwrap();
+        let credentials = credentials.unwrap();
+        assert_eq!(credentials.format(), TbfFooterV2CredentialsType::SHA256);
+        assert_eq!(credentials.data(), &[0xab; 32][..]);
+        assert_eq!(len, 4 + 4 + 32);
+
+        // Reserved space can have any length, and is padded to 4 bytes.
+        let (credentials, len) = parse_tbf_footer(&footers[len as usize..]).unwrap();
+        let credentials = credentials.unwrap();
+        assert_eq!(credentials.format(), TbfFooterV2CredentialsType::Reserved);
+        assert_eq!(credentials.data().len(), 6);
+        assert_eq!(len, 4 + 12);
+    }
+
+    #[test]
+    fn parse_other_footer() {
+        let mut footers = Vec::new();
+        push_tlv(&mut footers, 200, &[1, 2, 3]);
+        let (credentials, len) = parse_tbf_footer(leak(footers)).unwrap();
+        assert!(credentials.is_none());
+        assert_eq!(len, 8);
+    }
+
+    #[test]
+    fn parse_malformed_footer() {
+        // Credentials of the wrong length for their format.
+        let mut footers = Vec::new();
+        push_tlv(&mut footers, CREDENTIALS, &credentials(1, &[0; 31]));
+        assert!(matches!(
+            parse_tbf_footer(leak(footers)),
+            Err(TbfParseError::BadTlvEntry(128))
+        ));
+
+        // Unknown format.
+        let mut footers = Vec::new();
+        push_tlv(&mut footers, CREDENTIALS, &credentials(99, &[]));
+        assert!(matches!(
+            parse_tbf_footer(leak(footers)),
+            Err(TbfParseError::BadTlvEntry(128))
+        ));
+
+        // Footer longer than the flash that is left.
+        let mut footers = Vec::new();
+        push_tlv(&mut footers, CREDENTIALS, &credentials(1, &[0; 32]));
+        footers.truncate(20);
+        assert!(matches!(
+            parse_tbf_footer(leak(footers)),
+            Err(TbfParseError::NotEnoughFlash)
+        ));
+        assert!(matches!(
+            parse_tbf_footer(leak(std::vec![128, 0])),
+            Err(TbfParseError::NotEnoughFlash)
+        ));
+    }
+
+    /// A permissions TLV entry.
+    fn permission(driver_number: u32, offset: u32, allowed_commands: u64) -> Vec<u8> {
+        let mut entry = driver_number.to_le_bytes().to_vec();
+        entry.extend_from_slice(&offset.to_le_bytes());
+        entry.extend_from_slice(&allowed_commands.to_le_bytes());
+        entry
+    }
+
+    /// Parse the header of a TBF built with the header TLVs `tlvs`.
+    fn parse(tlvs: &[(u16, &[u8])]) -> Result<TbfHeader, TbfParseError> {
+        let (tbf, header_len) = build_tbf(tlvs, 32, &[]);
+        parse_tbf_header(&tbf[..header_len as usize], 2)
+    }
+
+    const PACKAGE_NAME: u16 = TbfHeaderTypes::TbfHeaderPackageName as u16;
+    const PERMISSIONS: u16 = TbfHeaderTypes::TbfHeaderPermissions as u16;
+    const IPC_CLIENTS: u16 = TbfHeaderTypes::TbfHeaderIpcClients as u16;
+
+    #[test]
+    fn parse_permissions() {
+        let mut permissions = permission(1, 0, 0b101);
+        permissions.extend(permission(2, 1, 1));
+        permissions.extend(permission(2, 0, u64::MAX));
+        let header = parse(&[(PERMISSIONS, &permissions)]).unwrap();
+
+        assert_eq!(
+            header.get_command_permissions(1, 0),
+            CommandPermissions::Mask(0b101)
+        );
+        assert_eq!(
+            header.get_command_permissions(2, 0),
+            CommandPermissions::Mask(u64::MAX)
+        );
+        assert_eq!(
+            header.get_command_permissions(2, 1),
+            CommandPermissions::Mask(1)
+        );
+        // A declared driver without permissions for these commands.
+        assert_eq!(
+            header.get_command_permissions(1, 1),
+            CommandPermissions::Mask(0)
+        );
+        assert_eq!(
+            header.get_command_permissions(3, 0),
+            CommandPermissions::NoPermsThisDriver
+        );
+
+        let header = parse(&[]).unwrap();
+        assert_eq!(
+            header.get_command_permissions(1, 0),
+            CommandPermissions::NoPermsAtAll
+        );
+    }
+
+    #[test]
+    fn parse_too_many_permissions() {
+        let mut permissions = Vec::new();
+        for driver in 0..MAX_DRIVER_PERMISSIONS as u32 {
+            permissions.extend(permission(driver, 0, 1));
+        }
+        let header = parse(&[(PERMISSIONS, &permissions)]).unwrap();
+        assert_eq!(
+            header.get_command_permissions(MAX_DRIVER_PERMISSIONS - 1, 0),
+            CommandPermissions::Mask(1)
+        );
+
+        // An entry that cannot be stored rejects the header, rather than
+        // leaving the app without access to the driver.
+        permissions.extend(permission(MAX_DRIVER_PERMISSIONS as u32, 0, 1));
+        assert!(matches!(
+            parse(&[(PERMISSIONS, &permissions)]),
+            Err(TbfParseError::BadTlvEntry(6))
+        ));
+    }
+
+    #[test]
+    fn parse_bad_lengths() {
+        assert!(matches!(
+            parse(&[(PERMISSIONS, &permission(1, 0, 1)[..15])]),
+            Err(TbfParseError::BadTlvEntry(6))
+        ));
+        assert!(matches!(
+            parse(&[(TbfHeaderTypes::TbfHeaderMain as u16, &[0; 8])]),
+            Err(TbfParseError::BadTlvEntry(1))
+        ));
+        assert!(matches!(
+            parse(&[(TbfHeaderTypes::TbfHeaderProgram as u16, &[0; 16])]),
+            Err(TbfParseError::BadTlvEntry(9))
+        ));
+        assert!(matches!(
+            parse(&[(TbfHeaderTypes::TbfHeaderRealtime as u16, &[0; 8])]),
+            Err(TbfParseError::BadTlvEntry(11))
+        ));
+    }
+
+    #[test]
+    fn parse_package_name() {
+        let header = parse(&[(PACKAGE_NAME, b"blink")]).unwrap();
+        assert_eq!(header.get_package_name(), Some("blink"));
+
+        assert!(matches!(
+            parse(&[(PACKAGE_NAME, &[0xff, 0xfe])]),
+            Err(TbfParseError::BadProcessName)
+        ));
+    }
+
+    #[test]
+    fn parse_ipc_clients() {
+        let header = parse(&[(IPC_CLIENTS, b"\x03abc\x01d")]).unwrap();
+        assert!(header.permits_ipc_client("abc"));
+        assert!(header.permits_ipc_client("d"));
+        assert!(!header.permits_ipc_client("ab"));
+
+        // Apps that do not list clients allow all of them.
+        assert!(parse(&[]).unwrap().permits_ipc_client("ab"));
+
+        // A name longer than the rest of the TLV.
+        assert!(matches!(
+            parse(&[(IPC_CLIENTS, b"\x04abc")]),
+            Err(TbfParseError::BadTlvEntry(12))
+        ));
+    }
+
+    #[test]
+    fn parse_checksum_mismatch() {
+        let (tbf, header_len) = build_tbf(&[(PACKAGE_NAME, b"blink")], 32, &[]);
+        let mut header = tbf[..header_len as usize].to_vec();
+        header[16 + 4] ^= 1;
+        assert!(matches!(
+            parse_tbf_header(leak(header), 2),
+            Err(TbfParseError::ChecksumMismatch(_, _))
+        ));
+    }
+
+    #[test]
+    fn built_tbf_has_binary_end() {
+        let (tbf, header_len) = build_tbf(&[], 64, &[]);
+        let header = parse_tbf_header(&tbf[..header_len as usize], 2).unwrap();
+        assert!(header.is_app() && header.enabled());
+        assert_eq!(header.get_binary_end(), header_len as u32 + 64);
+    }
+}
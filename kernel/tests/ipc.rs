@@ -0,0 +1,94 @@
+//! Tests of the message-passing IPC driver with mock processes.
+
+use kernel::capabilities::MemoryAllocationCapability;
+use kernel::ipc::IPCMessaging;
+use kernel::procs::ProcessType;
+use kernel::{Driver, ReturnCode};
+use tock_hil_mock::process::{mock_kernel, MockProcess};
+
+struct Capability;
+unsafe impl MemoryAllocationCapability for Capability {}
+
+const SERVICE: &str = "app270398";
+/// Has the same 30-bit FNV-1a hash as `SERVICE`.
+const COLLIDING: &str = "app818116";
+
+fn discover(ipc: &IPCMessaging, client: &MockProcess, name: &str) -> usize {
+    let (buffer, _) = client.share(name.as_bytes());
+    assert_eq!(
+        ipc.allow(client.appid(), 2, Some(buffer)),
+        ReturnCode::SUCCESS
+    );
+    match ipc.command(4, name.len(), 0, client.appid()) {
+        ReturnCode::SuccessWithValue { value } => value,
+        rval => panic!("discovering {} failed: {:?}", name, rval),
+    }
+}
+
+#[test]
+fn services_with_colliding_names_are_reachable() {
+    let (kernel, processes) = mock_kernel(&["client", SERVICE, COLLIDING]);
+    let ipc = IPCMessaging::new(kernel, &Capability);
+    let client = processes[0];
+
+    let mut received = Vec::new();
+    for service in processes[1..].iter() {
+        assert_eq!(ipc.command(1, 1, 0, service.appid()), ReturnCode::SUCCESS);
+        let (buffer, contents) = service.share(&[0; 8]);
+        assert_eq!(
+            ipc.allow(service.appid(), 0, Some(buffer)),
+            ReturnCode::SUCCESS
+        );
+        received.push(contents);
+    }
+
+    let service_id = discover(&ipc, client, SERVICE);
+    let colliding_id = discover(&ipc, client, COLLIDING);
+    assert_ne!(service_id, colliding_id);
+
+    for (identifier, message) in [(service_id, b"first"), (colliding_id, b"other")].iter() {
+        let (buffer, _) = client.share(&message[..]);
+        assert_eq!(
+            ipc.allow(client.appid(), 2, Some(buffer)),
+            ReturnCode::SUCCESS
+        );
+        assert!(matches!(
+            ipc.command(5, *identifier, message.len(), client.appid()),
+            ReturnCode::SuccessWithValue { .. }
+        ));
+        assert_eq!(ipc.command(6, 0, 0, client.appid()), ReturnCode::SUCCESS);
+    }
+    assert_eq!(&received[0].contents()[..5], b"first");
+    assert_eq!(&received[1].contents()[..5], b"other");
+}
+
+#[test]
+fn messages_wait_for_receive_buffer() {
+    let (kernel, processes) = mock_kernel(&["client", SERVICE]);
+    let ipc = IPCMessaging::new(kernel, &Capability);
+    let (client, service) = (processes[0], processes[1]);
+    assert_eq!(ipc.command(1, 2, 0, service.appid()), ReturnCode::SUCCESS);
+    let service_id = discover(&ipc, client, SERVICE);
+
+    let (buffer, _) = client.share(b"queued");
+    assert_eq!(
+        ipc.allow(client.appid(), 2, Some(buffer)),
+        ReturnCode::SUCCESS
+    );
+    assert!(matches!(
+        ipc.command(5, service_id, 6, client.appid()),
+        ReturnCode::SuccessWithValue { .. }
+    ));
+    // The request is still outstanding.
+    assert_eq!(
+        ipc.command(5, service_id, 6, client.appid()),
+        ReturnCode::EBUSY
+    );
+
+    let (buffer, received) = service.share(&[0; 8]);
+    assert_eq!(
+        ipc.allow(service.appid(), 0, Some(buffer)),
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(&received.contents()[..6], b"queued");
+}
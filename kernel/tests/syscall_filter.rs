@@ -0,0 +1,129 @@
+//! Tests of the system call filters that enforce TBF header permissions.
+
+use kernel::procs::ProcessType;
+use kernel::syscall::Syscall;
+use kernel::syscall_filter::{
+    SyscallFilter, TbfHeaderFilterDefaultAllow, TbfHeaderFilterDefaultDeny,
+};
+use kernel::ReturnCode;
+use tock_hil_mock::process::mock_kernel;
+
+const LED: usize = 2;
+const BUTTON: usize = 3;
+const IPC: usize = 0x10000;
+
+fn command(driver_number: usize, subdriver_number: usize) -> Syscall {
+    Syscall::COMMAND {
+        driver_number: driver_number,
+        subdriver_number: subdriver_number,
+        arg0: 0,
+        arg1: 0,
+    }
+}
+
+fn subscribe(driver_number: usize) -> Syscall {
+    Syscall::SUBSCRIBE {
+        driver_number: driver_number,
+        subdriver_number: 0,
+        callback_ptr: core::ptr::null_mut(),
+        appdata: 0,
+    }
+}
+
+fn allow(driver_number: usize) -> Syscall {
+    Syscall::ALLOW {
+        driver_number: driver_number,
+        subdriver_number: 0,
+        allow_address: core::ptr::null_mut(),
+        allow_size: 0,
+    }
+}
+
+const MEMOP: Syscall = Syscall::MEMOP {
+    operand: 0,
+    arg0: 0,
+};
+
+/// Both filters enforce the permissions a process declared.
+fn check_declared_permissions(filter: &dyn SyscallFilter) {
+    let (_, processes) = mock_kernel(&["app"]);
+    // Commands 0 and 2 of the LED driver, and command 65 of the button
+    // driver.
+    processes[0].set_permissions(&[(LED, 0, 0b101), (BUTTON, 1, 0b10)]);
+    let process: &dyn ProcessType = processes[0];
+
+    assert_eq!(filter.filter_syscall(process, &command(LED, 0)), Ok(()));
+    assert_eq!(filter.filter_syscall(process, &command(LED, 2)), Ok(()));
+    assert_eq!(
+        filter.filter_syscall(process, &command(LED, 1)),
+        Err(ReturnCode::ENOSUPPORT)
+    );
+    assert_eq!(filter.filter_syscall(process, &command(BUTTON, 65)), Ok(()));
+    assert_eq!(
+        filter.filter_syscall(process, &command(BUTTON, 1)),
+        Err(ReturnCode::ENOSUPPORT)
+    );
+    assert_eq!(filter.filter_syscall(process, &subscribe(BUTTON)), Ok(()));
+    assert_eq!(filter.filter_syscall(process, &allow(LED)), Ok(()));
+
+    // Undeclared drivers are hidden.
+    assert_eq!(
+        filter.filter_syscall(process, &command(IPC, 0)),
+        Err(ReturnCode::ENODEVICE)
+    );
+    assert_eq!(
+        filter.filter_syscall(process, &subscribe(IPC)),
+        Err(ReturnCode::ENODEVICE)
+    );
+    assert_eq!(
+        filter.filter_syscall(process, &allow(IPC)),
+        Err(ReturnCode::ENODEVICE)
+    );
+
+    assert_eq!(filter.filter_syscall(process, &MEMOP), Ok(()));
+    assert_eq!(filter.filter_syscall(process, &Syscall::YIELD), Ok(()));
+}
+
+#[test]
+fn default_allow_enforces_permissions() {
+    check_declared_permissions(&TbfHeaderFilterDefaultAllow);
+}
+
+#[test]
+fn default_deny_enforces_permissions() {
+    check_declared_permissions(&TbfHeaderFilterDefaultDeny);
+}
+
+#[test]
+fn default_allow_allows_processes_without_permissions() {
+    let (_, processes) = mock_kernel(&["app"]);
+    let process: &dyn ProcessType = processes[0];
+    let filter = TbfHeaderFilterDefaultAllow;
+
+    assert_eq!(filter.filter_syscall(process, &command(IPC, 1)), Ok(()));
+    assert_eq!(filter.filter_syscall(process, &subscribe(LED)), Ok(()));
+    assert_eq!(filter.filter_syscall(process, &allow(LED)), Ok(()));
+    assert_eq!(filter.filter_syscall(process, &MEMOP), Ok(()));
+}
+
+#[test]
+fn default_deny_only_allows_memory_operations_without_permissions() {
+    let (_, processes) = mock_kernel(&["app"]);
+    let process: &dyn ProcessType = processes[0];
+    let filter = TbfHeaderFilterDefaultDeny;
+
+    assert_eq!(
+        filter.filter_syscall(process, &command(LED, 0)),
+        Err(ReturnCode::ENODEVICE)
+    );
+    assert_eq!(
+        filter.filter_syscall(process, &subscribe(LED)),
+        Err(ReturnCode::ENODEVICE)
+    );
+    assert_eq!(
+        filter.filter_syscall(process, &allow(LED)),
+        Err(ReturnCode::ENODEVICE)
+    );
+    assert_eq!(filter.filter_syscall(process, &MEMOP), Ok(()));
+    assert_eq!(filter.filter_syscall(process, &Syscall::YIELD), Ok(()));
+}
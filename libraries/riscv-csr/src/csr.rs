@@ -20,6 +20,7 @@ pub const MEPC: usize = 0x341;
 pub const MCAUSE: usize = 0x342;
 pub const MTVAL: usize = 0x343;
 pub const MIP: usize = 0x344;
+pub const MSECCFG: usize = 0x747;
 pub const PMPCFG0: usize = 0x3A0;
 pub const PMPCFG1: usize = 0x3A1;
 pub const PMPCFG2: usize = 0x3A2;
@@ -187,6 +188,10 @@ impl<R: RegisterLongName> ReadWriteRiscvCsr<u32, R> {
             unsafe {
                 asm!("csrr {rd}, {csr}", rd = out(reg) r, csr = const MIP);
             }
+        } else if self.value == MSECCFG {
+            unsafe {
+                asm!("csrr {rd}, {csr}", rd = out(reg) r, csr = const MSECCFG);
+            }
         } else if self.value == PMPCFG0 {
             unsafe {
                 asm!("csrr {rd}, {csr}", rd = out(reg) r, csr = const PMPCFG0);
@@ -572,6 +577,10 @@ impl<R: RegisterLongName> ReadWriteRiscvCsr<u32, R> {
             unsafe {
                 asm!("csrw {csr}, {rs}", rs = in(reg) val_to_set, csr = const MIP);
             }
+        } else if self.value == MSECCFG {
+            unsafe {
+                asm!("csrw {csr}, {rs}", rs = in(reg) val_to_set, csr = const MSECCFG);
+            }
         } else if self.value == PMPCFG0 {
             unsafe {
                 asm!("csrw {csr}, {rs}", rs = in(reg) val_to_set, csr = const PMPCFG0);
@@ -0,0 +1,190 @@
+//! Mock AES-CCM engine.
+//!
+//! The mock does not implement AES. It leaves the message data in the clear,
+//! so tests can read what a capsule sent, and authenticates it with a tag
+//! computed by `tag()` over the key, nonce, authenticated data and message
+//! data. A capsule that uses the wrong key, nonce or header fails the tag
+//! check just as it would with real hardware, and a test can build messages
+//! that pass it with `MockAES128CCM::seal()`.
+
+use std::cell::{Cell, RefCell};
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM, AES128_KEY_SIZE, CCM_NONCE_LENGTH};
+use kernel::ReturnCode;
+
+/// An operation started by the client of the engine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CCMOperation {
+    pub key: [u8; AES128_KEY_SIZE],
+    pub nonce: [u8; CCM_NONCE_LENGTH],
+    /// The data that is only authenticated.
+    pub a_data: Vec<u8>,
+    /// The data that is authenticated and encrypted, before the operation.
+    pub m_data: Vec<u8>,
+    pub mic_len: usize,
+    pub confidential: bool,
+    pub encrypting: bool,
+}
+
+/// Compute the `mic_len`-byte tag the mock appends to messages.
+pub fn tag(key: &[u8], nonce: &[u8], a_data: &[u8], m_data: &[u8], mic_len: usize) -> Vec<u8> {
+    // FNV-1a over every input, each prefixed with its length so that moving
+    // bytes between inputs changes the tag.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for input in [key, nonce, a_data, m_data].iter() {
+        for byte in (input.len() as u32)
+            .to_be_bytes()
+            .iter()
+            .chain(input.iter())
+        {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    (0..mic_len)
+        .map(|i| (hash.rotate_left(8 * i as u32) >> 56) as u8)
+        .collect()
+}
+
+pub struct MockAES128CCM<'a> {
+    client: OptionalCell<&'a dyn CCMClient>,
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    nonce: Cell<[u8; CCM_NONCE_LENGTH]>,
+    operations: RefCell<Vec<CCMOperation>>,
+    /// Buffer of the operation in progress, and where its data is.
+    buffer: TakeCell<'static, [u8]>,
+    a_off: Cell<usize>,
+    m_off: Cell<usize>,
+    m_len: Cell<usize>,
+    mic_len: Cell<usize>,
+    encrypting: Cell<bool>,
+}
+
+impl<'a> MockAES128CCM<'a> {
+    pub fn new() -> MockAES128CCM<'a> {
+        MockAES128CCM {
+            client: OptionalCell::empty(),
+            key: Cell::new([0; AES128_KEY_SIZE]),
+            nonce: Cell::new([0; CCM_NONCE_LENGTH]),
+            operations: RefCell::new(Vec::new()),
+            buffer: TakeCell::empty(),
+            a_off: Cell::new(0),
+            m_off: Cell::new(0),
+            m_len: Cell::new(0),
+            mic_len: Cell::new(0),
+            encrypting: Cell::new(false),
+        }
+    }
+
+    /// Return `m_data` followed by the tag the mock expects for it.
+    pub fn seal(key: &[u8], nonce: &[u8], a_data: &[u8], m_data: &[u8], mic_len: usize) -> Vec<u8> {
+        let mut sealed = m_data.to_vec();
+        sealed.extend(tag(key, nonce, a_data, m_data, mic_len));
+        sealed
+    }
+
+    /// Whether an operation is waiting for `complete()`.
+    pub fn is_busy(&self) -> bool {
+        self.buffer.is_some()
+    }
+
+    /// All operations started so far.
+    pub fn operations(&self) -> Vec<CCMOperation> {
+        self.operations.borrow().clone()
+    }
+
+    /// Return the operations started so far, and forget them.
+    pub fn take_operations(&self) -> Vec<CCMOperation> {
+        self.operations.replace(Vec::new())
+    }
+
+    /// Complete the operation in progress. Encryption appends the tag after
+    /// the message data; decryption checks it. Returns false if no operation
+    /// was in progress.
+    pub fn complete(&self) -> bool {
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+        let (a_off, m_off) = (self.a_off.get(), self.m_off.get());
+        let m_end = m_off + self.m_len.get();
+        let mic_len = self.mic_len.get();
+        let expected = tag(
+            &self.key.get(),
+            &self.nonce.get(),
+            &buffer[a_off..m_off],
+            &buffer[m_off..m_end],
+            mic_len,
+        );
+        let tag_is_valid = if self.encrypting.get() {
+            buffer[m_end..m_end + mic_len].copy_from_slice(&expected);
+            true
+        } else {
+            buffer[m_end..m_end + mic_len] == expected[..]
+        };
+        self.client
+            .map(move |client| client.crypt_done(buffer, ReturnCode::SUCCESS, tag_is_valid));
+        true
+    }
+}
+
+impl<'a> AES128CCM<'a> for MockAES128CCM<'a> {
+    fn set_client(&'a self, client: &'a dyn CCMClient) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> ReturnCode {
+        if key.len() != AES128_KEY_SIZE {
+            return ReturnCode::EINVAL;
+        }
+        let mut copy = [0; AES128_KEY_SIZE];
+        copy.copy_from_slice(key);
+        self.key.set(copy);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> ReturnCode {
+        if nonce.len() != CCM_NONCE_LENGTH {
+            return ReturnCode::EINVAL;
+        }
+        let mut copy = [0; CCM_NONCE_LENGTH];
+        copy.copy_from_slice(nonce);
+        self.nonce.set(copy);
+        ReturnCode::SUCCESS
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        mic_len: usize,
+        confidential: bool,
+        encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(buf));
+        }
+        if a_off > m_off || m_off + m_len + mic_len > buf.len() {
+            return (ReturnCode::EINVAL, Some(buf));
+        }
+        self.operations.borrow_mut().push(CCMOperation {
+            key: self.key.get(),
+            nonce: self.nonce.get(),
+            a_data: buf[a_off..m_off].to_vec(),
+            m_data: buf[m_off..m_off + m_len].to_vec(),
+            mic_len: mic_len,
+            confidential: confidential,
+            encrypting: encrypting,
+        });
+        self.a_off.set(a_off);
+        self.m_off.set(m_off);
+        self.m_len.set(m_len);
+        self.mic_len.set(mic_len);
+        self.encrypting.set(encrypting);
+        self.buffer.replace(buf);
+        (ReturnCode::SUCCESS, None)
+    }
+}
@@ -0,0 +1,122 @@
+//! Mock alarm with a clock controlled by the test.
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{Alarm, AlarmClient, Freq1MHz, Frequency, Ticks, Ticks32, Time};
+use kernel::ReturnCode;
+
+/// Alarm whose time only moves when the test calls `advance()` or
+/// `set_now()`.
+pub struct MockAlarm<'a, F: Frequency = Freq1MHz> {
+    client: OptionalCell<&'a dyn AlarmClient>,
+    now: Cell<Ticks32>,
+    reference: Cell<Ticks32>,
+    dt: Cell<Ticks32>,
+    armed: Cell<bool>,
+    _frequency: PhantomData<F>,
+}
+
+impl<'a, F: Frequency> MockAlarm<'a, F> {
+    pub fn new() -> MockAlarm<'a, F> {
+        MockAlarm {
+            client: OptionalCell::empty(),
+            now: Cell::new(Ticks32::from(0)),
+            reference: Cell::new(Ticks32::from(0)),
+            dt: Cell::new(Ticks32::from(0)),
+            armed: Cell::new(false),
+            _frequency: PhantomData,
+        }
+    }
+
+    /// Set the current time without firing the alarm.
+    pub fn set_now(&self, now: u32) {
+        self.now.set(Ticks32::from(now));
+    }
+
+    /// Ticks until the alarm fires, if it is armed. An alarm whose time has
+    /// passed fires as soon as time advances.
+    pub fn ticks_until_fire(&self) -> Option<u32> {
+        if !self.armed.get() {
+            return None;
+        }
+        let elapsed = self.now.get().wrapping_sub(self.reference.get());
+        Some(if elapsed >= self.dt.get() {
+            0
+        } else {
+            self.dt.get().wrapping_sub(elapsed).into_u32()
+        })
+    }
+
+    /// Move time forward by `ticks`. The alarm fires, at the time it was set
+    /// for, each time time passes it, so a client that sets the alarm again
+    /// from its callback can fire several times.
+    pub fn advance(&self, ticks: u32) {
+        let mut remaining = ticks;
+        loop {
+            match self.ticks_until_fire() {
+                Some(until_fire) if until_fire <= remaining => {
+                    self.now
+                        .set(self.now.get().wrapping_add(Ticks32::from(until_fire)));
+                    remaining -= until_fire;
+                    self.fire();
+                }
+                _ => {
+                    self.now
+                        .set(self.now.get().wrapping_add(Ticks32::from(remaining)));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Move time forward by `ms` milliseconds.
+    pub fn advance_ms(&self, ms: u32) {
+        self.advance((ms as u64 * F::frequency() as u64 / 1000) as u32);
+    }
+
+    /// Fire the alarm now, whether or not it is armed and its time has come.
+    pub fn fire(&self) {
+        self.armed.set(false);
+        self.client.map(|client| client.alarm());
+    }
+}
+
+impl<F: Frequency> Time for MockAlarm<'_, F> {
+    type Frequency = F;
+    type Ticks = Ticks32;
+
+    fn now(&self) -> Ticks32 {
+        self.now.get()
+    }
+}
+
+impl<'a, F: Frequency> Alarm<'a> for MockAlarm<'a, F> {
+    fn set_alarm_client(&'a self, client: &'a dyn AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+        self.reference.set(reference);
+        self.dt.set(dt);
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> Ticks32 {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn disarm(&self) -> ReturnCode {
+        self.armed.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Ticks32 {
+        Ticks32::from(1)
+    }
+}
@@ -0,0 +1,203 @@
+//! Mock block storage with blocks of 512 bytes.
+
+use std::cell::{Cell, RefCell};
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient};
+use kernel::ReturnCode;
+
+pub const BLOCK_SIZE: usize = 512;
+
+/// An operation started by the client of the device, with its first block
+/// and number of blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockOperation {
+    Read(usize, usize),
+    Write(usize, usize),
+    Erase(usize, usize),
+}
+
+/// Block device whose contents are kept in memory, and are all zero
+/// initially. Writes and erases change the contents when they complete;
+/// erased blocks hold 0xff.
+pub struct MockBlockStorage {
+    client: OptionalCell<&'static dyn BlockStorageClient>,
+    contents: RefCell<Vec<u8>>,
+    operations: RefCell<Vec<BlockOperation>>,
+    /// Operation in progress, and its buffer, unless it is an erase.
+    current: Cell<Option<BlockOperation>>,
+    buffer: TakeCell<'static, [u8]>,
+    /// Return value of the next call that starts an operation.
+    next_error: Cell<ReturnCode>,
+    /// Whether the next operation completes with FAIL.
+    fail_completion: Cell<bool>,
+}
+
+impl MockBlockStorage {
+    pub fn new(num_blocks: usize) -> MockBlockStorage {
+        MockBlockStorage {
+            client: OptionalCell::empty(),
+            contents: RefCell::new(vec![0; num_blocks * BLOCK_SIZE]),
+            operations: RefCell::new(Vec::new()),
+            current: Cell::new(None),
+            buffer: TakeCell::empty(),
+            next_error: Cell::new(ReturnCode::SUCCESS),
+            fail_completion: Cell::new(false),
+        }
+    }
+
+    /// A copy of the current contents of the device.
+    pub fn contents(&self) -> Vec<u8> {
+        self.contents.borrow().clone()
+    }
+
+    /// Overwrite the device, starting at byte `offset`, without going
+    /// through the HIL.
+    pub fn write_contents(&self, offset: usize, data: &[u8]) {
+        self.contents.borrow_mut()[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    /// All operations started so far.
+    pub fn operations(&self) -> Vec<BlockOperation> {
+        self.operations.borrow().clone()
+    }
+
+    /// Return the operations started so far, and forget them.
+    pub fn take_operations(&self) -> Vec<BlockOperation> {
+        self.operations.replace(Vec::new())
+    }
+
+    /// Whether an operation is waiting for `complete()`.
+    pub fn is_busy(&self) -> bool {
+        self.current.get().is_some()
+    }
+
+    /// Make the next call that starts an operation fail with `error`.
+    pub fn fail_next_call(&self, error: ReturnCode) {
+        self.next_error.set(error);
+    }
+
+    /// Make the next operation complete with FAIL, without changing the
+    /// device.
+    pub fn fail_next_completion(&self) {
+        self.fail_completion.set(true);
+    }
+
+    /// Complete the operation in progress. Returns false if no operation was
+    /// in progress.
+    pub fn complete(&self) -> bool {
+        let operation = match self.current.take() {
+            Some(operation) => operation,
+            None => return false,
+        };
+        let result = if self.fail_completion.replace(false) {
+            ReturnCode::FAIL
+        } else {
+            ReturnCode::SUCCESS
+        };
+        match operation {
+            BlockOperation::Read(block, count) => {
+                let buffer = self.buffer.take().unwrap();
+                if result == ReturnCode::SUCCESS {
+                    let range = block * BLOCK_SIZE..(block + count) * BLOCK_SIZE;
+                    buffer[..count * BLOCK_SIZE].copy_from_slice(&self.contents.borrow()[range]);
+                }
+                self.client
+                    .map(move |client| client.read_done(buffer, result));
+            }
+            BlockOperation::Write(block, count) => {
+                let buffer = self.buffer.take().unwrap();
+                if result == ReturnCode::SUCCESS {
+                    let range = block * BLOCK_SIZE..(block + count) * BLOCK_SIZE;
+                    self.contents.borrow_mut()[range]
+                        .copy_from_slice(&buffer[..count * BLOCK_SIZE]);
+                }
+                self.client
+                    .map(move |client| client.write_done(buffer, result));
+            }
+            BlockOperation::Erase(block, count) => {
+                if result == ReturnCode::SUCCESS {
+                    let range = block * BLOCK_SIZE..(block + count) * BLOCK_SIZE;
+                    for byte in self.contents.borrow_mut()[range].iter_mut() {
+                        *byte = 0xff;
+                    }
+                }
+                self.client.map(move |client| client.erase_done(result));
+            }
+        }
+        true
+    }
+
+    /// Start `operation`, whose buffer needs `buffer_len` bytes.
+    fn start(&self, operation: BlockOperation, buffer_len: Option<usize>) -> ReturnCode {
+        let (block, count) = match operation {
+            BlockOperation::Read(block, count)
+            | BlockOperation::Write(block, count)
+            | BlockOperation::Erase(block, count) => (block, count),
+        };
+        let error = self.next_error.replace(ReturnCode::SUCCESS);
+        if error != ReturnCode::SUCCESS {
+            error
+        } else if buffer_len.map_or(false, |len| len < count * BLOCK_SIZE) {
+            ReturnCode::ESIZE
+        } else if count == 0 || block + count > self.number_of_blocks() {
+            ReturnCode::EINVAL
+        } else if self.is_busy() {
+            ReturnCode::EBUSY
+        } else {
+            self.operations.borrow_mut().push(operation);
+            self.current.set(Some(operation));
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn start_transfer(
+        &self,
+        operation: BlockOperation,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        match self.start(operation, Some(buffer.len())) {
+            ReturnCode::SUCCESS => {
+                self.buffer.replace(buffer);
+                Ok(())
+            }
+            error => Err((error, buffer)),
+        }
+    }
+}
+
+impl BlockStorage<'static> for MockBlockStorage {
+    fn set_client(&self, client: &'static dyn BlockStorageClient) {
+        self.client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn number_of_blocks(&self) -> usize {
+        self.contents.borrow().len() / BLOCK_SIZE
+    }
+
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: usize,
+        count: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        self.start_transfer(BlockOperation::Read(block, count), buffer)
+    }
+
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: usize,
+        count: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        self.start_transfer(BlockOperation::Write(block, count), buffer)
+    }
+
+    fn erase_blocks(&self, block: usize, count: usize) -> ReturnCode {
+        self.start(BlockOperation::Erase(block, count), None)
+    }
+}
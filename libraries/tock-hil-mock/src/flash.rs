@@ -0,0 +1,284 @@
+//! Mock flash with pages of 512 bytes.
+
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::ops::{Index, IndexMut};
+use std::slice;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::flash::{self, Client, Flash, HasClient};
+use kernel::ReturnCode;
+
+pub const PAGE_SIZE: usize = 512;
+
+pub struct MockPage(pub [u8; PAGE_SIZE]);
+
+impl Default for MockPage {
+    fn default() -> Self {
+        MockPage([0; PAGE_SIZE])
+    }
+}
+
+impl MockPage {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+impl Index<usize> for MockPage {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for MockPage {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for MockPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+/// An operation started by the client of the flash, with its page number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashOperation {
+    Read(usize),
+    Write(usize),
+    Erase(usize),
+}
+
+/// A page of the flash contents, aligned like flash pages usually are.
+#[repr(C, align(512))]
+struct StoredPage(UnsafeCell<[u8; PAGE_SIZE]>);
+
+/// Flash whose contents are kept in memory. Pages are erased (all bytes
+/// 0xFF) initially. Writes change the contents when they complete, so a test
+/// can check what a capsule does when it is interrupted in the middle of an
+/// operation.
+///
+/// A flash created with `new_mapped()` is memory-mapped, like the internal
+/// flash of a microcontroller: `memory()` returns its contents, and page
+/// numbers are addresses in memory divided by the page size.
+pub struct MockFlash {
+    client: OptionalCell<&'static dyn Client<MockFlash>>,
+    pages: &'static [StoredPage],
+    /// Page number of the first page.
+    first_page: usize,
+    operations: RefCell<Vec<FlashOperation>>,
+    /// Operation in progress, and the page buffer of reads and writes.
+    current: Cell<Option<FlashOperation>>,
+    buffer: TakeCell<'static, MockPage>,
+    /// Return value of the next call that starts an operation.
+    next_error: Cell<ReturnCode>,
+    /// Whether the next operation completes with `Error::FlashError`.
+    fail_completion: Cell<bool>,
+}
+
+impl MockFlash {
+    pub fn new(num_pages: usize) -> MockFlash {
+        let pages = (0..num_pages)
+            .map(|_| StoredPage(UnsafeCell::new([0xff; PAGE_SIZE])))
+            .collect::<Vec<_>>();
+        MockFlash {
+            client: OptionalCell::empty(),
+            pages: Box::leak(pages.into_boxed_slice()),
+            first_page: 0,
+            operations: RefCell::new(Vec::new()),
+            current: Cell::new(None),
+            buffer: TakeCell::empty(),
+            next_error: Cell::new(ReturnCode::SUCCESS),
+            fail_completion: Cell::new(false),
+        }
+    }
+
+    /// Create a memory-mapped flash.
+    pub fn new_mapped(num_pages: usize) -> MockFlash {
+        let mut flash = MockFlash::new(num_pages);
+        flash.first_page = flash.memory().as_ptr() as usize / PAGE_SIZE;
+        flash
+    }
+
+    pub fn num_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// The page number of the first page.
+    pub fn first_page(&self) -> usize {
+        self.first_page
+    }
+
+    /// The contents of the flash in memory. They change as operations
+    /// complete.
+    pub fn memory(&self) -> &'static [u8] {
+        unsafe {
+            slice::from_raw_parts(
+                self.pages.as_ptr() as *const u8,
+                self.pages.len() * PAGE_SIZE,
+            )
+        }
+    }
+
+    /// A copy of the current contents of the flash.
+    pub fn contents(&self) -> Vec<u8> {
+        self.memory().to_vec()
+    }
+
+    /// Overwrite the flash, starting at byte `offset`, without going through
+    /// the HIL.
+    pub fn write_contents(&self, offset: usize, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            let address = offset + i;
+            let mut page = self.load(address / PAGE_SIZE);
+            page[address % PAGE_SIZE] = *byte;
+            self.store(address / PAGE_SIZE, page);
+        }
+    }
+
+    fn load(&self, index: usize) -> [u8; PAGE_SIZE] {
+        unsafe { *self.pages[index].0.get() }
+    }
+
+    fn store(&self, index: usize, contents: [u8; PAGE_SIZE]) {
+        unsafe { *self.pages[index].0.get() = contents }
+    }
+
+    /// All operations started so far.
+    pub fn operations(&self) -> Vec<FlashOperation> {
+        self.operations.borrow().clone()
+    }
+
+    /// Return the operations started so far, and forget them.
+    pub fn take_operations(&self) -> Vec<FlashOperation> {
+        self.operations.replace(Vec::new())
+    }
+
+    /// Whether an operation is waiting for `complete()`.
+    pub fn is_busy(&self) -> bool {
+        self.current.get().is_some()
+    }
+
+    /// Make the next call that starts an operation fail with `error`.
+    pub fn fail_next_call(&self, error: ReturnCode) {
+        self.next_error.set(error);
+    }
+
+    /// Make the next operation complete with `Error::FlashError`, without
+    /// changing the flash.
+    pub fn fail_next_completion(&self) {
+        self.fail_completion.set(true);
+    }
+
+    /// Drop the operation in progress without changing the flash or calling
+    /// the client, as if power failed before it completed.
+    pub fn lose_power(&self) {
+        self.current.set(None);
+        self.buffer.take();
+    }
+
+    /// Complete the operation in progress. Returns false if no operation was
+    /// in progress.
+    pub fn complete(&self) -> bool {
+        let operation = match self.current.take() {
+            Some(operation) => operation,
+            None => return false,
+        };
+        let error = if self.fail_completion.replace(false) {
+            flash::Error::FlashError
+        } else {
+            flash::Error::CommandComplete
+        };
+        let succeeded = error == flash::Error::CommandComplete;
+        match operation {
+            FlashOperation::Read(page_number) => {
+                let buffer = self.buffer.take().unwrap();
+                if succeeded {
+                    buffer.0 = self.load(page_number - self.first_page);
+                }
+                self.client
+                    .map(move |client| client.read_complete(buffer, error));
+            }
+            FlashOperation::Write(page_number) => {
+                let buffer = self.buffer.take().unwrap();
+                if succeeded {
+                    self.store(page_number - self.first_page, buffer.0);
+                }
+                self.client
+                    .map(move |client| client.write_complete(buffer, error));
+            }
+            FlashOperation::Erase(page_number) => {
+                if succeeded {
+                    self.store(page_number - self.first_page, [0xff; PAGE_SIZE]);
+                }
+                self.client.map(move |client| client.erase_complete(error));
+            }
+        }
+        true
+    }
+
+    fn start(&self, operation: FlashOperation, page_number: usize) -> ReturnCode {
+        let error = self.next_error.replace(ReturnCode::SUCCESS);
+        if error != ReturnCode::SUCCESS {
+            error
+        } else if page_number < self.first_page || page_number - self.first_page >= self.num_pages()
+        {
+            ReturnCode::EINVAL
+        } else if self.is_busy() {
+            ReturnCode::EBUSY
+        } else {
+            self.operations.borrow_mut().push(operation);
+            self.current.set(Some(operation));
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<C: Client<Self>> HasClient<'static, C> for MockFlash {
+    fn set_client(&self, client: &'static C) {
+        self.client.set(client);
+    }
+}
+
+impl Flash for MockFlash {
+    type Page = MockPage;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        match self.start(FlashOperation::Read(page_number), page_number) {
+            ReturnCode::SUCCESS => {
+                self.buffer.replace(buf);
+                Ok(())
+            }
+            error => Err((error, buf)),
+        }
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        match self.start(FlashOperation::Write(page_number), page_number) {
+            ReturnCode::SUCCESS => {
+                self.buffer.replace(buf);
+                Ok(())
+            }
+            error => Err((error, buf)),
+        }
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        self.start(FlashOperation::Erase(page_number), page_number)
+    }
+}
@@ -0,0 +1,171 @@
+//! Mock GPIO pin.
+
+use std::cell::{Cell, RefCell};
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::gpio::{
+    Client, Configuration, Configure, FloatingState, Input, Interrupt, InterruptEdge, InterruptPin,
+    Output, Pin,
+};
+
+/// `InterruptEdge` is not `Copy`, so the pin keeps its own copy.
+#[derive(Clone, Copy, PartialEq)]
+enum Edge {
+    Rising,
+    Falling,
+    Either,
+}
+
+/// Pin whose input level is set by the test, and which records the levels
+/// its client outputs.
+pub struct MockPin<'a> {
+    client: OptionalCell<&'a dyn Client>,
+    input: Cell<bool>,
+    output: Cell<bool>,
+    level: Cell<bool>,
+    floating_state: Cell<FloatingState>,
+    interrupt: Cell<Option<Edge>>,
+    outputs: RefCell<Vec<bool>>,
+}
+
+impl<'a> MockPin<'a> {
+    pub fn new() -> MockPin<'a> {
+        MockPin {
+            client: OptionalCell::empty(),
+            input: Cell::new(false),
+            output: Cell::new(false),
+            level: Cell::new(false),
+            floating_state: Cell::new(FloatingState::PullNone),
+            interrupt: Cell::new(None),
+            outputs: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The current level of the pin.
+    pub fn level(&self) -> bool {
+        self.level.get()
+    }
+
+    /// Drive the pin to `level` from outside. If interrupts are enabled for
+    /// the resulting edge, the client is called.
+    pub fn set_input(&self, level: bool) {
+        let previous = self.level.replace(level);
+        let fire = match self.interrupt.get() {
+            Some(Edge::Rising) => !previous && level,
+            Some(Edge::Falling) => previous && !level,
+            Some(Edge::Either) => previous != level,
+            None => false,
+        };
+        if fire {
+            self.client.map(|client| client.fired());
+        }
+    }
+
+    /// Whether interrupts are enabled.
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupt.get().is_some()
+    }
+
+    /// Levels set by the client through `Output`, oldest first.
+    pub fn outputs(&self) -> Vec<bool> {
+        self.outputs.borrow().clone()
+    }
+
+    fn set_output(&self, level: bool) {
+        self.level.set(level);
+        self.outputs.borrow_mut().push(level);
+    }
+}
+
+impl Configure for MockPin<'_> {
+    fn configuration(&self) -> Configuration {
+        match (self.input.get(), self.output.get()) {
+            (true, true) => Configuration::InputOutput,
+            (true, false) => Configuration::Input,
+            (false, true) => Configuration::Output,
+            (false, false) => Configuration::LowPower,
+        }
+    }
+
+    fn make_output(&self) -> Configuration {
+        self.output.set(true);
+        self.configuration()
+    }
+
+    fn disable_output(&self) -> Configuration {
+        self.output.set(false);
+        self.configuration()
+    }
+
+    fn make_input(&self) -> Configuration {
+        self.input.set(true);
+        self.configuration()
+    }
+
+    fn disable_input(&self) -> Configuration {
+        self.input.set(false);
+        self.configuration()
+    }
+
+    fn deactivate_to_low_power(&self) {
+        self.input.set(false);
+        self.output.set(false);
+    }
+
+    fn set_floating_state(&self, state: FloatingState) {
+        self.floating_state.set(state);
+    }
+
+    fn floating_state(&self) -> FloatingState {
+        self.floating_state.get()
+    }
+}
+
+impl Output for MockPin<'_> {
+    fn set(&self) {
+        self.set_output(true);
+    }
+
+    fn clear(&self) {
+        self.set_output(false);
+    }
+
+    fn toggle(&self) -> bool {
+        let level = !self.level.get();
+        self.set_output(level);
+        level
+    }
+}
+
+impl Input for MockPin<'_> {
+    fn read(&self) -> bool {
+        self.level.get()
+    }
+}
+
+impl<'a> Interrupt<'a> for MockPin<'a> {
+    fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    fn enable_interrupts(&self, mode: InterruptEdge) {
+        self.interrupt.set(Some(match mode {
+            InterruptEdge::RisingEdge => Edge::Rising,
+            InterruptEdge::FallingEdge => Edge::Falling,
+            InterruptEdge::EitherEdge => Edge::Either,
+        }));
+    }
+
+    fn disable_interrupts(&self) {
+        self.interrupt.set(None);
+    }
+
+    /// Interrupts are delivered from `set_input()`, so none is ever
+    /// pending.
+    fn is_pending(&self) -> bool {
+        false
+    }
+}
+
+impl Pin for MockPin<'_> {}
+impl<'a> InterruptPin<'a> for MockPin<'a> {}
@@ -0,0 +1,157 @@
+//! Mock I2C device.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::i2c::{Error, I2CClient, I2CDevice};
+
+/// A transaction started by the client of the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum I2CTransaction {
+    /// The bytes written.
+    Write(Vec<u8>),
+    /// The number of bytes to read.
+    Read(usize),
+    /// The bytes written and the number of bytes then read.
+    WriteRead(Vec<u8>, usize),
+}
+
+/// How the device completes a transaction.
+#[derive(Clone, Debug)]
+pub struct I2CResponse {
+    /// Bytes returned by reads. Missing bytes read as zero.
+    pub data: Vec<u8>,
+    /// The error passed to the client.
+    pub error: Error,
+}
+
+impl I2CResponse {
+    /// A successful transaction that reads `data`.
+    pub fn ok(data: &[u8]) -> I2CResponse {
+        I2CResponse {
+            data: data.to_vec(),
+            error: Error::CommandComplete,
+        }
+    }
+
+    /// A transaction that fails with `error`.
+    pub fn error(error: Error) -> I2CResponse {
+        I2CResponse {
+            data: Vec::new(),
+            error: error,
+        }
+    }
+}
+
+pub struct MockI2CDevice<'a> {
+    client: OptionalCell<&'a dyn I2CClient>,
+    enabled: Cell<bool>,
+    transactions: RefCell<Vec<I2CTransaction>>,
+    responses: RefCell<VecDeque<I2CResponse>>,
+    /// Buffer of the transaction in progress, and the number of bytes it
+    /// reads.
+    buffer: TakeCell<'static, [u8]>,
+    read_len: Cell<usize>,
+}
+
+impl<'a> MockI2CDevice<'a> {
+    pub fn new() -> MockI2CDevice<'a> {
+        MockI2CDevice {
+            client: OptionalCell::empty(),
+            enabled: Cell::new(false),
+            transactions: RefCell::new(Vec::new()),
+            responses: RefCell::new(VecDeque::new()),
+            buffer: TakeCell::empty(),
+            read_len: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn I2CClient) {
+        self.client.set(client);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    /// Whether a transaction is waiting for `complete()`.
+    pub fn is_busy(&self) -> bool {
+        self.buffer.is_some()
+    }
+
+    /// All transactions started so far.
+    pub fn transactions(&self) -> Vec<I2CTransaction> {
+        self.transactions.borrow().clone()
+    }
+
+    /// Return the transactions started so far, and forget them.
+    pub fn take_transactions(&self) -> Vec<I2CTransaction> {
+        self.transactions.replace(Vec::new())
+    }
+
+    /// Queue the response to a future transaction. Responses are used in
+    /// order; transactions without a queued response succeed and read zeros.
+    pub fn push_response(&self, response: I2CResponse) {
+        self.responses.borrow_mut().push_back(response);
+    }
+
+    /// Complete the transaction in progress with the next queued response.
+    /// Returns false if no transaction was in progress.
+    pub fn complete(&self) -> bool {
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+        let response = self
+            .responses
+            .borrow_mut()
+            .pop_front()
+            .unwrap_or_else(|| I2CResponse::ok(&[]));
+        let read_len = self.read_len.get().min(buffer.len());
+        for (i, byte) in buffer[..read_len].iter_mut().enumerate() {
+            *byte = response.data.get(i).copied().unwrap_or(0);
+        }
+        self.client
+            .map(move |client| client.command_complete(buffer, response.error));
+        true
+    }
+
+    fn start(&self, transaction: I2CTransaction, buffer: &'static mut [u8], read_len: usize) {
+        assert!(
+            self.buffer.is_none(),
+            "I2C transaction started while another one is in progress"
+        );
+        self.transactions.borrow_mut().push(transaction);
+        self.read_len.set(read_len);
+        self.buffer.replace(buffer);
+    }
+}
+
+impl I2CDevice for MockI2CDevice<'_> {
+    fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    fn disable(&self) {
+        self.enabled.set(false);
+    }
+
+    fn write_read(&self, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        let written = data[..write_len as usize].to_vec();
+        self.start(
+            I2CTransaction::WriteRead(written, read_len as usize),
+            data,
+            read_len as usize,
+        );
+    }
+
+    fn write(&self, data: &'static mut [u8], len: u8) {
+        let written = data[..len as usize].to_vec();
+        self.start(I2CTransaction::Write(written), data, 0);
+    }
+
+    fn read(&self, buffer: &'static mut [u8], len: u8) {
+        self.start(I2CTransaction::Read(len as usize), buffer, len as usize);
+    }
+}
@@ -0,0 +1,58 @@
+//! Scriptable fake HIL implementations for testing capsules on the host.
+//!
+//! Each mock implements a HIL, records what its client asks it to do, and
+//! completes operations only when the test says so. Tests can therefore run
+//! a capsule in plain `cargo test`, step through its interaction with the
+//! hardware, check every transaction it makes, and inject the data or errors
+//! the "hardware" returns.
+//!
+//! - `i2c::MockI2CDevice` implements `hil::i2c::I2CDevice`.
+//! - `spi::MockSpiMasterDevice` implements `hil::spi::SpiMasterDevice`.
+//! - `flash::MockFlash` implements `hil::flash::Flash`.
+//! - `block_storage::MockBlockStorage` implements
+//!   `hil::block_storage::BlockStorage`.
+//! - `alarm::MockAlarm` implements `hil::time::Alarm` with a clock the test
+//!   advances.
+//! - `gpio::MockPin` implements `hil::gpio::Pin` and
+//!   `hil::gpio::InterruptPin`.
+//! - `uart::MockUart` implements `hil::uart::Uart`.
+//! - `aes_ccm::MockAES128CCM` implements
+//!   `hil::symmetric_encryption::AES128CCM` with a stand-in for AES.
+//! - `process::MockProcess` implements `procs::ProcessType`, so that capsules
+//!   can be tested through their `Driver` interface.
+//!
+//! Capsules keep references to the HIL implementations and buffers for the
+//! rest of the program, so tests usually create them with `leak()` and
+//! `leak_buffer()`.
+//!
+//! ```rust
+//! use kernel::hil::i2c::I2CDevice;
+//! use tock_hil_mock::i2c::{I2CResponse, I2CTransaction, MockI2CDevice};
+//!
+//! let i2c = tock_hil_mock::leak(MockI2CDevice::new());
+//! i2c.write(tock_hil_mock::leak_buffer(&[0xf3, 0]), 1);
+//! assert_eq!(i2c.transactions(), vec![I2CTransaction::Write(vec![0xf3])]);
+//! i2c.push_response(I2CResponse::ok(&[]));
+//! assert!(i2c.complete());
+//! ```
+
+pub mod aes_ccm;
+pub mod alarm;
+pub mod block_storage;
+pub mod flash;
+pub mod gpio;
+pub mod i2c;
+pub mod process;
+pub mod spi;
+pub mod uart;
+
+/// Move `value` to the heap for the rest of the test, as capsules need
+/// `'static` references.
+pub fn leak<T>(value: T) -> &'static T {
+    Box::leak(Box::new(value))
+}
+
+/// Create a `'static` buffer initialized with `contents`.
+pub fn leak_buffer(contents: &[u8]) -> &'static mut [u8] {
+    Box::leak(contents.to_vec().into_boxed_slice())
+}
@@ -0,0 +1,349 @@
+//! Mock processes, for testing capsules through their `Driver` interface.
+//!
+//! `mock_kernel()` creates a kernel with a process for each given package
+//! name. Grants of that kernel work for these processes, and each process
+//! can share buffers with capsules like `allow` does. The processes never
+//! run, and callbacks cannot be subscribed from outside the kernel.
+
+use std::alloc::{self, Layout};
+use std::cell::Cell;
+use std::fmt::Write;
+use std::ptr::NonNull;
+
+use kernel::capabilities::ExternalProcessCapability;
+use kernel::common::cells::OptionalCell;
+use kernel::mpu;
+use kernel::procs::{
+    CommandPermissions, Error, FaultReason, FaultRecord, FunctionCall, ProcessType, State,
+    SyscallHistogram, Task,
+};
+use kernel::syscall::{self, Syscall};
+use kernel::{AppId, AppSlice, CallbackId, Kernel, RealtimeParameters, ReturnCode, Shared};
+
+/// Number of grants a mock process supports.
+pub const MAX_GRANTS: usize = 16;
+
+struct Capability;
+unsafe impl ExternalProcessCapability for Capability {}
+
+/// Create a kernel with a process for each of `names`.
+pub fn mock_kernel(names: &[&'static str]) -> (&'static Kernel, Vec<&'static MockProcess>) {
+    let processes: Vec<&'static MockProcess> = names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| crate::leak(MockProcess::new(index, name)))
+        .collect();
+    let entries: Vec<Option<&'static dyn ProcessType>> = processes
+        .iter()
+        .map(|process| Some(*process as &'static dyn ProcessType))
+        .collect();
+    let kernel = crate::leak(Kernel::new(Box::leak(entries.into_boxed_slice())));
+    for process in processes.iter() {
+        process.kernel.set(kernel);
+    }
+    (kernel, processes)
+}
+
+/// A process that only has a name and grant memory.
+pub struct MockProcess {
+    kernel: OptionalCell<&'static Kernel>,
+    index: usize,
+    name: &'static str,
+    state: Cell<State>,
+    grants: Vec<Cell<*mut u8>>,
+    /// Driver number, offset and allowed commands of each permission the
+    /// process declared, as in a TBF permissions TLV.
+    permissions: Cell<Option<&'static [(usize, usize, u64)]>>,
+}
+
+/// A buffer of a process shared with a capsule.
+pub struct SharedBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl SharedBuffer {
+    /// A copy of the current contents of the buffer.
+    pub fn contents(&self) -> Vec<u8> {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len).to_vec() }
+    }
+}
+
+impl MockProcess {
+    fn new(index: usize, name: &'static str) -> MockProcess {
+        MockProcess {
+            kernel: OptionalCell::empty(),
+            index: index,
+            name: name,
+            state: Cell::new(State::Yielded),
+            grants: (0..MAX_GRANTS)
+                .map(|_| Cell::new(std::ptr::null_mut()))
+                .collect(),
+            permissions: Cell::new(None),
+        }
+    }
+
+    /// Declare driver permissions for the process, each a driver number, an
+    /// offset and a mask of allowed commands. By default the process has no
+    /// permissions TLV.
+    pub fn set_permissions(&self, permissions: &'static [(usize, usize, u64)]) {
+        self.permissions.set(Some(permissions));
+    }
+
+    /// Share a new buffer holding `contents` with a capsule. The returned
+    /// `SharedBuffer` shows what the capsule wrote to it.
+    pub fn share(&self, contents: &[u8]) -> (AppSlice<Shared, u8>, SharedBuffer) {
+        let buffer = Box::leak(contents.to_vec().into_boxed_slice());
+        let ptr = NonNull::new(buffer.as_mut_ptr()).unwrap();
+        let slice = unsafe { AppSlice::new_external(ptr, buffer.len(), self.appid(), &Capability) };
+        (
+            slice,
+            SharedBuffer {
+                ptr: ptr,
+                len: buffer.len(),
+            },
+        )
+    }
+}
+
+impl ProcessType for MockProcess {
+    fn appid(&self) -> AppId {
+        let kernel = self.kernel.expect("process created without mock_kernel()");
+        AppId::new_external(kernel, self.index, self.index, &Capability)
+    }
+
+    fn enqueue_task(&self, _task: Task) -> bool {
+        false
+    }
+
+    fn ready(&self) -> bool {
+        false
+    }
+
+    fn dequeue_task(&self) -> Option<Task> {
+        None
+    }
+
+    fn remove_pending_callbacks(&self, _callback_id: CallbackId) {}
+
+    fn get_state(&self) -> State {
+        self.state.get()
+    }
+
+    fn set_yielded_state(&self) {
+        self.state.set(State::Yielded);
+    }
+
+    fn stop(&self) {
+        self.state.set(State::StoppedYielded);
+    }
+
+    fn resume(&self) {
+        self.state.set(State::Yielded);
+    }
+
+    fn set_fault_state(&self, _reason: FaultReason) {
+        self.state.set(State::Fault);
+    }
+
+    /// Grants of a terminated process are inaccessible.
+    fn terminate(&self) {
+        self.state.set(State::Fault);
+    }
+
+    fn try_restart(&self) -> bool {
+        false
+    }
+
+    fn force_restart(&self) -> bool {
+        false
+    }
+
+    fn get_restart_count(&self) -> usize {
+        0
+    }
+
+    fn get_last_fault(&self) -> Option<FaultRecord> {
+        None
+    }
+
+    fn get_process_name(&self) -> &'static str {
+        self.name
+    }
+
+    fn ipc_identifier(&self) -> usize {
+        self.index + 1
+    }
+
+    fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions {
+        let permissions = match self.permissions.get() {
+            Some(permissions) => permissions,
+            None => return CommandPermissions::NoPermsAtAll,
+        };
+        let mut entries = permissions
+            .iter()
+            .filter(|&&(driver, _, _)| driver == driver_num);
+        match entries
+            .clone()
+            .find(|&&(_, entry_offset, _)| entry_offset == offset)
+        {
+            Some(&(_, _, allowed_commands)) => CommandPermissions::Mask(allowed_commands),
+            None if entries.next().is_some() => CommandPermissions::Mask(0),
+            None => CommandPermissions::NoPermsThisDriver,
+        }
+    }
+
+    fn get_realtime_parameters(&self) -> Option<RealtimeParameters> {
+        None
+    }
+
+    fn permits_ipc_client(&self, _client_name: &str) -> bool {
+        true
+    }
+
+    fn brk(&self, _new_break: *const u8) -> Result<*const u8, Error> {
+        Err(Error::OutOfMemory)
+    }
+
+    fn sbrk(&self, _increment: isize) -> Result<*const u8, Error> {
+        Err(Error::OutOfMemory)
+    }
+
+    fn mem_start(&self) -> *const u8 {
+        std::ptr::null()
+    }
+
+    fn mem_end(&self) -> *const u8 {
+        std::ptr::null()
+    }
+
+    fn flash_start(&self) -> *const u8 {
+        std::ptr::null()
+    }
+
+    fn flash_end(&self) -> *const u8 {
+        std::ptr::null()
+    }
+
+    fn app_memory_break(&self) -> *const u8 {
+        std::ptr::null()
+    }
+
+    fn kernel_memory_break(&self) -> *const u8 {
+        std::ptr::null()
+    }
+
+    fn number_writeable_flash_regions(&self) -> usize {
+        0
+    }
+
+    fn get_writeable_flash_region(&self, _region_index: usize) -> (u32, u32) {
+        (0, 0)
+    }
+
+    fn update_stack_start_pointer(&self, _stack_pointer: *const u8) {}
+
+    fn update_heap_start_pointer(&self, _heap_pointer: *const u8) {}
+
+    fn set_stack_bottom(&self, _stack_bottom: *const u8) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn stack_guard(&self) -> Option<mpu::Region> {
+        None
+    }
+
+    fn allow(
+        &self,
+        _buf_start_addr: *const u8,
+        _size: usize,
+    ) -> Result<Option<AppSlice<Shared, u8>>, ReturnCode> {
+        // Tests share buffers with `share()`.
+        Err(ReturnCode::EINVAL)
+    }
+
+    fn flash_non_protected_start(&self) -> *const u8 {
+        std::ptr::null()
+    }
+
+    fn setup_mpu(&self) {}
+
+    fn add_mpu_region(
+        &self,
+        _unallocated_memory_start: *const u8,
+        _unallocated_memory_size: usize,
+        _min_region_size: usize,
+    ) -> Option<mpu::Region> {
+        None
+    }
+
+    fn alloc(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
+        let layout = Layout::from_size_align(size.max(1), align).ok()?;
+        NonNull::new(unsafe { alloc::alloc_zeroed(layout) })
+    }
+
+    unsafe fn free(&self, _: *mut u8) {}
+
+    fn get_grant_ptr(&self, grant_num: usize) -> Option<*mut u8> {
+        match self.state.get() {
+            State::Fault => None,
+            _ => self.grants.get(grant_num).map(|grant| grant.get()),
+        }
+    }
+
+    unsafe fn set_grant_ptr(&self, grant_num: usize, grant_ptr: *mut u8) {
+        self.grants[grant_num].set(grant_ptr);
+    }
+
+    unsafe fn set_syscall_return_value(&self, _return_value: isize) {}
+
+    unsafe fn set_process_function(&self, _callback: FunctionCall) {}
+
+    unsafe fn switch_to(&self) -> Option<syscall::ContextSwitchReason> {
+        None
+    }
+
+    unsafe fn print_memory_map(&self, _writer: &mut dyn Write) {}
+
+    unsafe fn print_full_process(&self, _writer: &mut dyn Write) {}
+
+    fn debug_syscall_count(&self) -> usize {
+        0
+    }
+
+    fn debug_dropped_callback_count(&self) -> usize {
+        0
+    }
+
+    fn debug_timeslice_expiration_count(&self) -> usize {
+        0
+    }
+
+    fn debug_timeslice_expired(&self) {}
+
+    fn debug_deadline_miss_count(&self) -> usize {
+        0
+    }
+
+    fn debug_deadline_missed(&self) {}
+
+    fn debug_syscall_called(&self, _last_syscall: Syscall) {}
+
+    fn debug_syscall_histogram(&self) -> SyscallHistogram {
+        SyscallHistogram::default()
+    }
+
+    fn debug_cpu_time_us(&self) -> u64 {
+        0
+    }
+
+    fn debug_cpu_time_add(&self, _execution_time_us: u32) {}
+
+    fn debug_peak_stack_usage(&self) -> Option<usize> {
+        None
+    }
+
+    fn debug_peak_heap_usage(&self) -> Option<usize> {
+        None
+    }
+}
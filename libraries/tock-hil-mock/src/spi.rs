@@ -0,0 +1,158 @@
+//! Mock SPI master device.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+use kernel::ReturnCode;
+
+/// A transfer started by the client of the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpiTransfer {
+    /// The bytes written.
+    pub write: Vec<u8>,
+    /// Whether the client passed a buffer for the bytes read.
+    pub read: bool,
+}
+
+pub struct MockSpiMasterDevice<'a> {
+    client: OptionalCell<&'a dyn SpiMasterClient>,
+    polarity: Cell<ClockPolarity>,
+    phase: Cell<ClockPhase>,
+    rate: Cell<u32>,
+    transfers: RefCell<Vec<SpiTransfer>>,
+    /// Bytes returned by future transfers.
+    responses: RefCell<VecDeque<Vec<u8>>>,
+    /// Return value of the next `read_write_bytes()` call.
+    next_error: Cell<ReturnCode>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+}
+
+impl<'a> MockSpiMasterDevice<'a> {
+    pub fn new() -> MockSpiMasterDevice<'a> {
+        MockSpiMasterDevice {
+            client: OptionalCell::empty(),
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+            rate: Cell::new(0),
+            transfers: RefCell::new(Vec::new()),
+            responses: RefCell::new(VecDeque::new()),
+            next_error: Cell::new(ReturnCode::SUCCESS),
+            write_buffer: TakeCell::empty(),
+            read_buffer: TakeCell::empty(),
+            len: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    /// Whether a transfer is waiting for `complete()`.
+    pub fn is_busy(&self) -> bool {
+        self.write_buffer.is_some()
+    }
+
+    /// All transfers started so far.
+    pub fn transfers(&self) -> Vec<SpiTransfer> {
+        self.transfers.borrow().clone()
+    }
+
+    /// Return the transfers started so far, and forget them.
+    pub fn take_transfers(&self) -> Vec<SpiTransfer> {
+        self.transfers.replace(Vec::new())
+    }
+
+    /// Queue the bytes read by a future transfer. Responses are used in
+    /// order; transfers without a queued response read zeros.
+    pub fn push_response(&self, data: &[u8]) {
+        self.responses.borrow_mut().push_back(data.to_vec());
+    }
+
+    /// Make the next call to `read_write_bytes()` fail with `error`.
+    pub fn fail_next(&self, error: ReturnCode) {
+        self.next_error.set(error);
+    }
+
+    /// Complete the transfer in progress with the next queued response.
+    /// Returns false if no transfer was in progress.
+    pub fn complete(&self) -> bool {
+        let write_buffer = match self.write_buffer.take() {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+        let response = self.responses.borrow_mut().pop_front().unwrap_or_default();
+        let mut read_buffer = self.read_buffer.take();
+        if let Some(read_buffer) = read_buffer.as_mut() {
+            for (i, byte) in read_buffer[..self.len.get()].iter_mut().enumerate() {
+                *byte = response.get(i).copied().unwrap_or(0);
+            }
+        }
+        let len = self.len.get();
+        self.client
+            .map(move |client| client.read_write_done(write_buffer, read_buffer, len));
+        true
+    }
+}
+
+impl SpiMasterDevice for MockSpiMasterDevice<'_> {
+    fn configure(&self, cpol: ClockPolarity, cpal: ClockPhase, rate: u32) {
+        self.polarity.set(cpol);
+        self.phase.set(cpal);
+        self.rate.set(rate);
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        let error = self.next_error.replace(ReturnCode::SUCCESS);
+        if error != ReturnCode::SUCCESS {
+            return error;
+        }
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        let len = read_buffer
+            .as_ref()
+            .map_or(len, |read_buffer| len.min(read_buffer.len()))
+            .min(write_buffer.len());
+        self.transfers.borrow_mut().push(SpiTransfer {
+            write: write_buffer[..len].to_vec(),
+            read: read_buffer.is_some(),
+        });
+        self.len.set(len);
+        self.write_buffer.replace(write_buffer);
+        read_buffer.map(|read_buffer| self.read_buffer.replace(read_buffer));
+        ReturnCode::SUCCESS
+    }
+
+    fn set_polarity(&self, cpol: ClockPolarity) {
+        self.polarity.set(cpol);
+    }
+
+    fn set_phase(&self, cpal: ClockPhase) {
+        self.phase.set(cpal);
+    }
+
+    fn set_rate(&self, rate: u32) {
+        self.rate.set(rate);
+    }
+
+    fn get_polarity(&self) -> ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.phase.get()
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.rate.get()
+    }
+}
@@ -0,0 +1,222 @@
+//! Mock UART.
+
+use std::cell::{Cell, RefCell};
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::uart::{
+    Configure, Error, Parameters, Receive, ReceiveClient, Transmit, TransmitClient, Uart, UartData,
+};
+use kernel::ReturnCode;
+
+/// UART that records the bytes its client transmits, and receives bytes
+/// supplied by the test.
+pub struct MockUart<'a> {
+    tx_client: OptionalCell<&'a dyn TransmitClient>,
+    rx_client: OptionalCell<&'a dyn ReceiveClient>,
+    parameters: Cell<Option<Parameters>>,
+    transmitted: RefCell<Vec<u8>>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_aborted: Cell<bool>,
+    /// Return value of the next `transmit_buffer()` call.
+    next_tx_error: Cell<ReturnCode>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    /// Number of bytes received into `rx_buffer`.
+    rx_position: Cell<usize>,
+    rx_aborted: Cell<bool>,
+}
+
+impl<'a> MockUart<'a> {
+    pub fn new() -> MockUart<'a> {
+        MockUart {
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            parameters: Cell::new(None),
+            transmitted: RefCell::new(Vec::new()),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_aborted: Cell::new(false),
+            next_tx_error: Cell::new(ReturnCode::SUCCESS),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_position: Cell::new(0),
+            rx_aborted: Cell::new(false),
+        }
+    }
+
+    /// The parameters set by the last call to `configure()`.
+    pub fn parameters(&self) -> Option<Parameters> {
+        self.parameters.get()
+    }
+
+    /// All bytes transmitted so far, including those of the transmission in
+    /// progress.
+    pub fn transmitted(&self) -> Vec<u8> {
+        self.transmitted.borrow().clone()
+    }
+
+    /// Return the bytes transmitted so far, and forget them.
+    pub fn take_transmitted(&self) -> Vec<u8> {
+        self.transmitted.replace(Vec::new())
+    }
+
+    pub fn is_transmitting(&self) -> bool {
+        self.tx_buffer.is_some()
+    }
+
+    pub fn is_receiving(&self) -> bool {
+        self.rx_buffer.is_some()
+    }
+
+    /// Make the next call to `transmit_buffer()` fail with `error`.
+    pub fn fail_next_transmit(&self, error: ReturnCode) {
+        self.next_tx_error.set(error);
+    }
+
+    /// Complete the transmission in progress. Returns false if no
+    /// transmission was in progress.
+    pub fn complete_transmit(&self) -> bool {
+        let buffer = match self.tx_buffer.take() {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+        let rval = if self.tx_aborted.replace(false) {
+            ReturnCode::ECANCEL
+        } else {
+            ReturnCode::SUCCESS
+        };
+        let len = self.tx_len.get();
+        self.tx_client
+            .map(move |client| client.transmitted_buffer(buffer, len, rval));
+        true
+    }
+
+    /// Receive `bytes`. Once the buffer of the receive in progress is full,
+    /// the client is called; bytes left after that are dropped unless the
+    /// client starts another receive. Returns the number of bytes received.
+    pub fn receive_bytes(&self, bytes: &[u8]) -> usize {
+        let mut received = 0;
+        while received < bytes.len() && self.rx_buffer.is_some() {
+            let position = self.rx_position.get();
+            self.rx_buffer
+                .map(|buffer| buffer[position] = bytes[received]);
+            self.rx_position.set(position + 1);
+            received += 1;
+            if position + 1 == self.rx_len.get() {
+                self.complete_receive();
+            }
+        }
+        received
+    }
+
+    /// End the receive in progress with the bytes received so far. The
+    /// client gets `ECANCEL` if the receive was aborted, and `ESIZE`
+    /// otherwise if the buffer is not full. Returns false if no receive was in
+    /// progress.
+    pub fn complete_receive(&self) -> bool {
+        let buffer = match self.rx_buffer.take() {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+        let len = self.rx_position.get();
+        let (rval, error) = if self.rx_aborted.replace(false) {
+            (ReturnCode::ECANCEL, Error::Aborted)
+        } else if len < self.rx_len.get() {
+            (ReturnCode::ESIZE, Error::None)
+        } else {
+            (ReturnCode::SUCCESS, Error::None)
+        };
+        self.rx_client
+            .map(move |client| client.received_buffer(buffer, len, rval, error));
+        true
+    }
+}
+
+impl Configure for MockUart<'_> {
+    fn configure(&self, params: Parameters) -> ReturnCode {
+        self.parameters.set(Some(params));
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> Transmit<'a> for MockUart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let error = self.next_tx_error.replace(ReturnCode::SUCCESS);
+        if error != ReturnCode::SUCCESS {
+            return (error, Some(tx_buffer));
+        }
+        if self.is_transmitting() {
+            return (ReturnCode::EBUSY, Some(tx_buffer));
+        }
+        if tx_len == 0 || tx_len > tx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(tx_buffer));
+        }
+        self.transmitted
+            .borrow_mut()
+            .extend_from_slice(&tx_buffer[..tx_len]);
+        self.tx_len.set(tx_len);
+        self.tx_buffer.replace(tx_buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn transmit_word(&self, _word: u32) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        if self.is_transmitting() {
+            self.tx_aborted.set(true);
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> Receive<'a> for MockUart<'a> {
+    fn set_receive_client(&self, client: &'a dyn ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.is_receiving() {
+            return (ReturnCode::EBUSY, Some(rx_buffer));
+        }
+        if rx_len == 0 || rx_len > rx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(rx_buffer));
+        }
+        self.rx_len.set(rx_len);
+        self.rx_position.set(0);
+        self.rx_buffer.replace(rx_buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        if self.is_receiving() {
+            self.rx_aborted.set(true);
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> Uart<'a> for MockUart<'a> {}
+impl<'a> UartData<'a> for MockUart<'a> {}
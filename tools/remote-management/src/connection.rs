@@ -0,0 +1,123 @@
+//! Sending requests to a board over a serial port and waiting for responses.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::frame;
+
+/// How long to wait for a response before sending the request again.
+const TIMEOUT: Duration = Duration::from_secs(1);
+const ATTEMPTS: usize = 3;
+
+/// Bit set in the command of a response.
+const RESPONSE: u8 = 0x80;
+
+pub struct Connection {
+    port: File,
+    /// Bytes received from the board.
+    received: Receiver<u8>,
+    sequence: u8,
+}
+
+impl Connection {
+    /// Open the serial port `path` with `baud_rate` bits per second.
+    pub fn open(path: &str, baud_rate: u32) -> io::Result<Connection> {
+        // Put the port into raw mode, so that no bytes are changed or
+        // swallowed.
+        let device_flag = if cfg!(target_os = "macos") {
+            "-f"
+        } else {
+            "-F"
+        };
+        let status = Command::new("stty")
+            .args(&[device_flag, path, &baud_rate.to_string(), "raw", "-echo"])
+            .status()?;
+        if !status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("stty could not configure {}", path),
+            ));
+        }
+
+        let port = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut reader = port.try_clone()?;
+        let (sender, received) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 256];
+            while let Ok(count) = reader.read(&mut buffer) {
+                for &byte in &buffer[..count] {
+                    if sender.send(byte).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Connection {
+            port: port,
+            received: received,
+            sequence: 0,
+        })
+    }
+
+    /// Send a request, and return the result and data of its response. The
+    /// request is sent again if no response arrives in time.
+    pub fn request(&mut self, command: u8, arguments: &[u8]) -> io::Result<(i8, Vec<u8>)> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let mut payload = vec![self.sequence, command];
+        payload.extend_from_slice(arguments);
+        // The zero byte in front ends anything the board received before.
+        let mut request = vec![0];
+        request.extend(frame::encode(&payload));
+
+        for _ in 0..ATTEMPTS {
+            self.port.write_all(&request)?;
+            self.port.flush()?;
+            let deadline = Instant::now() + TIMEOUT;
+            while let Some(response) = self.receive_frame(deadline)? {
+                // Ignore responses to earlier requests that arrive late.
+                if response.len() >= 3
+                    && response[0] == self.sequence
+                    && response[1] == command | RESPONSE
+                {
+                    return Ok((response[2] as i8, response[3..].to_vec()));
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "the board did not respond",
+        ))
+    }
+
+    /// Wait for the next valid frame until `deadline`, and return its
+    /// payload.
+    fn receive_frame(&mut self, deadline: Instant) -> io::Result<Option<Vec<u8>>> {
+        let mut encoded = Vec::new();
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.received.recv_timeout(timeout) {
+                Ok(0) => {
+                    // Frames that are not valid, such as output of the kernel
+                    // on the same port, are skipped.
+                    if let Some(payload) = frame::decode(&encoded) {
+                        return Ok(Some(payload));
+                    }
+                    encoded.clear();
+                }
+                Ok(byte) => encoded.push(byte),
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the serial port was closed",
+                    ))
+                }
+            }
+        }
+    }
+}
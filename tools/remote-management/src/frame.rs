@@ -0,0 +1,68 @@
+//! Framing of requests and responses: the payload and its CRC-16/CCITT-FALSE,
+//! COBS-encoded and followed by a zero byte. This matches
+//! `capsules::remote_management`.
+
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |crc, &byte| {
+        let mut crc = crc ^ (u16::from(byte) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// Encode `payload` as a complete frame.
+pub fn encode(payload: &[u8]) -> Vec<u8> {
+    let mut data = payload.to_vec();
+    data.extend_from_slice(&crc16(payload).to_le_bytes());
+
+    let mut frame = vec![0];
+    let mut code_index = 0;
+    for byte in data {
+        if byte != 0 {
+            frame.push(byte);
+        }
+        let code = frame.len() - code_index;
+        if byte == 0 || code == 0xff {
+            frame[code_index] = code as u8;
+            code_index = frame.len();
+            frame.push(0);
+        }
+    }
+    frame[code_index] = (frame.len() - code_index) as u8;
+    frame.push(0);
+    frame
+}
+
+/// Decode a frame without its final zero byte, and check its CRC. Returns
+/// the payload.
+pub fn decode(frame: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut read = 0;
+    while read < frame.len() {
+        let code = frame[read] as usize;
+        if code == 0 || read + code > frame.len() {
+            return None;
+        }
+        data.extend_from_slice(&frame[read + 1..read + code]);
+        read += code;
+        if code < 0xff && read < frame.len() {
+            data.push(0);
+        }
+    }
+
+    if data.len() < 2 {
+        return None;
+    }
+    let crc = data.split_off(data.len() - 2);
+    if crc[..] == crc16(&data).to_le_bytes() {
+        Some(data)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,392 @@
+//! Command line client for the remote management protocol of
+//! `capsules::remote_management`.
+//!
+//! ```shell
+//! $ remote-management /dev/ttyACM0 list
+//! $ remote-management /dev/ttyACM0 --baud 115200 restart blink
+//! $ remote-management /dev/ttyACM0 install blink.tbf
+//! ```
+
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+
+mod connection;
+mod frame;
+
+use connection::Connection;
+
+const INFO: u8 = 0x01;
+const LIST: u8 = 0x02;
+const START: u8 = 0x03;
+const STOP: u8 = 0x04;
+const FAULT: u8 = 0x05;
+const RESTART: u8 = 0x06;
+const TERMINATE: u8 = 0x07;
+const KERNEL: u8 = 0x08;
+const COUNTERS: u8 = 0x09;
+const SYSCALLS: u8 = 0x0A;
+const STATE: u8 = 0x0B;
+const KERNEL_MEMORY: u8 = 0x0C;
+const INSTALL: u8 = 0x10;
+const WRITE: u8 = 0x11;
+const LOAD: u8 = 0x12;
+const ABORT: u8 = 0x13;
+const ERASE: u8 = 0x14;
+const REBOOT: u8 = 0x1F;
+
+/// Length of the sequence number, command and result of a response.
+const RESPONSE_HEADER_LENGTH: usize = 3;
+
+const USAGE: &str = "\
+Usage: remote-management <serial port> [--baud <rate>] <command> [<argument>]
+
+Commands:
+  info                  protocol version and dropped frames
+  list                  processes and where their TBF and memory are
+  start <name>          resume a stopped process
+  stop <name>           stop a process
+  fault <name>          force a process into a fault state
+  restart <name>        restart a process from its beginning
+  terminate <name>      stop a process and free its grants
+  kernel                kernel uptime and counters
+  counters <name>       counters and memory use of a process
+  syscalls <name>       syscalls of a process by driver
+  state <name>          memory map, registers and MPU configuration of a process
+  kernel-memory         addresses of the kernel's sections
+  install <tbf file>    install and start a new application
+  erase <name>          stop an application and free its flash
+  reboot                reset the board";
+
+const STATES: [&str; 7] = [
+    "Running",
+    "Yielded",
+    "StoppedRunning",
+    "StoppedYielded",
+    "StoppedFaulted",
+    "Fault",
+    "Unstarted",
+];
+
+/// Name of the `ReturnCode` that `result` stands for.
+fn error_name(result: i8) -> &'static str {
+    match result {
+        -1 => "FAIL",
+        -2 => "EBUSY",
+        -3 => "EALREADY",
+        -4 => "EOFF",
+        -5 => "ERESERVE",
+        -6 => "EINVAL",
+        -7 => "ESIZE",
+        -8 => "ECANCEL",
+        -9 => "ENOMEM",
+        -10 => "ENOSUPPORT",
+        -11 => "ENODEVICE",
+        -12 => "EUNINSTALLED",
+        -13 => "ENOACK",
+        _ => "unknown error",
+    }
+}
+
+fn error(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, message)
+}
+
+/// Reads the little-endian integers of a response in order.
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn take(&mut self, length: usize) -> &[u8] {
+        let length = length.min(self.0.len());
+        let (field, rest) = self.0.split_at(length);
+        self.0 = rest;
+        field
+    }
+
+    fn number(&mut self, length: usize) -> u64 {
+        self.take(length)
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | u64::from(byte))
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.number(1) as u8
+    }
+
+    fn u16(&mut self) -> u16 {
+        self.number(2) as u16
+    }
+
+    fn u32(&mut self) -> u32 {
+        self.number(4) as u32
+    }
+
+    fn u64(&mut self) -> u64 {
+        self.number(8)
+    }
+
+    fn rest(&mut self) -> String {
+        let length = self.0.len();
+        String::from_utf8_lossy(self.take(length)).into_owned()
+    }
+}
+
+/// Formats a time in microseconds, or `-` if the board does not know it.
+fn time(us: u64) -> String {
+    if us == u64::MAX {
+        "-".to_string()
+    } else {
+        format!("{} ms", us / 1000)
+    }
+}
+
+/// Formats a size in bytes, or `-` if the board does not know it.
+fn size(bytes: u32) -> String {
+    if bytes == u32::MAX {
+        "-".to_string()
+    } else {
+        bytes.to_string()
+    }
+}
+
+struct Board {
+    connection: Connection,
+}
+
+impl Board {
+    /// Send a request, and return the data of its response if it succeeded.
+    fn request(&mut self, command: u8, arguments: &[u8]) -> io::Result<Vec<u8>> {
+        match self.connection.request(command, arguments)? {
+            (0, data) => Ok(data),
+            (result, _) => Err(error(format!(
+                "the board returned {} ({})",
+                error_name(result),
+                result
+            ))),
+        }
+    }
+
+    fn info(&mut self) -> io::Result<()> {
+        let data = self.request(INFO, &[])?;
+        let mut fields = Fields(&data);
+        println!("Protocol version: {}", fields.u8());
+        println!("Maximum payload: {} bytes", fields.u16());
+        println!("Dropped frames: {}", fields.u32());
+        Ok(())
+    }
+
+    fn list(&mut self) -> io::Result<()> {
+        println!(
+            "{:<4} {:<16} {:<15} {:>8} {:>10} {:>8} {:>6} {:>10} {:>8}",
+            "PID", "Name", "State", "Restarts", "Flash", "Size", "Header", "RAM", "Size"
+        );
+        for index in 0..=u8::MAX {
+            let data = match self.connection.request(LIST, &[index])? {
+                (0, data) => data,
+                _ => break,
+            };
+            let mut fields = Fields(&data);
+            let id = fields.u32();
+            let state = STATES.get(fields.u8() as usize).unwrap_or(&"unknown");
+            let restarts = fields.u32();
+            let (flash_start, flash_length, header_length) =
+                (fields.u32(), fields.u32(), fields.u32());
+            let (memory_start, memory_length) = (fields.u32(), fields.u32());
+            let _writeable_flash_regions = fields.u8();
+            println!(
+                "{:<4} {:<16} {:<15} {:>8} {:#010x} {:>8} {:>6} {:#010x} {:>8}",
+                id,
+                fields.rest(),
+                state,
+                restarts,
+                flash_start,
+                flash_length,
+                header_length,
+                memory_start,
+                memory_length
+            );
+        }
+        Ok(())
+    }
+
+    fn kernel(&mut self) -> io::Result<()> {
+        let data = self.request(KERNEL, &[])?;
+        let mut fields = Fields(&data);
+        let (uptime, asleep, in_kernel) = (fields.u64(), fields.u64(), fields.u64());
+        println!(
+            "Uptime: {}, asleep: {}, in kernel: {}",
+            time(uptime),
+            time(asleep),
+            time(in_kernel)
+        );
+        println!("Total processes: {}", fields.u32());
+        println!("Active processes: {}", fields.u32());
+        println!("Timeslice expirations: {}", fields.u32());
+        println!("Deadline misses: {}", fields.u32());
+        Ok(())
+    }
+
+    fn counters(&mut self, name: &str) -> io::Result<()> {
+        let data = self.request(COUNTERS, name.as_bytes())?;
+        let mut fields = Fields(&data);
+        println!("Syscalls: {}", fields.u32());
+        println!("Dropped callbacks: {}", fields.u32());
+        println!("Restarts: {}", fields.u32());
+        println!("Timeslice expirations: {}", fields.u32());
+        println!("Deadline misses: {}", fields.u32());
+        println!("CPU time: {}", time(fields.u64()));
+        println!("Stack high-water mark: {}", size(fields.u32()));
+        println!("Heap high-water mark: {}", size(fields.u32()));
+        println!("Grants: {}/{}", fields.u16(), fields.u16());
+        let (app, grants, total) = (fields.u32(), fields.u32(), fields.u32());
+        println!(
+            "Memory: {} bytes, {} below the break, {} for grants",
+            total, app, grants
+        );
+        Ok(())
+    }
+
+    fn syscalls(&mut self, name: &str) -> io::Result<()> {
+        let data = self.request(SYSCALLS, name.as_bytes())?;
+        let mut fields = Fields(&data);
+        let other = fields.u32();
+        while !fields.0.is_empty() {
+            println!("{:#x}: {}", fields.u32(), fields.u32());
+        }
+        if other > 0 {
+            println!("other: {}", other);
+        }
+        Ok(())
+    }
+
+    fn state(&mut self, name: &str, max_payload: usize) -> io::Result<()> {
+        let mut state = Vec::new();
+        loop {
+            let mut arguments = (state.len() as u32).to_le_bytes().to_vec();
+            arguments.extend_from_slice(name.as_bytes());
+            let data = self.request(STATE, &arguments)?;
+            state.extend_from_slice(&data);
+            if data.len() < max_payload - RESPONSE_HEADER_LENGTH {
+                break;
+            }
+        }
+        println!("{}", String::from_utf8_lossy(&state));
+        Ok(())
+    }
+
+    fn kernel_memory(&mut self) -> io::Result<()> {
+        let data = self.request(KERNEL_MEMORY, &[])?;
+        let mut fields = Fields(&data);
+        for section in ["Stack", "Text", "Relocated data", "BSS"].iter() {
+            let (start, end) = (fields.u32(), fields.u32());
+            println!(
+                "{:<15} {:#010x}-{:#010x} ({} bytes)",
+                section,
+                start,
+                end,
+                end.wrapping_sub(start)
+            );
+        }
+        Ok(())
+    }
+
+    fn install(&mut self, path: &str, max_payload: usize) -> io::Result<()> {
+        let image = fs::read(path)?;
+        let data = self.request(INSTALL, &(image.len() as u32).to_le_bytes())?;
+        let address = Fields(&data).u32();
+        println!(
+            "Writing {} bytes to flash at {:#010x}",
+            image.len(),
+            address
+        );
+
+        // Each chunk follows the sequence number, command and offset.
+        let chunk_length = max_payload - 2 - 4;
+        for (index, chunk) in image.chunks(chunk_length).enumerate() {
+            let mut arguments = ((index * chunk_length) as u32).to_le_bytes().to_vec();
+            arguments.extend_from_slice(chunk);
+            if let Err(error) = self.request(WRITE, &arguments) {
+                let _ = self.request(ABORT, &[]);
+                return Err(error);
+            }
+        }
+
+        let data = self.request(LOAD, &[])?;
+        println!("Started process {}", Fields(&data).u32());
+        Ok(())
+    }
+
+    fn max_payload(&mut self) -> io::Result<usize> {
+        let data = self.request(INFO, &[])?;
+        let mut fields = Fields(&data);
+        fields.u8();
+        Ok(fields.u16() as usize)
+    }
+}
+
+fn run(args: &[String]) -> io::Result<()> {
+    let mut args = args.iter().map(String::as_str);
+    let port = args.next().ok_or_else(|| error(USAGE.to_string()))?;
+    let mut baud_rate = 115200;
+    let mut command = args.next();
+    if command == Some("--baud") {
+        baud_rate = args
+            .next()
+            .and_then(|rate| rate.parse().ok())
+            .ok_or_else(|| error("--baud needs a number".to_string()))?;
+        command = args.next();
+    }
+    let argument = args.next();
+
+    let mut board = Board {
+        connection: Connection::open(port, baud_rate)?,
+    };
+    let simple = |name: &str| match name {
+        "start" => Some((START, "resumed")),
+        "stop" => Some((STOP, "stopped")),
+        "fault" => Some((FAULT, "faulted")),
+        "restart" => Some((RESTART, "restarted")),
+        "terminate" => Some((TERMINATE, "terminated")),
+        "erase" => Some((ERASE, "erased")),
+        _ => None,
+    };
+
+    match (command, argument) {
+        (Some("info"), None) => board.info(),
+        (Some("list"), None) => board.list(),
+        (Some("kernel"), None) => board.kernel(),
+        (Some("kernel-memory"), None) => board.kernel_memory(),
+        (Some("counters"), Some(name)) => board.counters(name),
+        (Some("syscalls"), Some(name)) => board.syscalls(name),
+        (Some("state"), Some(name)) => {
+            let max_payload = board.max_payload()?;
+            board.state(name, max_payload)
+        }
+        (Some("install"), Some(path)) => {
+            let max_payload = board.max_payload()?;
+            board.install(path, max_payload)
+        }
+        (Some("reboot"), None) => {
+            board.request(REBOOT, &[])?;
+            println!("Rebooting");
+            Ok(())
+        }
+        (Some(command), Some(name)) if simple(command).is_some() => {
+            let (code, done) = simple(command).unwrap();
+            board.request(code, name.as_bytes())?;
+            println!("Process {} {}", name, done);
+            Ok(())
+        }
+        _ => Err(error(USAGE.to_string())),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(error) = run(&args) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}